@@ -1,6 +1,8 @@
 //! An echo bot that just repeats stuff sent to it (either in a channel or as PRIVMSG).
 
-use libtiny_client::{Client, Event, ServerInfo};
+use libtiny_client::{
+    Client, Event, FloodProtection, IpPreference, ReconnectBackoff, SASLFail, ServerInfo,
+};
 use libtiny_common::ChanNameRef;
 use libtiny_wire::{Cmd, Msg, MsgTarget, Pfx};
 
@@ -44,7 +46,28 @@ fn main() {
         nicks: vec![nick],
         auto_join: chans,
         nickserv_ident: None,
+        nickserv: None,
+        fallback_encoding: libtiny_wire::FallbackEncoding::Replace,
+        encoding: libtiny_wire::Encoding::Utf8,
         sasl_auth: None,
+        sasl_fail: SASLFail::Continue,
+        client_cert: None,
+        ca_certs: None,
+        pinned_cert: None,
+        tls_verify_hostname: true,
+        socks5_proxy: None,
+        bind_address: None,
+        ip_preference: IpPreference::Auto,
+        connect_timeout: None,
+        tls_handshake_timeout: None,
+        registration_timeout: None,
+        ping_interval: None,
+        ping_timeout: None,
+        rejoin_on_reconnect: true,
+        join_history_lines: 50,
+        reconnect_backoff: ReconnectBackoff::default(),
+        flood_protection: FloodProtection::default(),
+        away_poll: None,
     };
 
     println!("{server_info:?}");
@@ -72,6 +95,7 @@ async fn echo_bot_task(server_info: ServerInfo) {
         if let Event::Msg(Msg {
             pfx: Some(Pfx::User { nick, .. }),
             cmd: Cmd::PRIVMSG { target, msg, .. },
+            ..
         }) = ev
         {
             let echo_msg = match target {
@@ -0,0 +1,128 @@
+//! IRCv3 batches. Reference: <https://ircv3.net/specs/extensions/batch>
+
+use libtiny_wire::{BatchStart, Cmd, Msg};
+
+use std::collections::HashMap;
+
+/// An open batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    /// Reference of the batch, in the `batch` tags of the messages in the batch.
+    pub reference: String,
+    /// Type of the batch, e.g. `netsplit`, `chathistory`.
+    pub batch_type: String,
+    /// Parameters of the batch. Meaning depends on the type.
+    pub params: Vec<String>,
+    /// Reference of the batch this batch is in.
+    parent: Option<String>,
+}
+
+/// Open batches of a connection, to find the batches that messages are in. Updated with `BATCH`
+/// messages, in the order they're received.
+#[derive(Debug, Default)]
+pub struct Batches(HashMap<String, Batch>);
+
+impl Batches {
+    /// Start or end a batch if the message is a `BATCH` message. Returns the batch when it ends.
+    pub fn update(&mut self, msg: &Msg) -> Option<Batch> {
+        match &msg.cmd {
+            Cmd::BATCH {
+                reference,
+                start: Some(BatchStart { batch_type, params }),
+            } => {
+                self.0.insert(
+                    reference.clone(),
+                    Batch {
+                        reference: reference.clone(),
+                        batch_type: batch_type.clone(),
+                        params: params.clone(),
+                        parent: msg.batch().map(str::to_owned),
+                    },
+                );
+                None
+            }
+            Cmd::BATCH {
+                reference,
+                start: None,
+            } => self.0.remove(reference),
+            _ => None,
+        }
+    }
+
+    /// Batches the message is in, innermost first.
+    pub fn batches_of<'a>(&'a self, msg: &Msg) -> Vec<&'a Batch> {
+        let mut batches: Vec<&Batch> = vec![];
+        let mut reference = msg.batch();
+        while let Some(batch) = reference.and_then(|reference| self.0.get(reference)) {
+            // Parent references are sent by the server, don't loop forever on a cycle
+            if batches
+                .iter()
+                .any(|batch_| batch_.reference == batch.reference)
+            {
+                break;
+            }
+            batches.push(batch);
+            reference = batch.parent.as_deref();
+        }
+        batches
+    }
+
+    /// Whether the message is in a batch of the given type, directly or in a nested batch.
+    pub fn in_batch(&self, msg: &Msg, batch_type: &str) -> bool {
+        self.batches_of(msg)
+            .iter()
+            .any(|batch| batch.batch_type == batch_type)
+    }
+
+    /// Remove all batches. Called on disconnect, batches don't span connections.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Msg {
+        let mut buf = s.as_bytes().to_vec();
+        libtiny_wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_nested_batches() {
+        let mut batches = Batches::default();
+        assert_eq!(
+            batches.update(&parse(":irc.server BATCH +outer znc.in/playback\r\n")),
+            None
+        );
+        assert_eq!(
+            batches.update(&parse(
+                "@batch=outer :irc.server BATCH +inner netsplit irc.hub other.host\r\n"
+            )),
+            None
+        );
+
+        let quit = parse("@batch=inner :nick!~nick@host QUIT :irc.hub other.host\r\n");
+        let types: Vec<&str> = batches
+            .batches_of(&quit)
+            .iter()
+            .map(|batch| batch.batch_type.as_str())
+            .collect();
+        assert_eq!(types, vec!["netsplit", "znc.in/playback"]);
+        assert!(batches.in_batch(&quit, "znc.in/playback"));
+        assert!(!batches.in_batch(&quit, "chathistory"));
+
+        let ended = batches
+            .update(&parse("@batch=outer :irc.server BATCH -inner\r\n"))
+            .unwrap();
+        assert_eq!(ended.batch_type, "netsplit");
+        assert_eq!(ended.params, vec!["irc.hub", "other.host"]);
+        assert!(batches.batches_of(&quit).is_empty());
+
+        let msg = parse("@batch=outer :nick!~nick@host PRIVMSG #chan :hi\r\n");
+        assert!(batches.in_batch(&msg, "znc.in/playback"));
+        batches.update(&parse(":irc.server BATCH -outer\r\n"));
+        assert!(!batches.in_batch(&msg, "znc.in/playback"));
+    }
+}
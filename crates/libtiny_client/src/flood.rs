@@ -0,0 +1,172 @@
+//! Outgoing flood protection. Servers disconnect clients that send too many messages in a short
+//! time, so PRIVMSG and NOTICE messages are sent with a token bucket, see `FloodProtection`.
+//! Other messages (e.g. PONG, JOIN) are sent immediately.
+
+use crate::FloodProtection;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// A token bucket with `FloodProtection::burst` tokens, refilled with one token every
+/// `FloodProtection::interval`. Sending a rate limited message takes a token.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub(crate) fn new(limits: FloodProtection, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: f64::from(limits.burst),
+            last_refill: now,
+        }
+    }
+
+    /// Take a token to send a message. Returns how long to wait for the next token when the
+    /// bucket is empty, `Duration::ZERO` (after taking a token) otherwise. Limits are passed on
+    /// each call as they can be changed with `Client::set_flood_protection`.
+    pub(crate) fn take(&mut self, limits: FloodProtection, now: Instant) -> Duration {
+        if limits.interval.is_zero() {
+            return Duration::ZERO;
+        }
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() / limits.interval.as_secs_f64())
+            .min(f64::from(limits.burst.max(1)));
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Duration::ZERO
+        } else {
+            limits.interval.mul_f64(1.0 - self.tokens)
+        }
+    }
+}
+
+/// Rate limited messages waiting to be sent, with their targets. Shared by the task sending the
+/// messages and `Client`, to be able to drop the messages with `Client::clear_send_queue`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct SendQueue(Rc<RefCell<VecDeque<(String, String)>>>);
+
+impl SendQueue {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    /// Add a message to the end of the queue. Returns the number of queued messages to `target`.
+    pub(crate) fn push_back(&self, target: String, msg: String) -> usize {
+        self.0.borrow_mut().push_back((target.clone(), msg));
+        self.n_queued(&target)
+    }
+
+    /// Put a message that couldn't be sent back to the front of the queue.
+    pub(crate) fn push_front(&self, target: String, msg: String) {
+        self.0.borrow_mut().push_front((target, msg));
+    }
+
+    pub(crate) fn pop_front(&self) -> Option<(String, String)> {
+        self.0.borrow_mut().pop_front()
+    }
+
+    /// Number of queued messages to `target`.
+    pub(crate) fn n_queued(&self, target: &str) -> usize {
+        self.0
+            .borrow()
+            .iter()
+            .filter(|(target_, _)| target_ == target)
+            .count()
+    }
+
+    /// Drop the queued messages. Returns the targets of the dropped messages, one per message.
+    pub(crate) fn clear(&self) -> Vec<String> {
+        self.0
+            .borrow_mut()
+            .drain(..)
+            .map(|(target, _)| target)
+            .collect()
+    }
+}
+
+/// Target of a message if it's rate limited (PRIVMSG or NOTICE), `None` otherwise. `msg` is a
+/// message sent by the client, so it doesn't have a prefix, but it may have tags.
+pub(crate) fn rate_limited_target(msg: &str) -> Option<&str> {
+    let msg = match msg.strip_prefix('@') {
+        Some(msg) => msg.split_once(' ')?.1,
+        None => msg,
+    };
+    let mut words = msg.split_whitespace();
+    let cmd = words.next()?;
+    if cmd.eq_ignore_ascii_case("PRIVMSG") || cmd.eq_ignore_ascii_case("NOTICE") {
+        words.next()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_queue() {
+        let queue = SendQueue::default();
+        let queue_ = queue.clone();
+        assert_eq!(queue.push_back("#a".to_owned(), "1".to_owned()), 1);
+        assert_eq!(queue.push_back("#b".to_owned(), "2".to_owned()), 1);
+        assert_eq!(queue_.push_back("#a".to_owned(), "3".to_owned()), 2);
+        assert_eq!(queue.pop_front(), Some(("#a".to_owned(), "1".to_owned())));
+        assert_eq!(queue_.n_queued("#a"), 1);
+        assert_eq!(queue_.clear(), vec!["#b".to_owned(), "#a".to_owned()]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limits = FloodProtection {
+            burst: 3,
+            interval: Duration::from_secs(2),
+        };
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(limits, now);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(limits, now), Duration::ZERO);
+        }
+        assert_eq!(bucket.take(limits, now), Duration::from_secs(2));
+        let now = now + Duration::from_secs(1);
+        assert_eq!(bucket.take(limits, now), Duration::from_secs(1));
+        let now = now + Duration::from_secs(1);
+        assert_eq!(bucket.take(limits, now), Duration::ZERO);
+        assert_eq!(bucket.take(limits, now), Duration::from_secs(2));
+
+        // Bucket is refilled up to `burst` tokens
+        let now = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(limits, now), Duration::ZERO);
+        }
+        assert_ne!(bucket.take(limits, now), Duration::ZERO);
+
+        // Zero interval disables rate limiting
+        let no_limits = FloodProtection {
+            burst: 3,
+            interval: Duration::ZERO,
+        };
+        assert_eq!(bucket.take(no_limits, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_rate_limited_target() {
+        assert_eq!(rate_limited_target("PRIVMSG #chan :hi\r\n"), Some("#chan"));
+        assert_eq!(
+            rate_limited_target("@label=3 NOTICE nick :\x01VERSION tiny\x01\r\n"),
+            Some("nick")
+        );
+        assert_eq!(rate_limited_target("PONG :irc.server\r\n"), None);
+        assert_eq!(
+            rate_limited_target("@+typing=active TAGMSG #chan\r\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Server features advertised in RPL_ISUPPORT (005) parameters.
+//! Reference: <https://modern.ircdocs.horse/#rplisupport-parameters>
+
+use crate::modes::ChanModes;
+
+use libtiny_common::CaseMapping;
+
+/// Channel name prefixes used until the server sends `CHANTYPES`.
+const DEFAULT_CHAN_TYPES: &str = "#&";
+
+/// Max. number of mode changes with a parameter in a MODE message used until the server sends
+/// `MODES`, as in RFC 2812.
+const DEFAULT_MAX_MODES: usize = 3;
+
+/// Features of a server, from RPL_ISUPPORT. Defaults are used until the server sends
+/// RPL_ISUPPORT, and after reconnecting. See `Client::get_isupport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ISupport {
+    /// Channel name prefixes (`CHANTYPES`).
+    chan_types: String,
+    /// Max. number of mode changes with a parameter in a MODE message (`MODES`). `None` means no
+    /// limit.
+    max_modes: Option<usize>,
+    /// Max. nick length (`NICKLEN`). `None` when not known.
+    nick_len: Option<usize>,
+    /// How nicks and channel names are compared (`CASEMAPPING`).
+    case_mapping: CaseMapping,
+    /// Membership prefixes that can be used to send messages to the users in a channel with the
+    /// prefix, e.g. `@#chan` for ops (`STATUSMSG`).
+    statusmsg: String,
+    /// Whether the server supports WHOX, WHO queries with a list of fields to reply with
+    /// (`WHOX`).
+    whox: bool,
+    /// Membership prefixes and channel modes (`PREFIX` and `CHANMODES`).
+    pub(crate) chan_modes: ChanModes,
+}
+
+impl Default for ISupport {
+    fn default() -> Self {
+        ISupport {
+            chan_types: DEFAULT_CHAN_TYPES.to_owned(),
+            max_modes: Some(DEFAULT_MAX_MODES),
+            nick_len: None,
+            case_mapping: CaseMapping::default(),
+            statusmsg: String::new(),
+            whox: false,
+            chan_modes: ChanModes::default(),
+        }
+    }
+}
+
+impl ISupport {
+    /// Update from an RPL_ISUPPORT parameter, e.g. `CHANTYPES=#`. Parameters negated with a `-`
+    /// prefix are reset to the defaults. Unknown parameters and invalid values are ignored.
+    pub(crate) fn update(&mut self, param: &str) {
+        if let Some(name) = param.strip_prefix('-') {
+            let default = ISupport::default();
+            match name {
+                "CHANTYPES" => self.chan_types = default.chan_types,
+                "MODES" => self.max_modes = default.max_modes,
+                "NICKLEN" => self.nick_len = default.nick_len,
+                "CASEMAPPING" => self.case_mapping = default.case_mapping,
+                "STATUSMSG" => self.statusmsg = default.statusmsg,
+                "WHOX" => self.whox = default.whox,
+                _ => {}
+            }
+            return;
+        }
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        match name {
+            "CHANTYPES" => self.chan_types = value.to_owned(),
+            // No value means no limit
+            "MODES" if value.is_empty() => self.max_modes = None,
+            "MODES" => {
+                if let Ok(max_modes) = value.parse() {
+                    self.max_modes = Some(max_modes);
+                }
+            }
+            "NICKLEN" => {
+                if let Ok(nick_len) = value.parse() {
+                    self.nick_len = Some(nick_len);
+                }
+            }
+            "STATUSMSG" => self.statusmsg = value.to_owned(),
+            "WHOX" => self.whox = true,
+            "CASEMAPPING" => {
+                if let Some(case_mapping) = CaseMapping::parse(value) {
+                    self.case_mapping = case_mapping;
+                }
+            }
+            _ => self.chan_modes.update_isupport(param),
+        }
+    }
+
+    /// Channel name prefixes, e.g. `#&`.
+    pub fn chan_types(&self) -> &str {
+        &self.chan_types
+    }
+
+    /// Whether `name` is a channel name, according to `CHANTYPES`.
+    pub fn is_chan(&self, name: &str) -> bool {
+        name.starts_with(|c| self.chan_types.contains(c))
+    }
+
+    /// Max. number of mode changes with a parameter in a MODE message. `None` means no limit.
+    pub fn max_modes(&self) -> Option<usize> {
+        self.max_modes
+    }
+
+    /// Max. nick length. `None` when the server didn't send it.
+    pub fn nick_len(&self) -> Option<usize> {
+        self.nick_len
+    }
+
+    /// How nicks and channel names are compared.
+    pub fn case_mapping(&self) -> CaseMapping {
+        self.case_mapping
+    }
+
+    /// Whether the server supports WHOX.
+    pub fn whox(&self) -> bool {
+        self.whox
+    }
+
+    /// Whether two nicks are the same, according to `CASEMAPPING`.
+    pub fn nick_eq(&self, a: &str, b: &str) -> bool {
+        self.case_mapping.str_eq(a, b)
+    }
+
+    /// Split a STATUSMSG target (e.g. `@#chan`, for the ops of `#chan`) into the membership prefix
+    /// and the channel. `None` when the target is not a channel with a prefix in `STATUSMSG`.
+    pub fn split_statusmsg<'a>(&self, target: &'a str) -> Option<(char, &'a str)> {
+        let prefix = target.chars().next()?;
+        let chan = &target[prefix.len_utf8()..];
+        (self.statusmsg.contains(prefix) && self.is_chan(chan)).then_some((prefix, chan))
+    }
+
+    /// Nick in an RPL_NAMREPLY entry, without the membership prefixes of the server and the
+    /// `!user@host` suffix (with IRCv3 `userhost-in-names`).
+    pub fn names_nick<'a>(&self, name: &'a str) -> &'a str {
+        let (_, nick) = self.chan_modes.split_prefixes(name);
+        match nick.split_once('!') {
+            Some((nick, _)) => nick,
+            None => nick,
+        }
+    }
+
+    /// Split a mode change (e.g. `+ooo alice bob carol`) into parameters of MODE messages that
+    /// have at most `max_modes` mode changes with a parameter each.
+    pub fn split_mode_changes(&self, modes: &str) -> Vec<String> {
+        let mut words = modes.split_whitespace();
+        let mode_str = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let changes = self.chan_modes.changes(mode_str, &args);
+        let max_modes = match self.max_modes {
+            Some(max_modes) if max_modes > 0 => max_modes,
+            _ => return vec![modes.to_owned()],
+        };
+        if changes.iter().filter(|change| change.arg.is_some()).count() <= max_modes {
+            return vec![modes.to_owned()];
+        }
+
+        let mut msgs = vec![];
+        let mut mode_str = String::new();
+        let mut args = String::new();
+        let mut n_args = 0;
+        let mut set = None;
+        for change in changes {
+            if change.arg.is_some() && n_args == max_modes {
+                msgs.push(format!("{mode_str}{args}"));
+                mode_str.clear();
+                args.clear();
+                n_args = 0;
+                set = None;
+            }
+            if set != Some(change.set) {
+                mode_str.push(if change.set { '+' } else { '-' });
+                set = Some(change.set);
+            }
+            mode_str.push(change.mode);
+            if let Some(arg) = change.arg {
+                args.push(' ');
+                args.push_str(arg);
+                n_args += 1;
+            }
+        }
+        msgs.push(format!("{mode_str}{args}"));
+        msgs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update() {
+        let mut isupport = ISupport::default();
+        assert!(isupport.is_chan("#chan"));
+        assert!(isupport.is_chan("&chan"));
+        assert!(!isupport.is_chan("nick"));
+
+        isupport.update("CHANTYPES=#");
+        isupport.update("MODES=4");
+        isupport.update("NICKLEN=16");
+        isupport.update("PREFIX=(ov)@+");
+        isupport.update("WHOX");
+        assert!(!isupport.is_chan("&chan"));
+        assert!(isupport.whox());
+        assert_eq!(isupport.max_modes(), Some(4));
+        assert_eq!(isupport.nick_len(), Some(16));
+        assert_eq!(isupport.names_nick("@+nick!~user@host"), "nick");
+        // Not a prefix with this server
+        assert_eq!(isupport.names_nick("~nick"), "~nick");
+
+        isupport.update("MODES");
+        isupport.update("-CHANTYPES");
+        isupport.update("NICKLEN=x");
+        isupport.update("-WHOX");
+        assert_eq!(isupport.max_modes(), None);
+        assert!(!isupport.whox());
+        assert_eq!(isupport.chan_types(), "#&");
+        assert_eq!(isupport.nick_len(), Some(16));
+    }
+
+    #[test]
+    fn test_statusmsg() {
+        let mut isupport = ISupport::default();
+        assert_eq!(isupport.split_statusmsg("@#chan"), None);
+
+        isupport.update("STATUSMSG=@+");
+        assert_eq!(isupport.split_statusmsg("@#chan"), Some(('@', "#chan")));
+        assert_eq!(isupport.split_statusmsg("+&chan"), Some(('+', "&chan")));
+        assert_eq!(isupport.split_statusmsg("%#chan"), None);
+        assert_eq!(isupport.split_statusmsg("#chan"), None);
+        assert_eq!(isupport.split_statusmsg("@nick"), None);
+        assert_eq!(isupport.split_statusmsg(""), None);
+    }
+
+    #[test]
+    fn test_case_mapping() {
+        let mut isupport = ISupport::default();
+        assert!(isupport.nick_eq("Foo[]", "foo{}"));
+        assert!(isupport.nick_eq("foo~", "FOO^"));
+        assert!(!isupport.nick_eq("foo", "fooo"));
+
+        isupport.update("CASEMAPPING=strict-rfc1459");
+        assert!(isupport.nick_eq("Foo[]", "foo{}"));
+        assert!(!isupport.nick_eq("foo~", "foo^"));
+
+        isupport.update("CASEMAPPING=ascii");
+        assert!(isupport.nick_eq("Foo", "fOO"));
+        assert!(!isupport.nick_eq("foo[]", "foo{}"));
+        // Non-ASCII characters are not mapped
+        assert!(!isupport.nick_eq("Ä", "ä"));
+
+        // Unknown case mappings are ignored
+        isupport.update("CASEMAPPING=rfc7613");
+        assert_eq!(isupport.case_mapping(), CaseMapping::Ascii);
+    }
+
+    #[test]
+    fn test_split_mode_changes() {
+        let mut isupport = ISupport::default();
+        assert_eq!(
+            isupport.split_mode_changes("+oo alice bob"),
+            vec!["+oo alice bob"]
+        );
+        assert_eq!(
+            isupport.split_mode_changes("+ooo-v+mo alice bob carol dave eve"),
+            vec!["+ooo alice bob carol", "-v+mo dave eve"]
+        );
+
+        isupport.update("MODES=1");
+        assert_eq!(
+            isupport.split_mode_changes("+n-o+l alice 10"),
+            vec!["+n-o alice", "+l 10"]
+        );
+
+        isupport.update("MODES=");
+        assert_eq!(
+            isupport.split_mode_changes("+oooo a b c d"),
+            vec!["+oooo a b c d"]
+        );
+    }
+}
@@ -1,20 +1,31 @@
 #![allow(clippy::unneeded_field_pattern)]
 #![allow(clippy::cognitive_complexity)]
 
+mod batch;
+mod cert;
+mod flood;
+mod isupport;
+mod modes;
 mod pinger;
+mod sasl;
+mod socks5;
 mod state;
 mod stream;
+mod sts;
 mod utils;
 
-use libtiny_common::{ChanName, ChanNameRef};
+pub use batch::{Batch, Batches};
+pub use isupport::ISupport;
+use libtiny_common::{ChanName, ChanNameRef, TypingState};
 pub use libtiny_wire as wire;
 
+use flood::{SendQueue, TokenBucket};
 use pinger::Pinger;
 use state::State;
-use stream::{Stream, StreamError};
+use stream::{Stream, StreamError, TcpTarget, TlsOptions};
 
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
 
 use futures_util::future::FutureExt;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -30,12 +41,10 @@ extern crate log;
 // Public API
 //
 
-/// `Client` tries to reconnect on error after this many seconds.
-pub const RECONNECT_SECS: u64 = 30;
-
 #[derive(Debug, Clone)]
 pub struct ServerInfo {
-    /// Server address
+    /// Server address. `unix:<path>` connects to the Unix domain socket at `<path>` instead of a
+    /// TCP address, in which case `port`, `tls` and the SOCKS5 proxy are not used.
     pub addr: String,
 
     /// Server port
@@ -63,8 +72,276 @@ pub struct ServerInfo {
     /// join commands.
     pub nickserv_ident: Option<String>,
 
+    /// Nick of the NickServ service, for networks that use a different name. `None` means
+    /// `NickServ`.
+    pub nickserv: Option<String>,
+
+    /// How to decode messages from the server that are not valid UTF-8.
+    pub fallback_encoding: wire::FallbackEncoding,
+
+    /// Text encoding of the server. See `wire::Encoding`.
+    pub encoding: wire::Encoding,
+
     /// SASL authentication credentials,
     pub sasl_auth: Option<SASLAuth>,
+
+    /// What to do when SASL authentication fails.
+    pub sasl_fail: SASLFail,
+
+    /// TLS client certificate, for servers that identify users by the certificate fingerprint
+    /// (CertFP). Only used with TLS. The certificate of `SASLAuth::External` is used instead when
+    /// SASL EXTERNAL is enabled.
+    pub client_cert: Option<ClientCert>,
+
+    /// PEM-encoded CA certificates to trust in addition to the system roots when verifying the
+    /// server certificate, e.g. of a private CA. Only used with TLS.
+    pub ca_certs: Option<Vec<u8>>,
+
+    /// SHA-256 fingerprint of the server certificate to accept, in hex digits (optionally
+    /// separated with `:`). When set the server certificate is accepted if and only if it has
+    /// this fingerprint, without validating the certificate chain and the host name. Only used
+    /// with TLS. See also `Client::pin_cert`.
+    pub pinned_cert: Option<String>,
+
+    /// Verify that the server certificate is issued for the server address. Disable for e.g. onion
+    /// services with certificates issued for the clearnet address of the server. The certificate
+    /// chain is still verified, or the certificate is checked against `pinned_cert`. Only used
+    /// with TLS.
+    pub tls_verify_hostname: bool,
+
+    /// SOCKS5 proxy to connect through, e.g. Tor. The server address is resolved by the proxy, so
+    /// onion services can be used.
+    pub socks5_proxy: Option<SocketAddr>,
+
+    /// Local address to connect from, e.g. a vhost on a machine with multiple addresses. Only
+    /// server addresses of the same family (IPv4 or IPv6) are connected to.
+    pub bind_address: Option<IpAddr>,
+
+    /// Order to try the addresses of the server in.
+    pub ip_preference: IpPreference,
+
+    /// Give up opening the TCP connection to an address of the server (including the proxy
+    /// handshake) after this long. `None` means no limit other than the OS limits.
+    pub connect_timeout: Option<Duration>,
+
+    /// Give up the TLS handshake after this long. `None` means no limit. Only used with TLS.
+    pub tls_handshake_timeout: Option<Duration>,
+
+    /// Reconnect when the server doesn't accept the connection registration (RPL_WELCOME) in
+    /// this long after connecting, e.g. when the server doesn't reply to SASL authentication.
+    /// Default is 60 seconds.
+    pub registration_timeout: Option<Duration>,
+
+    /// How long to wait for a message from the server before sending a PING. Default is 60
+    /// seconds.
+    pub ping_interval: Option<Duration>,
+
+    /// How long to wait for the reply to a PING (or any other message) before declaring the
+    /// connection dead and reconnecting. Default is 60 seconds.
+    pub ping_timeout: Option<Duration>,
+
+    /// Rejoin the channels we were in when reconnecting. When disabled only `auto_join` channels
+    /// are joined after a reconnect.
+    pub rejoin_on_reconnect: bool,
+
+    /// Number of messages to fetch with IRCv3 `draft/chathistory` after joining a channel for the
+    /// first time. 0 disables fetching history on join.
+    pub join_history_lines: usize,
+
+    /// Delays between reconnect attempts.
+    pub reconnect_backoff: ReconnectBackoff,
+
+    /// Rate limit of outgoing PRIVMSG and NOTICE messages. Can be changed with
+    /// `Client::set_flood_protection`.
+    pub flood_protection: FloodProtection,
+
+    /// Poll the away status of the users in our channels with WHO when the server doesn't support
+    /// IRCv3 `away-notify`. `None` disables polling.
+    pub away_poll: Option<AwayPoll>,
+}
+
+/// Path of the Unix domain socket in a `unix:<path>` server address, see `ServerInfo::addr`.
+pub fn unix_socket_path(addr: &str) -> Option<&str> {
+    addr.strip_prefix("unix:").filter(|path| !path.is_empty())
+}
+
+/// Delays between reconnect attempts: `base` after the first failed connection, multiplied by
+/// `multiplier` after each failure, up to `max`. Delays are randomly shortened by up to 25%, so
+/// that clients disconnected at the same time don't reconnect in sync. The delay is reset to
+/// `base` after a successful registration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectBackoff {
+    pub base: Duration,
+    pub multiplier: f64,
+    pub max: Duration,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        ReconnectBackoff {
+            base: Duration::from_secs(10),
+            multiplier: 2.0,
+            max: Duration::from_secs(600),
+        }
+    }
+}
+
+impl ReconnectBackoff {
+    /// Delay after `n_failures + 1` failed connections in a row. `jitter` (between 0 and 1) is the
+    /// random part of the delay.
+    fn delay(&self, n_failures: u32, jitter: f64) -> Duration {
+        let exp = i32::try_from(n_failures).unwrap_or(i32::MAX);
+        let delay = (self.base.as_secs_f64() * self.multiplier.powi(exp))
+            .min(self.max.as_secs_f64())
+            .max(0.0);
+        Duration::from_secs_f64(delay * (1.0 - 0.25 * jitter))
+    }
+}
+
+/// Outgoing flood protection: `burst` PRIVMSG and NOTICE messages can be sent at once, after that
+/// one message is sent every `interval`. Messages over the limit are queued, see
+/// `Event::MsgsQueued`. Other messages (e.g. PONG) are sent immediately. Zero `interval` disables
+/// flood protection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloodProtection {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+impl Default for FloodProtection {
+    /// Similar to the limits of common IRC servers.
+    fn default() -> Self {
+        FloodProtection {
+            burst: 5,
+            interval: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Away status polling, see `ServerInfo::away_poll`. One channel is polled at a time, with the
+/// polls spread over `interval`, so every channel is polled about once in `interval`. Channels
+/// with more than `max_users` users are not polled. Polling pauses while the connection is
+/// lagging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AwayPoll {
+    pub interval: Duration,
+    pub max_users: usize,
+}
+
+/// Min. delay between two away status polls, to avoid flooding the server when we're in a lot of
+/// channels.
+const MIN_AWAY_POLL_DELAY: Duration = Duration::from_secs(2);
+
+/// A random number between 0 and 1, for `ReconnectBackoff` jitter.
+fn random_jitter() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    // `RandomState`s are randomly seeded
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    random as f64 / u64::MAX as f64
+}
+
+/// Max. length of a PRIVMSG text so that the message fits in 512 bytes when relayed by the server
+/// with our prefix. `usermask` is our last known `nick!user@host`, the nick may be old.
+fn max_privmsg_len(nick: &str, usermask: Option<&str>, extra_len: usize) -> usize {
+    // Max msg len calculation adapted from hexchat
+    // (src/common/outbound.c:split_up_text)
+    let user_host_len = match usermask.and_then(|usermask| usermask.split_once('!')) {
+        Some((_, user_host)) => user_host.len(),
+        // max username (9), max possible hostname (63), '@'. NOTE(osa): I think hexchat has an
+        // error here, it uses 65
+        None => 9 + 64,
+    };
+    let max = 512 // RFC 2812
+        - 2 // :, !
+        - 13 // " PRIVMSG ", " ", :, \r, \n
+        - nick.len()
+        - user_host_len;
+    let max = max.saturating_sub(extra_len);
+
+    assert!(max > 0);
+    max
+}
+
+/// Order to try the IPv4 and IPv6 addresses of a server in. The address of the last successful
+/// connection is always tried first when reconnecting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Alternate between IPv6 and IPv4 addresses, starting with the family of the first resolved
+    /// address. A broken family then only delays connecting by one attempt.
+    #[default]
+    Auto,
+
+    /// IPv4 addresses first.
+    PreferIpv4,
+
+    /// IPv6 addresses first.
+    PreferIpv6,
+}
+
+/// How long to wait for a connection attempt before trying the next address of the server, when
+/// `ServerInfo::connect_timeout` is not set. The last address is tried without a time limit.
+const ADDR_FALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default for `ServerInfo::registration_timeout`.
+const DEFAULT_REGISTRATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Stages of connecting to a server that can time out. See `Event::TimedOut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectStage {
+    /// Opening the TCP connection, including the SOCKS5 handshake when using a proxy.
+    Connect,
+
+    /// TLS handshake.
+    TlsHandshake,
+
+    /// Connection registration, from connecting until RPL_WELCOME. Includes capability
+    /// negotiation and SASL authentication.
+    Registration,
+}
+
+impl std::fmt::Display for ConnectStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            ConnectStage::Connect => "Connection",
+            ConnectStage::TlsHandshake => "TLS handshake",
+            ConnectStage::Registration => "Registration",
+        })
+    }
+}
+
+/// A user in a channel. See `Client::get_chan_users`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChanUser {
+    pub nick: String,
+    /// Membership prefixes of the user (e.g. `@` for operators), highest rank first.
+    pub prefixes: String,
+    /// Rank of the highest prefix, 0 is the highest. `None` when the user doesn't have a prefix.
+    pub rank: Option<usize>,
+    /// Whether the user is a channel operator, or has a higher rank.
+    pub is_op: bool,
+    /// `user@host` of the user, when known. See `Client::get_user_host`.
+    pub user_host: Option<String>,
+}
+
+/// Topic of a channel. See `Client::get_topic`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChanTopic {
+    pub topic: String,
+    /// Nick (or `nick!user@host`, depending on the server) of the user who set the topic, when
+    /// known.
+    pub set_by: Option<String>,
+    /// When the topic was set, in seconds since Unix epoch, when known.
+    pub set_at: Option<i64>,
+}
+
+/// An invitation to a channel we got. See `Client::invites`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    pub chan: ChanName,
+    /// Nick of the user who invited us.
+    pub from: String,
 }
 
 /// SASL authentication mechanisms
@@ -76,45 +353,182 @@ pub enum SASLAuth {
         username: String,
         password: String,
     },
+    ScramSha256 {
+        username: String,
+        password: String,
+        /// Authenticate with PLAIN if the server does not support SCRAM-SHA-256.
+        plain_fallback: bool,
+    },
     External {
-        /// PEM-encoded X509 private cert and private key file
-        pem: Vec<u8>,
+        /// TLS client certificate. A fingerprint of the certificate should be registered with
+        /// NickServ (CertFP).
+        cert: ClientCert,
     },
 }
 
+/// TLS client certificate, used for SASL EXTERNAL authentication and CertFP
+#[derive(Debug, Clone)]
+pub enum ClientCert {
+    /// PEM-encoded X509 certificate and PKCS#8 private key. These can be the same PEM file.
+    Pem { cert: Vec<u8>, key: Vec<u8> },
+
+    /// DER-encoded PKCS#12 archive with the certificate and the private key. `passphrase` is empty
+    /// when the archive is not encrypted. Only supported with the `tls-native` feature.
+    Pkcs12 { der: Vec<u8>, passphrase: String },
+}
+
+impl ClientCert {
+    /// Check that the certificate and the private key can be loaded for the TLS handshake.
+    pub fn validate(&self) -> Result<(), String> {
+        stream::check_client_cert(self)
+    }
+
+    /// SHA-256 fingerprint of the certificate, as lowercase hex digits. This is what NickServ
+    /// expects when registering the certificate (e.g. `/msg NickServ CERT ADD <fingerprint>`).
+    /// `None` for PKCS#12 archives, and PEM files without a certificate.
+    pub fn fingerprint(&self) -> Option<String> {
+        stream::client_cert_fingerprint(self)
+    }
+}
+
+/// Receiver for the replies to a message sent with an IRCv3 `label` tag (`labeled-response`): a
+/// single message, the messages in the `labeled-response` batch, or nothing when the server
+/// acknowledges the message without a reply (`ACK`). Replies are not reported as `Event::Msg`s.
+/// Receiving fails when the connection is lost before the reply.
+pub type LabeledReply = tokio::sync::oneshot::Receiver<Vec<wire::Msg>>;
+
+/// What to do when SASL authentication fails (ERR_SASLFAIL, ERR_SASLTOOLONG)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SASLFail {
+    /// Continue with the connection registration, unauthenticated.
+    #[default]
+    Continue,
+
+    /// Disconnect, and don't reconnect until `Client::reconnect` is called.
+    Disconnect,
+}
+
 /// IRC client events. Returned by `Client` to the users via a channel.
 ///
 /// Note that Client only returns when it can't resolve the domain name. In all other cases (no
 /// matter what the error is) it continues, in case of a connection error either by trying another
-/// IP address of the same domain, or by waiting `RECONNECT_SECS` seconds and then trying again.
-/// The latter happens after sending a `Disconnected` event.
+/// IP address of the same domain, or by waiting (see `ReconnectBackoff`) and then trying again.
+/// `WaitingToReconnect` is sent before waiting.
 #[derive(Debug)]
 pub enum Event {
     /// Client resolving domain name
     ResolvingHost,
-    /// Domain name resolved, client trying to connect to the given IP address
+    /// Domain name resolved, client trying to connect to the given IP address (the proxy address
+    /// when connecting through a SOCKS5 proxy)
     Connecting(SocketAddr),
+    /// Client trying to connect to the Unix domain socket at the given path (see
+    /// `ServerInfo::addr`)
+    ConnectingUnix(String),
     /// TCP connection established *and* the introduction sequence with the IRC server started.
     Connected,
     /// Disconnected from the server. Usually sent right after an `Event::IoErr`. Client tries to
-    /// reconnect after `RECONNECT_SECS` seconds after sending this event.
+    /// reconnect after sending this event, see `WaitingToReconnect`.
     Disconnected,
+    /// Client will try to connect again after `delay`, or when `Client::reconnect` is called.
+    /// Sent after failing to connect or losing the connection.
+    WaitingToReconnect { delay: Duration },
     /// An IO error happened.
     IoErr(std::io::Error),
     /// A TLS error happened
     TlsErr(stream::TlsError),
+    /// The TLS client certificate could not be loaded (e.g. invalid key, wrong PKCS#12
+    /// passphrase), or the TLS handshake failed while using a client certificate.
+    ClientCertErr(String),
+    /// The TLS server certificate was rejected: it could not be verified, or it doesn't match
+    /// `ServerInfo::pinned_cert`. `fingerprint` (SHA-256, lowercase hex digits) and `subject`
+    /// are of the certificate presented by the server, to let the user decide whether to pin it
+    /// with `Client::pin_cert`.
+    ServerCertErr {
+        err: String,
+        fingerprint: String,
+        subject: Option<String>,
+    },
+    /// The socket could not be bound to `ServerInfo::bind_address`, or the address can't be used
+    /// to connect to the server (e.g. an IPv4 bind address with an IPv6-only server).
+    BindErr { addr: IpAddr, err: std::io::Error },
+    /// A connection stage didn't finish in the time limit given in `ServerInfo`. `addr` is the
+    /// address connected to: the IP address (or the proxy address) for `ConnectStage::Connect`,
+    /// `host:port` of the server otherwise. Client tries the next address of the server, or
+    /// sends `Disconnected` and reconnects.
+    TimedOut {
+        stage: ConnectStage,
+        addr: String,
+        timeout: Duration,
+    },
     /// Remote end closed the connection
     ConnectionClosed,
     /// Client couldn't resolve host address. The client stops after sending this event.
     CantResolveAddr,
     /// Nick changed.
     NickChange { new_nick: String },
-    /// A message from the server
+    /// A message from the server. IRCv3 message tags (e.g. `time`, `msgid`, `account`) are in
+    /// `wire::Msg::tags`, see `wire::Tags::get`. Messages in IRCv3 batches are sent between the
+    /// `BATCH` messages that start and end the batch, use `Batches` to find the batches of a
+    /// message. Messages in `chathistory` batches requested by the client are reported with
+    /// `Event::History` instead, and replies to labeled messages are sent to their `LabeledReply`
+    /// receivers.
     Msg(wire::Msg),
     /// A wire-protocol error
     WireError(String),
     /// Channel join error message
     ChannelJoinError { chan: ChanName, msg: String },
+    /// SASL authentication failed and `ServerInfo::sasl_fail` is `SASLFail::Disconnect`. The
+    /// client disconnects after sending this event, and doesn't reconnect until
+    /// `Client::reconnect` is called.
+    SASLFailDisconnect,
+    /// The server didn't reply to a PING in `timeout` (`ServerInfo::ping_timeout`). The client
+    /// disconnects after sending this event, and reconnects.
+    PingTimeout { timeout: Duration },
+    /// Reply to a ping sent with `Client::ping`. `nick` is `None` for server pings.
+    PingReply { nick: Option<String>, rtt: Duration },
+    /// A message sent with `Client::privmsg` was echoed back by the server (IRCv3
+    /// `echo-message`) with a different text, e.g. because the server truncated it. `msg` is the
+    /// text we sent. The echo is sent as an `Event::Msg` before this event.
+    MsgModified { target: String, msg: String },
+    /// The server rejected a message sent with `Client::privmsg`. Only reported when IRCv3
+    /// `echo-message` and `labeled-response` are enabled. `reason` is from the error reply, which
+    /// is sent as an `Event::Msg` before this event.
+    MsgNotSent {
+        target: String,
+        msg: String,
+        reason: String,
+    },
+    /// Messages of a channel or user fetched from the server with IRCv3 `draft/chathistory`,
+    /// oldest first. History of a channel is fetched after joining it for the first time, and
+    /// with `Client::history`. `older` is `true` for messages fetched with `Client::history`,
+    /// which are older than the messages received before.
+    History {
+        target: wire::MsgTarget,
+        msgs: Vec<wire::Msg>,
+        older: bool,
+    },
+    /// Modes of a channel changed, or were received after joining the channel. See
+    /// `Client::get_chan_modes` for the format of `modes`.
+    ChanModes { chan: ChanName, modes: String },
+    /// The server advertised an IRCv3 STS policy in a plaintext connection. The client
+    /// disconnects after sending this event and reconnects with TLS on `port`, and keeps using
+    /// TLS in reconnects.
+    StsUpgrade { port: u16 },
+    /// The server advertised an IRCv3 STS policy in a TLS connection: TLS should be used on `port`
+    /// for `duration` in new connections to the server. Zero `duration` means the policy should
+    /// be removed. Persisting policies is left to the user.
+    StsPolicy { port: u16, duration: Duration },
+    /// `n_queued` messages to `target` (a channel or a nick) are waiting to be sent because of
+    /// `ServerInfo::flood_protection`. Sent when the number changes, with `n_queued == 0` when
+    /// all messages to `target` are sent or dropped (when the connection is lost).
+    MsgsQueued { target: String, n_queued: usize },
+    /// A PRIVMSG or NOTICE to `target` had characters that can't be encoded with
+    /// `ServerInfo::encoding`. The message was sent with '?' in place of the characters.
+    UnmappableChars { target: String },
+    /// Away status of a user in our channels changed, learned from a WHO reply (e.g. when polling
+    /// with `ServerInfo::away_poll`). Changes announced with IRCv3 `away-notify` are reported as
+    /// `AWAY` messages instead.
+    NickAway { nick: String, away: bool },
 }
 
 impl From<StreamError> for Event {
@@ -122,6 +536,26 @@ impl From<StreamError> for Event {
         match err {
             StreamError::TlsError(tls_err) => Event::TlsErr(tls_err),
             StreamError::IoError(io_err) => Event::IoErr(io_err),
+            StreamError::ClientCert(err) => Event::ClientCertErr(err),
+            StreamError::ServerCert {
+                err,
+                fingerprint,
+                subject,
+            } => Event::ServerCertErr {
+                err,
+                fingerprint,
+                subject,
+            },
+            StreamError::Bind { addr, err } => Event::BindErr { addr, err },
+            StreamError::Timeout {
+                stage,
+                addr,
+                timeout,
+            } => Event::TimedOut {
+                stage,
+                addr,
+                timeout,
+            },
         }
     }
 }
@@ -140,6 +574,9 @@ pub struct Client {
     /// Reference to the state, to be able to provide methods like `get_nick` and
     /// `is_nick_accepted`.
     state: State,
+
+    /// Rate limited messages waiting to be sent, to be able to drop them.
+    send_queue: SendQueue,
 }
 
 impl Client {
@@ -155,6 +592,13 @@ impl Client {
         self.msg_chan.try_send(Cmd::Reconnect(port)).unwrap()
     }
 
+    /// Accept the server certificate with the given SHA-256 fingerprint in the next connections,
+    /// instead of `ServerInfo::pinned_cert` and certificate verification. Use `reconnect` to
+    /// connect with the pinned certificate.
+    pub fn pin_cert(&mut self, fingerprint: &str) {
+        self.state.set_pinned_cert(fingerprint);
+    }
+
     /// Get host name of this connection.
     pub fn get_serv_name(&self) -> &str {
         &self.serv_name
@@ -167,6 +611,11 @@ impl Client {
         self.state.get_nick()
     }
 
+    /// Get nick of the NickServ service. See `ServerInfo::nickserv`.
+    pub fn get_nickserv(&self) -> String {
+        self.state.get_nickserv()
+    }
+
     /// Is current nick accepted by the server?
     pub fn is_nick_accepted(&self) -> bool {
         self.state.is_nick_accepted()
@@ -179,50 +628,85 @@ impl Client {
             .unwrap();
     }
 
-    /// Split a privmsg to multiple messages so that each message is, when the hostname and nick
-    /// prefix added by the server, fits in one IRC message.
+    /// Same as `raw_msg`, but with IRCv3 `labeled-response` returns a receiver for the replies to
+    /// the message. See `LabeledReply`. Returns `None` when `labeled-response` is not enabled,
+    /// replies are then reported as `Event::Msg`s.
+    pub fn labeled_raw_msg(&mut self, msg: &str) -> Option<LabeledReply> {
+        self.send_labeled(format!("{msg}\r\n"))
+    }
+
+    fn send_labeled(&mut self, wire_msg: String) -> Option<LabeledReply> {
+        let (wire_msg, reply) = match self.state.add_labeled_request() {
+            Some((label, reply)) => (wire::labeled(&label, &wire_msg), Some(reply)),
+            None => (wire_msg, None),
+        };
+        self.msg_chan.try_send(Cmd::Msg(wire_msg)).unwrap();
+        reply
+    }
+
+    /// Split a privmsg to multiple messages so that each message, with the `nick!user@host`
+    /// prefix added by the server when relaying it, fits in one IRC message. Messages are split
+    /// at whitespace when possible, and always at UTF-8 character boundaries.
     ///
-    /// `extra_len`: Size (in bytes) for a prefix/suffix etc. that'll be added to each line.
+    /// Our `user@host` is learned from the welcome message (with some servers), JOINs, WHOIS
+    /// replies, and messages echoed with IRCv3 `echo-message`. The longest possible `user@host`
+    /// is assumed until then.
+    ///
+    /// `extra_len`: Size (in bytes) for the target and a prefix/suffix etc. that'll be added to
+    /// each line.
     pub fn split_privmsg<'a>(
         &self,
         extra_len: usize,
         msg: &'a str,
     ) -> impl Iterator<Item = &'a str> + 'a {
-        // Max msg len calculation adapted from hexchat
-        // (src/common/outbound.c:split_up_text)
-        let mut max = 512; // RFC 2812
-        max -= 3; // :, !, @
-        max -= 13; // " PRIVMSG ", " ", :, \r, \n
-        max -= self.get_nick().len();
-        max -= extra_len;
-        match self.state.get_usermask() {
-            None => {
-                max -= 9; // max username
-                max -= 64; // max possible hostname (63) + '@'
-                // NOTE(osa): I think hexchat has an error here, it
-                // uses 65
-            }
-            Some(ref usermask) => {
-                max -= usermask.len();
-            }
-        }
-
-        assert!(max > 0);
-
+        let max = max_privmsg_len(
+            &self.get_nick(),
+            self.state.get_usermask().as_deref(),
+            extra_len,
+        );
         utils::split_iterator(msg, max)
     }
 
     /// Send a privmsg. Note that this method does not split long messages into smaller messages;
     /// use `split_privmsg` for that.
+    ///
+    /// When `echo_message` is enabled the message is shown to us when the server echoes it back.
     pub fn privmsg(&mut self, target: &str, msg: &str, is_action: bool) {
         let wire_fn = if is_action {
             wire::action
         } else {
             wire::privmsg
         };
+        let wire_msg = wire_fn(target, msg);
+        let wire_msg = match self.state.add_pending_msg(target, msg) {
+            Some(label) => wire::labeled(&label, &wire_msg),
+            None => wire_msg,
+        };
+        self.msg_chan.try_send(Cmd::Msg(wire_msg)).unwrap();
+        self.state.reset_typing(target);
+    }
+
+    /// Send a notice. Like `privmsg`, this method does not split long messages.
+    pub fn notice(&mut self, target: &str, msg: &str) {
         self.msg_chan
-            .try_send(Cmd::Msg(wire_fn(target, msg)))
+            .try_send(Cmd::Msg(wire::notice(target, msg)))
             .unwrap();
+        self.state.reset_typing(target);
+    }
+
+    /// Send an IRCv3 `+typing` notification to a channel or user. Does nothing when
+    /// `message-tags` is not enabled. Notifications are rate-limited as recommended by the spec,
+    /// so this can be called on every key press.
+    pub fn typing(&mut self, target: &str, state: TypingState) {
+        if let Some(msg) = self.state.typing(target, state, Instant::now()) {
+            self.msg_chan.try_send(Cmd::Msg(msg)).unwrap();
+        }
+    }
+
+    /// Whether the server echoes PRIVMSGs we send back to us (IRCv3 `echo-message`). When
+    /// enabled messages should be shown when they're echoed back, rather than when sent.
+    pub fn echo_message(&self) -> bool {
+        self.state.is_cap_enabled("echo-message")
     }
 
     /// Join the given list of channels.
@@ -238,6 +722,53 @@ impl Client {
         self.state.leave_channel(&mut self.msg_chan, chan, reason)
     }
 
+    /// Join a channel we were kicked from after `delay`, using the channel key if it was known.
+    /// Leaving the channel with `part` before the delay cancels the join.
+    pub fn rejoin_after(&mut self, chan: &ChanNameRef, delay: Duration) {
+        let Some((key, mut rcv_abort)) = self.state.rejoin(chan) else {
+            return;
+        };
+        let msg_chan = self.msg_chan.clone();
+        let chan = chan.to_owned();
+        tokio::task::spawn_local(async move {
+            // `Ok` means the channel was left, or the connection was reset
+            if tokio::time::timeout(delay, rcv_abort.recv()).await.is_err() {
+                let msg = match key {
+                    Some(key) => wire::join_with_key(&chan, &key),
+                    None => wire::join(std::iter::once(chan.as_ref())),
+                };
+                let _ = msg_chan.try_send(Cmd::Msg(msg));
+            }
+        });
+    }
+
+    /// Join a channel we left or were kicked from, using the channel key if it was known.
+    pub fn rejoin(&mut self, chan: &ChanNameRef) {
+        let msg = match self.state.take_chan_key(chan) {
+            Some(key) => wire::join_with_key(chan, &key),
+            None => wire::join(std::iter::once(chan)),
+        };
+        self.msg_chan.try_send(Cmd::Msg(msg)).unwrap()
+    }
+
+    /// The channel we left last, and the reason we sent when leaving it. `None` when we haven't
+    /// left a channel, or joined the channel again.
+    pub fn last_parted(&self) -> Option<(ChanName, Option<String>)> {
+        self.state.get_last_parted()
+    }
+
+    /// Invitations to channels we got in the last 10 minutes and haven't joined yet, oldest
+    /// first.
+    pub fn invites(&self) -> Vec<Invite> {
+        self.state.get_invites()
+    }
+
+    /// Drop the rate limited messages waiting to be sent. Returns the targets of the dropped
+    /// messages, one per message.
+    pub fn clear_send_queue(&mut self) -> Vec<String> {
+        self.send_queue.clear()
+    }
+
     /// Set away status. `None` means not away.
     pub fn away(&mut self, msg: Option<&str>) {
         self.state.set_away(msg);
@@ -262,10 +793,220 @@ impl Client {
         self.msg_chan.try_send(Cmd::Quit(reason)).unwrap();
     }
 
+    /// Measure lag to the server, or to a user with a CTCP PING when `nick` is given. Round-trip
+    /// time is reported with an `Event::PingReply` when the reply arrives.
+    pub fn ping(&mut self, nick: Option<&str>) {
+        let token = self.state.add_ping(nick);
+        let msg = match nick {
+            None => wire::ping(&token),
+            Some(nick) => wire::ctcp_ping(nick, &token),
+        };
+        self.msg_chan.try_send(Cmd::Msg(msg)).unwrap()
+    }
+
+    /// Reply to a CTCP request from `nick`. `ctcp` is the CTCP type, e.g. `VERSION`.
+    pub fn ctcp_reply(&mut self, nick: &str, ctcp: &str, body: &str) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::ctcp_reply(nick, ctcp, body)))
+            .unwrap()
+    }
+
+    /// Round-trip time of the last ping to the server that was replied. Pings are sent after not
+    /// hearing from the server for `ServerInfo::ping_interval`, and with `Client::ping`. `None`
+    /// until the first reply in a connection.
+    pub fn last_lag(&self) -> Option<Duration> {
+        self.state.last_lag()
+    }
+
+    /// Current flood protection settings. See `ServerInfo::flood_protection`.
+    pub fn flood_protection(&self) -> FloodProtection {
+        self.state.flood_protection()
+    }
+
+    /// Change the flood protection settings. Applies to the queued messages too.
+    pub fn set_flood_protection(&self, flood_protection: FloodProtection) {
+        self.state.set_flood_protection(flood_protection)
+    }
+
+    /// Check which of the given nicks are online. Server replies with RPL_ISON (303), listing
+    /// the ones that are online.
+    pub fn ison(&mut self, nicks: &[&str]) {
+        self.msg_chan.try_send(Cmd::Msg(wire::ison(nicks))).unwrap()
+    }
+
+    /// Set the topic of a channel. Server sends a TOPIC message back when the topic is changed.
+    pub fn set_topic(&mut self, chan: &ChanNameRef, topic: &str) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::topic(chan, topic)))
+            .unwrap()
+    }
+
+    /// Query the local time of the server, or of `target` server in the network. Server replies
+    /// with RPL_TIME (391).
+    pub fn time(&mut self, target: Option<&str>) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::time(target)))
+            .unwrap()
+    }
+
+    /// Query the software version of the server, or of `target` server in the network. Server
+    /// replies with RPL_VERSION (351).
+    pub fn version(&mut self, target: Option<&str>) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::version(target)))
+            .unwrap()
+    }
+
+    /// Query statistics of the server, or of `target` server in the network. Server replies with
+    /// RPL_STATS* numerics depending on `query`, followed by RPL_ENDOFSTATS (219).
+    pub fn stats(&mut self, query: &str, target: Option<&str>) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::stats(query, target)))
+            .unwrap()
+    }
+
+    /// Query the administrative information of the server, or of `target` server in the network.
+    /// Server replies with RPL_ADMINME (256) to RPL_ADMINEMAIL (259).
+    pub fn admin(&mut self, target: Option<&str>) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::admin(target)))
+            .unwrap()
+    }
+
+    /// Send a WHOIS message. With IRCv3 `labeled-response` replies are sent to the returned
+    /// receiver, otherwise they're reported as `Event::Msg`s.
+    pub fn whois(&mut self, nick: &str) -> Option<LabeledReply> {
+        self.send_labeled(wire::whois(nick))
+    }
+
+    /// Send a WHO message. Server replies with RPL_WHOREPLY (352) for each matching user, followed
+    /// by RPL_ENDOFWHO (315). With IRCv3 `labeled-response` replies are sent to the returned
+    /// receiver, otherwise they're reported as `Event::Msg`s.
+    pub fn who(&mut self, mask: &str) -> Option<LabeledReply> {
+        self.send_labeled(wire::who(mask))
+    }
+
+    /// Query the user, server and channel counts of the network. Server replies with
+    /// RPL_LUSERCLIENT (251) to RPL_LUSERME (255), and usually RPL_LOCALUSERS (265) and
+    /// RPL_GLOBALUSERS (266). With IRCv3 `labeled-response` replies are sent to the returned
+    /// receiver, otherwise they're reported as `Event::Msg`s.
+    pub fn lusers(&mut self) -> Option<LabeledReply> {
+        self.send_labeled(wire::lusers())
+    }
+
+    /// Change real name with IRCv3 `setname`. Check `get_enabled_caps` first, the message should
+    /// not be sent when `setname` is not enabled. The server replies with a `SETNAME` message
+    /// when the change is accepted, which also updates `ServerInfo::realname` for reconnects, or
+    /// with a `FAIL SETNAME` message.
+    pub fn setname(&mut self, realname: &str) {
+        self.msg_chan
+            .try_send(Cmd::Msg(wire::setname(realname)))
+            .unwrap()
+    }
+
+    /// Fetch `limit` messages of a channel or user older than the ones fetched before, with IRCv3
+    /// `draft/chathistory`. Messages are reported with an `Event::History`.
+    pub fn history(&mut self, target: &str, limit: usize) {
+        let msg = self.state.history_request(target, limit);
+        self.msg_chan.try_send(Cmd::Msg(msg)).unwrap()
+    }
+
+    /// Forget the users of a channel and get them from the server again, e.g. when the list of
+    /// users is out of date. Until the server replies the channel has no users.
+    pub fn refresh_names(&mut self, chan: &ChanNameRef) {
+        for msg in self.state.refresh_names(chan) {
+            self.msg_chan.try_send(Cmd::Msg(msg)).unwrap()
+        }
+    }
+
     /// Get all nicks in a channel.
     pub fn get_chan_nicks(&self, chan: &ChanNameRef) -> Vec<String> {
         self.state.get_chan_nicks(chan)
     }
+
+    /// Whether the nick is marked as away. Only known for users in the channels we're in, when
+    /// IRCv3 `away-notify` is enabled or with `ServerInfo::away_poll`.
+    pub fn is_away(&self, nick: &str) -> bool {
+        self.state.is_nick_away(nick)
+    }
+
+    /// Services account the nick is logged into. Only known for users in the channels we're in,
+    /// when IRCv3 `extended-join`, `account-notify` or `account-tag` is enabled, or after a
+    /// WHOIS.
+    pub fn get_account(&self, nick: &str) -> Option<String> {
+        self.state.get_account(nick)
+    }
+
+    /// `user@host` of the nick. Only known for users in the channels we're in, after they join or
+    /// when IRCv3 `userhost-in-names`, `away-notify` or `chghost` is enabled. Kept up to date with
+    /// `chghost`.
+    pub fn get_user_host(&self, nick: &str) -> Option<String> {
+        self.state.get_user_host(nick)
+    }
+
+    /// Real name of the nick. Only known for users in the channels we're in, after a WHO (sent
+    /// when joining a channel with IRCv3 `away-notify`, `chghost` or `setname`). Kept up to date
+    /// with `setname`.
+    pub fn get_realname(&self, nick: &str) -> Option<String> {
+        self.state.get_realname(nick)
+    }
+
+    /// Get all users in a channel, with their membership prefixes. Sorted by nick.
+    pub fn get_chan_users(&self, chan: &ChanNameRef) -> Vec<ChanUser> {
+        self.state.get_chan_users(chan)
+    }
+
+    /// Get the topic of a channel. `None` when the channel doesn't have a topic, or we haven't
+    /// received it yet.
+    pub fn get_topic(&self, chan: &ChanNameRef) -> Option<ChanTopic> {
+        self.state.get_topic(chan)
+    }
+
+    /// Get the creation time of a channel, in seconds since Unix epoch, from RPL_CREATIONTIME.
+    pub fn get_chan_creation_time(&self, chan: &ChanNameRef) -> Option<i64> {
+        self.state.get_chan_creation_time(chan)
+    }
+
+    /// Get the modes of a channel in the MODE syntax, e.g. `+klnt key 10`. Membership modes and
+    /// list modes (e.g. bans) are not included. Empty when no modes are set, `None` until the
+    /// server sends the modes after joining. Changes are reported with `Event::ChanModes`.
+    pub fn get_chan_modes(&self, chan: &ChanNameRef) -> Option<String> {
+        self.state.get_chan_modes(chan)
+    }
+
+    /// Get the modes of a channel from the server again. Replies are not reported as
+    /// `Event::Msg`s, an `Event::ChanModes` is sent when the reply arrives.
+    pub fn refresh_chan_modes(&mut self, chan: &ChanNameRef) {
+        if let Some(msg) = self.state.refresh_chan_modes(chan) {
+            self.msg_chan.try_send(Cmd::Msg(msg)).unwrap()
+        }
+    }
+
+    /// Get channels that we're currently in.
+    pub fn get_joined_chans(&self) -> Vec<ChanName> {
+        self.state.get_joined_chans()
+    }
+
+    /// Get the features of the server advertised in RPL_ISUPPORT, e.g. channel name prefixes.
+    pub fn get_isupport(&self) -> ISupport {
+        self.state.get_isupport()
+    }
+
+    /// Get IRCv3 capabilities advertised by the server (CAP LS).
+    pub fn get_server_caps(&self) -> Vec<String> {
+        self.state.get_server_caps()
+    }
+
+    /// Address of the last connection: the server address that worked, or the proxy address when
+    /// connecting through a SOCKS5 proxy. `None` until connected.
+    pub fn get_addr(&self) -> Option<SocketAddr> {
+        self.state.get_addr()
+    }
+
+    /// Get IRCv3 capabilities enabled for the connection (CAP ACK).
+    pub fn get_enabled_caps(&self) -> Vec<String> {
+        self.state.get_enabled_caps()
+    }
 }
 
 //
@@ -304,7 +1045,14 @@ fn connect(server_info: ServerInfo) -> (Client, mpsc::Receiver<Event>) {
     let irc_state = State::new(server_info.clone());
     let irc_state_clone = irc_state.clone();
 
-    let task = main_loop(server_info, irc_state_clone, snd_ev, rcv_cmd);
+    let send_queue = SendQueue::default();
+    let task = main_loop(
+        server_info,
+        irc_state_clone,
+        send_queue.clone(),
+        snd_ev,
+        rcv_cmd,
+    );
     tokio::task::spawn_local(task);
 
     (
@@ -312,6 +1060,7 @@ fn connect(server_info: ServerInfo) -> (Client, mpsc::Receiver<Event>) {
             msg_chan: snd_cmd,
             serv_name,
             state: irc_state,
+            send_queue,
         },
         rcv_ev,
     )
@@ -320,6 +1069,7 @@ fn connect(server_info: ServerInfo) -> (Client, mpsc::Receiver<Event>) {
 async fn main_loop(
     server_info: ServerInfo,
     irc_state: State,
+    send_queue: SendQueue,
     mut snd_ev: mpsc::Sender<Event>,
     rcv_cmd: mpsc::Receiver<Cmd>,
 ) {
@@ -328,13 +1078,40 @@ async fn main_loop(
     // We allow changing ports when reconnecting, so `mut`
     let mut port = server_info.port;
 
+    // Plaintext connections are upgraded to TLS with IRCv3 STS, so `mut`
+    let mut tls = server_info.tls;
+
     // Whether to wait before trying to (re)connect
     let mut wait = false;
 
+    // Address of the last successful connection, tried first when reconnecting
+    let mut last_addr: Option<SocketAddr> = None;
+
+    // When waiting, whether to wait for a `Cmd::Reconnect` instead of the reconnect delay
+    let mut wait_for_reconnect = false;
+
+    // Number of failed connections since the last successful registration, for the reconnect
+    // delay
+    let mut n_failures: u32 = 0;
+
     // Main loop just tries to (re)connect
     'connect: loop {
         if wait {
-            match wait_(&mut rcv_cmd).await {
+            let delay = if wait_for_reconnect {
+                None
+            } else {
+                let delay = server_info
+                    .reconnect_backoff
+                    .delay(n_failures, random_jitter());
+                n_failures = n_failures.saturating_add(1);
+                snd_ev
+                    .send(Event::WaitingToReconnect { delay })
+                    .await
+                    .unwrap();
+                Some(delay)
+            };
+            wait_for_reconnect = false;
+            match wait_(&mut rcv_cmd, delay).await {
                 TaskResult::Done(()) => {}
                 TaskResult::Reconnect(mb_port) => {
                     port = mb_port.unwrap_or(port);
@@ -351,71 +1128,115 @@ async fn main_loop(
         // trailing "\r\n") and the task directly sends them to the server.
         let (mut snd_msg, rcv_msg) = mpsc::channel::<String>(100);
 
-        //
-        // Resolve IP address
-        //
+        let serv_name = server_info.addr.clone();
 
-        snd_ev.send(Event::ResolvingHost).await.unwrap();
+        let targets: Vec<TcpTarget> = if unix_socket_path(&serv_name).is_some() {
+            // Connected in `try_connect`, nothing to resolve
+            vec![]
+        } else if let Some(proxy) = server_info.socks5_proxy {
+            // Host name is resolved by the proxy
+            vec![TcpTarget::Socks5 {
+                proxy,
+                host: &serv_name,
+                port,
+            }]
+        } else {
+            //
+            // Resolve IP address
+            //
 
-        let serv_name = server_info.addr.clone();
+            snd_ev.send(Event::ResolvingHost).await.unwrap();
 
-        debug!("Resolving address");
+            debug!("Resolving address");
 
-        let serv_name_clone = serv_name.clone();
+            let serv_name_clone = serv_name.clone();
 
-        let addr_iter = match resolve_addr(serv_name_clone, port, &mut rcv_cmd).await {
-            TaskResult::Done(Ok(addr_iter)) => {
-                debug!("resolve_addr: done");
-                addr_iter
-            }
-            TaskResult::Done(Err(err)) => {
-                debug!("resolve_addr: {err:?}");
-                snd_ev.send(Event::IoErr(err)).await.unwrap();
-                wait = true;
-                continue;
-            }
-            TaskResult::Reconnect(mb_port) => {
-                debug!("resolve_addr: try again");
-                port = mb_port.unwrap_or(port);
-                wait = false;
-                continue;
-            }
-            TaskResult::Return => {
-                debug!("resolve_addr: return");
+            let addr_iter = match resolve_addr(serv_name_clone, port, &mut rcv_cmd).await {
+                TaskResult::Done(Ok(addr_iter)) => {
+                    debug!("resolve_addr: done");
+                    addr_iter
+                }
+                TaskResult::Done(Err(err)) => {
+                    debug!("resolve_addr: {err:?}");
+                    snd_ev.send(Event::IoErr(err)).await.unwrap();
+                    wait = true;
+                    continue;
+                }
+                TaskResult::Reconnect(mb_port) => {
+                    debug!("resolve_addr: try again");
+                    port = mb_port.unwrap_or(port);
+                    wait = false;
+                    continue;
+                }
+                TaskResult::Return => {
+                    debug!("resolve_addr: return");
+                    return;
+                }
+            };
+
+            let addrs = addr_iter.collect::<Vec<_>>();
+
+            if addrs.is_empty() {
+                snd_ev.send(Event::CantResolveAddr).await.unwrap();
                 return;
             }
-        };
-
-        let addrs = addr_iter.collect::<Vec<_>>();
 
-        if addrs.is_empty() {
-            snd_ev.send(Event::CantResolveAddr).await.unwrap();
-            return;
-        }
+            debug!("Address resolved: {addrs:?}");
+
+            let addrs = order_addrs(addrs, server_info.ip_preference, last_addr);
+
+            // With a bind address try the addresses of the same family. If there are none try the
+            // others anyway to report the mismatch.
+            let addrs = match server_info.bind_address {
+                None => addrs,
+                Some(bind_addr) => {
+                    let (same_family, other_family): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+                        .into_iter()
+                        .partition(|addr| addr.is_ipv4() == bind_addr.is_ipv4());
+                    if same_family.is_empty() {
+                        other_family
+                    } else {
+                        same_family
+                    }
+                }
+            };
 
-        debug!("Address resolved: {addrs:?}");
+            addrs.into_iter().map(TcpTarget::Direct).collect()
+        };
 
         //
         // Establish TCP connection to the server
         //
 
-        let sasl_pem = if let Some(SASLAuth::External { pem }) = &server_info.sasl_auth {
-            Some(pem)
+        let client_cert = if let Some(SASLAuth::External { cert }) = &server_info.sasl_auth {
+            Some(cert)
         } else {
-            None
+            server_info.client_cert.as_ref()
+        };
+        // Pinned certificate can be updated with `Client::pin_cert`, so get it from the state
+        let pinned_cert = irc_state.get_pinned_cert();
+        let tls_opts = TlsOptions {
+            client_cert,
+            ca_certs: server_info.ca_certs.as_deref(),
+            pinned_cert: pinned_cert.as_deref(),
+            ignore_hostname: !server_info.tls_verify_hostname,
+            handshake_timeout: server_info.tls_handshake_timeout,
         };
 
         let stream = match try_connect(
-            addrs,
-            &serv_name,
-            server_info.tls,
-            sasl_pem,
+            targets,
+            &server_info,
+            tls,
+            &tls_opts,
             &mut rcv_cmd,
             &mut snd_ev,
         )
         .await
         {
-            TaskResult::Done(Some(stream)) => stream,
+            TaskResult::Done(Some((addr, stream))) => {
+                last_addr = addr;
+                stream
+            }
             TaskResult::Done(None) => {
                 snd_ev.send(Event::Disconnected).await.unwrap();
                 wait = true;
@@ -431,7 +1252,7 @@ async fn main_loop(
             }
         };
 
-        let (mut read_half, mut write_half) = tokio::io::split(stream);
+        let (mut read_half, write_half) = tokio::io::split(stream);
 
         debug!("Done");
 
@@ -441,33 +1262,52 @@ async fn main_loop(
 
         // Reset the connection state
         irc_state.reset();
-        // Introduce self
-        if server_info.sasl_auth.is_some() {
-            // Will introduce self after getting a response to this LS command.
-            // This is to avoid getting stuck during nick registration. See the
-            // discussion in #91.
-            snd_msg.try_send(wire::cap_ls()).unwrap();
-        } else {
+        irc_state.set_addr(last_addr);
+        // Introduce self. Capabilities (e.g. `server-time`) are requested after getting a
+        // response to the LS command. With SASL we also introduce self after the response, to
+        // avoid getting stuck during nick registration. See the discussion in #91.
+        snd_msg.try_send(wire::cap_ls()).unwrap();
+        if server_info.sasl_auth.is_none() {
             irc_state.introduce(&mut snd_msg);
         }
 
         // Spawn a task for outgoing messages.
-        let snd_ev_clone = snd_ev.clone();
-        tokio::task::spawn_local(async move {
-            let mut rcv_msg = ReceiverStream::new(rcv_msg);
-            while let Some(msg) = rcv_msg.next().await {
-                if let Err(io_err) = write_half.write_all(msg.as_bytes()).await {
-                    debug!("IO error when writing: {io_err:?}");
-                    snd_ev_clone.send(Event::IoErr(io_err)).await.unwrap();
-                    return;
-                }
-            }
-        });
+        tokio::task::spawn_local(send_task(
+            write_half,
+            rcv_msg,
+            irc_state.clone(),
+            send_queue.clone(),
+            server_info.encoding,
+            snd_ev.clone(),
+        ));
 
         // Spawn pinger task
-        let (mut pinger, rcv_ping_evs) = Pinger::new();
+        let ping_timeout = server_info
+            .ping_timeout
+            .unwrap_or(pinger::DEFAULT_PING_TIMEOUT);
+        let (mut pinger, rcv_ping_evs) = Pinger::new(
+            server_info
+                .ping_interval
+                .unwrap_or(pinger::DEFAULT_PING_INTERVAL),
+            ping_timeout,
+        );
         let mut rcv_ping_evs = ReceiverStream::new(rcv_ping_evs).fuse();
 
+        // Don't get stuck when the server stops replying during registration
+        let registration_timeout = server_info
+            .registration_timeout
+            .unwrap_or(DEFAULT_REGISTRATION_TIMEOUT);
+        let registration_timer = tokio::time::sleep(registration_timeout);
+        pin!(registration_timer);
+
+        let away_poll_timer = tokio::time::sleep(
+            server_info
+                .away_poll
+                .map(|away_poll| away_poll.interval)
+                .unwrap_or_default(),
+        );
+        pin!(away_poll_timer);
+
         let mut parse_buf: Vec<u8> = Vec::with_capacity(1024);
 
         loop {
@@ -520,22 +1360,113 @@ async fn main_loop(
                         }
                         Ok(bytes) => {
                             parse_buf.extend_from_slice(&read_buf[0..bytes]);
-                            while let Some(msg) = wire::parse_irc_msg(&mut parse_buf) {
+                            while let Some(msg) = wire::parse_irc_msg_with_encoding(
+                                &mut parse_buf,
+                                server_info.encoding,
+                                server_info.fallback_encoding,
+                            ) {
                                 match msg {
                                     Err(err) => {
                                         snd_ev.send(Event::WireError(err)).await.unwrap();
                                     }
-                                    Ok(mut msg) => {
+                                    Ok(msg) => {
                                         debug!("parsed msg: {msg:?}");
                                         pinger.reset();
+                                        // Messages in `chathistory` batches are reported at the
+                                        // end of the batch
+                                        let Some(msg) = irc_state.add_history_msg(msg) else {
+                                            continue;
+                                        };
+                                        // Replies to labeled messages are sent to their
+                                        // receivers
+                                        let Some(mut msg) = irc_state.add_labeled_reply(msg)
+                                        else {
+                                            continue;
+                                        };
+                                        // Check before updating the state, as the state update
+                                        // marks the WHO as done on RPL_ENDOFWHO, and the MODE
+                                        // query as done on RPL_CREATIONTIME.
+                                        let hide = irc_state.is_auto_reply(&msg);
                                         irc_state.update(&mut msg, &mut snd_ev, &mut snd_msg);
-                                        snd_ev.send(Event::Msg(msg)).await.unwrap();
+                                        if irc_state.is_nick_accepted() {
+                                            n_failures = 0;
+                                        }
+                                        let echo_ev = irc_state.match_echo(&msg);
+                                        if !hide {
+                                            snd_ev.send(Event::Msg(msg)).await.unwrap();
+                                        }
+                                        if let Some(echo_ev) = echo_ev {
+                                            snd_ev.send(echo_ev).await.unwrap();
+                                        }
+                                        for (nick, away) in irc_state.take_away_changes() {
+                                            snd_ev
+                                                .send(Event::NickAway { nick, away })
+                                                .await
+                                                .unwrap();
+                                        }
+                                        if let Some(sts) = irc_state.take_sts() {
+                                            if !tls && let Some(sts_port) = sts.port {
+                                                // Don't continue in plaintext, reconnect with
+                                                // TLS right away
+                                                snd_ev
+                                                    .send(Event::StsUpgrade { port: sts_port })
+                                                    .await
+                                                    .unwrap();
+                                                tls = true;
+                                                port = sts_port;
+                                                wait = false;
+                                                continue 'connect;
+                                            }
+                                            if tls && let Some(duration) = sts.duration {
+                                                snd_ev
+                                                    .send(Event::StsPolicy { port, duration })
+                                                    .await
+                                                    .unwrap();
+                                            }
+                                        }
+                                        if irc_state.is_sasl_failed()
+                                            && server_info.sasl_fail == SASLFail::Disconnect
+                                        {
+                                            snd_msg.try_send(wire::quit(None)).unwrap();
+                                            snd_ev.send(Event::SASLFailDisconnect).await.unwrap();
+                                            wait = true;
+                                            wait_for_reconnect = true;
+                                            continue 'connect;
+                                        }
                                     }
                                 }
                             }
                         }
                     }
                 }
+                () = &mut registration_timer, if !irc_state.is_nick_accepted() => {
+                    debug!("main loop: registration timed out");
+                    snd_ev
+                        .send(Event::TimedOut {
+                            stage: ConnectStage::Registration,
+                            addr: format!("{}:{}", server_info.addr, port),
+                            timeout: registration_timeout,
+                        })
+                        .await
+                        .unwrap();
+                    snd_ev.send(Event::Disconnected).await.unwrap();
+                    wait = true;
+                    continue 'connect;
+                }
+                () = &mut away_poll_timer, if server_info.away_poll.is_some() => {
+                    let away_poll = server_info.away_poll.unwrap();
+                    // Messages queued by flood protection also mean that the server is slow to
+                    // accept messages
+                    if send_queue.is_empty() {
+                        irc_state.poll_away(away_poll.max_users, &mut snd_msg);
+                    }
+                    let delay = irc_state
+                        .away_poll_delay(away_poll.interval)
+                        .max(MIN_AWAY_POLL_DELAY);
+                    away_poll_timer
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + delay);
+                }
                 ping_ev = rcv_ping_evs.next() => {
                     match ping_ev {
                         None => {
@@ -545,7 +1476,12 @@ async fn main_loop(
                             irc_state.send_ping(&mut snd_msg);
                         }
                         Some(pinger::Event::Disconnect) => {
-                            // TODO: indicate that this is a ping timeout
+                            snd_ev
+                                .send(Event::PingTimeout {
+                                    timeout: ping_timeout,
+                                })
+                                .await
+                                .unwrap();
                             snd_ev.send(Event::Disconnected).await.unwrap();
                             // TODO: hopefully dropping the pinger rcv end is enough to stop it?
                             wait = true;
@@ -558,14 +1494,134 @@ async fn main_loop(
     }
 }
 
+/// Send messages received from `rcv_msg` to the server. PRIVMSG and NOTICE messages are rate
+/// limited with `FloodProtection`: messages over the limit are queued in `queue` and sent in
+/// order, other messages are sent immediately. Returns when `rcv_msg` is closed, or on write
+/// errors.
+async fn send_task(
+    mut write_half: tokio::io::WriteHalf<Stream>,
+    rcv_msg: mpsc::Receiver<String>,
+    irc_state: State,
+    queue: SendQueue,
+    encoding: wire::Encoding,
+    snd_ev: mpsc::Sender<Event>,
+) {
+    let mut rcv_msg = ReceiverStream::new(rcv_msg).fuse();
+    let mut bucket = TokenBucket::new(irc_state.flood_protection(), Instant::now());
+
+    loop {
+        let mut delay = None;
+        // The queue may be cleared with `Client::clear_send_queue` while waiting
+        if let Some((target, msg)) = queue.pop_front() {
+            let wait = bucket.take(irc_state.flood_protection(), Instant::now());
+            if wait.is_zero() {
+                if !write_msg(&mut write_half, &msg, encoding, &snd_ev).await {
+                    queue.push_front(target, msg);
+                    break;
+                }
+                let n_queued = queue.n_queued(&target);
+                snd_ev
+                    .send(Event::MsgsQueued { target, n_queued })
+                    .await
+                    .unwrap();
+                continue;
+            }
+            queue.push_front(target, msg);
+            delay = Some(wait);
+        }
+
+        let msg = match delay {
+            None => rcv_msg.next().await,
+            Some(delay) => select! {
+                msg = rcv_msg.next() => msg,
+                () = tokio::time::sleep(delay) => continue,
+            },
+        };
+        let Some(msg) = msg else {
+            break;
+        };
+
+        let target = flood::rate_limited_target(&msg).map(str::to_owned);
+        match target {
+            Some(target)
+                if !queue.is_empty()
+                    || !bucket
+                        .take(irc_state.flood_protection(), Instant::now())
+                        .is_zero() =>
+            {
+                let n_queued = queue.push_back(target.clone(), msg);
+                snd_ev
+                    .send(Event::MsgsQueued { target, n_queued })
+                    .await
+                    .unwrap();
+            }
+            _ => {
+                if !write_msg(&mut write_half, &msg, encoding, &snd_ev).await {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Queued messages are dropped
+    let mut targets = queue.clear();
+    targets.sort();
+    targets.dedup();
+    for target in targets {
+        snd_ev
+            .send(Event::MsgsQueued {
+                target,
+                n_queued: 0,
+            })
+            .await
+            .unwrap();
+    }
+}
+
+/// Returns `false` after sending an `Event::IoErr` when the message can't be written.
+async fn write_msg(
+    write_half: &mut tokio::io::WriteHalf<Stream>,
+    msg: &str,
+    encoding: wire::Encoding,
+    snd_ev: &mpsc::Sender<Event>,
+) -> bool {
+    let (bytes, unmappable) = encoding.encode_msg(msg);
+    if unmappable && let Some(target) = flood::rate_limited_target(msg) {
+        snd_ev
+            .send(Event::UnmappableChars {
+                target: target.to_owned(),
+            })
+            .await
+            .unwrap();
+    }
+    match write_half.write_all(&bytes).await {
+        Ok(()) => true,
+        Err(io_err) => {
+            debug!("IO error when writing: {io_err:?}");
+            snd_ev.send(Event::IoErr(io_err)).await.unwrap();
+            false
+        }
+    }
+}
+
 enum TaskResult<A> {
     Done(A),
     Return,
     Reconnect(Option<u16>),
 }
 
-async fn wait_<S: StreamExt<Item = Cmd> + Unpin>(rcv_cmd: &mut S) -> TaskResult<()> {
-    let delay = tokio::time::sleep(Duration::from_secs(RECONNECT_SECS)).fuse();
+/// Wait for `delay`, or for a `Cmd::Reconnect` or `Cmd::Quit` when `delay` is `None`.
+async fn wait_<S: StreamExt<Item = Cmd> + Unpin>(
+    rcv_cmd: &mut S,
+    delay: Option<Duration>,
+) -> TaskResult<()> {
+    let delay = async {
+        match delay {
+            Some(delay) => tokio::time::sleep(delay).await,
+            None => std::future::pending().await,
+        }
+    }
+    .fuse();
     pin!(delay);
 
     loop {
@@ -596,6 +1652,50 @@ async fn wait_<S: StreamExt<Item = Cmd> + Unpin>(rcv_cmd: &mut S) -> TaskResult<
     }
 }
 
+/// Order resolved addresses of a server by `preference`, with `last_addr` first if it's one of
+/// them.
+fn order_addrs(
+    addrs: Vec<SocketAddr>,
+    preference: IpPreference,
+    last_addr: Option<SocketAddr>,
+) -> Vec<SocketAddr> {
+    let ipv6_first = match preference {
+        IpPreference::Auto => addrs.first().is_some_and(SocketAddr::is_ipv6),
+        IpPreference::PreferIpv4 => false,
+        IpPreference::PreferIpv6 => true,
+    };
+    let (ipv6, ipv4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let (first, second) = if ipv6_first {
+        (ipv6, ipv4)
+    } else {
+        (ipv4, ipv6)
+    };
+
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    if preference == IpPreference::Auto {
+        let mut first = first.into_iter();
+        let mut second = second.into_iter();
+        loop {
+            match (first.next(), second.next()) {
+                (None, None) => break,
+                (addr1, addr2) => ordered.extend(addr1.into_iter().chain(addr2)),
+            }
+        }
+    } else {
+        ordered.extend(first);
+        ordered.extend(second);
+    }
+
+    if let Some(last_addr) = last_addr
+        && let Some(idx) = ordered.iter().position(|addr| *addr == last_addr)
+    {
+        let addr = ordered.remove(idx);
+        ordered.insert(0, addr);
+    }
+    ordered
+}
+
 async fn resolve_addr<S: StreamExt<Item = Cmd> + Unpin>(
     serv_name: String,
     port: u16,
@@ -639,27 +1739,55 @@ async fn resolve_addr<S: StreamExt<Item = Cmd> + Unpin>(
 }
 
 async fn try_connect<S: StreamExt<Item = Cmd> + Unpin>(
-    addrs: Vec<SocketAddr>,
-    serv_name: &str,
+    targets: Vec<TcpTarget<'_>>,
+    server_info: &ServerInfo,
     use_tls: bool,
-    sasl_pem: Option<&Vec<u8>>,
+    tls_opts: &TlsOptions<'_>,
     rcv_cmd: &mut S,
     snd_ev: &mut mpsc::Sender<Event>,
-) -> TaskResult<Option<Stream>> {
+) -> TaskResult<Option<(Option<SocketAddr>, Stream)>> {
+    let bind_addr = server_info.bind_address;
     let connect_task = async move {
-        for addr in addrs {
+        if let Some(path) = unix_socket_path(&server_info.addr) {
+            snd_ev
+                .send(Event::ConnectingUnix(path.to_owned()))
+                .await
+                .unwrap();
+            return match Stream::new_unix(path, server_info.connect_timeout).await {
+                Err(err) => {
+                    snd_ev.send(Event::from(err)).await.unwrap();
+                    None
+                }
+                Ok(stream) => Some((None, stream)),
+            };
+        }
+
+        let n_targets = targets.len();
+        for (target_idx, target) in targets.into_iter().enumerate() {
+            let addr = target.addr();
             snd_ev.send(Event::Connecting(addr)).await.unwrap();
+            // Don't get stuck on an unreachable address when there are others to try
+            let connect_timeout = server_info
+                .connect_timeout
+                .or((target_idx + 1 < n_targets).then_some(ADDR_FALLBACK_TIMEOUT));
             let mb_stream = if use_tls {
-                Stream::new_tls(addr, serv_name, sasl_pem).await
+                Stream::new_tls(
+                    target,
+                    bind_addr,
+                    connect_timeout,
+                    &server_info.addr,
+                    tls_opts,
+                )
+                .await
             } else {
-                Stream::new_tcp(addr).await
+                Stream::new_tcp(target, bind_addr, connect_timeout).await
             };
             match mb_stream {
                 Err(err) => {
                     snd_ev.send(Event::from(err)).await.unwrap();
                 }
                 Ok(stream) => {
-                    return Some(stream);
+                    return Some((Some(addr), stream));
                 }
             }
         }
@@ -695,3 +1823,117 @@ async fn try_connect<S: StreamExt<Item = Cmd> + Unpin>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_socket_path() {
+        assert_eq!(
+            unix_socket_path("unix:/run/irc.sock"),
+            Some("/run/irc.sock")
+        );
+        assert_eq!(unix_socket_path("unix:"), None);
+        assert_eq!(unix_socket_path("irc.libera.chat"), None);
+    }
+
+    #[test]
+    fn test_order_addrs() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        let addrs = vec![
+            addr("[2001:db8::1]:6697"),
+            addr("[2001:db8::2]:6697"),
+            addr("192.0.2.1:6697"),
+            addr("192.0.2.2:6697"),
+            addr("192.0.2.3:6697"),
+        ];
+
+        assert_eq!(
+            order_addrs(addrs.clone(), IpPreference::Auto, None),
+            vec![
+                addr("[2001:db8::1]:6697"),
+                addr("192.0.2.1:6697"),
+                addr("[2001:db8::2]:6697"),
+                addr("192.0.2.2:6697"),
+                addr("192.0.2.3:6697"),
+            ]
+        );
+        assert_eq!(
+            order_addrs(addrs.clone(), IpPreference::PreferIpv4, None),
+            vec![
+                addr("192.0.2.1:6697"),
+                addr("192.0.2.2:6697"),
+                addr("192.0.2.3:6697"),
+                addr("[2001:db8::1]:6697"),
+                addr("[2001:db8::2]:6697"),
+            ]
+        );
+
+        // Last working address is tried first
+        assert_eq!(
+            order_addrs(
+                addrs.clone(),
+                IpPreference::PreferIpv6,
+                Some(addr("192.0.2.2:6697"))
+            ),
+            vec![
+                addr("192.0.2.2:6697"),
+                addr("[2001:db8::1]:6697"),
+                addr("[2001:db8::2]:6697"),
+                addr("192.0.2.1:6697"),
+                addr("192.0.2.3:6697"),
+            ]
+        );
+        // Unless it's not resolved anymore
+        assert_eq!(
+            order_addrs(
+                addrs[..2].to_vec(),
+                IpPreference::Auto,
+                Some(addr("192.0.2.2:6697"))
+            ),
+            addrs[..2].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_max_privmsg_len() {
+        // Relayed messages are exactly 512 bytes with the known usermask
+        let max = max_privmsg_len("tiny", Some("tiny!~tiny@host.example"), "#chan".len());
+        let relayed = format!(
+            ":tiny!~tiny@host.example PRIVMSG #chan :{}\r\n",
+            "x".repeat(max)
+        );
+        assert_eq!(relayed.len(), 512);
+
+        // Usermask with an old nick
+        assert_eq!(
+            max_privmsg_len("tiny_", Some("tiny!~tiny@host.example"), 5),
+            max - 1
+        );
+
+        // Longest possible user@host without a usermask
+        assert_eq!(max_privmsg_len("tiny", None, 5), 512 - 2 - 13 - 4 - 73 - 5);
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let backoff = ReconnectBackoff {
+            base: Duration::from_secs(10),
+            multiplier: 3.0,
+            max: Duration::from_secs(100),
+        };
+        let secs = Duration::from_secs;
+        assert_eq!(backoff.delay(0, 0.0), secs(10));
+        assert_eq!(backoff.delay(1, 0.0), secs(30));
+        assert_eq!(backoff.delay(2, 0.0), secs(90));
+        assert_eq!(backoff.delay(3, 0.0), secs(100));
+        assert_eq!(backoff.delay(u32::MAX, 0.0), secs(100));
+        // Jitter shortens the delay by up to 25%
+        assert_eq!(backoff.delay(3, 1.0), secs(75));
+        assert_eq!(backoff.delay(0, 0.5), Duration::from_millis(8750));
+
+        let jitter = random_jitter();
+        assert!((0.0..=1.0).contains(&jitter));
+    }
+}
@@ -0,0 +1,299 @@
+//! Channel membership prefixes and mode changes, based on RPL_ISUPPORT `PREFIX` and `CHANMODES`
+//! parameters. Reference: <https://modern.ircdocs.horse/#rplisupport-parameters>
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Channel modes supported by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ChanModes {
+    /// Membership modes and their prefixes, highest rank first.
+    prefixes: Vec<(char, char)>,
+    /// Modes that add or remove an entry in a list, e.g. bans (type A in `CHANMODES`). Always
+    /// have a parameter.
+    list_modes: Vec<char>,
+    /// Modes that always have a parameter (type B in `CHANMODES`).
+    param_modes: Vec<char>,
+    /// Modes that have a parameter only when set (type C in `CHANMODES`).
+    set_param_modes: Vec<char>,
+}
+
+impl Default for ChanModes {
+    /// Used until the server sends RPL_ISUPPORT. Prefixes are the ones `wire::drop_nick_prefix`
+    /// drops.
+    fn default() -> Self {
+        ChanModes {
+            prefixes: vec![('q', '~'), ('a', '&'), ('o', '@'), ('h', '%'), ('v', '+')],
+            list_modes: vec!['b', 'e', 'I'],
+            param_modes: vec!['k'],
+            set_param_modes: vec!['l'],
+        }
+    }
+}
+
+/// A membership mode change in a MODE message.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct PrefixChange<'a> {
+    pub(crate) nick: &'a str,
+    pub(crate) prefix: char,
+    /// Whether the mode is set or unset.
+    pub(crate) set: bool,
+}
+
+/// A mode change in a MODE message.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ModeChange<'a> {
+    pub(crate) mode: char,
+    /// Whether the mode is set or unset.
+    pub(crate) set: bool,
+    pub(crate) arg: Option<&'a str>,
+}
+
+/// Modes set in a channel, with their parameters. Membership modes and list modes (e.g. bans)
+/// are not included.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ChanModeState(BTreeMap<char, Option<String>>);
+
+impl ChanModeState {
+    /// Apply the changes in a MODE message, or in RPL_CHANNELMODEIS. `modes` is the mode string
+    /// (e.g. `+kl-m`), `args` are the parameters after it.
+    pub(crate) fn apply(&mut self, chan_modes: &ChanModes, modes: &str, args: &[&str]) {
+        for change in chan_modes.changes(modes, args) {
+            if chan_modes.is_prefix_mode(change.mode)
+                || chan_modes.list_modes.contains(&change.mode)
+            {
+                continue;
+            }
+            if change.set {
+                self.0.insert(change.mode, change.arg.map(str::to_owned));
+            } else {
+                self.0.remove(&change.mode);
+            }
+        }
+    }
+
+    /// Key of the channel (mode `k`). Servers send it only to the users in the channel.
+    pub(crate) fn key(&self) -> Option<&str> {
+        self.0.get(&'k')?.as_deref()
+    }
+}
+
+/// Modes in the MODE syntax, e.g. `+klnt key 10`. Empty when no modes are set.
+impl fmt::Display for ChanModeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        write!(f, "+")?;
+        for mode in self.0.keys() {
+            write!(f, "{mode}")?;
+        }
+        for arg in self.0.values().flatten() {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ChanModes {
+    /// Update supported modes from an RPL_ISUPPORT parameter, e.g. `PREFIX=(ov)@+`. Other
+    /// parameters and invalid values are ignored.
+    pub(crate) fn update_isupport(&mut self, param: &str) {
+        if let Some(value) = param.strip_prefix("PREFIX=") {
+            if value.is_empty() {
+                self.prefixes.clear();
+            } else if let Some((modes, prefixes)) = value
+                .strip_prefix('(')
+                .and_then(|value| value.split_once(')'))
+                && modes.chars().count() == prefixes.chars().count()
+            {
+                self.prefixes = modes.chars().zip(prefixes.chars()).collect();
+            }
+        } else if let Some(value) = param.strip_prefix("CHANMODES=") {
+            let types: Vec<&str> = value.split(',').collect();
+            if types.len() >= 3 {
+                self.list_modes = types[0].chars().collect();
+                self.param_modes = types[1].chars().collect();
+                self.set_param_modes = types[2].chars().collect();
+            }
+        }
+    }
+
+    /// Split membership prefixes of a nick in RPL_NAMREPLY. With `multi-prefix` there may be more
+    /// than one prefix.
+    pub(crate) fn split_prefixes<'a>(&self, nick: &'a str) -> (&'a str, &'a str) {
+        let nick_start = nick.find(|c| self.rank(c).is_none()).unwrap_or(nick.len());
+        nick.split_at(nick_start)
+    }
+
+    /// Rank of a prefix. 0 is the highest rank.
+    pub(crate) fn rank(&self, prefix: char) -> Option<usize> {
+        self.prefixes
+            .iter()
+            .position(|(_, prefix_)| *prefix_ == prefix)
+    }
+
+    /// Whether the prefix is for channel operators, or a higher rank.
+    pub(crate) fn is_op(&self, prefix: char) -> bool {
+        let op_rank = self.prefixes.iter().position(|(mode, _)| *mode == 'o');
+        match (self.rank(prefix), op_rank) {
+            (Some(rank), Some(op_rank)) => rank <= op_rank,
+            _ => false,
+        }
+    }
+
+    /// Add a prefix to a set of prefixes, keeping them sorted by rank.
+    pub(crate) fn add_prefix(&self, prefixes: &mut String, prefix: char) {
+        if !prefixes.contains(prefix) {
+            prefixes.push(prefix);
+            let mut chars: Vec<char> = prefixes.chars().collect();
+            chars.sort_by_key(|c| self.rank(*c));
+            *prefixes = chars.into_iter().collect();
+        }
+    }
+
+    fn is_prefix_mode(&self, mode: char) -> bool {
+        self.prefixes.iter().any(|(mode_, _)| *mode_ == mode)
+    }
+
+    /// Membership changes in a channel MODE message. `modes` is the mode string (e.g. `+ov-b`),
+    /// `args` are the parameters after it.
+    pub(crate) fn prefix_changes<'a>(
+        &self,
+        modes: &str,
+        args: &[&'a str],
+    ) -> Vec<PrefixChange<'a>> {
+        self.changes(modes, args)
+            .into_iter()
+            .filter_map(|change| {
+                let (_, prefix) = self
+                    .prefixes
+                    .iter()
+                    .find(|(mode, _)| *mode == change.mode)?;
+                Some(PrefixChange {
+                    nick: change.arg?,
+                    prefix: *prefix,
+                    set: change.set,
+                })
+            })
+            .collect()
+    }
+
+    /// Changes in a channel MODE message, with the parameters of the modes that have one.
+    /// Modes not listed in RPL_ISUPPORT don't have a parameter.
+    pub(crate) fn changes<'a>(&self, modes: &str, args: &[&'a str]) -> Vec<ModeChange<'a>> {
+        let mut changes = vec![];
+        let mut args = args.iter();
+        let mut set = true;
+        for mode in modes.chars() {
+            match mode {
+                '+' => set = true,
+                '-' => set = false,
+                _ => {
+                    let has_arg = self.is_prefix_mode(mode)
+                        || self.list_modes.contains(&mode)
+                        || self.param_modes.contains(&mode)
+                        || (set && self.set_param_modes.contains(&mode));
+                    let arg = if has_arg { args.next().copied() } else { None };
+                    changes.push(ModeChange { mode, set, arg });
+                }
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isupport() {
+        let mut modes = ChanModes::default();
+        modes.update_isupport("PREFIX=(ov)@+");
+        modes.update_isupport("CHANMODES=eIbq,k,flj,CFLMPQScgimnprstz");
+        assert_eq!(modes.prefixes, vec![('o', '@'), ('v', '+')]);
+        assert_eq!(modes.list_modes, vec!['e', 'I', 'b', 'q']);
+        assert_eq!(modes.param_modes, vec!['k']);
+        assert_eq!(modes.set_param_modes, vec!['f', 'l', 'j']);
+
+        // Invalid values are ignored
+        modes.update_isupport("PREFIX=(ov)@");
+        modes.update_isupport("CHANMODES=b");
+        assert_eq!(modes.prefixes, vec![('o', '@'), ('v', '+')]);
+        assert_eq!(modes.list_modes, vec!['e', 'I', 'b', 'q']);
+
+        modes.update_isupport("PREFIX=");
+        assert!(modes.prefixes.is_empty());
+    }
+
+    #[test]
+    fn test_prefixes() {
+        let modes = ChanModes::default();
+        assert_eq!(modes.split_prefixes("@+nick"), ("@+", "nick"));
+        assert_eq!(modes.split_prefixes("nick"), ("", "nick"));
+        assert_eq!(modes.split_prefixes("~"), ("~", ""));
+
+        assert!(modes.is_op('~'));
+        assert!(modes.is_op('@'));
+        assert!(!modes.is_op('%'));
+        assert!(!modes.is_op('+'));
+        assert!(!modes.is_op('!'));
+
+        let mut prefixes = String::new();
+        modes.add_prefix(&mut prefixes, '+');
+        modes.add_prefix(&mut prefixes, '@');
+        modes.add_prefix(&mut prefixes, '+');
+        assert_eq!(prefixes, "@+");
+    }
+
+    #[test]
+    fn test_prefix_changes() {
+        let modes = ChanModes::default();
+        assert_eq!(
+            modes.prefix_changes("+ob-v+l", &["alice", "*!*@host", "bob", "10"]),
+            vec![
+                PrefixChange {
+                    nick: "alice",
+                    prefix: '@',
+                    set: true,
+                },
+                PrefixChange {
+                    nick: "bob",
+                    prefix: '+',
+                    set: false,
+                },
+            ]
+        );
+        // Type C modes don't have a parameter when unset
+        assert_eq!(
+            modes.prefix_changes("-l+v", &["alice"]),
+            vec![PrefixChange {
+                nick: "alice",
+                prefix: '+',
+                set: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mode_state() {
+        let mut modes = ChanModes::default();
+        modes.update_isupport("CHANMODES=beI,k,l,imnpst");
+        let mut state = ChanModeState::default();
+        assert_eq!(state.to_string(), "");
+
+        // RPL_CHANNELMODEIS
+        state.apply(&modes, "+ntk", &["secret"]);
+        assert_eq!(state.to_string(), "+knt secret");
+        assert_eq!(state.key(), Some("secret"));
+
+        // Membership and list modes are not included, parameters are skipped correctly
+        state.apply(&modes, "+ob-k+li", &["alice", "*!*@host", "secret", "10"]);
+        assert_eq!(state.to_string(), "+ilnt 10");
+        assert_eq!(state.key(), None);
+
+        state.apply(&modes, "-l+m-n", &[]);
+        assert_eq!(state.to_string(), "+imt");
+    }
+}
@@ -24,11 +24,26 @@ enum PingerState {
     ExpectPong,
 }
 
-async fn pinger_task(rcv_rst: mpsc::Receiver<()>, snd_ev: mpsc::Sender<Event>) {
+/// Default for `ServerInfo::ping_interval`.
+pub(crate) const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default for `ServerInfo::ping_timeout`.
+pub(crate) const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(60);
+
+async fn pinger_task(
+    rcv_rst: mpsc::Receiver<()>,
+    snd_ev: mpsc::Sender<Event>,
+    ping_interval: Duration,
+    ping_timeout: Duration,
+) {
     let mut rcv_rst_fused = ReceiverStream::new(rcv_rst).fuse();
     let mut state = PingerState::SendPing;
     loop {
-        match timeout(Duration::from_secs(60), rcv_rst_fused.next()).await {
+        let wait = match state {
+            PingerState::SendPing => ping_interval,
+            PingerState::ExpectPong => ping_timeout,
+        };
+        match timeout(wait, rcv_rst_fused.next()).await {
             Err(_) => match state {
                 PingerState::SendPing => {
                     state = PingerState::ExpectPong;
@@ -52,11 +67,16 @@ async fn pinger_task(rcv_rst: mpsc::Receiver<()>, snd_ev: mpsc::Sender<Event>) {
 }
 
 impl Pinger {
-    pub(crate) fn new() -> (Pinger, mpsc::Receiver<Event>) {
+    /// `ping_interval`: how long to wait for a reset before sending a ping. `ping_timeout`: how
+    /// long to wait for a reset after sending a ping before disconnecting.
+    pub(crate) fn new(
+        ping_interval: Duration,
+        ping_timeout: Duration,
+    ) -> (Pinger, mpsc::Receiver<Event>) {
         let (snd_ev, rcv_ev) = mpsc::channel(1);
         // No need for sending another "reset" when there's already one waiting to be processed
         let (snd_rst, rcv_rst) = mpsc::channel(1);
-        tokio::task::spawn_local(pinger_task(rcv_rst, snd_ev));
+        tokio::task::spawn_local(pinger_task(rcv_rst, snd_ev, ping_interval, ping_timeout));
         (Pinger { snd_rst }, rcv_ev)
     }
 
@@ -0,0 +1,383 @@
+//! SASL mechanisms and the AUTHENTICATE message encoding. See
+//! <https://ircv3.net/specs/extensions/sasl-3.1.html>.
+
+use crate::SASLAuth;
+use libtiny_wire as wire;
+
+use base64::engine::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ring::{digest, hmac, pbkdf2, rand};
+
+use std::num::NonZeroU32;
+
+/// AUTHENTICATE messages are split into chunks of this size after base64 encoding.
+const CHUNK_SIZE: usize = 400;
+
+/// A SASL mechanism in use in an authentication.
+#[derive(Debug)]
+pub(crate) enum Mechanism {
+    Plain { username: String, password: String },
+    External,
+    ScramSha256(Scram),
+}
+
+impl Mechanism {
+    pub(crate) fn new(auth: &SASLAuth) -> Mechanism {
+        match auth {
+            SASLAuth::Plain { username, password } => Mechanism::Plain {
+                username: username.clone(),
+                password: password.clone(),
+            },
+            SASLAuth::External { .. } => Mechanism::External,
+            SASLAuth::ScramSha256 {
+                username, password, ..
+            } => Mechanism::ScramSha256(Scram::new(username, password)),
+        }
+    }
+
+    /// Name of the mechanism, sent in the first AUTHENTICATE message.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Mechanism::Plain { .. } => "PLAIN",
+            Mechanism::External => "EXTERNAL",
+            Mechanism::ScramSha256(_) => "SCRAM-SHA-256",
+        }
+    }
+
+    /// When the server rejects the mechanism before the exchange starts, the mechanism to try next.
+    /// PLAIN is only tried after SCRAM-SHA-256 when `plain_fallback` is set in the config.
+    pub(crate) fn fallback(&self, auth: &SASLAuth) -> Option<Mechanism> {
+        match (self, auth) {
+            (
+                Mechanism::ScramSha256(scram),
+                SASLAuth::ScramSha256 {
+                    username,
+                    password,
+                    plain_fallback: true,
+                },
+            ) if scram.stage == ScramStage::Initial => Some(Mechanism::Plain {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the mechanism finished its part of the exchange, and RPL_SASLSUCCESS can be
+    /// accepted. SCRAM-SHA-256 needs the server signature to be verified, otherwise a server that
+    /// doesn't know the password could skip the server-final-message and report success.
+    pub(crate) fn is_done(&self) -> bool {
+        match self {
+            Mechanism::Plain { .. } | Mechanism::External => true,
+            Mechanism::ScramSha256(scram) => scram.stage == ScramStage::Done,
+        }
+    }
+
+    /// Response to a server challenge. An error means the authentication should be aborted.
+    pub(crate) fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Mechanism::Plain { username, password } => {
+                Ok(format!("{username}\x00{username}\x00{password}").into_bytes())
+            }
+            // Reply with an empty response (Empty responses are sent as "AUTHENTICATE +")
+            Mechanism::External => Ok(vec![]),
+            Mechanism::ScramSha256(scram) => scram.respond(challenge),
+        }
+    }
+}
+
+/// AUTHENTICATE messages for a response: base64 encoded and split into chunks. Empty responses and
+/// responses that are a multiple of the chunk size end with "AUTHENTICATE +".
+pub(crate) fn authenticate_msgs(response: &[u8]) -> Vec<String> {
+    let encoded = BASE64.encode(response);
+    let mut msgs: Vec<String> = encoded
+        .as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| wire::authenticate(std::str::from_utf8(chunk).unwrap()))
+        .collect();
+    if encoded.len().is_multiple_of(CHUNK_SIZE) {
+        msgs.push(wire::authenticate("+"));
+    }
+    msgs
+}
+
+/// Collects chunks of AUTHENTICATE messages from the server.
+#[derive(Debug, Default)]
+pub(crate) struct ChallengeBuf {
+    buf: String,
+}
+
+impl ChallengeBuf {
+    /// Add an AUTHENTICATE parameter. Returns the decoded challenge when it's complete.
+    pub(crate) fn add(&mut self, param: &str) -> Option<Result<Vec<u8>, String>> {
+        if param != "+" {
+            self.buf.push_str(param);
+        }
+        if param.len() == CHUNK_SIZE {
+            return None;
+        }
+        let challenge = BASE64
+            .decode(&self.buf)
+            .map_err(|err| format!("Invalid base64 in AUTHENTICATE: {err}"));
+        self.buf.clear();
+        Some(challenge)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+/// SCRAM-SHA-256 client, see RFC 5802 and RFC 7677.
+#[derive(Debug)]
+pub(crate) struct Scram {
+    username: String,
+    password: String,
+    client_nonce: String,
+    stage: ScramStage,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ScramStage {
+    /// Mechanism is requested, client-first-message is not sent yet
+    Initial,
+    /// client-first-message is sent, waiting for server-first-message
+    ClientFirst { client_first_bare: String },
+    /// client-final-message is sent, waiting for server-final-message
+    ClientFinal {
+        server_key: Vec<u8>,
+        auth_message: String,
+    },
+    /// Server signature verified
+    Done,
+    /// An invalid message was received from the server, authentication is aborted
+    Failed,
+}
+
+/// "n,,": no channel binding, no authorization identity
+const GS2_HEADER: &str = "n,,";
+
+impl Scram {
+    fn new(username: &str, password: &str) -> Scram {
+        let mut nonce = [0u8; 18];
+        rand::SecureRandom::fill(&rand::SystemRandom::new(), &mut nonce).unwrap();
+        Scram::with_nonce(username, password, BASE64.encode(nonce))
+    }
+
+    fn with_nonce(username: &str, password: &str, client_nonce: String) -> Scram {
+        Scram {
+            username: username.to_owned(),
+            password: password.to_owned(),
+            client_nonce,
+            stage: ScramStage::Initial,
+        }
+    }
+
+    fn respond(&mut self, challenge: &[u8]) -> Result<Vec<u8>, String> {
+        match std::mem::replace(&mut self.stage, ScramStage::Failed) {
+            ScramStage::Initial => {
+                let client_first_bare = format!(
+                    "n={},r={}",
+                    self.username.replace('=', "=3D").replace(',', "=2C"),
+                    self.client_nonce
+                );
+                let client_first = format!("{GS2_HEADER}{client_first_bare}");
+                self.stage = ScramStage::ClientFirst { client_first_bare };
+                Ok(client_first.into_bytes())
+            }
+
+            ScramStage::ClientFirst { client_first_bare } => {
+                let server_first = std::str::from_utf8(challenge)
+                    .map_err(|_| "Invalid UTF-8 in SCRAM server-first-message".to_owned())?;
+                let (nonce, salt, iterations) = parse_server_first(server_first)?;
+                if !nonce.starts_with(&self.client_nonce) || nonce == self.client_nonce {
+                    return Err("SCRAM server nonce does not extend the client nonce".to_owned());
+                }
+
+                let mut salted_password = [0u8; digest::SHA256_OUTPUT_LEN];
+                pbkdf2::derive(
+                    pbkdf2::PBKDF2_HMAC_SHA256,
+                    iterations,
+                    &salt,
+                    self.password.as_bytes(),
+                    &mut salted_password,
+                );
+                let salted_password = hmac::Key::new(hmac::HMAC_SHA256, &salted_password);
+
+                let client_key = hmac::sign(&salted_password, b"Client Key");
+                let stored_key = digest::digest(&digest::SHA256, client_key.as_ref());
+                let server_key = hmac::sign(&salted_password, b"Server Key");
+
+                let client_final_without_proof =
+                    format!("c={},r={}", BASE64.encode(GS2_HEADER), nonce);
+                let auth_message =
+                    format!("{client_first_bare},{server_first},{client_final_without_proof}");
+
+                let client_signature = hmac::sign(
+                    &hmac::Key::new(hmac::HMAC_SHA256, stored_key.as_ref()),
+                    auth_message.as_bytes(),
+                );
+                let client_proof: Vec<u8> = client_key
+                    .as_ref()
+                    .iter()
+                    .zip(client_signature.as_ref())
+                    .map(|(a, b)| a ^ b)
+                    .collect();
+
+                self.stage = ScramStage::ClientFinal {
+                    server_key: server_key.as_ref().to_vec(),
+                    auth_message,
+                };
+                Ok(format!(
+                    "{},p={}",
+                    client_final_without_proof,
+                    BASE64.encode(client_proof)
+                )
+                .into_bytes())
+            }
+
+            ScramStage::ClientFinal {
+                server_key,
+                auth_message,
+            } => {
+                let server_final = std::str::from_utf8(challenge)
+                    .map_err(|_| "Invalid UTF-8 in SCRAM server-final-message".to_owned())?;
+                if let Some(err) = server_final.strip_prefix("e=") {
+                    return Err(format!("SCRAM authentication error: {err}"));
+                }
+                let server_signature = server_final
+                    .split(',')
+                    .find_map(|attr| attr.strip_prefix("v="))
+                    .and_then(|sig| BASE64.decode(sig).ok())
+                    .ok_or_else(|| "Invalid SCRAM server-final-message".to_owned())?;
+                hmac::verify(
+                    &hmac::Key::new(hmac::HMAC_SHA256, &server_key),
+                    auth_message.as_bytes(),
+                    &server_signature,
+                )
+                .map_err(|_| "SCRAM server signature does not match".to_owned())?;
+                // Server verified, reply with an empty response
+                self.stage = ScramStage::Done;
+                Ok(vec![])
+            }
+
+            ScramStage::Done | ScramStage::Failed => {
+                Err("Unexpected SCRAM message after authentication".to_owned())
+            }
+        }
+    }
+}
+
+/// Max. PBKDF2 iteration count we accept from the server. Key derivation blocks the client while
+/// it runs, so a server can freeze the client with a huge count. Servers use a few thousand
+/// iterations.
+const MAX_SCRAM_ITERATIONS: u32 = 1_000_000;
+
+/// Parse server-first-message: nonce, salt, and iteration count.
+fn parse_server_first(msg: &str) -> Result<(&str, Vec<u8>, NonZeroU32), String> {
+    let mut nonce = None;
+    let mut salt = None;
+    let mut iterations = None;
+    for attr in msg.split(',') {
+        match attr.split_once('=') {
+            Some(("m", _)) => {
+                return Err("Unsupported SCRAM extension in server-first-message".to_owned());
+            }
+            Some(("r", value)) => nonce = Some(value),
+            Some(("s", value)) => salt = BASE64.decode(value).ok(),
+            Some(("i", value)) => iterations = value.parse::<NonZeroU32>().ok(),
+            _ => {}
+        }
+    }
+    match (nonce, salt, iterations) {
+        (Some(_), Some(_), Some(iterations)) if iterations.get() > MAX_SCRAM_ITERATIONS => {
+            Err(format!(
+                "SCRAM iteration count {iterations} is over the limit of {MAX_SCRAM_ITERATIONS}"
+            ))
+        }
+        (Some(nonce), Some(salt), Some(iterations)) => Ok((nonce, salt, iterations)),
+        _ => Err(format!("Invalid SCRAM server-first-message: {msg}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scram_sha_256() {
+        // Test vector from RFC 7677
+        let mut scram = Scram::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO".to_owned());
+        assert_eq!(
+            scram.respond(b"").unwrap(),
+            b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO"
+        );
+        assert_eq!(
+            scram
+                .respond(
+                    b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                      s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+                )
+                .unwrap(),
+            b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+              p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert_eq!(
+            scram
+                .respond(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+                .unwrap(),
+            b""
+        );
+        assert_eq!(scram.stage, ScramStage::Done);
+    }
+
+    #[test]
+    fn test_scram_bad_server_signature() {
+        let mut scram = Scram::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO".to_owned());
+        scram.respond(b"").unwrap();
+        scram
+            .respond(
+                b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                  s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+            )
+            .unwrap();
+        assert!(
+            scram
+                .respond(b"v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+                .is_err()
+        );
+        assert_eq!(scram.stage, ScramStage::Failed);
+    }
+
+    #[test]
+    fn test_scram_too_many_iterations() {
+        let mut scram = Scram::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO".to_owned());
+        scram.respond(b"").unwrap();
+        assert_eq!(
+            scram.respond(
+                b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                  s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4294967295"
+            ),
+            Err("SCRAM iteration count 4294967295 is over the limit of 1000000".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_authenticate_chunks() {
+        assert_eq!(authenticate_msgs(b""), vec!["AUTHENTICATE +\r\n"]);
+
+        // 300 bytes encode to 400 bytes of base64, which needs a "+" after
+        let msgs = authenticate_msgs(&[0; 300]);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[1], "AUTHENTICATE +\r\n");
+
+        let msgs = authenticate_msgs(&[0; 301]);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[1], "AUTHENTICATE AA==\r\n");
+
+        let mut buf = ChallengeBuf::default();
+        assert!(buf.add(&"A".repeat(400)).is_none());
+        assert_eq!(buf.add("+").unwrap().unwrap(), vec![0; 300]);
+        assert_eq!(buf.add("+").unwrap().unwrap(), b"");
+    }
+}
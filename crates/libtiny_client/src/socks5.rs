@@ -0,0 +1,114 @@
+//! SOCKS5 client, for connecting through a proxy like Tor. Only the CONNECT command without
+//! authentication is supported. Host names are sent to the proxy unresolved, so the proxy can
+//! connect to e.g. onion services. Reference: <https://www.rfc-editor.org/rfc/rfc1928>
+
+use std::io;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Ask the proxy connected with `stream` to connect to `host:port`. After this the stream is
+/// connected to `host:port`.
+pub(crate) async fn handshake<S>(mut stream: S, host: &str, port: u16) -> io::Result<S>
+where
+    S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    let host_len =
+        u8::try_from(host.len()).map_err(|_| proxy_error(format!("host name too long: {host}")))?;
+
+    stream.write_all(&[VERSION, 1, NO_AUTH]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [VERSION, NO_AUTH] {
+        return Err(proxy_error(
+            "proxy requires authentication, or is not a SOCKS5 proxy".to_owned(),
+        ));
+    }
+
+    let mut request = vec![VERSION, CMD_CONNECT, 0, ATYP_DOMAIN, host_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // VER, REP, RSV, ATYP, then the bound address and port, which we don't need
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(proxy_error("invalid reply".to_owned()));
+    }
+    if reply[1] != 0 {
+        return Err(proxy_error(reply_error(reply[1]).to_owned()));
+    }
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => usize::from(stream.read_u8().await?),
+        _ => return Err(proxy_error("invalid reply".to_owned())),
+    };
+    let mut bound_addr = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound_addr).await?;
+
+    Ok(stream)
+}
+
+fn proxy_error(msg: String) -> io::Error {
+    io::Error::other(format!("SOCKS5 proxy error: {msg}"))
+}
+
+fn reply_error(reply: u8) -> &'static str {
+    match reply {
+        1 => "general failure",
+        2 => "connection not allowed",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        run(async {
+            proxy
+                .write_all(&[5, 0, 5, 0, 0, 1, 127, 0, 0, 1, 0x1a, 0x0b])
+                .await
+                .unwrap();
+            handshake(&mut client, "abc.onion", 6697).await.unwrap();
+
+            let mut request = [0u8; 3 + 5 + 9 + 2];
+            proxy.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request[..3], &[5, 1, 0]);
+            assert_eq!(&request[3..8], &[5, 1, 0, 3, 9]);
+            assert_eq!(&request[8..17], b"abc.onion");
+            assert_eq!(&request[17..], &6697u16.to_be_bytes());
+        });
+
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        run(async {
+            proxy.write_all(&[5, 0, 5, 4, 0, 1]).await.unwrap();
+            let err = handshake(&mut client, "abc.onion", 6697).await.unwrap_err();
+            assert_eq!(err.to_string(), "SOCKS5 proxy error: host unreachable");
+        });
+    }
+}
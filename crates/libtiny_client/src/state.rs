@@ -1,16 +1,24 @@
 #![allow(clippy::get_first, clippy::zero_prefixed_literal)]
 
-use crate::{Cmd, Event, ServerInfo};
-use crate::{SASLAuth, utils};
-use libtiny_common::{ChanName, ChanNameRef};
+use crate::batch::Batches;
+use crate::isupport::ISupport;
+use crate::modes::{ChanModeState, ChanModes};
+use crate::sasl::{self, ChallengeBuf, Mechanism};
+use crate::sts::StsCap;
+use crate::{ChanTopic, ChanUser, Cmd, Event, FloodProtection, Invite, ServerInfo};
+use crate::{SASLFail, utils};
+use libtiny_common::{ChanName, ChanNameRef, TypingState};
 use libtiny_wire as wire;
-use libtiny_wire::{Msg, Pfx};
+use libtiny_wire::{BatchStart, Msg, Pfx};
 
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
 use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
 use tokio::time::{Duration, timeout};
 use tokio_stream::StreamExt;
 use tokio_stream::wrappers::ReceiverStream;
@@ -35,6 +43,31 @@ impl State {
         self.inner.borrow_mut().send_ping(snd_irc_msg)
     }
 
+    pub(crate) fn last_lag(&self) -> Option<Duration> {
+        self.inner.borrow().last_lag
+    }
+
+    pub(crate) fn poll_away(&self, max_users: usize, snd_irc_msg: &mut Sender<String>) {
+        self.inner.borrow_mut().poll_away(max_users, snd_irc_msg)
+    }
+
+    pub(crate) fn away_poll_delay(&self, interval: Duration) -> Duration {
+        self.inner.borrow().away_poll_delay(interval)
+    }
+
+    /// Take the away status changes learned from WHO replies since the last call.
+    pub(crate) fn take_away_changes(&self) -> Vec<(String, bool)> {
+        std::mem::take(&mut self.inner.borrow_mut().away_changes)
+    }
+
+    pub(crate) fn flood_protection(&self) -> FloodProtection {
+        self.inner.borrow().flood_protection
+    }
+
+    pub(crate) fn set_flood_protection(&self, flood_protection: FloodProtection) {
+        self.inner.borrow_mut().flood_protection = flood_protection;
+    }
+
     pub(crate) fn update(
         &self,
         msg: &mut Msg,
@@ -53,6 +86,10 @@ impl State {
         self.inner.borrow().current_nick.clone()
     }
 
+    pub(crate) fn get_nickserv(&self) -> String {
+        self.inner.borrow().nickserv.clone()
+    }
+
     // FIXME: Maybe use RwLock instead of Mutex
     pub(crate) fn is_nick_accepted(&self) -> bool {
         self.inner.borrow().nick_accepted
@@ -70,6 +107,34 @@ impl State {
         self.inner.borrow().get_chan_nicks(chan)
     }
 
+    pub(crate) fn get_chan_users(&self, chan: &ChanNameRef) -> Vec<ChanUser> {
+        self.inner.borrow().get_chan_users(chan)
+    }
+
+    pub(crate) fn get_topic(&self, chan: &ChanNameRef) -> Option<ChanTopic> {
+        self.inner.borrow().get_chan(chan)?.topic.clone()
+    }
+
+    pub(crate) fn get_chan_creation_time(&self, chan: &ChanNameRef) -> Option<i64> {
+        self.inner.borrow().get_chan(chan)?.created
+    }
+
+    pub(crate) fn get_chan_modes(&self, chan: &ChanNameRef) -> Option<String> {
+        let inner = self.inner.borrow();
+        Some(inner.get_chan(chan)?.modes.as_ref()?.to_string())
+    }
+
+    pub(crate) fn refresh_chan_modes(&self, chan: &ChanNameRef) -> Option<String> {
+        let mut inner = self.inner.borrow_mut();
+        let chan = inner.get_chan_mut(chan)?;
+        chan.modes_pending = true;
+        Some(wire::chan_modes(chan.name.display()))
+    }
+
+    pub(crate) fn get_joined_chans(&self) -> Vec<ChanName> {
+        self.inner.borrow().get_joined_chans()
+    }
+
     pub(crate) fn leave_channel(
         &self,
         msg_chan: &mut Sender<Cmd>,
@@ -81,9 +146,135 @@ impl State {
             .leave_channel(msg_chan, chan, reason)
     }
 
+    pub(crate) fn rejoin(&self, chan: &ChanNameRef) -> Option<(Option<String>, Receiver<()>)> {
+        self.inner.borrow_mut().rejoin(chan)
+    }
+
+    /// Key of a channel we left or were kicked from, when known.
+    pub(crate) fn take_chan_key(&self, chan: &ChanNameRef) -> Option<String> {
+        self.inner.borrow_mut().left_chan_keys.remove(chan)
+    }
+
+    pub(crate) fn get_last_parted(&self) -> Option<(ChanName, Option<String>)> {
+        self.inner.borrow().last_parted.clone()
+    }
+
+    pub(crate) fn get_invites(&self) -> Vec<Invite> {
+        let mut inner = self.inner.borrow_mut();
+        inner
+            .invites
+            .retain(|(_, time)| time.elapsed() < INVITE_TIMEOUT);
+        inner
+            .invites
+            .iter()
+            .map(|(invite, _)| invite.clone())
+            .collect()
+    }
+
     pub(crate) fn kill_join_tasks(&self) {
         self.inner.borrow_mut().kill_join_tasks();
     }
+
+    pub(crate) fn add_ping(&self, nick: Option<&str>) -> String {
+        self.inner.borrow_mut().add_ping(nick)
+    }
+
+    pub(crate) fn typing(&self, target: &str, state: TypingState, now: Instant) -> Option<String> {
+        self.inner.borrow_mut().typing(target, state, now)
+    }
+
+    pub(crate) fn reset_typing(&self, target: &str) {
+        self.inner.borrow_mut().reset_typing(target)
+    }
+
+    pub(crate) fn get_isupport(&self) -> ISupport {
+        self.inner.borrow().isupport.clone()
+    }
+
+    pub(crate) fn get_server_caps(&self) -> Vec<String> {
+        self.inner.borrow().server_caps.clone()
+    }
+
+    pub(crate) fn get_enabled_caps(&self) -> Vec<String> {
+        self.inner.borrow().enabled_caps.clone()
+    }
+
+    pub(crate) fn get_addr(&self) -> Option<SocketAddr> {
+        self.inner.borrow().addr
+    }
+
+    pub(crate) fn set_addr(&self, addr: Option<SocketAddr>) {
+        self.inner.borrow_mut().addr = addr;
+    }
+
+    pub(crate) fn is_sasl_failed(&self) -> bool {
+        self.inner.borrow().sasl_failed
+    }
+
+    /// Take the STS policy advertised by the server, if it advertised one since the last call.
+    pub(crate) fn take_sts(&self) -> Option<StsCap> {
+        self.inner.borrow_mut().sts.take()
+    }
+
+    pub(crate) fn is_cap_enabled(&self, cap: &str) -> bool {
+        self.inner.borrow().is_cap_enabled(cap)
+    }
+
+    pub(crate) fn get_pinned_cert(&self) -> Option<String> {
+        self.inner.borrow().server_info.pinned_cert.clone()
+    }
+
+    pub(crate) fn set_pinned_cert(&self, fingerprint: &str) {
+        self.inner.borrow_mut().server_info.pinned_cert = Some(fingerprint.to_owned());
+    }
+
+    pub(crate) fn add_pending_msg(&self, target: &str, msg: &str) -> Option<String> {
+        self.inner.borrow_mut().add_pending_msg(target, msg)
+    }
+
+    pub(crate) fn match_echo(&self, msg: &Msg) -> Option<Event> {
+        self.inner.borrow_mut().match_echo(msg)
+    }
+
+    pub(crate) fn is_nick_away(&self, nick: &str) -> bool {
+        self.inner.borrow().is_nick_away(nick)
+    }
+
+    pub(crate) fn get_account(&self, nick: &str) -> Option<String> {
+        self.inner.borrow().get_account(nick)
+    }
+
+    pub(crate) fn get_user_host(&self, nick: &str) -> Option<String> {
+        self.inner.borrow().get_user_host(nick)
+    }
+
+    pub(crate) fn get_realname(&self, nick: &str) -> Option<String> {
+        self.inner.borrow().get_realname(nick)
+    }
+
+    pub(crate) fn is_auto_reply(&self, msg: &Msg) -> bool {
+        self.inner.borrow().is_auto_reply(msg)
+    }
+
+    pub(crate) fn add_history_msg(&self, msg: Msg) -> Option<Msg> {
+        self.inner.borrow_mut().add_history_msg(msg)
+    }
+
+    pub(crate) fn add_labeled_request(&self) -> Option<(String, oneshot::Receiver<Vec<Msg>>)> {
+        self.inner.borrow_mut().add_labeled_request()
+    }
+
+    pub(crate) fn add_labeled_reply(&self, msg: Msg) -> Option<Msg> {
+        self.inner.borrow_mut().add_labeled_reply(msg)
+    }
+
+    pub(crate) fn history_request(&self, target: &str, limit: usize) -> String {
+        self.inner.borrow_mut().history_request(target, limit)
+    }
+
+    pub(crate) fn refresh_names(&self, chan: &ChanNameRef) -> Vec<String> {
+        self.inner.borrow_mut().refresh_names(chan)
+    }
 }
 
 struct StateInner {
@@ -93,6 +284,9 @@ struct StateInner {
     /// NickServ password
     nickserv_ident: Option<String>,
 
+    /// Nick of the NickServ service
+    nickserv: String,
+
     /// An index to `nicks`. When out of range we add `current_nick_idx - nicks.length()`
     /// underscores to the last nick in `nicks`
     current_nick_idx: usize,
@@ -114,6 +308,12 @@ struct StateInner {
     /// Away reason if away mode is on. `None` otherwise.
     away_status: Option<String>,
 
+    /// Features of the server, from RPL_ISUPPORT.
+    isupport: ISupport,
+
+    /// Address of the current connection. See `Client::get_addr`.
+    addr: Option<SocketAddr>,
+
     /// servername to be used in PING messages. Read from 002 RPL_YOURHOST. `None` until 002.
     servername: Option<String>,
 
@@ -126,16 +326,165 @@ struct StateInner {
     /// Do we have a nick yet? Try another nick on ERR_NICKNAMEINUSE (433) until we've got a nick.
     nick_accepted: bool,
 
+    /// Pings sent with `Client::ping` that are not replied yet, keyed by their tokens. Values are
+    /// the pinged nicks (`None` for server pings) and the times the pings were sent.
+    pings: HashMap<String, (Option<String>, Instant)>,
+
+    /// Used to generate unique ping tokens.
+    next_ping_id: u64,
+
+    /// Token of the last ping sent to check the connection, and the time it was sent. `None`
+    /// when replied.
+    lag_ping: Option<(String, Instant)>,
+
+    /// Round-trip time of the last ping to the server that was replied.
+    last_lag: Option<Duration>,
+
+    /// Current flood protection settings, initialized from `ServerInfo::flood_protection`.
+    flood_protection: FloodProtection,
+
+    /// Away status changes learned from WHO replies, not yet reported by the main loop.
+    away_changes: Vec<(String, bool)>,
+
+    /// IRCv3 `+typing` notifications we sent, by target (lowercase): the last state and the time
+    /// it was sent.
+    typing_sent: HashMap<String, (TypingState, Instant)>,
+
+    /// IRCv3 capabilities advertised by the server in CAP LS.
+    server_caps: Vec<String>,
+
+    /// IRCv3 capabilities enabled by the server with CAP ACK.
+    enabled_caps: Vec<String>,
+
+    /// IRCv3 STS policy advertised in CAP LS, not yet handled by the main loop.
+    sts: Option<StsCap>,
+
+    /// Did SASL authentication fail in this connection? (ERR_SASLFAIL, ERR_SASLTOOLONG, or
+    /// ERR_SASLABORTED)
+    sasl_failed: bool,
+
+    /// SASL mechanism of the authentication in progress.
+    sasl_mechanism: Option<Mechanism>,
+
+    /// Chunks of the current SASL challenge from the server.
+    sasl_challenge: ChallengeBuf,
+
+    /// PRIVMSGs waiting to be echoed back by the server, in the order they're sent. Only used
+    /// with `echo-message`.
+    pending_msgs: VecDeque<PendingMsg>,
+
+    /// Used to generate unique `label` tags for `pending_msgs` and `labeled_requests`.
+    next_label_id: u64,
+
+    /// Messages sent with `Client::labeled_raw_msg` waiting for their replies, by their `label`
+    /// tags.
+    labeled_requests: HashMap<String, LabeledRequest>,
+
+    /// IRCv3 `draft/chathistory` requests waiting for their batches: targets and whether the
+    /// request is for older messages (`Client::history`).
+    history_requests: Vec<(String, bool)>,
+
+    /// Open IRCv3 batches.
+    batches: Batches,
+
+    /// `chathistory` batches being received, by their reference tags.
+    history_batches: HashMap<String, HistoryBatch>,
+
+    /// Targets (lowercase) that we fetched history for, with `server-time` of the oldest fetched
+    /// message. History is fetched automatically only on the first join to a channel, to avoid
+    /// showing the same messages again after reconnecting.
+    history_oldest: HashMap<String, Option<String>>,
+
+    /// Keys of the channels we left or were kicked from, when known. Used when rejoining the
+    /// channels with `Client::rejoin_after` and `Client::rejoin`.
+    left_chan_keys: HashMap<ChanName, String>,
+
+    /// The channel we left last with a PART, and the reason we sent. See `Client::last_parted`.
+    last_parted: Option<(ChanName, Option<String>)>,
+
+    /// Invitations to channels we haven't joined yet, with the time we got them. Expire after
+    /// `INVITE_TIMEOUT`. See `Client::invites`.
+    invites: Vec<(Invite, Instant)>,
+
     /// Server information
     server_info: ServerInfo,
 }
 
+/// A PRIVMSG sent with `echo-message` enabled.
+#[derive(Debug)]
+struct PendingMsg {
+    /// `label` tag of the message. Only set with `labeled-response`.
+    label: Option<String>,
+    target: String,
+    msg: String,
+}
+
+/// A message sent with a `label` tag, with IRCv3 `labeled-response`.
+#[derive(Debug)]
+struct LabeledRequest {
+    snd_reply: oneshot::Sender<Vec<Msg>>,
+    /// Reference of the `labeled-response` batch of the reply, once the batch starts.
+    batch: Option<String>,
+    /// Messages in the batch.
+    msgs: Vec<Msg>,
+}
+
+/// Messages in an IRCv3 `chathistory` batch.
+#[derive(Debug)]
+struct HistoryBatch {
+    target: String,
+    msgs: Vec<Msg>,
+    older: bool,
+}
+
+/// Minimum time between `+typing=active` notifications to a target, as recommended by the spec.
+const TYPING_ACTIVE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Max. number of PRIVMSGs to remember while waiting for the echoes. Messages that the server
+/// doesn't echo back (e.g. because of an error) are dropped from `pending_msgs` when a later
+/// message is echoed, this is to avoid growing the queue indefinitely when the server doesn't
+/// echo them at all.
+const MAX_PENDING_MSGS: usize = 100;
+
 #[derive(Debug)]
 struct Chan {
     /// Name of the channel
     name: ChanName,
     /// Set of nicknames in channel
     nicks: HashSet<String>,
+    /// Nicks in the channel that are marked as away. Maintained when IRCv3 `away-notify` is
+    /// enabled, populated with a WHO after joining the channel. Otherwise updated with WHO polls
+    /// when `ServerInfo::away_poll` is set.
+    away_nicks: HashSet<String>,
+    /// Services accounts of the nicks in the channel that are logged in. Maintained with IRCv3
+    /// `extended-join`, `account-notify` and `account-tag`, when enabled.
+    accounts: HashMap<String, String>,
+    /// Membership prefixes (e.g. `@` for operators) of the nicks in the channel that have one,
+    /// highest rank first.
+    prefixes: HashMap<String, String>,
+    /// `user@host` of the nicks in the channel, when known. Learned from JOINs, WHO replies and
+    /// NAMES replies with IRCv3 `userhost-in-names`, kept up to date with IRCv3 `chghost`.
+    user_hosts: HashMap<String, String>,
+    /// Real names of the nicks in the channel, when known. Learned from WHO replies, kept up to
+    /// date with IRCv3 `setname`.
+    realnames: HashMap<String, String>,
+    /// Whether we sent a WHO for the channel to populate `away_nicks` and `user_hosts` and are
+    /// waiting for the replies. These replies are not reported to the user.
+    who_pending: bool,
+    /// When we last sent a WHO to poll the away status of the users in the channel.
+    last_away_poll: Option<Instant>,
+    /// Topic of the channel, when known. Learned from RPL_TOPIC and RPL_TOPICWHOTIME after
+    /// joining, kept up to date with TOPIC messages.
+    topic: Option<ChanTopic>,
+    /// Creation time of the channel in seconds since Unix epoch, from RPL_CREATIONTIME.
+    created: Option<i64>,
+    /// Modes of the channel. Learned from RPL_CHANNELMODEIS after joining, kept up to date with
+    /// MODE messages. `None` until RPL_CHANNELMODEIS.
+    modes: Option<ChanModeState>,
+    /// Whether we sent a MODE query for the channel (after joining, or with
+    /// `Client::refresh_chan_modes`) and are waiting for the replies. These replies are not
+    /// reported to the user.
+    modes_pending: bool,
     /// Channel joined state
     join_state: JoinState,
     /// Join attempts
@@ -164,31 +513,154 @@ enum JoinState {
 
 const MAX_JOIN_RETRIES: u8 = 3;
 
+/// Nick of the NickServ service when `ServerInfo::nickserv` is not set.
+const DEFAULT_NICKSERV: &str = "NickServ";
+
+/// WHOX token of the WHO queries sent to poll away status, to recognize the replies.
+const AWAY_POLL_WHOX_TOKEN: &str = "176";
+
+/// Away status polling pauses when a ping to the server is not replied in this long, or the last
+/// reply took longer.
+const MAX_AWAY_POLL_LAG: Duration = Duration::from_secs(10);
+
+/// Name of a capability in CAP LS and CAP NEW, without the value (`sasl` in `sasl=PLAIN,EXTERNAL`).
+fn cap_name(cap: &str) -> &str {
+    cap.split_once('=').map_or(cap, |(name, _)| name)
+}
+
+/// How long invitations to channels are kept, see `Client::invites`.
+const INVITE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// IRCv3 capabilities we request when the server advertises them, in CAP LS or CAP NEW. `sasl` is
+/// requested separately, only in CAP LS and when SASL authentication is configured.
+/// `znc.in/self-message` makes ZNC relay the messages sent by the other clients of the bouncer.
+const SUPPORTED_CAPS: [&str; 16] = [
+    "server-time",
+    "echo-message",
+    "labeled-response",
+    "away-notify",
+    "account-notify",
+    "account-tag",
+    "extended-join",
+    "chghost",
+    "multi-prefix",
+    "userhost-in-names",
+    "batch",
+    "draft/chathistory",
+    "message-tags",
+    "setname",
+    "cap-notify",
+    "znc.in/self-message",
+];
+
 impl Chan {
     fn new(name: ChanName) -> Chan {
         Chan {
             name,
             nicks: HashSet::new(),
-            join_state: JoinState::NotJoined,
-            join_attempts: MAX_JOIN_RETRIES,
-        }
-    }
-
-    fn with_nicks(name: ChanName, nicks: HashSet<String>) -> Chan {
-        Chan {
-            name,
-            nicks,
+            away_nicks: HashSet::new(),
+            accounts: HashMap::new(),
+            prefixes: HashMap::new(),
+            user_hosts: HashMap::new(),
+            realnames: HashMap::new(),
+            who_pending: false,
+            last_away_poll: None,
+            topic: None,
+            created: None,
+            modes: None,
+            modes_pending: false,
             join_state: JoinState::NotJoined,
             join_attempts: MAX_JOIN_RETRIES,
         }
     }
 
     fn reset(&mut self) {
-        self.nicks.clear();
+        self.clear_nicks();
+        self.who_pending = false;
+        self.last_away_poll = None;
+        self.modes = None;
+        self.modes_pending = false;
         self.join_state = JoinState::NotJoined;
         self.join_attempts = MAX_JOIN_RETRIES;
     }
 
+    fn clear_nicks(&mut self) {
+        self.nicks.clear();
+        self.away_nicks.clear();
+        self.accounts.clear();
+        self.prefixes.clear();
+        self.user_hosts.clear();
+        self.realnames.clear();
+    }
+
+    /// Remove a nick and its state. Returns whether the nick was in the channel.
+    fn remove_nick(&mut self, nick: &str) -> bool {
+        self.away_nicks.remove(nick);
+        self.accounts.remove(nick);
+        self.prefixes.remove(nick);
+        self.user_hosts.remove(nick);
+        self.realnames.remove(nick);
+        self.nicks.remove(nick)
+    }
+
+    /// Rename a nick, keeping its state. Returns whether the nick was in the channel.
+    fn rename_nick(&mut self, old_nick: &str, new_nick: &str) -> bool {
+        if !self.nicks.remove(old_nick) {
+            return false;
+        }
+        self.nicks.insert(new_nick.to_owned());
+        if self.away_nicks.remove(old_nick) {
+            self.away_nicks.insert(new_nick.to_owned());
+        }
+        if let Some(account) = self.accounts.remove(old_nick) {
+            self.accounts.insert(new_nick.to_owned(), account);
+        }
+        if let Some(prefixes) = self.prefixes.remove(old_nick) {
+            self.prefixes.insert(new_nick.to_owned(), prefixes);
+        }
+        if let Some(user_host) = self.user_hosts.remove(old_nick) {
+            self.user_hosts.insert(new_nick.to_owned(), user_host);
+        }
+        if let Some(realname) = self.realnames.remove(old_nick) {
+            self.realnames.insert(new_nick.to_owned(), realname);
+        }
+        true
+    }
+
+    /// Add a nick from RPL_NAMREPLY, with its prefixes. With IRCv3 `userhost-in-names` the nick is
+    /// followed by `!user@host`.
+    fn add_name(&mut self, name: &str, modes: &ChanModes) {
+        let (prefixes, nick) = modes.split_prefixes(name);
+        let nick = match nick.split_once('!') {
+            Some((nick, user_host)) => {
+                self.user_hosts
+                    .insert(nick.to_owned(), user_host.to_owned());
+                nick
+            }
+            None => nick,
+        };
+        if !prefixes.is_empty() {
+            let mut prefixes_ = String::new();
+            for prefix in prefixes.chars() {
+                modes.add_prefix(&mut prefixes_, prefix);
+            }
+            self.prefixes.insert(nick.to_owned(), prefixes_);
+        }
+        self.nicks.insert(nick.to_owned());
+    }
+
+    /// Set or clear (when `account` is `None`) services account of a nick.
+    fn set_account(&mut self, nick: &str, account: Option<&str>) {
+        match account {
+            Some(account) => {
+                self.accounts.insert(nick.to_owned(), account.to_owned());
+            }
+            None => {
+                self.accounts.remove(nick);
+            }
+        }
+    }
+
     fn set_joining(&mut self, stop_task: Sender<()>) {
         self.join_state = JoinState::Joining { stop_task }
     }
@@ -217,148 +689,745 @@ impl StateInner {
         StateInner {
             nicks: server_info.nicks.clone(),
             nickserv_ident: server_info.nickserv_ident.clone(),
+            nickserv: server_info
+                .nickserv
+                .clone()
+                .unwrap_or_else(|| DEFAULT_NICKSERV.to_owned()),
             current_nick_idx: 0,
             current_nick,
             chans,
             away_status: None,
+            isupport: ISupport::default(),
+            addr: None,
             servername: None,
             usermask: None,
             nick_accepted: false,
+            pings: HashMap::new(),
+            next_ping_id: 0,
+            lag_ping: None,
+            last_lag: None,
+            flood_protection: server_info.flood_protection,
+            away_changes: vec![],
+            typing_sent: HashMap::new(),
+            server_caps: vec![],
+            enabled_caps: vec![],
+            sts: None,
+            sasl_failed: false,
+            sasl_mechanism: None,
+            sasl_challenge: ChallengeBuf::default(),
+            pending_msgs: VecDeque::new(),
+            next_label_id: 0,
+            labeled_requests: HashMap::new(),
+            history_requests: vec![],
+            batches: Batches::default(),
+            history_batches: HashMap::new(),
+            history_oldest: HashMap::new(),
+            left_chan_keys: HashMap::new(),
+            last_parted: None,
+            invites: vec![],
             server_info,
         }
     }
 
+    /// Whether `nick` is our current nick, according to the server's case mapping.
+    fn is_current_nick(&self, nick: &str) -> bool {
+        self.isupport.nick_eq(nick, &self.current_nick)
+    }
+
     fn reset(&mut self) {
         self.nick_accepted = false;
         self.nicks.clone_from(&self.server_info.nicks);
         self.current_nick_idx = 0;
         self.current_nick.clone_from(&self.nicks[0]);
-        // Only reset the values here; the key set will be used to join channels
-        for chan in &mut self.chans {
-            chan.reset();
+        if self.server_info.rejoin_on_reconnect {
+            // Only reset the values here; the key set will be used to join channels
+            for chan in &mut self.chans {
+                chan.reset();
+            }
+        } else {
+            self.chans = self
+                .server_info
+                .auto_join
+                .iter()
+                .map(|s| Chan::new(s.to_owned()))
+                .collect();
         }
         self.servername = None;
         self.usermask = None;
+        // RPL_ISUPPORT is sent again on reconnect
+        self.isupport = ISupport::default();
+        // Replies to pings sent in the old connection won't arrive
+        self.pings.clear();
+        self.lag_ping = None;
+        self.last_lag = None;
+        self.away_changes.clear();
+        self.typing_sent.clear();
+        // Capabilities are negotiated again on reconnect
+        self.server_caps.clear();
+        self.enabled_caps.clear();
+        self.sts = None;
+        self.sasl_failed = false;
+        self.sasl_mechanism = None;
+        self.sasl_challenge.clear();
+        // Messages sent in the old connection won't be echoed or replied. Dropping the reply
+        // senders tells the waiting receivers.
+        self.pending_msgs.clear();
+        self.labeled_requests.clear();
+        self.history_requests.clear();
+        self.batches.clear();
+        self.history_batches.clear();
+        self.invites.clear();
     }
 
-    fn send_ping(&mut self, snd_irc_msg: &mut Sender<String>) {
-        if let Some(ref servername) = self.servername {
-            snd_irc_msg.try_send(wire::ping(servername)).unwrap();
-        }
+    fn is_cap_enabled(&self, cap: &str) -> bool {
+        self.enabled_caps.iter().any(|cap_| cap_ == cap)
     }
 
-    fn introduce(&mut self, snd_irc_msg: &mut Sender<String>) {
-        if let Some(ref pass) = self.server_info.pass {
-            snd_irc_msg.try_send(wire::pass(pass)).unwrap();
-        }
-        snd_irc_msg
-            .try_send(wire::nick(&self.current_nick))
-            .unwrap();
-        let user = self.server_info.user.as_ref().unwrap_or(&self.nicks[0]);
-        snd_irc_msg
-            .try_send(wire::user(user, &self.server_info.realname))
-            .unwrap();
+    /// Whether the server advertised the capability in CAP LS or CAP NEW.
+    fn is_cap_advertised(&self, cap: &str) -> bool {
+        self.server_caps.iter().any(|cap_| cap_name(cap_) == cap)
     }
 
-    fn get_next_nick(&mut self) -> &str {
-        self.current_nick_idx += 1;
-        // debug!("current_nick_idx: {}", self.current_nick_idx);
-        if self.current_nick_idx >= self.nicks.len() {
-            let n_underscores = self.current_nick_idx - self.nicks.len() + 1;
-            let mut new_nick = self.nicks.last().unwrap().to_string();
-            for _ in 0..n_underscores {
-                new_nick.push('_');
+    fn add_server_caps(&mut self, caps: &[String]) {
+        for cap in caps.iter().filter(|cap| !cap.is_empty()) {
+            if !self.server_caps.contains(cap) {
+                self.server_caps.push(cap.clone());
             }
-            self.current_nick = new_nick;
+        }
+    }
+
+    /// Remember a PRIVMSG to match it with its echo, when `echo-message` is enabled. Returns the
+    /// `label` tag to add to the message when `labeled-response` is enabled.
+    fn add_pending_msg(&mut self, target: &str, msg: &str) -> Option<String> {
+        if !self.is_cap_enabled("echo-message") {
+            return None;
+        }
+        let label = if self.is_cap_enabled("labeled-response") {
+            Some(self.new_label())
         } else {
-            self.current_nick
-                .clone_from(&self.nicks[self.current_nick_idx]);
+            None
+        };
+        if self.pending_msgs.len() == MAX_PENDING_MSGS {
+            self.pending_msgs.pop_front();
         }
-        &self.current_nick
+        self.pending_msgs.push_back(PendingMsg {
+            label: label.clone(),
+            target: target.to_owned(),
+            msg: msg.to_owned(),
+        });
+        label
     }
 
-    fn update(
-        &mut self,
-        msg: &mut Msg,
-        snd_ev: &mut Sender<Event>,
-        snd_irc_msg: &mut Sender<String>,
-    ) {
-        let Msg { pfx, cmd } = msg;
+    fn new_label(&mut self) -> String {
+        let label = format!("tiny{}", self.next_label_id);
+        self.next_label_id += 1;
+        label
+    }
 
-        use wire::Cmd::*;
-        match cmd {
-            // PING: Send PONG
-            PING { server } => {
-                snd_irc_msg.try_send(wire::pong(server)).unwrap();
+    /// Start a request for a labeled reply. Returns the `label` tag to add to the message, and
+    /// the receiver for the reply. `None` when `labeled-response` is not enabled.
+    fn add_labeled_request(&mut self) -> Option<(String, oneshot::Receiver<Vec<Msg>>)> {
+        if !self.is_cap_enabled("labeled-response") {
+            return None;
+        }
+        let label = self.new_label();
+        let (snd_reply, rcv_reply) = oneshot::channel();
+        self.labeled_requests.insert(
+            label.clone(),
+            LabeledRequest {
+                snd_reply,
+                batch: None,
+                msgs: vec![],
+            },
+        );
+        Some((label, rcv_reply))
+    }
+
+    /// Add the message to the reply of a labeled request if it's a part of one: a single reply
+    /// message, an `ACK` (no reply), or a message in a `labeled-response` batch. Returns the
+    /// message back if it's not. `BATCH` messages are returned, they're needed to track batches.
+    fn add_labeled_reply(&mut self, msg: Msg) -> Option<Msg> {
+        if self.labeled_requests.is_empty() {
+            return Some(msg);
+        }
+
+        if let Some(label) = msg.tags.get("label")
+            && self.labeled_requests.contains_key(label)
+        {
+            if let wire::Cmd::BATCH {
+                reference,
+                start: Some(BatchStart { batch_type, .. }),
+            } = &msg.cmd
+                && batch_type == "labeled-response"
+            {
+                self.labeled_requests.get_mut(label).unwrap().batch = Some(reference.clone());
+                return Some(msg);
             }
+            let request = self.labeled_requests.remove(label).unwrap();
+            let msgs = match &msg.cmd {
+                wire::Cmd::Other { cmd, .. } if cmd == "ACK" => vec![],
+                _ => vec![msg],
+            };
+            // Receiver may be dropped if the caller is not interested in the reply
+            let _ = request.snd_reply.send(msgs);
+            return None;
+        }
 
-            // JOIN: If this is us then update usermask if possible, create the channel state. If
-            // someone else add the nick to channel.
-            JOIN { chan } => {
-                match pfx {
-                    Some(Pfx::User { nick, user }) if nick == &self.current_nick => {
-                        // Set usermask
-                        let usermask = format!("{nick}!{user}");
-                        self.usermask = Some(usermask);
-                    }
-                    _ => {}
-                }
+        // End of a `labeled-response` batch
+        if let wire::Cmd::BATCH {
+            reference,
+            start: None,
+        } = &msg.cmd
+        {
+            let label = self
+                .labeled_requests
+                .iter()
+                .find(|(_, request)| request.batch.as_ref() == Some(reference))
+                .map(|(label, _)| label.clone());
+            if let Some(label) = label {
+                let request = self.labeled_requests.remove(&label).unwrap();
+                let _ = request.snd_reply.send(request.msgs);
+            }
+            return Some(msg);
+        }
+        if matches!(msg.cmd, wire::Cmd::BATCH { .. }) {
+            return Some(msg);
+        }
 
-                match pfx {
-                    Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => {
-                        if nick == &self.current_nick {
-                            // We joined a channel, initialize channel state
-                            match utils::find_idx(&self.chans, |c| &c.name == chan) {
-                                None => {
-                                    let mut chan = Chan::new(chan.to_owned());
-                                    // Since nick was found in the prefix, we are in the channel
-                                    chan.join_state = JoinState::Joined;
-                                    self.chans.push(chan);
-                                }
-                                Some(chan_idx) => {
-                                    // This happens because we initialize channel states for channels
-                                    // that we will join on connection when the client is first created
-                                    let chan = &mut self.chans[chan_idx];
-                                    chan.join_state = JoinState::Joined;
-                                    chan.nicks.clear();
-                                }
-                            }
-                        } else {
-                            match utils::find_idx(&self.chans, |c| &c.name == chan) {
-                                Some(chan_idx) => {
-                                    self.chans[chan_idx]
-                                        .nicks
-                                        .insert(wire::drop_nick_prefix(nick).to_owned());
-                                }
-                                None => {
-                                    debug!("Can't find channel state for JOIN: {cmd:?}");
-                                }
-                            }
-                        }
-                    }
-                    Some(Pfx::Server(_)) | None => {}
-                }
+        let batch_refs: Vec<String> = self
+            .batches
+            .batches_of(&msg)
+            .into_iter()
+            .map(|batch| batch.reference.clone())
+            .collect();
+        match self.labeled_requests.values_mut().find(|request| {
+            request
+                .batch
+                .as_ref()
+                .is_some_and(|batch| batch_refs.contains(batch))
+        }) {
+            Some(request) => {
+                request.msgs.push(msg);
+                None
             }
+            None => Some(msg),
+        }
+    }
 
-            // PART: If this is us remove the channel state. Otherwise remove the nick from the
-            // channel.
-            PART { chan, .. } => match pfx {
+    /// Match a message from the server with a PRIVMSG in `pending_msgs`. Returns
+    /// `Event::MsgModified` if the message is an echo of a PRIVMSG we sent, with a different text,
+    /// or `Event::MsgNotSent` if the message is an error reply to a labeled PRIVMSG.
+    fn match_echo(&mut self, msg: &Msg) -> Option<Event> {
+        if self.pending_msgs.is_empty() {
+            return None;
+        }
+        let label = msg.tags.get("label");
+        match &msg.cmd {
+            wire::Cmd::PRIVMSG {
+                target,
+                msg: echo,
+                is_notice: false,
+                ..
+            } => {
+                match &msg.pfx {
+                    Some(Pfx::User { nick, .. } | Pfx::Ambiguous(nick))
+                        if self.is_current_nick(nick) => {}
+                    _ => return None,
+                }
+                let target = match target {
+                    wire::MsgTarget::Chan(chan) => chan.display(),
+                    wire::MsgTarget::User(nick) => nick,
+                };
+                // Without labels echoes are matched in order. Messages sent before the matched one
+                // won't be echoed, drop them.
+                let idx = self.pending_msgs.iter().position(|pending| match label {
+                    Some(label) => pending.label.as_deref() == Some(label),
+                    None => {
+                        pending.target.eq_ignore_ascii_case(target)
+                            && pending.msg.starts_with(echo.as_str())
+                    }
+                })?;
+                let pending = self.pending_msgs.drain(..=idx).next_back().unwrap();
+                if &pending.msg != echo {
+                    return Some(Event::MsgModified {
+                        target: pending.target,
+                        msg: pending.msg,
+                    });
+                }
+                None
+            }
+            cmd => {
+                let label = label?;
+                let idx = self
+                    .pending_msgs
+                    .iter()
+                    .position(|pending| pending.label.as_deref() == Some(label))?;
+                let pending = self.pending_msgs.remove(idx).unwrap();
+                match cmd {
+                    wire::Cmd::Reply { num, params } if *num >= 400 => Some(Event::MsgNotSent {
+                        target: pending.target,
+                        msg: pending.msg,
+                        reason: params.last().cloned().unwrap_or_default(),
+                    }),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Add the message to its `chathistory` batch, if it's in one (directly or in a nested
+    /// batch). Returns the message back if it's not. `BATCH` messages are not added, they're
+    /// needed to track nested batches.
+    fn add_history_msg(&mut self, msg: Msg) -> Option<Msg> {
+        if matches!(msg.cmd, wire::Cmd::BATCH { .. }) {
+            return Some(msg);
+        }
+        let history_ref = self
+            .batches
+            .batches_of(&msg)
+            .into_iter()
+            .find(|batch| self.history_batches.contains_key(&batch.reference))
+            .map(|batch| batch.reference.clone());
+        match history_ref.and_then(|reference| self.history_batches.get_mut(&reference)) {
+            Some(batch) => {
+                batch.msgs.push(msg);
+                None
+            }
+            None => Some(msg),
+        }
+    }
+
+    /// Make a `draft/chathistory` request for `limit` messages of `target` older than the ones
+    /// fetched before. When we don't have history for the target the latest messages are
+    /// requested.
+    fn history_request(&mut self, target: &str, limit: usize) -> String {
+        self.history_requests.push((target.to_owned(), true));
+        match self.history_oldest.get(&target.to_lowercase()) {
+            Some(Some(oldest)) => wire::chathistory_before(target, oldest, limit),
+            _ => wire::chathistory_latest(target, limit),
+        }
+    }
+
+    /// Forget the users of a channel, and return the messages to get them again: NAMES, and WHO
+    /// when we also got the users with WHO after joining the channel.
+    fn refresh_names(&mut self, chan: &ChanNameRef) -> Vec<String> {
+        let Some(chan_idx) = utils::find_idx(&self.chans, |c| &c.name == chan) else {
+            return vec![];
+        };
+        let mut msgs = vec![wire::names(chan.display())];
+        let who = self.is_cap_enabled("away-notify")
+            || self.is_cap_enabled("chghost")
+            || self.is_cap_enabled("setname");
+        let chan = &mut self.chans[chan_idx];
+        chan.clear_nicks();
+        if who {
+            msgs.push(wire::who(chan.name.display()));
+            chan.who_pending = true;
+        }
+        msgs
+    }
+
+    /// Handle a BATCH message. Messages of `chathistory` batches for our requests are reported
+    /// together with an `Event::History` when the batch ends.
+    fn batch(&mut self, msg: &Msg, snd_ev: &mut Sender<Event>) {
+        if let wire::Cmd::BATCH {
+            reference,
+            start: Some(BatchStart { batch_type, params }),
+        } = &msg.cmd
+            && batch_type == "chathistory"
+            && let Some(target) = params.first()
+            && let Some(idx) = self
+                .history_requests
+                .iter()
+                .position(|(target_, _)| target_.eq_ignore_ascii_case(target))
+        {
+            let (_, older) = self.history_requests.remove(idx);
+            self.history_batches.insert(
+                reference.to_owned(),
+                HistoryBatch {
+                    target: target.to_owned(),
+                    msgs: vec![],
+                    older,
+                },
+            );
+        }
+
+        if let Some(batch) = self.batches.update(msg)
+            && let Some(HistoryBatch {
+                target,
+                msgs,
+                older,
+            }) = self.history_batches.remove(&batch.reference)
+        {
+            let oldest = self
+                .history_oldest
+                .entry(target.to_lowercase())
+                .or_default();
+            if let Some(time) = msgs.first().and_then(|msg| msg.tags.get("time")) {
+                *oldest = Some(time.to_owned());
+            }
+            let target = if self.isupport.is_chan(&target) {
+                wire::MsgTarget::Chan(ChanName::new(target))
+            } else {
+                wire::MsgTarget::User(target)
+            };
+            snd_ev
+                .try_send(Event::History {
+                    target,
+                    msgs,
+                    older,
+                })
+                .unwrap();
+        }
+    }
+
+    /// Make a `+typing` notification for `target`, if it should be sent. Needs `message-tags`.
+    /// `active` notifications are sent at most once every `TYPING_ACTIVE_INTERVAL` for a target,
+    /// `paused` and `done` only after an `active` or `paused`.
+    fn typing(&mut self, target: &str, state: TypingState, now: Instant) -> Option<String> {
+        if !self.is_cap_enabled("message-tags") {
+            return None;
+        }
+        let key = target.to_lowercase();
+        let send = match (self.typing_sent.get(&key), state) {
+            (Some((TypingState::Active, sent)), TypingState::Active) => {
+                now.duration_since(*sent) >= TYPING_ACTIVE_INTERVAL
+            }
+            (_, TypingState::Active) => true,
+            (Some((last, _)), state) => *last != state,
+            (None, _) => false,
+        };
+        if !send {
+            return None;
+        }
+        if state == TypingState::Done {
+            self.typing_sent.remove(&key);
+        } else {
+            self.typing_sent.insert(key, (state, now));
+        }
+        Some(wire::typing(target, state))
+    }
+
+    /// Forget the typing state of `target`. Called when we send a message, which ends typing.
+    fn reset_typing(&mut self, target: &str) {
+        self.typing_sent.remove(&target.to_lowercase());
+    }
+
+    fn add_ping(&mut self, nick: Option<&str>) -> String {
+        let token = format!("tiny{}", self.next_ping_id);
+        self.next_ping_id += 1;
+        self.pings
+            .insert(token.clone(), (nick.map(str::to_owned), Instant::now()));
+        token
+    }
+
+    /// Report the round-trip time of the ping with the given token, if it's sent to `nick` (or to
+    /// the server when `nick` is `None`) and not replied yet.
+    fn ping_reply(&mut self, token: &str, nick: Option<&str>, snd_ev: &mut Sender<Event>) {
+        let matches = match (self.pings.get(token), nick) {
+            (Some((None, _)), None) => true,
+            (Some((Some(pinged), _)), Some(nick)) => pinged.eq_ignore_ascii_case(nick),
+            _ => false,
+        };
+        if matches {
+            let (nick, sent) = self.pings.remove(token).unwrap();
+            let rtt = sent.elapsed();
+            if nick.is_none() {
+                self.last_lag = Some(rtt);
+            }
+            snd_ev.try_send(Event::PingReply { nick, rtt }).unwrap();
+        }
+    }
+
+    /// Send a ping to check the connection and measure the lag. Only sent after registration
+    /// (RPL_YOURHOST).
+    fn send_ping(&mut self, snd_irc_msg: &mut Sender<String>) {
+        if self.servername.is_some() {
+            let token = format!("tiny{}", self.next_ping_id);
+            self.next_ping_id += 1;
+            snd_irc_msg.try_send(wire::ping(&token)).unwrap();
+            self.lag_ping = Some((token, Instant::now()));
+        }
+    }
+
+    /// Whether the connection is lagging: the last ping to the server is not replied in
+    /// `MAX_AWAY_POLL_LAG`, or the reply took longer.
+    fn is_lagging(&self) -> bool {
+        self.lag_ping
+            .as_ref()
+            .is_some_and(|(_, sent)| sent.elapsed() > MAX_AWAY_POLL_LAG)
+            || self.last_lag.is_some_and(|lag| lag > MAX_AWAY_POLL_LAG)
+    }
+
+    /// Send a WHO to poll the away status of the users in the channel polled least recently.
+    /// Channels with more than `max_users` users and channels with a WHO in progress are
+    /// skipped. Nothing is sent with IRCv3 `away-notify`, or when the connection is lagging.
+    fn poll_away(&mut self, max_users: usize, snd_irc_msg: &mut Sender<String>) {
+        if self.is_cap_enabled("away-notify") || self.is_lagging() {
+            return;
+        }
+        let whox = self.isupport.whox();
+        let Some(chan) = self
+            .chans
+            .iter_mut()
+            .filter(|chan| {
+                matches!(chan.join_state, JoinState::Joined)
+                    && !chan.who_pending
+                    && chan.nicks.len() <= max_users
+            })
+            .min_by_key(|chan| chan.last_away_poll)
+        else {
+            return;
+        };
+        // With WHOX only ask for the fields we need: token, channel, nick, and flags
+        let msg = if whox {
+            wire::whox(chan.name.display(), "tcnf", AWAY_POLL_WHOX_TOKEN)
+        } else {
+            wire::who(chan.name.display())
+        };
+        snd_irc_msg.try_send(msg).unwrap();
+        chan.who_pending = true;
+        chan.last_away_poll = Some(Instant::now());
+    }
+
+    /// Delay between two away status polls: `interval` divided between the joined channels.
+    fn away_poll_delay(&self, interval: Duration) -> Duration {
+        let n_chans = self
+            .chans
+            .iter()
+            .filter(|chan| matches!(chan.join_state, JoinState::Joined))
+            .count();
+        interval / n_chans.max(1) as u32
+    }
+
+    /// Update away status of a user in a channel from the flags of a WHO reply. Flags start with
+    /// 'G' (gone) when the user is away, 'H' (here) otherwise.
+    fn set_who_away(&mut self, chan_idx: usize, nick: &str, flags: &str) {
+        let was_away = self.is_nick_away(nick);
+        let away_nicks = &mut self.chans[chan_idx].away_nicks;
+        if flags.starts_with('G') {
+            away_nicks.insert(nick.to_owned());
+        } else {
+            away_nicks.remove(nick);
+        }
+        let away = self.is_nick_away(nick);
+        if away != was_away {
+            self.away_changes.push((nick.to_owned(), away));
+        }
+    }
+
+    fn introduce(&mut self, snd_irc_msg: &mut Sender<String>) {
+        if let Some(ref pass) = self.server_info.pass {
+            snd_irc_msg.try_send(wire::pass(pass)).unwrap();
+        }
+        snd_irc_msg
+            .try_send(wire::nick(&self.current_nick))
+            .unwrap();
+        let user = self.server_info.user.as_ref().unwrap_or(&self.nicks[0]);
+        snd_irc_msg
+            .try_send(wire::user(user, &self.server_info.realname))
+            .unwrap();
+    }
+
+    fn get_next_nick(&mut self) -> &str {
+        self.current_nick_idx += 1;
+        // debug!("current_nick_idx: {}", self.current_nick_idx);
+        if self.current_nick_idx >= self.nicks.len() {
+            let n_underscores = self.current_nick_idx - self.nicks.len() + 1;
+            let mut new_nick = self.nicks.last().unwrap().to_string();
+            for _ in 0..n_underscores {
+                new_nick.push('_');
+            }
+            self.current_nick = new_nick;
+        } else {
+            self.current_nick
+                .clone_from(&self.nicks[self.current_nick_idx]);
+        }
+        &self.current_nick
+    }
+
+    fn update(
+        &mut self,
+        msg: &mut Msg,
+        snd_ev: &mut Sender<Event>,
+        snd_irc_msg: &mut Sender<String>,
+    ) {
+        // BATCH: Track open batches, collect `chathistory` batches
+        if let wire::Cmd::BATCH { .. } = msg.cmd {
+            self.batch(msg, snd_ev);
+            return;
+        }
+
+        let Msg { tags, pfx, cmd } = msg;
+
+        // With `account-tag` messages from logged in users have an `account` tag
+        if self.is_cap_enabled("account-tag")
+            && let Some(Pfx::User { nick, .. }) = pfx
+        {
+            let account = tags.get("account");
+            for chan in self.chans.iter_mut() {
+                if chan.nicks.contains(nick) {
+                    chan.set_account(nick, account);
+                }
+            }
+        }
+
+        // Messages from us (e.g. JOINs, and PRIVMSGs echoed with IRCv3 `echo-message`) have our
+        // usermask in the prefix
+        if let Some(Pfx::User { nick, user }) = pfx
+            && self.is_current_nick(nick)
+        {
+            self.usermask = Some(format!("{nick}!{user}"));
+        }
+
+        // Message targets are parsed as channels when they start with `#`. Use the channel name
+        // prefixes of the server (RPL_ISUPPORT `CHANTYPES`) instead.
+        if let wire::Cmd::PRIVMSG { target, .. } | wire::Cmd::TAGMSG { target } = cmd {
+            match target {
+                wire::MsgTarget::User(name) if self.isupport.is_chan(name) => {
+                    *target = wire::MsgTarget::Chan(ChanName::new(std::mem::take(name)));
+                }
+                wire::MsgTarget::Chan(chan) if !self.isupport.is_chan(chan.display()) => {
+                    *target = wire::MsgTarget::User(chan.display().to_owned());
+                }
+                _ => {}
+            }
+        }
+
+        use wire::Cmd::*;
+        match cmd {
+            // PING: Send PONG
+            PING { server } => {
+                snd_irc_msg.try_send(wire::pong(server)).unwrap();
+            }
+
+            // PONG: Update the lag, report round-trip time if this is a reply to a `Client::ping`
+            PONG { token, .. } => match self.lag_ping.take() {
+                Some((lag_token, sent)) if lag_token == *token => {
+                    self.last_lag = Some(sent.elapsed());
+                }
+                lag_ping => {
+                    self.lag_ping = lag_ping;
+                    self.ping_reply(token, None, snd_ev);
+                }
+            },
+
+            // CTCP PING reply: Report round-trip time if this is a reply to a `Client::ping`
+            PRIVMSG {
+                is_notice: true,
+                ctcp: Some(wire::CTCP::Ping),
+                msg,
+                ..
+            } => {
+                if let Some(Pfx::User { nick, .. } | Pfx::Ambiguous(nick)) = pfx {
+                    self.ping_reply(msg.trim(), Some(nick), snd_ev);
+                }
+            }
+
+            // JOIN: If this is us then create the channel state. If someone else add the nick to
+            // channel.
+            JOIN { chan, account } => {
+                match &*pfx {
+                    Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => {
+                        if self.is_current_nick(nick) {
+                            // We joined a channel, initialize channel state
+                            if self
+                                .last_parted
+                                .as_ref()
+                                .is_some_and(|(parted, _)| parted == chan)
+                            {
+                                self.last_parted = None;
+                            }
+                            self.invites.retain(|(invite, _)| &invite.chan != chan);
+                            let chan_idx = match utils::find_idx(&self.chans, |c| &c.name == chan) {
+                                None => {
+                                    let mut chan = Chan::new(chan.to_owned());
+                                    // Since nick was found in the prefix, we are in the channel
+                                    chan.join_state = JoinState::Joined;
+                                    self.chans.push(chan);
+                                    self.chans.len() - 1
+                                }
+                                Some(chan_idx) => {
+                                    // This happens because we initialize channel states for channels
+                                    // that we will join on connection when the client is first created
+                                    let chan = &mut self.chans[chan_idx];
+                                    chan.join_state = JoinState::Joined;
+                                    chan.clear_nicks();
+                                    chan_idx
+                                }
+                            };
+                            // NAMES doesn't include away status, hosts and real names, get them
+                            // with WHO. After this `away-notify`, `chghost` and `setname` keep
+                            // them up to date.
+                            if self.is_cap_enabled("away-notify")
+                                || self.is_cap_enabled("chghost")
+                                || self.is_cap_enabled("setname")
+                            {
+                                snd_irc_msg.try_send(wire::who(chan.display())).unwrap();
+                                self.chans[chan_idx].who_pending = true;
+                            }
+                            snd_irc_msg
+                                .try_send(wire::chan_modes(chan.display()))
+                                .unwrap();
+                            self.chans[chan_idx].modes_pending = true;
+                            let target = chan.display().to_lowercase();
+                            let limit = self.server_info.join_history_lines;
+                            if limit != 0
+                                && self.is_cap_enabled("draft/chathistory")
+                                && !self.history_oldest.contains_key(&target)
+                            {
+                                snd_irc_msg
+                                    .try_send(wire::chathistory_latest(chan.display(), limit))
+                                    .unwrap();
+                                self.history_requests
+                                    .push((chan.display().to_owned(), false));
+                            }
+                        } else {
+                            match utils::find_idx(&self.chans, |c| &c.name == chan) {
+                                Some(chan_idx) => {
+                                    let nick = wire::drop_nick_prefix(nick);
+                                    let chan = &mut self.chans[chan_idx];
+                                    chan.nicks.insert(nick.to_owned());
+                                    if let Some(Pfx::User { user, .. }) = &*pfx {
+                                        chan.user_hosts.insert(nick.to_owned(), user.to_owned());
+                                    }
+                                    // Without `extended-join` the account may be in the tags
+                                    let account = account.as_deref().or(tags.get("account"));
+                                    chan.set_account(nick, account);
+                                }
+                                None => {
+                                    debug!("Can't find channel state for JOIN: {cmd:?}");
+                                }
+                            }
+                        }
+                    }
+                    Some(Pfx::Server(_)) | None => {}
+                }
+            }
+
+            // PART: If this is us remove the channel state. Otherwise remove the nick from the
+            // channel.
+            PART { chan, msg: reason } => match pfx {
                 Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => {
-                    if nick == &self.current_nick {
+                    if self.is_current_nick(nick) {
                         match utils::find_idx(&self.chans, |c| &c.name == chan) {
                             None => {
                                 debug!("Can't find channel state: {}", chan.display());
                             }
                             Some(chan_idx) => {
-                                self.chans.remove(chan_idx);
+                                let chan = self.chans.remove(chan_idx);
+                                if let Some(key) = chan.modes.as_ref().and_then(ChanModeState::key)
+                                {
+                                    self.left_chan_keys
+                                        .insert(chan.name.clone(), key.to_owned());
+                                }
+                                self.last_parted = Some((chan.name, reason.clone()));
                             }
                         }
                     } else {
                         match utils::find_idx(&self.chans, |c| &c.name == chan) {
                             Some(chan_idx) => {
-                                self.chans[chan_idx]
-                                    .nicks
-                                    .remove(wire::drop_nick_prefix(nick));
+                                self.chans[chan_idx].remove_nick(wire::drop_nick_prefix(nick));
                             }
                             None => {
                                 debug!("Can't find channel state for PART: {cmd:?}");
@@ -369,8 +1438,61 @@ impl StateInner {
                 Some(Pfx::Server(_)) | None => {}
             },
 
+            // KICK: If we're kicked remove the channel state. Otherwise remove the nick from the
+            // channel.
+            KICK { chan, nick, .. } => {
+                if self.is_current_nick(nick) {
+                    match utils::find_idx(&self.chans, |c| &c.name == chan) {
+                        None => {
+                            debug!("Can't find channel state: {}", chan.display());
+                        }
+                        Some(chan_idx) => {
+                            let chan = self.chans.remove(chan_idx);
+                            if let Some(key) = chan.modes.as_ref().and_then(ChanModeState::key) {
+                                self.left_chan_keys.insert(chan.name, key.to_owned());
+                            }
+                        }
+                    }
+                } else {
+                    match utils::find_idx(&self.chans, |c| &c.name == chan) {
+                        Some(chan_idx) => {
+                            self.chans[chan_idx].remove_nick(wire::drop_nick_prefix(nick));
+                        }
+                        None => {
+                            debug!("Can't find channel state for KICK: {cmd:?}");
+                        }
+                    }
+                }
+            }
+
             // QUIT: Update the `chans` field for the channels that the user was in
             QUIT { chans, .. } => {
+                let nick = match pfx {
+                    Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => nick,
+                    Some(Pfx::Server(_)) | None => {
+                        return;
+                    }
+                };
+                for chan in self.chans.iter_mut() {
+                    if chan.remove_nick(nick) {
+                        chans.push(chan.name.to_owned());
+                    }
+                }
+            }
+
+            // ACCOUNT: Update services account of the user in channels. Logging out clears it.
+            ACCOUNT { account } => {
+                if let Some(Pfx::User { nick, .. } | Pfx::Ambiguous(nick)) = pfx {
+                    for chan in self.chans.iter_mut() {
+                        if chan.nicks.contains(nick) {
+                            chan.set_account(nick, account.as_deref());
+                        }
+                    }
+                }
+            }
+
+            // AWAY: Update away status of the user in channels, fill in `chans`
+            AWAY { msg, chans } => {
                 let nick = match pfx {
                     Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => nick,
                     Some(Pfx::Server(_)) | None => {
@@ -380,22 +1502,63 @@ impl StateInner {
                 for chan in self.chans.iter_mut() {
                     if chan.nicks.contains(nick) {
                         chans.push(chan.name.to_owned());
-                        chan.nicks.remove(nick);
+                        if msg.is_some() {
+                            chan.away_nicks.insert(nick.to_owned());
+                        } else {
+                            chan.away_nicks.remove(nick);
+                        }
                     }
                 }
             }
 
-            // 396: Try to set usermask.
-            Reply { num: 396, params } => {
-                // :hobana.freenode.net 396 osa1 haskell/developer/osa1
-                // :is now your hidden host (set by services.)
-                if params.len() == 3 {
-                    let usermask =
-                        format!("{}!~{}@{}", self.current_nick, self.nicks[0], params[1]);
-                    self.usermask = Some(usermask);
+            // CHGHOST: Update username and host of the user in channels, fill in `chans`
+            CHGHOST { user, host, chans } => {
+                let nick = match pfx {
+                    Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => nick,
+                    Some(Pfx::Server(_)) | None => {
+                        return;
+                    }
+                };
+                let user_host = format!("{user}@{host}");
+                if self.is_current_nick(nick) {
+                    self.usermask = Some(format!("{nick}!{user_host}"));
+                }
+                for chan in self.chans.iter_mut() {
+                    if chan.nicks.contains(nick) {
+                        chans.push(chan.name.to_owned());
+                        chan.user_hosts.insert(nick.to_owned(), user_host.clone());
+                    }
+                }
+            }
+
+            // SETNAME: Update real name of the user in channels, fill in `chans`. Our own real
+            // name is updated so that it's used in the next connection registration.
+            SETNAME { realname, chans } => {
+                let nick = match pfx {
+                    Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => nick,
+                    Some(Pfx::Server(_)) | None => {
+                        return;
+                    }
+                };
+                if self.is_current_nick(nick) {
+                    self.server_info.realname = realname.clone();
+                }
+                for chan in self.chans.iter_mut() {
+                    if chan.nicks.contains(nick) {
+                        chans.push(chan.name.to_owned());
+                        chan.realnames.insert(nick.to_owned(), realname.clone());
+                    }
                 }
             }
 
+            // 396: Try to set usermask.
+            // :hobana.freenode.net 396 osa1 haskell/developer/osa1
+            // :is now your hidden host (set by services.)
+            Reply { num: 396, params } if params.len() == 3 => {
+                let usermask = format!("{}!~{}@{}", self.current_nick, self.nicks[0], params[1]);
+                self.usermask = Some(usermask);
+            }
+
             // Reply 477 when user needs to be identified with NickServ to join a channel
             // ex. Reply { num: 477, params: ["<your_nick>", "<channel name>", "<Server reply message>"] }
             Reply { num: 477, params } => {
@@ -404,6 +1567,7 @@ impl StateInner {
                     let channel = ChanNameRef::new(channel);
                     snd_ev
                         .try_send(Event::Msg(wire::Msg {
+                            tags: wire::Tags::default(),
                             pfx: pfx.clone(),
                             cmd: wire::Cmd::PRIVMSG {
                                 ctcp: None,
@@ -492,8 +1656,16 @@ impl StateInner {
                 }
             }
 
-            // RPL_WELCOME: Start introduction sequence and NickServ authentication.
-            Reply { num: 001, .. } => {
+            // RPL_WELCOME: Start introduction sequence and NickServ authentication. Some servers
+            // end the welcome message with our usermask.
+            Reply { num: 001, params } => {
+                if let Some(usermask) = params.last().and_then(|msg| msg.split_whitespace().last())
+                    && let Some((nick, user_host)) = usermask.split_once('!')
+                    && self.is_current_nick(nick)
+                    && user_host.contains('@')
+                {
+                    self.usermask = Some(usermask.to_owned());
+                }
                 snd_ev.try_send(Event::Connected).unwrap();
                 snd_ev
                     .try_send(Event::NickChange {
@@ -503,7 +1675,7 @@ impl StateInner {
                 self.nick_accepted = true;
                 if let Some(ref pwd) = self.nickserv_ident {
                     snd_irc_msg
-                        .try_send(wire::privmsg("NickServ", &format!("identify {pwd}")))
+                        .try_send(wire::privmsg(&self.nickserv, &format!("identify {pwd}")))
                         .unwrap();
                 }
             }
@@ -526,17 +1698,15 @@ impl StateInner {
             }
 
             // ERR_NICKNAMEINUSE: Try another nick if we don't have a nick yet.
-            Reply { num: 433, .. } => {
-                if !self.nick_accepted {
-                    let new_nick = self.get_next_nick();
-                    // debug!("new nick: {}", new_nick);
-                    snd_ev
-                        .try_send(Event::NickChange {
-                            new_nick: new_nick.to_owned(),
-                        })
-                        .unwrap();
-                    snd_irc_msg.try_send(wire::nick(new_nick)).unwrap();
-                }
+            Reply { num: 433, .. } if !self.nick_accepted => {
+                let new_nick = self.get_next_nick();
+                // debug!("new nick: {}", new_nick);
+                snd_ev
+                    .try_send(Event::NickChange {
+                        new_nick: new_nick.to_owned(),
+                    })
+                    .unwrap();
+                snd_irc_msg.try_send(wire::nick(new_nick)).unwrap();
             }
 
             // NICK message sent from the server when our nick change request was successful
@@ -546,7 +1716,7 @@ impl StateInner {
             } => {
                 match pfx {
                     Some(Pfx::User { nick: old_nick, .. }) | Some(Pfx::Ambiguous(old_nick)) => {
-                        if old_nick == &self.current_nick {
+                        if self.is_current_nick(old_nick) {
                             snd_ev
                                 .try_send(Event::NickChange {
                                     new_nick: new_nick.to_owned(),
@@ -565,17 +1735,25 @@ impl StateInner {
 
                             self.current_nick.clone_from(new_nick);
 
+                            if let Some(usermask) = &mut self.usermask
+                                && let Some((_, user_host)) = usermask.split_once('!')
+                            {
+                                *usermask = format!("{new_nick}!{user_host}");
+                            }
+
                             if let Some(ref pwd) = self.nickserv_ident {
                                 snd_irc_msg
-                                    .try_send(wire::privmsg("NickServ", &format!("identify {pwd}")))
+                                    .try_send(wire::privmsg(
+                                        &self.nickserv,
+                                        &format!("identify {pwd}"),
+                                    ))
                                     .unwrap();
                             }
                         }
 
                         // Rename the nick in channel states, also populate the chan list
                         for chan in &mut self.chans {
-                            if chan.nicks.remove(old_nick) {
-                                chan.nicks.insert(new_nick.to_owned());
+                            if chan.rename_nick(old_nick, new_nick) {
                                 chans.push(chan.name.to_owned());
                             }
                         }
@@ -597,53 +1775,302 @@ impl StateInner {
                 }
             }
 
+            // RPL_UNAWAY: Servers may also unset away status without an AWAY command, e.g. when we
+            // send a message. Don't set it again after reconnecting.
+            Reply { num: 305, .. } => {
+                self.away_status = None;
+            }
+
             // RPL_NAMREPLY: Set users in a channel
             Reply { num: 353, params } => {
                 let chan = ChanNameRef::new(&params[2]);
-                match utils::find_idx(&self.chans, |c| &c.name == chan) {
-                    None => self.chans.push(Chan::with_nicks(
-                        chan.to_owned(),
-                        params[3]
-                            .split_whitespace()
-                            .map(|s| wire::drop_nick_prefix(s).to_owned())
-                            .collect(),
-                    )),
-                    Some(idx) => {
-                        let nick_set = &mut self.chans[idx].nicks;
-                        for nick in params[3].split_whitespace() {
-                            nick_set.insert(wire::drop_nick_prefix(nick).to_owned());
-                        }
+                let idx = match utils::find_idx(&self.chans, |c| &c.name == chan) {
+                    None => {
+                        self.chans.push(Chan::new(chan.to_owned()));
+                        self.chans.len() - 1
                     }
+                    Some(idx) => idx,
+                };
+                for name in params[3].split_whitespace() {
+                    self.chans[idx].add_name(name, &self.isupport.chan_modes);
                 }
             }
 
-            // SASL authentication
-            CAP {
-                client: _,
-                subcommand,
-                params,
-            } => {
-                match subcommand.as_ref() {
-                    "ACK" => {
-                        if params.iter().any(|cap| cap.as_str() == "sasl") {
-                            if let Some(sasl) = &self.server_info.sasl_auth {
-                                let msg = match sasl {
-                                    SASLAuth::Plain { .. } => "PLAIN",
-                                    SASLAuth::External { .. } => "EXTERNAL",
-                                };
-                                snd_irc_msg.try_send(wire::authenticate(msg)).unwrap();
-                            } else {
-                                warn!("SASL AUTH not set but got SASL ACK");
+            // RPL_ISUPPORT: Update server features
+            // :server 005 <me> <param>... :are supported by this server
+            Reply { num: 005, params } if params.len() > 2 => {
+                for param in &params[1..params.len() - 1] {
+                    self.isupport.update(param);
+                }
+            }
+
+            // MODE: Update modes of a channel, and membership prefixes of users in the channel
+            Other { cmd: mode, params } if mode == "MODE" && params.len() > 1 => {
+                let chan = ChanNameRef::new(&params[0]);
+                if let Some(idx) = utils::find_idx(&self.chans, |c| &c.name == chan) {
+                    let args: Vec<&str> = params[2..].iter().map(String::as_str).collect();
+                    let chan = &mut self.chans[idx];
+                    if let Some(modes) = &mut chan.modes {
+                        let old_modes = modes.clone();
+                        modes.apply(&self.isupport.chan_modes, &params[1], &args);
+                        if *modes != old_modes {
+                            snd_ev
+                                .try_send(Event::ChanModes {
+                                    chan: chan.name.clone(),
+                                    modes: modes.to_string(),
+                                })
+                                .unwrap();
+                        }
+                    }
+                    for change in self.isupport.chan_modes.prefix_changes(&params[1], &args) {
+                        if !chan.nicks.contains(change.nick) {
+                            continue;
+                        }
+                        let prefixes = chan.prefixes.entry(change.nick.to_owned()).or_default();
+                        if change.set {
+                            self.isupport.chan_modes.add_prefix(prefixes, change.prefix);
+                        } else {
+                            prefixes.retain(|c| c != change.prefix);
+                        }
+                        if prefixes.is_empty() {
+                            chan.prefixes.remove(change.nick);
+                        }
+                    }
+                }
+            }
+
+            // RPL_WHOREPLY: Update away status, hosts and real names of users in a channel.
+            // :server 352 <me> <chan> <user> <host> <server> <nick> <flags> :<hops> <realname>
+            Reply { num: 352, params } if params.len() > 6 => {
+                let chan = ChanNameRef::new(&params[1]);
+                if let Some(idx) = utils::find_idx(&self.chans, |c| &c.name == chan) {
+                    let chan = &mut self.chans[idx];
+                    let nick = &params[5];
+                    if chan.nicks.contains(nick) {
+                        chan.user_hosts
+                            .insert(nick.to_owned(), format!("{}@{}", params[2], params[3]));
+                        if let Some((_hops, realname)) =
+                            params.get(7).and_then(|param| param.split_once(' '))
+                        {
+                            chan.realnames.insert(nick.to_owned(), realname.to_owned());
+                        }
+                    }
+                    self.set_who_away(idx, nick, &params[6]);
+                }
+            }
+
+            // RPL_WHOSPCRPL: WHOX reply to an away status poll
+            // :server 354 <me> <token> <chan> <nick> <flags>
+            Reply { num: 354, params } if params.len() > 4 && params[1] == AWAY_POLL_WHOX_TOKEN => {
+                let chan = ChanNameRef::new(&params[2]);
+                if let Some(idx) = utils::find_idx(&self.chans, |c| &c.name == chan) {
+                    self.set_who_away(idx, &params[3], &params[4]);
+                }
+            }
+
+            // RPL_WHOISUSER: Update usermask when this is a WHOIS of us
+            Reply { num: 311, params } if params.len() > 3 && self.is_current_nick(&params[1]) => {
+                self.usermask = Some(format!("{}!{}@{}", params[1], params[2], params[3]));
+            }
+
+            // RPL_WHOISACCOUNT: Update services account of the user
+            // :server 330 <me> <nick> <account> :is logged in as
+            Reply { num: 330, params } if params.len() > 2 => {
+                let nick = &params[1];
+                for chan in self.chans.iter_mut() {
+                    if chan.nicks.contains(nick) {
+                        chan.set_account(nick, Some(&params[2]));
+                    }
+                }
+            }
+
+            // RPL_ENDOFWHO
+            Reply { num: 315, params } if params.len() > 1 => {
+                let chan = ChanNameRef::new(&params[1]);
+                if let Some(idx) = utils::find_idx(&self.chans, |c| &c.name == chan) {
+                    self.chans[idx].who_pending = false;
+                }
+            }
+
+            // RPL_NOTOPIC
+            Reply { num: 331, params } if params.len() > 1 => {
+                if let Some(chan) = self.get_chan_mut(ChanNameRef::new(&params[1])) {
+                    chan.topic = None;
+                }
+            }
+
+            // RPL_TOPIC: Sent after joining and on TOPIC queries, followed by RPL_TOPICWHOTIME
+            Reply { num: 332, params } if params.len() > 2 => {
+                if let Some(chan) = self.get_chan_mut(ChanNameRef::new(&params[1])) {
+                    chan.topic = Some(ChanTopic {
+                        topic: params[2].clone(),
+                        set_by: None,
+                        set_at: None,
+                    });
+                }
+            }
+
+            // RPL_TOPICWHOTIME
+            Reply { num: 333, params } if params.len() > 3 => {
+                if let Some(topic) = self
+                    .get_chan_mut(ChanNameRef::new(&params[1]))
+                    .and_then(|chan| chan.topic.as_mut())
+                {
+                    topic.set_by = Some(params[2].clone());
+                    topic.set_at = params[3].parse().ok();
+                }
+            }
+
+            // RPL_CHANNELMODEIS: Modes of a channel
+            // :server 324 <me> <chan> <modes> <mode params>...
+            Reply { num: 324, params } if params.len() > 2 => {
+                let args: Vec<&str> = params[3..].iter().map(String::as_str).collect();
+                let mut modes = ChanModeState::default();
+                modes.apply(&self.isupport.chan_modes, &params[2], &args);
+                if let Some(chan) = self.get_chan_mut(ChanNameRef::new(&params[1])) {
+                    snd_ev
+                        .try_send(Event::ChanModes {
+                            chan: chan.name.clone(),
+                            modes: modes.to_string(),
+                        })
+                        .unwrap();
+                    chan.modes = Some(modes);
+                }
+            }
+
+            // RPL_CREATIONTIME: Usually sent after RPL_CHANNELMODEIS, ends the replies to a MODE
+            // query
+            Reply { num: 329, params } if params.len() > 2 => {
+                if let Some(chan) = self.get_chan_mut(ChanNameRef::new(&params[1])) {
+                    chan.created = params[2].parse().ok();
+                    chan.modes_pending = false;
+                }
+            }
+
+            INVITE { nick, chan } => {
+                if let Some(Pfx::User { nick: from, .. } | Pfx::Ambiguous(from)) = pfx
+                    && self.is_current_nick(nick)
+                {
+                    self.invites.retain(|(invite, _)| &invite.chan != chan);
+                    self.invites.push((
+                        Invite {
+                            chan: chan.clone(),
+                            from: from.clone(),
+                        },
+                        Instant::now(),
+                    ));
+                }
+            }
+
+            TOPIC { chan, topic } => {
+                let set_by = match pfx {
+                    Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => Some(nick.clone()),
+                    _ => None,
+                };
+                if let Some(chan) = self.get_chan_mut(chan) {
+                    chan.topic = if topic.is_empty() {
+                        None
+                    } else {
+                        Some(ChanTopic {
+                            topic: topic.clone(),
+                            set_by,
+                            set_at: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .ok()
+                                .map(|duration| duration.as_secs() as i64),
+                        })
+                    };
+                }
+            }
+
+            // Capability negotiation, SASL authentication
+            CAP {
+                client: _,
+                subcommand,
+                params,
+                more,
+            } => {
+                match subcommand.as_ref() {
+                    "ACK" => {
+                        for cap in params.iter().filter(|cap| !cap.is_empty()) {
+                            // "-cap" disables a capability
+                            match cap.strip_prefix('-') {
+                                Some(cap) => self.enabled_caps.retain(|cap_| cap_ != cap),
+                                None => {
+                                    if !self.enabled_caps.contains(cap) {
+                                        self.enabled_caps.push(cap.clone());
+                                    }
+                                }
+                            }
+                        }
+
+                        if params.iter().any(|cap| cap.as_str() == "sasl") {
+                            if let Some(sasl) = &self.server_info.sasl_auth {
+                                let mechanism = Mechanism::new(sasl);
+                                snd_irc_msg
+                                    .try_send(wire::authenticate(mechanism.name()))
+                                    .unwrap();
+                                self.sasl_mechanism = Some(mechanism);
+                                self.sasl_challenge.clear();
+                                // CAP END is sent after authentication
+                                return;
+                            } else {
+                                warn!("SASL AUTH not set but got SASL ACK");
                             }
                         }
+                        // Capabilities requested after CAP NEW are acknowledged after
+                        // registration, when negotiation is already over
+                        if !self.nick_accepted {
+                            snd_irc_msg.try_send(wire::cap_end()).unwrap();
+                        }
                     }
-                    "NAK" => {
+                    "NAK" if !self.nick_accepted => {
                         snd_irc_msg.try_send(wire::cap_end()).unwrap();
                     }
+                    // https://ircv3.net/specs/extensions/capability-negotiation#cap-new-subcommand
+                    "NEW" => {
+                        self.add_server_caps(params);
+                        let req: Vec<&str> = SUPPORTED_CAPS
+                            .into_iter()
+                            .filter(|cap| {
+                                params.iter().any(|cap_| cap_name(cap_) == *cap)
+                                    && !self.is_cap_enabled(cap)
+                            })
+                            .collect();
+                        if !req.is_empty() {
+                            snd_irc_msg.try_send(wire::cap_req(&req)).unwrap();
+                        }
+                    }
+                    // Capabilities deleted by the server are disabled, which stops the features
+                    // depending on them (checked with `is_cap_enabled`)
+                    "DEL" => {
+                        for cap in params.iter().filter(|cap| !cap.is_empty()) {
+                            self.server_caps.retain(|cap_| cap_name(cap_) != cap);
+                            self.enabled_caps.retain(|cap_| cap_ != cap);
+                        }
+                    }
+                    // Multiline reply, capabilities are requested after the last line
+                    "LS" if *more => self.add_server_caps(params),
                     "LS" => {
-                        self.introduce(snd_irc_msg);
-                        if params.iter().any(|cap| cap == "sasl") {
-                            snd_irc_msg.try_send(wire::cap_req(&["sasl"])).unwrap();
+                        self.add_server_caps(params);
+                        if let Some(sts) = StsCap::from_caps(&self.server_caps) {
+                            self.sts = Some(sts);
+                        }
+                        // Without SASL we introduce right after sending CAP LS
+                        if self.server_info.sasl_auth.is_some() {
+                            self.introduce(snd_irc_msg);
+                        }
+                        let mut req: Vec<&str> = SUPPORTED_CAPS
+                            .into_iter()
+                            .filter(|cap| self.is_cap_advertised(cap))
+                            .collect();
+                        if self.server_info.sasl_auth.is_some() && self.is_cap_advertised("sasl") {
+                            req.push("sasl");
+                        }
+                        if req.is_empty() {
+                            snd_irc_msg.try_send(wire::cap_end()).unwrap();
+                        } else {
+                            snd_irc_msg.try_send(wire::cap_req(&req)).unwrap();
                             // Will wait for CAP ... ACK from server before authentication.
                         }
                     }
@@ -653,26 +2080,65 @@ impl StateInner {
 
             // https://ircv3.net/specs/extensions/sasl-3.1.html
             AUTHENTICATE { param } => {
-                if param.as_str() == "+" {
-                    // Empty AUTHENTICATE response; server accepted the specified SASL mechanism
-                    if let Some(ref auth) = self.server_info.sasl_auth {
-                        let msg = match auth {
-                            SASLAuth::Plain { username, password } => {
-                                let msg = format!("{username}\x00{username}\x00{password}");
-                                use base64::engine::Engine;
-                                base64::engine::general_purpose::STANDARD.encode(msg)
-                            }
-                            // Reply with an empty response (Empty responses are sent as "AUTHENTICATE +")
-                            SASLAuth::External { .. } => "+".to_string(),
-                        };
-                        snd_irc_msg.try_send(wire::authenticate(&msg)).unwrap();
+                // Server accepted the mechanism ("AUTHENTICATE +"), or sent a challenge
+                let Some(mechanism) = &mut self.sasl_mechanism else {
+                    warn!("Got AUTHENTICATE without a SASL mechanism");
+                    return;
+                };
+                let response = match self.sasl_challenge.add(param) {
+                    // Challenge continues in the next message
+                    None => return,
+                    Some(challenge) => {
+                        challenge.and_then(|challenge| mechanism.respond(&challenge))
+                    }
+                };
+                match response {
+                    Ok(response) => {
+                        for msg in sasl::authenticate_msgs(&response) {
+                            snd_irc_msg.try_send(msg).unwrap();
+                        }
+                    }
+                    Err(err) => {
+                        // Server replies with ERR_SASLABORTED
+                        warn!("Aborting SASL authentication: {err}");
+                        snd_irc_msg.try_send(wire::authenticate("*")).unwrap();
+                    }
+                }
+            }
+
+            Reply { num: 903, .. } => {
+                // RPL_SASLSUCCESS
+                match self.sasl_mechanism.take() {
+                    Some(mechanism) if !mechanism.is_done() => {
+                        warn!(
+                            "Got RPL_SASLSUCCESS before {} authentication finished",
+                            mechanism.name()
+                        );
+                        self.sasl_fail(snd_irc_msg);
+                    }
+                    _ => {
+                        snd_irc_msg.try_send(wire::cap_end()).unwrap();
                     }
                 }
             }
 
-            Reply { num: 903, .. } | Reply { num: 904, .. } => {
-                // 903: RPL_SASLSUCCESS, 904: ERR_SASLFAIL
-                snd_irc_msg.try_send(wire::cap_end()).unwrap();
+            Reply { num: 904, .. } | Reply { num: 905, .. } | Reply { num: 906, .. } => {
+                // 904: ERR_SASLFAIL, 905: ERR_SASLTOOLONG, 906: ERR_SASLABORTED
+                let fallback = match (&self.sasl_mechanism, &self.server_info.sasl_auth) {
+                    (Some(mechanism), Some(auth)) => mechanism.fallback(auth),
+                    _ => None,
+                };
+                if let Some(mechanism) = fallback {
+                    // Server does not support the mechanism, try the next one
+                    snd_irc_msg
+                        .try_send(wire::authenticate(mechanism.name()))
+                        .unwrap();
+                    self.sasl_mechanism = Some(mechanism);
+                    self.sasl_challenge.clear();
+                    return;
+                }
+                self.sasl_mechanism = None;
+                self.sasl_fail(snd_irc_msg);
             }
 
             // Ignore the rest
@@ -680,6 +2146,14 @@ impl StateInner {
         }
     }
 
+    fn sasl_fail(&mut self, snd_irc_msg: &mut Sender<String>) {
+        self.sasl_failed = true;
+        // With `SASLFail::Disconnect` the main loop disconnects
+        if self.server_info.sasl_fail == SASLFail::Continue {
+            snd_irc_msg.try_send(wire::cap_end()).unwrap();
+        }
+    }
+
     fn get_chan_nicks(&self, chan: &ChanNameRef) -> Vec<String> {
         match utils::find_idx(&self.chans, |c| c.name == *chan) {
             None => {
@@ -700,6 +2174,118 @@ impl StateInner {
         }
     }
 
+    fn is_nick_away(&self, nick: &str) -> bool {
+        self.chans.iter().any(|chan| chan.away_nicks.contains(nick))
+    }
+
+    fn get_account(&self, nick: &str) -> Option<String> {
+        self.chans
+            .iter()
+            .find_map(|chan| chan.accounts.get(nick))
+            .cloned()
+    }
+
+    fn get_user_host(&self, nick: &str) -> Option<String> {
+        self.chans
+            .iter()
+            .find_map(|chan| chan.user_hosts.get(nick))
+            .cloned()
+    }
+
+    fn get_realname(&self, nick: &str) -> Option<String> {
+        self.chans
+            .iter()
+            .find_map(|chan| chan.realnames.get(nick))
+            .cloned()
+    }
+
+    /// Whether the message is a reply to a WHO we sent after joining a channel, or to a MODE
+    /// query we sent after joining a channel or with `Client::refresh_chan_modes`. Echoes of
+    /// NickServ identification messages we sent (with `echo-message`) are also hidden, as they
+    /// have the password.
+    fn is_auto_reply(&self, msg: &Msg) -> bool {
+        match &msg.cmd {
+            wire::Cmd::PRIVMSG {
+                target: wire::MsgTarget::User(target),
+                msg: text,
+                is_notice: false,
+                ..
+            } if target.eq_ignore_ascii_case(&self.nickserv) => {
+                matches!(&msg.pfx, Some(Pfx::User { nick, .. } | Pfx::Ambiguous(nick))
+                    if self.is_current_nick(nick))
+                    && text
+                        .get(..9)
+                        .is_some_and(|cmd| cmd.eq_ignore_ascii_case("identify "))
+            }
+            wire::Cmd::Reply {
+                num: 352 | 315,
+                params,
+            } if params.len() > 1 => {
+                let chan = ChanNameRef::new(&params[1]);
+                self.chans.iter().any(|c| &c.name == chan && c.who_pending)
+            }
+            wire::Cmd::Reply { num: 354, params }
+                if params.len() > 2 && params[1] == AWAY_POLL_WHOX_TOKEN =>
+            {
+                let chan = ChanNameRef::new(&params[2]);
+                self.chans.iter().any(|c| &c.name == chan && c.who_pending)
+            }
+            wire::Cmd::Reply {
+                num: 324 | 329,
+                params,
+            } if params.len() > 1 => {
+                let chan = ChanNameRef::new(&params[1]);
+                self.chans
+                    .iter()
+                    .any(|c| &c.name == chan && c.modes_pending)
+            }
+            _ => false,
+        }
+    }
+
+    fn get_chan(&self, chan: &ChanNameRef) -> Option<&Chan> {
+        self.chans.iter().find(|c| c.name == *chan)
+    }
+
+    fn get_chan_mut(&mut self, chan: &ChanNameRef) -> Option<&mut Chan> {
+        self.chans.iter_mut().find(|c| c.name == *chan)
+    }
+
+    fn get_chan_users(&self, chan: &ChanNameRef) -> Vec<ChanUser> {
+        let chan = match utils::find_idx(&self.chans, |c| c.name == *chan) {
+            None => {
+                error!("Could not find channel index in get_chan_users.");
+                return vec![];
+            }
+            Some(chan_idx) => &self.chans[chan_idx],
+        };
+        let mut users: Vec<ChanUser> = chan
+            .nicks
+            .iter()
+            .map(|nick| {
+                let prefixes = chan.prefixes.get(nick).cloned().unwrap_or_default();
+                let first = prefixes.chars().next();
+                ChanUser {
+                    nick: nick.clone(),
+                    rank: first.and_then(|prefix| self.isupport.chan_modes.rank(prefix)),
+                    is_op: first.is_some_and(|prefix| self.isupport.chan_modes.is_op(prefix)),
+                    prefixes,
+                    user_host: chan.user_hosts.get(nick).cloned(),
+                }
+            })
+            .collect();
+        users.sort_unstable_by_key(|user| user.nick.to_lowercase());
+        users
+    }
+
+    fn get_joined_chans(&self) -> Vec<ChanName> {
+        self.chans
+            .iter()
+            .filter(|chan| matches!(chan.join_state, JoinState::Joined))
+            .map(|chan| chan.name.clone())
+            .collect()
+    }
+
     /// If channel is in Joining state cancel Joining task, otherwise sent part message
     fn leave_channel(
         &mut self,
@@ -713,6 +2299,8 @@ impl StateInner {
                 JoinState::Joining { stop_task, .. } => {
                     debug!("Aborting task to retry joining {}", chan.display());
                     let _ = stop_task.try_send(());
+                    // We won't get a PART for the channel, remove the state here
+                    self.chans.remove(idx);
                 }
                 JoinState::Joined => msg_chan
                     .try_send(Cmd::Msg(wire::part(chan, reason)))
@@ -721,6 +2309,22 @@ impl StateInner {
         }
     }
 
+    /// Prepare to rejoin a channel we were kicked from. The channel is in joining state until the
+    /// JOIN is sent, so leaving the channel (or a reconnect) cancels it. Returns the channel key if
+    /// we know it, and the receiver to abort the join. Returns `None` when the channel is already
+    /// joined or being joined.
+    fn rejoin(&mut self, chan: &ChanNameRef) -> Option<(Option<String>, Receiver<()>)> {
+        let key = self.left_chan_keys.remove(chan);
+        if self.get_chan(chan).is_some() {
+            return None;
+        }
+        let (snd_abort, rcv_abort) = tokio::sync::mpsc::channel(1);
+        let mut chan_state = Chan::new(chan.to_owned());
+        chan_state.set_joining(snd_abort);
+        self.chans.push(chan_state);
+        Some((key, rcv_abort))
+    }
+
     /// Kills all tasks that are trying to join channels
     fn kill_join_tasks(&mut self) {
         for chan in &mut self.chans {
@@ -788,6 +2392,44 @@ fn parse_server_pfx(pfx: Option<&Pfx>) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{FloodProtection, IpPreference, ReconnectBackoff, SASLAuth};
+
+    /// `ServerInfo` of the tests. Tests override the fields they need.
+    fn test_server_info() -> ServerInfo {
+        ServerInfo {
+            addr: "irc.server".to_owned(),
+            port: 6667,
+            tls: false,
+            pass: None,
+            user: None,
+            realname: "tiny".to_owned(),
+            nicks: vec!["tiny".to_owned()],
+            auto_join: vec![],
+            nickserv_ident: None,
+            nickserv: None,
+            fallback_encoding: wire::FallbackEncoding::Replace,
+            encoding: wire::Encoding::Utf8,
+            sasl_auth: None,
+            sasl_fail: SASLFail::Continue,
+            client_cert: None,
+            ca_certs: None,
+            pinned_cert: None,
+            tls_verify_hostname: true,
+            socks5_proxy: None,
+            bind_address: None,
+            ip_preference: IpPreference::Auto,
+            connect_timeout: None,
+            tls_handshake_timeout: None,
+            registration_timeout: None,
+            ping_interval: None,
+            ping_timeout: None,
+            rejoin_on_reconnect: true,
+            join_history_lines: 50,
+            reconnect_backoff: ReconnectBackoff::default(),
+            flood_protection: FloodProtection::default(),
+            away_poll: None,
+        }
+    }
 
     #[test]
     fn test_parse_servername_1() {
@@ -824,6 +2466,1786 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ping_reply() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, mut rcv_ev) = tokio::sync::mpsc::channel(10);
+
+        let serv_token = state.add_ping(None);
+        let nick_token = state.add_ping(Some("osa1"));
+        assert_ne!(serv_token, nick_token);
+
+        // Replies from the wrong source are ignored
+        state.ping_reply(&nick_token, None, &mut snd_ev);
+        state.ping_reply(&serv_token, Some("osa1"), &mut snd_ev);
+        state.ping_reply(&nick_token, Some("foo"), &mut snd_ev);
+        assert!(rcv_ev.try_recv().is_err());
+
+        state.ping_reply(&nick_token, Some("OSA1"), &mut snd_ev);
+        assert!(matches!(
+            rcv_ev.try_recv(),
+            Ok(Event::PingReply { nick: Some(nick), .. }) if nick == "osa1"
+        ));
+        state.ping_reply(&serv_token, None, &mut snd_ev);
+        assert!(matches!(
+            rcv_ev.try_recv(),
+            Ok(Event::PingReply { nick: None, .. })
+        ));
+
+        // Pings are only replied once
+        state.ping_reply(&serv_token, None, &mut snd_ev);
+        assert!(rcv_ev.try_recv().is_err());
+        assert!(state.last_lag.is_some());
+    }
+
+    #[test]
+    fn test_lag_ping() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, mut rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let parse = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+        };
+
+        // Not sent before registration
+        state.send_ping(&mut snd_irc_msg);
+        assert!(rcv_irc_msg.try_recv().is_err());
+
+        let mut yourhost =
+            parse(":irc.server 002 tiny :Your host is irc.server, running version 1\r\n");
+        state.update(&mut yourhost, &mut snd_ev, &mut snd_irc_msg);
+        state.send_ping(&mut snd_irc_msg);
+        let ping = rcv_irc_msg.try_recv().unwrap();
+        let token = ping.strip_prefix("PING ").unwrap().trim_end();
+        assert_eq!(state.last_lag, None);
+
+        // Replies to other pings don't update the lag
+        let mut pong = parse(":irc.server PONG irc.server :other\r\n");
+        state.update(&mut pong, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(state.last_lag, None);
+
+        let mut pong = parse(&format!(":irc.server PONG irc.server :{token}\r\n"));
+        state.update(&mut pong, &mut snd_ev, &mut snd_irc_msg);
+        assert!(state.last_lag.is_some());
+        // Lag pings are not reported as `Event::PingReply`s
+        while let Ok(ev) = rcv_ev.try_recv() {
+            assert!(!matches!(ev, Event::PingReply { .. }));
+        }
+
+        state.reset();
+        assert_eq!(state.last_lag, None);
+    }
+
+    #[test]
+    fn test_caps() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut cap = |subcommand: &str, caps: &str| {
+            let mut msg = Msg {
+                tags: wire::Tags::default(),
+                pfx: None,
+                cmd: wire::Cmd::CAP {
+                    client: "*".to_owned(),
+                    subcommand: subcommand.to_owned(),
+                    params: caps.split(' ').map(str::to_owned).collect(),
+                    more: false,
+                },
+            };
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+
+        cap("LS", "multi-prefix away-notify ");
+        cap("ACK", "multi-prefix away-notify");
+        cap("ACK", "-away-notify");
+
+        assert_eq!(state.server_caps, vec!["multi-prefix", "away-notify"]);
+        assert_eq!(state.enabled_caps, vec!["multi-prefix"]);
+
+        state.reset();
+        assert!(state.server_caps.is_empty());
+        assert!(state.enabled_caps.is_empty());
+    }
+
+    #[test]
+    fn test_cap_ls_sts() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut handle = |state: &mut StateInner, s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap().unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+
+        handle(&mut state, ":srv CAP * LS :sts=port=6697 server-time\r\n");
+        assert_eq!(
+            state.sts,
+            Some(StsCap {
+                port: Some(6697),
+                duration: None,
+            })
+        );
+        assert_eq!(
+            rcv_irc_msg.try_recv().unwrap(),
+            wire::cap_req(&["server-time"])
+        );
+
+        // Multiline reply: capabilities are requested after the last line, capabilities with
+        // values are requested by name
+        state.reset();
+        handle(
+            &mut state,
+            ":srv CAP * LS * :multi-prefix sts=duration=300\r\n",
+        );
+        assert_eq!(state.sts, None);
+        assert!(rcv_irc_msg.try_recv().is_err());
+        handle(&mut state, ":srv CAP * LS :server-time=foo\r\n");
+        assert_eq!(
+            state.sts,
+            Some(StsCap {
+                port: None,
+                duration: Some(Duration::from_secs(300)),
+            })
+        );
+        assert_eq!(
+            rcv_irc_msg.try_recv().unwrap(),
+            wire::cap_req(&["server-time", "multi-prefix"])
+        );
+        assert_eq!(
+            state.server_caps,
+            vec!["multi-prefix", "sts=duration=300", "server-time=foo"]
+        );
+    }
+
+    #[test]
+    fn test_cap_new_del() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut cap = |state: &mut StateInner, subcommand: &str, caps: &str| {
+            let mut msg = Msg {
+                tags: wire::Tags::default(),
+                pfx: None,
+                cmd: wire::Cmd::CAP {
+                    client: "tiny".to_owned(),
+                    subcommand: subcommand.to_owned(),
+                    params: caps.split(' ').map(str::to_owned).collect(),
+                    more: false,
+                },
+            };
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+
+        state.server_caps.push("multi-prefix".to_owned());
+        state.enabled_caps.push("multi-prefix".to_owned());
+        state.nick_accepted = true;
+
+        // Only supported capabilities that are not enabled yet are requested
+        cap(&mut state, "NEW", "away-notify foo multi-prefix");
+        assert_eq!(
+            rcv_irc_msg.try_recv().unwrap(),
+            wire::cap_req(&["away-notify"])
+        );
+        assert_eq!(
+            state.server_caps,
+            vec!["multi-prefix", "away-notify", "foo"]
+        );
+
+        // No CAP END after registration
+        cap(&mut state, "ACK", "away-notify");
+        assert!(rcv_irc_msg.try_recv().is_err());
+        assert!(state.is_cap_enabled("away-notify"));
+
+        cap(&mut state, "NEW", "bar");
+        assert!(rcv_irc_msg.try_recv().is_err());
+
+        cap(&mut state, "DEL", "away-notify foo");
+        assert!(rcv_irc_msg.try_recv().is_err());
+        assert_eq!(state.server_caps, vec!["multi-prefix", "bar"]);
+        assert_eq!(state.enabled_caps, vec!["multi-prefix"]);
+        assert!(!state.is_cap_enabled("away-notify"));
+    }
+
+    #[test]
+    fn test_away_notify() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let user = |nick: &str| {
+            Some(Pfx::User {
+                nick: nick.to_owned(),
+                user: "~user@host".to_owned(),
+            })
+        };
+        let msg = |pfx: Option<Pfx>, cmd: wire::Cmd| Msg {
+            tags: wire::Tags::default(),
+            pfx,
+            cmd,
+        };
+        let reply = |num: u16, params: &[&str]| {
+            msg(
+                Some(Pfx::Server("irc.server".to_owned())),
+                wire::Cmd::Reply {
+                    num,
+                    params: params.iter().map(|s| (*s).to_owned()).collect(),
+                },
+            )
+        };
+        let away = |nick: &str, away_msg: Option<&str>| {
+            msg(
+                user(nick),
+                wire::Cmd::AWAY {
+                    msg: away_msg.map(str::to_owned),
+                    chans: vec![],
+                },
+            )
+        };
+        let chan = ChanName::new("#chan".to_owned());
+
+        let mut ack = msg(
+            None,
+            wire::Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "ACK".to_owned(),
+                params: vec!["away-notify".to_owned()],
+                more: false,
+            },
+        );
+        state.update(&mut ack, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::cap_end());
+
+        // Joining a channel sends a WHO to get the away status of the users
+        let mut join = msg(
+            user("tiny"),
+            wire::Cmd::JOIN {
+                chan: chan.clone(),
+                account: None,
+            },
+        );
+        state.update(&mut join, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::who("#chan"));
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::chan_modes("#chan"));
+        let mut names = reply(353, &["tiny", "=", "#chan", "tiny @alice bob"]);
+        state.update(&mut names, &mut snd_ev, &mut snd_irc_msg);
+
+        for (nick, flags) in [("alice", "H@"), ("bob", "G")] {
+            let mut who = reply(
+                352,
+                &[
+                    "tiny",
+                    "#chan",
+                    "~u",
+                    "host",
+                    "irc.server",
+                    nick,
+                    flags,
+                    "0 real",
+                ],
+            );
+            assert!(state.is_auto_reply(&who));
+            state.update(&mut who, &mut snd_ev, &mut snd_irc_msg);
+        }
+        let mut end_of_who = reply(315, &["tiny", "#chan", "End of /WHO list."]);
+        assert!(state.is_auto_reply(&end_of_who));
+        state.update(&mut end_of_who, &mut snd_ev, &mut snd_irc_msg);
+        assert!(!state.is_auto_reply(&end_of_who));
+        assert!(!state.is_nick_away("alice"));
+        assert!(state.is_nick_away("bob"));
+
+        let mut alice_away = away("alice", Some("lunch"));
+        state.update(&mut alice_away, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(
+            alice_away.cmd,
+            wire::Cmd::AWAY {
+                msg: Some("lunch".to_owned()),
+                chans: vec![chan.clone()],
+            }
+        );
+        state.update(&mut away("bob", None), &mut snd_ev, &mut snd_irc_msg);
+        assert!(state.is_nick_away("alice"));
+        assert!(!state.is_nick_away("bob"));
+
+        // Nick changes keep the away status, parting clears it
+        let mut nick = msg(
+            user("alice"),
+            wire::Cmd::NICK {
+                nick: "alice_".to_owned(),
+                chans: vec![],
+            },
+        );
+        state.update(&mut nick, &mut snd_ev, &mut snd_irc_msg);
+        assert!(!state.is_nick_away("alice"));
+        assert!(state.is_nick_away("alice_"));
+        let mut part = msg(
+            user("alice_"),
+            wire::Cmd::PART {
+                chan: chan.clone(),
+                msg: None,
+            },
+        );
+        state.update(&mut part, &mut snd_ev, &mut snd_irc_msg);
+        assert!(!state.is_nick_away("alice_"));
+
+        // Away status of users not in our channels is not tracked
+        state.update(
+            &mut away("carol", Some("zzz")),
+            &mut snd_ev,
+            &mut snd_irc_msg,
+        );
+        assert!(!state.is_nick_away("carol"));
+        assert!(rcv_irc_msg.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_away_poll() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let user = |nick: &str| {
+            Some(Pfx::User {
+                nick: nick.to_owned(),
+                user: "~user@host".to_owned(),
+            })
+        };
+        let msg = |pfx: Option<Pfx>, cmd: wire::Cmd| Msg {
+            tags: wire::Tags::default(),
+            pfx,
+            cmd,
+        };
+        let reply = |num: u16, params: &[&str]| {
+            msg(
+                Some(Pfx::Server("irc.server".to_owned())),
+                wire::Cmd::Reply {
+                    num,
+                    params: params.iter().map(|s| (*s).to_owned()).collect(),
+                },
+            )
+        };
+
+        // No polling before joining channels
+        state.poll_away(100, &mut snd_irc_msg);
+        assert!(rcv_irc_msg.try_recv().is_err());
+
+        let mut join = msg(
+            user("tiny"),
+            wire::Cmd::JOIN {
+                chan: ChanName::new("#chan".to_owned()),
+                account: None,
+            },
+        );
+        state.update(&mut join, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::chan_modes("#chan"));
+        let mut names = reply(353, &["tiny", "=", "#chan", "tiny @alice bob"]);
+        state.update(&mut names, &mut snd_ev, &mut snd_irc_msg);
+
+        state.poll_away(100, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::who("#chan"));
+        // Not polled again until the WHO is done
+        state.poll_away(100, &mut snd_irc_msg);
+        assert!(rcv_irc_msg.try_recv().is_err());
+        for (nick, flags) in [("alice", "H@"), ("bob", "G")] {
+            let mut who = reply(
+                352,
+                &[
+                    "tiny",
+                    "#chan",
+                    "~u",
+                    "host",
+                    "irc.server",
+                    nick,
+                    flags,
+                    "0 real",
+                ],
+            );
+            assert!(state.is_auto_reply(&who));
+            state.update(&mut who, &mut snd_ev, &mut snd_irc_msg);
+        }
+        let mut end_of_who = reply(315, &["tiny", "#chan", "End of /WHO list."]);
+        assert!(state.is_auto_reply(&end_of_who));
+        state.update(&mut end_of_who, &mut snd_ev, &mut snd_irc_msg);
+        assert!(state.is_nick_away("bob"));
+        assert_eq!(
+            std::mem::take(&mut state.away_changes),
+            vec![("bob".to_owned(), true)]
+        );
+
+        // Channels over the size limit are not polled, and polling pauses when lagging
+        state.poll_away(2, &mut snd_irc_msg);
+        assert!(rcv_irc_msg.try_recv().is_err());
+        state.last_lag = Some(MAX_AWAY_POLL_LAG * 2);
+        state.poll_away(100, &mut snd_irc_msg);
+        assert!(rcv_irc_msg.try_recv().is_err());
+        state.last_lag = None;
+
+        // With WHOX only the fields needed are requested
+        state.isupport.update("WHOX");
+        state.poll_away(100, &mut snd_irc_msg);
+        assert_eq!(
+            rcv_irc_msg.try_recv().unwrap(),
+            wire::whox("#chan", "tcnf", AWAY_POLL_WHOX_TOKEN)
+        );
+        let mut whox = reply(354, &["tiny", AWAY_POLL_WHOX_TOKEN, "#chan", "bob", "H"]);
+        assert!(state.is_auto_reply(&whox));
+        state.update(&mut whox, &mut snd_ev, &mut snd_irc_msg);
+        state.update(&mut end_of_who, &mut snd_ev, &mut snd_irc_msg);
+        assert!(!state.is_nick_away("bob"));
+        assert_eq!(state.away_changes, vec![("bob".to_owned(), false)]);
+        assert_eq!(
+            state.away_poll_delay(Duration::from_secs(60)),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn test_chghost() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let user = |nick: &str| {
+            Some(Pfx::User {
+                nick: nick.to_owned(),
+                user: format!("~{nick}@host"),
+            })
+        };
+        let msg = |pfx: Option<Pfx>, cmd: wire::Cmd| Msg {
+            tags: wire::Tags::default(),
+            pfx,
+            cmd,
+        };
+        let chghost = |nick: &str, host: &str| {
+            msg(
+                user(nick),
+                wire::Cmd::CHGHOST {
+                    user: nick.to_owned(),
+                    host: host.to_owned(),
+                    chans: vec![],
+                },
+            )
+        };
+        let chan = ChanName::new("#chan".to_owned());
+
+        let mut ack = msg(
+            None,
+            wire::Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "ACK".to_owned(),
+                params: vec!["chghost".to_owned()],
+                more: false,
+            },
+        );
+        state.update(&mut ack, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::cap_end());
+
+        // Joining a channel sends a WHO to get the hosts of the users
+        let join = |nick: &str| {
+            msg(
+                user(nick),
+                wire::Cmd::JOIN {
+                    chan: chan.clone(),
+                    account: None,
+                },
+            )
+        };
+        state.update(&mut join("tiny"), &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::who("#chan"));
+        let mut names = msg(
+            Some(Pfx::Server("irc.server".to_owned())),
+            wire::Cmd::Reply {
+                num: 353,
+                params: vec![
+                    "tiny".to_owned(),
+                    "=".to_owned(),
+                    "#chan".to_owned(),
+                    "tiny alice".to_owned(),
+                ],
+            },
+        );
+        state.update(&mut names, &mut snd_ev, &mut snd_irc_msg);
+        let mut who = msg(
+            Some(Pfx::Server("irc.server".to_owned())),
+            wire::Cmd::Reply {
+                num: 352,
+                params: [
+                    "tiny",
+                    "#chan",
+                    "~a",
+                    "1.2.3.4",
+                    "irc.server",
+                    "alice",
+                    "H",
+                    "0 a",
+                ]
+                .iter()
+                .map(|s| (*s).to_owned())
+                .collect(),
+            },
+        );
+        state.update(&mut who, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(state.get_user_host("alice").as_deref(), Some("~a@1.2.3.4"));
+
+        // Hosts of users joining later are in the JOIN prefix
+        state.update(&mut join("bob"), &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(state.get_user_host("bob").as_deref(), Some("~bob@host"));
+
+        let mut alice_chghost = chghost("alice", "cloak/alice");
+        state.update(&mut alice_chghost, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(
+            alice_chghost.cmd,
+            wire::Cmd::CHGHOST {
+                user: "alice".to_owned(),
+                host: "cloak/alice".to_owned(),
+                chans: vec![chan.clone()],
+            }
+        );
+        assert_eq!(
+            state.get_user_host("alice").as_deref(),
+            Some("alice@cloak/alice")
+        );
+
+        // Nick changes keep the host
+        let mut nick = msg(
+            user("alice"),
+            wire::Cmd::NICK {
+                nick: "alice_".to_owned(),
+                chans: vec![],
+            },
+        );
+        state.update(&mut nick, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(state.get_user_host("alice"), None);
+        assert_eq!(
+            state.get_user_host("alice_").as_deref(),
+            Some("alice@cloak/alice")
+        );
+
+        // Our own host changes update the usermask
+        state.update(
+            &mut chghost("tiny", "cloak/tiny"),
+            &mut snd_ev,
+            &mut snd_irc_msg,
+        );
+        assert_eq!(state.usermask.as_deref(), Some("tiny!tiny@cloak/tiny"));
+    }
+
+    #[test]
+    fn test_forced_nick_change() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, mut rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut update = |state: &mut StateInner, s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap().unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+
+        update(
+            &mut state,
+            ":irc.server 001 tiny :Welcome to the network tiny!~tiny@1.2.3.4\r\n",
+        );
+        update(&mut state, ":bob!~bob@host NICK bob_\r\n");
+        assert_eq!(state.current_nick, "tiny");
+
+        // Nick changed by services, without a `/nick`
+        update(&mut state, ":tiny!~tiny@1.2.3.4 NICK Guest123\r\n");
+        assert_eq!(state.current_nick, "Guest123");
+        assert_eq!(state.usermask.as_deref(), Some("Guest123!~tiny@1.2.3.4"));
+
+        let mut nick_changes = vec![];
+        while let Ok(ev) = rcv_ev.try_recv() {
+            if let Event::NickChange { new_nick } = ev {
+                nick_changes.push(new_nick);
+            }
+        }
+        assert_eq!(nick_changes, vec!["tiny".to_owned(), "Guest123".to_owned()]);
+    }
+
+    #[test]
+    fn test_usermask() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut update = |state: &mut StateInner, s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap().unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+
+        // Welcome messages without a usermask
+        update(
+            &mut state,
+            ":irc.server 001 tiny :Welcome to the network tiny\r\n",
+        );
+        assert_eq!(state.usermask, None);
+
+        update(
+            &mut state,
+            ":irc.server 001 tiny :Welcome to the network tiny!~tiny@1.2.3.4\r\n",
+        );
+        assert_eq!(state.usermask.as_deref(), Some("tiny!~tiny@1.2.3.4"));
+
+        // WHOIS replies of other users are ignored
+        update(&mut state, ":irc.server 311 tiny bob ~bob host :Bob\r\n");
+        assert_eq!(state.usermask.as_deref(), Some("tiny!~tiny@1.2.3.4"));
+        update(
+            &mut state,
+            ":irc.server 311 tiny tiny ~tiny cloak/tiny :tiny\r\n",
+        );
+        assert_eq!(state.usermask.as_deref(), Some("tiny!~tiny@cloak/tiny"));
+
+        // Messages echoed with `echo-message`
+        update(&mut state, ":tiny!tiny@vhost PRIVMSG #chan :hi\r\n");
+        assert_eq!(state.usermask.as_deref(), Some("tiny!tiny@vhost"));
+    }
+
+    #[test]
+    fn test_setname() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let msg = |nick: &str, cmd: wire::Cmd| Msg {
+            tags: wire::Tags::default(),
+            pfx: Some(Pfx::User {
+                nick: nick.to_owned(),
+                user: format!("~{nick}@host"),
+            }),
+            cmd,
+        };
+        let setname = |nick: &str, realname: &str| {
+            msg(
+                nick,
+                wire::Cmd::SETNAME {
+                    realname: realname.to_owned(),
+                    chans: vec![],
+                },
+            )
+        };
+        let chan = ChanName::new("#chan".to_owned());
+
+        state.enabled_caps.push("setname".to_owned());
+        let join = |nick: &str| {
+            msg(
+                nick,
+                wire::Cmd::JOIN {
+                    chan: chan.clone(),
+                    account: None,
+                },
+            )
+        };
+        state.update(&mut join("tiny"), &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::who("#chan"));
+        state.update(&mut join("alice"), &mut snd_ev, &mut snd_irc_msg);
+
+        // Real names are learned from WHO replies
+        let mut who = Msg {
+            tags: wire::Tags::default(),
+            pfx: Some(Pfx::Server("irc.server".to_owned())),
+            cmd: wire::Cmd::Reply {
+                num: 352,
+                params: [
+                    "tiny",
+                    "#chan",
+                    "~alice",
+                    "host",
+                    "irc.server",
+                    "alice",
+                    "H",
+                    "0 Alice A.",
+                ]
+                .iter()
+                .map(|s| (*s).to_owned())
+                .collect(),
+            },
+        };
+        state.update(&mut who, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(state.get_realname("alice").as_deref(), Some("Alice A."));
+
+        let mut alice_setname = setname("alice", "Alice B.");
+        state.update(&mut alice_setname, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(
+            alice_setname.cmd,
+            wire::Cmd::SETNAME {
+                realname: "Alice B.".to_owned(),
+                chans: vec![chan.clone()],
+            }
+        );
+        assert_eq!(state.get_realname("alice").as_deref(), Some("Alice B."));
+        assert_eq!(state.get_realname("bob"), None);
+
+        // Our own real name is used in the next connection registration
+        state.update(
+            &mut setname("tiny", "tiny client"),
+            &mut snd_ev,
+            &mut snd_irc_msg,
+        );
+        assert_eq!(state.server_info.realname, "tiny client");
+    }
+
+    #[test]
+    fn test_accounts() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut update = |state: &mut StateInner, nick: &str, tags: &str, cmd: wire::Cmd| {
+            let mut msg = Msg {
+                tags: wire::Tags::parse(tags),
+                pfx: Some(Pfx::User {
+                    nick: nick.to_owned(),
+                    user: "~user@host".to_owned(),
+                }),
+                cmd,
+            };
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+        let join = |account: Option<&str>| wire::Cmd::JOIN {
+            chan: ChanName::new("#chan".to_owned()),
+            account: account.map(str::to_owned),
+        };
+        let account = |account: Option<&str>| wire::Cmd::ACCOUNT {
+            account: account.map(str::to_owned),
+        };
+        state.enabled_caps = vec![
+            "account-notify".to_owned(),
+            "account-tag".to_owned(),
+            "extended-join".to_owned(),
+        ];
+
+        update(&mut state, "tiny", "", join(Some("tiny")));
+        update(&mut state, "alice", "", join(Some("alice_acct")));
+        update(&mut state, "bob", "", join(None));
+        assert_eq!(state.get_account("alice"), Some("alice_acct".to_owned()));
+        assert_eq!(state.get_account("bob"), None);
+
+        // account-notify
+        update(&mut state, "bob", "", account(Some("bob_acct")));
+        assert_eq!(state.get_account("bob"), Some("bob_acct".to_owned()));
+        update(&mut state, "alice", "", account(None));
+        assert_eq!(state.get_account("alice"), None);
+
+        // account-tag, no tag means the user is not logged in
+        let privmsg = || wire::Cmd::PRIVMSG {
+            target: wire::MsgTarget::Chan(ChanName::new("#chan".to_owned())),
+            msg: "hi".to_owned(),
+            is_notice: false,
+            ctcp: None,
+        };
+        update(&mut state, "alice", "account=alice2", privmsg());
+        assert_eq!(state.get_account("alice"), Some("alice2".to_owned()));
+        update(&mut state, "alice", "", privmsg());
+        assert_eq!(state.get_account("alice"), None);
+
+        // Nick changes keep the account, quitting clears it
+        update(
+            &mut state,
+            "bob",
+            "account=bob_acct",
+            wire::Cmd::NICK {
+                nick: "bob_".to_owned(),
+                chans: vec![],
+            },
+        );
+        assert_eq!(state.get_account("bob"), None);
+        assert_eq!(state.get_account("bob_"), Some("bob_acct".to_owned()));
+        update(
+            &mut state,
+            "bob_",
+            "account=bob_acct",
+            wire::Cmd::QUIT {
+                msg: None,
+                chans: vec![],
+            },
+        );
+        assert_eq!(state.get_account("bob_"), None);
+    }
+
+    #[test]
+    fn test_chan_user_prefixes() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut update = |state: &mut StateInner, nick: Option<&str>, cmd: wire::Cmd| {
+            let mut msg = Msg {
+                tags: wire::Tags::default(),
+                pfx: Some(match nick {
+                    Some(nick) => Pfx::User {
+                        nick: nick.to_owned(),
+                        user: "~user@host".to_owned(),
+                    },
+                    None => Pfx::Server("irc.server".to_owned()),
+                }),
+                cmd,
+            };
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+        let params = |params: &[&str]| params.iter().map(|s| (*s).to_owned()).collect();
+        let chan = ChanName::new("#chan".to_owned());
+        let users = |state: &StateInner| -> Vec<(String, String, Option<usize>, bool)> {
+            state
+                .get_chan_users(&chan)
+                .into_iter()
+                .map(|user| (user.prefixes, user.nick, user.rank, user.is_op))
+                .collect()
+        };
+        let user_hosts = |state: &StateInner| -> Vec<Option<String>> {
+            state
+                .get_chan_users(&chan)
+                .into_iter()
+                .map(|user| user.user_host)
+                .collect()
+        };
+        let user = |prefixes: &str, nick: &str, rank: Option<usize>, is_op: bool| {
+            (prefixes.to_owned(), nick.to_owned(), rank, is_op)
+        };
+
+        update(
+            &mut state,
+            None,
+            wire::Cmd::Reply {
+                num: 5,
+                params: params(&["tiny", "PREFIX=(ohv)@%+", "are supported by this server"]),
+            },
+        );
+        update(
+            &mut state,
+            Some("tiny"),
+            wire::Cmd::JOIN {
+                chan: chan.clone(),
+                account: None,
+            },
+        );
+        update(
+            &mut state,
+            None,
+            wire::Cmd::Reply {
+                num: 353,
+                params: params(&[
+                    "tiny",
+                    "=",
+                    "#chan",
+                    "tiny @+alice!~a@host +bob carol!carol@cloak/carol",
+                ]),
+            },
+        );
+        assert_eq!(
+            users(&state),
+            vec![
+                user("@+", "alice", Some(0), true),
+                user("+", "bob", Some(2), false),
+                user("", "carol", None, false),
+                user("", "tiny", None, false),
+            ]
+        );
+        assert_eq!(
+            user_hosts(&state),
+            vec![
+                Some("~a@host".to_owned()),
+                None,
+                Some("carol@cloak/carol".to_owned()),
+                None,
+            ]
+        );
+        assert_eq!(
+            state.get_chan_nicks(&chan),
+            vec!["alice", "bob", "carol", "tiny"]
+        );
+
+        let mode = |modes: &[&str]| wire::Cmd::Other {
+            cmd: "MODE".to_owned(),
+            params: params(modes),
+        };
+        update(
+            &mut state,
+            Some("alice"),
+            mode(&["#chan", "+h-o+b", "bob", "alice", "*!*@x"]),
+        );
+        update(&mut state, Some("alice"), mode(&["#chan", "+o", "carol"]));
+        update(
+            &mut state,
+            Some("carol"),
+            wire::Cmd::NICK {
+                nick: "carol_".to_owned(),
+                chans: vec![],
+            },
+        );
+        assert_eq!(
+            users(&state),
+            vec![
+                user("+", "alice", Some(2), false),
+                user("%+", "bob", Some(1), false),
+                user("@", "carol_", Some(0), true),
+                user("", "tiny", None, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cap_req() {
+        for sasl in [false, true] {
+            let server_info = ServerInfo {
+                sasl_auth: sasl.then(|| SASLAuth::Plain {
+                    username: "tiny".to_owned(),
+                    password: "hunter2".to_owned(),
+                }),
+                ..test_server_info()
+            };
+            let mut state = StateInner::new(server_info);
+            let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+            let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+            let mut cap = |subcommand: &str, caps: &str| {
+                let mut msg = Msg {
+                    tags: wire::Tags::default(),
+                    pfx: None,
+                    cmd: wire::Cmd::CAP {
+                        client: "*".to_owned(),
+                        subcommand: subcommand.to_owned(),
+                        params: caps.split(' ').map(str::to_owned).collect(),
+                        more: false,
+                    },
+                };
+                state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+            };
+
+            cap("LS", "multi-prefix sasl znc.in/self-message server-time");
+            if sasl {
+                assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::nick("tiny"));
+                assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::user("tiny", "tiny"));
+                assert_eq!(
+                    rcv_irc_msg.try_recv().unwrap(),
+                    wire::cap_req(&["server-time", "multi-prefix", "znc.in/self-message", "sasl"])
+                );
+                cap("ACK", "server-time multi-prefix znc.in/self-message sasl");
+                assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::authenticate("PLAIN"));
+            } else {
+                assert_eq!(
+                    rcv_irc_msg.try_recv().unwrap(),
+                    wire::cap_req(&["server-time", "multi-prefix", "znc.in/self-message"])
+                );
+                cap("ACK", "server-time multi-prefix znc.in/self-message");
+                assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::cap_end());
+            }
+            assert!(rcv_irc_msg.try_recv().is_err());
+        }
+    }
+
+    #[test]
+    fn test_echo_message() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let echo = |target: &str, msg: &str, label: Option<&str>| Msg {
+            tags: match label {
+                Some(label) => wire::Tags::parse(&format!("label={label}")),
+                None => wire::Tags::default(),
+            },
+            pfx: Some(Pfx::User {
+                nick: "tiny".to_owned(),
+                user: "tiny@host".to_owned(),
+            }),
+            cmd: wire::Cmd::PRIVMSG {
+                target: wire::MsgTarget::Chan(ChanName::new(target.to_owned())),
+                msg: msg.to_owned(),
+                is_notice: false,
+                ctcp: None,
+            },
+        };
+
+        // Messages are not tracked without echo-message
+        assert_eq!(state.add_pending_msg("#chan", "hi"), None);
+        assert!(state.pending_msgs.is_empty());
+
+        state.enabled_caps.push("echo-message".to_owned());
+
+        // First message is not echoed, second one is truncated
+        assert_eq!(state.add_pending_msg("#chan", "not sent"), None);
+        assert_eq!(state.add_pending_msg("#chan", "hello world"), None);
+        assert_eq!(state.add_pending_msg("#chan", "bye"), None);
+        assert!(matches!(
+            state.match_echo(&echo("#chan", "hello", None)),
+            Some(Event::MsgModified { target, msg }) if target == "#chan" && msg == "hello world"
+        ));
+        assert!(state.match_echo(&echo("#chan", "bye", None)).is_none());
+        assert!(state.pending_msgs.is_empty());
+
+        // With labeled-response errors are reported
+        state.enabled_caps.push("labeled-response".to_owned());
+        assert_eq!(
+            state.add_pending_msg("#chan", "hi"),
+            Some("tiny0".to_owned())
+        );
+        assert_eq!(
+            state.add_pending_msg("#chan", "hi again"),
+            Some("tiny1".to_owned())
+        );
+        let err = Msg {
+            tags: wire::Tags::parse("label=tiny0"),
+            pfx: Some(Pfx::Server("irc.server".to_owned())),
+            cmd: wire::Cmd::Reply {
+                num: 404,
+                params: vec![
+                    "tiny".to_owned(),
+                    "#chan".to_owned(),
+                    "Cannot send to channel".to_owned(),
+                ],
+            },
+        };
+        assert!(matches!(
+            state.match_echo(&err),
+            Some(Event::MsgNotSent { target, msg, reason })
+                if target == "#chan" && msg == "hi" && reason == "Cannot send to channel"
+        ));
+        assert!(
+            state
+                .match_echo(&echo("#chan", "hi again", Some("tiny1")))
+                .is_none()
+        );
+        assert!(state.pending_msgs.is_empty());
+    }
+
+    #[test]
+    fn test_sasl_fail() {
+        for sasl_fail in [SASLFail::Continue, SASLFail::Disconnect] {
+            let server_info = ServerInfo {
+                sasl_auth: Some(SASLAuth::Plain {
+                    username: "tiny".to_owned(),
+                    password: "hunter2".to_owned(),
+                }),
+                sasl_fail,
+                ..test_server_info()
+            };
+            let mut state = StateInner::new(server_info);
+            let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+            let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+
+            let mut msg = Msg {
+                tags: wire::Tags::default(),
+                pfx: None,
+                cmd: wire::Cmd::Reply {
+                    num: 904,
+                    params: vec!["tiny".to_owned(), "SASL authentication failed".to_owned()],
+                },
+            };
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+            assert!(state.sasl_failed);
+
+            // Registration continues only with `SASLFail::Continue`
+            match sasl_fail {
+                SASLFail::Continue => assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::cap_end()),
+                SASLFail::Disconnect => assert!(rcv_irc_msg.try_recv().is_err()),
+            }
+
+            state.reset();
+            assert!(!state.sasl_failed);
+        }
+    }
+
+    #[test]
+    fn test_rejoin_on_reconnect() {
+        for rejoin_on_reconnect in [true, false] {
+            let server_info = ServerInfo {
+                auto_join: vec![ChanName::new("#auto".to_owned())],
+                rejoin_on_reconnect,
+                ..test_server_info()
+            };
+            let mut state = StateInner::new(server_info);
+            let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+            let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+
+            let mut join = Msg {
+                tags: wire::Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "tiny".to_owned(),
+                    user: "~tiny@host".to_owned(),
+                }),
+                cmd: wire::Cmd::JOIN {
+                    chan: ChanName::new("#joined".to_owned()),
+                    account: None,
+                },
+            };
+            state.update(&mut join, &mut snd_ev, &mut snd_irc_msg);
+            assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::chan_modes("#joined"));
+
+            state.reset();
+            let mut end_of_motd = Msg {
+                tags: wire::Tags::default(),
+                pfx: None,
+                cmd: wire::Cmd::Reply {
+                    num: 376,
+                    params: vec!["tiny".to_owned(), "End of /MOTD command.".to_owned()],
+                },
+            };
+            state.update(&mut end_of_motd, &mut snd_ev, &mut snd_irc_msg);
+            let expected = if rejoin_on_reconnect {
+                "JOIN #auto,#joined\r\n"
+            } else {
+                "JOIN #auto\r\n"
+            };
+            assert_eq!(rcv_irc_msg.try_recv().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_typing() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let now = Instant::now();
+        let secs = |n: u64| now + Duration::from_secs(n);
+
+        // Not sent without `message-tags`
+        assert_eq!(state.typing("#chan", TypingState::Active, now), None);
+        state.enabled_caps.push("message-tags".to_owned());
+
+        let active = Some(wire::typing("#chan", TypingState::Active));
+        assert_eq!(state.typing("#chan", TypingState::Active, now), active);
+        assert_eq!(state.typing("#CHAN", TypingState::Active, secs(1)), None);
+        assert_eq!(state.typing("#chan", TypingState::Active, secs(3)), active);
+        assert_eq!(
+            state.typing("#chan", TypingState::Paused, secs(8)),
+            Some(wire::typing("#chan", TypingState::Paused))
+        );
+        assert_eq!(state.typing("#chan", TypingState::Paused, secs(9)), None);
+        assert_eq!(
+            state.typing("#chan", TypingState::Done, secs(10)),
+            Some(wire::typing("#chan", TypingState::Done))
+        );
+        // Nothing to end
+        assert_eq!(state.typing("#chan", TypingState::Done, secs(11)), None);
+
+        // Sending a message ends typing
+        assert_eq!(state.typing("#chan", TypingState::Active, secs(12)), active);
+        state.reset_typing("#chan");
+        assert_eq!(state.typing("#chan", TypingState::Done, secs(13)), None);
+    }
+
+    #[test]
+    fn test_refresh_names() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let parse = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+        };
+        let chan = ChanNameRef::new("#chan");
+
+        for msg in [
+            ":tiny!~tiny@host JOIN #chan\r\n",
+            ":irc.server 353 tiny = #chan :tiny @op gone\r\n",
+            ":irc.server 366 tiny #chan :End of /NAMES list.\r\n",
+        ] {
+            state.update(&mut parse(msg), &mut snd_ev, &mut snd_irc_msg);
+        }
+        assert_eq!(state.get_chan_nicks(chan), vec!["gone", "op", "tiny"]);
+
+        // Nicks are cleared until the server replies
+        assert_eq!(state.refresh_names(chan), vec![wire::names("#chan")]);
+        assert!(state.get_chan_nicks(chan).is_empty());
+        state.update(
+            &mut parse(":irc.server 353 tiny = #chan :tiny @op\r\n"),
+            &mut snd_ev,
+            &mut snd_irc_msg,
+        );
+        assert_eq!(state.get_chan_nicks(chan), vec!["op", "tiny"]);
+
+        assert!(state.refresh_names(ChanNameRef::new("#other")).is_empty());
+    }
+
+    #[test]
+    fn test_topic() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let parse = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+        };
+        let chan = ChanNameRef::new("#chan");
+
+        for msg in [
+            ":tiny!~tiny@host JOIN #chan\r\n",
+            ":irc.server 332 tiny #chan :hello world\r\n",
+            ":irc.server 333 tiny #chan op!~op@host 1700000000\r\n",
+            ":irc.server 329 tiny #chan 1600000000\r\n",
+        ] {
+            state.update(&mut parse(msg), &mut snd_ev, &mut snd_irc_msg);
+        }
+        let chan_state = state.get_chan(chan).unwrap();
+        assert_eq!(
+            chan_state.topic,
+            Some(ChanTopic {
+                topic: "hello world".to_owned(),
+                set_by: Some("op!~op@host".to_owned()),
+                set_at: Some(1700000000),
+            })
+        );
+        assert_eq!(chan_state.created, Some(1600000000));
+
+        state.update(
+            &mut parse(":op!~op@host TOPIC #chan :new topic\r\n"),
+            &mut snd_ev,
+            &mut snd_irc_msg,
+        );
+        let topic = state.get_chan(chan).unwrap().topic.clone().unwrap();
+        assert_eq!(topic.topic, "new topic");
+        assert_eq!(topic.set_by.as_deref(), Some("op"));
+        assert!(topic.set_at.is_some());
+
+        state.update(
+            &mut parse(":op!~op@host TOPIC #chan :\r\n"),
+            &mut snd_ev,
+            &mut snd_irc_msg,
+        );
+        assert_eq!(state.get_chan(chan).unwrap().topic, None);
+    }
+
+    #[test]
+    fn test_chathistory() {
+        let server_info = ServerInfo {
+            join_history_lines: 20,
+            ..test_server_info()
+        };
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, mut rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let parse = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+        };
+
+        let mut ack = parse(":irc.server CAP * ACK :batch draft/chathistory\r\n");
+        state.update(&mut ack, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::cap_end());
+
+        // Joining a channel requests the latest messages
+        let mut join = parse(":tiny!~tiny@host JOIN #chan\r\n");
+        state.update(&mut join, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::chan_modes("#chan"));
+        assert_eq!(
+            rcv_irc_msg.try_recv().unwrap(),
+            wire::chathistory_latest("#chan", 20)
+        );
+
+        let mut batch_start = parse(":irc.server BATCH +ref chathistory #chan\r\n");
+        state.update(&mut batch_start, &mut snd_ev, &mut snd_irc_msg);
+        for (time, text) in [
+            ("2025-01-01T10:00:00.000Z", "first"),
+            ("2025-01-01T10:01:00.000Z", "second"),
+        ] {
+            let msg = parse(&format!(
+                "@batch=ref;time={time} :alice!~alice@host PRIVMSG #chan :{text}\r\n"
+            ));
+            assert_eq!(state.add_history_msg(msg), None);
+        }
+        // Messages not in the batch are not buffered
+        let msg = parse("@batch=other :alice!~alice@host PRIVMSG #chan :hi\r\n");
+        assert!(state.add_history_msg(msg).is_some());
+        assert!(rcv_ev.try_recv().is_err());
+
+        let mut batch_end = parse(":irc.server BATCH -ref\r\n");
+        state.update(&mut batch_end, &mut snd_ev, &mut snd_irc_msg);
+        match rcv_ev.try_recv().unwrap() {
+            Event::History {
+                target,
+                msgs,
+                older,
+            } => {
+                assert_eq!(
+                    target,
+                    wire::MsgTarget::Chan(ChanName::new("#chan".to_owned()))
+                );
+                assert_eq!(msgs.len(), 2);
+                assert!(!older);
+            }
+            other => panic!("Unexpected event: {other:?}"),
+        }
+
+        // Further requests fetch messages older than the oldest one fetched
+        assert_eq!(
+            state.history_request("#chan", 10),
+            wire::chathistory_before("#chan", "2025-01-01T10:00:00.000Z", 10)
+        );
+
+        // History is not fetched again when rejoining
+        state.update(&mut join, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::chan_modes("#chan"));
+        assert!(rcv_irc_msg.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_kick_rejoin() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let (mut snd_cmd, mut rcv_cmd) = tokio::sync::mpsc::channel(10);
+        let mut update = |state: &mut StateInner, s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap().unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+        let chan = ChanNameRef::new("#chan");
+
+        update(&mut state, ":tiny!~tiny@host JOIN #chan\r\n");
+        update(&mut state, ":irc.server 324 tiny #chan +kn secret\r\n");
+        update(&mut state, ":op!~op@host KICK #chan tiny :bye\r\n");
+        assert!(state.get_chan(chan).is_none());
+
+        // The key is used when rejoining
+        let (key, mut rcv_abort) = state.rejoin(chan).unwrap();
+        assert_eq!(key.as_deref(), Some("secret"));
+        assert!(state.get_joined_chans().is_empty());
+        assert!(state.rejoin(chan).is_none());
+
+        // Leaving the channel cancels the rejoin
+        state.leave_channel(&mut snd_cmd, chan, None);
+        assert_eq!(rcv_abort.try_recv(), Ok(()));
+        assert!(rcv_cmd.try_recv().is_err());
+        assert!(state.get_chan(chan).is_none());
+
+        let (key, _rcv_abort) = state.rejoin(chan).unwrap();
+        assert_eq!(key, None);
+    }
+
+    #[test]
+    fn test_invites() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut update = |state: &mut StateInner, s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap().unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+        let invite = |chan: &str, from: &str| Invite {
+            chan: ChanName::new(chan.to_owned()),
+            from: from.to_owned(),
+        };
+        let invites = |state: &StateInner| -> Vec<Invite> {
+            state
+                .invites
+                .iter()
+                .map(|(invite, _)| invite.clone())
+                .collect()
+        };
+
+        update(&mut state, ":alice!~alice@host INVITE tiny :#a\r\n");
+        update(&mut state, ":bob!~bob@host INVITE tiny :#b\r\n");
+        // Invites of other users (with `invite-notify`) are not ours
+        update(&mut state, ":bob!~bob@host INVITE carol :#c\r\n");
+        // A new invite to the same channel replaces the old one
+        update(&mut state, ":carol!~carol@host INVITE Tiny :#a\r\n");
+        assert_eq!(
+            invites(&state),
+            vec![invite("#b", "bob"), invite("#a", "carol")]
+        );
+
+        update(&mut state, ":tiny!~tiny@host JOIN #b\r\n");
+        assert_eq!(invites(&state), vec![invite("#a", "carol")]);
+
+        state.reset();
+        assert!(state.invites.is_empty());
+    }
+
+    #[test]
+    fn test_part_rejoin() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let mut update = |state: &mut StateInner, s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            let mut msg = wire::parse_irc_msg(&mut buf).unwrap().unwrap();
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+        let chan = ChanNameRef::new("#chan");
+
+        update(&mut state, ":tiny!~tiny@host JOIN #chan\r\n");
+        update(&mut state, ":irc.server 324 tiny #chan +kn secret\r\n");
+        update(&mut state, ":tiny!~tiny@host PART #chan :brb\r\n");
+        assert!(state.get_chan(chan).is_none());
+        assert_eq!(
+            state.last_parted,
+            Some((chan.to_owned(), Some("brb".to_owned())))
+        );
+        assert_eq!(
+            state.left_chan_keys.get(chan).map(String::as_str),
+            Some("secret")
+        );
+
+        // Joining the channel again forgets the part
+        update(&mut state, ":tiny!~tiny@host JOIN #chan\r\n");
+        assert_eq!(state.last_parted, None);
+    }
+
+    #[test]
+    fn test_isupport_chan_types() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let parse = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+        };
+        let mut target = |state: &mut StateInner, s: &str| {
+            let mut msg = parse(s);
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+            match msg.cmd {
+                wire::Cmd::Reply { .. } => None,
+                wire::Cmd::PRIVMSG { target, .. } | wire::Cmd::TAGMSG { target } => Some(target),
+                cmd => panic!("Unexpected message: {cmd:?}"),
+            }
+        };
+
+        assert_eq!(
+            target(&mut state, ":alice!a@host PRIVMSG &chan :hi\r\n"),
+            Some(wire::MsgTarget::Chan(ChanName::new("&chan".to_owned())))
+        );
+
+        target(
+            &mut state,
+            ":server 005 tiny CHANTYPES=& NICKLEN=16 :are supported by this server\r\n",
+        );
+        assert_eq!(state.isupport.nick_len(), Some(16));
+        assert_eq!(
+            target(&mut state, ":alice!a@host PRIVMSG #chan :hi\r\n"),
+            Some(wire::MsgTarget::User("#chan".to_owned()))
+        );
+        assert_eq!(
+            target(&mut state, "@+typing=active :alice!a@host TAGMSG &chan\r\n"),
+            Some(wire::MsgTarget::Chan(ChanName::new("&chan".to_owned())))
+        );
+
+        // RPL_ISUPPORT is sent again after reconnecting
+        state.reset();
+        assert_eq!(state.isupport, ISupport::default());
+    }
+
+    #[test]
+    fn test_hide_identify_echo() {
+        let server_info = ServerInfo {
+            nickserv_ident: Some("hunter2".to_owned()),
+            nickserv: Some("AuthServ".to_owned()),
+            ..test_server_info()
+        };
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let parse = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+        };
+
+        let mut welcome = parse(":irc.server 001 tiny :Welcome\r\n");
+        state.update(&mut welcome, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(
+            rcv_irc_msg.try_recv().unwrap(),
+            wire::privmsg("AuthServ", "identify hunter2")
+        );
+
+        // Echoes of NickServ identification are not shown, to not show the password
+        let echo = parse(":tiny!~tiny@host PRIVMSG AuthServ :identify hunter2\r\n");
+        assert!(state.is_auto_reply(&echo));
+        let echo = parse(":tiny!~tiny@host PRIVMSG authserv :IDENTIFY tiny hunter2\r\n");
+        assert!(state.is_auto_reply(&echo));
+
+        let msg = parse(":tiny!~tiny@host PRIVMSG AuthServ :info tiny\r\n");
+        assert!(!state.is_auto_reply(&msg));
+        let msg = parse(":tiny!~tiny@host PRIVMSG NickServ :identify hunter2\r\n");
+        assert!(!state.is_auto_reply(&msg));
+        let msg = parse(":alice!~alice@host PRIVMSG AuthServ :identify hunter2\r\n");
+        assert!(!state.is_auto_reply(&msg));
+    }
+
+    #[test]
+    fn test_chan_modes() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, mut rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let parse = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+        };
+        let chan = ChanNameRef::new("#chan");
+
+        // Joining a channel requests the modes
+        let mut join = parse(":tiny!~tiny@host JOIN #chan\r\n");
+        state.update(&mut join, &mut snd_ev, &mut snd_irc_msg);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::chan_modes("#chan"));
+        assert_eq!(
+            state
+                .get_chan(chan)
+                .unwrap()
+                .modes
+                .as_ref()
+                .map(|m| m.to_string()),
+            None
+        );
+
+        // Replies are not shown
+        let mut modes = parse(":irc.server 324 tiny #chan +ntk secret\r\n");
+        assert!(state.is_auto_reply(&modes));
+        state.update(&mut modes, &mut snd_ev, &mut snd_irc_msg);
+        let mut created = parse(":irc.server 329 tiny #chan 1700000000\r\n");
+        assert!(state.is_auto_reply(&created));
+        state.update(&mut created, &mut snd_ev, &mut snd_irc_msg);
+        assert!(!state.is_auto_reply(&modes));
+
+        let mut mode = parse(":alice!~alice@host MODE #chan -k+ol secret bob 10\r\n");
+        state.update(&mut mode, &mut snd_ev, &mut snd_irc_msg);
+        // Membership changes don't change the modes
+        let mut op = parse(":alice!~alice@host MODE #chan +o carol\r\n");
+        state.update(&mut op, &mut snd_ev, &mut snd_irc_msg);
+
+        let mut events = vec![];
+        while let Ok(ev) = rcv_ev.try_recv() {
+            if let Event::ChanModes { chan, modes } = ev {
+                events.push((chan.display().to_owned(), modes));
+            }
+        }
+        assert_eq!(
+            events,
+            vec![
+                ("#chan".to_owned(), "+knt secret".to_owned()),
+                ("#chan".to_owned(), "+lnt 10".to_owned()),
+            ]
+        );
+        assert_eq!(
+            state
+                .get_chan(chan)
+                .unwrap()
+                .modes
+                .as_ref()
+                .map(|m| m.to_string()),
+            Some("+lnt 10".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_labeled_response() {
+        let server_info = test_server_info();
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, _rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+        let parse = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+        };
+        // Same as the main loop: messages not in replies update the state
+        let mut handle = |state: &mut StateInner, s: &str| -> bool {
+            match state.add_labeled_reply(parse(s)) {
+                Some(mut msg) => {
+                    state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+                    false
+                }
+                None => true,
+            }
+        };
+
+        // Not labeled without `labeled-response`
+        assert!(state.add_labeled_request().is_none());
+        state.enabled_caps.push("labeled-response".to_owned());
+
+        // Reply in a batch
+        let (label, mut whois) = state.add_labeled_request().unwrap();
+        assert!(!handle(
+            &mut state,
+            &format!("@label={label} :irc.server BATCH +b1 labeled-response\r\n")
+        ));
+        assert!(handle(
+            &mut state,
+            "@batch=b1 :irc.server 311 tiny alice ~alice host * :Alice\r\n"
+        ));
+        // Messages not in the batch are not replies
+        assert!(!handle(
+            &mut state,
+            ":alice!~alice@host PRIVMSG tiny :hi\r\n"
+        ));
+        assert!(handle(
+            &mut state,
+            "@batch=b1 :irc.server 318 tiny alice :End of /WHOIS list.\r\n"
+        ));
+        assert!(whois.try_recv().is_err());
+        assert!(!handle(&mut state, ":irc.server BATCH -b1\r\n"));
+        let msgs = whois.try_recv().unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert!(matches!(msgs[0].cmd, wire::Cmd::Reply { num: 311, .. }));
+
+        // Single message reply, and ACK
+        let (label1, mut reply1) = state.add_labeled_request().unwrap();
+        let (label2, mut reply2) = state.add_labeled_request().unwrap();
+        assert!(handle(
+            &mut state,
+            &format!("@label={label2} :irc.server ACK\r\n")
+        ));
+        assert!(handle(
+            &mut state,
+            &format!("@label={label1} :irc.server 401 tiny bob :No such nick\r\n")
+        ));
+        assert!(reply2.try_recv().unwrap().is_empty());
+        assert_eq!(reply1.try_recv().unwrap().len(), 1);
+
+        // Replies are not received after disconnecting
+        let (_, mut reply) = state.add_labeled_request().unwrap();
+        state.reset();
+        assert!(reply.try_recv().is_err());
+        assert!(state.labeled_requests.is_empty());
+    }
+
+    #[test]
+    fn test_sasl_plain_fallback() {
+        for plain_fallback in [false, true] {
+            let server_info = ServerInfo {
+                sasl_auth: Some(SASLAuth::ScramSha256 {
+                    username: "tiny".to_owned(),
+                    password: "hunter2".to_owned(),
+                    plain_fallback,
+                }),
+                ..test_server_info()
+            };
+            let mut state = StateInner::new(server_info);
+            let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+            let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+
+            let mut ack = Msg {
+                tags: wire::Tags::default(),
+                pfx: None,
+                cmd: wire::Cmd::CAP {
+                    client: "*".to_owned(),
+                    subcommand: "ACK".to_owned(),
+                    params: vec!["sasl".to_owned()],
+                    more: false,
+                },
+            };
+            state.update(&mut ack, &mut snd_ev, &mut snd_irc_msg);
+            assert_eq!(
+                rcv_irc_msg.try_recv().unwrap(),
+                wire::authenticate("SCRAM-SHA-256")
+            );
+
+            // Server does not support SCRAM-SHA-256
+            let mut fail = Msg {
+                tags: wire::Tags::default(),
+                pfx: None,
+                cmd: wire::Cmd::Reply {
+                    num: 904,
+                    params: vec!["tiny".to_owned(), "SASL authentication failed".to_owned()],
+                },
+            };
+            state.update(&mut fail, &mut snd_ev, &mut snd_irc_msg);
+
+            if plain_fallback {
+                assert!(!state.sasl_failed);
+                assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::authenticate("PLAIN"));
+            } else {
+                assert!(state.sasl_failed);
+                assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::cap_end());
+            }
+        }
+    }
+
+    #[test]
+    fn test_sasl_scram_early_success() {
+        use base64::engine::Engine;
+        use base64::engine::general_purpose::STANDARD as BASE64;
+
+        let server_info = ServerInfo {
+            sasl_auth: Some(SASLAuth::ScramSha256 {
+                username: "tiny".to_owned(),
+                password: "hunter2".to_owned(),
+                plain_fallback: false,
+            }),
+            ..test_server_info()
+        };
+        let mut state = StateInner::new(server_info);
+        let (mut snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(10);
+        let (mut snd_irc_msg, mut rcv_irc_msg) = tokio::sync::mpsc::channel(10);
+
+        let mut update = |state: &mut StateInner, cmd: wire::Cmd| {
+            let mut msg = Msg {
+                tags: wire::Tags::default(),
+                pfx: None,
+                cmd,
+            };
+            state.update(&mut msg, &mut snd_ev, &mut snd_irc_msg);
+        };
+        let authenticate = |param: &[u8]| wire::Cmd::AUTHENTICATE {
+            param: BASE64.encode(param),
+        };
+
+        update(
+            &mut state,
+            wire::Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "ACK".to_owned(),
+                params: vec!["sasl".to_owned()],
+                more: false,
+            },
+        );
+        assert_eq!(
+            rcv_irc_msg.try_recv().unwrap(),
+            wire::authenticate("SCRAM-SHA-256")
+        );
+
+        // client-first-message
+        update(
+            &mut state,
+            wire::Cmd::AUTHENTICATE {
+                param: "+".to_owned(),
+            },
+        );
+        let client_first = rcv_irc_msg.try_recv().unwrap();
+        let client_first = BASE64
+            .decode(
+                client_first
+                    .strip_prefix("AUTHENTICATE ")
+                    .unwrap()
+                    .trim_end(),
+            )
+            .unwrap();
+        let client_first = String::from_utf8(client_first).unwrap();
+        let client_nonce = client_first.split_once(",r=").unwrap().1;
+
+        // client-final-message
+        update(
+            &mut state,
+            authenticate(
+                format!("r={client_nonce}server,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096").as_bytes(),
+            ),
+        );
+        assert!(rcv_irc_msg.try_recv().is_ok());
+
+        // Server reports success without sending server-final-message, so its signature is never
+        // verified
+        update(
+            &mut state,
+            wire::Cmd::Reply {
+                num: 903,
+                params: vec![
+                    "tiny".to_owned(),
+                    "SASL authentication successful".to_owned(),
+                ],
+            },
+        );
+        assert!(state.sasl_failed);
+        assert_eq!(rcv_irc_msg.try_recv().unwrap(), wire::cap_end());
+    }
+
     #[test]
     fn test_parse_servername_2() {
         // Gitter variation
@@ -0,0 +1,80 @@
+//! IRCv3 strict transport security (STS) policies. See
+//! <https://ircv3.net/specs/extensions/sts>.
+
+use std::time::Duration;
+
+/// Value of the `sts` capability advertised in CAP LS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StsCap {
+    /// Port to use TLS on. Only meaningful in plaintext connections: the client should reconnect
+    /// with TLS on this port.
+    pub(crate) port: Option<u16>,
+
+    /// How long to keep using TLS for the server. Only meaningful in TLS connections. Zero means
+    /// the policy should be removed.
+    pub(crate) duration: Option<Duration>,
+}
+
+impl StsCap {
+    /// Parse the value of the `sts` capability, e.g. `port=6697,duration=2592000`. Unknown keys
+    /// (e.g. `preload`) are ignored. Returns `None` when a known key has an invalid value, as
+    /// required by the spec.
+    pub(crate) fn parse(value: &str) -> Option<StsCap> {
+        let mut cap = StsCap {
+            port: None,
+            duration: None,
+        };
+        for key_value in value.split(',') {
+            match key_value.split_once('=') {
+                Some(("port", port)) => cap.port = Some(port.parse().ok()?),
+                Some(("duration", duration)) => {
+                    cap.duration = Some(Duration::from_secs(duration.parse().ok()?))
+                }
+                _ => {}
+            }
+        }
+        Some(cap)
+    }
+
+    /// Find and parse the `sts` capability in CAP LS parameters.
+    pub(crate) fn from_caps(caps: &[String]) -> Option<StsCap> {
+        caps.iter()
+            .find_map(|cap| cap.strip_prefix("sts="))
+            .and_then(StsCap::parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sts_cap() {
+        assert_eq!(
+            StsCap::parse("port=6697"),
+            Some(StsCap {
+                port: Some(6697),
+                duration: None
+            })
+        );
+        assert_eq!(
+            StsCap::parse("duration=300,preload,port=6697"),
+            Some(StsCap {
+                port: Some(6697),
+                duration: Some(Duration::from_secs(300))
+            })
+        );
+        assert_eq!(StsCap::parse("port=abc"), None);
+        assert_eq!(StsCap::parse("duration=-1"), None);
+
+        let caps = vec!["multi-prefix".to_owned(), "sts=duration=0".to_owned()];
+        assert_eq!(
+            StsCap::from_caps(&caps),
+            Some(StsCap {
+                port: None,
+                duration: Some(Duration::ZERO)
+            })
+        );
+        assert_eq!(StsCap::from_caps(&caps[..1]), None);
+    }
+}
@@ -4,7 +4,8 @@ pub(crate) struct SplitIterator<'a> {
 }
 
 /// Iterate over subslices that are at most `max` long (in bytes). Splits are
-/// made on whitespace characters when possible.
+/// made on whitespace characters when possible, and always at character
+/// boundaries.
 pub(crate) fn split_iterator(s: &str, max: usize) -> SplitIterator<'_> {
     SplitIterator { s: Some(s), max }
 }
@@ -122,4 +123,18 @@ mod tests {
         let ret: Vec<&str> = vec![];
         assert_eq!(iter.collect::<Vec<&str>>(), ret);
     }
+
+    #[test]
+    fn test_split_iterator_multi_byte() {
+        // 'ş' (2 bytes) and '😀' (4 bytes) straddle the limit
+        let iter = split_iterator("abşcd", 3);
+        assert_eq!(iter.collect::<Vec<&str>>(), vec!["ab", "şc", "d"]);
+
+        let iter = split_iterator("ab😀cd😀", 5);
+        assert_eq!(iter.collect::<Vec<&str>>(), vec!["ab", "😀c", "d😀"]);
+
+        // Whitespace is still preferred
+        let iter = split_iterator("a ğğ ğğ", 5);
+        assert_eq!(iter.collect::<Vec<&str>>(), vec!["a ", "ğğ ", "ğğ"]);
+    }
 }
@@ -5,8 +5,9 @@ use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 
 /// Channel names according to RFC 2812, section 1.3. Channel names are case insensitive, so this
-/// type defines `Eq`, and `Hash` traits that work in a case-insensitive way. `ChanName::display`
-/// method shows the channel name with the original casing.
+/// type defines `Eq`, and `Hash` traits that work in a case-insensitive way, using
+/// `CaseMapping::Rfc1459` regardless of the server's case mapping. `ChanName::display` method
+/// shows the channel name with the original casing.
 #[derive(Debug, Clone)]
 pub struct ChanName(String);
 
@@ -35,18 +36,62 @@ impl Borrow<ChanNameRef> for ChanName {
     }
 }
 
-// Used to normalize channel names. Rules are:
-//
-// - ASCII characters are mapped to their lowercase versions
-// - '[', ']', '\\', '~' are mapped to '{', '}', '|', '^', respectively. See RFC 2812 section 2.2.
-// - Non-ASCII characters are left unchanged.
+// Used to normalize channel names. `Rfc1459` maps the most characters, so channels that are the
+// same with the other case mappings are the same with this one too.
 fn to_lower(c: char) -> char {
-    match c {
-        '[' => '{',
-        ']' => '}',
-        '\\' => '|',
-        '~' => '^',
-        _ => c.to_ascii_lowercase(),
+    CaseMapping::Rfc1459.to_lower_char(c)
+}
+
+/// How nicks and channel names are compared, from RPL_ISUPPORT `CASEMAPPING`. Reference:
+/// <https://modern.ircdocs.horse/#casemapping-parameter>
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CaseMapping {
+    /// ASCII letters are mapped to their lowercase versions.
+    Ascii,
+
+    /// Like `Ascii`, and '[', ']', '\\', '~' are mapped to '{', '}', '|', '^', respectively. See
+    /// RFC 2812 section 2.2. Used until the server sends `CASEMAPPING`.
+    #[default]
+    Rfc1459,
+
+    /// Like `Rfc1459`, but '~' and '^' are different.
+    StrictRfc1459,
+}
+
+impl CaseMapping {
+    /// Parse a `CASEMAPPING` value. Returns `None` for unknown case mappings.
+    pub fn parse(s: &str) -> Option<CaseMapping> {
+        match s {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            _ => None,
+        }
+    }
+
+    /// Map a character to its lowercase version. Non-ASCII characters are left unchanged.
+    pub fn to_lower_char(self, c: char) -> char {
+        match (self, c) {
+            (CaseMapping::Rfc1459 | CaseMapping::StrictRfc1459, '[') => '{',
+            (CaseMapping::Rfc1459 | CaseMapping::StrictRfc1459, ']') => '}',
+            (CaseMapping::Rfc1459 | CaseMapping::StrictRfc1459, '\\') => '|',
+            (CaseMapping::Rfc1459, '~') => '^',
+            _ => c.to_ascii_lowercase(),
+        }
+    }
+
+    /// Map a string to its lowercase version. Only ASCII characters are mapped, so byte indices
+    /// in the string stay the same.
+    pub fn to_lower(self, s: &str) -> String {
+        s.chars().map(|c| self.to_lower_char(c)).collect()
+    }
+
+    /// Compare nicks or channel names.
+    pub fn str_eq(self, a: &str, b: &str) -> bool {
+        a.len() == b.len()
+            && a.chars()
+                .zip(b.chars())
+                .all(|(a, b)| self.to_lower_char(a) == self.to_lower_char(b))
     }
 }
 
@@ -255,4 +300,44 @@ pub enum Event {
 
     /// A command was submitted. `cmd` won't have an initial '/'.
     Cmd { cmd: String, source: MsgSource },
+
+    /// Typing state of the user in a channel or user tab changed. Only sent when typing
+    /// notifications are enabled.
+    Typing {
+        state: TypingState,
+        source: MsgSource,
+    },
+}
+
+/// Typing states of IRCv3 `+typing` notifications. See
+/// <https://ircv3.net/specs/client-tags/typing>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypingState {
+    /// The user is typing.
+    Active,
+    /// The user typed something, but stopped typing for a while.
+    Paused,
+    /// The user cleared the input without sending a message.
+    Done,
+}
+
+impl TypingState {
+    /// Parse a `+typing` tag value.
+    pub fn parse(s: &str) -> Option<TypingState> {
+        match s {
+            "active" => Some(TypingState::Active),
+            "paused" => Some(TypingState::Paused),
+            "done" => Some(TypingState::Done),
+            _ => None,
+        }
+    }
+
+    /// Value of the state in a `+typing` tag.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TypingState::Active => "active",
+            TypingState::Paused => "paused",
+            TypingState::Done => "done",
+        }
+    }
 }
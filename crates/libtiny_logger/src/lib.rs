@@ -19,6 +19,16 @@ pub struct Logger {
     inner: Rc<RefCell<LoggerInner>>,
 }
 
+/// A message read from a log file. See `Logger::user_history`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogMsg {
+    pub sender: String,
+    pub msg: String,
+    pub ts: Tm,
+    /// Whether the message is a CTCP ACTION, logged as `* <sender> <msg>`.
+    pub is_action: bool,
+}
+
 #[derive(Debug)]
 pub enum LoggerInitError {
     CouldNotCreateDir { dir_path: PathBuf, err: io::Error },
@@ -33,6 +43,22 @@ impl Logger {
             inner: Rc::new(RefCell::new(LoggerInner::new(log_dir, report_err)?)),
         })
     }
+
+    /// Last `max_msgs` messages in the log file of the private conversation with `nick`. Empty
+    /// when the file doesn't exist or can't be read.
+    pub fn user_history(&self, serv: &str, nick: &str, max_msgs: usize) -> Vec<LogMsg> {
+        let mut path = self.inner.borrow().log_dir.clone();
+        path.push(format!("{}_{}.txt", serv, replace_forward_slash(nick)));
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                info!("Can't read log file {path:?}: {err}");
+                return vec![];
+            }
+        };
+        let mut msgs = parse_log_msgs(&contents);
+        msgs.split_off(msgs.len().saturating_sub(max_msgs))
+    }
 }
 
 macro_rules! delegate {
@@ -314,7 +340,7 @@ impl LoggerInner {
         let msg = remove_irc_control_chars(msg);
         self.apply_to_target(target, |fd: &mut File, report_err: &dyn Fn(String)| {
             let io_ret = if is_action {
-                writeln!(fd, "[{}] {} {}", strf(&ts), sender, msg)
+                writeln!(fd, "[{}] * {} {}", strf(&ts), sender, msg)
             } else {
                 writeln!(fd, "[{}] {}: {}", strf(&ts), sender, msg)
             };
@@ -452,6 +478,52 @@ impl LoggerInner {
     }
 }
 
+/// Messages in a log file. Only lines in `[<time>] <sender>: <msg>` and `[<time>] * <sender> <msg>`
+/// (actions) forms are returned: joins, leaves, client messages, and actions logged by older
+/// versions can't be told apart reliably so they're skipped. Log lines only have the time, dates
+/// are taken from the "Logging started" headers.
+fn parse_log_msgs(contents: &str) -> Vec<LogMsg> {
+    let mut date = time::strftime("%Y-%m-%d", &time::now()).unwrap();
+    let mut msgs = vec![];
+    for line in contents.lines() {
+        if let Some(started) = line.strip_prefix("*** Logging started at ") {
+            if let Some((date_, _)) = started.split_once(' ') {
+                date = date_.to_owned();
+            }
+            continue;
+        }
+        let Some((hms, rest)) = line
+            .strip_prefix('[')
+            .and_then(|line| line.split_once("] "))
+        else {
+            continue;
+        };
+        let (sender, msg, is_action) = match rest.strip_prefix("* ") {
+            Some(action) => match action.split_once(' ') {
+                Some((sender, msg)) => (sender, msg, true),
+                None => continue,
+            },
+            None => match rest.split_once(": ") {
+                Some((sender, msg)) => (sender, msg, false),
+                None => continue,
+            },
+        };
+        if sender.is_empty() || sender.contains(' ') || sender.starts_with('[') {
+            continue;
+        }
+        let Ok(ts) = time::strptime(&format!("{date} {hms}"), "%Y-%m-%d %H:%M:%S") else {
+            continue;
+        };
+        msgs.push(LogMsg {
+            sender: sender.to_owned(),
+            msg: msg.to_owned(),
+            ts,
+            is_action,
+        });
+    }
+    msgs
+}
+
 fn now() -> String {
     time::strftime("%H:%M:%S", &time::now()).unwrap()
 }
@@ -459,3 +531,68 @@ fn now() -> String {
 fn strf(tm: &Tm) -> String {
     time::strftime("%H:%M:%S", tm).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_msgs() {
+        let log = "
+*** Logging started at 2025-03-04 10:00:00
+
+[10:00:01] alice: hi: there
+[10:00:02] bob waves
+[10:00:03] [client] Disconnected from server
+[10:00:04] bob left.
+[10:00:05] bob: bye
+[10:00:06] * bob waves
+
+*** Logging ended at 2025-03-04 10:01:00
+
+*** Logging started at 2025-03-05 09:00:00
+
+[09:00:01] alice: good morning
+";
+        let msgs: Vec<(String, String, String, bool)> = parse_log_msgs(log)
+            .into_iter()
+            .map(|msg| {
+                (
+                    time::strftime("%Y-%m-%d %H:%M:%S", &msg.ts).unwrap(),
+                    msg.sender,
+                    msg.msg,
+                    msg.is_action,
+                )
+            })
+            .collect();
+        assert_eq!(
+            msgs,
+            vec![
+                (
+                    "2025-03-04 10:00:01".to_owned(),
+                    "alice".to_owned(),
+                    "hi: there".to_owned(),
+                    false
+                ),
+                (
+                    "2025-03-04 10:00:05".to_owned(),
+                    "bob".to_owned(),
+                    "bye".to_owned(),
+                    false
+                ),
+                (
+                    "2025-03-04 10:00:06".to_owned(),
+                    "bob".to_owned(),
+                    "waves".to_owned(),
+                    true
+                ),
+                (
+                    "2025-03-05 09:00:01".to_owned(),
+                    "alice".to_owned(),
+                    "good morning".to_owned(),
+                    false
+                ),
+            ]
+        );
+    }
+}
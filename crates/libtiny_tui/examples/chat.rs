@@ -99,6 +99,6 @@ fn handle_input_ev(ui: &TUI, ev: Event, abort: &mut mpsc::Sender<()>) {
         Quit { .. } => {
             abort.try_send(()).unwrap();
         }
-        Msg { .. } | Lines { .. } => {}
+        Msg { .. } | Lines { .. } | Typing { .. } => {}
     }
 }
@@ -1,7 +1,7 @@
 // To see how color numbers map to actual colors in your terminal run
 // `cargo run --example colors`. Use tab to swap fg/bg colors.
 
-use libtiny_common::{ChanName, ChanNameRef};
+use libtiny_common::{ChanName, ChanNameRef, MsgSource};
 use serde::Deserialize;
 use serde::de::{self, Deserializer, MapAccess, Visitor};
 use std::collections::HashMap;
@@ -26,9 +26,53 @@ pub(crate) struct Config {
 
     pub(crate) layout: Option<Layout>,
 
-    #[serde(default = "default_max_nick_length")]
+    /// Max. width of the nick column in aligned layout. Longer nicks are truncated.
+    #[serde(default = "default_max_nick_length", alias = "max_nick")]
     pub(crate) max_nick_length: usize,
 
+    /// Don't split URLs in the middle when wrapping messages. URLs that don't fit in a line are
+    /// shown starting from the first column of a new line.
+    #[serde(default)]
+    pub(crate) paste_safe_urls: bool,
+
+    /// Show quits caused by a netsplit in one line, collapsed into a summary.
+    #[serde(default)]
+    pub(crate) collapse_netsplits: bool,
+
+    /// Send IRCv3 typing notifications in channel and user tabs.
+    #[serde(default)]
+    pub(crate) typing_notifications: bool,
+
+    /// Show who is typing in channel and user tabs, from IRCv3 typing notifications.
+    #[serde(default = "default_true")]
+    pub(crate) show_typing: bool,
+
+    /// Ask before closing a server tab, or a channel or user tab with unread highlights.
+    #[serde(default)]
+    pub(crate) confirm_close: bool,
+
+    /// Ask before sending a message to a channel after not sending a message there for this many
+    /// minutes, to catch messages typed in the wrong tab.
+    #[serde(default)]
+    pub(crate) confirm_send_after_minutes: Option<u64>,
+
+    /// Scroll to the last message when sending a message in a scrolled up tab.
+    #[serde(default)]
+    pub(crate) scroll_to_bottom_on_send: bool,
+
+    /// Where to show the messages of tabs that don't have enough messages to fill the screen.
+    #[serde(default)]
+    pub(crate) message_align: MsgAlign,
+
+    /// Keep channel and user tabs of each server sorted by name. Same as `tab_order: name`, used
+    /// when `tab_order` is not set.
+    #[serde(default)]
+    pub(crate) sort_tabs: bool,
+
+    /// Order of the channel and user tabs of each server.
+    #[serde(default)]
+    pub(crate) tab_order: Option<TabOrder>,
+
     #[serde(default)]
     pub(crate) key_map: Option<KeyMap>,
 }
@@ -53,6 +97,7 @@ impl Default for Defaults {
             tab_config: TabConfig {
                 ignore: Some(false),
                 notify: Some(Notifier::default()),
+                ..Default::default()
             },
         }
     }
@@ -72,9 +117,10 @@ pub enum Chan {
 }
 
 impl Chan {
-    pub fn from_cmd_args(s: &str) -> Result<Chan, String> {
-        // Make sure channel starts with '#'
-        let s = if !s.starts_with('#') {
+    /// Parse a channel in `/join` arguments. `#` is added to the name when it doesn't start with
+    /// one of `chan_types` (channel name prefixes of the server).
+    pub fn from_cmd_args(s: &str, chan_types: &str) -> Result<Chan, String> {
+        let s = if !s.starts_with(|c| chan_types.contains(c)) {
             format!("#{s}")
         } else {
             s.to_string()
@@ -196,6 +242,43 @@ pub struct TabConfig {
     /// Notification setting for tab.
     #[serde(default)]
     pub notify: Option<Notifier>,
+
+    /// Whether to rejoin the channel after being kicked.
+    #[serde(default)]
+    pub rejoin_on_kick: Option<bool>,
+
+    /// Seconds to wait before rejoining after a kick.
+    #[serde(default)]
+    pub rejoin_delay: Option<u64>,
+
+    /// Max. number of CTCP requests from a nick within a minute. More requests are considered a
+    /// flood and ignored.
+    #[serde(default)]
+    pub ctcp_flood_limit: Option<usize>,
+
+    /// Whether to hide the message of the day sent by the server after connecting. Only used in
+    /// server configs.
+    #[serde(default)]
+    pub hide_motd: Option<bool>,
+
+    /// Whether to hide the user and server statistics (LUSERS replies) sent by the server after
+    /// connecting. Only used in server configs.
+    #[serde(default)]
+    pub hide_lusers: Option<bool>,
+
+    /// Seconds after a netsplit in which joins of the users lost in the split are shown as a
+    /// netjoin. Only used in server configs.
+    #[serde(default)]
+    pub netsplit_timeout: Option<u64>,
+
+    /// Whether to also show WALLOPS in the mentions tab. Only used in server configs.
+    #[serde(default)]
+    pub wallops_mentions: Option<bool>,
+
+    /// Nick prefix of bouncer control users (e.g. `*` for ZNC's `*status`). Messages from these
+    /// users don't highlight their tabs. Only used in server configs.
+    #[serde(default)]
+    pub bouncer_control_prefix: Option<char>,
 }
 
 impl TabConfig {
@@ -210,6 +293,7 @@ impl TabConfig {
             }
             match word {
                 "-ignore" => config.ignore = Some(true),
+                "-rejoin" => config.rejoin_on_kick = Some(true),
                 "-notify" => match words.next() {
                     Some(notify_setting) => {
                         config.notify = Some(Notifier::from_cmd_args(notify_setting)?)
@@ -227,6 +311,16 @@ impl TabConfig {
         TabConfig {
             ignore: self.ignore.or(config.ignore),
             notify: self.notify.or(config.notify),
+            rejoin_on_kick: self.rejoin_on_kick.or(config.rejoin_on_kick),
+            rejoin_delay: self.rejoin_delay.or(config.rejoin_delay),
+            ctcp_flood_limit: self.ctcp_flood_limit.or(config.ctcp_flood_limit),
+            hide_motd: self.hide_motd.or(config.hide_motd),
+            hide_lusers: self.hide_lusers.or(config.hide_lusers),
+            netsplit_timeout: self.netsplit_timeout.or(config.netsplit_timeout),
+            wallops_mentions: self.wallops_mentions.or(config.wallops_mentions),
+            bouncer_control_prefix: self
+                .bouncer_control_prefix
+                .or(config.bouncer_control_prefix),
         }
     }
 
@@ -237,6 +331,10 @@ impl TabConfig {
     }
 }
 
+fn default_true() -> bool {
+    true
+}
+
 fn default_max_nick_length() -> usize {
     12
 }
@@ -257,6 +355,65 @@ pub(crate) enum Layout {
     Aligned,
 }
 
+/// Where to show the messages of a tab that doesn't have enough messages to fill the message area.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MsgAlign {
+    /// Right above the input field, with the empty space on top.
+    #[default]
+    Bottom,
+    /// At the top, with the empty space below.
+    Top,
+}
+
+impl MsgAlign {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            MsgAlign::Bottom => "bottom",
+            MsgAlign::Top => "top",
+        }
+    }
+}
+
+/// Order of the channel and user tabs of a server. Server tabs are always before the tabs of the
+/// server, and can be moved manually.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TabOrder {
+    /// In the order the tabs are opened, tabs can be moved manually.
+    #[default]
+    Join,
+    /// Channel tabs and then user tabs, sorted by name.
+    Name,
+    /// Channel tabs and then user tabs, in the order they are opened. Tabs can be moved manually
+    /// among the tabs of the same type.
+    Type,
+}
+
+impl TabOrder {
+    /// Key to sort the channel and user tabs of a server with. `None` when tabs are not sorted.
+    pub(crate) fn key(self, src: &MsgSource) -> Option<(u8, String)> {
+        let (kind, name) = match src {
+            MsgSource::Serv { .. } => (0, String::new()),
+            MsgSource::Chan { chan, .. } => (1, chan.display().to_lowercase()),
+            MsgSource::User { nick, .. } => (2, nick.to_lowercase()),
+        };
+        match self {
+            TabOrder::Join => None,
+            TabOrder::Name => Some((kind, name)),
+            TabOrder::Type => Some((kind, String::new())),
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            TabOrder::Join => "join",
+            TabOrder::Name => "name",
+            TabOrder::Type => "type",
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(default)]
 pub struct Colors {
@@ -271,7 +428,11 @@ pub struct Colors {
     pub nick_change: Style,
     pub faded: Style,
     pub exit_dialogue: Style,
+    pub server_notice: Style,
     pub highlight: Style,
+    pub mention: Style,
+    pub highlight_line: Style,
+    pub own_msg: Style,
     pub completion: Style,
     pub timestamp: Style,
     pub tab_active: Style,
@@ -281,6 +442,65 @@ pub struct Colors {
     pub tab_joinpart: Style,
 }
 
+impl Colors {
+    /// Styles with their names in the config file.
+    pub(crate) fn styles(&self) -> [(&'static str, Style); 22] {
+        [
+            ("clear", self.clear),
+            ("user_msg", self.user_msg),
+            ("err_msg", self.err_msg),
+            ("topic", self.topic),
+            ("cursor", self.cursor),
+            ("join", self.join),
+            ("part", self.part),
+            ("nick_change", self.nick_change),
+            ("faded", self.faded),
+            ("exit_dialogue", self.exit_dialogue),
+            ("server_notice", self.server_notice),
+            ("highlight", self.highlight),
+            ("mention", self.mention),
+            ("highlight_line", self.highlight_line),
+            ("own_msg", self.own_msg),
+            ("completion", self.completion),
+            ("timestamp", self.timestamp),
+            ("tab_active", self.tab_active),
+            ("tab_normal", self.tab_normal),
+            ("tab_new_msg", self.tab_new_msg),
+            ("tab_highlight", self.tab_highlight),
+            ("tab_joinpart", self.tab_joinpart),
+        ]
+    }
+
+    /// Get a style by its name in the config file.
+    pub(crate) fn style_mut(&mut self, name: &str) -> Option<&mut Style> {
+        match name {
+            "clear" => Some(&mut self.clear),
+            "user_msg" => Some(&mut self.user_msg),
+            "err_msg" => Some(&mut self.err_msg),
+            "topic" => Some(&mut self.topic),
+            "cursor" => Some(&mut self.cursor),
+            "join" => Some(&mut self.join),
+            "part" => Some(&mut self.part),
+            "nick_change" => Some(&mut self.nick_change),
+            "faded" => Some(&mut self.faded),
+            "exit_dialogue" => Some(&mut self.exit_dialogue),
+            "server_notice" => Some(&mut self.server_notice),
+            "highlight" => Some(&mut self.highlight),
+            "mention" => Some(&mut self.mention),
+            "highlight_line" => Some(&mut self.highlight_line),
+            "own_msg" => Some(&mut self.own_msg),
+            "completion" => Some(&mut self.completion),
+            "timestamp" => Some(&mut self.timestamp),
+            "tab_active" => Some(&mut self.tab_active),
+            "tab_normal" => Some(&mut self.tab_normal),
+            "tab_new_msg" => Some(&mut self.tab_new_msg),
+            "tab_highlight" => Some(&mut self.tab_highlight),
+            "tab_joinpart" => Some(&mut self.tab_joinpart),
+            _ => None,
+        }
+    }
+}
+
 impl Default for Colors {
     fn default() -> Self {
         Colors {
@@ -322,10 +542,26 @@ impl Default for Colors {
                 fg: TB_DEFAULT,
                 bg: 4,
             },
+            server_notice: Style {
+                fg: 6,
+                bg: TB_DEFAULT,
+            },
             highlight: Style {
                 fg: 9 | TB_BOLD,
                 bg: TB_DEFAULT,
             },
+            mention: Style {
+                fg: 9 | TB_BOLD | TB_UNDERLINE,
+                bg: TB_DEFAULT,
+            },
+            highlight_line: Style {
+                fg: TB_DEFAULT,
+                bg: TB_DEFAULT,
+            },
+            own_msg: Style {
+                fg: 0,
+                bg: TB_DEFAULT,
+            },
             completion: Style {
                 fg: 84,
                 bg: TB_DEFAULT,
@@ -488,6 +724,22 @@ impl<'de> Deserialize<'de> for Style {
     }
 }
 
+/// Shows a style in the config file syntax, e.g. `{fg: 9, bg: 0, attrs: [bold]}`.
+impl std::fmt::Display for Style {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{fg: {}, bg: {}", self.fg & 0xFF, self.bg & 0xFF)?;
+        let attrs: Vec<&str> = ATTRS
+            .iter()
+            .filter(|&&(_, attr)| self.fg & attr != 0)
+            .map(|&(name, _)| name)
+            .collect();
+        if !attrs.is_empty() {
+            write!(f, ", attrs: [{}]", attrs.join(", "))?;
+        }
+        write!(f, "}}")
+    }
+}
+
 pub(crate) fn parse_config(config_path: &Path) -> Result<Config, serde_yaml::Error> {
     // tiny creates a config file with the defaults when it can't find one, but the config file can
     // be deleted before a `/reload`.
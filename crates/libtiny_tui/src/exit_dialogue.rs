@@ -3,14 +3,40 @@ use termbox_simple::Termbox;
 use crate::key_map::KeyAction;
 use crate::{config::Colors, widget::WidgetRet};
 
+/// A yes/no prompt shown instead of the input field. Used to confirm quitting, closing tabs, and
+/// sending messages.
 #[derive(Debug)]
 pub(crate) struct ExitDialogue {
     width: i32,
+
+    /// The question to show.
+    msg: String,
+
+    /// Command to run when the user answers yes. `None` sends the input field contents.
+    cmd: Option<String>,
 }
 
 impl ExitDialogue {
     pub(crate) fn new(width: i32) -> ExitDialogue {
-        ExitDialogue { width }
+        ExitDialogue::confirm(width, MSG.to_owned(), "quit".to_owned())
+    }
+
+    /// A prompt that runs `cmd` when confirmed.
+    pub(crate) fn confirm(width: i32, msg: String, cmd: String) -> ExitDialogue {
+        ExitDialogue {
+            width,
+            msg,
+            cmd: Some(cmd),
+        }
+    }
+
+    /// A prompt that sends the input field contents when confirmed.
+    pub(crate) fn confirm_send(width: i32, msg: String) -> ExitDialogue {
+        ExitDialogue {
+            width,
+            msg,
+            cmd: None,
+        }
     }
 }
 
@@ -25,7 +51,7 @@ impl ExitDialogue {
         tb.hide_cursor();
 
         let mut col = 0;
-        for char in MSG.chars() {
+        for char in self.msg.chars() {
             tb.change_cell(
                 pos_x + col,
                 pos_y,
@@ -50,7 +76,10 @@ impl ExitDialogue {
 
     pub(crate) fn keypressed(&self, key_action: &KeyAction) -> WidgetRet {
         match key_action {
-            KeyAction::Input('y') | KeyAction::InputSend => WidgetRet::Command("quit".to_string()),
+            KeyAction::Input('y') | KeyAction::InputSend => match &self.cmd {
+                Some(cmd) => WidgetRet::Command(cmd.clone()),
+                None => WidgetRet::SendInput,
+            },
             _ => WidgetRet::Remove,
         }
     }
@@ -0,0 +1,91 @@
+//! A popup listing key bindings and commands, drawn over the current tab. See
+//! `KeyAction::ShowHelp`.
+
+use term_input::Key;
+use termbox_simple::Termbox;
+
+use crate::config::Colors;
+use crate::termbox;
+
+/// Max. width of the popup. The popup is centered on wider screens.
+const MAX_WIDTH: i32 = 80;
+
+#[derive(Debug)]
+pub(crate) struct Help {
+    lines: Vec<String>,
+
+    /// Current page, starting from 0.
+    page: usize,
+}
+
+impl Help {
+    pub(crate) fn new(lines: Vec<String>) -> Help {
+        Help { lines, page: 0 }
+    }
+
+    /// Number of lines shown in a page on a screen of the given height, excluding the title line.
+    fn page_size(height: i32) -> usize {
+        (height - 1).max(1) as usize
+    }
+
+    fn n_pages(&self, height: i32) -> usize {
+        self.lines.len().div_ceil(Self::page_size(height)).max(1)
+    }
+
+    /// Handle a key press. `pgdown` and space show the next page, `pgup` shows the previous page.
+    /// Returns `false` when the popup should be closed: on any other key, or on next page in the
+    /// last page.
+    pub(crate) fn keypressed(&mut self, key: Key, height: i32) -> bool {
+        let n_pages = self.n_pages(height);
+        match key {
+            Key::PageDown | Key::Char(' ') => {
+                self.page += 1;
+                self.page < n_pages
+            }
+            Key::PageUp => {
+                self.page = self.page.min(n_pages - 1).saturating_sub(1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Draw the popup in the top `height` lines of the screen.
+    pub(crate) fn draw(&self, tb: &mut Termbox, colors: &Colors, width: i32, height: i32) {
+        if height < 1 {
+            return;
+        }
+
+        tb.hide_cursor();
+
+        let style = colors.exit_dialogue;
+        let popup_width = width.min(MAX_WIDTH);
+        let pos_x = (width - popup_width) / 2;
+        let max_chars = (popup_width - 2).max(0) as usize;
+
+        for y in 0..height {
+            for x in pos_x..pos_x + popup_width {
+                tb.change_cell(x, y, ' ', style.fg, style.bg);
+            }
+        }
+
+        let n_pages = self.n_pages(height);
+        let page = self.page.min(n_pages - 1);
+        let mut pos_y = 0;
+        if height > 1 {
+            let title = format!(
+                "Help ({}/{}): pgdown next page, pgup previous page, other keys close",
+                page + 1,
+                n_pages
+            );
+            termbox::print_chars(tb, pos_x + 1, 0, style, title.chars().take(max_chars));
+            pos_y += 1;
+        }
+
+        let page_size = Self::page_size(height);
+        for line in self.lines.iter().skip(page * page_size).take(page_size) {
+            termbox::print_chars(tb, pos_x + 1, pos_y, style, line.chars().take(max_chars));
+            pos_y += 1;
+        }
+    }
+}
@@ -23,6 +23,63 @@ const SCROLL_FALLBACK_WIDTH: i32 = 36;
 /// Input history size
 const HIST_SIZE: usize = 30;
 
+/// Maximum number of undo steps kept for the input field
+const UNDO_SIZE: usize = 100;
+
+/// Maximum number of entries in the kill ring
+const KILL_RING_SIZE: usize = 30;
+
+/// The line to add to the input history for a sent line. Passwords in NickServ identify commands
+/// (`/identify`, `/ns identify`, `/nickserv identify`) are not added to the history, only the
+/// command is. Server and SASL passwords are removed from `/connect` commands.
+fn history_line(line: &InputLine) -> InputLine {
+    let str: String = line.get_buffer().iter().collect();
+    let words: Vec<&str> = str.split_whitespace().collect();
+    let n_cmd_words = match words.as_slice() {
+        ["/identify", _, ..] => 1,
+        ["/ns" | "/nickserv", identify, _, ..] if identify.eq_ignore_ascii_case("identify") => 2,
+        ["/connect", args @ ..] => {
+            return match connect_without_passwords(args) {
+                Some(args) => InputLine::from_buffer(format!("/connect {args}").chars().collect()),
+                None => line.clone(),
+            };
+        }
+        _ => return line.clone(),
+    };
+    let cmd = format!("{} ", words[..n_cmd_words].join(" "));
+    InputLine::from_buffer(cmd.chars().collect())
+}
+
+/// Remove the server password and the `-sasl <user>:<password>` flag from `/connect` arguments.
+/// Returns `None` when the arguments don't have a password. Flags should be kept in sync with
+/// `/connect` in tiny.
+fn connect_without_passwords(args: &[&str]) -> Option<String> {
+    let mut kept = vec![];
+    let mut n_positional = 0;
+    let n_args = args.len();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "-sasl" => {
+                args.next();
+            }
+            "-nick" | "-join" => {
+                kept.push(arg);
+                kept.extend(args.next());
+            }
+            flag if flag.starts_with('-') => kept.push(flag),
+            _ => {
+                // Server address, then the password
+                n_positional += 1;
+                if n_positional == 1 {
+                    kept.push(arg);
+                }
+            }
+        }
+    }
+    (kept.len() != n_args).then(|| kept.join(" "))
+}
+
 pub(crate) struct InputArea {
     /// The message that's currently being edited (not yet sent)
     buffer: InputLine,
@@ -52,6 +109,42 @@ pub(crate) struct InputArea {
     /// Current nickname. Not available on initialization (e.g. before registration with the
     /// server). Set with `set_nick`.
     nick: Option<Nickname>,
+
+    /// Snapshots of `buffer` and cursor before each edit group, most recent last.
+    undo_stack: Vec<(InputLine, i32)>,
+
+    /// Snapshots popped from `undo_stack` by undo, most recent last. Cleared on new edits.
+    redo_stack: Vec<(InputLine, i32)>,
+
+    /// Killed (deleted with a kill command) text, most recent last.
+    kill_ring: Vec<String>,
+
+    /// The last edit. Used to group insertions for undo, to append consecutive kills to the same
+    /// kill ring entry, and to replace yanked text on yank-pop.
+    last_action: LastAction,
+}
+
+enum LastAction {
+    Other,
+
+    /// Inserted a character. Consecutive insertions are undone as one group.
+    Insert,
+
+    /// Killed text in the given direction.
+    Kill(KillDir),
+
+    /// Yanked `len` characters at `start` from the given kill ring entry.
+    Yank {
+        start: usize,
+        len: usize,
+        ring_idx: usize,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum KillDir {
+    Forward,
+    Backward,
 }
 
 enum Mode {
@@ -120,6 +213,10 @@ impl InputArea {
             history: Vec::with_capacity(HIST_SIZE),
             mode: Mode::Edit,
             nick: None,
+            undo_stack: vec![],
+            redo_stack: vec![],
+            kill_ring: vec![],
+            last_action: LastAction::Other,
         }
     }
 
@@ -212,16 +309,20 @@ impl InputArea {
     }
 
     pub(crate) fn keypressed(&mut self, key_action: &KeyAction) -> WidgetRet {
+        let last_action = mem::replace(&mut self.last_action, LastAction::Other);
+
         match key_action {
             KeyAction::InputSend => {
                 if self.current_buffer_len() > 0 {
                     self.modify();
+                    self.undo_stack.clear();
+                    self.redo_stack.clear();
 
                     let ret = mem::replace(&mut self.buffer, InputLine::new());
                     if self.history.len() == HIST_SIZE {
                         self.history.remove(0);
                     }
-                    self.history.push(ret.clone());
+                    self.history.push(history_line(&ret));
 
                     self.move_cursor(0);
 
@@ -232,7 +333,7 @@ impl InputArea {
             }
             KeyAction::InputDeletePrevChar => {
                 if self.cursor > 0 {
-                    self.modify();
+                    self.begin_edit();
                     self.buffer.remove(self.cursor as usize - 1);
                     self.dec_cursor();
                 }
@@ -240,7 +341,7 @@ impl InputArea {
             }
             KeyAction::InputDeleteNextChar => {
                 if self.cursor < self.current_buffer_len() {
-                    self.modify();
+                    self.begin_edit();
                     self.buffer.remove(self.cursor as usize);
                     // TODO: We should probably call move_cursor here to update scroll?
                 }
@@ -256,21 +357,60 @@ impl InputArea {
             }
             KeyAction::InputDeleteToStart => {
                 if self.cursor != 0 {
-                    self.modify();
-                    self.buffer.drain(..self.cursor as usize);
+                    self.begin_edit();
+                    let killed = self.buffer.drain(..self.cursor as usize).collect();
                     self.move_cursor(0);
+                    self.add_kill(killed, KillDir::Backward, &last_action);
                 }
                 WidgetRet::KeyHandled
             }
             KeyAction::InputDeleteToEnd => {
                 if self.cursor != self.current_buffer_len() {
-                    self.modify();
-                    self.buffer.drain(self.cursor as usize..);
+                    self.begin_edit();
+                    let killed = self.buffer.drain(self.cursor as usize..).collect();
+                    self.add_kill(killed, KillDir::Forward, &last_action);
                 }
                 WidgetRet::KeyHandled
             }
             KeyAction::InputDeletePrevWord => {
-                self.consume_word_before_curs();
+                if let Some(killed) = self.consume_word_before_curs(is_word_char) {
+                    self.add_kill(killed, KillDir::Backward, &last_action);
+                }
+                WidgetRet::KeyHandled
+            }
+            KeyAction::InputDeletePrevBigWord => {
+                if let Some(killed) = self.consume_word_before_curs(is_big_word_char) {
+                    self.add_kill(killed, KillDir::Backward, &last_action);
+                }
+                WidgetRet::KeyHandled
+            }
+            KeyAction::InputDeleteNextWord => {
+                if let Some(killed) = self.consume_word_after_curs() {
+                    self.add_kill(killed, KillDir::Forward, &last_action);
+                }
+                WidgetRet::KeyHandled
+            }
+            KeyAction::InputYank => {
+                if !self.kill_ring.is_empty() {
+                    self.yank(self.kill_ring.len() - 1, None);
+                }
+                WidgetRet::KeyHandled
+            }
+            KeyAction::InputYankPop => {
+                // Only valid right after a yank: replace the yanked text with the previous kill
+                if let LastAction::Yank {
+                    start,
+                    len,
+                    ring_idx,
+                } = last_action
+                {
+                    let ring_idx = if ring_idx == 0 {
+                        self.kill_ring.len() - 1
+                    } else {
+                        ring_idx - 1
+                    };
+                    self.yank(ring_idx, Some((start, len)));
+                }
                 WidgetRet::KeyHandled
             }
             KeyAction::InputMoveCursLeft => {
@@ -283,43 +423,30 @@ impl InputArea {
             }
             KeyAction::InputMoveWordLeft => {
                 if self.cursor > 0 {
-                    let mut cur = self.cursor as usize;
-                    let mut skipped = false;
-                    while cur > 0 && self.char_at(cur - 1).is_whitespace() {
-                        cur -= 1;
-                        skipped = true;
-                    }
-                    while cur > 0 && self.char_at(cur - 1).is_alphanumeric() {
-                        cur -= 1;
-                        skipped = true;
-                    }
-                    if !skipped {
-                        cur -= 1; // skip at least one char
-                    }
+                    let cur = self.word_start_before(self.cursor as usize, is_word_char);
                     self.move_cursor(cur as i32);
                 }
                 WidgetRet::KeyHandled
             }
             KeyAction::InputMoveWordRight => {
-                let len = self.current_buffer_len() as usize;
-                if (self.cursor as usize) < len {
-                    let mut cur = self.cursor as usize;
-                    let mut skipped = false;
-                    while cur < len && self.char_at(cur).is_alphanumeric() {
-                        cur += 1;
-                        skipped = true;
-                    }
-                    while cur < len && self.char_at(cur).is_whitespace() {
-                        cur += 1;
-                        skipped = true;
-                    }
-                    if !skipped {
-                        cur += 1; // skip at least one char
-                    }
+                if self.cursor < self.current_buffer_len() {
+                    let cur = self.next_word_start(self.cursor as usize);
                     self.move_cursor(cur as i32);
                 }
                 WidgetRet::KeyHandled
             }
+            KeyAction::InputTransposeChars => {
+                let len = self.current_buffer_len();
+                if len >= 2 && self.cursor > 0 {
+                    self.begin_edit();
+                    // At the end of the line swap the last two characters
+                    let idx = min(self.cursor, len - 1) as usize;
+                    let char = self.buffer.remove(idx - 1);
+                    self.buffer.insert(idx, char);
+                    self.move_cursor(idx as i32 + 1);
+                }
+                WidgetRet::KeyHandled
+            }
             KeyAction::InputPrevEntry => {
                 self.completion_prev_entry();
                 WidgetRet::KeyHandled
@@ -329,9 +456,22 @@ impl InputArea {
                 WidgetRet::KeyHandled
             }
             KeyAction::Input(ch) => {
-                self.modify();
+                if let LastAction::Insert = last_action {
+                    self.modify();
+                } else {
+                    self.begin_edit();
+                }
                 self.buffer.insert(self.cursor as usize, *ch);
                 self.inc_cursor();
+                self.last_action = LastAction::Insert;
+                WidgetRet::KeyHandled
+            }
+            KeyAction::InputUndo => {
+                self.undo();
+                WidgetRet::KeyHandled
+            }
+            KeyAction::InputRedo => {
+                self.redo();
                 WidgetRet::KeyHandled
             }
             KeyAction::Command(cmd) => WidgetRet::Command(cmd.to_owned()),
@@ -341,10 +481,19 @@ impl InputArea {
 
     /// Get contents of the text field and cursor location and clear it.
     pub(crate) fn flush(&mut self) -> (String, i32) {
+        self.begin_edit();
         let cursor = ::std::mem::replace(&mut self.cursor, 0);
         (self.buffer.drain(..).collect(), cursor)
     }
 
+    /// The message shown in the input field, ignoring autocompletions.
+    pub(crate) fn get_buffer(&self) -> &[char] {
+        match &self.mode {
+            Mode::Edit | Mode::Autocomplete { .. } => self.buffer.get_buffer(),
+            Mode::History(hist_curs) => self.history[*hist_curs as usize].get_buffer(),
+        }
+    }
+
     /// Add a line to the text field history.
     pub(crate) fn add_history(&mut self, str: &str) {
         self.history
@@ -353,6 +502,7 @@ impl InputArea {
 
     pub(crate) fn set(&mut self, str: &str) {
         self.mode = Mode::Edit;
+        self.redo_stack.clear();
         self.buffer = InputLine::from_buffer(str.chars().collect());
         self.height = None;
         self.move_cursor_to_end();
@@ -362,44 +512,129 @@ impl InputArea {
         self.cursor = cursor.clamp(0, self.current_buffer_len());
     }
 
-    fn consume_word_before_curs(&mut self) {
+    /// Delete the word before the cursor, made of characters satisfying `is_word_char`. Returns
+    /// the deleted text.
+    fn consume_word_before_curs(&mut self, is_word_char: fn(char) -> bool) -> Option<String> {
         // No modifications can happen if the scroll is at the beginning
         if self.cursor == 0 {
-            return;
+            return None;
         }
 
-        self.modify();
+        self.begin_edit();
 
-        let char = self.buffer.get((self.cursor - 1) as usize);
+        let end = self.cursor as usize;
+        let start = self.word_start_before(end, is_word_char);
 
-        // Try to imitate vim's behaviour here.
-        if char.is_whitespace() {
-            self.consume_before(char::is_whitespace);
-            self.consume_before(char::is_alphanumeric);
-        } else {
-            let char = self.buffer.get((self.cursor - 1) as usize);
-            if char.is_alphanumeric() {
-                self.consume_before(char::is_alphanumeric);
-            } else if self.cursor != 0 {
-                // consume at least one char
-                let cursor = self.cursor;
-                self.buffer.remove(cursor as usize - 1);
-                self.move_cursor(cursor - 1);
-            }
+        let killed = self.buffer.drain(start..end).collect();
+        self.move_cursor(start as i32);
+        Some(killed)
+    }
+
+    /// Delete the word after the cursor. Returns the deleted text.
+    fn consume_word_after_curs(&mut self) -> Option<String> {
+        if self.cursor == self.current_buffer_len() {
+            return None;
+        }
+
+        self.begin_edit();
+
+        let start = self.cursor as usize;
+        let end = self.word_end_after(start);
+
+        let killed = self.buffer.drain(start..end).collect();
+        self.move_cursor(start as i32);
+        Some(killed)
+    }
+
+    /// Start of the word before `idx`. Try to imitate vim's behaviour here: skips whitespace, then
+    /// word characters. Skips at least one character. `idx` should be larger than 0.
+    fn word_start_before(&self, idx: usize, is_word_char: fn(char) -> bool) -> usize {
+        let start = self.skip_before(idx, char::is_whitespace);
+        let start = self.skip_before(start, is_word_char);
+        if start == idx { idx - 1 } else { start }
+    }
+
+    /// End of the word after `idx`: skips whitespace, then word characters. Skips at least one
+    /// character. `idx` should be smaller than the line length.
+    fn word_end_after(&self, idx: usize) -> usize {
+        let end = self.skip_after(idx, char::is_whitespace);
+        let end = self.skip_after(end, is_word_char);
+        if end == idx { idx + 1 } else { end }
+    }
+
+    /// Start of the next word after `idx`: skips word characters, then whitespace. Skips at least
+    /// one character. `idx` should be smaller than the line length.
+    fn next_word_start(&self, idx: usize) -> usize {
+        let end = self.skip_after(idx, is_word_char);
+        let end = self.skip_after(end, char::is_whitespace);
+        if end == idx { idx + 1 } else { end }
+    }
+
+    /// Index of the first character of the run of characters satisfying `f` that ends at `idx`.
+    fn skip_before<F>(&self, mut idx: usize, f: F) -> usize
+    where
+        F: Fn(char) -> bool,
+    {
+        while idx > 0 && f(self.char_at(idx - 1)) {
+            idx -= 1;
         }
+        idx
     }
 
-    fn consume_before<F>(&mut self, f: F)
+    /// Index after the run of characters satisfying `f` that starts at `idx`.
+    fn skip_after<F>(&self, mut idx: usize, f: F) -> usize
     where
         F: Fn(char) -> bool,
     {
-        let end_range = self.cursor as usize;
-        let mut begin_range = self.cursor - 1;
-        while begin_range >= 0 && f(self.buffer.get(begin_range as usize)) {
-            begin_range -= 1;
+        let len = self.current_buffer_len() as usize;
+        while idx < len && f(self.char_at(idx)) {
+            idx += 1;
         }
-        self.buffer.drain(((begin_range + 1) as usize)..end_range);
-        self.move_cursor(begin_range + 1);
+        idx
+    }
+
+    /// Add killed text to the kill ring. Consecutive kills in the same direction are merged into
+    /// one entry.
+    fn add_kill(&mut self, killed: String, dir: KillDir, last_action: &LastAction) {
+        match self.kill_ring.last_mut() {
+            Some(last) if matches!(last_action, LastAction::Kill(last_dir) if *last_dir == dir) => {
+                match dir {
+                    KillDir::Forward => last.push_str(&killed),
+                    KillDir::Backward => last.insert_str(0, &killed),
+                }
+            }
+            _ => {
+                if self.kill_ring.len() == KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+                self.kill_ring.push(killed);
+            }
+        }
+        self.last_action = LastAction::Kill(dir);
+    }
+
+    /// Insert the kill ring entry at the cursor. When `replace` is given, the text in the given
+    /// range (a previous yank) is replaced.
+    fn yank(&mut self, ring_idx: usize, replace: Option<(usize, usize)>) {
+        self.begin_edit();
+        let start = match replace {
+            Some((start, len)) => {
+                self.buffer.drain(start..start + len);
+                start
+            }
+            None => self.cursor as usize,
+        };
+        let mut idx = start;
+        for c in self.kill_ring[ring_idx].chars() {
+            self.buffer.insert(idx, c);
+            idx += 1;
+        }
+        self.move_cursor(idx as i32);
+        self.last_action = LastAction::Yank {
+            start,
+            len: idx - start,
+            ring_idx,
+        };
     }
 
     fn completion_prev_entry(&mut self) {
@@ -644,6 +879,75 @@ impl InputArea {
         self.mode = Mode::Edit;
     }
 
+    /// Start a new undo group: save the unsent buffer, then switch to editing the shown line.
+    /// When a completion is being committed it gets its own undo group.
+    fn begin_edit(&mut self) {
+        let completing = self.in_autocomplete();
+        self.push_undo();
+        self.modify();
+        if completing {
+            self.push_undo();
+        }
+        self.redo_stack.clear();
+    }
+
+    fn push_undo(&mut self) {
+        if self.undo_stack.len() == UNDO_SIZE {
+            self.undo_stack.remove(0);
+        }
+        let cursor = self.edit_cursor();
+        self.undo_stack.push((self.buffer.clone(), cursor));
+    }
+
+    /// Cursor position in `buffer`. In history mode the cursor is on the history entry, so we
+    /// use the end of the buffer. In autocomplete mode we use the cursor before the completion.
+    fn edit_cursor(&self) -> i32 {
+        match self.mode {
+            Mode::Edit => self.cursor,
+            Mode::History(_) => self.buffer.len() as i32,
+            Mode::Autocomplete {
+                insertion_point, ..
+            } => insertion_point as i32,
+        }
+    }
+
+    /// Leave history and autocomplete modes without modifying `buffer`. Returns whether the mode
+    /// was changed.
+    fn cancel_mode(&mut self) -> bool {
+        if let Mode::Edit = self.mode {
+            return false;
+        }
+        let cursor = self.edit_cursor();
+        self.mode = Mode::Edit;
+        self.height = None;
+        self.move_cursor(cursor);
+        true
+    }
+
+    fn undo(&mut self) {
+        // Undoing a history selection or a completion that hasn't been committed yet restores
+        // the unsent buffer
+        if self.cancel_mode() {
+            return;
+        }
+        if let Some((buffer, cursor)) = self.undo_stack.pop() {
+            let current = mem::replace(&mut self.buffer, buffer);
+            self.redo_stack.push((current, self.cursor));
+            self.height = None;
+            self.move_cursor(cursor);
+        }
+    }
+
+    fn redo(&mut self) {
+        self.cancel_mode();
+        if let Some((buffer, cursor)) = self.redo_stack.pop() {
+            let current = mem::replace(&mut self.buffer, buffer);
+            self.undo_stack.push((current, self.cursor));
+            self.height = None;
+            self.move_cursor(cursor);
+        }
+    }
+
     ////////////////////////////////////////////////////////////////////////////
     // Manipulating cursor
 
@@ -713,6 +1017,8 @@ impl InputArea {
 
 impl InputArea {
     pub(crate) fn autocomplete(&mut self, dict: &Trie) {
+        self.last_action = LastAction::Other;
+
         if self.in_autocomplete() {
             // scroll next if you hit the KeyAction::InputAutoComplete key again
             self.completion_prev_entry();
@@ -768,6 +1074,24 @@ impl InputArea {
     }
 }
 
+/// Whether the character is a part of a word, for moving and deleting by words. Combining marks
+/// are a part of the word of their base characters.
+fn is_word_char(char: char) -> bool {
+    char.is_alphanumeric()
+        || matches!(char,
+            '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}')
+}
+
+/// Whether the character is a part of a "big word" (vim's WORD): any run of non-whitespace
+/// characters, including punctuation. Used to delete whole URLs and nicks with punctuation.
+fn is_big_word_char(char: char) -> bool {
+    !char.is_whitespace()
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
@@ -801,4 +1125,262 @@ mod tests {
         input_area.set(multiline_string_no_spaces);
         assert_eq!(input_area.get_height(input_area.width), 2);
     }
+
+    fn buffer_str(input_area: &mut InputArea) -> String {
+        input_area.shown_line().get_buffer().iter().collect()
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut input_area = InputArea::new(40, 50);
+        for c in "hello world".chars() {
+            input_area.keypressed(&KeyAction::Input(c));
+        }
+        input_area.keypressed(&KeyAction::InputDeletePrevWord);
+        input_area.keypressed(&KeyAction::InputDeletePrevChar);
+        assert_eq!(buffer_str(&mut input_area), "hello");
+
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "hello ");
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "hello world");
+        assert_eq!(input_area.cursor, 11);
+        // Consecutive insertions are undone as one group
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "");
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "");
+
+        input_area.keypressed(&KeyAction::InputRedo);
+        assert_eq!(buffer_str(&mut input_area), "hello world");
+        input_area.keypressed(&KeyAction::InputRedo);
+        assert_eq!(buffer_str(&mut input_area), "hello ");
+
+        // New edits clear redo history
+        input_area.keypressed(&KeyAction::Input('x'));
+        input_area.keypressed(&KeyAction::InputRedo);
+        assert_eq!(buffer_str(&mut input_area), "hello x");
+
+        // Moving the cursor starts a new insertion group
+        input_area.keypressed(&KeyAction::InputMoveCursStart);
+        input_area.keypressed(&KeyAction::Input('y'));
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "hello x");
+        assert_eq!(input_area.cursor, 0);
+    }
+
+    #[test]
+    fn undo_history_edit() {
+        let mut input_area = InputArea::new(40, 50);
+        input_area.add_history("old message");
+        for c in "unsent".chars() {
+            input_area.keypressed(&KeyAction::Input(c));
+        }
+
+        // Browsing history and coming back keeps the unsent text
+        input_area.keypressed(&KeyAction::InputPrevEntry);
+        assert_eq!(buffer_str(&mut input_area), "old message");
+        input_area.keypressed(&KeyAction::InputNextEntry);
+        assert_eq!(buffer_str(&mut input_area), "unsent");
+
+        // Editing a history entry replaces the buffer, undo restores the unsent text
+        input_area.keypressed(&KeyAction::InputPrevEntry);
+        input_area.keypressed(&KeyAction::Input('!'));
+        assert_eq!(buffer_str(&mut input_area), "old message!");
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "unsent");
+        assert_eq!(input_area.cursor, 6);
+    }
+
+    #[test]
+    fn password_not_in_history() {
+        let mut input_area = InputArea::new(40, 50);
+        for line in [
+            "/identify hunter2",
+            "/ns IDENTIFY tiny hunter2",
+            "/ns info tiny",
+        ] {
+            for c in line.chars() {
+                input_area.keypressed(&KeyAction::Input(c));
+            }
+            input_area.keypressed(&KeyAction::InputSend);
+        }
+
+        input_area.keypressed(&KeyAction::InputPrevEntry);
+        assert_eq!(buffer_str(&mut input_area), "/ns info tiny");
+        input_area.keypressed(&KeyAction::InputPrevEntry);
+        assert_eq!(buffer_str(&mut input_area), "/ns IDENTIFY ");
+        input_area.keypressed(&KeyAction::InputPrevEntry);
+        assert_eq!(buffer_str(&mut input_area), "/identify ");
+
+        let mut input_area = InputArea::new(40, 50);
+        for line in [
+            "/connect -tls irc.example.net hunter2 -sasl user:pass -nick tiny",
+            "/connect irc.example.net -join #a,#b",
+        ] {
+            for c in line.chars() {
+                input_area.keypressed(&KeyAction::Input(c));
+            }
+            input_area.keypressed(&KeyAction::InputSend);
+        }
+
+        input_area.keypressed(&KeyAction::InputPrevEntry);
+        assert_eq!(
+            buffer_str(&mut input_area),
+            "/connect irc.example.net -join #a,#b"
+        );
+        input_area.keypressed(&KeyAction::InputPrevEntry);
+        assert_eq!(
+            buffer_str(&mut input_area),
+            "/connect -tls irc.example.net -nick tiny"
+        );
+    }
+
+    #[test]
+    fn kill_ring() {
+        let mut input_area = InputArea::new(40, 50);
+        for c in "one two three".chars() {
+            input_area.keypressed(&KeyAction::Input(c));
+        }
+        // Consecutive backward kills are merged
+        input_area.keypressed(&KeyAction::InputDeletePrevWord);
+        input_area.keypressed(&KeyAction::InputDeletePrevWord);
+        assert_eq!(buffer_str(&mut input_area), "one ");
+        input_area.keypressed(&KeyAction::InputMoveCursStart);
+        // Consecutive forward kills are merged
+        input_area.keypressed(&KeyAction::InputDeleteNextWord);
+        input_area.keypressed(&KeyAction::InputDeleteToEnd);
+        assert_eq!(buffer_str(&mut input_area), "");
+        assert_eq!(input_area.kill_ring, vec!["two three", "one "]);
+
+        input_area.keypressed(&KeyAction::InputYank);
+        assert_eq!(buffer_str(&mut input_area), "one ");
+        input_area.keypressed(&KeyAction::InputYankPop);
+        assert_eq!(buffer_str(&mut input_area), "two three");
+        assert_eq!(input_area.cursor, 9);
+        input_area.keypressed(&KeyAction::InputYankPop);
+        assert_eq!(buffer_str(&mut input_area), "one ");
+
+        // Yank-pop is ignored after other edits
+        input_area.keypressed(&KeyAction::Input('x'));
+        input_area.keypressed(&KeyAction::InputYankPop);
+        assert_eq!(buffer_str(&mut input_area), "one x");
+
+        // Kills after a cursor movement start a new entry
+        input_area.keypressed(&KeyAction::InputDeletePrevChar);
+        input_area.keypressed(&KeyAction::InputMoveCursLeft);
+        input_area.keypressed(&KeyAction::InputDeletePrevWord);
+        input_area.keypressed(&KeyAction::InputMoveCursRight);
+        input_area.keypressed(&KeyAction::InputDeletePrevWord);
+        assert_eq!(buffer_str(&mut input_area), "");
+        assert_eq!(input_area.kill_ring, vec!["two three", "one ", "one", " "]);
+    }
+
+    #[test]
+    fn word_editing_multi_byte() {
+        let mut input_area = InputArea::new(40, 50);
+        for c in "héllo, wörld 日本語".chars() {
+            input_area.keypressed(&KeyAction::Input(c));
+        }
+        input_area.keypressed(&KeyAction::InputMoveWordLeft);
+        assert_eq!(input_area.cursor, 13);
+        input_area.keypressed(&KeyAction::InputMoveWordLeft);
+        assert_eq!(input_area.cursor, 7);
+        input_area.keypressed(&KeyAction::InputMoveWordLeft);
+        assert_eq!(input_area.cursor, 6);
+        // Punctuation is skipped one character at a time
+        input_area.keypressed(&KeyAction::InputMoveWordLeft);
+        assert_eq!(input_area.cursor, 5);
+        input_area.keypressed(&KeyAction::InputMoveWordLeft);
+        assert_eq!(input_area.cursor, 0);
+        input_area.keypressed(&KeyAction::InputMoveWordRight);
+        assert_eq!(input_area.cursor, 5);
+        input_area.keypressed(&KeyAction::InputMoveWordRight);
+        assert_eq!(input_area.cursor, 6);
+        input_area.keypressed(&KeyAction::InputMoveWordRight);
+        assert_eq!(input_area.cursor, 7);
+        input_area.keypressed(&KeyAction::InputMoveWordRight);
+        assert_eq!(input_area.cursor, 13);
+
+        input_area.keypressed(&KeyAction::InputDeleteNextWord);
+        assert_eq!(buffer_str(&mut input_area), "héllo, wörld ");
+        input_area.keypressed(&KeyAction::InputMoveCursStart);
+        input_area.keypressed(&KeyAction::InputDeleteNextWord);
+        assert_eq!(buffer_str(&mut input_area), ", wörld ");
+        input_area.keypressed(&KeyAction::InputMoveCursEnd);
+        input_area.keypressed(&KeyAction::InputDeletePrevWord);
+        assert_eq!(buffer_str(&mut input_area), ", ");
+
+        // Combining marks are a part of the word
+        input_area.set("cafe\u{301} au");
+        input_area.keypressed(&KeyAction::InputMoveWordLeft);
+        input_area.keypressed(&KeyAction::InputMoveWordLeft);
+        assert_eq!(input_area.cursor, 0);
+        input_area.keypressed(&KeyAction::InputMoveWordRight);
+        assert_eq!(input_area.cursor, 6);
+        input_area.keypressed(&KeyAction::InputMoveCursLeft);
+        input_area.keypressed(&KeyAction::InputDeletePrevWord);
+        assert_eq!(buffer_str(&mut input_area), " au");
+    }
+
+    #[test]
+    fn delete_prev_big_word() {
+        let mut input_area = InputArea::new(40, 50);
+        for c in "see https://exämple.org/ö  ".chars() {
+            input_area.keypressed(&KeyAction::Input(c));
+        }
+        // Punctuation is a part of the word, trailing whitespace is deleted with the word
+        input_area.keypressed(&KeyAction::InputDeletePrevBigWord);
+        assert_eq!(buffer_str(&mut input_area), "see ");
+        input_area.keypressed(&KeyAction::InputDeletePrevBigWord);
+        assert_eq!(buffer_str(&mut input_area), "");
+        // Merged into one kill ring entry
+        assert_eq!(input_area.kill_ring, vec!["see https://exämple.org/ö  "]);
+    }
+
+    #[test]
+    fn transpose_chars() {
+        let mut input_area = InputArea::new(40, 50);
+        input_area.keypressed(&KeyAction::InputTransposeChars);
+        input_area.keypressed(&KeyAction::Input('ü'));
+        input_area.keypressed(&KeyAction::InputTransposeChars);
+        assert_eq!(buffer_str(&mut input_area), "ü");
+
+        for c in "日b".chars() {
+            input_area.keypressed(&KeyAction::Input(c));
+        }
+        // At the end of the line the last two characters are swapped
+        input_area.keypressed(&KeyAction::InputTransposeChars);
+        assert_eq!(buffer_str(&mut input_area), "üb日");
+        assert_eq!(input_area.cursor, 3);
+
+        // Otherwise the characters around the cursor are swapped and the cursor moves forward
+        input_area.keypressed(&KeyAction::InputMoveCursStart);
+        input_area.keypressed(&KeyAction::InputTransposeChars);
+        assert_eq!(buffer_str(&mut input_area), "üb日");
+        input_area.keypressed(&KeyAction::InputMoveCursRight);
+        input_area.keypressed(&KeyAction::InputTransposeChars);
+        assert_eq!(buffer_str(&mut input_area), "bü日");
+        assert_eq!(input_area.cursor, 2);
+
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "üb日");
+    }
+
+    #[test]
+    fn undo_completion() {
+        let mut dict = Trie::new();
+        dict.insert("osa1");
+        let mut input_area = InputArea::new(40, 50);
+        input_area.keypressed(&KeyAction::Input('o'));
+        input_area.autocomplete(&dict);
+        input_area.keypressed(&KeyAction::Input(':'));
+        assert_eq!(buffer_str(&mut input_area), "osa1:");
+
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "osa1");
+        input_area.keypressed(&KeyAction::InputUndo);
+        assert_eq!(buffer_str(&mut input_area), "o");
+        assert_eq!(input_area.cursor, 1);
+    }
 }
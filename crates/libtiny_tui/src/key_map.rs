@@ -16,6 +16,7 @@ pub(crate) enum KeyAction {
     Exit,
 
     RunEditor,
+    ShowHelp,
 
     TabNext,
     TabPrev,
@@ -29,6 +30,7 @@ pub(crate) enum KeyAction {
     MessagesScrollDown,
     MessagesScrollTop,
     MessagesScrollBottom,
+    MessagesToggleNetsplits,
 
     Input(char),
     Command(String),
@@ -41,12 +43,19 @@ pub(crate) enum KeyAction {
     InputDeleteToStart,
     InputDeleteToEnd,
     InputDeletePrevWord,
+    InputDeletePrevBigWord,
+    InputDeleteNextWord,
+    InputYank,
+    InputYankPop,
     InputMoveCursEnd,
     InputMoveCursStart,
     InputMoveCursLeft,
     InputMoveCursRight,
     InputMoveWordLeft,
     InputMoveWordRight,
+    InputTransposeChars,
+    InputUndo,
+    InputRedo,
 }
 
 impl Default for KeyMap {
@@ -55,6 +64,7 @@ impl Default for KeyMap {
             (Key::Esc, KeyAction::Cancel),
             (Key::Ctrl('c'), KeyAction::Exit),
             (Key::Ctrl('x'), KeyAction::RunEditor),
+            (Key::FKey(FKey::F1), KeyAction::ShowHelp),
             (Key::Ctrl('n'), KeyAction::TabNext),
             (Key::Ctrl('p'), KeyAction::TabPrev),
             (Key::AltArrow(Arrow::Left), KeyAction::TabMoveLeft),
@@ -79,6 +89,7 @@ impl Default for KeyMap {
             (Key::MouseWheelDown, KeyAction::MessagesScrollDown),
             (Key::Home, KeyAction::MessagesScrollTop),
             (Key::End, KeyAction::MessagesScrollBottom),
+            (Key::Ctrl('o'), KeyAction::MessagesToggleNetsplits),
             (Key::Tab, KeyAction::InputAutoComplete),
             (Key::Arrow(Arrow::Up), KeyAction::InputPrevEntry),
             (Key::Arrow(Arrow::Down), KeyAction::InputNextEntry),
@@ -90,10 +101,19 @@ impl Default for KeyMap {
             (Key::Ctrl('e'), KeyAction::InputMoveCursEnd),
             (Key::Ctrl('k'), KeyAction::InputDeleteToEnd),
             (Key::Ctrl('w'), KeyAction::InputDeletePrevWord),
+            (Key::AltBackspace, KeyAction::InputDeletePrevWord),
+            (Key::AltChar('d'), KeyAction::InputDeleteNextWord),
+            (Key::Ctrl('y'), KeyAction::InputYank),
+            (Key::AltChar('y'), KeyAction::InputYankPop),
             (Key::Arrow(Arrow::Left), KeyAction::InputMoveCursLeft),
             (Key::Arrow(Arrow::Right), KeyAction::InputMoveCursRight),
             (Key::CtrlArrow(Arrow::Left), KeyAction::InputMoveWordLeft),
             (Key::CtrlArrow(Arrow::Right), KeyAction::InputMoveWordRight),
+            (Key::AltChar('b'), KeyAction::InputMoveWordLeft),
+            (Key::AltChar('f'), KeyAction::InputMoveWordRight),
+            (Key::Ctrl('t'), KeyAction::InputTransposeChars),
+            (Key::Ctrl('/'), KeyAction::InputUndo),
+            (Key::AltChar('_'), KeyAction::InputRedo),
         ];
         let hash_map = map.into_iter().collect();
         KeyMap(hash_map)
@@ -108,6 +128,29 @@ impl KeyMap {
     pub(crate) fn load(&mut self, key_map: &KeyMap) {
         self.0.extend(key_map.0.clone())
     }
+
+    /// Bound actions with the keys bound to them, sorted by action name. Disabled keys are not
+    /// included.
+    pub(crate) fn bindings(&self) -> Vec<(String, Vec<String>)> {
+        let mut bindings: HashMap<String, Vec<String>> = HashMap::new();
+        for (key, action) in &self.0 {
+            let action = match action {
+                KeyAction::Disable => continue,
+                KeyAction::TabGoto(c) => format!("tab_goto {c}"),
+                action => action.to_string().trim_end().to_owned(),
+            };
+            bindings
+                .entry(action)
+                .or_default()
+                .push(KeyDisplay(*key).to_string());
+        }
+        let mut bindings: Vec<(String, Vec<String>)> = bindings.into_iter().collect();
+        for (_, keys) in &mut bindings {
+            keys.sort();
+        }
+        bindings.sort();
+        bindings
+    }
 }
 
 impl<'de> Deserialize<'de> for KeyMap {
@@ -212,6 +255,8 @@ impl<'de> Deserialize<'de> for MappedKey {
                         "alt" => {
                             if let Some(arrow) = parse_arrow(k2) {
                                 Key::AltArrow(arrow)
+                            } else if k2 == "backspace" {
+                                Key::AltBackspace
                             } else if let Some(f_key) = parse_f_key(k2) {
                                 Key::AltF(f_key)
                             } else {
@@ -234,6 +279,7 @@ impl<'de> Deserialize<'de> for MappedKey {
                                     // `ctrl_i`, which would otherwise be ignored.
                                     'i' => Key::Tab,
                                     '[' => Key::Esc,
+                                    '_' => Key::Ctrl('/'),
                                     other => Key::Ctrl(other),
                                 }
                             }
@@ -298,6 +344,7 @@ impl Display for KeyAction {
             KeyAction::Disable => "disable",
             KeyAction::Exit => "exit",
             KeyAction::RunEditor => "run_editor",
+            KeyAction::ShowHelp => "show_help",
             KeyAction::TabNext => "tab_next",
             KeyAction::TabPrev => "tab_prev",
             KeyAction::TabMoveLeft => "tab_move_left",
@@ -309,6 +356,7 @@ impl Display for KeyAction {
             KeyAction::MessagesScrollDown => "messages_scroll_down",
             KeyAction::MessagesScrollTop => "messages_scroll_top",
             KeyAction::MessagesScrollBottom => "messages_scroll_bottom",
+            KeyAction::MessagesToggleNetsplits => "messages_toggle_netsplits",
             KeyAction::Input(c) => return writeln!(f, "input_{c}"),
             KeyAction::Command(string) => return writeln!(f, "command_{string}"),
             KeyAction::InputAutoComplete => "input_auto_complete",
@@ -320,12 +368,19 @@ impl Display for KeyAction {
             KeyAction::InputDeleteToStart => "input_delete_to_start",
             KeyAction::InputDeleteToEnd => "input_delete_to_end",
             KeyAction::InputDeletePrevWord => "input_delete_prev_word",
+            KeyAction::InputDeletePrevBigWord => "input_delete_prev_big_word",
+            KeyAction::InputDeleteNextWord => "input_delete_next_word",
+            KeyAction::InputYank => "input_yank",
+            KeyAction::InputYankPop => "input_yank_pop",
             KeyAction::InputMoveCursEnd => "input_move_curs_end",
             KeyAction::InputMoveCursStart => "input_move_curs_start",
             KeyAction::InputMoveCursLeft => "input_move_curs_left",
             KeyAction::InputMoveCursRight => "input_move_curs_right",
             KeyAction::InputMoveWordLeft => "input_move_word_left",
             KeyAction::InputMoveWordRight => "input_move_word_right",
+            KeyAction::InputTransposeChars => "input_transpose_chars",
+            KeyAction::InputUndo => "input_undo",
+            KeyAction::InputRedo => "input_redo",
         };
         writeln!(f, "{s}")
     }
@@ -379,6 +434,7 @@ impl Display for KeyDisplay {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.0 {
             Key::AltArrow(arrow) => write!(f, "alt_{}", ArrowDisplay(arrow)),
+            Key::AltBackspace => write!(f, "alt_backspace"),
             Key::AltChar(char) => write!(f, "alt_{char}"),
             Key::AltF(fkey) => write!(f, "alt_{}", FKeyDisplay(fkey)),
             Key::Arrow(arrow) => write!(f, "{}", ArrowDisplay(arrow)),
@@ -429,9 +485,15 @@ fn deser_key() {
     let s = "alt__";
     let key: MappedKey = serde_yaml::from_str(s).unwrap();
     assert_eq!(Key::AltChar('_'), key.0);
+    let s = "alt_backspace";
+    let key: MappedKey = serde_yaml::from_str(s).unwrap();
+    assert_eq!(Key::AltBackspace, key.0);
     let s = "ctrl_/";
     let key: MappedKey = serde_yaml::from_str(s).unwrap();
     assert_eq!(Key::Ctrl('/'), key.0);
+    let s = "ctrl__";
+    let key: MappedKey = serde_yaml::from_str(s).unwrap();
+    assert_eq!(Key::Ctrl('/'), key.0);
     let s = "ctrl_f2";
     let key: MappedKey = serde_yaml::from_str(s).unwrap();
     assert_eq!(Key::CtrlF(FKey::F2), key.0);
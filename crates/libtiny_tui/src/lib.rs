@@ -4,6 +4,7 @@
 pub mod config;
 mod editor;
 mod exit_dialogue;
+mod help;
 mod input_area;
 mod key_map;
 mod line_split;
@@ -11,6 +12,7 @@ mod messaging;
 #[doc(hidden)]
 pub mod msg_area; // Public to be able to use in an example
 mod notifier;
+mod password_dialogue;
 mod tab;
 mod termbox;
 pub mod test_utils;
@@ -25,18 +27,22 @@ mod tests;
 
 use crate::tui::{CmdResult, TUIRet};
 use config::TabConfig;
-use libtiny_common::{ChanNameRef, Event, MsgSource, MsgTarget, TabStyle};
+use libtiny_common::{
+    CaseMapping, ChanNameRef, Event, MsgSource, MsgTarget, TabStyle, TypingState,
+};
 pub use notifier::Notifier;
 use term_input::Input;
+pub use tui::HistoryMsg;
 
 use std::cell::RefCell;
 use std::path::PathBuf;
 use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
 
 use time::Tm;
 use tokio::select;
 use tokio::signal::unix::{SignalKind, signal};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::task::spawn_local;
 use tokio_stream::wrappers::{ReceiverStream, SignalStream};
 use tokio_stream::{Stream, StreamExt};
@@ -62,6 +68,9 @@ impl TUI {
         // Spawn SIGWINCH handler
         spawn_local(sigwinch_handler(inner.clone(), rcv_abort));
 
+        // Spawn typing notification timer
+        spawn_local(typing_timer(inner.clone(), snd_ev.clone()));
+
         // Spawn input handler task
         let input = Input::new();
         spawn_local(input_handler(input, tui, snd_ev, snd_abort));
@@ -83,6 +92,9 @@ impl TUI {
         // We don't need to handle SIGWINCH in testing so the receiver end is not used
         let (snd_abort, _rcv_abort) = mpsc::channel::<()>(1);
 
+        // Spawn typing notification timer
+        spawn_local(typing_timer(inner.clone(), snd_ev.clone()));
+
         // Spawn input handler task
         spawn_local(input_handler(input_stream, tui, snd_ev, snd_abort));
 
@@ -126,6 +138,26 @@ async fn sigwinch_handler(tui: Weak<RefCell<tui::TUI>>, rcv_abort: mpsc::Receive
     }
 }
 
+/// Interval of `typing_timer` ticks.
+const TYPING_TICK: Duration = Duration::from_secs(1);
+
+/// Hides timed out typing notifications and pauses our typing. See `TUI::typing_tick`.
+async fn typing_timer(tui: Weak<RefCell<tui::TUI>>, snd_ev: mpsc::Sender<Event>) {
+    let mut interval = tokio::time::interval(TYPING_TICK);
+    loop {
+        interval.tick().await;
+        let Some(tui) = tui.upgrade() else {
+            return;
+        };
+        let mut tui = tui.borrow_mut();
+        if let Some((state, source)) = tui.typing_tick(Instant::now()) {
+            // Typing notifications are best-effort, don't block or panic when the channel is full
+            let _ = snd_ev.try_send(Event::Typing { state, source });
+        }
+        tui.draw();
+    }
+}
+
 async fn input_handler<S>(
     mut input_stream: S,
     tui: Rc<RefCell<tui::TUI>>,
@@ -218,6 +250,11 @@ async fn input_handler<S>(
             }
         }
 
+        for (state, source) in tui.borrow_mut().typing_update(Instant::now()) {
+            // Typing notifications are best-effort, don't block or panic when the channel is full
+            let _ = snd_ev.try_send(Event::Typing { state, source });
+        }
+
         tui.borrow_mut().draw();
     }
 }
@@ -238,13 +275,20 @@ impl TUI {
     delegate!(close_server_tab(serv_name: &str,));
     delegate!(new_chan_tab(serv_name: &str, chan: &ChanNameRef,));
     delegate!(close_chan_tab(serv_name: &str, chan: &ChanNameRef,));
+    delegate!(new_user_tab(serv_name: &str, nick: &str,));
     delegate!(close_user_tab(serv_name: &str, nick: &str,));
     delegate!(add_client_msg(msg: &str, target: &MsgTarget,));
     delegate!(add_msg(msg: &str, ts: Tm, target: &MsgTarget,));
+    delegate!(add_server_notice(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_err_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_client_err_msg(msg: &str, target: &MsgTarget,));
-    delegate!(clear_nicks(serv_name: &str,));
+    delegate!(add_client_notify_msg(msg: &str, target: &MsgTarget,));
+    delegate!(clear_nicks(target: &MsgTarget,));
     delegate!(set_nick(serv_name: &str, new_nick: &str,));
+    delegate!(set_away(serv_name: &str, away: bool,));
+    delegate!(set_chan_modes(serv_name: &str, chan: &ChanNameRef, modes: &str,));
+    delegate!(set_case_mapping(serv_name: &str, case_mapping: CaseMapping,));
+    delegate!(set_nick_away(serv_name: &str, nick: &str, away: bool,));
     delegate!(add_privmsg(
         sender: &str,
         msg: &str,
@@ -253,8 +297,34 @@ impl TUI {
         highlight: bool,
         is_action: bool,
     ));
+    delegate!(add_playback_privmsg(
+        sender: &str,
+        msg: &str,
+        ts: Tm,
+        target: &MsgTarget,
+        highlight: bool,
+        is_action: bool,
+    ));
+    delegate!(add_history(
+        msgs: &[HistoryMsg],
+        prepend: bool,
+        target: &MsgTarget,
+    ));
     delegate!(add_nick(nick: &str, ts: Option<Tm>, target: &MsgTarget,));
+    delegate!(add_nicks(nicks: &[String], target: &MsgTarget,));
     delegate!(remove_nick(nick: &str, ts: Option<Tm>, target: &MsgTarget,));
+    delegate!(netsplit_quit(
+        nick: &str,
+        servers: &str,
+        ts: Option<Tm>,
+        target: &MsgTarget,
+    ));
+    delegate!(netjoin(
+        nick: &str,
+        servers: &str,
+        ts: Option<Tm>,
+        target: &MsgTarget,
+    ));
     delegate!(rename_nick(
         old_nick: &str,
         new_nick: &str,
@@ -268,6 +338,9 @@ impl TUI {
         chan_name: &ChanNameRef,
     ));
     delegate!(set_tab_style(style: TabStyle, target: &MsgTarget,));
+    delegate!(set_typing(nick: &str, state: TypingState, target: &MsgTarget,));
+    delegate!(set_queued(n_queued: usize, target: &MsgTarget,));
+    delegate!(confirm(msg: &str, cmd: &str, target: &MsgTarget,));
 
     pub fn get_tab_config(&self, serv_name: &str, chan_name: Option<&ChanNameRef>) -> TabConfig {
         self.inner
@@ -288,6 +361,23 @@ impl TUI {
         }
     }
 
+    /// Ask for a password in the server tab, and then ask `store_msg`, a yes/no question, e.g.
+    /// whether to store the password. The password is shown masked. The receiver gets the password
+    /// and whether the user answered yes, and fails when the user cancels.
+    pub fn ask_password(
+        &self,
+        serv_name: &str,
+        msg: &str,
+        store_msg: &str,
+    ) -> oneshot::Receiver<(String, bool)> {
+        let (snd, rcv) = oneshot::channel();
+        if let Some(tui) = self.inner.upgrade() {
+            tui.borrow_mut()
+                .ask_password(serv_name, msg, store_msg, snd);
+        }
+        rcv
+    }
+
     pub fn user_tab_exists(&self, serv_name: &str, nick: &str) -> bool {
         match self.inner.upgrade() {
             Some(tui) => tui.borrow().user_tab_exists(serv_name, nick),
@@ -295,6 +385,13 @@ impl TUI {
         }
     }
 
+    pub fn confirm_close(&self, src: &MsgSource, cmd: &str) -> bool {
+        match self.inner.upgrade() {
+            Some(tui) => tui.borrow_mut().confirm_close(src, cmd),
+            None => false,
+        }
+    }
+
     pub fn current_tab(&self) -> Option<MsgSource> {
         self.inner
             .upgrade()
@@ -17,6 +17,12 @@ pub(crate) struct LineDataCache {
     current_line_length: i32,
     /// The type of line we're trying to calculate height for
     line_type: LineType,
+    /// Char index ranges (start inclusive, end exclusive) of URLs in the line that should not be
+    /// split unless they are longer than the width, in which case they are rendered starting from
+    /// the first column of a new line. See `set_urls`.
+    urls: Vec<(i32, i32)>,
+    /// Subset of `split_indices` where the line should be drawn without the alignment padding.
+    unpadded_splits: Vec<i32>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -53,6 +59,8 @@ impl LineDataCache {
             last_whitespace_idx: None,
             current_line_length: 0,
             line_type: LineType::Input { nick_length },
+            urls: Vec::new(),
+            unpadded_splits: Vec::new(),
         }
     }
 
@@ -70,6 +78,8 @@ impl LineDataCache {
             last_whitespace_idx: None,
             current_line_length: 0,
             line_type,
+            urls: Vec::new(),
+            unpadded_splits: Vec::new(),
         }
     }
 
@@ -81,6 +91,12 @@ impl LineDataCache {
         self.line_type = line_type
     }
 
+    /// Set the URLs in the line to keep unbroken. Only used for message lines. Should be called
+    /// before `calculate_height`, with sorted and non-overlapping ranges.
+    pub(crate) fn set_urls(&mut self, urls: Vec<(i32, i32)>) {
+        self.urls = urls;
+    }
+
     /// Performs a check to see if the width or nickname length changed
     /// which would require an invalidation of the cache and recalculation of
     /// the line height.
@@ -113,10 +129,17 @@ impl LineDataCache {
         &self.split_indices
     }
 
-    pub(crate) fn new_line_offset(&self) -> i32 {
+    /// Column offset of the line starting at the given split index.
+    pub(crate) fn new_line_offset(&self, split_idx: i32) -> i32 {
         match self.line_type {
             LineType::Input { .. } | LineType::Msg => 0,
-            LineType::AlignedMsg { msg_padding } => msg_padding as i32,
+            LineType::AlignedMsg { msg_padding } => {
+                if self.unpadded_splits.binary_search(&split_idx).is_ok() {
+                    0
+                } else {
+                    msg_padding as i32
+                }
+            }
         }
     }
 
@@ -151,8 +174,37 @@ impl LineDataCache {
             }
         }
 
+        // Index of the next URL in `urls`, and whether we're in a URL that doesn't fit in a line
+        let mut url_idx = 0;
+        let mut in_long_url = false;
+
         for (c, current_idx) in buffer.skip(offset).zip(offset..) {
             let current_idx = current_idx as i32;
+
+            if let Some(&(url_start, url_end)) = self.urls.get(url_idx) {
+                if current_idx == url_end {
+                    in_long_url = false;
+                    url_idx += 1;
+                } else if current_idx == url_start && url_end - url_start > self.multi_line_width()
+                {
+                    // URL will be split, start it on a new line, from the first column
+                    in_long_url = true;
+                    let line_start = self.split_indices.last().copied().unwrap_or(0);
+                    if self.current_line_length == 1 && line_start == current_idx - 1 {
+                        // The current line only has the (not drawn) whitespace before the URL
+                        self.unpadded_splits.push(line_start);
+                        self.current_line_length = 0;
+                    } else if self.current_line_length != 0 {
+                        temp_count += 1;
+                        self.current_line_length = 0;
+                        self.split_indices.push(current_idx);
+                        self.unpadded_splits.push(current_idx);
+                    }
+                    self.line_width = self.width;
+                    self.last_whitespace_idx = None;
+                }
+            }
+
             self.current_line_length += 1;
 
             if c.is_whitespace() {
@@ -175,7 +227,12 @@ impl LineDataCache {
                 // Splitting on non-whitespace
                 if self.current_line_length > self.line_width {
                     // set width to full width
-                    self.line_width = self.multi_line_width();
+                    self.line_width = if in_long_url {
+                        self.unpadded_splits.push(current_idx);
+                        self.width
+                    } else {
+                        self.multi_line_width()
+                    };
                     // if the previous character was a whitespace, then we have a clean split
                     if let Some(last_whitespace_idx) = self.last_whitespace_idx {
                         // if the split is larger than the width we have,
@@ -1,17 +1,24 @@
 use termbox_simple::Termbox;
 
 use std::convert::From;
+use std::time::{Duration, Instant};
 
 use time::{self, Tm};
+use tokio::sync::oneshot;
 
-use crate::config::Colors;
+use crate::config::{Colors, MsgAlign};
 use crate::exit_dialogue::ExitDialogue;
 use crate::input_area::InputArea;
 use crate::key_map::KeyAction;
 use crate::msg_area::line::SegStyle;
 use crate::msg_area::{Layout, MsgArea};
+use crate::password_dialogue::PasswordDialogue;
+use crate::termbox;
 use crate::trie::Trie;
+use crate::tui::HistoryMsg;
+use crate::utils::is_nick_char;
 use crate::widget::WidgetRet;
+use libtiny_wire::formatting::{IrcFormatEvent, parse_irc_formatting_indices};
 
 /// An input field and an area for showing messages and activities of a tab (channel, server,
 /// mentions tab).
@@ -19,12 +26,16 @@ pub(crate) struct MessagingUI {
     /// The area showing the messages and activities.
     msg_area: MsgArea,
 
-    /// The input field. `exit_dialogue` handles the input when available.
-    // Two fields (instead of an enum) to avoid borrowchk problems.
+    /// The input field. `exit_dialogue` or `password_dialogue` handles the input when available.
+    // Separate fields (instead of an enum) to avoid borrowchk problems.
     input_field: InputArea,
 
     exit_dialogue: Option<ExitDialogue>,
 
+    /// Shown when a password is needed, e.g. to connect to the server. `exit_dialogue` is shown
+    /// over it.
+    password_dialogue: Option<PasswordDialogue>,
+
     /// Width of the UI, in characters.
     width: i32,
 
@@ -37,16 +48,46 @@ pub(crate) struct MessagingUI {
     /// The last line in `msg_area` that shows join, leave, disconnect activities.
     last_activity_line: Option<ActivityLine>,
 
+    /// The last line in `msg_area` that shows quits caused by a netsplit, or joins after one.
+    last_netsplit_line: Option<NetsplitLine>,
+
     /// Last timestamp added to the UI.
     last_ts: Option<Timestamp>,
+
+    /// Nicks typing in the tab, with the times their typing notifications expire. Shown in a line
+    /// above the input field.
+    typing: Vec<(String, Instant)>,
+
+    /// Number of our messages waiting to be sent because of flood protection. Shown in the typing
+    /// line.
+    n_queued: usize,
+
+    /// Parts of the widget that changed since the last `draw`.
+    damage: Damage,
 }
 
+/// Parts of a `MessagingUI` that need to be redrawn.
+#[derive(Debug, Default, Clone, Copy)]
+struct Damage {
+    msg_area: bool,
+    input_field: bool,
+}
+
+/// How long to show a nick as typing after an `active` typing notification. The spec recommends
+/// senders to repeat `active` every 3 seconds, and receivers to time out after 6 seconds.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
 /// Length of ": " suffix of nicks in messages
 pub(crate) const MSG_NICK_SUFFIX_LEN: usize = 2;
 
-/// Like `time::Tm`, but we only care about hour and minute parts.
+/// Like `time::Tm`, but we only care about date, hour, and minute parts.
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub(crate) struct Timestamp {
+    /// Years since 1900, as in `time::Tm`.
+    year: i32,
+    /// Months since January, as in `time::Tm`.
+    mon: i32,
+    mday: i32,
     hour: i32,
     min: i32,
 }
@@ -66,11 +107,22 @@ impl Timestamp {
     fn stamp(&self) -> String {
         format!("{:02}:{:02} ", self.hour, self.min)
     }
+
+    fn same_day(&self, other: &Timestamp) -> bool {
+        (self.year, self.mon, self.mday) == (other.year, other.mon, other.mday)
+    }
+
+    fn date(&self) -> String {
+        format!("{}-{:02}-{:02}", self.year + 1900, self.mon + 1, self.mday)
+    }
 }
 
 impl From<Tm> for Timestamp {
     fn from(tm: Tm) -> Timestamp {
         Timestamp {
+            year: tm.tm_year,
+            mon: tm.tm_mon,
+            mday: tm.tm_mday,
             hour: tm.tm_hour,
             min: tm.tm_min,
         }
@@ -86,26 +138,52 @@ struct ActivityLine {
     line_idx: usize,
 }
 
+/// A line showing quits caused by a netsplit, or joins after the servers reconnect (netjoin),
+/// collapsed into a summary by default.
+struct NetsplitLine {
+    /// The servers that split, e.g. "irc.example.net irc2.example.net".
+    servers: String,
+
+    /// Whether the line shows joins rather than quits.
+    join: bool,
+
+    /// Index of the line in its `MsgArea`.
+    line_idx: usize,
+
+    /// Number of nicks shown in the line.
+    n_nicks: usize,
+}
+
 impl MessagingUI {
     pub(crate) fn new(
         width: i32,
         height: i32,
         scrollback: usize,
         msg_layout: Layout,
+        paste_safe_urls: bool,
     ) -> MessagingUI {
         MessagingUI {
-            msg_area: MsgArea::new(width, height - 1, scrollback, msg_layout),
+            msg_area: MsgArea::new(width, height - 1, scrollback, msg_layout, paste_safe_urls),
             input_field: InputArea::new(width, get_input_field_max_height(height)),
             exit_dialogue: None,
+            password_dialogue: None,
             width,
             height,
             nicks: Trie::new(),
             last_activity_line: None,
+            last_netsplit_line: None,
             last_ts: None,
+            typing: vec![],
+            n_queued: 0,
+            damage: Damage {
+                msg_area: true,
+                input_field: true,
+            },
         }
     }
 
     pub(crate) fn set_nick(&mut self, nick: String) {
+        self.msg_area.fit_nick(&nick);
         let nick_color = self.get_nick_color(&nick);
         self.input_field.set_nick(nick, nick_color);
         // update text field size
@@ -118,21 +196,103 @@ impl MessagingUI {
         self.input_field.get_nick()
     }
 
-    pub(crate) fn draw(&mut self, tb: &mut Termbox, colors: &Colors, pos_x: i32, pos_y: i32) {
-        match &self.exit_dialogue {
-            Some(exit_dialogue) => {
-                exit_dialogue.draw(tb, colors, pos_x, self.height - 1);
+    /// Mark the whole widget as changed, so that the next `draw` renders all of it. Needed when
+    /// the cells of the widget may have been overwritten, e.g. after switching tabs.
+    pub(crate) fn invalidate(&mut self) {
+        self.damage = Damage {
+            msg_area: true,
+            input_field: true,
+        };
+    }
+
+    /// Draw the parts of the widget that changed since the last call. Returns whether anything
+    /// was drawn.
+    pub(crate) fn draw(
+        &mut self,
+        tb: &mut Termbox,
+        colors: &Colors,
+        pos_x: i32,
+        pos_y: i32,
+    ) -> bool {
+        let Damage {
+            mut msg_area,
+            input_field,
+        } = std::mem::take(&mut self.damage);
+
+        if !msg_area && !input_field {
+            return false;
+        }
+
+        let typing_height = self.typing_height();
+
+        if input_field {
+            // A change in the input field height (or the typing line) also resizes (and moves the
+            // contents of) the message area
+            let input_field_height = self.input_field.get_height(self.width);
+            if self.height - typing_height - input_field_height != self.msg_area.get_height() {
+                msg_area = true;
             }
-            None => {
-                // Draw InputArea first because it can trigger a resize of MsgArea
-                self.input_field
-                    .draw(tb, colors, pos_x, pos_y, self.height, &mut self.msg_area);
+        }
+
+        // Rows of the widget to clear before drawing
+        let msg_area_height = self.msg_area.get_height();
+        let (clear_from, clear_to) = match (msg_area, input_field) {
+            (true, true) => (pos_y, pos_y + self.height),
+            (true, false) => (pos_y, pos_y + msg_area_height),
+            (false, _) => (pos_y + msg_area_height, pos_y + self.height),
+        };
+        tb.clear_lines(clear_from, clear_to);
+
+        if input_field {
+            match (&self.exit_dialogue, &self.password_dialogue) {
+                (Some(exit_dialogue), _) => {
+                    exit_dialogue.draw(tb, colors, pos_x, self.height - 1);
+                }
+                (None, Some(password_dialogue)) => {
+                    password_dialogue.draw(tb, colors, pos_x, self.height - 1);
+                }
+                (None, None) => {
+                    // Draw InputArea first because it can trigger a resize of MsgArea
+                    self.input_field.draw(
+                        tb,
+                        colors,
+                        pos_x,
+                        pos_y + typing_height,
+                        self.height - typing_height,
+                        &mut self.msg_area,
+                    );
+                    if let Some(typing_line) = self.typing_line() {
+                        termbox::print_chars(
+                            tb,
+                            pos_x,
+                            pos_y + self.msg_area.get_height(),
+                            colors.faded,
+                            typing_line.chars().take(self.width as usize),
+                        );
+                    }
+                }
             }
         }
-        self.msg_area.draw(tb, colors, pos_x, pos_y);
+
+        if msg_area {
+            self.msg_area.draw(tb, colors, pos_x, pos_y);
+        }
+
+        true
     }
 
     pub(crate) fn keypressed(&mut self, key_action: &KeyAction) -> WidgetRet {
+        match key_action {
+            KeyAction::MessagesPageUp
+            | KeyAction::MessagesPageDown
+            | KeyAction::MessagesScrollUp
+            | KeyAction::MessagesScrollDown
+            | KeyAction::MessagesScrollTop
+            | KeyAction::MessagesScrollBottom
+            | KeyAction::MessagesToggleNetsplits => self.damage.msg_area = true,
+            _ => self.damage.input_field = true,
+        }
+
         match key_action {
             KeyAction::Exit => {
                 self.toggle_exit_dialogue();
@@ -162,13 +322,29 @@ impl MessagingUI {
                 self.msg_area.scroll_bottom();
                 WidgetRet::KeyHandled
             }
+            KeyAction::MessagesToggleNetsplits => {
+                self.msg_area.toggle_folds();
+                WidgetRet::KeyHandled
+            }
             KeyAction::InputAutoComplete => {
-                if self.exit_dialogue.is_none() {
+                if !self.is_dialogue_shown() {
                     self.input_field.autocomplete(&self.nicks);
                 }
                 WidgetRet::KeyHandled
             }
             key_action => {
+                if self.exit_dialogue.is_none()
+                    && let Some(password_dialogue) = &mut self.password_dialogue
+                {
+                    return match password_dialogue.keypressed(key_action) {
+                        WidgetRet::Remove => {
+                            self.password_dialogue = None;
+                            WidgetRet::KeyHandled
+                        }
+                        ret => ret,
+                    };
+                }
+
                 let ret = {
                     if let Some(exit_dialogue) = self.exit_dialogue.as_ref() {
                         exit_dialogue.keypressed(key_action)
@@ -177,11 +353,16 @@ impl MessagingUI {
                     }
                 };
 
-                if let WidgetRet::Remove = ret {
-                    self.exit_dialogue = None;
-                    WidgetRet::KeyHandled
-                } else {
-                    ret
+                match ret {
+                    WidgetRet::Remove => {
+                        self.exit_dialogue = None;
+                        WidgetRet::KeyHandled
+                    }
+                    WidgetRet::SendInput => {
+                        self.exit_dialogue = None;
+                        self.input_field.keypressed(&KeyAction::InputSend)
+                    }
+                    ret => ret,
                 }
             }
         }
@@ -190,39 +371,99 @@ impl MessagingUI {
     pub(crate) fn resize(&mut self, width: i32, height: i32) {
         self.width = width;
         self.height = height;
+        self.invalidate();
 
         self.input_field
             .resize(width, get_input_field_max_height(height));
         // msg_area should resize based on input_field's rendered height
-        let msg_area_height = height - self.input_field.get_height(width);
+        let msg_area_height = height - self.typing_height() - self.input_field.get_height(width);
         self.msg_area.resize(width, msg_area_height);
 
         // We don't show the nick in exit dialogue, so it has the full width
         if let Some(exit_dialogue) = &mut self.exit_dialogue {
             exit_dialogue.resize(width);
         }
+        if let Some(password_dialogue) = &mut self.password_dialogue {
+            password_dialogue.resize(width);
+        }
+    }
+
+    /// Contents of the input field.
+    pub(crate) fn input_field_buffer(&self) -> &[char] {
+        self.input_field.get_buffer()
+    }
+
+    /// Scroll the message area to the last message.
+    pub(crate) fn scroll_bottom(&mut self) {
+        self.damage.msg_area = true;
+        self.msg_area.scroll_bottom();
+    }
+
+    pub(crate) fn set_paste_safe_urls(&mut self, paste_safe_urls: bool) {
+        self.damage.msg_area = true;
+        self.msg_area.set_paste_safe_urls(paste_safe_urls);
+    }
+
+    /// Set where to draw the messages when they don't fill the message area.
+    pub(crate) fn set_msg_align(&mut self, align: MsgAlign) {
+        self.damage.msg_area = true;
+        self.msg_area.set_align(align);
     }
 
     /// Get contents of the input field and cursor location and clear it.
     pub(crate) fn flush_input_field(&mut self) -> (String, i32) {
+        self.damage.input_field = true;
         self.input_field.flush()
     }
 
     /// Add a line to input field history.
     pub(crate) fn add_input_field_history(&mut self, str: &str) {
+        self.damage.input_field = true;
         self.input_field.add_history(str)
     }
 
     /// Set input field contents.
     pub(crate) fn set_input_field(&mut self, str: &str) {
+        self.damage.input_field = true;
         self.input_field.set(str)
     }
 
     /// Set cursor location in the input field.
     pub(crate) fn set_cursor(&mut self, cursor: i32) {
+        self.damage.input_field = true;
         self.input_field.set_cursor(cursor);
     }
 
+    /// Show a yes/no prompt instead of the input field. `cmd` is run when the user answers yes.
+    pub(crate) fn show_confirm_dialogue(&mut self, msg: String, cmd: String) {
+        self.damage.input_field = true;
+        self.exit_dialogue = Some(ExitDialogue::confirm(self.width, msg, cmd));
+    }
+
+    /// Ask the user before sending the input field contents. The input field is kept when the
+    /// user cancels.
+    pub(crate) fn show_confirm_send_dialogue(&mut self, msg: String) {
+        self.damage.input_field = true;
+        self.exit_dialogue = Some(ExitDialogue::confirm_send(self.width, msg));
+    }
+
+    /// Ask for a password instead of the input field, and then ask `store_msg`. See
+    /// `PasswordDialogue`.
+    pub(crate) fn show_password_dialogue(
+        &mut self,
+        msg: String,
+        store_msg: String,
+        snd: oneshot::Sender<(String, bool)>,
+    ) {
+        self.damage.input_field = true;
+        self.password_dialogue = Some(PasswordDialogue::new(self.width, msg, store_msg, snd));
+    }
+
+    /// Whether a prompt is shown instead of the input field.
+    pub(crate) fn is_dialogue_shown(&self) -> bool {
+        self.exit_dialogue.is_some() || self.password_dialogue.is_some()
+    }
+
     fn toggle_exit_dialogue(&mut self) {
         if self.exit_dialogue.take().is_none() {
             // We don't show the nick in exit dialogue, so it has the full width
@@ -244,8 +485,17 @@ impl MessagingUI {
     ///
     /// In compact layout this adds the indentation for the timestamp column if we're already
     /// showing the timestamp.
+    ///
+    /// When the date of `ts` is different than the date of the last timestamp (e.g. at midnight,
+    /// or when a bouncer replays old messages) this also adds a line showing the new date.
     fn add_timestamp(&mut self, ts: Timestamp) {
+        self.damage.msg_area = true;
         if let Some(ts_) = self.last_ts {
+            if !ts_.same_day(&ts) {
+                self.msg_area
+                    .add_text(&format!("Day changed to {}", ts.date()), SegStyle::Faded);
+                self.msg_area.flush_line();
+            }
             if ts_ != ts {
                 self.msg_area.add_text(&ts.stamp(), SegStyle::Timestamp);
             } else if self.msg_area.layout().is_aligned() {
@@ -267,16 +517,28 @@ impl MessagingUI {
     }
 
     pub(crate) fn add_client_err_msg(&mut self, msg: &str) {
+        self.damage.msg_area = true;
         self.msg_area.add_text(msg, SegStyle::ErrMsg);
         self.msg_area.flush_line();
     }
 
     pub(crate) fn add_client_notify_msg(&mut self, msg: &str) {
+        self.damage.msg_area = true;
         self.msg_area.add_text(msg, SegStyle::Faded);
+        self.msg_area.set_current_line_unsearchable();
+        self.msg_area.flush_line();
+    }
+
+    /// Add a line of `/grep` results. Not included in later searches.
+    pub(crate) fn add_search_result(&mut self, msg: &str) {
+        self.damage.msg_area = true;
+        self.msg_area.add_text(msg, SegStyle::UserMsg);
+        self.msg_area.set_current_line_unsearchable();
         self.msg_area.flush_line();
     }
 
     pub(crate) fn add_client_msg(&mut self, msg: &str) {
+        self.damage.msg_area = true;
         self.msg_area.add_text(msg, SegStyle::UserMsg);
         self.msg_area.flush_line();
     }
@@ -294,6 +556,7 @@ impl MessagingUI {
         // we add the user to the nick list so that tab completion will complete their nick. See
         // #253 for details.
         self.nicks.insert(sender);
+        self.set_typing(sender, false, Instant::now());
 
         self.add_timestamp(ts);
 
@@ -301,17 +564,21 @@ impl MessagingUI {
         let nick_col_style = SegStyle::NickColor(nick_color);
 
         // actions are /me msgs so they don't show the nick in the nick column, but in the msg
-        let layout = self.msg_area.layout();
+        self.msg_area
+            .fit_nick(if is_action { "**" } else { sender });
+        let nick_col_width = self.msg_area.nick_col_width();
         let format_nick = |s: &str| -> String {
-            if let Layout::Aligned { max_nick_len, .. } = layout {
-                let mut aligned = format!("{s:>max_nick_len$.max_nick_len$}");
-                if s.len() > max_nick_len {
-                    aligned.pop();
-                    aligned.push('…');
+            match nick_col_width {
+                None => s.to_string(),
+                Some(width) if s.chars().count() > width => {
+                    // Truncate long nicks, with a marker
+                    let mut truncated: String = s.chars().take(width.saturating_sub(1)).collect();
+                    if width > 0 {
+                        truncated.push('…');
+                    }
+                    truncated
                 }
-                aligned
-            } else {
-                s.to_string()
+                Some(width) => format!("{s:>width$}"),
             }
         };
         if is_action {
@@ -328,14 +595,30 @@ impl MessagingUI {
             self.msg_area.add_text(": ", SegStyle::Faded);
         }
 
+        let nick = self.get_nick();
         let msg_style = if highlight {
             SegStyle::Highlight
+        } else if nick.as_deref() == Some(sender) {
+            SegStyle::OwnMsg
         } else {
             SegStyle::UserMsg
         };
 
-        self.msg_area.add_text(msg, msg_style);
+        let mentions = match nick {
+            Some(nick) if highlight => find_mentions(msg, &nick),
+            _ => vec![],
+        };
+        let mut msg_idx = 0;
+        for (start, end) in mentions {
+            self.msg_area.add_text(&msg[msg_idx..start], msg_style);
+            self.msg_area.add_text(&msg[start..end], SegStyle::Mention);
+            msg_idx = end;
+        }
+        self.msg_area.add_text(&msg[msg_idx..], msg_style);
         self.msg_area.set_current_line_alignment();
+        if highlight {
+            self.msg_area.set_current_line_highlight();
+        }
         self.msg_area.flush_line();
     }
 
@@ -345,6 +628,12 @@ impl MessagingUI {
         self.msg_area.flush_line();
     }
 
+    pub(crate) fn add_server_notice(&mut self, msg: &str, ts: Timestamp) {
+        self.add_timestamp(ts);
+        self.msg_area.add_text(msg, SegStyle::ServerNotice);
+        self.msg_area.flush_line();
+    }
+
     pub(crate) fn add_err_msg(&mut self, msg: &str, ts: Timestamp) {
         self.add_timestamp(ts);
         self.msg_area.add_text(msg, SegStyle::ErrMsg);
@@ -352,11 +641,62 @@ impl MessagingUI {
     }
 
     pub(crate) fn clear(&mut self) {
+        self.damage.msg_area = true;
         self.msg_area.clear();
         self.last_activity_line = None;
+        self.last_netsplit_line = None;
         self.last_ts = None;
     }
 
+    /// Add messages fetched from the server. With `prepend` the messages are added before the
+    /// existing lines, otherwise they're added after the existing lines followed by a line marking
+    /// the end of the history. Messages are not highlighted.
+    pub(crate) fn add_history(&mut self, msgs: &[HistoryMsg], prepend: bool) {
+        if msgs.is_empty() {
+            return;
+        }
+        self.damage.msg_area = true;
+        if prepend {
+            let saved = self.msg_area.take_lines();
+            let last_ts = self.last_ts.take();
+            for msg in msgs {
+                self.add_history_msg(msg);
+            }
+            let n_lines = self.msg_area.restore_lines(saved);
+            self.last_ts = last_ts;
+            if let Some(line) = &mut self.last_activity_line {
+                line.line_idx += n_lines;
+            }
+            if let Some(line) = &mut self.last_netsplit_line {
+                line.line_idx += n_lines;
+            }
+        } else {
+            for msg in msgs {
+                self.add_history_msg(msg);
+            }
+            self.msg_area.add_text("End of history", SegStyle::Faded);
+            self.msg_area.flush_line();
+            // Don't add activities of live messages to the lines before history
+            self.last_activity_line = None;
+            self.last_netsplit_line = None;
+        }
+    }
+
+    fn add_history_msg(&mut self, msg: &HistoryMsg) {
+        self.add_privmsg(
+            &msg.sender,
+            &msg.msg,
+            Timestamp::from(msg.ts),
+            false,
+            msg.is_action,
+        );
+    }
+
+    /// Search messages in the tab. See `MsgArea::search`.
+    pub(crate) fn search(&self, query: &str, ignore_case: bool) -> Vec<(Option<String>, String)> {
+        self.msg_area.search(query, ignore_case)
+    }
+
     fn get_nick_color(&self, sender: &str) -> usize {
         // Anything works as long as it's fast
         let mut hash: usize = 5381;
@@ -375,8 +715,16 @@ impl MessagingUI {
         self.nicks.clear();
     }
 
+    pub(crate) fn add_nicks(&mut self, nicks: &[String]) {
+        for nick in nicks {
+            self.nicks.insert(nick);
+            self.msg_area.fit_nick(nick);
+        }
+    }
+
     pub(crate) fn join(&mut self, nick: &str, ts: Option<Timestamp>, ignore: bool) {
         self.nicks.insert(nick);
+        self.msg_area.fit_nick(nick);
 
         if !ignore && let Some(ts) = ts {
             let line_idx = self.get_activity_line_idx(ts);
@@ -389,6 +737,7 @@ impl MessagingUI {
 
     pub(crate) fn part(&mut self, nick: &str, ts: Option<Timestamp>, ignore: bool) {
         self.nicks.remove(nick);
+        self.set_typing(nick, false, Instant::now());
 
         if !ignore && let Some(ts) = ts {
             let line_idx = self.get_activity_line_idx(ts);
@@ -399,9 +748,101 @@ impl MessagingUI {
         }
     }
 
+    /// Like `part`, but for quits caused by a netsplit between `servers`. Consecutive quits from
+    /// the same netsplit are shown in one line, collapsed into a summary unless expanded with
+    /// `KeyAction::MessagesToggleNetsplits`.
+    pub(crate) fn netsplit_quit(
+        &mut self,
+        nick: &str,
+        servers: &str,
+        ts: Option<Timestamp>,
+        ignore: bool,
+    ) {
+        self.nicks.remove(nick);
+        self.set_typing(nick, false, Instant::now());
+
+        if !ignore && let Some(ts) = ts {
+            self.add_netsplit_nick(nick, servers, ts, false);
+        }
+    }
+
+    /// Like `join`, but for joins in a netjoin, after the netsplit between `servers` is over.
+    /// Shown like `netsplit_quit`.
+    pub(crate) fn netjoin(
+        &mut self,
+        nick: &str,
+        servers: &str,
+        ts: Option<Timestamp>,
+        ignore: bool,
+    ) {
+        self.nicks.insert(nick);
+        self.msg_area.fit_nick(nick);
+
+        if !ignore && let Some(ts) = ts {
+            self.add_netsplit_nick(nick, servers, ts, true);
+        }
+    }
+
+    fn add_netsplit_nick(&mut self, nick: &str, servers: &str, ts: Timestamp, join: bool) {
+        let (line_idx, n_nicks) = match &mut self.last_netsplit_line {
+            Some(l)
+                if l.servers == servers
+                    && l.join == join
+                    && Some(l.line_idx) == self.msg_area.num_lines().checked_sub(1) =>
+            {
+                l.n_nicks += 1;
+                (l.line_idx, l.n_nicks)
+            }
+            _ => {
+                self.add_timestamp(ts);
+                if let Some(nick_col_width) = self.msg_area.nick_col_width() {
+                    self.msg_area.add_text(
+                        &WHITESPACE[..nick_col_width + MSG_NICK_SUFFIX_LEN],
+                        SegStyle::UserMsg,
+                    )
+                }
+                self.msg_area.set_current_line_alignment();
+                let header = if join { "Netjoin" } else { "Netsplit" };
+                self.msg_area
+                    .add_text(&format!("{header} ({servers}):"), SegStyle::Faded);
+                let line_idx = self.msg_area.flush_line();
+                let expanded = self.msg_area.folds_expanded();
+                self.msg_area
+                    .modify_line(line_idx, |line| line.fold(expanded));
+                self.last_netsplit_line = Some(NetsplitLine {
+                    servers: servers.to_owned(),
+                    join,
+                    line_idx,
+                    n_nicks: 1,
+                });
+                (line_idx, 1)
+            }
+        };
+
+        self.damage.msg_area = true;
+        let verb = if join { "joined" } else { "quit" };
+        let summary = if n_nicks == 1 {
+            format!(" 1 user {verb}")
+        } else {
+            format!(" {n_nicks} users {verb}")
+        };
+        self.msg_area.modify_line(line_idx, |line| {
+            line.add_char(' ', SegStyle::UserMsg);
+            if join {
+                line.add_char('+', SegStyle::Join);
+            } else {
+                line.add_char('-', SegStyle::Part);
+            }
+            line.add_text(nick, SegStyle::Faded);
+            line.set_fold_summary(&summary, SegStyle::Faded);
+        });
+    }
+
     pub(crate) fn nick(&mut self, old_nick: &str, new_nick: &str, ts: Timestamp) {
         self.nicks.remove(old_nick);
         self.nicks.insert(new_nick);
+        self.msg_area.fit_nick(new_nick);
+        self.set_typing(old_nick, false, Instant::now());
 
         let line_idx = self.get_activity_line_idx(ts);
         self.msg_area.modify_line(line_idx, |line| {
@@ -411,7 +852,65 @@ impl MessagingUI {
         });
     }
 
+    /// Show or hide `nick` in the typing line. `typing` nicks are hidden after `TYPING_TIMEOUT`
+    /// unless refreshed.
+    pub(crate) fn set_typing(&mut self, nick: &str, typing: bool, now: Instant) {
+        let idx = self.typing.iter().position(|(nick_, _)| nick_ == nick);
+        match (idx, typing) {
+            (Some(idx), true) => self.typing[idx].1 = now + TYPING_TIMEOUT,
+            (None, true) => {
+                self.typing.push((nick.to_owned(), now + TYPING_TIMEOUT));
+                self.damage.input_field = true;
+            }
+            (Some(idx), false) => {
+                self.typing.remove(idx);
+                self.damage.input_field = true;
+            }
+            (None, false) => {}
+        }
+    }
+
+    /// Hide nicks whose typing notifications timed out.
+    pub(crate) fn expire_typing(&mut self, now: Instant) {
+        let n_typing = self.typing.len();
+        self.typing.retain(|(_, expires)| *expires > now);
+        if self.typing.len() != n_typing {
+            self.damage.input_field = true;
+        }
+    }
+
+    /// Show the number of our messages waiting to be sent in the typing line. 0 hides it.
+    pub(crate) fn set_queued(&mut self, n_queued: usize) {
+        if self.n_queued != n_queued {
+            self.n_queued = n_queued;
+            self.damage.input_field = true;
+        }
+    }
+
+    fn typing_line(&self) -> Option<String> {
+        let typing = match self.typing.as_slice() {
+            [] => None,
+            [(nick, _)] => Some(format!("{nick} is typing…")),
+            [(nick1, _), (nick2, _)] => Some(format!("{nick1} and {nick2} are typing…")),
+            typing => Some(format!("{} people are typing…", typing.len())),
+        };
+        let queued = (self.n_queued != 0).then(|| format!("sending… ({} queued)", self.n_queued));
+        match (typing, queued) {
+            (Some(typing), Some(queued)) => Some(format!("{typing} {queued}")),
+            (typing, queued) => typing.or(queued),
+        }
+    }
+
+    fn typing_height(&self) -> i32 {
+        if self.typing.is_empty() && self.n_queued == 0 {
+            0
+        } else {
+            1
+        }
+    }
+
     fn get_activity_line_idx(&mut self, ts: Timestamp) -> usize {
+        self.damage.msg_area = true;
         match &self.last_activity_line {
             Some(l)
                 if l.ts == ts && Some(l.line_idx) == self.msg_area.num_lines().checked_sub(1) =>
@@ -428,9 +927,9 @@ impl MessagingUI {
             }
             _ => {
                 self.add_timestamp(ts);
-                if let Layout::Aligned { max_nick_len, .. } = self.msg_area.layout() {
+                if let Some(nick_col_width) = self.msg_area.nick_col_width() {
                     self.msg_area.add_text(
-                        &WHITESPACE[..max_nick_len + MSG_NICK_SUFFIX_LEN],
+                        &WHITESPACE[..nick_col_width + MSG_NICK_SUFFIX_LEN],
                         SegStyle::UserMsg,
                     )
                 }
@@ -442,3 +941,52 @@ impl MessagingUI {
         }
     }
 }
+
+/// Byte ranges of the occurrences of `nick` in `msg` that are not a part of a longer nick. IRC
+/// formatting characters are skipped when searching, so mentions in colored text are found too.
+fn find_mentions(msg: &str, nick: &str) -> Vec<(usize, usize)> {
+    if nick.is_empty() {
+        return vec![];
+    }
+
+    // `msg` without the formatting characters, and start indices of the text chunks in it and in
+    // `msg`
+    let mut text = String::with_capacity(msg.len());
+    let mut chunks: Vec<(usize, usize)> = vec![];
+    for (msg_idx, event) in parse_irc_formatting_indices(msg) {
+        if let IrcFormatEvent::Text(chunk) = event {
+            chunks.push((text.len(), msg_idx));
+            text.push_str(chunk);
+        }
+    }
+
+    // Index of the byte at `text_idx` in `msg`. Chunks for tabs and newlines are not slices of
+    // `msg`, but they're whitespace, so mentions never start or end in them.
+    let msg_idx = |text_idx: usize| -> usize {
+        let (text_start, msg_start) = chunks
+            .iter()
+            .rev()
+            .find(|(text_start, _)| *text_start <= text_idx)
+            .unwrap();
+        msg_start + (text_idx - text_start)
+    };
+
+    text.match_indices(nick)
+        .map(|(start, _)| (start, start + nick.len()))
+        .filter(|&(start, end)| {
+            !text[..start].chars().next_back().is_some_and(is_nick_char)
+                && !text[end..].chars().next().is_some_and(is_nick_char)
+        })
+        .map(|(start, end)| (msg_idx(start), msg_idx(end - 1) + 1))
+        .collect()
+}
+
+#[test]
+fn test_find_mentions_tabs() {
+    assert_eq!(find_mentions("osa1:\thi", "osa1"), vec![(0, 4)]);
+    assert_eq!(find_mentions("hi\tosa1", "osa1"), vec![(3, 7)]);
+    assert_eq!(
+        find_mentions("\x0304osa1\x03:\r\nosa1", "osa1"),
+        vec![(3, 7), (11, 15)]
+    );
+}
@@ -15,6 +15,32 @@ pub(crate) struct Line {
     current_seg: StyledString,
 
     line_data: LineDataCache,
+
+    /// Whether to avoid splitting URLs in the middle when wrapping. See
+    /// `LineDataCache::set_urls`.
+    paste_safe_urls: bool,
+
+    /// When set, the rest of the line after a point can be collapsed into a summary.
+    fold: Option<Fold>,
+
+    /// Whether the line is a message that mentions us. Drawn with `Colors::highlight_line`.
+    highlight: bool,
+
+    /// Whether the line is included in `MsgArea::search` results. Lines added by the client
+    /// (e.g. search results) are not.
+    searchable: bool,
+}
+
+/// A collapsible part of a line. See `Line::fold`.
+#[derive(Debug)]
+struct Fold {
+    /// Number of segments shown when the fold is collapsed.
+    at: usize,
+
+    /// Shown instead of the segments after `at` when the fold is collapsed.
+    summary: StyledString,
+
+    expanded: bool,
 }
 
 #[derive(Debug)]
@@ -41,7 +67,13 @@ pub(crate) enum SegStyle {
     Part,
     Nick,
     Faded,
+    /// Server notices and WALLOPS.
+    ServerNotice,
     Highlight,
+    /// Our nick in a message that mentions us.
+    Mention,
+    /// A message we sent.
+    OwnMsg,
     Timestamp,
 }
 
@@ -61,7 +93,10 @@ impl StyledString {
             Part => colors.part,
             Nick => colors.nick_change,
             Faded => colors.faded,
+            ServerNotice => colors.server_notice,
             Highlight => colors.highlight,
+            Mention => colors.mention,
+            OwnMsg => colors.own_msg,
             Timestamp => colors.timestamp,
         }
     }
@@ -82,6 +117,94 @@ impl Line {
             segments: vec![],
             current_seg: StyledString::default(),
             line_data: LineDataCache::msg_line(0, None),
+            paste_safe_urls: false,
+            fold: None,
+            highlight: false,
+            searchable: true,
+        }
+    }
+
+    pub(crate) fn set_highlight(&mut self) {
+        self.highlight = true;
+    }
+
+    pub(crate) fn is_highlight(&self) -> bool {
+        self.highlight
+    }
+
+    pub(crate) fn set_unsearchable(&mut self) {
+        self.searchable = false;
+    }
+
+    pub(crate) fn is_searchable(&self) -> bool {
+        self.searchable
+    }
+
+    /// Make the text added to the line after this call collapsible into a summary. See
+    /// `set_fold_summary`.
+    pub(crate) fn fold(&mut self, expanded: bool) {
+        if !self.current_seg.string.is_empty() {
+            let style = self.current_seg.style;
+            let seg = std::mem::replace(
+                &mut self.current_seg,
+                StyledString {
+                    string: String::new(),
+                    style,
+                },
+            );
+            self.segments.push(seg);
+        }
+        self.fold = Some(Fold {
+            at: self.segments.len(),
+            summary: StyledString::default(),
+            expanded,
+        });
+        self.line_data.set_dirty();
+    }
+
+    /// Set the text shown instead of the folded part of the line when it's collapsed.
+    pub(crate) fn set_fold_summary(&mut self, summary: &str, style: SegStyle) {
+        if let Some(fold) = &mut self.fold {
+            fold.summary = StyledString {
+                string: summary.to_owned(),
+                style,
+            };
+            self.line_data.set_dirty();
+        }
+    }
+
+    /// Expand or collapse the folded part of the line, if it has one.
+    pub(crate) fn set_fold_expanded(&mut self, expanded: bool) {
+        if let Some(fold) = &mut self.fold
+            && fold.expanded != expanded
+        {
+            fold.expanded = expanded;
+            self.line_data.set_dirty();
+        }
+    }
+
+    /// Segments of the line as shown on the screen. Replaces the folded part of the line with the
+    /// summary when the fold is collapsed.
+    fn visible_segments(&self) -> impl Iterator<Item = &StyledString> {
+        let (n_segs, summary) = match &self.fold {
+            Some(Fold {
+                at,
+                summary,
+                expanded: false,
+            }) => (*at, Some(summary)),
+            _ => (usize::MAX, None),
+        };
+        self.segments
+            .iter()
+            .chain(std::iter::once(&self.current_seg))
+            .take(n_segs)
+            .chain(summary)
+    }
+
+    pub(crate) fn set_paste_safe_urls(&mut self, paste_safe_urls: bool) {
+        if self.paste_safe_urls != paste_safe_urls {
+            self.paste_safe_urls = paste_safe_urls;
+            self.line_data.set_dirty();
         }
     }
 
@@ -169,6 +292,34 @@ impl Line {
         self.line_data.set_dirty();
     }
 
+    /// Timestamp of the line, if the line starts with one. Lines with the same timestamp as the
+    /// previous line don't repeat it, so this returns `None` for those.
+    pub(crate) fn timestamp(&self) -> Option<&str> {
+        self.segments
+            .iter()
+            .chain(std::iter::once(&self.current_seg))
+            .find(|seg| !seg.string.is_empty())
+            .filter(|seg| seg.style == SegStyle::Timestamp)
+            .map(|seg| seg.string.trim())
+            .filter(|ts| !ts.is_empty())
+    }
+
+    /// Text of the line without the timestamp and formatting, e.g. for searching.
+    pub(crate) fn text(&self) -> String {
+        let mut text = String::new();
+        for seg in self
+            .segments
+            .iter()
+            .chain(std::iter::once(&self.current_seg))
+        {
+            if seg.style != SegStyle::Timestamp {
+                text.push_str(&seg.string);
+            }
+        }
+        // Strip nick alignment padding
+        text.trim_start().to_owned()
+    }
+
     /// Add a single character to the line. The character should not be an IRC formatting character
     /// or other control characters like '\n' and '\r'.
     pub(crate) fn add_char(&mut self, char: char, style: SegStyle) {
@@ -182,13 +333,16 @@ impl Line {
     pub(crate) fn rendered_height(&mut self, width: i32) -> i32 {
         let msg_padding = self.line_type().msg_padding();
         if self.line_data.is_dirty() || self.line_data.needs_resize(width, 0, msg_padding) {
-            self.line_data = LineDataCache::msg_line(width, msg_padding);
-            let mut full_line = self
-                .segments
-                .iter()
-                .flat_map(|s| s.string.chars())
-                .chain(self.current_seg.string.chars());
-            self.line_data.calculate_height(&mut full_line, 0);
+            let mut line_data = LineDataCache::msg_line(width, msg_padding);
+            let full_line = self.visible_segments().flat_map(|s| s.string.chars());
+            if self.paste_safe_urls {
+                let chars: Vec<char> = full_line.collect();
+                line_data.set_urls(find_urls(&chars));
+                line_data.calculate_height(chars.into_iter(), 0);
+            } else {
+                line_data.calculate_height(full_line, 0);
+            }
+            self.line_data = line_data;
         }
         self.line_data.get_line_count().unwrap() as i32
     }
@@ -207,12 +361,17 @@ impl Line {
         let mut char_idx = 0;
         let mut split_indices_iter = self.line_data.get_splits().iter().copied().peekable();
 
-        for seg in self
-            .segments
-            .iter()
-            .chain(std::iter::once(&self.current_seg))
-        {
-            let sty = seg.style(colors);
+        for seg in self.visible_segments() {
+            let mut sty = seg.style(colors);
+            // Colors of highlighted lines are used when the segment doesn't have a color
+            if self.highlight {
+                if sty.fg & 0xFF == termbox_simple::TB_DEFAULT {
+                    sty.fg |= colors.highlight_line.fg;
+                }
+                if sty.bg == termbox_simple::TB_DEFAULT {
+                    sty.bg = colors.highlight_line.bg;
+                }
+            }
             for c in seg.string.chars() {
                 // If split_indices_iter yields we already know the indices for the start of each line. If it
                 // does not then we just continue outputting on this line.
@@ -225,7 +384,7 @@ impl Line {
                         break;
                     }
                     // Reset column
-                    col = pos_x + self.line_data.new_line_offset();
+                    col = pos_x + self.line_data.new_line_offset(*next_line_start);
 
                     // Move to the next line start index
                     split_indices_iter.next();
@@ -248,6 +407,35 @@ impl Line {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+const URL_PREFIXES: [&str; 4] = ["http://", "https://", "ftp://", "www."];
+
+/// Find char index ranges (start inclusive, end exclusive) of URLs in the given line. A URL is a
+/// whitespace-separated word that starts with one of `URL_PREFIXES`.
+fn find_urls(chars: &[char]) -> Vec<(i32, i32)> {
+    let mut urls = vec![];
+    let mut idx = 0;
+    while idx < chars.len() {
+        if chars[idx].is_whitespace() {
+            idx += 1;
+            continue;
+        }
+        let word_start = idx;
+        while idx < chars.len() && !chars[idx].is_whitespace() {
+            idx += 1;
+        }
+        let word = &chars[word_start..idx];
+        let is_url = URL_PREFIXES.iter().any(|pfx| {
+            word.len() > pfx.len() && word.iter().zip(pfx.chars()).all(|(c1, c2)| *c1 == c2)
+        });
+        if is_url {
+            urls.push((word_start as i32, idx as i32));
+        }
+    }
+    urls
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 // Termbox colors: http://www.calmar.ws/vim/256-xterm-24bit-rgb-color-chart.html
 //                 (alternatively just run `cargo run --example colors`)
 fn irc_color_to_termbox(irc_color: Color) -> u8 {
@@ -371,3 +559,43 @@ fn align_test() {
 
     assert_eq!(line.rendered_height(3), 4);
 }
+
+#[test]
+fn find_urls_test() {
+    let chars: Vec<char> = "see https://a.b/c and www.x.org, not http:// ftp"
+        .chars()
+        .collect();
+    assert_eq!(find_urls(&chars), vec![(4, 17), (22, 32)]);
+}
+
+#[test]
+fn paste_safe_urls_test() {
+    let mut line = Line::new();
+    line.set_paste_safe_urls(true);
+    line.set_type(LineType::AlignedMsg { msg_padding: 2 });
+    /*
+    ab cd
+    https
+    ://x.
+    y ef
+    */
+    line.add_text("ab cd https://x.y ef", SegStyle::UserMsg);
+    assert_eq!(line.rendered_height(5), 4);
+    assert_eq!(line.line_data.get_splits(), &[5, 11, 16]);
+    assert_eq!(line.line_data.new_line_offset(5), 0);
+    assert_eq!(line.line_data.new_line_offset(11), 0);
+    assert_eq!(line.line_data.new_line_offset(16), 0);
+
+    // Other lines are still padded
+    line.add_text(" gh", SegStyle::UserMsg);
+    assert_eq!(line.rendered_height(5), 5);
+    assert_eq!(line.line_data.get_splits(), &[5, 11, 16, 21]);
+    assert_eq!(line.line_data.new_line_offset(21), 2);
+
+    // URLs that fit in a line are moved to the next line as usual
+    let mut line = Line::new();
+    line.set_paste_safe_urls(true);
+    line.add_text("ab www.x.y", SegStyle::UserMsg);
+    assert_eq!(line.rendered_height(8), 2);
+    assert_eq!(line.line_data.get_splits(), &[3]);
+}
@@ -5,9 +5,10 @@ use std::{cmp::max, mem, str};
 use termbox_simple::Termbox;
 
 pub(crate) use self::line::{Line, SegStyle};
-use crate::config::Colors;
+use crate::config::{Colors, MsgAlign};
 use crate::line_split::LineType;
 use crate::messaging::{MSG_NICK_SUFFIX_LEN, Timestamp};
+use crate::termbox;
 
 pub(crate) struct MsgArea {
     lines: VecDeque<Line>,
@@ -22,6 +23,10 @@ pub(crate) struct MsgArea {
     /// bottom of screen.
     scroll: i32,
 
+    /// Whether lines were added below the visible lines since scrolling up. Shown with a
+    /// `-- more --` line at the bottom until scrolled back to the bottom.
+    more_below: bool,
+
     line_buf: Line,
 
     /// Cached total rendered height of all lines. Invalidate on resize, update
@@ -29,6 +34,25 @@ pub(crate) struct MsgArea {
     lines_height: Option<i32>,
 
     layout: Layout,
+
+    /// Whether collapsible parts of lines (e.g. netsplit quits) are expanded. See `Line::fold`.
+    folds_expanded: bool,
+
+    /// Length of the longest nick added to the nick column, in characters. The nick column in
+    /// aligned layout is as wide as this, up to `max_nick_len`.
+    nick_len: usize,
+
+    /// Avoid splitting URLs when wrapping lines.
+    paste_safe_urls: bool,
+
+    /// Where to draw the lines when they don't fill the area.
+    align: MsgAlign,
+}
+
+/// Lines removed with `MsgArea::take_lines`.
+pub(crate) struct SavedLines {
+    lines: VecDeque<Line>,
+    scroll: i32,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -41,28 +65,30 @@ impl Layout {
     pub(crate) fn is_aligned(&self) -> bool {
         matches!(self, Layout::Aligned { .. })
     }
-
-    fn msg_padding(&self) -> usize {
-        match self {
-            Layout::Compact => 0,
-            Layout::Aligned { max_nick_len } => {
-                Timestamp::WIDTH + max_nick_len + MSG_NICK_SUFFIX_LEN
-            }
-        }
-    }
 }
 
 impl MsgArea {
-    pub(crate) fn new(width: i32, height: i32, scrollback: usize, layout: Layout) -> MsgArea {
+    pub(crate) fn new(
+        width: i32,
+        height: i32,
+        scrollback: usize,
+        layout: Layout,
+        paste_safe_urls: bool,
+    ) -> MsgArea {
         MsgArea {
             lines: VecDeque::with_capacity(512.min(scrollback)),
             scrollback,
             width,
             height,
             scroll: 0,
+            more_below: false,
             line_buf: Line::new(),
             lines_height: Some(0),
             layout,
+            folds_expanded: false,
+            nick_len: 0,
+            paste_safe_urls,
+            align: MsgAlign::default(),
         }
     }
 
@@ -89,16 +115,83 @@ impl MsgArea {
         self.layout
     }
 
+    /// Width of the nick column in aligned layout. `None` in compact layout.
+    pub(crate) fn nick_col_width(&self) -> Option<usize> {
+        match self.layout {
+            Layout::Compact => None,
+            Layout::Aligned { max_nick_len } => Some(self.nick_len.min(max_nick_len)),
+        }
+    }
+
+    /// Widen the nick column in aligned layout to fit the nick, up to `max_nick_len`. Lines that
+    /// are already added are not re-aligned.
+    pub(crate) fn fit_nick(&mut self, nick: &str) {
+        self.nick_len = self.nick_len.max(nick.chars().count());
+    }
+
+    pub(crate) fn set_align(&mut self, align: MsgAlign) {
+        self.align = align;
+    }
+
+    pub(crate) fn set_paste_safe_urls(&mut self, paste_safe_urls: bool) {
+        if self.paste_safe_urls == paste_safe_urls {
+            return;
+        }
+        self.paste_safe_urls = paste_safe_urls;
+        for line in &mut self.lines {
+            line.set_paste_safe_urls(paste_safe_urls);
+        }
+        // Same as resizing: line heights change, try to keep the scroll position
+        let (width, height) = (self.width, self.height);
+        self.resize(width, height);
+    }
+
+    pub(crate) fn folds_expanded(&self) -> bool {
+        self.folds_expanded
+    }
+
+    /// Expand or collapse the collapsible parts of all lines.
+    pub(crate) fn toggle_folds(&mut self) {
+        self.folds_expanded = !self.folds_expanded;
+        for line in &mut self.lines {
+            line.set_fold_expanded(self.folds_expanded);
+        }
+        // Same as resizing: line heights change, try to keep the scroll position
+        let (width, height) = (self.width, self.height);
+        self.resize(width, height);
+    }
+
     /// Used to force a line to be aligned.
     pub(crate) fn set_current_line_alignment(&mut self) {
-        let msg_padding = self.layout.msg_padding();
+        let msg_padding = match self.nick_col_width() {
+            None => 0,
+            Some(nick_col_width) => Timestamp::WIDTH + nick_col_width + MSG_NICK_SUFFIX_LEN,
+        };
         self.line_buf.set_type(LineType::AlignedMsg { msg_padding });
     }
 
+    /// Draw the current line with `Colors::highlight_line`.
+    pub(crate) fn set_current_line_highlight(&mut self) {
+        self.line_buf.set_highlight();
+    }
+
+    /// Leave the current line out of `search` results.
+    pub(crate) fn set_current_line_unsearchable(&mut self) {
+        self.line_buf.set_unsearchable();
+    }
+
     pub(crate) fn draw(&mut self, tb: &mut Termbox, colors: &Colors, pos_x: i32, pos_y: i32) {
         // Where to render current line
         let mut row = pos_y + self.height - 1;
 
+        // With top alignment lines that don't fill the area are drawn from the top
+        if self.align == MsgAlign::Top {
+            let total_height = self.update_total_visible_lines();
+            if total_height < self.height {
+                row = pos_y + total_height - 1;
+            }
+        }
+
         // How many visible lines to skip
         let mut skip = self.scroll;
 
@@ -125,6 +218,16 @@ impl MsgArea {
             // How many lines to skip in the `Line` before rendering
             let render_from = max(0, pos_y - line_row);
 
+            // Background of highlighted lines covers the whole width, not just the text
+            let highlight_bg = colors.highlight_line.bg;
+            if line.is_highlight() && highlight_bg != termbox_simple::TB_DEFAULT {
+                for row in line_row + render_from..line_row + height {
+                    for col in pos_x..pos_x + self.width {
+                        tb.change_cell(col, row, ' ', colors.highlight_line.fg, highlight_bg);
+                    }
+                }
+            }
+
             line.draw(tb, colors, pos_x, line_row, render_from, height);
             row = line_row - 1;
             line_idx -= 1;
@@ -134,9 +237,24 @@ impl MsgArea {
                 break;
             }
         }
+
+        if self.more_below && self.height > 0 {
+            let row = pos_y + self.height - 1;
+            tb.clear_lines(row, row + 1);
+            termbox::print_chars(
+                tb,
+                pos_x,
+                row,
+                colors.faded,
+                MORE_INDICATOR.chars().take(self.width as usize),
+            );
+        }
     }
 }
 
+/// Shown at the bottom of the message area when new lines are added while scrolled up.
+const MORE_INDICATOR: &str = "-- more --";
+
 ////////////////////////////////////////////////////////////////////////////////
 // Scrolling
 
@@ -166,6 +284,7 @@ impl MsgArea {
         if self.scroll > 0 {
             self.scroll -= 1;
         }
+        self.update_more_below();
     }
 
     pub(crate) fn scroll_top(&mut self) {
@@ -174,6 +293,7 @@ impl MsgArea {
 
     pub(crate) fn scroll_bottom(&mut self) {
         self.scroll = 0;
+        self.update_more_below();
     }
 
     pub(crate) fn page_up(&mut self) {
@@ -184,6 +304,14 @@ impl MsgArea {
 
     pub(crate) fn page_down(&mut self) {
         self.scroll = max(0, self.scroll - 10);
+        self.update_more_below();
+    }
+
+    /// Hide the `-- more --` indicator when scrolled to the bottom.
+    fn update_more_below(&mut self) {
+        if self.scroll == 0 {
+            self.more_below = false;
+        }
     }
 
     /// Recalculate the scroll offset due to resizing of the window
@@ -192,6 +320,7 @@ impl MsgArea {
             let ratio = (self.scroll as f32 + old_height as f32) / old_total_lines as f32;
             let total_lines = self.update_total_visible_lines();
             self.scroll = max(0, ((ratio * total_lines as f32) as i32) - self.height);
+            self.update_more_below();
         }
     }
 }
@@ -204,6 +333,7 @@ impl MsgArea {
     }
 
     pub(crate) fn flush_line(&mut self) -> usize {
+        self.line_buf.set_paste_safe_urls(self.paste_safe_urls);
         let line_height = self.line_buf.rendered_height(self.width);
         // Check if we're about to overflow
         let mut removed_line_height = 0;
@@ -217,6 +347,7 @@ impl MsgArea {
             .push_back(mem::replace(&mut self.line_buf, Line::new()));
         if self.scroll != 0 {
             self.scroll += line_height;
+            self.more_below = true;
         }
         if let Some(ref mut total_height) = self.lines_height {
             *total_height += line_height - removed_line_height;
@@ -231,10 +362,67 @@ impl MsgArea {
         f(&mut self.lines[idx]);
     }
 
+    /// Search lines for `query`. Returns timestamps and text of the matching lines, oldest first.
+    /// Lines marked with `set_current_line_unsearchable` are skipped.
+    pub(crate) fn search(&self, query: &str, ignore_case: bool) -> Vec<(Option<String>, String)> {
+        let query = if ignore_case {
+            query.to_lowercase()
+        } else {
+            query.to_owned()
+        };
+        let mut matches = vec![];
+        let mut last_ts: Option<&str> = None;
+        for line in &self.lines {
+            if let Some(ts) = line.timestamp() {
+                last_ts = Some(ts);
+            }
+            if !line.is_searchable() {
+                continue;
+            }
+            let text = line.text();
+            let found = if ignore_case {
+                text.to_lowercase().contains(&query)
+            } else {
+                text.contains(&query)
+            };
+            if found {
+                matches.push((last_ts.map(str::to_owned), text));
+            }
+        }
+        matches
+    }
+
+    /// Remove all lines, to be able to add lines before them. Removed lines are added back with
+    /// `restore_lines`.
+    pub(crate) fn take_lines(&mut self) -> SavedLines {
+        self.lines_height = Some(0);
+        SavedLines {
+            lines: mem::take(&mut self.lines),
+            scroll: mem::take(&mut self.scroll),
+        }
+    }
+
+    /// Add lines removed with `take_lines` after the current lines. Current lines that don't fit
+    /// into the scrollback are dropped. Returns the number of lines before the restored lines.
+    pub(crate) fn restore_lines(&mut self, saved: SavedLines) -> usize {
+        let SavedLines { lines, scroll } = saved;
+        while self.lines.len() > self.scrollback.saturating_sub(lines.len()) {
+            self.lines.pop_front();
+        }
+        let n_lines = self.lines.len();
+        self.lines.extend(lines);
+        // Scroll is an offset from the last line, so the visible lines don't change
+        self.scroll = scroll;
+        self.lines_height = None;
+        n_lines
+    }
+
     pub(crate) fn clear(&mut self) {
         self.lines.clear();
         self.scroll = 0;
+        self.more_below = false;
         self.lines_height = Some(0);
+        self.nick_len = 0;
     }
 }
 
@@ -243,9 +431,36 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn search() {
+        let mut msg_area = MsgArea::new(100, 1, usize::MAX, Layout::Compact, false);
+        msg_area.add_text("00:00 ", SegStyle::Timestamp);
+        msg_area.add_text("osa1", SegStyle::NickColor(0));
+        msg_area.add_text(": ", SegStyle::Faded);
+        msg_area.add_text("Hello world", SegStyle::UserMsg);
+        msg_area.flush_line();
+        msg_area.add_text("      ", SegStyle::Timestamp);
+        msg_area.add_text("hello again", SegStyle::UserMsg);
+        msg_area.flush_line();
+        msg_area.add_text("no match", SegStyle::UserMsg);
+        msg_area.flush_line();
+
+        assert_eq!(
+            msg_area.search("Hello", false),
+            vec![(Some("00:00".to_owned()), "osa1: Hello world".to_owned())]
+        );
+        assert_eq!(
+            msg_area.search("hello", true),
+            vec![
+                (Some("00:00".to_owned()), "osa1: Hello world".to_owned()),
+                (Some("00:00".to_owned()), "hello again".to_owned()),
+            ]
+        );
+    }
+
     #[test]
     fn newline_scrolling() {
-        let mut msg_area = MsgArea::new(100, 1, usize::MAX, Layout::Compact);
+        let mut msg_area = MsgArea::new(100, 1, usize::MAX, Layout::Compact, false);
         // Adding a new line when scroll is 0 should not change it
         assert_eq!(msg_area.scroll, 0);
         msg_area.add_text("line1", SegStyle::UserMsg);
@@ -263,10 +478,61 @@ mod tests {
         assert_eq!(msg_area.scroll, 2);
     }
 
+    #[test]
+    fn more_below() {
+        let mut msg_area = MsgArea::new(100, 1, usize::MAX, Layout::Compact, false);
+        for line in ["line1", "line2", "line3"] {
+            msg_area.add_text(line, SegStyle::UserMsg);
+            msg_area.flush_line();
+        }
+        assert!(!msg_area.more_below);
+
+        // Scrolling up doesn't show the indicator, new lines do
+        msg_area.scroll_up();
+        msg_area.scroll_up();
+        assert!(!msg_area.more_below);
+        msg_area.add_text("line4", SegStyle::UserMsg);
+        msg_area.flush_line();
+        assert!(msg_area.more_below);
+
+        // Hidden only when back at the bottom
+        msg_area.scroll_down();
+        assert!(msg_area.more_below);
+        msg_area.page_down();
+        assert!(!msg_area.more_below);
+    }
+
+    #[test]
+    fn test_restore_lines() {
+        let mut msg_area = MsgArea::new(100, 1, 4, Layout::Compact, false);
+        for line in ["new1", "new2"] {
+            msg_area.add_text(line, SegStyle::UserMsg);
+            msg_area.flush_line();
+        }
+        msg_area.scroll_up();
+        let saved = msg_area.take_lines();
+        for line in ["old1", "old2", "old3"] {
+            msg_area.add_text(line, SegStyle::UserMsg);
+            msg_area.flush_line();
+        }
+        // "old1" doesn't fit into the scrollback
+        assert_eq!(msg_area.restore_lines(saved), 2);
+        assert_eq!(msg_area.scroll, 1);
+        assert_eq!(
+            msg_area.search("", false),
+            vec![
+                (None, "old2".to_owned()),
+                (None, "old3".to_owned()),
+                (None, "new1".to_owned()),
+                (None, "new2".to_owned()),
+            ]
+        );
+    }
+
     #[test]
     fn test_max_lines() {
         // Can't show more than 3 lines.
-        let mut msg_area = MsgArea::new(100, 1, 3, Layout::Compact);
+        let mut msg_area = MsgArea::new(100, 1, 3, Layout::Compact, false);
         msg_area.add_text("first", SegStyle::UserMsg);
         msg_area.flush_line();
         msg_area.add_text("second", SegStyle::UserMsg);
@@ -56,26 +56,30 @@ impl Notifier {
         target: &MsgTarget,
         our_nick: &str,
         mention: bool,
+        is_action: bool,
     ) {
         if our_nick == sender {
             return;
         }
 
         let msg = remove_irc_control_chars(msg);
+        let msg = if is_action {
+            format!("* {sender} {msg}")
+        } else {
+            msg
+        };
 
         match *target {
-            MsgTarget::Chan { chan, .. } => {
-                if *self == Notifier::Messages || (*self == Notifier::Mentions && mention) {
-                    notify(&format!("{} in {}", sender, chan.display()), &msg)
-                }
+            MsgTarget::Chan { chan, .. }
+                if *self == Notifier::Messages || (*self == Notifier::Mentions && mention) =>
+            {
+                notify(&format!("{} in {}", sender, chan.display()), &msg)
             }
             MsgTarget::User {
                 nick: ref nick_sender,
                 ..
-            } => {
-                if *self != Notifier::Off {
-                    notify(&format!("{nick_sender} sent a private message"), &msg)
-                }
+            } if *self != Notifier::Off => {
+                notify(&format!("{nick_sender} sent a private message"), &msg)
             }
             _ => {}
         }
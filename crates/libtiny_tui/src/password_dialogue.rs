@@ -0,0 +1,111 @@
+use termbox_simple::Termbox;
+use tokio::sync::oneshot;
+
+use crate::key_map::KeyAction;
+use crate::{config::Colors, widget::WidgetRet};
+
+/// A password prompt shown instead of the input field. The password is shown masked. After the
+/// password is entered the user is asked whether to store it, and the password and the answer are
+/// sent to the receiver of `snd`. The sender is dropped when the user cancels.
+pub(crate) struct PasswordDialogue {
+    width: i32,
+
+    /// The prompt to show before the masked password.
+    msg: String,
+
+    /// The yes/no question asked after the password is entered.
+    store_msg: String,
+
+    password: String,
+
+    /// Whether the password is entered and `store_msg` is shown.
+    entered: bool,
+
+    snd: Option<oneshot::Sender<(String, bool)>>,
+}
+
+impl PasswordDialogue {
+    pub(crate) fn new(
+        width: i32,
+        msg: String,
+        store_msg: String,
+        snd: oneshot::Sender<(String, bool)>,
+    ) -> PasswordDialogue {
+        PasswordDialogue {
+            width,
+            msg,
+            store_msg,
+            password: String::new(),
+            entered: false,
+            snd: Some(snd),
+        }
+    }
+
+    pub(crate) fn resize(&mut self, width: i32) {
+        self.width = width;
+    }
+
+    pub(crate) fn draw(&self, tb: &mut Termbox, colors: &Colors, pos_x: i32, pos_y: i32) {
+        tb.hide_cursor();
+
+        let text = if self.entered {
+            self.store_msg.clone()
+        } else {
+            format!("{} {}", self.msg, "*".repeat(self.password.chars().count()))
+        };
+
+        let mut col = 0;
+        for char in text.chars().take(self.width as usize) {
+            tb.change_cell(
+                pos_x + col,
+                pos_y,
+                char,
+                colors.exit_dialogue.fg,
+                colors.exit_dialogue.bg,
+            );
+            col += 1;
+        }
+
+        while col < self.width {
+            tb.change_cell(
+                pos_x + col,
+                pos_y,
+                ' ',
+                colors.exit_dialogue.fg,
+                colors.exit_dialogue.bg,
+            );
+            col += 1;
+        }
+    }
+
+    pub(crate) fn keypressed(&mut self, key_action: &KeyAction) -> WidgetRet {
+        if self.entered {
+            let store = match key_action {
+                KeyAction::Input('y') => true,
+                KeyAction::Input('n') | KeyAction::Cancel => false,
+                _ => return WidgetRet::KeyIgnored,
+            };
+            if let Some(snd) = self.snd.take() {
+                let _ = snd.send((std::mem::take(&mut self.password), store));
+            }
+            return WidgetRet::Remove;
+        }
+
+        match key_action {
+            KeyAction::Input(char) => {
+                self.password.push(*char);
+                WidgetRet::KeyHandled
+            }
+            KeyAction::InputDeletePrevChar => {
+                self.password.pop();
+                WidgetRet::KeyHandled
+            }
+            KeyAction::InputSend => {
+                self.entered = !self.password.is_empty();
+                WidgetRet::KeyHandled
+            }
+            KeyAction::Cancel => WidgetRet::Remove,
+            _ => WidgetRet::KeyIgnored,
+        }
+    }
+}
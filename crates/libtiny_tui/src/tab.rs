@@ -1,6 +1,8 @@
 use libtiny_common::{MsgSource, TabStyle};
 use termbox_simple::{TB_UNDERLINE, Termbox};
 
+use std::time::Instant;
+
 use unicode_width::UnicodeWidthStr;
 
 use crate::{
@@ -15,8 +17,21 @@ pub(crate) struct Tab {
     pub(crate) style: TabStyle,
     /// Alt-character to use to switch to this tab.
     pub(crate) switch: Option<char>,
+    /// Number of highlights since the user's last message in this tab.
+    pub(crate) highlights: usize,
+    /// Whether the user is marked as away, shown after the tab name. In server tabs this is our
+    /// away status, in user tabs the other user's.
+    pub(crate) away: bool,
+    /// Modes of the channel (e.g. `+nt`) in channel tabs, shown after the tab name. Empty when
+    /// no modes are set or the modes are not known.
+    pub(crate) modes: String,
+    /// When the user last sent a message in this tab, or when the tab was created.
+    pub(crate) last_sent: Instant,
 }
 
+/// Shown after the tab name when the user is marked as away.
+const AWAY_SUFFIX: &str = " [away]";
+
 fn tab_style(style: TabStyle, colors: &Colors) -> Style {
     match style {
         TabStyle::Normal => colors.tab_normal,
@@ -43,7 +58,11 @@ impl Tab {
     }
 
     pub(crate) fn width(&self) -> i32 {
-        self.visible_name().width() as i32
+        let mut suffix_width = if self.away { AWAY_SUFFIX.len() } else { 0 };
+        if !self.modes.is_empty() {
+            suffix_width += self.modes.width() + 3; // " [" and "]"
+        }
+        (self.visible_name().width() + suffix_width) as i32
     }
 
     pub(crate) fn draw(
@@ -70,5 +89,19 @@ impl Tab {
             }
             pos_x += 1;
         }
+
+        if self.away {
+            for ch in AWAY_SUFFIX.chars() {
+                tb.change_cell(pos_x, pos_y, ch, style.fg, style.bg);
+                pos_x += 1;
+            }
+        }
+
+        if !self.modes.is_empty() {
+            for ch in format!(" [{}]", self.modes).chars() {
+                tb.change_cell(pos_x, pos_y, ch, style.fg, style.bg);
+                pos_x += 1;
+            }
+        }
     }
 }
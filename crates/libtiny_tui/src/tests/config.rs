@@ -12,7 +12,11 @@ fn parsing_tab_configs() {
               - name: "#tiny"
                 ignore: true
                 notify: "messages"
+                rejoin_on_kick: true
+                rejoin_delay: 10
             notify: "mentions"
+            ctcp_flood_limit: 3
+            hide_motd: true
           - addr: "server2"
             join:
               - "#tiny2" 
@@ -32,10 +36,20 @@ fn parsing_tab_configs() {
                     config: TabConfig {
                         ignore: Some(true),
                         notify: Some(Notifier::Messages),
+                        rejoin_on_kick: Some(true),
+                        rejoin_delay: Some(10),
+                        ctcp_flood_limit: None,
+                        hide_motd: None,
+                        hide_lusers: None,
+                        netsplit_timeout: None,
+                        wallops_mentions: None,
+                        bouncer_control_prefix: None,
                     },
                 }],
                 config: TabConfig {
                     notify: Some(Notifier::Mentions),
+                    ctcp_flood_limit: Some(3),
+                    hide_motd: Some(true),
                     ..Default::default()
                 },
             },
@@ -52,6 +66,7 @@ fn parsing_tab_configs() {
             tab_config: TabConfig {
                 ignore: Some(false),
                 notify: Some(Notifier::Off),
+                ..Default::default()
             },
         },
         ..Default::default()
@@ -63,7 +78,10 @@ fn parsing_tab_configs() {
         tab_configs.get("server", None),
         Some(TabConfig {
             ignore: Some(false),              // overwritten by defaults
-            notify: Some(Notifier::Mentions)  // configured
+            notify: Some(Notifier::Mentions), // configured
+            ctcp_flood_limit: Some(3),        // configured
+            hide_motd: Some(true),            // configured
+            ..Default::default()
         })
     );
 
@@ -71,7 +89,8 @@ fn parsing_tab_configs() {
         tab_configs.get("server2", None),
         Some(TabConfig {
             ignore: Some(true),          // configured
-            notify: Some(Notifier::Off)  // overwritten by defaults
+            notify: Some(Notifier::Off), // overwritten by defaults
+            ..Default::default()
         })
     );
 
@@ -81,7 +100,15 @@ fn parsing_tab_configs() {
         tab_configs.get("server", Some(ChanNameRef::new("#tiny"))),
         Some(TabConfig {
             ignore: Some(true),               // configured
-            notify: Some(Notifier::Messages)  // configured
+            notify: Some(Notifier::Messages), // configured
+            rejoin_on_kick: Some(true),       // configured
+            rejoin_delay: Some(10),           // configured
+            ctcp_flood_limit: Some(3),        // overwritten by server
+            hide_motd: Some(true),            // overwritten by server
+            hide_lusers: None,
+            netsplit_timeout: None,
+            wallops_mentions: None,
+            bouncer_control_prefix: None,
         })
     );
 
@@ -94,46 +121,87 @@ fn parsing_tab_configs() {
         tab_configs.get("server2", Some(ChanNameRef::new("#tiny2"))),
         Some(TabConfig {
             ignore: Some(true),          // overwritten by server
-            notify: Some(Notifier::Off)  // overwritten by defaults
+            notify: Some(Notifier::Off), // overwritten by defaults
+            ..Default::default()
         })
     );
 }
 
+#[test]
+fn parsing_max_nick() {
+    let config_str = r##"
+        servers: []
+        defaults: {}
+        max_nick: 8
+        "##;
+    let config: Config = serde_yaml::from_str(config_str).expect("parsed config");
+    assert_eq!(config.max_nick_length, 8);
+
+    let config: Config = serde_yaml::from_str("servers: []\ndefaults: {}").expect("parsed config");
+    assert_eq!(config.max_nick_length, 12);
+}
+
 #[test]
 fn tab_config_command() {
     assert_eq!(
         TabConfig::from_cmd_args("").unwrap(),
         TabConfig {
             ignore: None,
-            notify: None
+            notify: None,
+            ..Default::default()
         }
     );
     assert_eq!(
         TabConfig::from_cmd_args("-ignore").unwrap(),
         TabConfig {
             ignore: Some(true),
-            notify: None
+            notify: None,
+            ..Default::default()
         }
     );
     assert_eq!(
         TabConfig::from_cmd_args("-notify off").unwrap(),
         TabConfig {
             ignore: None,
-            notify: Some(Notifier::Off)
+            notify: Some(Notifier::Off),
+            ..Default::default()
         }
     );
     assert_eq!(
         TabConfig::from_cmd_args("-notify off -ignore").unwrap(),
         TabConfig {
             ignore: Some(true),
-            notify: Some(Notifier::Off)
+            notify: Some(Notifier::Off),
+            ..Default::default()
         }
     );
     assert_eq!(
         TabConfig::from_cmd_args("-ignore -notify off").unwrap(),
         TabConfig {
             ignore: Some(true),
-            notify: Some(Notifier::Off)
+            notify: Some(Notifier::Off),
+            ..Default::default()
         }
     );
+    assert_eq!(
+        TabConfig::from_cmd_args("-rejoin -ignore").unwrap(),
+        TabConfig {
+            ignore: Some(true),
+            rejoin_on_kick: Some(true),
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn style_display() {
+    let colors = Colors::default();
+    for (_, style) in colors.styles() {
+        let style_str = style.to_string();
+        assert_eq!(serde_yaml::from_str::<Style>(&style_str).unwrap(), style);
+    }
+    assert_eq!(
+        colors.mention.to_string(),
+        "{fg: 9, bg: 0, attrs: [bold, underline]}"
+    );
 }
@@ -61,8 +61,8 @@ fn test_alignment_long_string() {
     #[rustfmt::skip]
     let screen =
         "|                                        |
-         |00:00         osa1: 12345678901234567890|
-         |                    1234567890          |
+         |00:00 osa1: 1234567890123456789012345678|
+         |            90                          |
          |osa1:                                   |
          |mentions irc.server_1.org #chan         |";
 
@@ -72,16 +72,25 @@ fn test_alignment_long_string() {
 #[test]
 fn test_mnemonic_generation() {
     let mut tui = TUI::new_test(10, 10);
-    tui.new_chan_tab("s1", ChanNameRef::new("#ab"));
-    tui.new_chan_tab("s2", ChanNameRef::new("#ab"));
-    tui.new_chan_tab("s3", ChanNameRef::new("#ab"));
-    tui.new_chan_tab("s4", ChanNameRef::new("#ab"));
+    tui.new_chan_tab("s1", ChanNameRef::new("#ac"));
+    tui.new_chan_tab("s2", ChanNameRef::new("#ac"));
+    tui.new_chan_tab("s3", ChanNameRef::new("#ac"));
+    tui.new_chan_tab("s4", ChanNameRef::new("#ac"));
     let tabs = tui.get_tabs();
     assert_eq!(tabs.len(), 9); // mentions, 4 servers, 4 channels
     assert_eq!(tabs[2].switch, Some('a'));
-    assert_eq!(tabs[4].switch, Some('b'));
+    assert_eq!(tabs[4].switch, Some('c'));
     assert_eq!(tabs[6].switch, Some('a'));
-    assert_eq!(tabs[8].switch, Some('b'));
+    assert_eq!(tabs[8].switch, Some('c'));
+}
+
+#[test]
+fn test_mnemonic_skips_bound_keys() {
+    let mut tui = TUI::new_test(10, 10);
+    // `alt_d` is bound to `input_delete_next_word`
+    tui.new_chan_tab("s1", ChanNameRef::new("#dev"));
+    let tabs = tui.get_tabs();
+    assert_eq!(tabs[2].switch, Some('e'));
 }
 
 fn setup_tui(layout: Layout) -> (TUI, MsgTarget<'static>) {
@@ -109,6 +118,27 @@ fn setup_tui(layout: Layout) -> (TUI, MsgTarget<'static>) {
     (tui, MsgTarget::Chan { serv, chan })
 }
 
+#[test]
+fn test_alignment_truncates_long_nicks() {
+    let (mut tui, target) = setup_tui(Layout::Aligned { max_nick_len: 8 });
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_privmsg("osa1", "hi", ts, &target, false, false);
+    tui.add_privmsg("a_long_bot_nick", "hello", ts, &target, false, false);
+    tui.add_privmsg("osa1", "hi again", ts, &target, false, false);
+    tui.draw();
+
+    // The nick column grows to fit the longest nick, up to `max_nick_len`
+    #[rustfmt::skip]
+    let screen =
+        "|00:00 osa1: hi                          |
+         |      a_long_…: hello                   |
+         |          osa1: hi again                |
+         |osa1:                                   |
+         |mentions irc.server_1.org #chan         |";
+
+    expect_screen(screen, &tui.get_front_buffer(), 40, 5, Location::caller());
+}
+
 fn setup_aligned_tui() -> (TUI, MsgTarget<'static>) {
     setup_tui(Layout::Aligned { max_nick_len: 12 })
 }
@@ -146,8 +176,8 @@ fn test_aligned_layout_activity_timestamp() {
         #[rustfmt::skip]
         let screen =
             "|                                        |
-             |00:00         osa1: hi                  |
-             |                    +test               |
+             |00:00 osa1: hi                          |
+             |            +test                       |
              |osa1:                                   |
              |mentions irc.server_1.org #chan         |";
 
@@ -169,8 +199,8 @@ fn test_aligned_layout_activity_timestamp() {
         #[rustfmt::skip]
         let screen =
             "|                                        |
-             |00:00         osa1: hi                  |
-             |00:01               +test               |
+             |00:00 osa1: hi                          |
+             |00:01       +test                       |
              |osa1:                                   |
              |mentions irc.server_1.org #chan         |";
 
@@ -191,8 +221,8 @@ fn test_aligned_layout_activity_timestamp() {
         #[rustfmt::skip]
         let screen =
             "|                                        |
-             |00:00         osa1: hi                  |
-             |              osa1: test                |
+             |00:00 osa1: hi                          |
+             |      osa1: test                        |
              |osa1:                                   |
              |mentions irc.server_1.org #chan         |";
 
@@ -214,8 +244,8 @@ fn test_aligned_layout_activity_timestamp() {
         #[rustfmt::skip]
         let screen =
             "|                                        |
-             |00:00         osa1: hi                  |
-             |00:01         osa1: test                |
+             |00:00 osa1: hi                          |
+             |00:01 osa1: test                        |
              |osa1:                                   |
              |mentions irc.server_1.org #chan         |";
 
@@ -236,8 +266,8 @@ fn test_aligned_layout_activity_timestamp() {
         #[rustfmt::skip]
         let screen =
             "|                                        |
-             |00:00               +test               |
-             |              osa1: hi                  |
+             |00:00       +test                       |
+             |      osa1: hi                          |
              |osa1:                                   |
              |mentions irc.server_1.org #chan         |";
 
@@ -259,8 +289,8 @@ fn test_aligned_layout_activity_timestamp() {
         #[rustfmt::skip]
         let screen =
             "|                                        |
-             |00:00               +test               |
-             |00:01         osa1: hi                  |
+             |00:00       +test                       |
+             |00:01 osa1: hi                          |
              |osa1:                                   |
              |mentions irc.server_1.org #chan         |";
 
@@ -279,7 +309,7 @@ fn test_aligned_layout_activity_timestamp() {
         let screen =
             "|                                        |
              |                                        |
-             |00:00               +test1 +test2       |
+             |00:00        +test1 +test2              |
              |osa1:                                   |
              |mentions irc.server_1.org #chan         |";
 
@@ -298,8 +328,8 @@ fn test_aligned_layout_activity_timestamp() {
         #[rustfmt::skip]
         let screen =
             "|                                        |
-             |00:00               +test1              |
-             |00:01               +test2              |
+             |00:00        +test1                     |
+             |00:01        +test2                     |
              |osa1:                                   |
              |mentions irc.server_1.org #chan         |";
 
@@ -500,7 +530,7 @@ fn test_clear_timestamp_aligned() {
     let screen =
         "|                                        |
          |                                        |
-         |00:00               +test2              |
+         |00:00        +test2                     |
          |osa1:                                   |
          |mentions irc.server_1.org #chan         |";
 
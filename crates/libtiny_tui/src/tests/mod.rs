@@ -1,10 +1,13 @@
 use std::panic::Location;
+use std::time::{Duration, Instant};
 
-use libtiny_common::{ChanNameRef, MsgTarget};
-use term_input::{Event, Key};
+use libtiny_common::{CaseMapping, ChanNameRef, MsgSource, MsgTarget, TabStyle, TypingState};
+use term_input::{Arrow, Event, FKey, Key};
+use tokio::sync::oneshot;
 
+use crate::config::{Colors, TabOrder};
 use crate::test_utils::expect_screen;
-use crate::tui::TUI;
+use crate::tui::{TUI, TUIRet};
 
 mod layout;
 mod resize;
@@ -31,6 +34,30 @@ fn init_screen() {
     expect_screen(screen, &tui.get_front_buffer(), 20, 4, Location::caller());
 }
 
+#[test]
+fn user_tab_case_mapping() {
+    let mut tui = TUI::new_test(30, 4);
+    let serv = "irc.server";
+    tui.new_server_tab(serv, None);
+    tui.new_user_tab(serv, "Foo[]");
+    // Same nick with the default case mapping
+    tui.new_user_tab(serv, "foo{}");
+    assert!(tui.user_tab_exists(serv, "FOO{}"));
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|Any mentions to you will be   |
+         |listed here.                  |
+         |                              |
+         |mentions irc.server Foo[]     |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 4, Location::caller());
+
+    tui.set_case_mapping(serv, CaseMapping::Ascii);
+    assert!(!tui.user_tab_exists(serv, "foo{}"));
+    assert!(tui.user_tab_exists(serv, "FOO[]"));
+}
+
 #[test]
 fn close_rightmost_tab() {
     // After closing right-most tab the tab bar should scroll left.
@@ -382,3 +409,907 @@ fn test_text_field_wrap() {
     // TODO: Test changing nick (osa: I don't understand how nick length is taken into account when
     // falling back to scrolling)
 }
+
+#[test]
+fn partial_redraw() {
+    let mut tui = TUI::new_test(20, 4);
+    let serv = "irc.server_1.org";
+    tui.new_server_tab(serv, None);
+    tui.draw();
+
+    // Nothing changed, nothing should be written to the terminal
+    let flushed = tui.total_flushed();
+    tui.draw();
+    assert_eq!(tui.total_flushed(), flushed);
+
+    // A message to an inactive tab only changes the tab bar
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_msg("hi", ts, &MsgTarget::Server { serv });
+    tui.set_tab_style(TabStyle::NewMsg, &MsgTarget::Server { serv });
+    tui.draw();
+    assert!(tui.total_flushed() > flushed);
+
+    enter_string(&mut tui, "abc");
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|Any mentions to you |
+         |will be listed here.|
+         |abc                 |
+         |mentions >          |";
+    expect_screen(screen, &tui.get_front_buffer(), 20, 4, Location::caller());
+
+    // Resize should repaint everything
+    tui.set_size(25, 4);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|Any mentions to you will |
+         |be listed here.          |
+         |abc                      |
+         |mentions irc.server_1.org|";
+    expect_screen(screen, &tui.get_front_buffer(), 25, 4, Location::caller());
+}
+
+#[test]
+fn grep_all_tabs() {
+    let mut tui = TUI::new_test(40, 7);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    let chan_target = MsgTarget::Chan { serv, chan };
+    let serv_target = MsgTarget::Server { serv };
+
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_privmsg("osa1", "Hello world", ts, &chan_target, false, false);
+    tui.add_privmsg("osa1", "bye", ts, &chan_target, false, false);
+    let ts = time::at_utc(time::Timespec::new(60, 0));
+    tui.add_msg("hello from server", ts, &serv_target);
+
+    tui.next_tab();
+    let src = MsgSource::Serv {
+        serv: serv.to_owned(),
+    };
+    tui.try_handle_cmd("grep -i -n 1 hello", &src);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|Matches for \"hello\":                    |
+         |[irc.server_1.org] 00:01 hello from     |
+         |server                                  |
+         |1 more match not shown, use `-n` to show|
+         |more                                    |
+         |                                        |
+         |mentions irc.server_1.org #chan         |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 7, Location::caller());
+
+    tui.try_handle_cmd("grep Hello", &src);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|server                                  |
+         |1 more match not shown, use `-n` to show|
+         |more                                    |
+         |Matches for \"Hello\":                    |
+         |[#chan] 00:00 osa1: Hello world         |
+         |                                        |
+         |mentions irc.server_1.org #chan         |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 7, Location::caller());
+
+    // Results of earlier searches are not searched
+    tui.try_handle_cmd("grep -i hello", &src);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|[#chan] 00:00 osa1: Hello world         |
+         |Matches for \"hello\":                    |
+         |[irc.server_1.org] 00:01 hello from     |
+         |server                                  |
+         |[#chan] 00:00 osa1: Hello world         |
+         |                                        |
+         |mentions irc.server_1.org #chan         |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 7, Location::caller());
+}
+
+#[test]
+fn own_msg_and_mention_styles() {
+    let mut tui = TUI::new_test(30, 4);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.set_nick(serv, "osa1");
+    tui.next_tab();
+    tui.next_tab();
+
+    let target = MsgTarget::Chan { serv, chan };
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_privmsg("osa1", "hi", ts, &target, false, false);
+    tui.add_privmsg("bob", "osa1: hi", ts, &target, true, false);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|00:00 osa1: hi                |
+         |bob: osa1: hi                 |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 4, Location::caller());
+
+    let colors = Colors::default();
+    let fg = |x: usize, y: usize| tui.get_front_buffer().cells[y * 30 + x].fg;
+    assert_eq!(fg(12, 0), colors.own_msg.fg);
+    assert_eq!(fg(5, 1), colors.mention.fg);
+    assert_eq!(fg(8, 1), colors.mention.fg);
+    assert_eq!(fg(9, 1), colors.highlight.fg);
+    assert_eq!(fg(11, 1), colors.highlight.fg);
+}
+
+#[test]
+fn highlight_line() {
+    let mut tui = TUI::new_test(30, 4);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.set_nick(serv, "osa1");
+    tui.next_tab();
+    tui.next_tab();
+    let src = MsgSource::Chan {
+        serv: serv.to_owned(),
+        chan: chan.to_owned(),
+    };
+    tui.try_handle_cmd("set colors.highlight_line {fg: default, bg: 52}", &src);
+    tui.clear(&MsgTarget::CurrentTab);
+
+    let target = MsgTarget::Chan { serv, chan };
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_privmsg("bob", "hi", ts, &target, false, false);
+    tui.add_privmsg("bob", "osa1: hi", ts, &target, true, false);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|00:00 bob: hi                 |
+         |bob: osa1: hi                 |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 4, Location::caller());
+
+    let colors = Colors::default();
+    let cell = |x: usize, y: usize| tui.get_front_buffer().cells[y * 30 + x];
+    assert_eq!(cell(6, 0).bg, colors.user_msg.bg);
+    assert_eq!(cell(29, 0).bg, colors.user_msg.bg);
+    // The whole highlighted line has the background, text colors don't change
+    for x in 0..30 {
+        assert_eq!(cell(x, 1).bg, 52);
+    }
+    assert_eq!(cell(5, 1).fg, colors.mention.fg);
+    assert_eq!(cell(11, 1).fg, colors.highlight.fg);
+}
+
+#[test]
+fn message_align() {
+    let mut tui = TUI::new_test(30, 5);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.set_nick(serv, "osa1");
+    tui.next_tab();
+    tui.next_tab();
+    let src = MsgSource::Chan {
+        serv: serv.to_owned(),
+        chan: chan.to_owned(),
+    };
+    tui.clear(&MsgTarget::CurrentTab);
+
+    let target = MsgTarget::Chan { serv, chan };
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_privmsg("bob", "hi", ts, &target, false, false);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                              |
+         |                              |
+         |00:00 bob: hi                 |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+
+    tui.try_handle_cmd("set message_align top", &src);
+    tui.clear(&MsgTarget::CurrentTab);
+    tui.add_privmsg("bob", "hi", ts, &target, false, false);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|00:00 bob: hi                 |
+         |                              |
+         |                              |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+
+    // Lines that fill the area are drawn the same with both alignments
+    for _ in 0..3 {
+        tui.add_privmsg("bob", "hi", ts, &target, false, false);
+    }
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|bob: hi                       |
+         |bob: hi                       |
+         |bob: hi                       |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+}
+
+#[test]
+fn own_msg_after_nick_change() {
+    let mut tui = TUI::new_test(30, 4);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.set_nick(serv, "osa1");
+    tui.next_tab();
+    tui.next_tab();
+
+    let target = MsgTarget::Chan { serv, chan };
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.set_nick(serv, "osa2");
+    tui.add_privmsg("osa1", "old", ts, &target, false, false);
+    tui.add_privmsg("osa2", "new", ts, &target, false, false);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|00:00 osa1: old               |
+         |osa2: new                     |
+         |osa2:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 4, Location::caller());
+
+    let colors = Colors::default();
+    let fg = |x: usize, y: usize| tui.get_front_buffer().cells[y * 30 + x].fg;
+    assert_eq!(fg(12, 0), colors.user_msg.fg);
+    assert_eq!(fg(6, 1), colors.own_msg.fg);
+}
+
+#[test]
+fn typing_indicator() {
+    // Typing of others is shown without sending our own typing notifications
+    let mut tui = TUI::new_test(30, 5);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.set_nick(serv, "osa1");
+    tui.next_tab();
+    tui.next_tab();
+
+    let target = MsgTarget::Chan { serv, chan };
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_privmsg("bob", "hi", ts, &target, false, false);
+    tui.set_typing("alice", TypingState::Active, &target);
+    tui.set_typing("bob", TypingState::Active, &target);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                              |
+         |00:00 bob: hi                 |
+         |alice and bob are typing…     |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+
+    // Messages and `paused` notifications end typing
+    tui.add_privmsg("alice", "hello", ts, &target, false, false);
+    tui.set_typing("bob", TypingState::Paused, &target);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                              |
+         |00:00 bob: hi                 |
+         |alice: hello                  |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+
+    // Notifications time out
+    tui.set_typing("alice", TypingState::Active, &target);
+    tui.typing_tick(Instant::now() + Duration::from_secs(10));
+    tui.draw();
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+
+    // Messages waiting to be sent are shown in the same line
+    tui.set_queued(3, &target);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|00:00 bob: hi                 |
+         |alice: hello                  |
+         |sending… (3 queued)           |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+
+    tui.set_queued(0, &target);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                              |
+         |00:00 bob: hi                 |
+         |alice: hello                  |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+
+    // Typing is not shown with `show_typing: false`
+    tui.set_show_typing(false);
+    tui.set_typing("alice", TypingState::Active, &target);
+    tui.draw();
+    expect_screen(screen, &tui.get_front_buffer(), 30, 5, Location::caller());
+}
+
+#[test]
+fn own_typing() {
+    let mut tui = TUI::new_test(30, 5);
+    tui.set_typing_notifications(true);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.next_tab();
+
+    let now = Instant::now();
+    let secs = |n: u64| now + Duration::from_secs(n);
+    let chan_src = MsgSource::Chan {
+        serv: serv.to_owned(),
+        chan: chan.to_owned(),
+    };
+
+    // Not sent in server tabs
+    enter_string(&mut tui, "hi");
+    assert_eq!(tui.typing_update(now), vec![]);
+    tui.handle_input_event(Event::Key(Key::Char('\r')), &mut None);
+
+    tui.next_tab();
+    enter_string(&mut tui, "h");
+    assert_eq!(
+        tui.typing_update(now),
+        vec![(TypingState::Active, chan_src.clone())]
+    );
+    // No change in the input
+    assert_eq!(tui.typing_update(secs(1)), vec![]);
+    assert_eq!(tui.typing_tick(secs(4)), None);
+    assert_eq!(
+        tui.typing_tick(secs(6)),
+        Some((TypingState::Paused, chan_src.clone()))
+    );
+    assert_eq!(tui.typing_tick(secs(7)), None);
+    enter_string(&mut tui, "i");
+    assert_eq!(
+        tui.typing_update(secs(8)),
+        vec![(TypingState::Active, chan_src.clone())]
+    );
+
+    // Commands are not messages
+    for _ in 0..2 {
+        tui.handle_input_event(Event::Key(Key::Backspace), &mut None);
+    }
+    enter_string(&mut tui, "/");
+    assert_eq!(
+        tui.typing_update(secs(9)),
+        vec![(TypingState::Done, chan_src)]
+    );
+}
+
+#[test]
+fn day_change() {
+    let mut tui = TUI::new_test(30, 6);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.set_nick(serv, "osa1");
+    tui.next_tab();
+    tui.next_tab();
+
+    let target = MsgTarget::Chan { serv, chan };
+    let ts = time::at_utc(time::Timespec::new(23 * 3600 + 59 * 60, 0));
+    tui.add_privmsg("bob", "night", ts, &target, false, false);
+    // Same time on the next day
+    let ts = time::at_utc(time::Timespec::new(24 * 3600 + 23 * 3600 + 59 * 60, 0));
+    tui.add_privmsg("bob", "a day later", ts, &target, false, false);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                              |
+         |23:59 bob: night              |
+         |Day changed to 1970-01-02     |
+         |23:59 bob: a day later        |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 6, Location::caller());
+}
+
+#[test]
+fn collapse_netsplits() {
+    let mut tui = TUI::new_test(30, 6);
+    tui.set_collapse_netsplits(true);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.set_nick(serv, "osa1");
+    tui.new_chan_tab(serv, chan);
+    tui.next_tab();
+    tui.next_tab();
+
+    let target = MsgTarget::Chan { serv, chan };
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_privmsg("bob", "hi", ts, &target, false, false);
+    for nick in ["alice", "bob", "carol"] {
+        tui.netsplit_quit(nick, "a.net b.net", Some(ts), &target);
+    }
+    tui.remove_nick("dave", Some(ts), &target);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|00:00 bob: hi                 |
+         |Netsplit (a.net b.net): 3     |
+         |users quit                    |
+         |-dave                         |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 6, Location::caller());
+
+    tui.handle_input_event(Event::Key(Key::Ctrl('o')), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|00:00 bob: hi                 |
+         |Netsplit (a.net b.net): -alice|
+         |-bob -carol                   |
+         |-dave                         |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 6, Location::caller());
+
+    for nick in ["alice", "carol"] {
+        tui.netjoin(nick, "a.net b.net", Some(ts), &target);
+    }
+    tui.handle_input_event(Event::Key(Key::Ctrl('o')), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|users quit                    |
+         |-dave                         |
+         |Netjoin (a.net b.net): 2 users|
+         |joined                        |
+         |osa1:                         |
+         |< irc.server_1.org #chan      |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 6, Location::caller());
+}
+
+#[test]
+fn help_popup() {
+    let mut tui = TUI::new_test(40, 5);
+    let serv = "irc.server_1.org";
+    tui.new_server_tab(serv, None);
+    tui.next_tab();
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_msg("hello", ts, &MsgTarget::Server { serv });
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                        |
+         |                                        |
+         |00:00 hello                             |
+         |                                        |
+         |mentions irc.server_1.org               |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 5, Location::caller());
+
+    tui.handle_input_event(Event::Key(Key::FKey(FKey::F1)), &mut None);
+    tui.draw();
+
+    // Tab bar is still visible
+    #[rustfmt::skip]
+    let screen =
+        "| Help (1/21): pgdown next page, pgup pr |
+         | Key bindings:                          |
+         |   cancel: esc                          |
+         |   exit: ctrl_c                         |
+         |mentions irc.server_1.org               |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 5, Location::caller());
+
+    tui.handle_input_event(Event::Key(Key::PageDown), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "| Help (2/21): pgdown next page, pgup pr |
+         |   input_auto_complete: tab             |
+         |   input_delete_next_char: del          |
+         |   input_delete_next_word: alt_d        |
+         |mentions irc.server_1.org               |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 5, Location::caller());
+
+    // Any other key closes the popup and shows the tab again
+    tui.handle_input_event(Event::Key(Key::Char('x')), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                        |
+         |                                        |
+         |00:00 hello                             |
+         |                                        |
+         |mentions irc.server_1.org               |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 5, Location::caller());
+}
+
+#[test]
+fn set_cmd() {
+    let mut tui = TUI::new_test(40, 9);
+    let serv = "irc.server_1.org";
+    tui.new_server_tab(serv, None);
+    tui.next_tab();
+    let src = MsgSource::Serv {
+        serv: serv.to_owned(),
+    };
+
+    tui.try_handle_cmd("set colors.faded {fg: red, bg: 0}", &src);
+    tui.try_handle_cmd("set collapse_netsplits maybe", &src);
+    tui.try_handle_cmd("set paste_safe_urls", &src);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                        |
+         |                                        |
+         |colors.faded: {fg: 9, bg: 0}            |
+         |Invalid value for collapse_netsplits:   |
+         |invalid type: string \"maybe\", expected a|
+         |boolean at line 1 column 1              |
+         |paste_safe_urls: false                  |
+         |                                        |
+         |mentions irc.server_1.org               |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 9, Location::caller());
+}
+
+#[test]
+fn confirm_close() {
+    let mut tui = TUI::new_test(50, 3);
+    tui.set_confirm_close(true);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.next_tab();
+    tui.next_tab();
+    let src = MsgSource::Chan {
+        serv: serv.to_owned(),
+        chan: chan.to_owned(),
+    };
+    let target = MsgTarget::Chan { serv, chan };
+
+    // No highlights, closed without asking
+    assert!(!tui.confirm_close(&src, "close -f"));
+
+    tui.set_tab_style(TabStyle::Highlight, &target);
+    tui.set_tab_style(TabStyle::Highlight, &target);
+    assert!(tui.confirm_close(&src, "close -f bye"));
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                                  |
+         |Close #chan? It has 2 unread highlights. (y/n)    |
+         |mentions irc.server_1.org #chan                   |";
+    expect_screen(screen, &tui.get_front_buffer(), 50, 3, Location::caller());
+
+    let ret = tui.handle_input_event(Event::Key(Key::Char('y')), &mut None);
+    assert!(matches!(ret, Some(TUIRet::KeyCommand { cmd, .. }) if cmd == "close -f bye"));
+
+    // Sending a message marks highlights as read
+    tui.set_tab_style(TabStyle::Highlight, &target);
+    enter_string(&mut tui, "hi\r");
+    assert!(!tui.confirm_close(&src, "close -f"));
+
+    // Server tabs always ask
+    let src = MsgSource::Serv {
+        serv: serv.to_owned(),
+    };
+    assert!(tui.confirm_close(&src, "close -f"));
+}
+
+#[test]
+fn confirm_send() {
+    let mut tui = TUI::new_test(60, 3);
+    tui.set_confirm_send_after(Some(Duration::from_secs(60)));
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.next_tab();
+    tui.next_tab();
+
+    // New tabs count as active
+    enter_string(&mut tui, "hi");
+    let ret = tui.handle_input_event(Event::Key(Key::Char('\r')), &mut None);
+    assert!(matches!(ret, Some(TUIRet::Input { msg, .. }) if msg == ['h', 'i']));
+
+    tui.set_confirm_send_after(Some(Duration::ZERO));
+    // Commands are sent without asking
+    enter_string(&mut tui, "/me");
+    let ret = tui.handle_input_event(Event::Key(Key::Char('\r')), &mut None);
+    assert!(matches!(ret, Some(TUIRet::Input { .. })));
+
+    enter_string(&mut tui, "hi\r");
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                                            |
+         |Send to #chan? No messages from you there for 0 min. (y/n)  |
+         |mentions irc.server_1.org #chan                             |";
+    expect_screen(screen, &tui.get_front_buffer(), 60, 3, Location::caller());
+
+    // The message is kept when cancelled
+    tui.handle_input_event(Event::Key(Key::Esc), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                                            |
+         |hi                                                          |
+         |mentions irc.server_1.org #chan                             |";
+    expect_screen(screen, &tui.get_front_buffer(), 60, 3, Location::caller());
+
+    enter_string(&mut tui, "\r");
+    let ret = tui.handle_input_event(Event::Key(Key::Char('y')), &mut None);
+    assert!(matches!(ret, Some(TUIRet::Input { msg, .. }) if msg == ['h', 'i']));
+}
+
+#[test]
+fn password_dialogue() {
+    let mut tui = TUI::new_test(40, 3);
+    let serv = "irc.server_1.org";
+    tui.new_server_tab(serv, None);
+    tui.next_tab();
+
+    let (snd, mut rcv) = oneshot::channel();
+    tui.ask_password(serv, "Password:", "Store it? (y/n)", snd);
+    enter_string(&mut tui, "hunter3");
+    tui.handle_input_event(Event::Key(Key::Backspace), &mut None);
+    enter_string(&mut tui, "2");
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                        |
+         |Password: *******                       |
+         |mentions irc.server_1.org               |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 3, Location::caller());
+
+    enter_string(&mut tui, "\r");
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                        |
+         |Store it? (y/n)                         |
+         |mentions irc.server_1.org               |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 3, Location::caller());
+    assert!(rcv.try_recv().is_err());
+
+    enter_string(&mut tui, "y");
+    assert_eq!(rcv.try_recv(), Ok(("hunter2".to_owned(), true)));
+
+    // The input field is shown again
+    enter_string(&mut tui, "hi");
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                        |
+         |hi                                      |
+         |mentions irc.server_1.org               |";
+    expect_screen(screen, &tui.get_front_buffer(), 40, 3, Location::caller());
+
+    // Cancelling drops the sender
+    let (snd, mut rcv) = oneshot::channel();
+    tui.ask_password(serv, "Password:", "Store it? (y/n)", snd);
+    tui.handle_input_event(Event::Key(Key::Esc), &mut None);
+    assert_eq!(rcv.try_recv(), Err(oneshot::error::TryRecvError::Closed));
+}
+
+#[test]
+fn sort_tabs() {
+    let mut tui = TUI::new_test(50, 5);
+    tui.set_tab_order(TabOrder::Name);
+    let serv = "irc.server_1.org";
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, ChanNameRef::new("#zeta"));
+    tui.new_user_tab(serv, "bob");
+    tui.new_chan_tab(serv, ChanNameRef::new("#Alpha"));
+    tui.new_user_tab(serv, "carol");
+
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.rename_nick(
+        "carol",
+        "alice",
+        ts,
+        &MsgTarget::User {
+            serv,
+            nick: "carol",
+        },
+    );
+
+    tui.next_tab();
+    tui.next_tab();
+    assert_eq!(
+        tui.current_tab(),
+        &MsgSource::Chan {
+            serv: serv.to_owned(),
+            chan: ChanNameRef::new("#Alpha").to_owned(),
+        }
+    );
+
+    // Channel tabs can't be moved manually
+    tui.handle_input_event(Event::Key(Key::AltArrow(Arrow::Left)), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                                  |
+         |Can't move the tab: channel and user tabs are     |
+         |sorted by name (`tab_order: name`)                |
+         |                                                  |
+         |mentions irc.server_1.org #Alpha #zeta alice bob  |";
+    expect_screen(screen, &tui.get_front_buffer(), 50, 5, Location::caller());
+}
+
+#[test]
+fn go_to_tab() {
+    let mut tui = TUI::new_test(50, 6);
+    let serv1 = "irc.server_1.org";
+    let serv2 = "irc.server_2.org";
+    tui.new_server_tab(serv1, None);
+    tui.new_chan_tab(serv1, ChanNameRef::new("#tiny-dev"));
+    tui.new_chan_tab(serv1, ChanNameRef::new("#Tiny"));
+    tui.new_server_tab(serv2, None);
+    tui.new_chan_tab(serv2, ChanNameRef::new("#tiny"));
+    tui.new_user_tab(serv2, "bob");
+
+    let chan = |serv: &str, chan: &str| MsgSource::Chan {
+        serv: serv.to_owned(),
+        chan: ChanNameRef::new(chan).to_owned(),
+    };
+
+    // Prefix match ranks higher than subsequence match
+    tui.go("de");
+    assert_eq!(tui.current_tab(), &chan(serv1, "#tiny-dev"));
+
+    // Subsequence match
+    tui.go("BB");
+    assert_eq!(
+        tui.current_tab(),
+        &MsgSource::User {
+            serv: serv2.to_owned(),
+            nick: "bob".to_owned(),
+        }
+    );
+
+    // Exact matches (case-insensitive, without the `#`) rank higher than prefix matches, both
+    // are listed
+    tui.go("tiny");
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                                  |
+         |Multiple tabs match, use `/go <n>` to switch:     |
+         |1: #Tiny (irc.server_1.org)                       |
+         |2: #tiny (irc.server_2.org)                       |
+         |                                                  |
+         |< #tiny-dev #Tiny irc.server_2.org #tiny bob      |";
+    expect_screen(screen, &tui.get_front_buffer(), 50, 6, Location::caller());
+
+    tui.go("2");
+    assert_eq!(tui.current_tab(), &chan(serv2, "#tiny"));
+
+    tui.go("xyz");
+    assert_eq!(tui.current_tab(), &chan(serv2, "#tiny"));
+}
+
+#[test]
+fn tab_order_type() {
+    let mut tui = TUI::new_test(50, 5);
+    tui.set_tab_order(TabOrder::Type);
+    let serv = "irc.server_1.org";
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, ChanNameRef::new("#zeta"));
+    tui.new_user_tab(serv, "bob");
+    tui.new_chan_tab(serv, ChanNameRef::new("#alpha"));
+
+    // Channel tabs can be moved among channel tabs, but not over user tabs
+    tui.next_tab();
+    tui.next_tab();
+    tui.handle_input_event(Event::Key(Key::AltArrow(Arrow::Right)), &mut None);
+    tui.handle_input_event(Event::Key(Key::AltArrow(Arrow::Right)), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                                  |
+         |Can't move the tab: channel and user tabs are     |
+         |grouped by type (`tab_order: type`)               |
+         |                                                  |
+         |mentions irc.server_1.org #alpha #zeta bob        |";
+    expect_screen(screen, &tui.get_front_buffer(), 50, 5, Location::caller());
+}
+
+#[test]
+fn scroll_to_bottom_on_send() {
+    let mut tui = TUI::new_test(30, 4);
+    tui.set_scroll_to_bottom_on_send(true);
+    let serv = "irc.server_1.org";
+    let target = MsgTarget::Server { serv };
+    tui.new_server_tab(serv, None);
+    tui.next_tab();
+    for msg in ["line 1", "line 2", "line 3"] {
+        tui.add_client_msg(msg, &target);
+    }
+
+    tui.draw();
+
+    tui.handle_input_event(Event::Key(Key::ShiftArrow(Arrow::Up)), &mut None);
+    tui.add_client_msg("line 4", &target);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|line 1                        |
+         |-- more --                    |
+         |                              |
+         |mentions irc.server_1.org     |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 4, Location::caller());
+
+    // Commands don't scroll
+    enter_string(&mut tui, "/clear");
+    tui.handle_input_event(Event::Key(Key::Char('\r')), &mut None);
+    tui.draw();
+    expect_screen(screen, &tui.get_front_buffer(), 30, 4, Location::caller());
+
+    enter_string(&mut tui, "hi\r");
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|line 3                        |
+         |line 4                        |
+         |                              |
+         |mentions irc.server_1.org     |";
+    expect_screen(screen, &tui.get_front_buffer(), 30, 4, Location::caller());
+}
@@ -2,7 +2,7 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::panic::Location;
 
-use libtiny_common::{ChanNameRef, MsgTarget};
+use libtiny_common::{ChanNameRef, MsgSource, MsgTarget};
 use term_input::{Arrow, Key};
 
 use crate::test_utils::expect_screen;
@@ -357,3 +357,51 @@ fn test_clear_resize_recalc_scroll() {
 
     expect_screen(screen1, &tui.get_front_buffer(), 20, 6, Location::caller());
 }
+
+// Tab bar and message wrapping should be updated immediately on resize to a narrower terminal,
+// without waiting for the next draw. `/redraw` should paint the same screen from scratch.
+#[test]
+fn test_resize_narrow_and_redraw() {
+    let mut tui = TUI::new_test(40, 4);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#chan");
+    tui.new_server_tab(serv, None);
+    tui.set_nick(serv, "osa1");
+    tui.new_chan_tab(serv, chan);
+    tui.next_tab();
+    tui.next_tab();
+
+    let target = MsgTarget::Chan { serv, chan };
+    let ts = time::at_utc(time::Timespec::new(0, 0));
+    tui.add_privmsg("osa1", "hello world, hello tiny", ts, &target, false, false);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen1 =
+       "|                                        |
+        |00:00 osa1: hello world, hello tiny     |
+        |osa1:                                   |
+        |mentions irc.server_1.org #chan         |";
+
+    expect_screen(screen1, &tui.get_front_buffer(), 40, 4, Location::caller());
+
+    tui.set_size(16, 4);
+
+    #[rustfmt::skip]
+    let screen2 =
+       "|hello world,    |
+        |hello tiny      |
+        |                |
+        |< #chan         |";
+
+    expect_screen(screen2, &tui.get_front_buffer(), 16, 4, Location::caller());
+
+    let flushed = tui.total_flushed();
+    let src = MsgSource::Chan {
+        serv: serv.to_owned(),
+        chan: chan.to_owned(),
+    };
+    tui.try_handle_cmd("redraw", &src);
+    assert!(tui.total_flushed() > flushed);
+    expect_screen(screen2, &tui.get_front_buffer(), 16, 4, Location::caller());
+}
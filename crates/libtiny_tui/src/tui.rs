@@ -11,10 +11,15 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::{self, SplitWhitespace};
+use std::time::{Duration, Instant};
 use time::Tm;
+use tokio::sync::oneshot;
 
-use crate::config::{Colors, Config, Style, TabConfig, TabConfigs, parse_config};
+use crate::config::{
+    Colors, Config, MsgAlign, Style, TabConfig, TabConfigs, TabOrder, parse_config,
+};
 use crate::editor;
+use crate::help::Help;
 use crate::key_map::{KeyAction, KeyMap};
 use crate::messaging::{MessagingUI, Timestamp};
 use crate::msg_area::Layout;
@@ -22,7 +27,7 @@ use crate::notifier::Notifier;
 use crate::tab::Tab;
 use crate::widget::WidgetRet;
 
-use libtiny_common::{ChanNameRef, MsgSource, MsgTarget, TabStyle};
+use libtiny_common::{CaseMapping, ChanNameRef, MsgSource, MsgTarget, TabStyle, TypingState};
 use term_input::{Event, Key};
 use termbox_simple::{CellBuf, Termbox};
 
@@ -65,12 +70,61 @@ const NOTIFY_CMD: CmdUsage = CmdUsage::new(
     "`/notify [off|mentions|messages]`",
 );
 const SWITCH_CMD: CmdUsage = CmdUsage::new("switch", "Switches to tab", "`/switch <tab name>`");
+const GO_CMD: CmdUsage = CmdUsage::new(
+    "go",
+    "Switches to best matching tab",
+    "`/go <tab name>` or `/go <n>`",
+);
 const RELOAD_CMD: CmdUsage = CmdUsage::new("reload", "Reloads config file", "`/reload`");
+const GREP_CMD: CmdUsage = CmdUsage::new(
+    "grep",
+    "Searches messages in all tabs",
+    "`/grep [-i] [-n <max results>] <text>`",
+);
+
+const REDRAW_CMD: CmdUsage = CmdUsage::new("redraw", "Clears and redraws the screen", "`/redraw`");
+
+const SET_CMD: CmdUsage = CmdUsage::new(
+    "set",
+    "Shows or changes settings until restart or /reload",
+    "`/set`, `/set <setting>`, or `/set <setting> <value>`",
+);
+
+const TUI_COMMANDS: [CmdUsage; 10] = [
+    QUIT_CMD, CLEAR_CMD, IGNORE_CMD, NOTIFY_CMD, SWITCH_CMD, GO_CMD, RELOAD_CMD, GREP_CMD, SET_CMD,
+    REDRAW_CMD,
+];
 
-const TUI_COMMANDS: [CmdUsage; 6] = [
-    QUIT_CMD, CLEAR_CMD, IGNORE_CMD, NOTIFY_CMD, SWITCH_CMD, RELOAD_CMD,
+/// Client commands listed in the help popup, in addition to `TUI_COMMANDS`.
+const COMMON_CLIENT_COMMANDS: [CmdUsage; 5] = [
+    CmdUsage::new("join", "Joins a channel", "`/join <chan>`"),
+    CmdUsage::new(
+        "msg",
+        "Sends a message to a user",
+        "`/msg <nick> <message>`",
+    ),
+    CmdUsage::new("me", "Sends emote message", "`/me <message>`"),
+    CmdUsage::new("nick", "Sets your nick", "`/nick <nick>`"),
+    CmdUsage::new(
+        "close",
+        "Closes current tab",
+        "`/close` or `/close <reason>`",
+    ),
 ];
 
+/// Default maximum number of results shown by `/grep`
+const GREP_MAX_RESULTS: usize = 50;
+
+/// A message fetched from the server, e.g. with IRCv3 `draft/chathistory`. See
+/// `TUI::add_history`.
+#[derive(Debug, Clone)]
+pub struct HistoryMsg {
+    pub sender: String,
+    pub msg: String,
+    pub ts: Tm,
+    pub is_action: bool,
+}
+
 // Public for benchmarks
 pub struct TUI {
     /// Termbox instance
@@ -85,6 +139,36 @@ pub struct TUI {
     /// Messaging area layout: aligned or compact
     msg_layout: Layout,
 
+    /// Avoid splitting URLs in the middle when wrapping messages
+    paste_safe_urls: bool,
+
+    /// Where to show messages that don't fill the message area
+    msg_align: MsgAlign,
+
+    /// Collapse quits caused by a netsplit into a summary line
+    collapse_netsplits: bool,
+
+    /// Send typing notifications
+    typing_notifications: bool,
+
+    /// Show typing notifications of others
+    show_typing: bool,
+
+    /// Our typing state in a channel or user tab, when typing notifications are enabled
+    own_typing: Option<OwnTyping>,
+
+    /// Ask before closing server tabs and tabs with unread highlights
+    confirm_close: bool,
+
+    /// Ask before sending a message to a channel we haven't sent a message to for this long
+    confirm_send_after: Option<Duration>,
+
+    /// Scroll to the last message when sending a message
+    scroll_to_bottom_on_send: bool,
+
+    /// Order of the channel and user tabs of each server
+    tab_order: TabOrder,
+
     tabs: Vec<Tab>,
     active_idx: usize,
     width: i32,
@@ -93,11 +177,52 @@ pub struct TUI {
 
     key_map: KeyMap,
 
+    /// The help popup, when shown. Drawn over the active tab and handles all key presses.
+    help: Option<Help>,
+
     /// Config file path
     config_path: Option<PathBuf>,
 
     /// TabConfig settings loaded from config file
     tab_configs: TabConfigs,
+
+    /// Case mappings of the servers, used to find the tabs of nicks. Servers not in the map use
+    /// the default case mapping.
+    case_mappings: HashMap<String, CaseMapping>,
+
+    /// Tabs listed by the last `/go` with multiple matches, to be selected with `/go <n>`.
+    go_matches: Vec<MsgSource>,
+
+    /// Parts of the screen that need to be redrawn in the next `draw`. Contents of the active
+    /// tab are tracked by the tab's widget.
+    damage: Damage,
+}
+
+/// Our typing state in a tab, tracked to send typing notifications.
+struct OwnTyping {
+    /// The tab we're typing in.
+    src: MsgSource,
+
+    /// Contents of the input field in the last update, to detect changes.
+    input: Vec<char>,
+
+    /// The last state reported.
+    state: TypingState,
+
+    /// When the input field last changed.
+    last_change: Instant,
+}
+
+/// Typing is considered paused when the input field doesn't change for this long.
+const TYPING_PAUSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Damage {
+    /// Redraw the whole screen, e.g. after a resize or switching tabs.
+    all: bool,
+
+    /// Redraw the tab bar.
+    tab_bar: bool,
 }
 
 pub(crate) enum CmdResult {
@@ -129,8 +254,14 @@ impl TUI {
         self.tb.get_front_buffer()
     }
 
+    /// Number of bytes written to the terminal so far. Useful for benchmarking rendering.
+    pub fn total_flushed(&self) -> u64 {
+        self.tb.total_flushed()
+    }
+
     pub(crate) fn activate(&mut self) {
-        self.tb.activate()
+        self.tb.activate();
+        self.damage.all = true;
     }
 
     #[cfg(test)]
@@ -138,6 +269,42 @@ impl TUI {
         self.msg_layout = layout
     }
 
+    #[cfg(test)]
+    pub(crate) fn set_confirm_close(&mut self, confirm_close: bool) {
+        self.confirm_close = confirm_close
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_confirm_send_after(&mut self, confirm_send_after: Option<Duration>) {
+        self.confirm_send_after = confirm_send_after
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_scroll_to_bottom_on_send(&mut self, scroll_to_bottom_on_send: bool) {
+        self.scroll_to_bottom_on_send = scroll_to_bottom_on_send
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_tab_order(&mut self, tab_order: TabOrder) {
+        self.tab_order = tab_order;
+        self.sort_tabs();
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_collapse_netsplits(&mut self, collapse_netsplits: bool) {
+        self.collapse_netsplits = collapse_netsplits
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_typing_notifications(&mut self, typing_notifications: bool) {
+        self.typing_notifications = typing_notifications
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_show_typing(&mut self, show_typing: bool) {
+        self.show_typing = show_typing
+    }
+
     pub(crate) fn current_tab(&self) -> &MsgSource {
         &self.tabs[self.active_idx].src
     }
@@ -159,14 +326,31 @@ impl TUI {
             colors: Colors::default(),
             scrollback: usize::MAX,
             msg_layout: Layout::Compact,
+            paste_safe_urls: false,
+            msg_align: MsgAlign::default(),
+            collapse_netsplits: false,
+            typing_notifications: false,
+            show_typing: true,
+            own_typing: None,
+            confirm_close: false,
+            confirm_send_after: None,
+            scroll_to_bottom_on_send: false,
+            tab_order: TabOrder::default(),
             tabs: Vec::new(),
             active_idx: 0,
             width,
             height,
             h_scroll: 0,
             key_map: KeyMap::default(),
+            help: None,
             config_path,
             tab_configs: TabConfigs::default(),
+            case_mappings: HashMap::new(),
+            go_matches: vec![],
+            damage: Damage {
+                all: true,
+                tab_bar: true,
+            },
         };
 
         // Init "mentions" tab. This needs to happen right after creating the TUI to be able to
@@ -273,6 +457,138 @@ impl TUI {
         }
     }
 
+    /// Show all settings, show one setting, or change a setting. Settings that are not TUI
+    /// settings are passed to the caller, and the caller shows its settings after ours.
+    fn set(&mut self, args: &str) -> CmdResult {
+        let (key, value) = match args.trim().split_once(' ') {
+            Some((key, value)) => (key, Some(value.trim())),
+            None => (args.trim(), None),
+        };
+
+        if key.is_empty() {
+            for (key, value) in self.settings() {
+                self.add_client_msg(&format!("{key}: {value}"), &MsgTarget::CurrentTab);
+            }
+            return CmdResult::Pass;
+        }
+
+        if !self.settings().iter().any(|(key_, _)| key_ == key) {
+            return CmdResult::Pass;
+        }
+
+        if let Some(value) = value
+            && let Err(err) = self.set_setting(key, value)
+        {
+            self.add_client_err_msg(&err, &MsgTarget::CurrentTab);
+            return CmdResult::Handled;
+        }
+
+        if let Some((key, value)) = self.settings().into_iter().find(|(key_, _)| key_ == key) {
+            self.add_client_msg(&format!("{key}: {value}"), &MsgTarget::CurrentTab);
+        }
+        CmdResult::Handled
+    }
+
+    /// Settings that can be changed with `/set`, with their current values in the config file
+    /// syntax.
+    fn settings(&self) -> Vec<(String, String)> {
+        let mut settings = vec![
+            (
+                "paste_safe_urls".to_owned(),
+                self.paste_safe_urls.to_string(),
+            ),
+            (
+                "collapse_netsplits".to_owned(),
+                self.collapse_netsplits.to_string(),
+            ),
+            (
+                "typing_notifications".to_owned(),
+                self.typing_notifications.to_string(),
+            ),
+            ("show_typing".to_owned(), self.show_typing.to_string()),
+            (
+                "scroll_to_bottom_on_send".to_owned(),
+                self.scroll_to_bottom_on_send.to_string(),
+            ),
+            ("tab_order".to_owned(), self.tab_order.as_str().to_owned()),
+            (
+                "message_align".to_owned(),
+                self.msg_align.as_str().to_owned(),
+            ),
+            ("colors.nick".to_owned(), format!("{:?}", self.colors.nick)),
+        ];
+        for (name, style) in self.colors.styles() {
+            settings.push((format!("colors.{name}"), style.to_string()));
+        }
+        settings
+    }
+
+    fn set_setting(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: serde::de::DeserializeOwned>(key: &str, value: &str) -> Result<T, String> {
+            serde_yaml::from_str(value).map_err(|err| format!("Invalid value for {key}: {err}"))
+        }
+
+        match key {
+            "paste_safe_urls" => {
+                self.paste_safe_urls = parse(key, value)?;
+                for tab in &mut self.tabs {
+                    tab.widget.set_paste_safe_urls(self.paste_safe_urls);
+                }
+            }
+            "collapse_netsplits" => {
+                self.collapse_netsplits = parse(key, value)?;
+            }
+            "typing_notifications" => {
+                self.typing_notifications = parse(key, value)?;
+            }
+            "show_typing" => {
+                self.show_typing = parse(key, value)?;
+            }
+            "scroll_to_bottom_on_send" => {
+                self.scroll_to_bottom_on_send = parse(key, value)?;
+            }
+            "tab_order" => {
+                self.tab_order = parse(key, value)?;
+                self.sort_tabs();
+            }
+            "message_align" => {
+                self.msg_align = parse(key, value)?;
+                for tab in &mut self.tabs {
+                    tab.widget.set_msg_align(self.msg_align);
+                }
+            }
+            // Older name of `tab_order: name`
+            "sort_tabs" => {
+                let sort_tabs: bool = parse(key, value)?;
+                self.tab_order = if sort_tabs {
+                    TabOrder::Name
+                } else {
+                    TabOrder::Join
+                };
+                self.sort_tabs();
+            }
+            "colors.nick" => {
+                let nick_colors: Vec<u8> = parse(key, value)?;
+                if nick_colors.is_empty() {
+                    return Err(format!("Invalid value for {key}: list is empty"));
+                }
+                self.colors.nick = nick_colors;
+            }
+            _ => {
+                let style = key
+                    .strip_prefix("colors.")
+                    .and_then(|name| self.colors.style_mut(name))
+                    .ok_or_else(|| format!("Unknown setting: {key}. Usage: {}", SET_CMD.usage))?;
+                *style = parse(key, value)?;
+                let clear = self.colors.clear;
+                self.tb.set_clear_attributes(clear.fg as u8, clear.bg as u8);
+            }
+        }
+
+        self.damage.all = true;
+        Ok(())
+    }
+
     pub(crate) fn try_handle_cmd(&mut self, cmd: &str, src: &MsgSource) -> CmdResult {
         let mut words = cmd.split_whitespace();
         match words.next() {
@@ -298,6 +614,25 @@ impl TUI {
                 }
                 CmdResult::Handled
             }
+            Some("go") => {
+                match words.next() {
+                    Some(s) => self.go(s),
+                    None => self.add_client_err_msg(
+                        &format!("Usage: {}", GO_CMD.usage),
+                        &MsgTarget::CurrentTab,
+                    ),
+                }
+                CmdResult::Handled
+            }
+            Some("grep") => {
+                self.grep(&mut words);
+                CmdResult::Handled
+            }
+            Some("set") => self.set(&cmd["set".len()..]),
+            Some("redraw") => {
+                self.redraw();
+                CmdResult::Handled
+            }
             Some("reload") => {
                 self.reload_config();
                 self.add_client_notify_msg("Reloaded config file.", &MsgTarget::CurrentTab);
@@ -357,9 +692,38 @@ impl TUI {
                 max_nick_length,
                 key_map,
                 layout,
+                paste_safe_urls,
+                collapse_netsplits,
+                typing_notifications,
+                show_typing,
+                confirm_close,
+                confirm_send_after_minutes,
+                scroll_to_bottom_on_send,
+                message_align,
+                sort_tabs,
+                tab_order,
                 ..
             } = config;
             self.set_colors(colors);
+            self.paste_safe_urls = paste_safe_urls;
+            self.collapse_netsplits = collapse_netsplits;
+            self.typing_notifications = typing_notifications;
+            self.show_typing = show_typing;
+            self.confirm_close = confirm_close;
+            self.confirm_send_after =
+                confirm_send_after_minutes.map(|mins| Duration::from_secs(mins * 60));
+            self.scroll_to_bottom_on_send = scroll_to_bottom_on_send;
+            self.tab_order = tab_order.unwrap_or(if sort_tabs {
+                TabOrder::Name
+            } else {
+                TabOrder::Join
+            });
+            self.sort_tabs();
+            self.msg_align = message_align;
+            for tab in &mut self.tabs {
+                tab.widget.set_paste_safe_urls(paste_safe_urls);
+                tab.widget.set_msg_align(message_align);
+            }
             self.scrollback = scrollback.max(1);
             self.key_map.load(&key_map.unwrap_or_default());
             if let Some(layout) = layout {
@@ -384,6 +748,7 @@ impl TUI {
         self.tb
             .set_clear_attributes(colors.clear.fg as u8, colors.clear.bg as u8);
         self.colors = colors;
+        self.damage.all = true;
     }
 
     fn new_tab(&mut self, idx: usize, src: MsgSource, alias: Option<String>) {
@@ -393,55 +758,30 @@ impl TUI {
             MsgSource::User { nick, .. } => nick.to_owned(),
         });
 
-        let switch = {
-            // Maps a switch key to number of times it's used
-            let mut switch_keys: HashMap<char, u16> = HashMap::with_capacity(self.tabs.len());
-
-            for tab in &self.tabs {
-                if let Some(key) = tab.switch {
-                    *switch_keys.entry(key).or_default() += 1;
-                }
-            }
+        let switch = switch_char(&self.key_map, &self.tabs, &visible_name);
 
-            // From the characters in tab name, find the one that is used the least
-            let mut new_tab_switch_char: Option<(char, u16)> = None;
-            for ch in visible_name.chars() {
-                if !ch.is_alphabetic() {
-                    continue;
-                }
-                match switch_keys.get(&ch).copied() {
-                    None => {
-                        new_tab_switch_char = Some((ch, 0));
-                        break;
-                    }
-                    Some(n_uses) => match new_tab_switch_char {
-                        None => {
-                            new_tab_switch_char = Some((ch, n_uses));
-                        }
-                        Some((_, new_tab_switch_char_n_uses)) => {
-                            if new_tab_switch_char_n_uses > n_uses {
-                                new_tab_switch_char = Some((ch, n_uses));
-                            }
-                        }
-                    },
-                }
-            }
-            new_tab_switch_char.map(|(ch, _)| ch)
-        };
+        let mut widget = MessagingUI::new(
+            self.width,
+            self.height - 1,
+            self.scrollback,
+            self.msg_layout,
+            self.paste_safe_urls,
+        );
+        widget.set_msg_align(self.msg_align);
 
+        self.damage.all = true;
         self.tabs.insert(
             idx,
             Tab {
                 visible_name,
-                widget: MessagingUI::new(
-                    self.width,
-                    self.height - 1,
-                    self.scrollback,
-                    self.msg_layout,
-                ),
+                widget,
                 src,
                 style: TabStyle::Normal,
                 switch,
+                highlights: 0,
+                away: false,
+                modes: String::new(),
+                last_sent: Instant::now(),
             },
         );
     }
@@ -473,6 +813,7 @@ impl TUI {
             }
         }
         self.fix_scroll_after_close();
+        self.damage.all = true;
     }
 
     /// Returns index of the new tab if a new tab is created.
@@ -499,7 +840,8 @@ impl TUI {
                     if let Some(nick) = self.tabs[serv_tab_idx].widget.get_nick() {
                         self.tabs[tab_idx].widget.set_nick(nick);
                     }
-                    Some(tab_idx)
+                    self.sort_tabs();
+                    self.find_chan_tab_idx(serv, chan)
                 }
             },
             Some(_) => None,
@@ -514,6 +856,7 @@ impl TUI {
             }
         }
         self.fix_scroll_after_close();
+        self.damage.all = true;
     }
 
     /// Returns index of the new tab if a new tab is created.
@@ -537,7 +880,8 @@ impl TUI {
                         self.tabs[tab_idx + 1].widget.set_nick(nick);
                     }
                     self.tabs[tab_idx + 1].widget.join(nick, None, false);
-                    Some(tab_idx + 1)
+                    self.sort_tabs();
+                    self.find_user_tab_idx(serv, nick)
                 }
             },
             Some(_) => None,
@@ -552,6 +896,7 @@ impl TUI {
             }
         }
         self.fix_scroll_after_close();
+        self.damage.all = true;
     }
 
     pub(crate) fn handle_input_event(
@@ -660,6 +1005,14 @@ impl TUI {
         key: Key,
         rcv_editor_ret: &mut Option<editor::ResultReceiver>,
     ) -> Option<TUIRet> {
+        if let Some(help) = &mut self.help {
+            if !help.keypressed(key, self.height - 1) {
+                self.help = None;
+            }
+            self.damage.all = true;
+            return None;
+        }
+
         let key_action = self.key_map.get(&key).or(match key {
             Key::Char(c) => Some(KeyAction::Input(c)),
             Key::AltChar(c) => Some(KeyAction::TabGoto(c)),
@@ -668,6 +1021,10 @@ impl TUI {
 
         let key_action = key_action?;
 
+        if key_action == KeyAction::InputSend && self.confirm_send() {
+            return None;
+        }
+
         match self.tabs[self.active_idx].widget.keypressed(&key_action) {
             WidgetRet::KeyHandled => None,
 
@@ -681,13 +1038,49 @@ impl TUI {
                 from: self.tabs[self.active_idx].src.clone(),
             }),
 
-            WidgetRet::Input(input) => Some(TUIRet::Input {
-                msg: input,
-                from: self.tabs[self.active_idx].src.clone(),
-            }),
+            WidgetRet::Input(input) => {
+                let tab = &mut self.tabs[self.active_idx];
+                tab.highlights = 0;
+                tab.last_sent = Instant::now();
+                if self.scroll_to_bottom_on_send && input.first() != Some(&'/') {
+                    tab.widget.scroll_bottom();
+                }
+                Some(TUIRet::Input {
+                    msg: input,
+                    from: self.tabs[self.active_idx].src.clone(),
+                })
+            }
+
+            WidgetRet::Remove | WidgetRet::SendInput => unimplemented!(),
+        }
+    }
 
-            WidgetRet::Remove => unimplemented!(),
+    /// When `confirm_send_after` is set, ask the user before sending a message to the current
+    /// channel if they haven't sent a message there for that long. The prompt shows the channel,
+    /// to catch messages typed in the wrong tab. Returns whether the user is asked.
+    fn confirm_send(&mut self) -> bool {
+        let Some(confirm_send_after) = self.confirm_send_after else {
+            return false;
+        };
+        let tab = &mut self.tabs[self.active_idx];
+        if !matches!(tab.src, MsgSource::Chan { .. }) || tab.widget.is_dialogue_shown() {
+            return false;
+        }
+        let input = tab.widget.input_field_buffer();
+        if input.is_empty() || input[0] == '/' {
+            return false;
+        }
+        let idle = tab.last_sent.elapsed();
+        if idle < confirm_send_after {
+            return false;
         }
+        let msg = format!(
+            "Send to {}? No messages from you there for {} min. (y/n)",
+            tab.visible_name,
+            idle.as_secs() / 60
+        );
+        tab.widget.show_confirm_send_dialogue(msg);
+        true
     }
 
     fn handle_keypress(
@@ -700,6 +1093,11 @@ impl TUI {
                 self.run_editor("", rcv_editor_ret);
             }
 
+            KeyAction::ShowHelp => {
+                self.help = Some(Help::new(self.help_lines()));
+                self.damage.all = true;
+            }
+
             KeyAction::TabNext => {
                 self.next_tab();
             }
@@ -725,6 +1123,18 @@ impl TUI {
     /// Handles resize events. Call on SIGWINCH.
     pub(crate) fn resize(&mut self) {
         self.tb.resize();
+        self.update_size();
+    }
+
+    /// Clears the terminal and draws everything from scratch. Used by `/redraw` when the screen is
+    /// messed up, e.g. after a missed resize or by another program writing to the terminal.
+    fn redraw(&mut self) {
+        self.tb.force_redraw();
+        self.update_size();
+    }
+
+    /// Re-read terminal size from termbox and update the layout.
+    fn update_size(&mut self) {
         self.tb.clear();
 
         self.width = self.tb.width();
@@ -744,10 +1154,19 @@ impl TUI {
     }
 
     fn resize_(&mut self) {
+        self.damage.all = true;
         for tab in &mut self.tabs {
             tab.widget.resize(self.width, self.height - 1);
         }
         // scroll the tab bar so that currently active tab is still visible
+        self.scroll_to_active_tab();
+
+        // redraw after resize
+        self.draw()
+    }
+
+    /// Scroll the tab bar so that the active tab is visible, showing as many tabs as possible.
+    fn scroll_to_active_tab(&mut self) {
         let (mut tab_left, mut tab_right) = self.rendered_tabs();
         if tab_left == tab_right {
             // nothing to show
@@ -791,9 +1210,6 @@ impl TUI {
             tab_left = tab_left_;
             num_visible = num_visible_;
         }
-
-        // redraw after resize
-        self.draw()
     }
 
     pub(crate) fn get_tab_config(
@@ -822,6 +1238,49 @@ impl TUI {
 ////////////////////////////////////////////////////////////////////////////////
 // Rendering
 
+/// Find a switch character for a new tab with name `visible_name`: an alphabetic character in the
+/// name that is used the least by `tabs`, and not bound to another action with alt.
+fn switch_char(key_map: &KeyMap, tabs: &[Tab], visible_name: &str) -> Option<char> {
+    // Maps a switch key to number of times it's used
+    let mut switch_keys: HashMap<char, u16> = HashMap::with_capacity(tabs.len());
+
+    for tab in tabs {
+        if let Some(key) = tab.switch {
+            *switch_keys.entry(key).or_default() += 1;
+        }
+    }
+
+    // From the characters in tab name, find the one that is used the least
+    let mut new_tab_switch_char: Option<(char, u16)> = None;
+    for ch in visible_name.chars() {
+        if !ch.is_alphabetic() {
+            continue;
+        }
+        // Skip keys bound to other actions, e.g. `alt_d`
+        if matches!(key_map.get(&Key::AltChar(ch)), Some(action) if action != KeyAction::TabGoto(ch))
+        {
+            continue;
+        }
+        match switch_keys.get(&ch).copied() {
+            None => {
+                new_tab_switch_char = Some((ch, 0));
+                break;
+            }
+            Some(n_uses) => match new_tab_switch_char {
+                None => {
+                    new_tab_switch_char = Some((ch, n_uses));
+                }
+                Some((_, new_tab_switch_char_n_uses)) => {
+                    if new_tab_switch_char_n_uses > n_uses {
+                        new_tab_switch_char = Some((ch, n_uses));
+                    }
+                }
+            },
+        }
+    }
+    new_tab_switch_char.map(|(ch, _)| ch)
+}
+
 fn arrow_style(tabs: &[Tab], colors: &Colors) -> Style {
     let tab_style = tabs
         .iter()
@@ -906,17 +1365,59 @@ impl TUI {
         (i, j)
     }
 
+    /// Draw the parts of the screen that changed since the last call and update the terminal.
     pub fn draw(&mut self) {
-        self.tb.clear();
-
         if self.height < 2 {
+            self.tb.clear();
+            self.damage.all = true;
             return;
         }
 
-        self.tabs[self.active_idx]
+        let Damage { all, tab_bar } = std::mem::take(&mut self.damage);
+
+        if all {
+            self.tb.clear();
+            self.tabs[self.active_idx].widget.invalidate();
+        }
+
+        let widget_drawn = self.tabs[self.active_idx]
             .widget
             .draw(&mut self.tb, &self.colors, 0, 0);
 
+        if let Some(help) = &self.help {
+            help.draw(&mut self.tb, &self.colors, self.width, self.height - 1);
+        }
+
+        if all || tab_bar {
+            self.tb.clear_lines(self.height - 1, self.height);
+            self.draw_tab_bar();
+        } else if !widget_drawn {
+            // Nothing changed, avoid writing to the terminal
+            return;
+        }
+
+        self.tb.present();
+    }
+
+    /// Contents of the help popup: key bindings and common commands.
+    fn help_lines(&self) -> Vec<String> {
+        let mut lines = vec!["Key bindings:".to_owned()];
+        for (action, keys) in self.key_map.bindings() {
+            lines.push(format!("  {}: {}", action, keys.join(", ")));
+        }
+        lines.push(String::new());
+        lines.push("Commands (`/help` lists all):".to_owned());
+        for cmd in COMMON_CLIENT_COMMANDS.iter().chain(TUI_COMMANDS.iter()) {
+            lines.push(format!(
+                "  {} - {}",
+                cmd.usage.replace('`', ""),
+                cmd.description
+            ));
+        }
+        lines
+    }
+
+    fn draw_tab_bar(&mut self) {
         // decide whether we need to draw left/right arrows in tab bar
         let left_arr = self.draw_left_arrow();
         let right_arr = self.draw_right_arrow();
@@ -952,8 +1453,6 @@ impl TUI {
             self.tb
                 .change_cell(pos_x, self.height - 1, RIGHT_ARROW, style.fg, style.bg);
         }
-
-        self.tb.present();
     }
 
     ////////////////////////////////////////////////////////////////////////////
@@ -1025,6 +1524,79 @@ impl TUI {
         self.h_scroll -= scroll_left;
     }
 
+    /// Search all tabs and show matching lines in the current tab.
+    fn grep(&mut self, words: &mut SplitWhitespace) {
+        let mut ignore_case = false;
+        let mut max_results = GREP_MAX_RESULTS;
+        let mut query_words: Vec<&str> = vec![];
+        while let Some(word) = words.next() {
+            if !query_words.is_empty() {
+                query_words.push(word);
+                continue;
+            }
+            match word {
+                "-i" => ignore_case = true,
+                "-n" => match words.next().and_then(|n| n.parse::<usize>().ok()) {
+                    Some(n) if n > 0 => max_results = n,
+                    _ => {
+                        self.add_client_err_msg(
+                            &format!("Usage: {}", GREP_CMD.usage),
+                            &MsgTarget::CurrentTab,
+                        );
+                        return;
+                    }
+                },
+                _ => query_words.push(word),
+            }
+        }
+
+        if query_words.is_empty() {
+            self.add_client_err_msg(
+                &format!("Usage: {}", GREP_CMD.usage),
+                &MsgTarget::CurrentTab,
+            );
+            return;
+        }
+        let query = query_words.join(" ");
+
+        let mut results: Vec<String> = vec![];
+        let mut total = 0;
+        for tab in &self.tabs {
+            for (ts, text) in tab.widget.search(&query, ignore_case) {
+                total += 1;
+                if results.len() < max_results {
+                    results.push(match ts {
+                        Some(ts) => format!("[{}] {} {}", tab.visible_name(), ts, text),
+                        None => format!("[{}] {}", tab.visible_name(), text),
+                    });
+                }
+            }
+        }
+
+        if results.is_empty() {
+            self.add_client_notify_msg(
+                &format!("No matches for \"{query}\""),
+                &MsgTarget::CurrentTab,
+            );
+            return;
+        }
+
+        self.add_client_notify_msg(&format!("Matches for \"{query}\":"), &MsgTarget::CurrentTab);
+        for result in &results {
+            self.tabs[self.active_idx].widget.add_search_result(result);
+        }
+        let n_more = total - results.len();
+        if n_more != 0 {
+            self.add_client_notify_msg(
+                &format!(
+                    "{n_more} more {} not shown, use `-n` to show more",
+                    if n_more == 1 { "match" } else { "matches" }
+                ),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    }
+
     pub(crate) fn switch(&mut self, string: &str) {
         let mut next_idx = self.active_idx;
         for (tab_idx, tab) in self.tabs.iter().enumerate() {
@@ -1055,7 +1627,73 @@ impl TUI {
         }
     }
 
+    /// Switch to the tab with the name that best matches `query`: exact matches first, then
+    /// prefix, substring and subsequence matches, ignoring case. When multiple tabs match equally
+    /// well they're listed, to be selected with `/go <n>`.
+    pub(crate) fn go(&mut self, query: &str) {
+        if let Ok(n) = query.parse::<usize>()
+            && !self.go_matches.is_empty()
+        {
+            let tab_idx = n
+                .checked_sub(1)
+                .and_then(|i| self.go_matches.get(i))
+                .and_then(|src| self.tabs.iter().position(|tab| &tab.src == src));
+            match tab_idx {
+                Some(tab_idx) => self.select_tab(tab_idx),
+                None => self.add_client_err_msg(
+                    &format!("No tab {n} in the last `/go` matches"),
+                    &MsgTarget::CurrentTab,
+                ),
+            }
+            return;
+        }
+
+        let query = query.to_lowercase();
+        let mut best_rank = None;
+        let mut matches: Vec<usize> = vec![];
+        for (tab_idx, tab) in self.tabs.iter().enumerate() {
+            let Some(rank) = go_match_rank(&tab.visible_name.to_lowercase(), &query) else {
+                continue;
+            };
+            if best_rank.is_none_or(|best_rank| rank < best_rank) {
+                best_rank = Some(rank);
+                matches.clear();
+            }
+            if best_rank == Some(rank) {
+                matches.push(tab_idx);
+            }
+        }
+
+        match matches.as_slice() {
+            [] => {
+                self.add_client_err_msg(&format!("No tab matches {query}"), &MsgTarget::CurrentTab)
+            }
+            [tab_idx] => self.select_tab(*tab_idx),
+            _ => {
+                self.go_matches = matches
+                    .iter()
+                    .map(|tab_idx| self.tabs[*tab_idx].src.clone())
+                    .collect();
+                self.add_client_msg(
+                    "Multiple tabs match, use `/go <n>` to switch:",
+                    &MsgTarget::CurrentTab,
+                );
+                for (i, tab_idx) in matches.into_iter().enumerate() {
+                    let tab = &self.tabs[tab_idx];
+                    let line = match &tab.src {
+                        MsgSource::Serv { .. } => format!("{}: {}", i + 1, tab.visible_name),
+                        MsgSource::Chan { serv, .. } | MsgSource::User { serv, .. } => {
+                            format!("{}: {} ({})", i + 1, tab.visible_name, serv)
+                        }
+                    };
+                    self.add_client_msg(&line, &MsgTarget::CurrentTab);
+                }
+            }
+        }
+    }
+
     fn next_tab_(&mut self) {
+        self.damage.all = true;
         if self.active_idx == self.tabs.len() - 1 {
             self.active_idx = 0;
             self.h_scroll = 0;
@@ -1077,6 +1715,7 @@ impl TUI {
     }
 
     fn prev_tab_(&mut self) {
+        self.damage.all = true;
         if self.active_idx == 0 {
             let next_active = self.tabs.len() - 1;
             while self.active_idx != next_active {
@@ -1100,10 +1739,79 @@ impl TUI {
         }
     }
 
+    /// Sort channel and user tabs of each server according to `tab_order`. Server tabs are not
+    /// moved. Switch characters of the tabs are updated for the new order.
+    fn sort_tabs(&mut self) {
+        if self.tab_order == TabOrder::Join || self.tabs.is_empty() {
+            return;
+        }
+
+        let mut servs: Vec<String> = vec![];
+        for tab in &self.tabs {
+            if !servs.iter().any(|serv| serv == tab.src.serv_name()) {
+                servs.push(tab.src.serv_name().to_owned());
+            }
+        }
+        let sort_key = |tab: &Tab| {
+            let serv_idx = servs
+                .iter()
+                .position(|serv| serv == tab.src.serv_name())
+                .unwrap();
+            (serv_idx, self.tab_order.key(&tab.src))
+        };
+
+        if self.tabs.is_sorted_by_key(sort_key) {
+            return;
+        }
+
+        let active_src = self.tabs[self.active_idx].src.clone();
+        self.tabs.sort_by_cached_key(sort_key);
+        self.active_idx = self
+            .tabs
+            .iter()
+            .position(|tab| tab.src == active_src)
+            .unwrap();
+
+        for tab_idx in 0..self.tabs.len() {
+            let (tabs, rest) = self.tabs.split_at_mut(tab_idx);
+            let tab = &mut rest[0];
+            tab.switch = switch_char(&self.key_map, tabs, &tab.visible_name);
+        }
+
+        self.scroll_to_active_tab();
+        self.damage.all = true;
+    }
+
+    /// When tabs are sorted automatically, channel and user tabs can only be moved over the tabs
+    /// with the same sort key. Shows an error and returns `true` when the active tab can't be
+    /// moved over the tab at `idx`.
+    fn check_tab_move(&mut self, idx: usize) -> bool {
+        if self.tab_order == TabOrder::Join || self.is_server_tab(self.active_idx) {
+            return false;
+        }
+        let key = self.tab_order.key(&self.tabs[self.active_idx].src);
+        if self.is_server_tab(idx) || key != self.tab_order.key(&self.tabs[idx].src) {
+            let order = match self.tab_order {
+                TabOrder::Name => "sorted by name",
+                _ => "grouped by type",
+            };
+            self.add_client_err_msg(
+                &format!(
+                    "Can't move the tab: channel and user tabs are {order} (`tab_order: {}`)",
+                    self.tab_order.as_str()
+                ),
+                &MsgTarget::CurrentTab,
+            );
+            return true;
+        }
+        false
+    }
+
     fn move_tab_left(&mut self) {
-        if self.active_idx == 0 {
+        if self.active_idx == 0 || self.check_tab_move(self.active_idx - 1) {
             return;
         }
+        self.damage.all = true;
         if self.is_server_tab(self.active_idx) {
             // move all server tabs
             let (left, right) = self.server_tab_range(self.active_idx);
@@ -1125,9 +1833,10 @@ impl TUI {
     }
 
     fn move_tab_right(&mut self) {
-        if self.active_idx == self.tabs.len() - 1 {
+        if self.active_idx == self.tabs.len() - 1 || self.check_tab_move(self.active_idx + 1) {
             return;
         }
+        self.damage.all = true;
         if self.is_server_tab(self.active_idx) {
             // move all server tabs
             let (left, right) = self.server_tab_range(self.active_idx);
@@ -1229,7 +1938,7 @@ impl TUI {
                         nick: ref nick_,
                     } = tab.src
                         && serv == serv_
-                        && nick == nick_
+                        && self.nick_eq(serv, nick, nick_)
                     {
                         target_idxs.push(tab_idx);
                         break;
@@ -1285,11 +1994,19 @@ impl TUI {
             )
             .ignore
             .unwrap_or_default();
+        let mut tab_bar_changed = false;
         self.apply_to_target(target, false, &mut |tab: &mut Tab, is_active: bool| {
+            if style == TabStyle::Highlight {
+                tab.highlights += 1;
+            }
             if (!ignore || style != TabStyle::JoinOrPart) && tab.style < style && !is_active {
                 tab.set_style(style);
+                tab_bar_changed = true;
             }
         });
+        if tab_bar_changed {
+            self.damage.tab_bar = true;
+        }
     }
 
     /// An error message coming from Tiny, probably because of a command error
@@ -1339,11 +2056,37 @@ impl TUI {
                 .add_privmsg(sender, msg, Timestamp::from(ts), highlight, is_action);
             let nick = tab.widget.get_nick();
             if let Some(nick_) = nick {
-                notifier.notify_privmsg(sender, msg, target, &nick_, highlight);
+                notifier.notify_privmsg(sender, msg, target, &nick_, highlight, is_action);
             }
         });
     }
 
+    /// Like `add_privmsg`, but for messages replayed by the server or a bouncer (e.g. in a
+    /// `znc.in/playback` batch), which don't trigger notifications.
+    pub(crate) fn add_playback_privmsg(
+        &mut self,
+        sender: &str,
+        msg: &str,
+        ts: Tm,
+        target: &MsgTarget,
+        highlight: bool,
+        is_action: bool,
+    ) {
+        self.apply_to_target(target, true, &mut |tab: &mut Tab, _| {
+            tab.widget
+                .add_privmsg(sender, msg, Timestamp::from(ts), highlight, is_action);
+        });
+    }
+
+    /// Add messages fetched from the server to a tab. With `prepend` the messages are added
+    /// before the messages in the tab, otherwise after them. Unlike `add_privmsg` these messages
+    /// don't trigger notifications.
+    pub(crate) fn add_history(&mut self, msgs: &[HistoryMsg], prepend: bool, target: &MsgTarget) {
+        self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
+            tab.widget.add_history(msgs, prepend);
+        });
+    }
+
     /// A message without any explicit sender info. Useful for e.g. in server
     /// and debug log tabs. Timestamped and logged.
     pub fn add_msg(&mut self, msg: &str, ts: Tm, target: &MsgTarget) {
@@ -1352,6 +2095,13 @@ impl TUI {
         });
     }
 
+    /// Server notices and WALLOPS. Timestamped and logged.
+    pub fn add_server_notice(&mut self, msg: &str, ts: Tm, target: &MsgTarget) {
+        self.apply_to_target(target, true, &mut |tab: &mut Tab, _| {
+            tab.widget.add_server_notice(msg, Timestamp::from(ts));
+        });
+    }
+
     /// Error messages related with the protocol - e.g. can't join a channel,
     /// nickname is in use etc. Timestamped and logged.
     pub(crate) fn add_err_msg(&mut self, msg: &str, ts: Tm, target: &MsgTarget) {
@@ -1367,9 +2117,9 @@ impl TUI {
         });
     }
 
-    pub(crate) fn clear_nicks(&mut self, serv: &str) {
-        let target = MsgTarget::AllServTabs { serv };
-        self.apply_to_target(&target, false, &mut |tab: &mut Tab, _| {
+    /// Clear nick lists of tabs, e.g. when disconnected.
+    pub(crate) fn clear_nicks(&mut self, target: &MsgTarget) {
+        self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
             tab.widget.clear_nicks();
         });
     }
@@ -1388,6 +2138,14 @@ impl TUI {
         });
     }
 
+    /// Add nicks to the nick lists of tabs without showing joins, e.g. when receiving the list of
+    /// users in a channel.
+    pub(crate) fn add_nicks(&mut self, nicks: &[String], target: &MsgTarget) {
+        self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
+            tab.widget.add_nicks(nicks);
+        });
+    }
+
     pub(crate) fn remove_nick(&mut self, nick: &str, ts: Option<Tm>, target: &MsgTarget) {
         let ignore = self
             .get_tab_config(
@@ -1402,6 +2160,62 @@ impl TUI {
         });
     }
 
+    /// Like `remove_nick`, but for quits caused by a netsplit between `servers`. Shown as a normal
+    /// quit unless `collapse_netsplits` is set.
+    pub(crate) fn netsplit_quit(
+        &mut self,
+        nick: &str,
+        servers: &str,
+        ts: Option<Tm>,
+        target: &MsgTarget,
+    ) {
+        if !self.collapse_netsplits {
+            self.remove_nick(nick, ts, target);
+            return;
+        }
+
+        let ignore = self
+            .get_tab_config(
+                target.serv_name().unwrap_or_default(),
+                target.chan_or_user_name(),
+            )
+            .ignore
+            .unwrap_or_default();
+
+        self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
+            tab.widget
+                .netsplit_quit(nick, servers, ts.map(Timestamp::from), ignore);
+        });
+    }
+
+    /// Like `add_nick`, but for joins in a netjoin after the netsplit between `servers` is over.
+    /// Shown as a normal join unless `collapse_netsplits` is set.
+    pub(crate) fn netjoin(
+        &mut self,
+        nick: &str,
+        servers: &str,
+        ts: Option<Tm>,
+        target: &MsgTarget,
+    ) {
+        if !self.collapse_netsplits {
+            self.add_nick(nick, ts, target);
+            return;
+        }
+
+        let ignore = self
+            .get_tab_config(
+                target.serv_name().unwrap_or_default(),
+                target.chan_or_user_name(),
+            )
+            .ignore
+            .unwrap_or_default();
+
+        self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
+            tab.widget
+                .netjoin(nick, servers, ts.map(Timestamp::from), ignore);
+        });
+    }
+
     pub(crate) fn rename_nick(
         &mut self,
         old_nick: &str,
@@ -1409,16 +2223,24 @@ impl TUI {
         ts: Tm,
         target: &MsgTarget,
     ) {
+        let mut tab_renamed = false;
         self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
             tab.widget.nick(old_nick, new_nick, Timestamp::from(ts));
-            // TODO: Does this actually rename the tab?
             tab.update_source(&|src: &mut MsgSource| {
                 if let MsgSource::User { ref mut nick, .. } = *src {
                     nick.clear();
                     nick.push_str(new_nick);
                 }
             });
+            if matches!(tab.src, MsgSource::User { .. }) && tab.visible_name == old_nick {
+                tab.visible_name = new_nick.to_owned();
+                tab_renamed = true;
+            }
         });
+        if tab_renamed {
+            self.damage.tab_bar = true;
+            self.sort_tabs();
+        }
     }
 
     pub(crate) fn set_nick(&mut self, serv: &str, new_nick: &str) {
@@ -1428,16 +2250,215 @@ impl TUI {
         });
     }
 
+    /// Show or hide the away indicator in the server tab of `serv`.
+    pub(crate) fn set_away(&mut self, serv: &str, away: bool) {
+        if let Some(tab_idx) = self.find_serv_tab_idx(serv) {
+            self.set_tab_away(tab_idx, away);
+        }
+    }
+
+    /// Show or hide the away indicator in the user tab of `nick`, if the tab exists.
+    /// Show or hide `nick` in the typing line of the target tab, after a typing notification.
+    pub(crate) fn set_typing(&mut self, nick: &str, state: TypingState, target: &MsgTarget) {
+        if !self.show_typing {
+            return;
+        }
+        let now = Instant::now();
+        self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
+            tab.widget
+                .set_typing(nick, state == TypingState::Active, now);
+        });
+    }
+
+    /// Show the number of our messages to the target waiting to be sent because of flood
+    /// protection. 0 hides the indicator.
+    pub(crate) fn set_queued(&mut self, n_queued: usize, target: &MsgTarget) {
+        self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
+            tab.widget.set_queued(n_queued);
+        });
+    }
+
+    /// Update our typing state after an input event. Returns the typing notifications to send.
+    /// Typing in a new tab ends typing in the previous one.
+    pub(crate) fn typing_update(&mut self, now: Instant) -> Vec<(TypingState, MsgSource)> {
+        let mut notifications = vec![];
+        if !self.typing_notifications {
+            self.own_typing = None;
+            return notifications;
+        }
+
+        let tab = &self.tabs[self.active_idx];
+        let input = tab.widget.input_field_buffer();
+        let typing = matches!(tab.src, MsgSource::Chan { .. } | MsgSource::User { .. })
+            && !input.is_empty()
+            && input[0] != '/';
+
+        if let Some(own_typing) = &mut self.own_typing
+            && own_typing.src == tab.src
+            && typing
+        {
+            if own_typing.input != input {
+                own_typing.input = input.to_vec();
+                own_typing.state = TypingState::Active;
+                own_typing.last_change = now;
+                notifications.push((TypingState::Active, tab.src.clone()));
+            }
+            return notifications;
+        }
+
+        if let Some(own_typing) = self.own_typing.take() {
+            notifications.push((TypingState::Done, own_typing.src));
+        }
+        if typing {
+            self.own_typing = Some(OwnTyping {
+                src: tab.src.clone(),
+                input: input.to_vec(),
+                state: TypingState::Active,
+                last_change: now,
+            });
+            notifications.push((TypingState::Active, tab.src.clone()));
+        }
+        notifications
+    }
+
+    /// Called periodically to hide timed out typing notifications, and to pause our typing when
+    /// the input field doesn't change for a while. Returns the typing notification to send.
+    pub(crate) fn typing_tick(&mut self, now: Instant) -> Option<(TypingState, MsgSource)> {
+        for tab in &mut self.tabs {
+            tab.widget.expire_typing(now);
+        }
+
+        let own_typing = self.own_typing.as_mut()?;
+        if own_typing.state == TypingState::Active
+            && now.duration_since(own_typing.last_change) >= TYPING_PAUSE_TIMEOUT
+        {
+            own_typing.state = TypingState::Paused;
+            return Some((TypingState::Paused, own_typing.src.clone()));
+        }
+        None
+    }
+
+    pub(crate) fn set_nick_away(&mut self, serv: &str, nick: &str, away: bool) {
+        if let Some(tab_idx) = self.find_user_tab_idx(serv, nick) {
+            self.set_tab_away(tab_idx, away);
+        }
+    }
+
+    /// Set how nicks of the server are compared. Tabs of nicks that differ only in case are the
+    /// same tab. Existing tabs are not merged: when the new case mapping makes the nicks of two
+    /// tabs equal, messages go to the first one.
+    pub(crate) fn set_case_mapping(&mut self, serv: &str, case_mapping: CaseMapping) {
+        self.case_mappings.insert(serv.to_owned(), case_mapping);
+    }
+
+    /// Whether two nicks of the server are the same.
+    fn nick_eq(&self, serv: &str, nick1: &str, nick2: &str) -> bool {
+        self.case_mappings
+            .get(serv)
+            .copied()
+            .unwrap_or_default()
+            .str_eq(nick1, nick2)
+    }
+
+    /// Show the modes of a channel after the channel tab name. `modes` is in the MODE syntax
+    /// (e.g. `+klnt key 10`), only the mode letters are shown.
+    pub(crate) fn set_chan_modes(&mut self, serv: &str, chan: &ChanNameRef, modes: &str) {
+        let Some(tab_idx) = self.find_chan_tab_idx(serv, chan) else {
+            return;
+        };
+        let modes = modes.split(' ').next().unwrap_or_default();
+        if self.tabs[tab_idx].modes != modes {
+            self.tabs[tab_idx].modes = modes.to_owned();
+            // Tab widths changed, make sure the active tab is still visible
+            self.scroll_to_active_tab();
+            self.damage.tab_bar = true;
+        }
+    }
+
+    fn set_tab_away(&mut self, tab_idx: usize, away: bool) {
+        if self.tabs[tab_idx].away != away {
+            self.tabs[tab_idx].away = away;
+            // Tab widths changed, make sure the active tab is still visible
+            self.scroll_to_active_tab();
+            self.damage.tab_bar = true;
+        }
+    }
+
     pub(crate) fn clear(&mut self, target: &MsgTarget) {
         self.apply_to_target(target, false, &mut |tab: &mut Tab, _| tab.widget.clear());
     }
 
     // TODO: Maybe remove this and add a `create: bool` field to MsgTarget::User
+    /// When `confirm_close` is set, ask the user before closing the tab of `src` if it's a server
+    /// tab or has unread highlights. `cmd` is run when the user answers yes. Returns whether the
+    /// user is asked.
+    pub(crate) fn confirm_close(&mut self, src: &MsgSource, cmd: &str) -> bool {
+        if !self.confirm_close {
+            return false;
+        }
+
+        let tab_idx = match src {
+            MsgSource::Serv { serv } => self.find_serv_tab_idx(serv),
+            MsgSource::Chan { serv, chan } => self.find_chan_tab_idx(serv, chan.borrow()),
+            MsgSource::User { serv, nick } => self.find_user_tab_idx(serv, nick),
+        };
+        let Some(tab_idx) = tab_idx else {
+            return false;
+        };
+        let tab = &mut self.tabs[tab_idx];
+
+        let msg = match (src, tab.highlights) {
+            (MsgSource::Serv { serv }, _) => {
+                format!("Close {serv}? This disconnects from the server. (y/n)")
+            }
+            (_, 0) => return false,
+            (_, 1) => format!(
+                "Close {}? It has 1 unread highlight. (y/n)",
+                tab.visible_name
+            ),
+            (_, n) => format!(
+                "Close {}? It has {} unread highlights. (y/n)",
+                tab.visible_name, n
+            ),
+        };
+        tab.widget.show_confirm_dialogue(msg, cmd.to_owned());
+        true
+    }
+
+    /// Ask the user a yes/no question in the tabs of `target`. `cmd` is run when the user answers
+    /// yes.
+    pub(crate) fn confirm(&mut self, msg: &str, cmd: &str, target: &MsgTarget) {
+        self.apply_to_target(target, false, &mut |tab: &mut Tab, _| {
+            tab.widget
+                .show_confirm_dialogue(msg.to_owned(), cmd.to_owned())
+        });
+    }
+
+    /// Ask for a password in the server tab of `serv`, and then ask `store_msg`. The password and
+    /// the answer are sent to `snd`, which is dropped when the user cancels or the tab doesn't
+    /// exist.
+    pub(crate) fn ask_password(
+        &mut self,
+        serv: &str,
+        msg: &str,
+        store_msg: &str,
+        snd: oneshot::Sender<(String, bool)>,
+    ) {
+        if let Some(tab_idx) = self.find_serv_tab_idx(serv) {
+            self.tabs[tab_idx].widget.show_password_dialogue(
+                msg.to_owned(),
+                store_msg.to_owned(),
+                snd,
+            );
+            self.set_tab_style(TabStyle::Highlight, &MsgTarget::Server { serv });
+        }
+    }
+
     pub(crate) fn user_tab_exists(&self, serv_: &str, nick_: &str) -> bool {
         for tab in &self.tabs {
             if let MsgSource::User { ref serv, ref nick } = tab.src
                 && serv_ == serv
-                && nick_ == nick
+                && self.nick_eq(serv, nick_, nick)
             {
                 return true;
             }
@@ -1498,7 +2519,7 @@ impl TUI {
         for (tab_idx, tab) in self.tabs.iter().enumerate() {
             if let MsgSource::User { ref serv, ref nick } = tab.src
                 && serv_ == serv
-                && nick_ == nick
+                && self.nick_eq(serv, nick_, nick)
             {
                 return Some(tab_idx);
             }
@@ -1537,3 +2558,20 @@ impl TUI {
         (left, right)
     }
 }
+
+/// How well a lowercase tab name matches a lowercase `/go` query, lower is better. `None` when it
+/// doesn't match. Channel name prefixes are optional in exact and prefix matches.
+fn go_match_rank(name: &str, query: &str) -> Option<u8> {
+    let short_name = name.trim_start_matches(['#', '&']);
+    if name == query || short_name == query {
+        Some(0)
+    } else if name.starts_with(query) || short_name.starts_with(query) {
+        Some(1)
+    } else if name.contains(query) {
+        Some(2)
+    } else {
+        // Characters of the query in the same order, e.g. `tny` for `#tiny`
+        let mut chars = name.chars();
+        query.chars().all(|c| chars.any(|c_| c_ == c)).then_some(3)
+    }
+}
@@ -11,6 +11,10 @@ pub(crate) enum WidgetRet {
     /// A command is ran.
     Command(String),
 
+    /// The user confirmed sending the input field contents. Handled by the widget that owns the
+    /// input field.
+    SendInput,
+
     /// Remove the widget. E.g. close the tab, hide the dialogue etc.
     Remove,
 }
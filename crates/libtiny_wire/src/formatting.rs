@@ -174,11 +174,18 @@ struct FormatEventParser<'a> {
 
     /// Current index in `str`. We maintain indices to be able to extract slices from `str`.
     cursor: usize,
+
+    /// Index of the last returned event in `str`.
+    event_start: usize,
 }
 
 impl<'a> FormatEventParser<'a> {
     fn new(str: &'a str) -> Self {
-        Self { str, cursor: 0 }
+        Self {
+            str,
+            cursor: 0,
+            event_start: 0,
+        }
     }
 
     fn peek(&self) -> Option<char> {
@@ -295,6 +302,7 @@ impl<'a> Iterator for FormatEventParser<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
+            self.event_start = self.cursor;
             let next = self.peek()?;
 
             match next {
@@ -374,6 +382,17 @@ pub fn parse_irc_formatting(s: &str) -> impl Iterator<Item = IrcFormatEvent<'_>>
     FormatEventParser::new(s)
 }
 
+/// Same as `parse_irc_formatting`, but also returns the byte index of each event in `s`. Note that
+/// `IrcFormatEvent::Text`s for tabs and newlines are not slices of `s`, and are longer than the
+/// character they replace.
+pub fn parse_irc_formatting_indices(s: &str) -> impl Iterator<Item = (usize, IrcFormatEvent<'_>)> {
+    let mut parser = FormatEventParser::new(s);
+    std::iter::from_fn(move || {
+        let event = Iterator::next(&mut parser)?;
+        Some((parser.event_start, event))
+    })
+}
+
 /// Removes all IRC formatting characters and ASCII control characters.
 pub fn remove_irc_control_chars(str: &str) -> String {
     let mut s = String::with_capacity(str.len());
@@ -409,6 +428,32 @@ fn test_translate_irc_control_chars() {
     assert_eq!(remove_irc_control_chars("\x03,foo"), ",foo");
 }
 
+#[test]
+fn test_parse_indices() {
+    let s = "a\x02\x01\tb\x0304,05c\r\nd";
+    let events: Vec<(usize, IrcFormatEvent)> = parse_irc_formatting_indices(s).collect();
+    assert_eq!(
+        events,
+        vec![
+            (0, IrcFormatEvent::Text("a")),
+            (1, IrcFormatEvent::Bold),
+            (3, IrcFormatEvent::Text(TAB_STR)),
+            (4, IrcFormatEvent::Text("b")),
+            (
+                5,
+                IrcFormatEvent::Color {
+                    fg: Color::Red,
+                    bg: Some(Color::Brown),
+                }
+            ),
+            (11, IrcFormatEvent::Text("c")),
+            (12, IrcFormatEvent::Text(" ")),
+            (13, IrcFormatEvent::Text(" ")),
+            (14, IrcFormatEvent::Text("d")),
+        ]
+    );
+}
+
 #[test]
 fn test_parse_text_1() {
     let s = "just \x02\x1d\x1f\x1e\x11\x04rrggbb\x16\x0f testing";
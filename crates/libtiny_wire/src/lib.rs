@@ -6,10 +6,14 @@
 //! the IRC message format in full generality.
 
 pub mod formatting;
+pub mod tags;
 
+pub use tags::Tags;
+
+use std::borrow::Cow;
 use std::str;
 
-use libtiny_common::{ChanName, ChanNameRef};
+use libtiny_common::{ChanName, ChanNameRef, TypingState};
 
 pub fn pass(pass: &str) -> String {
     format!("PASS {pass}\r\n")
@@ -39,6 +43,103 @@ pub fn pong(arg: &str) -> String {
     format!("PONG {arg}\r\n")
 }
 
+/// Add a `label` tag to a message, for IRCv3 `labeled-response`.
+pub fn labeled(label: &str, msg: &str) -> String {
+    format!("@label={label} {msg}")
+}
+
+pub fn ison(nicks: &[&str]) -> String {
+    format!("ISON {}\r\n", nicks.join(" "))
+}
+
+/// Query the user, server and channel counts of the network.
+pub fn lusers() -> String {
+    "LUSERS\r\n".to_owned()
+}
+
+pub fn whois(nick: &str) -> String {
+    format!("WHOIS {nick}\r\n")
+}
+
+/// Change real name with IRCv3 `setname`.
+pub fn setname(realname: &str) -> String {
+    format!("SETNAME :{realname}\r\n")
+}
+
+/// Send an IRCv3 `+typing` notification to a channel or user. Needs `message-tags`.
+pub fn typing(target: &str, state: TypingState) -> String {
+    format!("@+typing={} TAGMSG {target}\r\n", state.as_str())
+}
+
+/// Set the topic of a channel. Empty `topic` clears the topic.
+pub fn topic(chan: &ChanNameRef, topic: &str) -> String {
+    format!("TOPIC {} :{}\r\n", chan.display(), topic)
+}
+
+/// Query the local time of the server, or of `target` server in the network.
+pub fn time(target: Option<&str>) -> String {
+    match target {
+        None => "TIME\r\n".to_owned(),
+        Some(target) => format!("TIME {target}\r\n"),
+    }
+}
+
+/// Query the software version of the server, or of `target` server in the network.
+pub fn version(target: Option<&str>) -> String {
+    match target {
+        None => "VERSION\r\n".to_owned(),
+        Some(target) => format!("VERSION {target}\r\n"),
+    }
+}
+
+/// Query statistics of the server, or of `target` server in the network. `query` is usually a
+/// single letter, e.g. `u` for uptime.
+pub fn stats(query: &str, target: Option<&str>) -> String {
+    match target {
+        None => format!("STATS {query}\r\n"),
+        Some(target) => format!("STATS {query} {target}\r\n"),
+    }
+}
+
+/// Query the administrative information of the server, or of `target` server in the network.
+pub fn admin(target: Option<&str>) -> String {
+    match target {
+        None => "ADMIN\r\n".to_owned(),
+        Some(target) => format!("ADMIN {target}\r\n"),
+    }
+}
+
+pub fn who(mask: &str) -> String {
+    format!("WHO {mask}\r\n")
+}
+
+/// WHO query with WHOX `fields` (e.g. `tcnf`) and `token`, which servers send back in the
+/// RPL_WHOSPCRPL (354) replies when `fields` includes `t`.
+pub fn whox(mask: &str, fields: &str, token: &str) -> String {
+    format!("WHO {mask} %{fields},{token}\r\n")
+}
+
+pub fn names(chan: &str) -> String {
+    format!("NAMES {chan}\r\n")
+}
+
+/// Query the modes of a channel. Server replies with RPL_CHANNELMODEIS (324), and usually
+/// RPL_CREATIONTIME (329).
+pub fn chan_modes(chan: &str) -> String {
+    format!("MODE {chan}\r\n")
+}
+
+/// Request the latest `limit` messages of a channel or user with IRCv3 `draft/chathistory`.
+pub fn chathistory_latest(target: &str, limit: usize) -> String {
+    format!("CHATHISTORY LATEST {target} * {limit}\r\n")
+}
+
+/// Request `limit` messages of a channel or user sent before `time`, a `server-time` timestamp,
+/// with IRCv3 `draft/chathistory`.
+pub fn chathistory_before(target: &str, time: &str, limit: usize) -> String {
+    format!("CHATHISTORY BEFORE {target} timestamp={time} {limit}\r\n")
+}
+
 pub fn join<'a, I>(chans: I) -> String
 where
     I: Iterator<Item = &'a ChanNameRef> + 'a,
@@ -47,6 +148,10 @@ where
     format!("JOIN {}\r\n", chans.join(","))
 }
 
+pub fn join_with_key(chan: &ChanNameRef, key: &str) -> String {
+    format!("JOIN {} {}\r\n", chan.display(), key)
+}
+
 pub fn part(chan: &ChanNameRef, reason: Option<String>) -> String {
     match reason {
         None => format!("PART {}\r\n", chan.display()),
@@ -62,11 +167,30 @@ pub fn privmsg(msgtarget: &str, msg: &str) -> String {
     format!("PRIVMSG {msgtarget} :{msg}\r\n")
 }
 
+pub fn notice(msgtarget: &str, msg: &str) -> String {
+    assert!(msgtarget.len() + msg.len() + 11 <= 512); // See comments in `privmsg`
+    format!("NOTICE {msgtarget} :{msg}\r\n")
+}
+
 pub fn action(msgtarget: &str, msg: &str) -> String {
     assert!(msgtarget.len() + msg.len() + 21 <= 512); // See comments in `privmsg`
     format!("PRIVMSG {msgtarget} :\x01ACTION {msg}\x01\r\n")
 }
 
+pub fn ctcp_ping(msgtarget: &str, token: &str) -> String {
+    assert!(msgtarget.len() + token.len() + 19 <= 512); // See comments in `privmsg`
+    format!("PRIVMSG {msgtarget} :\x01PING {token}\x01\r\n")
+}
+
+/// Reply to a CTCP request. CTCP replies are sent as NOTICEs.
+pub fn ctcp_reply(msgtarget: &str, ctcp: &str, body: &str) -> String {
+    if body.is_empty() {
+        format!("NOTICE {msgtarget} :\x01{ctcp}\x01\r\n")
+    } else {
+        format!("NOTICE {msgtarget} :\x01{ctcp} {body}\x01\r\n")
+    }
+}
+
 pub fn away(msg: Option<&str>) -> String {
     match msg {
         None => "AWAY\r\n".to_string(),
@@ -74,8 +198,10 @@ pub fn away(msg: Option<&str>) -> String {
     }
 }
 
+/// Version 302 of capability negotiation: the server sends capability values (e.g. `sts=port=6697`)
+/// and may split the list into multiple lines.
 pub fn cap_ls() -> String {
-    "CAP LS\r\n".to_string()
+    "CAP LS 302\r\n".to_string()
 }
 
 pub fn cap_req(cap_identifiers: &[&str]) -> String {
@@ -191,6 +317,8 @@ pub enum MsgTarget {
 /// An IRC message
 #[derive(Debug, PartialEq, Eq)]
 pub struct Msg {
+    /// IRCv3 message tags. Empty when the message doesn't have tags.
+    pub tags: Tags,
     /// Sender of a message. According to RFC 2812 it's optional:
     ///
     /// > If the prefix is missing from the message, it is assumed to have originated from the
@@ -199,11 +327,61 @@ pub struct Msg {
     pub cmd: Cmd,
 }
 
+impl Msg {
+    /// Time of the message in the IRCv3 `server-time` tag (`time=`). `None` if the tag doesn't
+    /// exist or is malformed. See <https://ircv3.net/specs/extensions/server-time>.
+    pub fn server_time(&self) -> Option<time::Timespec> {
+        parse_server_time(self.tags.get("time")?)
+    }
+
+    /// State in the IRCv3 `+typing` client tag, or in `+draft/typing` sent by clients that
+    /// implement the draft version of the spec. `None` if the tag doesn't exist or is invalid.
+    pub fn typing(&self) -> Option<TypingState> {
+        let state = self
+            .tags
+            .get("+typing")
+            .or_else(|| self.tags.get("+draft/typing"))?;
+        TypingState::parse(state)
+    }
+
+    /// Reference of the IRCv3 batch the message is in (`batch=`). `None` if the message is not in
+    /// a batch. See <https://ircv3.net/specs/extensions/batch>.
+    pub fn batch(&self) -> Option<&str> {
+        self.tags.get("batch")
+    }
+}
+
+/// Parse a `server-time` timestamp, e.g. `2011-10-19T16:40:51.620Z`. The time is always in UTC.
+fn parse_server_time(s: &str) -> Option<time::Timespec> {
+    let s = s.strip_suffix('Z')?;
+    let (secs, millis) = match s.split_once('.') {
+        None => (s, 0),
+        Some((secs, millis)) => {
+            if millis.is_empty() || millis.len() > 3 || !millis.bytes().all(|b| b.is_ascii_digit())
+            {
+                return None;
+            }
+            // Right-pad to milliseconds: ".6" is 600 milliseconds
+            (secs, format!("{millis:0<3}").parse::<i32>().ok()?)
+        }
+    };
+    if secs.len() != "2011-10-19T16:40:51".len() {
+        return None;
+    }
+    let tm = time::strptime(secs, "%Y-%m-%dT%H:%M:%S").ok()?;
+    let mut ts = tm.to_timespec();
+    ts.nsec = millis * 1_000_000;
+    Some(ts)
+}
+
 /// A client-to-client protocol message. See <https://defs.ircdocs.horse/defs/ctcp.html>.
 #[derive(Debug, PartialEq, Eq)]
 pub enum CTCP {
     Version,
     Action,
+    Ping,
+    Time,
+    ClientInfo,
     Other(String),
 }
 
@@ -212,9 +390,24 @@ impl CTCP {
         match s {
             "VERSION" => CTCP::Version,
             "ACTION" => CTCP::Action,
+            "PING" => CTCP::Ping,
+            "TIME" => CTCP::Time,
+            "CLIENTINFO" => CTCP::ClientInfo,
             _ => CTCP::Other(s.to_owned()),
         }
     }
+
+    /// The CTCP type as sent in messages, e.g. `VERSION`.
+    pub fn as_str(&self) -> &str {
+        match self {
+            CTCP::Version => "VERSION",
+            CTCP::Action => "ACTION",
+            CTCP::Ping => "PING",
+            CTCP::Time => "TIME",
+            CTCP::ClientInfo => "CLIENTINFO",
+            CTCP::Other(s) => s,
+        }
+    }
 }
 
 /// An IRC command or reply
@@ -233,7 +426,10 @@ pub enum Cmd {
     JOIN {
         // TODO: Same as above, this should be a list ...
         chan: ChanName, // TODO: key field might be useful when joining restricted channels. In
-                        // practice I've never needed it.
+        // practice I've never needed it.
+        /// Services account of the user, with IRCv3 `extended-join`. `None` when the user is not
+        /// logged in or `extended-join` is not enabled.
+        account: Option<String>,
     },
 
     PART {
@@ -242,6 +438,13 @@ pub enum Cmd {
         msg: Option<String>,
     },
 
+    KICK {
+        chan: ChanName,
+        /// The kicked nick
+        nick: String,
+        msg: Option<String>,
+    },
+
     QUIT {
         msg: Option<String>,
         /// Channels of the user that just quit. This is not a part of the IRC message, but
@@ -258,18 +461,63 @@ pub enum Cmd {
         chans: Vec<ChanName>,
     },
 
+    /// Services account change of a user, sent when IRCv3 `account-notify` is enabled. `account`
+    /// is `None` when the user logged out.
+    ACCOUNT {
+        account: Option<String>,
+    },
+
+    /// Away status change of a user, sent when IRCv3 `away-notify` is enabled. `msg` is `None`
+    /// when the user is no longer away.
+    AWAY {
+        msg: Option<String>,
+        /// Channels of the user. Not a part of the IRC message, filled in by `libtiny_client`,
+        /// similar to `QUIT` and `NICK`.
+        chans: Vec<ChanName>,
+    },
+
+    /// Username and host change of a user, sent when IRCv3 `chghost` is enabled.
+    CHGHOST {
+        user: String,
+        host: String,
+        /// Channels of the user. Not a part of the IRC message, filled in by `libtiny_client`,
+        /// similar to `QUIT` and `NICK`.
+        chans: Vec<ChanName>,
+    },
+
+    /// Real name change of a user, sent when IRCv3 `setname` is enabled.
+    SETNAME {
+        realname: String,
+        /// Channels of the user. Not a part of the IRC message, filled in by `libtiny_client`,
+        /// similar to `QUIT` and `NICK`.
+        chans: Vec<ChanName>,
+    },
+
     PING {
         server: String,
     },
 
     PONG {
         server: String,
+        /// The last parameter, which is the argument of the PING message being replied.
+        token: String,
     },
 
     ERROR {
         msg: String,
     },
 
+    /// A message to the users with user mode `+w`, usually sent by IRC operators or servers.
+    WALLOPS {
+        msg: String,
+    },
+
+    /// An invitation of `nick` to `chan`, sent by the user in the prefix.
+    INVITE {
+        nick: String,
+        chan: ChanName,
+    },
+
     TOPIC {
         chan: ChanName,
         topic: String,
@@ -279,12 +527,30 @@ pub enum Cmd {
         client: String,
         subcommand: String,
         params: Vec<String>,
+        /// The list continues in the next CAP message with the same subcommand (`CAP * LS * :...`).
+        more: bool,
     },
 
     AUTHENTICATE {
         param: String,
     },
 
+    /// A message with only tags, sent when IRCv3 `message-tags` is enabled. Used for client-only
+    /// tags like `+typing`.
+    TAGMSG {
+        target: MsgTarget,
+    },
+
+    /// Start or end of an IRCv3 batch, sent when `batch` is enabled. Messages in the batch have a
+    /// `batch` tag with the reference of the batch. Batches can be nested: a `BATCH` message can
+    /// itself be in a batch.
+    BATCH {
+        /// Reference of the batch, without the `+` or `-` prefix.
+        reference: String,
+        /// Type and parameters of the batch when it starts. `None` when it ends.
+        start: Option<BatchStart>,
+    },
+
     /// An IRC message other than the ones listed above.
     Other {
         cmd: String,
@@ -299,6 +565,14 @@ pub enum Cmd {
     },
 }
 
+/// Type and parameters of an IRCv3 batch, e.g. `netsplit` with the names of the servers that
+/// split.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BatchStart {
+    pub batch_type: String,
+    pub params: Vec<String>,
+}
+
 /// An intermediate type used during parsing.
 enum MsgType<'a> {
     Cmd(&'a str),
@@ -307,14 +581,194 @@ enum MsgType<'a> {
 
 static CRLF: [u8; 2] = [b'\r', b'\n'];
 
+/// How to decode incoming messages that are not valid UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FallbackEncoding {
+    /// Replace invalid UTF-8 sequences with U+FFFD REPLACEMENT CHARACTER.
+    #[default]
+    Replace,
+
+    /// Decode the words that are not valid UTF-8 as ISO-8859-1 (Latin-1), where every byte is the
+    /// code point with the same value. Words are decoded separately so that lines mixing the
+    /// encodings (e.g. a Latin-1 message in a UTF-8 channel name) are decoded correctly.
+    Latin1,
+
+    /// Like `Latin1`, but decode bytes 0x80-0x9F as Windows-1252 (CP1252) characters.
+    Cp1252,
+}
+
+impl FallbackEncoding {
+    /// Decode a line received from the server.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        if let Ok(str) = str::from_utf8(bytes) {
+            return str.to_owned();
+        }
+        match self {
+            FallbackEncoding::Replace => String::from_utf8_lossy(bytes).into_owned(),
+            FallbackEncoding::Latin1 | FallbackEncoding::Cp1252 => bytes
+                .split_inclusive(|byte| *byte == b' ')
+                .map(|word| match str::from_utf8(word) {
+                    Ok(word) => word.to_owned(),
+                    Err(_) => word
+                        .iter()
+                        .map(|byte| match self {
+                            FallbackEncoding::Cp1252 => cp1252_char(*byte),
+                            _ => char::from(*byte),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Text encoding of a server. The trailing parameter of incoming messages (message text, topics,
+/// part and quit reasons etc.) is decoded with the encoding, and text of outgoing PRIVMSG and
+/// NOTICE messages is encoded with it. Commands, channel names, and nicks are sent and received
+/// as UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Send and receive UTF-8 as-is.
+    #[default]
+    Utf8,
+
+    /// ISO-8859-1
+    Latin1,
+
+    /// Windows-1252
+    Cp1252,
+}
+
+impl Encoding {
+    /// Decode a line received from the server. With UTF-8 the line is decoded with `fallback`.
+    /// Otherwise the trailing parameter is decoded with the encoding, and the rest of the line
+    /// (tags, prefix, command, and middle parameters) with `fallback`.
+    pub fn decode(self, bytes: &[u8], fallback: FallbackEncoding) -> String {
+        let text_idx = match self {
+            Encoding::Utf8 => None,
+            Encoding::Latin1 | Encoding::Cp1252 => trailing_param_idx(bytes),
+        };
+        let Some(text_idx) = text_idx else {
+            return fallback.decode(bytes);
+        };
+        let mut line = fallback.decode(&bytes[..text_idx]);
+        line.extend(bytes[text_idx..].iter().map(|byte| match self {
+            Encoding::Cp1252 => cp1252_char(*byte),
+            _ => char::from(*byte),
+        }));
+        line
+    }
+
+    /// Encode an outgoing line. Characters in PRIVMSG and NOTICE text that can't be encoded are
+    /// replaced with '?', in which case the `bool` is `true`. With UTF-8 the line is returned
+    /// as-is.
+    pub fn encode_msg(self, msg: &str) -> (Cow<'_, [u8]>, bool) {
+        if self == Encoding::Utf8 {
+            return (Cow::Borrowed(msg.as_bytes()), false);
+        }
+        let Some(text_idx) = privmsg_text_idx(msg) else {
+            return (Cow::Borrowed(msg.as_bytes()), false);
+        };
+        let mut bytes = Vec::with_capacity(msg.len());
+        bytes.extend_from_slice(&msg.as_bytes()[..text_idx]);
+        let mut unmappable = false;
+        for char in msg[text_idx..].chars() {
+            let byte = match self {
+                Encoding::Utf8 => unreachable!(),
+                Encoding::Latin1 => u8::try_from(u32::from(char)).ok(),
+                Encoding::Cp1252 => cp1252_byte(char),
+            };
+            bytes.push(byte.unwrap_or_else(|| {
+                unmappable = true;
+                b'?'
+            }));
+        }
+        (Cow::Owned(bytes), unmappable)
+    }
+}
+
+/// Index of the text in a PRIVMSG or NOTICE line, after the optional tags.
+fn privmsg_text_idx(msg: &str) -> Option<usize> {
+    let cmd_idx = if msg.starts_with('@') {
+        msg.find(' ')? + 1
+    } else {
+        0
+    };
+    let (cmd, _) = msg[cmd_idx..].split_once(' ')?;
+    if !cmd.eq_ignore_ascii_case("PRIVMSG") && !cmd.eq_ignore_ascii_case("NOTICE") {
+        return None;
+    }
+    Some(cmd_idx + msg[cmd_idx..].find(" :")? + 2)
+}
+
+/// Index of the trailing parameter in an incoming line, after the optional tags and prefix.
+fn trailing_param_idx(line: &[u8]) -> Option<usize> {
+    let mut idx = 0;
+    if line.first() == Some(&b'@') {
+        idx = line.iter().position(|byte| *byte == b' ')?;
+    }
+    while line.get(idx) == Some(&b' ') {
+        idx += 1;
+    }
+    if line.get(idx) == Some(&b':') {
+        idx += line[idx..].iter().position(|byte| *byte == b' ')?;
+    }
+    let param_idx = line[idx..].windows(2).position(|sub| sub == b" :")?;
+    Some(idx + param_idx + 2)
+}
+
+/// CP1252 characters of bytes 0x80-0x9F. Bytes not defined in CP1252 are mapped to the C1
+/// control characters with the same value, as in ISO-8859-1.
+const CP1252_80_9F: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+fn cp1252_char(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => CP1252_80_9F[usize::from(byte - 0x80)],
+        _ => char::from(byte),
+    }
+}
+
+fn cp1252_byte(char: char) -> Option<u8> {
+    match u32::from(char) {
+        code @ (0..=0x7F | 0xA0..=0xFF) => Some(code as u8),
+        _ => CP1252_80_9F
+            .iter()
+            .position(|c| *c == char)
+            .map(|idx| 0x80 + idx as u8),
+    }
+}
+
 /// Try to read an IRC message off a buffer. Drops the message when parsing is successful.
-/// Otherwise the buffer is left unchanged.
+/// Otherwise the buffer is left unchanged. Invalid UTF-8 sequences are replaced with U+FFFD
+/// REPLACEMENT CHARACTER, see `parse_irc_msg_with_fallback` for other encodings.
 pub fn parse_irc_msg(buf: &mut Vec<u8>) -> Option<Result<Msg, String>> {
-    // Find "\r\n" separator. We can't do this *after* generating the lossy UTF-8, as that may have
-    // different size than the original buffer after inserting "REPLACEMENT CHARACTER"s.
+    parse_irc_msg_with_fallback(buf, FallbackEncoding::Replace)
+}
+
+/// Like `parse_irc_msg`, but decodes messages that are not valid UTF-8 with `fallback`.
+pub fn parse_irc_msg_with_fallback(
+    buf: &mut Vec<u8>,
+    fallback: FallbackEncoding,
+) -> Option<Result<Msg, String>> {
+    parse_irc_msg_with_encoding(buf, Encoding::Utf8, fallback)
+}
+
+/// Like `parse_irc_msg`, but decodes messages as described in `Encoding::decode`.
+pub fn parse_irc_msg_with_encoding(
+    buf: &mut Vec<u8>,
+    encoding: Encoding,
+    fallback: FallbackEncoding,
+) -> Option<Result<Msg, String>> {
+    // Find "\r\n" separator. We can't do this *after* decoding, as that may have different size
+    // than the original buffer after inserting "REPLACEMENT CHARACTER"s or multi-byte code points.
     let crlf_idx = buf.windows(2).position(|sub| sub == CRLF)?;
 
-    let msg_owned: String = String::from_utf8_lossy(&buf[0..crlf_idx]).to_string();
+    let msg_owned: String = encoding.decode(&buf[0..crlf_idx], fallback);
     let msg: &str = &msg_owned;
 
     let ret = parse_one_message(msg);
@@ -325,6 +779,17 @@ pub fn parse_irc_msg(buf: &mut Vec<u8>) -> Option<Result<Msg, String>> {
 
 // NB. 'msg' does not contain '\r\n' suffix.
 fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
+    let tags = match msg.strip_prefix('@') {
+        Some(tags) => {
+            let ws_idx = tags
+                .find(' ')
+                .ok_or(format!("Can't find tags terminator (' ') in msg: {msg:?}"))?;
+            msg = tags[ws_idx + 1..].trim_start_matches(' ');
+            Tags::parse(&tags[..ws_idx])
+        }
+        None => Tags::default(),
+    };
+
     let pfx: Option<Pfx> = {
         if let Some(':') = msg.chars().next() {
             // parse prefix
@@ -340,11 +805,12 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
     };
 
     let msg_ty: MsgType = {
-        let ws_idx = msg.find(' ').ok_or(format!(
-            "Can't find message type terminator (' ') in msg: {msg:?}"
-        ))?;
-        let cmd = &msg[..ws_idx];
-        msg = &msg[ws_idx + 1..]; // Consume ' '
+        // Commands without parameters (e.g. `AWAY` from `away-notify`) don't have a terminator
+        let (cmd, params) = msg.split_once(' ').unwrap_or((msg, ""));
+        if cmd.is_empty() {
+            return Err(format!("Can't find message type in msg: {msg:?}"));
+        }
+        msg = params;
         match cmd.parse::<u16>() {
             Ok(num) => MsgType::Num(num),
             Err(_) => MsgType::Cmd(cmd),
@@ -393,12 +859,17 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
                 ctcp,
             }
         }
-        MsgType::Cmd("JOIN") if params.len() == 1 => {
+        // With `extended-join` JOIN messages also have the account name and the real name
+        MsgType::Cmd("JOIN") if params.len() == 1 || params.len() == 3 => {
             let chan = params[0];
             Cmd::JOIN {
                 chan: ChanName::new(chan.to_owned()),
+                account: params.get(1).and_then(|account| parse_account(account)),
             }
         }
+        MsgType::Cmd("ACCOUNT") if params.len() == 1 => Cmd::ACCOUNT {
+            account: parse_account(params[0]),
+        },
         MsgType::Cmd("PART") if params.len() == 1 || params.len() == 2 => {
             let mb_msg = if params.len() == 2 {
                 Some(params[1].to_owned())
@@ -410,8 +881,13 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
                 msg: mb_msg,
             }
         }
+        MsgType::Cmd("KICK") if params.len() == 2 || params.len() == 3 => Cmd::KICK {
+            chan: ChanName::new(params[0].to_owned()),
+            nick: params[1].to_owned(),
+            msg: params.get(2).map(|s| (*s).to_owned()),
+        },
         MsgType::Cmd("QUIT") if params.is_empty() || params.len() == 1 => {
-            let mb_msg = params.get(1).map(|s| (*s).to_owned());
+            let mb_msg = params.first().map(|s| (*s).to_owned());
 
             Cmd::QUIT {
                 msg: mb_msg,
@@ -425,15 +901,39 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
                 chans: Vec::new(),
             }
         }
+        MsgType::Cmd("AWAY") if params.len() <= 1 => Cmd::AWAY {
+            msg: params
+                .first()
+                .filter(|msg| !msg.is_empty())
+                .map(|msg| (*msg).to_owned()),
+            chans: Vec::new(),
+        },
+        MsgType::Cmd("CHGHOST") if params.len() == 2 => Cmd::CHGHOST {
+            user: params[0].to_owned(),
+            host: params[1].to_owned(),
+            chans: Vec::new(),
+        },
+        MsgType::Cmd("SETNAME") if params.len() == 1 => Cmd::SETNAME {
+            realname: params[0].to_owned(),
+            chans: Vec::new(),
+        },
         MsgType::Cmd("PING") if params.len() == 1 => Cmd::PING {
             server: params[0].to_owned(),
         },
         MsgType::Cmd("PONG") if !params.is_empty() => Cmd::PONG {
             server: params[0].to_owned(),
+            token: params[params.len() - 1].to_owned(),
         },
         MsgType::Cmd("ERROR") if params.len() == 1 => Cmd::ERROR {
             msg: params[0].to_owned(),
         },
+        MsgType::Cmd("WALLOPS") if params.len() == 1 => Cmd::WALLOPS {
+            msg: params[0].to_owned(),
+        },
+        MsgType::Cmd("INVITE") if params.len() == 2 => Cmd::INVITE {
+            nick: params[0].to_owned(),
+            chan: ChanName::new(params[1].to_owned()),
+        },
         MsgType::Cmd("TOPIC") if params.len() == 2 => Cmd::TOPIC {
             chan: ChanName::new(params[0].to_owned()),
             topic: params[1].to_owned(),
@@ -442,10 +942,44 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
             client: params[0].to_owned(),
             subcommand: params[1].to_owned(),
             params: params[2].split(' ').map(|s| s.to_owned()).collect(),
+            more: false,
+        },
+        MsgType::Cmd("CAP") if params.len() == 4 && params[2] == "*" => Cmd::CAP {
+            client: params[0].to_owned(),
+            subcommand: params[1].to_owned(),
+            params: params[3].split(' ').map(|s| s.to_owned()).collect(),
+            more: true,
         },
         MsgType::Cmd("AUTHENTICATE") if params.len() == 1 => Cmd::AUTHENTICATE {
             param: params[0].to_owned(),
         },
+        MsgType::Cmd("BATCH")
+            if params.len() > 1 && params[0].len() > 1 && params[0].starts_with('+') =>
+        {
+            Cmd::BATCH {
+                reference: params[0][1..].to_owned(),
+                start: Some(BatchStart {
+                    batch_type: params[1].to_owned(),
+                    params: params[2..].iter().map(|s| (*s).to_owned()).collect(),
+                }),
+            }
+        }
+        MsgType::Cmd("BATCH")
+            if params.len() == 1 && params[0].len() > 1 && params[0].starts_with('-') =>
+        {
+            Cmd::BATCH {
+                reference: params[0][1..].to_owned(),
+                start: None,
+            }
+        }
+        MsgType::Cmd("TAGMSG") if params.len() == 1 => {
+            let target = if params[0].starts_with('#') {
+                MsgTarget::Chan(ChanName::new(params[0].to_owned()))
+            } else {
+                MsgTarget::User(params[0].to_owned())
+            };
+            Cmd::TAGMSG { target }
+        }
         MsgType::Num(n) => Cmd::Reply {
             num: n,
             params: params.into_iter().map(|s| s.to_owned()).collect(),
@@ -456,7 +990,16 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
         },
     };
 
-    Ok(Msg { pfx, cmd })
+    Ok(Msg { tags, pfx, cmd })
+}
+
+/// Parse an account name in `ACCOUNT` and extended `JOIN` messages. `*` means not logged in.
+fn parse_account(account: &str) -> Option<String> {
+    if account == "*" {
+        None
+    } else {
+        Some(account.to_owned())
+    }
 }
 
 fn parse_params(chrs: &str) -> Vec<&str> {
@@ -525,6 +1068,18 @@ pub fn drop_nick_prefix(nick: &str) -> &str {
     }
 }
 
+/// Nick in an RPL_NAMREPLY entry, without the membership prefixes (more than one with IRCv3
+/// `multi-prefix`) and the `!user@host` suffix (with IRCv3 `userhost-in-names`).
+pub fn names_nick(name: &str) -> &str {
+    static PREFIXES: [char; 5] = ['~', '&', '@', '%', '+'];
+
+    let nick = name.trim_start_matches(PREFIXES);
+    match nick.split_once('!') {
+        Some((nick, _)) => nick,
+        None => nick,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -568,6 +1123,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::User {
                     nick: "nick".to_owned(),
                     user: "~nick@unaffiliated/nick".to_owned(),
@@ -583,6 +1139,162 @@ mod tests {
         assert_eq!(buf.len(), 0);
     }
 
+    #[test]
+    fn test_fallback_encoding() {
+        // "café" in Latin-1 from a user with a Latin-1 nick, to a channel with a UTF-8 name
+        let line = b":J\xf6rg!~j@host PRIVMSG #caf\xc3\xa9 :caf\xe9 ol\xe9\r\n";
+
+        let mut buf = line.to_vec();
+        let msg = parse_irc_msg_with_fallback(&mut buf, FallbackEncoding::Latin1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            msg.pfx,
+            Some(Pfx::User {
+                nick: "Jörg".to_owned(),
+                user: "~j@host".to_owned(),
+            })
+        );
+        assert_eq!(
+            msg.cmd,
+            Cmd::PRIVMSG {
+                target: MsgTarget::Chan(ChanName::new("#café".to_owned())),
+                msg: "café olé".to_owned(),
+                is_notice: false,
+                ctcp: None,
+            }
+        );
+        assert!(buf.is_empty());
+
+        let mut buf = line.to_vec();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            msg.cmd,
+            Cmd::PRIVMSG {
+                target: MsgTarget::Chan(ChanName::new("#café".to_owned())),
+                msg: "caf\u{fffd} ol\u{fffd}".to_owned(),
+                is_notice: false,
+                ctcp: None,
+            }
+        );
+
+        // Valid UTF-8 is never decoded as Latin-1
+        assert_eq!(
+            FallbackEncoding::Latin1.decode("café".as_bytes()),
+            "café".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_encoding() {
+        let msg = "@+draft/reply=1 PRIVMSG #café :€5 for café ✓\r\n";
+        assert_eq!(
+            Encoding::Cp1252.encode_msg(msg),
+            (
+                Cow::Owned::<[u8]>(
+                    b"@+draft/reply=1 PRIVMSG #caf\xc3\xa9 :\x805 for caf\xe9 ?\r\n".to_vec()
+                ),
+                true
+            )
+        );
+        assert_eq!(
+            Encoding::Latin1.encode_msg("NOTICE jörg :olé\r\n"),
+            (
+                Cow::Owned::<[u8]>(b"NOTICE j\xc3\xb6rg :ol\xe9\r\n".to_vec()),
+                false
+            )
+        );
+        // Only PRIVMSG and NOTICE text is encoded
+        assert_eq!(
+            Encoding::Latin1.encode_msg("TOPIC #a :olé\r\n").0,
+            "TOPIC #a :olé\r\n".as_bytes()
+        );
+        assert_eq!(
+            Encoding::Utf8.encode_msg(msg),
+            (Cow::Borrowed(msg.as_bytes()), false)
+        );
+
+        let mut buf = b"@a=b :J\xf6rg!~j@host PRIVMSG #caf\xc3\xa9 :\x805 for caf\xe9\r\n".to_vec();
+        let msg =
+            parse_irc_msg_with_encoding(&mut buf, Encoding::Cp1252, FallbackEncoding::Replace)
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            msg.pfx,
+            Some(Pfx::User {
+                nick: "J\u{fffd}rg".to_owned(),
+                user: "~j@host".to_owned(),
+            })
+        );
+        assert_eq!(
+            msg.cmd,
+            Cmd::PRIVMSG {
+                target: MsgTarget::Chan(ChanName::new("#café".to_owned())),
+                msg: "€5 for café".to_owned(),
+                is_notice: false,
+                ctcp: None,
+            }
+        );
+
+        // The trailing parameter is decoded with the encoding even when it's valid UTF-8
+        assert_eq!(
+            Encoding::Latin1.decode(b"PART #caf\xc3\xa9 :caf\xc3\xa9", FallbackEncoding::Replace),
+            "PART #café :cafÃ©".to_owned()
+        );
+        // Lines without a trailing parameter are decoded with the fallback
+        assert_eq!(
+            Encoding::Latin1.decode(b"JOIN #caf\xe9", FallbackEncoding::Replace),
+            "JOIN #caf\u{fffd}".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_tags_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            "@time=2011-10-19T16:40:51.620Z;msgid=a\\sb\\:c;+draft/x :nick!~nick@host PRIVMSG #tiny :hi\r\n"
+        )
+        .unwrap();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.tags.len(), 3);
+        assert_eq!(msg.tags.get("time"), Some("2011-10-19T16:40:51.620Z"));
+        assert_eq!(msg.tags.get("msgid"), Some("a b;c"));
+        assert_eq!(msg.tags.get("+draft/x"), Some(""));
+        assert_eq!(
+            msg.pfx,
+            Some(Pfx::User {
+                nick: "nick".to_owned(),
+                user: "~nick@host".to_owned(),
+            })
+        );
+        assert_eq!(
+            msg.server_time(),
+            Some(time::Timespec::new(1319042451, 620_000_000))
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_server_time_parsing() {
+        assert_eq!(
+            parse_server_time("2011-10-19T16:40:51Z"),
+            Some(time::Timespec::new(1319042451, 0))
+        );
+        assert_eq!(
+            parse_server_time("2011-10-19T16:40:51.6Z"),
+            Some(time::Timespec::new(1319042451, 600_000_000))
+        );
+        // Malformed timestamps
+        assert_eq!(parse_server_time("2011-10-19T16:40:51.620"), None);
+        assert_eq!(parse_server_time("2011-10-19T16:40:51.Z"), None);
+        assert_eq!(parse_server_time("2011-10-19T16:40:51.1234Z"), None);
+        assert_eq!(parse_server_time("2011-10-19 16:40:51Z"), None);
+        assert_eq!(parse_server_time("2011-10-19T16:40Z"), None);
+        assert_eq!(parse_server_time("yesterday"), None);
+        assert_eq!(parse_server_time(""), None);
+    }
+
     #[test]
     fn test_notice_parsing() {
         let mut buf = vec![];
@@ -594,6 +1306,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::Server("barjavel.freenode.net".to_owned())),
                 cmd: Cmd::PRIVMSG {
                     target: MsgTarget::User("*".to_owned()),
@@ -652,6 +1365,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::User {
                     nick: "tiny".to_owned(),
                     user: "~tiny@123.123.123.123".to_owned(),
@@ -664,6 +1378,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_kick_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":op!~op@123.123.123.123 KICK #haskell tiny :Bye\r\n\
+             :op!~op@123.123.123.123 KICK #haskell tiny\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::KICK {
+                chan: ChanName::new("#haskell".to_owned()),
+                nick: "tiny".to_owned(),
+                msg: Some("Bye".to_owned()),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::KICK {
+                chan: ChanName::new("#haskell".to_owned()),
+                nick: "tiny".to_owned(),
+                msg: None,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_away_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":nick!~nick@123.123.123.123 AWAY :Gone for lunch\r\n\
+             :nick!~nick@123.123.123.123 AWAY\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::AWAY {
+                msg: Some("Gone for lunch".to_owned()),
+                chans: vec![],
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::AWAY {
+                msg: None,
+                chans: vec![],
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
     #[test]
     fn test_join_parsing() {
         let mut buf = vec![];
@@ -671,18 +1439,232 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::User {
                     nick: "tiny".to_owned(),
                     user: "~tiny@192.168.0.1".to_owned(),
                 }),
                 cmd: Cmd::JOIN {
                     chan: ChanName::new("#haskell".to_owned()),
+                    account: None,
                 },
             }
         );
         assert_eq!(buf.len(), 0);
     }
 
+    #[test]
+    fn test_extended_join_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":tiny!~tiny@192.168.0.1 JOIN #haskell tiny_acct :Real Name\r\n\
+             :tiny!~tiny@192.168.0.1 JOIN #haskell * :Real Name\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::JOIN {
+                chan: ChanName::new("#haskell".to_owned()),
+                account: Some("tiny_acct".to_owned()),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::JOIN {
+                chan: ChanName::new("#haskell".to_owned()),
+                account: None,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_account_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":nick!~nick@host ACCOUNT acct\r\n\
+             :nick!~nick@host ACCOUNT *\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::ACCOUNT {
+                account: Some("acct".to_owned()),
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::ACCOUNT { account: None }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_names_nick() {
+        assert_eq!(names_nick("nick"), "nick");
+        assert_eq!(names_nick("@nick"), "nick");
+        assert_eq!(names_nick("@+nick"), "nick");
+        assert_eq!(names_nick("@+nick!~user@host"), "nick");
+    }
+
+    #[test]
+    fn test_chghost_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":nick!~nick@host CHGHOST nick cloak/nick\r\n").unwrap();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            msg.pfx,
+            Some(Pfx::User {
+                nick: "nick".to_owned(),
+                user: "~nick@host".to_owned(),
+            })
+        );
+        assert_eq!(
+            msg.cmd,
+            Cmd::CHGHOST {
+                user: "nick".to_owned(),
+                host: "cloak/nick".to_owned(),
+                chans: vec![],
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_setname_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":nick!~nick@host SETNAME :New Real Name\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::SETNAME {
+                realname: "New Real Name".to_owned(),
+                chans: vec![],
+            }
+        );
+        assert_eq!(buf.len(), 0);
+        assert_eq!(setname("New Real Name"), "SETNAME :New Real Name\r\n");
+    }
+
+    #[test]
+    fn test_batch_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":irc.server BATCH +ref netsplit irc.hub other.host\r\n\
+             @batch=ref :nick!~nick@host QUIT :irc.hub other.host\r\n\
+             :irc.server BATCH -ref\r\n"
+        )
+        .unwrap();
+
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.batch(), None);
+        assert_eq!(
+            msg.cmd,
+            Cmd::BATCH {
+                reference: "ref".to_owned(),
+                start: Some(BatchStart {
+                    batch_type: "netsplit".to_owned(),
+                    params: vec!["irc.hub".to_owned(), "other.host".to_owned()],
+                }),
+            }
+        );
+
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.batch(), Some("ref"));
+        assert_eq!(
+            msg.cmd,
+            Cmd::QUIT {
+                msg: Some("irc.hub other.host".to_owned()),
+                chans: vec![],
+            }
+        );
+
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            msg.cmd,
+            Cmd::BATCH {
+                reference: "ref".to_owned(),
+                start: None,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_cap_ls_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":irc.server CAP * LS * :multi-prefix sasl=PLAIN,EXTERNAL\r\n\
+             :irc.server CAP * LS :sts=port=6697 server-time\r\n"
+        )
+        .unwrap();
+
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            msg.cmd,
+            Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "LS".to_owned(),
+                params: vec!["multi-prefix".to_owned(), "sasl=PLAIN,EXTERNAL".to_owned()],
+                more: true,
+            }
+        );
+
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            msg.cmd,
+            Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "LS".to_owned(),
+                params: vec!["sts=port=6697".to_owned(), "server-time".to_owned()],
+                more: false,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_tagmsg_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            "@+typing=active :nick!~nick@host TAGMSG #chan\r\n\
+             @+typing=bogus :nick!~nick@host TAGMSG tiny\r\n\
+             @+draft/typing=done :nick!~nick@host TAGMSG tiny\r\n"
+        )
+        .unwrap();
+
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            msg.cmd,
+            Cmd::TAGMSG {
+                target: MsgTarget::Chan(ChanName::new("#chan".to_owned())),
+            }
+        );
+        assert_eq!(msg.typing(), Some(TypingState::Active));
+
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(
+            msg.cmd,
+            Cmd::TAGMSG {
+                target: MsgTarget::User("tiny".to_owned()),
+            }
+        );
+        assert_eq!(msg.typing(), None);
+
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.typing(), Some(TypingState::Done));
+        assert_eq!(buf.len(), 0);
+
+        assert_eq!(
+            typing("#chan", TypingState::Paused),
+            "@+typing=paused TAGMSG #chan\r\n"
+        );
+    }
+
     // Example from https://tools.ietf.org/id/draft-oakley-irc-ctcp-01.html
     #[test]
     fn test_ctcp_action_parsing_1() {
@@ -695,6 +1677,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::User {
                     nick: "dan".to_owned(),
                     user: "u@localhost".to_owned(),
@@ -790,6 +1773,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ctcp_ping_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":a!b@c NOTICE target :\x01PING 1234\x01\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::PRIVMSG {
+                target: MsgTarget::User("target".to_owned()),
+                msg: "1234".to_owned(),
+                is_notice: true,
+                ctcp: Some(CTCP::Ping),
+            }
+        );
+    }
+
+    #[test]
+    fn test_ctcp_time_clientinfo_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":a!b@c PRIVMSG target :\x01TIME\x01\r\n").unwrap();
+        write!(&mut buf, ":a!b@c PRIVMSG target :\x01CLIENTINFO\x01\r\n").unwrap();
+        for ctcp in [CTCP::Time, CTCP::ClientInfo] {
+            assert_eq!(
+                parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+                Cmd::PRIVMSG {
+                    target: MsgTarget::User("target".to_owned()),
+                    msg: "".to_owned(),
+                    is_notice: false,
+                    ctcp: Some(ctcp),
+                }
+            );
+        }
+        assert_eq!(
+            ctcp_reply("nick", "TIME", "Fri Oct 16 10:25:24 2026"),
+            "NOTICE nick :\x01TIME Fri Oct 16 10:25:24 2026\x01\r\n"
+        );
+    }
+
+    #[test]
+    fn test_pong_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":irc.server PONG irc.server :1234\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::PONG {
+                server: "irc.server".to_owned(),
+                token: "1234".to_owned(),
+            }
+        );
+
+        let mut buf = vec![];
+        write!(&mut buf, "PONG :1234\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::PONG {
+                server: "1234".to_owned(),
+                token: "1234".to_owned(),
+            }
+        );
+    }
+
     #[test]
     fn other_ctcp_parsing() {
         let mut buf = vec![];
@@ -828,6 +1871,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: Tags::default(),
                 pfx: None,
                 cmd: Cmd::ERROR {
                     msg: "Closing Link: 212.252.143.51 (Excess Flood)".to_owned(),
@@ -836,6 +1880,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_wallops_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":oper!~oper@host WALLOPS :Server restart soon\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap(),
+            Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "oper".to_owned(),
+                    user: "~oper@host".to_owned(),
+                }),
+                cmd: Cmd::WALLOPS {
+                    msg: "Server restart soon".to_owned(),
+                },
+            },
+        );
+    }
+
+    #[test]
+    fn test_invite_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":alice!~alice@host INVITE tiny :#chan\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap(),
+            Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "alice".to_owned(),
+                    user: "~alice@host".to_owned(),
+                }),
+                cmd: Cmd::INVITE {
+                    nick: "tiny".to_owned(),
+                    chan: ChanName::new("#chan".to_owned()),
+                },
+            },
+        );
+    }
+
     #[test]
     fn test_parse_pfx() {
         use Pfx::*;
@@ -0,0 +1,146 @@
+//! Implements parsing IRCv3 message tags. Reference:
+//! <https://ircv3.net/specs/extensions/message-tags>
+
+use std::collections::HashMap;
+
+/// Tags of a message. Values are unescaped. Tags without a value (`key`) and tags with an empty
+/// value (`key=`) are the same, both have an empty value.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tags {
+    tags: HashMap<String, String>,
+}
+
+impl Tags {
+    /// Parse tags of a message, without the leading `@`. When a key is repeated the last value is
+    /// used.
+    pub fn parse(tags: &str) -> Tags {
+        let tags = tags
+            .split(';')
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| match tag.split_once('=') {
+                Some((key, value)) => (key.to_owned(), unescape_value(value)),
+                None => (tag.to_owned(), String::new()),
+            })
+            .collect();
+        Tags { tags }
+    }
+
+    /// Get value of a tag. Client-only tags are prefixed with `+`, e.g. `+typing`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.tags.contains_key(key)
+    }
+
+    /// Iterate (key, value) pairs, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+}
+
+fn unescape_value(value: &str) -> String {
+    let mut ret = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        // Invalid escapes drop the backslash, a trailing backslash is dropped
+        match chars.next() {
+            Some(':') => ret.push(';'),
+            Some('s') => ret.push(' '),
+            Some('r') => ret.push('\r'),
+            Some('n') => ret.push('\n'),
+            Some(c) => ret.push(c),
+            None => {}
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(tags: &Tags) -> Vec<(&str, &str)> {
+        let mut tags: Vec<(&str, &str)> = tags.iter().collect();
+        tags.sort();
+        tags
+    }
+
+    #[test]
+    fn test_spec_examples() {
+        // Examples in the spec, without the leading `@`
+        let tags = Tags::parse("id=123AB;rose");
+        assert_eq!(sorted(&tags), vec![("id", "123AB"), ("rose", "")]);
+
+        let tags = Tags::parse("url=;netsplit=tur,ty");
+        assert_eq!(sorted(&tags), vec![("netsplit", "tur,ty"), ("url", "")]);
+
+        let tags = Tags::parse("aaa=bbb;ccc;example.com/ddd=eee");
+        assert_eq!(
+            sorted(&tags),
+            vec![("aaa", "bbb"), ("ccc", ""), ("example.com/ddd", "eee")]
+        );
+
+        let tags = Tags::parse(r"+example=raw+:=,escaped\:\s\\");
+        assert_eq!(sorted(&tags), vec![("+example", r"raw+:=,escaped; \")]);
+
+        let tags = Tags::parse("+example-client-tag=example-value");
+        assert_eq!(tags.get("+example-client-tag"), Some("example-value"));
+        assert_eq!(tags.get("example-client-tag"), None);
+
+        let tags = Tags::parse("+example.com/foo=bar");
+        assert_eq!(tags.get("+example.com/foo"), Some("bar"));
+    }
+
+    #[test]
+    fn test_escapes() {
+        let tags = Tags::parse(r"a=\:\s\\\r\n;b=x\by;c=trailing\;d=\\\\");
+        assert_eq!(tags.get("a"), Some("; \\\r\n"));
+        // Invalid escape
+        assert_eq!(tags.get("b"), Some("xby"));
+        // Trailing backslash
+        assert_eq!(tags.get("c"), Some("trailing"));
+        assert_eq!(tags.get("d"), Some(r"\\"));
+    }
+
+    #[test]
+    fn test_empty_values() {
+        let tags = Tags::parse("a;b=;c=d");
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags.get("a"), Some(""));
+        assert_eq!(tags.get("b"), Some(""));
+        assert_eq!(tags.get("c"), Some("d"));
+        assert!(tags.contains("a"));
+        assert!(!tags.contains("d"));
+
+        // Empty tags are skipped
+        let tags = Tags::parse(";a=1;;");
+        assert_eq!(sorted(&tags), vec![("a", "1")]);
+
+        assert!(Tags::parse("").is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let tags = Tags::parse("a=1;b=2;a=3");
+        assert_eq!(sorted(&tags), vec![("a", "3"), ("b", "2")]);
+
+        let tags = Tags::parse("a=1;a");
+        assert_eq!(tags.get("a"), Some(""));
+    }
+}
@@ -32,6 +32,7 @@ extern crate log;
 #[derive(Clone, Copy, Debug, PartialEq, Hash, Eq)]
 pub enum Key {
     AltArrow(Arrow),
+    AltBackspace,
     AltChar(char),
     AltF(FKey),
     Arrow(Arrow),
@@ -119,6 +120,7 @@ byte_seq_parser! {
     [27, 91, 50, 49, 59, 51, 126] => Key::AltF(FKey::F10),
     [27, 91, 50, 51, 59, 51, 126] => Key::AltF(FKey::F11),
     [27, 91, 50, 52, 59, 51, 126] => Key::AltF(FKey::F12),
+    [27, 127] => Key::AltBackspace,
     [27, 91, 66] => Key::Arrow(Arrow::Down),
     [27, 79, 66] => Key::Arrow(Arrow::Down),
     [27, 91, 68] => Key::Arrow(Arrow::Left),
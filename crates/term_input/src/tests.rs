@@ -16,6 +16,14 @@ fn test_alt_key() {
     );
 }
 
+#[test]
+fn test_alt_backspace_key() {
+    assert_eq!(
+        parse_single_event("\x1B\x7F".as_bytes()),
+        Event::Key(Key::AltBackspace)
+    );
+}
+
 #[test]
 fn test_left_key() {
     assert_eq!(
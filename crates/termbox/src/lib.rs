@@ -46,7 +46,9 @@ pub struct Termbox {
     // value.
     terminal_cursor: (u16, u16),
     output_buffer: Vec<u8>,
-    // total_flushed: u64,
+    // Number of bytes written to the terminal so far. Also updated in test instances, to be able
+    // to measure how much output rendering generates.
+    total_flushed: u64,
 }
 
 #[derive(Clone)]
@@ -166,7 +168,7 @@ impl Termbox {
             cursor: Some((0, 0)),
             terminal_cursor: (0, 0),
             output_buffer: Vec::with_capacity(32 * 1024),
-            // total_flushed: 0,
+            total_flushed: 0,
         };
 
         termbox.hide_cursor();
@@ -192,6 +194,7 @@ impl Termbox {
             cursor: Some((0, 0)),
             terminal_cursor: (0, 0),
             output_buffer: Vec::with_capacity(32 * 1024),
+            total_flushed: 0,
         }
     }
 
@@ -240,6 +243,17 @@ impl Termbox {
         self.buffer_size_change_request = true;
     }
 
+    /// Clear the terminal and re-send every cell and attribute in the next `present`, instead of
+    /// only the ones that changed. Also re-reads the terminal size. Useful when the terminal
+    /// contents are messed up, e.g. by another program writing to the terminal.
+    pub fn force_redraw(&mut self) {
+        // Invalidate the last attributes so that the next `send_attr` resets the terminal
+        // attributes
+        self.last_fg = u16::MAX;
+        self.last_bg = u16::MAX;
+        self.buffer_size_change_request = true;
+    }
+
     pub fn width(&self) -> i32 {
         self.term_width as i32
     }
@@ -256,6 +270,23 @@ impl Termbox {
         self.back_buffer.clear(self.clear_fg, self.clear_bg);
     }
 
+    /// Clear rows `y_start` (inclusive) to `y_end` (exclusive) of the back buffer. Useful for
+    /// re-rendering a part of the screen without touching the rest.
+    pub fn clear_lines(&mut self, y_start: i32, y_end: i32) {
+        debug_assert!(y_start >= 0);
+        let w = usize::from(self.term_width);
+        let y_start = min(y_start as usize, usize::from(self.term_height));
+        let y_end = min(y_end.max(0) as usize, usize::from(self.term_height));
+        if y_start >= y_end {
+            return;
+        }
+        for cell in self.back_buffer.cells[y_start * w..y_end * w].iter_mut() {
+            cell.ch = ' ';
+            cell.fg = u16::from(self.clear_fg);
+            cell.bg = u16::from(self.clear_bg);
+        }
+    }
+
     pub fn set_clear_attributes(&mut self, fg: u8, bg: u8) {
         self.clear_fg = fg;
         self.clear_bg = bg;
@@ -367,7 +398,7 @@ impl Termbox {
     }
 
     fn flush_output_buffer(&mut self) {
-        // self.total_flushed += self.output_buffer.len() as u64;
+        self.total_flushed += self.output_buffer.len() as u64;
         if let Some(ref mut tty) = self.tty {
             tty.write_all(&self.output_buffer).unwrap();
         }
@@ -377,7 +408,12 @@ impl Termbox {
     fn update_size(&mut self) {
         let old_w = self.term_width;
         let old_h = self.term_height;
-        let (w, h) = termion::terminal_size().unwrap();
+        // Test instances (and suspended instances) don't have a terminal, keep the current size
+        let (w, h) = if self.tty.is_some() {
+            termion::terminal_size().unwrap()
+        } else {
+            (self.term_width, self.term_height)
+        };
         self.term_width = w;
         self.term_height = h;
         self.back_buffer.resize(old_w, old_h, w, h);
@@ -516,8 +552,6 @@ impl Drop for Termbox {
                 libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSAFLUSH, &self.old_term);
             }
         }
-
-        //  eprintln!("Total bytes flushed: {}", self.total_flushed);
     }
 }
 
@@ -531,6 +565,12 @@ impl Termbox {
         self.front_buffer.clone()
     }
 
+    /// Returns the total number of bytes written to the terminal so far. Useful when testing and
+    /// benchmarking rendering.
+    pub fn total_flushed(&self) -> u64 {
+        self.total_flushed
+    }
+
     /// Sets size of the buffers. Useful when testing.
     pub fn set_buffer_size(&mut self, w: u16, h: u16) {
         let old_w = self.term_width;
@@ -0,0 +1,120 @@
+//! Server certificates accepted with `/pin-cert`. Pins are persisted in a file, one pin per line:
+//!
+//! ```text
+//! <host> <SHA-256 fingerprint of the certificate>
+//! ```
+//!
+//! Pins in the config file (`tls_pin_fingerprint`) take precedence over these.
+
+use libtiny_client::ServerInfo;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Pins loaded from a pin file.
+#[derive(Debug)]
+pub(crate) struct CertPins {
+    /// `None` when the data directory is not available. Pins are not persisted in this case.
+    path: Option<PathBuf>,
+
+    /// Host names (lowercase) and fingerprints.
+    pins: Vec<(String, String)>,
+}
+
+/// Pin file path: `$XDG_DATA_HOME/tiny/cert_pins`.
+fn pins_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|mut path| {
+        path.push("tiny");
+        path.push("cert_pins");
+        path
+    })
+}
+
+impl CertPins {
+    /// Load pins from the default pin file. A missing or invalid file is the same as an empty
+    /// one.
+    pub(crate) fn load() -> CertPins {
+        CertPins::load_from(pins_path())
+    }
+
+    fn load_from(path: Option<PathBuf>) -> CertPins {
+        let pins = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let (host, fingerprint) = line.split_once(' ')?;
+                Some((host.to_owned(), fingerprint.trim().to_owned()))
+            })
+            .collect();
+        CertPins { path, pins }
+    }
+
+    /// Pinned certificate fingerprint of a host.
+    pub(crate) fn get(&self, host: &str) -> Option<&str> {
+        let host = host.to_lowercase();
+        self.pins
+            .iter()
+            .find(|(host_, _)| *host_ == host)
+            .map(|(_, fingerprint)| fingerprint.as_str())
+    }
+
+    /// Add or update the pin of a host.
+    pub(crate) fn set(&mut self, host: &str, fingerprint: &str) {
+        let host = host.to_lowercase();
+        self.pins.retain(|(host_, _)| *host_ != host);
+        self.pins.push((host, fingerprint.to_owned()));
+    }
+
+    /// Write the pins to the pin file.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = String::new();
+        for (host, fingerprint) in &self.pins {
+            contents.push_str(&format!("{host} {fingerprint}\n"));
+        }
+        fs::write(path, contents)
+    }
+
+    /// Path of the pin file, for error messages.
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Use the pinned certificate of the server, unless one is given in the config file.
+    pub(crate) fn apply(&self, server_info: &mut ServerInfo) {
+        if server_info.pinned_cert.is_none() {
+            server_info.pinned_cert = self.get(&server_info.addr).map(str::to_owned);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_file() {
+        let path = std::env::temp_dir().join(format!("tiny_cert_pins_test_{}", std::process::id()));
+
+        let mut pins = CertPins::load_from(Some(path.clone()));
+        assert_eq!(pins.get("irc.example.org"), None);
+        pins.set("IRC.Example.org", "aa");
+        pins.set("bouncer.local", "bb");
+        pins.set("irc.example.org", "cc");
+        pins.save().unwrap();
+
+        let pins = CertPins::load_from(Some(path));
+        assert_eq!(pins.get("irc.example.org"), Some("cc"));
+        assert_eq!(pins.get("Bouncer.local"), Some("bb"));
+
+        let _ = fs::remove_file(pins.path().unwrap());
+    }
+}
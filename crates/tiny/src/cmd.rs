@@ -1,26 +1,37 @@
-use crate::config::Defaults;
+use crate::cert_pins::CertPins;
+use crate::config::{Defaults, PassOrCmd, Server, TOR_SOCKS_PROXY, is_onion};
+use crate::conn;
+use crate::sts::StsPolicies;
 use crate::ui::UI;
 use crate::utils;
-use libtiny_client::{Client, ServerInfo};
+use libtiny_client::{Client, ISupport, SASLAuth, SASLFail, ServerInfo};
 use libtiny_common::{ChanNameRef, MsgSource, MsgTarget};
 use libtiny_tui::config::Chan;
 
 use std::borrow::Borrow;
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
 
 pub(crate) fn run_cmd(
     cmd: &str,
     src: MsgSource,
     defaults: &Defaults,
+    servers: &[Server<PassOrCmd>],
     ui: &UI,
     clients: &mut Vec<Client>,
+    snd_client: &mpsc::Sender<Client>,
 ) {
     match parse_cmd(cmd) {
         Some(ParsedCmd { cmd, args }) => {
             let cmd_args = CmdArgs {
                 args,
                 defaults,
+                servers,
                 ui,
                 clients,
+                snd_client,
                 src,
             };
             (cmd.cmd_fn)(cmd_args);
@@ -61,8 +72,12 @@ fn parse_cmd(cmd: &str) -> Option<ParsedCmd<'_>> {
 struct CmdArgs<'a> {
     args: &'a str,
     defaults: &'a Defaults,
+    /// Servers in the config file.
+    servers: &'a [Server<PassOrCmd>],
     ui: &'a UI,
     clients: &'a mut Vec<Client>,
+    /// Clients of the servers in the config file are sent here after reading their passwords.
+    snd_client: &'a mpsc::Sender<Client>,
     src: MsgSource,
 }
 
@@ -98,20 +113,79 @@ fn find_client<'a>(clients: &'a mut [Client], serv_name: &str) -> Option<&'a mut
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static CMDS: [&Cmd; 9] = [
+static CMDS: [&Cmd; 35] = [
+    &ADMIN_CMD,
     &AWAY_CMD,
+    &BAN_CMD,
+    &CAPS_CMD,
+    &CLEARQUEUE_CMD,
     &CLOSE_CMD,
     &CONNECT_CMD,
+    &HISTORY_CMD,
+    &IDENTIFY_CMD,
+    &ISON_CMD,
     &JOIN_CMD,
+    &LUSERS_CMD,
     &ME_CMD,
+    &MODE_CMD,
     &MSG_CMD,
     &NAMES_CMD,
     &NICK_CMD,
+    &NICKSERV_CMD,
+    &NOTICE_CMD,
+    &NS_CMD,
+    &PARTALL_CMD,
+    &PIN_CERT_CMD,
+    &PING_CMD,
+    &REJOIN_CMD,
+    &SET_CMD,
+    &SETNAME_CMD,
+    &STATS_CMD,
+    &STS_CMD,
+    &TIME_CMD,
+    &TOPIC_CMD,
+    &VERSION_CMD,
+    &WHO_CMD,
+    &WHOIS_CMD,
+    &ZNC_CMD,
     &HELP_CMD,
 ];
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static ADMIN_CMD: Cmd = Cmd {
+    name: "admin",
+    cmd_fn: admin,
+    description: "Shows the administrative contact information of the server",
+    usage: "`/admin` or `/admin <server>`",
+};
+
+fn admin(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() > 1 {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", ADMIN_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.admin(words.first().copied()),
+        None => ui.add_client_err_msg(
+            &format!("Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static AWAY_CMD: Cmd = Cmd {
     name: "away",
     cmd_fn: away,
@@ -132,44 +206,172 @@ fn away(args: CmdArgs) {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static BAN_CMD: Cmd = Cmd {
+    name: "ban",
+    cmd_fn: ban,
+    description: "Bans a user from the channel",
+    usage: "`/ban <nick>`",
+};
+
+fn ban(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() != 1 {
+        return ui.add_client_err_msg(&format!("Usage: {}", BAN_CMD.usage), &MsgTarget::CurrentTab);
+    }
+    let nick = words[0];
+    if let MsgSource::Chan { ref serv, ref chan } = src {
+        match find_client(clients, serv) {
+            Some(client) => {
+                let mask = ban_mask(nick, client.get_user_host(nick).as_deref());
+                client.raw_msg(&format!("MODE {} +b {}", chan.display(), mask));
+            }
+            None => ui.add_client_err_msg(
+                &format!("Can't ban: Not connected to server {serv}"),
+                &MsgTarget::CurrentTab,
+            ),
+        }
+    } else {
+        ui.add_client_err_msg("/ban only supported in chan tabs", &MsgTarget::CurrentTab);
+    }
+}
+
+/// Ban mask for a nick. Bans the host when it's known, so that the ban survives nick changes.
+fn ban_mask(nick: &str, user_host: Option<&str>) -> String {
+    match user_host.and_then(|user_host| user_host.split_once('@')) {
+        Some((_, host)) => format!("*!*@{host}"),
+        None => format!("{nick}!*@*"),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static CAPS_CMD: Cmd = Cmd {
+    name: "caps",
+    cmd_fn: caps,
+    description: "Shows IRCv3 capabilities of the server",
+    usage: "`/caps`",
+};
+
+fn caps(args: CmdArgs) {
+    let CmdArgs {
+        ui, clients, src, ..
+    } = args;
+    let client = match find_client(clients, src.serv_name()) {
+        Some(client) => client,
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+
+    let show_caps = |caps: Vec<String>| {
+        if caps.is_empty() {
+            "(none)".to_owned()
+        } else {
+            caps.join(" ")
+        }
+    };
+    let target = MsgTarget::Server {
+        serv: src.serv_name(),
+    };
+    ui.add_client_msg(
+        &format!(
+            "Server capabilities: {}",
+            show_caps(client.get_server_caps())
+        ),
+        &target,
+    );
+    ui.add_client_msg(
+        &format!(
+            "Enabled capabilities: {}",
+            show_caps(client.get_enabled_caps())
+        ),
+        &target,
+    );
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static CLEARQUEUE_CMD: Cmd = Cmd {
+    name: "clearqueue",
+    cmd_fn: clearqueue,
+    description: "Drops messages waiting to be sent because of flood protection",
+    usage: "`/clearqueue`",
+};
+
+fn clearqueue(args: CmdArgs) {
+    let CmdArgs {
+        ui, clients, src, ..
+    } = args;
+    let serv = src.serv_name();
+    let Some(client) = find_client(clients, serv) else {
+        return ui.add_client_err_msg(
+            &format!("Can't clear queue: Not connected to server {serv}"),
+            &MsgTarget::CurrentTab,
+        );
+    };
+    let mut targets = client.clear_send_queue();
+    let n_dropped = targets.len();
+    targets.sort();
+    targets.dedup();
+    let isupport = client.get_isupport();
+    for target in &targets {
+        ui.set_queued(0, &conn::privmsg_target(serv, target, &isupport));
+    }
+    let msg = match n_dropped {
+        0 => "No queued messages".to_owned(),
+        1 => "Dropped 1 queued message".to_owned(),
+        n => format!("Dropped {n} queued messages"),
+    };
+    ui.add_client_msg(&msg, &MsgTarget::CurrentTab);
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static CLOSE_CMD: Cmd = Cmd {
     name: "close",
     cmd_fn: close,
     description: "Closes current tab",
-    usage: "`/close` or `/close <reason>`",
+    usage: "`/close [-f|--force]` or `/close [-f|--force] <reason>`",
 };
 
 fn close(args: CmdArgs) {
     let CmdArgs {
         args,
+        defaults,
         ui,
         clients,
         src,
         ..
     } = args;
+    let (force, args) = split_flag(args, &["-f", "--force"]);
     match src {
         MsgSource::Serv { ref serv } if serv == "mentions" => {
             // ignore
         }
+        _ if !force && ui.confirm_close(&src, &format!("close -f {args}")) => {
+            // Closed by the confirmation prompt
+        }
         MsgSource::Serv { serv } => {
             ui.close_server_tab(&serv);
             let client_idx = find_client_idx(clients, &serv).unwrap();
             // TODO: this probably won't close the connection?
             let mut client = clients.remove(client_idx);
-            if args.is_empty() {
-                client.quit(None);
-            } else {
-                client.quit(Some(args.to_string()));
-            }
+            client.quit(defaults.quit_reason(args));
         }
         MsgSource::Chan { serv, chan } => {
             ui.close_chan_tab(&serv, chan.borrow());
             let client_idx = find_client_idx(clients, &serv).unwrap();
-            if args.is_empty() {
-                clients[client_idx].part(&chan, None);
-            } else {
-                clients[client_idx].part(&chan, Some(args.to_string()));
-            }
+            clients[client_idx].part(&chan, defaults.part_reason(args));
         }
         MsgSource::User { serv, nick } => {
             ui.close_user_tab(&serv, &nick);
@@ -177,38 +379,143 @@ fn close(args: CmdArgs) {
     }
 }
 
+/// Split one of `flags` (e.g. `-f` or `--force`) from the beginning of command arguments.
+fn split_flag<'a>(args: &'a str, flags: &[&str]) -> (bool, &'a str) {
+    let args = args.trim_start();
+    for flag in flags {
+        if let Some(rest) = args.strip_prefix(flag) {
+            if rest.is_empty() {
+                return (true, rest);
+            }
+            if let Some(rest) = rest.strip_prefix(' ') {
+                return (true, rest.trim_start());
+            }
+        }
+    }
+    (false, args)
+}
+
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
 static CONNECT_CMD: Cmd = Cmd {
     name: "connect",
     cmd_fn: connect,
     description: "Connects to a server",
-    usage: "`/connect <host>:<port>` or `/connect` to reconnect",
+    usage: "`/connect [-tls] <host>[:<port>] [<password>] [-nick <nick>] [-sasl <user>:<password>] \
+            [-join <chan>,<chan>]`, `/connect <network>`, or `/connect` to reconnect",
 };
 
+/// Port to connect to when `/connect` arguments don't have one.
+const DEFAULT_PORT: u16 = 6667;
+
+/// Port to connect to with TLS when `/connect` arguments don't have one.
+const DEFAULT_TLS_PORT: u16 = 6697;
+
+/// Arguments of `/connect <host>[:<port>] [<password>]` with flags.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ConnectArgs<'a> {
+    host: &'a str,
+    /// `None` means the default port, `DEFAULT_TLS_PORT` or `DEFAULT_PORT`
+    port: Option<u16>,
+    pass: Option<&'a str>,
+    /// Connect with TLS (`-tls`). Otherwise `tls` in the defaults is used.
+    tls: bool,
+    /// Nick to use instead of the default nicks (`-nick`)
+    nick: Option<&'a str>,
+    /// SASL PLAIN user name and password (`-sasl`)
+    sasl: Option<(&'a str, &'a str)>,
+    /// Channels to join instead of the default channels (`-join`)
+    join: Vec<&'a str>,
+}
+
+fn parse_connect_args<'a>(words: &[&'a str]) -> Result<ConnectArgs<'a>, String> {
+    let mut args = ConnectArgs::default();
+    let mut positional = vec![];
+    let mut words = words.iter();
+    while let Some(&word) = words.next() {
+        let mut value = || {
+            words
+                .next()
+                .copied()
+                .ok_or_else(|| format!("Missing value for {word}"))
+        };
+        match word {
+            "-tls" => args.tls = true,
+            "-nick" => args.nick = Some(value()?),
+            "-sasl" => {
+                args.sasl = Some(
+                    value()?
+                        .split_once(':')
+                        .ok_or("SASL credentials should be in form <user>:<password>")?,
+                )
+            }
+            "-join" => args.join = value()?.split(',').filter(|c| !c.is_empty()).collect(),
+            flag if flag.starts_with('-') => return Err(format!("Unknown flag: {flag}")),
+            _ => positional.push(word),
+        }
+    }
+
+    let addr = match positional[..] {
+        [addr] => addr,
+        [addr, pass] => {
+            args.pass = Some(pass);
+            addr
+        }
+        [] => return Err("Missing server address".to_owned()),
+        _ => return Err(format!("Unexpected argument: {:?}", positional[2])),
+    };
+    match addr.split_once(':') {
+        None => args.host = addr,
+        // Unix domain socket, no port
+        Some(_) if libtiny_client::unix_socket_path(addr).is_some() => args.host = addr,
+        Some((host, port)) => {
+            args.host = host;
+            args.port = Some(
+                port.parse()
+                    .map_err(|err| format!("Can't parse port {port}: {err}"))?,
+            );
+        }
+    }
+    Ok(args)
+}
+
 fn connect(args: CmdArgs) {
     let CmdArgs {
         args,
         defaults,
+        servers,
         ui,
         clients,
+        snd_client,
         src,
-        ..
     } = args;
     let words: Vec<&str> = args.split_whitespace().collect();
 
     match words.len() {
-        0 => reconnect(ui, clients, src),
-        1 => connect_(words[0], None, defaults, ui, clients),
-        2 => connect_(words[0], Some(words[1]), defaults, ui, clients),
-        _ => ui.add_client_err_msg(
-            &format!("Usage: {}", CONNECT_CMD.usage),
-            &MsgTarget::CurrentTab,
-        ),
+        0 => reconnect(defaults, servers, ui, clients, snd_client, src),
+        1 if !words[0].contains(':') && servers.iter().any(|s| s.in_network(words[0])) => {
+            connect_network(words[0], defaults, servers, ui, clients, snd_client)
+        }
+        _ => match parse_connect_args(&words) {
+            Ok(connect_args) => connect_(connect_args, defaults, ui, clients),
+            Err(err) => ui.add_client_err_msg(
+                &format!("{err}. Usage: {}", CONNECT_CMD.usage),
+                &MsgTarget::CurrentTab,
+            ),
+        },
     }
 }
 
-fn reconnect(ui: &UI, clients: &mut [Client], src: MsgSource) {
+/// Reconnect to the current server. Connects to the server when it's in the config file and not
+/// connected, e.g. because its passwords couldn't be read.
+fn reconnect(
+    defaults: &Defaults,
+    servers: &[Server<PassOrCmd>],
+    ui: &UI,
+    clients: &mut [Client],
+    snd_client: &mpsc::Sender<Client>,
+    src: MsgSource,
+) {
     if let Some(client) = find_client(clients, src.serv_name()) {
         ui.add_client_msg(
             "Reconnecting...",
@@ -217,43 +524,60 @@ fn reconnect(ui: &UI, clients: &mut [Client], src: MsgSource) {
             },
         );
         client.reconnect(None);
+    } else if let Some(server) = servers.iter().find(|server| server.addr == src.serv_name()) {
+        crate::connect_server(server.clone(), defaults, ui, snd_client);
     }
 }
 
-fn connect_(
-    serv_addr: &str,
-    pass: Option<&str>,
+/// Connect to a server of a network in the config file. Reconnects when already connected to a
+/// server of the network.
+fn connect_network(
+    network: &str,
     defaults: &Defaults,
+    servers: &[Server<PassOrCmd>],
     ui: &UI,
-    clients: &mut Vec<Client>,
+    clients: &mut [Client],
+    snd_client: &mpsc::Sender<Client>,
 ) {
-    fn split_port(s: &str) -> Option<(&str, &str)> {
-        s.find(':').map(|split| (&s[0..split], &s[split + 1..]))
+    let mut network_servers = servers.iter().filter(|server| server.in_network(network));
+
+    if let Some(client) = clients.iter_mut().find(|client| {
+        network_servers
+            .clone()
+            .any(|server| server.addr == client.get_serv_name())
+    }) {
+        let serv = client.get_serv_name().to_owned();
+        ui.add_client_msg("Reconnecting...", &MsgTarget::AllServTabs { serv: &serv });
+        client.reconnect(None);
+        return;
     }
 
-    // parse host name and port
-    let (serv_name, serv_port) = {
-        match split_port(serv_addr) {
-            None => {
-                return ui
-                    .add_client_err_msg("connect: Need a <host>:<port>", &MsgTarget::CurrentTab);
-            }
-            Some((serv_name, serv_port)) => match serv_port.parse::<u16>() {
-                Err(err) => {
-                    return ui.add_client_err_msg(
-                        &format!("connect: Can't parse port {serv_port}: {err}"),
-                        &MsgTarget::CurrentTab,
-                    );
-                }
-                Ok(serv_port) => (serv_name, serv_port),
-            },
-        }
-    };
+    // `connect` checks that the network has servers
+    let server = network_servers.next().unwrap().clone();
+    crate::connect_server(server, defaults, ui, snd_client);
+}
+
+fn connect_(args: ConnectArgs, defaults: &Defaults, ui: &UI, clients: &mut Vec<Client>) {
+    let serv_name = args.host;
+    let tls = args.tls || defaults.tls;
+    let serv_port = args
+        .port
+        .unwrap_or(if tls { DEFAULT_TLS_PORT } else { DEFAULT_PORT });
+
+    // Servers with an STS policy are only connected with TLS, on the policy port
+    let sts_policies = StsPolicies::load();
+    let sts_port = sts_policies.get(serv_name).map(|policy| policy.port);
+    if let Some(sts_port) = sts_port {
+        ui.add_client_msg(
+            &format!("Server has an STS policy, using TLS on port {sts_port}"),
+            &MsgTarget::CurrentTab,
+        );
+    }
 
     // if we already connected to this server reconnect using new port
     if let Some(client) = find_client(clients, serv_name) {
         ui.add_client_msg("Connecting...", &MsgTarget::AllServTabs { serv: serv_name });
-        client.reconnect(Some(serv_port));
+        client.reconnect(Some(sts_port.unwrap_or(serv_port)));
         return;
     }
 
@@ -265,38 +589,228 @@ fn connect_(
     let msg_target = MsgTarget::Server { serv: serv_name };
     ui.add_client_msg("Connecting...", &msg_target);
 
-    let (client, rcv_ev) = Client::new(ServerInfo {
+    let mut server_info = ServerInfo {
         addr: serv_name.to_owned(),
         port: serv_port,
-        tls: defaults.tls,
-        user: None,
+        tls,
+        user: defaults.username.clone(),
         realname: defaults.realname.clone(),
-        pass: pass.map(str::to_owned),
-        nicks: defaults.nicks.clone(),
-        auto_join: defaults
-            .join
-            .iter()
-            .map(|c| ChanNameRef::new(c).to_owned())
-            .collect(),
+        pass: args.pass.map(str::to_owned),
+        nicks: match args.nick {
+            Some(nick) => vec![nick.to_owned()],
+            None => defaults.nicks.clone(),
+        },
+        auto_join: if args.join.is_empty() {
+            defaults
+                .join
+                .iter()
+                .map(|c| ChanNameRef::new(c).to_owned())
+                .collect()
+        } else {
+            args.join
+                .iter()
+                .map(|c| ChanNameRef::new(c).to_owned())
+                .collect()
+        },
         nickserv_ident: None,
-        sasl_auth: None,
-    });
+        nickserv: None,
+        fallback_encoding: libtiny_wire::FallbackEncoding::Replace,
+        encoding: libtiny_wire::Encoding::Utf8,
+        sasl_auth: args.sasl.map(|(username, password)| SASLAuth::Plain {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }),
+        sasl_fail: SASLFail::Continue,
+        client_cert: None,
+        ca_certs: None,
+        pinned_cert: None,
+        tls_verify_hostname: true,
+        // Onion services can only be connected through Tor
+        socks5_proxy: is_onion(serv_name).then_some(TOR_SOCKS_PROXY),
+        bind_address: defaults.bind_address,
+        ip_preference: defaults.ip_preference.into(),
+        connect_timeout: None,
+        tls_handshake_timeout: None,
+        registration_timeout: None,
+        ping_interval: None,
+        ping_timeout: None,
+        rejoin_on_reconnect: defaults.rejoin_on_reconnect,
+        join_history_lines: defaults.join_history_lines,
+        reconnect_backoff: defaults.reconnect_backoff.into(),
+        flood_protection: defaults.flood_protection.into(),
+        away_poll: None,
+    };
+    sts_policies.upgrade(&mut server_info);
+    CertPins::load().apply(&mut server_info);
+    let (client, rcv_ev) = Client::new(server_info);
 
     // Spawn UI task
     let ui_clone = ui.clone();
     let client_clone = client.clone();
-    tokio::task::spawn_local(crate::conn::task(rcv_ev, ui_clone, Box::new(client_clone)));
+    tokio::task::spawn_local(crate::conn::task(
+        rcv_ev,
+        ui_clone,
+        Box::new(client_clone),
+        defaults.ctcp_replies.settings(),
+        defaults.dedup_window,
+    ));
 
     clients.push(client);
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static HISTORY_CMD: Cmd = Cmd {
+    name: "history",
+    cmd_fn: history,
+    description: "Fetches older messages of the channel or user from the server",
+    usage: "`/history` or `/history <count>` (default: 50)",
+};
+
+/// Default number of messages to fetch with `/history`.
+const DEFAULT_HISTORY_COUNT: usize = 50;
+
+fn history(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let count = match words.as_slice() {
+        [] => DEFAULT_HISTORY_COUNT,
+        [count] => match count.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                return ui.add_client_err_msg(
+                    &format!("Usage: {}", HISTORY_CMD.usage),
+                    &MsgTarget::CurrentTab,
+                );
+            }
+        },
+        _ => {
+            return ui.add_client_err_msg(
+                &format!("Usage: {}", HISTORY_CMD.usage),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+    let target = match src {
+        MsgSource::Chan { ref chan, .. } => chan.display(),
+        MsgSource::User { ref nick, .. } => nick,
+        MsgSource::Serv { .. } => {
+            return ui.add_client_err_msg(
+                "/history only supported in chan and user tabs",
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+    let client = match find_client(clients, src.serv_name()) {
+        Some(client) => client,
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+    if !client
+        .get_enabled_caps()
+        .iter()
+        .any(|cap| cap == "draft/chathistory")
+    {
+        return ui.add_client_err_msg(
+            "Server doesn't support fetching history (draft/chathistory)",
+            &MsgTarget::CurrentTab,
+        );
+    }
+    client.history(target, count);
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static IDENTIFY_CMD: Cmd = Cmd {
+    name: "identify",
+    cmd_fn: identify,
+    description: "Identifies with NickServ",
+    usage: "`/identify <password>` or `/identify <account> <password>`",
+};
+
+fn identify(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.is_empty() || words.len() > 2 {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", IDENTIFY_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+
+    // The message has the password, so it's not shown in the tab (and not logged)
+    let serv = src.serv_name();
+    if let Some(client) = find_client(clients, serv) {
+        let nickserv = client.get_nickserv();
+        client.privmsg(&nickserv, &format!("IDENTIFY {}", words.join(" ")), false);
+        ui.add_client_msg(
+            &format!("Identifying with {nickserv}..."),
+            &MsgTarget::Server { serv },
+        );
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static ISON_CMD: Cmd = Cmd {
+    name: "ison",
+    cmd_fn: ison,
+    description: "Checks which of the given nicks are online",
+    usage: "`/ison <nick> [<nick> ...]`",
+};
+
+fn ison(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let nicks: Vec<&str> = args.split_whitespace().collect();
+    if nicks.is_empty() {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", ISON_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.ison(&nicks),
+        None => ui.add_client_err_msg(
+            &format!(
+                "Can't check nicks: Not connected to server {}",
+                src.serv_name()
+            ),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static JOIN_CMD: Cmd = Cmd {
     name: "join",
     cmd_fn: join,
     description: "Joins a channel",
-    usage: "`/join <chan1> [-ignore] [-notify [off|mentions|messages]],<chan2>...` or `/join` in a channel tab to rejoin",
+    usage: "`/join <chan1> [-ignore] [-notify [off|mentions|messages]] [-rejoin],<chan2>...`, \
+            `/join` in a channel tab to rejoin or in a server tab to accept the last invite, or \
+            `/join -invites [<n>]`",
 };
 
 fn join(args: CmdArgs) {
@@ -317,17 +831,59 @@ fn join(args: CmdArgs) {
         );
     }
 
-    let chans = args
-        .split(',')
-        .map(str::trim)
-        .filter_map(|c| match Chan::from_cmd_args(c) {
-            Ok(c) => Some(c),
-            Err(err) => {
-                ui.add_client_err_msg(&err, &MsgTarget::CurrentTab);
-                None
+    let (chan_types, invites) = match find_client(clients, src.serv_name()) {
+        Some(client) => (
+            client.get_isupport().chan_types().to_owned(),
+            client.invites(),
+        ),
+        None => ("#".to_owned(), vec![]),
+    };
+
+    // `/join -invites` lists the channels we're invited to, `/join -invites <n>` joins the n-th
+    let chans = if let Some(idx) = args.trim().strip_prefix("-invites") {
+        let idx = idx.trim();
+        if idx.is_empty() {
+            if invites.is_empty() {
+                ui.add_client_msg("No pending invites", &MsgTarget::CurrentTab);
             }
-        })
-        .collect::<Vec<Chan>>();
+            for (i, invite) in invites.iter().enumerate() {
+                ui.add_client_msg(
+                    &format!(
+                        "{}: {} (invited by {})",
+                        i + 1,
+                        invite.chan.display(),
+                        invite.from
+                    ),
+                    &MsgTarget::CurrentTab,
+                );
+            }
+            return;
+        }
+        match idx
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| invites.get(idx.wrapping_sub(1)))
+        {
+            Some(invite) => vec![Chan::Name(invite.chan.clone())],
+            None => {
+                return ui.add_client_err_msg(
+                    &format!("No invite {idx}, see `/join -invites`"),
+                    &MsgTarget::CurrentTab,
+                );
+            }
+        }
+    } else {
+        args.split(',')
+            .map(str::trim)
+            .filter_map(|c| match Chan::from_cmd_args(c, &chan_types) {
+                Ok(c) => Some(c),
+                Err(err) => {
+                    ui.add_client_err_msg(&err, &MsgTarget::CurrentTab);
+                    None
+                }
+            })
+            .collect::<Vec<Chan>>()
+    };
 
     let chans = if chans.is_empty() {
         match ui.current_tab() {
@@ -337,6 +893,10 @@ fn join(args: CmdArgs) {
                 let config = ui.get_tab_config(&serv, Some(chan.as_ref()));
                 vec![Chan::WithConfig { name: chan, config }]
             }
+            Some(MsgSource::Serv { .. }) if !invites.is_empty() => {
+                // Accept the last invite
+                vec![Chan::Name(invites[invites.len() - 1].chan.clone())]
+            }
             Some(MsgSource::Serv { .. } | MsgSource::User { .. }) => {
                 return ui.add_client_err_msg(
                     &format!("Usage: {}", JOIN_CMD.usage),
@@ -375,14 +935,14 @@ fn join(args: CmdArgs) {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static ME_CMD: Cmd = Cmd {
-    name: "me",
-    cmd_fn: me,
-    description: "Sends emote message",
-    usage: "`/me <message>`",
+static LUSERS_CMD: Cmd = Cmd {
+    name: "lusers",
+    cmd_fn: lusers,
+    description: "Shows user, server and channel counts of the network",
+    usage: "`/lusers`",
 };
 
-fn me(args: CmdArgs) {
+fn lusers(args: CmdArgs) {
     let CmdArgs {
         args,
         ui,
@@ -390,10 +950,104 @@ fn me(args: CmdArgs) {
         src,
         ..
     } = args;
-    if args.is_empty() {
-        return ui.add_client_err_msg(&format!("Usage: {}", ME_CMD.usage), &MsgTarget::CurrentTab);
-    }
-    crate::ui::send_msg(ui, clients, &src, args.to_string(), true);
+    if !args.trim().is_empty() {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", LUSERS_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    let serv = src.serv_name();
+    match find_client(clients, serv) {
+        Some(client) => {
+            // With labeled-response the replies are shown together, otherwise they're shown in
+            // the server tab as they arrive
+            if let Some(reply) = client.lusers() {
+                let ui = ui.clone();
+                let serv = serv.to_owned();
+                tokio::task::spawn_local(async move {
+                    if let Ok(msgs) = reply.await {
+                        conn::show_lusers_reply(&ui, &msgs, &MsgTarget::Server { serv: &serv });
+                        ui.draw();
+                    }
+                });
+            }
+        }
+        None => ui.add_client_err_msg(
+            &format!("Not connected to server {serv}"),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static ME_CMD: Cmd = Cmd {
+    name: "me",
+    cmd_fn: me,
+    description: "Sends emote message",
+    usage: "`/me <message>`",
+};
+
+fn me(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    if args.is_empty() {
+        return ui.add_client_err_msg(&format!("Usage: {}", ME_CMD.usage), &MsgTarget::CurrentTab);
+    }
+    crate::ui::send_msg(ui, clients, &src, args.to_string(), true);
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static MODE_CMD: Cmd = Cmd {
+    name: "mode",
+    cmd_fn: mode,
+    description: "Shows or changes the channel modes",
+    usage: "`/mode` or `/mode <modes> [<params>]`",
+};
+
+fn mode(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let MsgSource::Chan { ref serv, ref chan } = src else {
+        return ui.add_client_err_msg("/mode only supported in chan tabs", &MsgTarget::CurrentTab);
+    };
+    let Some(client) = find_client(clients, serv) else {
+        return ui.add_client_err_msg(
+            &format!("Not connected to server {serv}"),
+            &MsgTarget::CurrentTab,
+        );
+    };
+    let args = args.trim();
+    if !args.is_empty() {
+        // Servers ignore mode changes over the limit
+        for modes in client.get_isupport().split_mode_changes(args) {
+            client.raw_msg(&format!("MODE {} {}", chan.display(), modes));
+        }
+        return;
+    }
+    // Show the modes we know, and get them from the server again in case they're out of date. The
+    // tab name is updated when the reply arrives.
+    match client.get_chan_modes(chan) {
+        Some(modes) if modes.is_empty() => {
+            ui.add_client_msg("No channel modes are set", &MsgTarget::CurrentTab)
+        }
+        Some(modes) => {
+            ui.add_client_msg(&format!("Channel modes: {modes}"), &MsgTarget::CurrentTab)
+        }
+        None => ui.add_client_msg("Channel modes are not known yet", &MsgTarget::CurrentTab),
+    }
+    client.refresh_chan_modes(chan);
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -454,6 +1108,13 @@ fn msg(args: CmdArgs) {
         }
     };
 
+    let serv = src.serv_name();
+    if let Some(client) = find_client(clients, serv)
+        && is_statusmsg_target(&client.get_isupport(), target)
+    {
+        return send_to_target(ui, client, target, msg, false);
+    }
+
     let src = if clients
         .iter()
         .any(|client| client.get_serv_name() == target)
@@ -461,8 +1122,13 @@ fn msg(args: CmdArgs) {
         MsgSource::Serv {
             serv: target.to_owned(),
         }
+    } else if find_client(clients, serv).is_some_and(|client| client.get_isupport().is_chan(target))
+    {
+        MsgSource::Chan {
+            serv: serv.to_owned(),
+            chan: ChanNameRef::new(target).to_owned(),
+        }
     } else {
-        let serv = src.serv_name();
         MsgSource::User {
             serv: serv.to_owned(),
             nick: target.to_owned(),
@@ -478,9 +1144,57 @@ static NAMES_CMD: Cmd = Cmd {
     name: "names",
     cmd_fn: names,
     description: "Shows users in channel",
-    usage: "`/names`",
+    usage: "`/names [-ops] [-sort [alpha|rank]] [-accounts] [-count]`, `/names -refresh` or \
+            `/names <nick>`",
 };
 
+#[derive(Debug, Default, PartialEq, Eq)]
+struct NamesFlags {
+    /// Show only channel operators
+    ops: bool,
+    sort: Option<NamesSort>,
+    /// Show services accounts of the users
+    accounts: bool,
+    /// Show only the number of users
+    count: bool,
+    /// Get the users from the server again instead of showing them
+    refresh: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum NamesSort {
+    Alpha,
+    Rank,
+}
+
+fn parse_names_flags(words: &[&str]) -> Result<NamesFlags, String> {
+    let mut flags = NamesFlags::default();
+    let mut words = words.iter().peekable();
+    while let Some(word) = words.next() {
+        match *word {
+            "-ops" => flags.ops = true,
+            "-accounts" => flags.accounts = true,
+            "-count" => flags.count = true,
+            "-refresh" => flags.refresh = true,
+            "-sort" => {
+                flags.sort = Some(match words.peek() {
+                    Some(&&"alpha") => {
+                        words.next();
+                        NamesSort::Alpha
+                    }
+                    Some(&&"rank") => {
+                        words.next();
+                        NamesSort::Rank
+                    }
+                    _ => NamesSort::Rank,
+                })
+            }
+            other => return Err(format!("Unexpected argument: {other:?}")),
+        }
+    }
+    Ok(flags)
+}
+
 fn names(args: CmdArgs) {
     let CmdArgs {
         args,
@@ -499,21 +1213,72 @@ fn names(args: CmdArgs) {
     };
 
     if let MsgSource::Chan { ref serv, ref chan } = src {
-        let nicks_vec = client.get_chan_nicks(chan);
         let target = MsgTarget::Chan { serv, chan };
-        if words.is_empty() {
-            ui.add_client_msg(
-                &format!("{} users: {}", nicks_vec.len(), nicks_vec.join(", ")),
-                &target,
-            );
-        } else {
-            let nick = words[0];
+        if let [nick] = words[..]
+            && !nick.starts_with('-')
+        {
+            let nicks_vec = client.get_chan_nicks(chan);
             if nicks_vec.iter().any(|v| v == nick) {
                 ui.add_client_msg(&format!("{nick} is online"), &target);
             } else {
                 ui.add_client_msg(&format!("{nick} is not in the channel"), &target);
             }
+            return;
+        }
+
+        let flags = match parse_names_flags(&words) {
+            Ok(flags) => flags,
+            Err(err) => {
+                return ui.add_client_err_msg(
+                    &format!("{err}. Usage: {}", NAMES_CMD.usage),
+                    &MsgTarget::CurrentTab,
+                );
+            }
+        };
+
+        if flags.refresh {
+            client.refresh_names(chan);
+            ui.clear_nicks(&target);
+            ui.add_client_msg(&format!("Refreshing users of {}", chan.display()), &target);
+            return;
+        }
+
+        let mut users = client.get_chan_users(chan);
+        if flags.ops {
+            users.retain(|user| user.is_op);
+        }
+        // Users are sorted by nick, stable sort keeps the users with the same rank sorted
+        if flags.sort == Some(NamesSort::Rank) {
+            users.sort_by_key(|user| user.rank.unwrap_or(usize::MAX));
+        }
+
+        let what = if flags.ops { "operators" } else { "users" };
+        if flags.count {
+            ui.add_client_msg(&format!("{} {}", users.len(), what), &target);
+            return;
         }
+
+        // Show the highest prefix of the users when filtering or sorting by rank
+        let show_prefix = flags.ops || flags.sort.is_some();
+        let nicks: Vec<String> = users
+            .iter()
+            .map(|user| {
+                let mut nick = user.nick.clone();
+                if show_prefix && let Some(prefix) = user.prefixes.chars().next() {
+                    nick.insert(0, prefix);
+                }
+                if flags.accounts
+                    && let Some(account) = client.get_account(&user.nick)
+                {
+                    nick.push_str(&format!(" ({account})"));
+                }
+                nick
+            })
+            .collect();
+        ui.add_client_msg(
+            &format!("{} {}: {}", nicks.len(), what, nicks.join(", ")),
+            &target,
+        );
     } else {
         ui.add_client_err_msg("/names only supported in chan tabs", &MsgTarget::CurrentTab);
     }
@@ -540,6 +1305,17 @@ fn nick(args: CmdArgs) {
     if words.len() == 1 {
         if let Some(client) = find_client(clients, src.serv_name()) {
             let new_nick = words[0];
+            if let Some(nick_len) = client.get_isupport().nick_len()
+                && new_nick.chars().count() > nick_len
+            {
+                ui.add_client_err_msg(
+                    &format!(
+                        "Nick {new_nick} is longer than the server's limit of {nick_len} \
+                         characters, the server may truncate or reject it"
+                    ),
+                    &MsgTarget::CurrentTab,
+                );
+            }
             client.nick(new_nick);
         }
     } else {
@@ -550,45 +1326,970 @@ fn nick(args: CmdArgs) {
     }
 }
 
-static HELP_CMD: Cmd = Cmd {
-    name: "help",
-    cmd_fn: help,
-    description: "Displays this message",
-    usage: "`/help`",
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static NICKSERV_CMD: Cmd = Cmd {
+    name: "nickserv",
+    cmd_fn: nickserv,
+    description: "Sends a command to NickServ",
+    usage: "`/nickserv <command>`, e.g. `/nickserv info`",
 };
 
-fn help(args: CmdArgs) {
-    let CmdArgs { ui, .. } = args;
-    ui.add_client_msg("Client Commands:", &MsgTarget::CurrentTab);
-    for cmd in CMDS.iter() {
-        ui.add_client_msg(
+static NS_CMD: Cmd = Cmd {
+    name: "ns",
+    cmd_fn: nickserv,
+    description: "Same as `/nickserv`",
+    usage: "`/ns <command>`, e.g. `/ns info`",
+};
+
+fn nickserv(args: CmdArgs) {
+    let msg = args.args.trim();
+    let Some(subcmd) = msg.split_whitespace().next() else {
+        return args.ui.add_client_err_msg(
+            &format!("Usage: {}", NICKSERV_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    };
+
+    // Don't show the password in `/ns identify <password>`
+    if subcmd.eq_ignore_ascii_case("identify") {
+        let password = msg[subcmd.len()..].trim_start();
+        return identify(CmdArgs {
+            args: password,
+            ..args
+        });
+    }
+
+    let CmdArgs {
+        ui, clients, src, ..
+    } = args;
+    let serv = src.serv_name();
+    if let Some(client) = find_client(clients, serv) {
+        let src = MsgSource::User {
+            serv: serv.to_owned(),
+            nick: client.get_nickserv(),
+        };
+        crate::ui::send_msg(ui, clients, &src, msg.to_owned(), false);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static NOTICE_CMD: Cmd = Cmd {
+    name: "notice",
+    cmd_fn: notice,
+    description: "Sends a notice to a user or channel",
+    usage: "`/notice <target> <message>`",
+};
+
+fn notice(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let (target, msg) = match args.trim().split_once(char::is_whitespace) {
+        Some((target, msg)) if !msg.trim().is_empty() => (target, msg.trim_start()),
+        _ => {
+            return ui.add_client_err_msg(
+                &format!("Usage: {}", NOTICE_CMD.usage),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+    match find_client(clients, src.serv_name()) {
+        Some(client) => send_to_target(ui, client, target, msg, true),
+        None => ui.add_client_err_msg(
+            &format!("Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+/// Whether `target` is a channel name with a membership prefix, e.g. `@#chan`.
+fn is_statusmsg_target(isupport: &ISupport, target: &str) -> bool {
+    !isupport.is_chan(target)
+        && target
+            .chars()
+            .next()
+            .is_some_and(|prefix| isupport.is_chan(&target[prefix.len_utf8()..]))
+}
+
+/// Send a message or notice to `target` and show it in the target's tab. A channel name with a
+/// membership prefix (e.g. `@#chan`, for the ops of `#chan`) is sent to the users with the prefix
+/// in the channel and shown in the channel tab, if the server supports it (`STATUSMSG`).
+fn send_to_target(ui: &UI, client: &mut Client, target: &str, msg: &str, is_notice: bool) {
+    let serv = client.get_serv_name().to_owned();
+    let isupport = client.get_isupport();
+    let (ui_target, sender) = match isupport.split_statusmsg(target) {
+        Some((prefix, chan)) => (
+            MsgTarget::Chan {
+                serv: &serv,
+                chan: ChanNameRef::new(chan),
+            },
+            conn::statusmsg_sender(prefix, &client.get_nick()),
+        ),
+        None if is_statusmsg_target(&isupport, target) => {
+            return ui.add_client_err_msg(
+                &format!(
+                    "Can't send to {target}: server doesn't support STATUSMSG with this prefix"
+                ),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        None => (
+            conn::privmsg_target(&serv, target, &isupport),
+            client.get_nick(),
+        ),
+    };
+
+    let ts = time::now();
+    // With `echo-message` messages are shown when the server echoes them back
+    let echo_message = client.echo_message();
+    for msg in client.split_privmsg(target.len(), msg) {
+        if is_notice {
+            client.notice(target, msg);
+        } else {
+            client.privmsg(target, msg, false);
+        }
+        if !echo_message {
+            ui.add_privmsg(&sender, msg, ts, &ui_target, false, false);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static PARTALL_CMD: Cmd = Cmd {
+    name: "part-all",
+    cmd_fn: part_all,
+    description: "Leaves all channels of the current server, optionally closing the tabs",
+    usage: "`/part-all [-c|--close]` or `/part-all [-c|--close] <reason>`",
+};
+
+fn part_all(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        defaults,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let (close, reason) = split_flag(args, &["-c", "--close"]);
+    let serv = src.serv_name();
+    let client = match find_client(clients, serv) {
+        Some(client) if client.is_nick_accepted() => client,
+        _ => {
+            return ui.add_client_err_msg(
+                &format!("Not connected to server {serv}"),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+
+    let chans = client.get_joined_chans();
+    if chans.is_empty() {
+        return ui.add_client_msg("Not in any channels", &MsgTarget::CurrentTab);
+    }
+
+    let reason = defaults.part_reason(reason);
+    for chan in &chans {
+        client.part(chan, reason.clone());
+        if close {
+            ui.close_chan_tab(serv, chan);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static PIN_CERT_CMD: Cmd = Cmd {
+    name: "pin-cert",
+    cmd_fn: pin_cert,
+    description: "Accepts the server certificate with the given fingerprint and reconnects",
+    usage: "`/pin-cert <SHA-256 fingerprint>`",
+};
+
+fn pin_cert(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let [fingerprint] = words.as_slice() else {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", PIN_CERT_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    };
+    let serv = src.serv_name();
+    let client = match find_client(clients, serv) {
+        Some(client) => client,
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Not connected to server {serv}"),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+
+    let mut cert_pins = CertPins::load();
+    cert_pins.set(serv, fingerprint);
+    if let Err(err) = cert_pins.save() {
+        ui.add_client_err_msg(
             &format!(
-                "/{:<10} - {:<25} - Usage: {}",
-                cmd.name, cmd.description, cmd.usage
+                "Can't save the pinned certificate to {:?}: {err}",
+                cert_pins.path().unwrap_or_else(|| Path::new(""))
             ),
             &MsgTarget::CurrentTab,
-        )
+        );
     }
+
+    client.pin_cert(fingerprint);
+    ui.add_client_msg(
+        &format!("Pinned certificate {fingerprint}, reconnecting..."),
+        &MsgTarget::AllServTabs { serv },
+    );
+    client.reconnect(None);
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-#[test]
-fn test_parse_cmd() {
-    let ParsedCmd { cmd, args } = parse_cmd("msg NickServ identify notMyPassword").unwrap();
-    assert_eq!(cmd.name, "msg");
-    assert_eq!(args, "NickServ identify notMyPassword");
+static PING_CMD: Cmd = Cmd {
+    name: "ping",
+    cmd_fn: ping,
+    description: "Measures lag to the server or a user",
+    usage: "`/ping` or `/ping <nick>`",
+};
 
-    let ParsedCmd { cmd, args } = parse_cmd("join #foo").unwrap();
-    assert_eq!(cmd.name, "join");
-    assert_eq!(args, "#foo");
+fn ping(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() > 1 {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", PING_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.ping(words.first().copied()),
+        None => ui.add_client_err_msg(
+            &format!("Can't ping: Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
 }
 
-#[test]
-fn test_msg_args() {
-    assert_eq!(split_msg_args("foo,bar"), None);
-    assert_eq!(split_msg_args("foo bar"), Some(("foo", "bar")));
-    assert_eq!(split_msg_args("foo, bar"), Some(("foo,", "bar"))); // nick not valid according to RFC but whatever
-    assert_eq!(split_msg_args("foo ,bar"), Some(("foo", ",bar")));
-    assert_eq!(split_msg_args("#blah blah"), None);
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static REJOIN_CMD: Cmd = Cmd {
+    name: "rejoin",
+    cmd_fn: rejoin,
+    description: "Joins the channel of the current tab, or the channel left last, again",
+    usage: "`/rejoin`",
+};
+
+fn rejoin(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    if !args.trim().is_empty() {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", REJOIN_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    let serv = src.serv_name();
+    let Some(client) = find_client(clients, serv) else {
+        return ui.add_client_err_msg(
+            &format!("Can't rejoin: Not connected to server {serv}"),
+            &MsgTarget::CurrentTab,
+        );
+    };
+    let joined = client.get_joined_chans();
+    let last_parted = client
+        .last_parted()
+        .filter(|(chan, _)| !joined.contains(chan));
+    // Rejoin the channel of the current tab if we're not in it, otherwise the channel we left last
+    let (chan, reason) = match src {
+        MsgSource::Chan { ref chan, .. } if !joined.contains(chan) => {
+            let reason = last_parted
+                .filter(|(parted, _)| parted == chan)
+                .and_then(|(_, reason)| reason);
+            (chan.clone(), reason)
+        }
+        _ => match last_parted {
+            Some(last_parted) => last_parted,
+            None => {
+                return ui.add_client_err_msg(
+                    "Nothing to rejoin: no channel was left recently",
+                    &MsgTarget::CurrentTab,
+                );
+            }
+        },
+    };
+
+    // Creates the tab if it was closed
+    let config = ui.get_tab_config(serv, Some(&chan));
+    ui.set_tab_config(serv, Some(&chan), config);
+    let msg = match reason {
+        Some(reason) => format!("Rejoining {} (left with: {reason})", chan.display()),
+        None => format!("Rejoining {}", chan.display()),
+    };
+    ui.add_client_msg(&msg, &MsgTarget::Chan { serv, chan: &chan });
+    client.rejoin(&chan);
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static SET_CMD: Cmd = Cmd {
+    name: "set",
+    cmd_fn: set,
+    description: "Shows or changes settings of the server",
+    usage: "`/set`, `/set <setting>`, or `/set <setting> <value>`",
+};
+
+/// Settings of the current server that can be changed with `/set`. TUI settings are handled by
+/// the TUI, other settings are passed to us.
+static SERVER_SETTINGS: [&str; 5] = [
+    "flood_protection.burst",
+    "flood_protection.interval_ms",
+    "hide_motd",
+    "hide_lusers",
+    "wallops_mentions",
+];
+
+fn set(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let (key, value) = match args.trim().split_once(' ') {
+        Some((key, value)) => (key, Some(value.trim())),
+        None => (args.trim(), None),
+    };
+    let serv = src.serv_name();
+    let client = find_client(clients, serv);
+
+    if key.is_empty() {
+        for (key, value) in server_settings(ui, client.as_deref(), serv) {
+            ui.add_client_msg(&format!("{key}: {value}"), &MsgTarget::CurrentTab);
+        }
+        return;
+    }
+
+    if !SERVER_SETTINGS.contains(&key) {
+        return ui.add_client_err_msg(
+            &format!("Unknown setting: {key}. Usage: {}", SET_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+
+    if let Some(value) = value
+        && let Err(err) = set_server_setting(ui, client.as_deref(), serv, key, value)
+    {
+        return ui.add_client_err_msg(&err, &MsgTarget::CurrentTab);
+    }
+
+    if let Some((key, value)) = server_settings(ui, client.as_deref(), serv)
+        .into_iter()
+        .find(|(key_, _)| *key_ == key)
+    {
+        ui.add_client_msg(&format!("{key}: {value}"), &MsgTarget::CurrentTab);
+    }
+}
+
+/// Settings of a server with their current values in the config file syntax. Flood protection
+/// settings are only available when connected.
+fn server_settings(ui: &UI, client: Option<&Client>, serv: &str) -> Vec<(&'static str, String)> {
+    let mut settings = vec![];
+    if let Some(client) = client {
+        let flood_protection = client.flood_protection();
+        settings.push(("flood_protection.burst", flood_protection.burst.to_string()));
+        settings.push((
+            "flood_protection.interval_ms",
+            flood_protection.interval.as_millis().to_string(),
+        ));
+    }
+    let tab_config = ui.get_tab_config(serv, None);
+    settings.push((
+        "hide_motd",
+        tab_config.hide_motd.unwrap_or(false).to_string(),
+    ));
+    settings.push((
+        "hide_lusers",
+        tab_config.hide_lusers.unwrap_or(false).to_string(),
+    ));
+    settings.push((
+        "wallops_mentions",
+        tab_config.wallops_mentions.unwrap_or(false).to_string(),
+    ));
+    settings
+}
+
+fn set_server_setting(
+    ui: &UI,
+    client: Option<&Client>,
+    serv: &str,
+    key: &str,
+    value: &str,
+) -> Result<(), String> {
+    fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String>
+    where
+        T::Err: std::fmt::Display,
+    {
+        value
+            .parse()
+            .map_err(|err| format!("Invalid value for {key}: {err}"))
+    }
+
+    match key {
+        "hide_motd" | "hide_lusers" | "wallops_mentions" => {
+            let value = Some(parse(key, value)?);
+            let mut tab_config = ui.get_tab_config(serv, None);
+            match key {
+                "hide_motd" => tab_config.hide_motd = value,
+                "hide_lusers" => tab_config.hide_lusers = value,
+                _ => tab_config.wallops_mentions = value,
+            }
+            ui.set_tab_config(serv, None, tab_config);
+        }
+        _ => {
+            let client = client.ok_or_else(|| format!("Not connected to server {serv}"))?;
+            let mut flood_protection = client.flood_protection();
+            if key == "flood_protection.burst" {
+                flood_protection.burst = parse(key, value)?;
+                if flood_protection.burst == 0 {
+                    return Err(format!("Invalid value for {key}: burst can't be 0"));
+                }
+            } else {
+                flood_protection.interval = Duration::from_millis(parse(key, value)?);
+            }
+            client.set_flood_protection(flood_protection);
+        }
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static SETNAME_CMD: Cmd = Cmd {
+    name: "setname",
+    cmd_fn: setname,
+    description: "Changes your real name without reconnecting",
+    usage: "`/setname <real name>`",
+};
+
+fn setname(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let realname = args.trim();
+    if realname.is_empty() {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", SETNAME_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    let client = match find_client(clients, src.serv_name()) {
+        Some(client) => client,
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+    if !client.get_enabled_caps().iter().any(|cap| cap == "setname") {
+        return ui.add_client_err_msg(
+            "Server doesn't support changing real name (setname)",
+            &MsgTarget::CurrentTab,
+        );
+    }
+    client.setname(realname);
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static STATS_CMD: Cmd = Cmd {
+    name: "stats",
+    cmd_fn: stats,
+    description: "Shows statistics of the server, e.g. `/stats u` for uptime",
+    usage: "`/stats <query>` or `/stats <query> <server>`",
+};
+
+fn stats(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.is_empty() || words.len() > 2 {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", STATS_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.stats(words[0], words.get(1).copied()),
+        None => ui.add_client_err_msg(
+            &format!("Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static STS_CMD: Cmd = Cmd {
+    name: "sts",
+    cmd_fn: sts,
+    description: "Shows the STS policies learned from servers",
+    usage: "`/sts list`",
+};
+
+fn sts(args: CmdArgs) {
+    let CmdArgs { args, ui, .. } = args;
+    if args.trim() != "list" {
+        return ui.add_client_err_msg(&format!("Usage: {}", STS_CMD.usage), &MsgTarget::CurrentTab);
+    }
+    let sts_policies = StsPolicies::load();
+    if sts_policies.policies().is_empty() {
+        return ui.add_client_msg("No STS policies", &MsgTarget::CurrentTab);
+    }
+    for policy in sts_policies.policies() {
+        let expires = time::at_utc(time::Timespec::new(policy.expires as i64, 0));
+        ui.add_client_msg(
+            &format!(
+                "{}: TLS on port {} until {}",
+                policy.host,
+                policy.port,
+                time::strftime("%Y-%m-%d %H:%M UTC", &expires).unwrap()
+            ),
+            &MsgTarget::CurrentTab,
+        );
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static TIME_CMD: Cmd = Cmd {
+    name: "time",
+    cmd_fn: time,
+    description: "Shows the local time of the server",
+    usage: "`/time` or `/time <server>`",
+};
+
+fn time(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() > 1 {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", TIME_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.time(words.first().copied()),
+        None => ui.add_client_err_msg(
+            &format!("Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static TOPIC_CMD: Cmd = Cmd {
+    name: "topic",
+    cmd_fn: topic,
+    description: "Shows or sets the channel topic",
+    usage: "`/topic` or `/topic <topic>`",
+};
+
+fn topic(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let MsgSource::Chan { ref serv, ref chan } = src else {
+        return ui.add_client_err_msg("/topic only supported in chan tabs", &MsgTarget::CurrentTab);
+    };
+    let Some(client) = find_client(clients, serv) else {
+        return ui.add_client_err_msg(
+            &format!("Not connected to server {serv}"),
+            &MsgTarget::CurrentTab,
+        );
+    };
+    let args = args.trim();
+    if !args.is_empty() {
+        return client.set_topic(chan, args);
+    }
+    match client.get_topic(chan) {
+        Some(topic) => {
+            ui.add_client_msg(&format!("Topic: {}", topic.topic), &MsgTarget::CurrentTab);
+            if let Some(set_by) = &topic.set_by {
+                ui.add_client_msg(
+                    &conn::topic_set_line(set_by, topic.set_at),
+                    &MsgTarget::CurrentTab,
+                );
+            }
+        }
+        None => ui.add_client_msg("No topic is set", &MsgTarget::CurrentTab),
+    }
+    if let Some(created) = client.get_chan_creation_time(chan) {
+        ui.add_client_msg(
+            &format!("Channel created on {}", conn::format_unix_time(created)),
+            &MsgTarget::CurrentTab,
+        );
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static VERSION_CMD: Cmd = Cmd {
+    name: "version",
+    cmd_fn: version,
+    description: "Shows the software version of the server",
+    usage: "`/version` or `/version <server>`",
+};
+
+fn version(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() > 1 {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", VERSION_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.version(words.first().copied()),
+        None => ui.add_client_err_msg(
+            &format!("Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static WHO_CMD: Cmd = Cmd {
+    name: "who",
+    cmd_fn: who,
+    description: "Lists users matching a mask, e.g. users in a channel",
+    usage: "`/who <mask>`, e.g. `/who #chan`",
+};
+
+fn who(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() != 1 {
+        return ui.add_client_err_msg(&format!("Usage: {}", WHO_CMD.usage), &MsgTarget::CurrentTab);
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => {
+            // With labeled-response the reply is shown in the tab the command was run in,
+            // otherwise the replies are shown in the current tab as they arrive
+            if let Some(reply) = client.who(words[0]) {
+                let ui = ui.clone();
+                tokio::task::spawn_local(async move {
+                    if let Ok(msgs) = reply.await {
+                        crate::conn::show_whois_reply(&ui, &msgs, &src.to_target());
+                        ui.draw();
+                    }
+                });
+            }
+        }
+        None => ui.add_client_err_msg(
+            &format!("Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static WHOIS_CMD: Cmd = Cmd {
+    name: "whois",
+    cmd_fn: whois,
+    description: "Shows information about a user",
+    usage: "`/whois <nick>`",
+};
+
+fn whois(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() != 1 {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", WHOIS_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => {
+            // With labeled-response the reply is shown in the current tab, otherwise the replies
+            // are shown in the server tab as they arrive
+            if let Some(reply) = client.whois(words[0]) {
+                let ui = ui.clone();
+                tokio::task::spawn_local(async move {
+                    if let Ok(msgs) = reply.await {
+                        crate::conn::show_whois_reply(&ui, &msgs, &src.to_target());
+                        ui.draw();
+                    }
+                });
+            }
+        }
+        None => ui.add_client_err_msg(
+            &format!("Can't whois: Not connected to server {}", src.serv_name()),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static ZNC_CMD: Cmd = Cmd {
+    name: "znc",
+    cmd_fn: znc,
+    description: "Sends a command to a bouncer module",
+    usage: "`/znc <module> <command>`, e.g. `/znc status help`",
+};
+
+fn znc(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let Some((module, msg)) = args
+        .trim()
+        .split_once(' ')
+        .filter(|(_, msg)| !msg.trim().is_empty())
+    else {
+        return ui.add_client_err_msg(&format!("Usage: {}", ZNC_CMD.usage), &MsgTarget::CurrentTab);
+    };
+
+    // Control users of modules are named with a prefix, e.g. `*status`
+    let serv = src.serv_name();
+    let prefix = conn::bouncer_control_prefix(ui, serv);
+    let nick = match module.strip_prefix(prefix) {
+        Some(_) => module.to_owned(),
+        None => format!("{prefix}{module}"),
+    };
+    let src = MsgSource::User {
+        serv: serv.to_owned(),
+        nick,
+    };
+    crate::ui::send_msg(ui, clients, &src, msg.trim().to_owned(), false);
+}
+
+static HELP_CMD: Cmd = Cmd {
+    name: "help",
+    cmd_fn: help,
+    description: "Displays this message",
+    usage: "`/help`",
+};
+
+fn help(args: CmdArgs) {
+    let CmdArgs { ui, .. } = args;
+    ui.add_client_msg("Client Commands:", &MsgTarget::CurrentTab);
+    for cmd in CMDS.iter() {
+        ui.add_client_msg(
+            &format!(
+                "/{:<10} - {:<25} - Usage: {}",
+                cmd.name, cmd.description, cmd.usage
+            ),
+            &MsgTarget::CurrentTab,
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_parse_cmd() {
+    let ParsedCmd { cmd, args } = parse_cmd("msg NickServ identify notMyPassword").unwrap();
+    assert_eq!(cmd.name, "msg");
+    assert_eq!(args, "NickServ identify notMyPassword");
+
+    let ParsedCmd { cmd, args } = parse_cmd("join #foo").unwrap();
+    assert_eq!(cmd.name, "join");
+    assert_eq!(args, "#foo");
+}
+
+#[test]
+fn test_ban_mask() {
+    assert_eq!(ban_mask("alice", None), "alice!*@*");
+    assert_eq!(
+        ban_mask("alice", Some("~alice@cloak/alice")),
+        "*!*@cloak/alice"
+    );
+}
+
+#[test]
+fn test_parse_names_flags() {
+    assert_eq!(parse_names_flags(&[]), Ok(NamesFlags::default()));
+    assert_eq!(
+        parse_names_flags(&["-ops", "-sort", "-count"]),
+        Ok(NamesFlags {
+            ops: true,
+            sort: Some(NamesSort::Rank),
+            accounts: false,
+            count: true,
+            refresh: false,
+        })
+    );
+    assert_eq!(
+        parse_names_flags(&["-sort", "alpha", "-accounts"]),
+        Ok(NamesFlags {
+            ops: false,
+            sort: Some(NamesSort::Alpha),
+            accounts: true,
+            count: false,
+            refresh: false,
+        })
+    );
+    assert_eq!(
+        parse_names_flags(&["-refresh"]),
+        Ok(NamesFlags {
+            refresh: true,
+            ..NamesFlags::default()
+        })
+    );
+    assert!(parse_names_flags(&["-sort", "foo"]).is_err());
+    assert!(parse_names_flags(&["-ops", "nick"]).is_err());
+}
+
+#[test]
+fn test_parse_connect_args() {
+    assert_eq!(
+        parse_connect_args(&["irc.example.net"]),
+        Ok(ConnectArgs {
+            host: "irc.example.net",
+            ..ConnectArgs::default()
+        })
+    );
+    assert_eq!(
+        parse_connect_args(&[
+            "-tls",
+            "irc.example.net:6697",
+            "hunter2",
+            "-nick",
+            "altnick",
+            "-sasl",
+            "user:pass:word",
+            "-join",
+            "#a,#b",
+        ]),
+        Ok(ConnectArgs {
+            host: "irc.example.net",
+            port: Some(6697),
+            pass: Some("hunter2"),
+            tls: true,
+            nick: Some("altnick"),
+            sasl: Some(("user", "pass:word")),
+            join: vec!["#a", "#b"],
+        })
+    );
+    assert!(parse_connect_args(&["irc.example.net", "-foo"]).is_err());
+    assert!(parse_connect_args(&["irc.example.net", "-nick"]).is_err());
+    assert!(parse_connect_args(&["irc.example.net", "-sasl", "user"]).is_err());
+    assert!(parse_connect_args(&["irc.example.net:port"]).is_err());
+    assert!(parse_connect_args(&["irc.example.net", "pass", "extra"]).is_err());
+    assert!(parse_connect_args(&["-tls"]).is_err());
+    assert_eq!(
+        parse_connect_args(&["unix:/run/irc.sock", "-nick", "altnick"]),
+        Ok(ConnectArgs {
+            host: "unix:/run/irc.sock",
+            nick: Some("altnick"),
+            ..ConnectArgs::default()
+        })
+    );
+}
+
+#[test]
+fn test_split_flag() {
+    let force = &["-f", "--force"];
+    assert_eq!(split_flag("", force), (false, ""));
+    assert_eq!(split_flag("bye", force), (false, "bye"));
+    assert_eq!(split_flag("-f", force), (true, ""));
+    assert_eq!(split_flag("-f bye now", force), (true, "bye now"));
+    assert_eq!(split_flag("--force bye", force), (true, "bye"));
+    assert_eq!(split_flag("-fbye", force), (false, "-fbye"));
+    assert_eq!(split_flag("-c bye", force), (false, "-c bye"));
+}
+
+#[test]
+fn test_msg_args() {
+    assert_eq!(split_msg_args("foo,bar"), None);
+    assert_eq!(split_msg_args("foo bar"), Some(("foo", "bar")));
+    assert_eq!(split_msg_args("foo, bar"), Some(("foo,", "bar"))); // nick not valid according to RFC but whatever
+    assert_eq!(split_msg_args("foo ,bar"), Some(("foo", ",bar")));
+    assert_eq!(split_msg_args("#blah blah"), None);
+}
+
+#[test]
+fn test_statusmsg_target() {
+    let isupport = ISupport::default();
+    assert!(is_statusmsg_target(&isupport, "@#chan"));
+    assert!(is_statusmsg_target(&isupport, "+&chan"));
+    assert!(!is_statusmsg_target(&isupport, "#chan"));
+    assert!(!is_statusmsg_target(&isupport, "nick"));
+    assert!(!is_statusmsg_target(&isupport, ""));
 }
@@ -1,4 +1,8 @@
-use libtiny_client::SASLAuth as ClientSASLAuth;
+use libtiny_client::{
+    AwayPoll, ClientCert, FloodProtection as ClientFloodProtection,
+    IpPreference as ClientIpPreference, ReconnectBackoff as ClientReconnectBackoff,
+    SASLAuth as ClientSASLAuth, SASLFail as ClientSASLFail,
+};
 use serde::{Deserialize, Deserializer};
 
 use shellexpand::LookupError;
@@ -6,8 +10,10 @@ use std::env::VarError;
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
 use libtiny_tui::config::Chan;
 
@@ -19,11 +25,22 @@ pub(crate) enum SASLAuth<P> {
         username: String,
         /// Password
         password: P,
+        /// Mechanism to authenticate with the password
+        #[serde(default)]
+        mechanism: PasswordMechanism,
+        /// Authenticate with PLAIN if the server does not support `mechanism`
+        #[serde(default)]
+        plain_fallback: bool,
     },
     External {
-        /// Path to PEM file with private key and certificate (PKCS8 format).
+        /// Path to the certificate: a PEM file with the certificate (and the private key, if `key`
+        /// is not given), or a PKCS#12 file with the certificate and the private key.
         /// A fingerprint of the certificate should be registered with NickServ
-        pem: PathBuf,
+        #[serde(alias = "pem")]
+        cert: PathBuf,
+        /// Path to PEM file with the private key (PKCS8 format)
+        #[serde(default)]
+        key: Option<PathBuf>,
     },
 }
 
@@ -32,14 +49,309 @@ impl TryFrom<SASLAuth<String>> for ClientSASLAuth {
 
     fn try_from(sasl: SASLAuth<String>) -> Result<Self, Self::Error> {
         Ok(match sasl {
-            SASLAuth::Plain { username, password } => ClientSASLAuth::Plain { username, password },
-            SASLAuth::External { pem } => ClientSASLAuth::External {
-                pem: std::fs::read(pem).map_err(|e| format!("Could not read PEM file: {e}"))?,
+            SASLAuth::Plain {
+                username,
+                password,
+                mechanism: PasswordMechanism::Plain,
+                ..
+            } => ClientSASLAuth::Plain { username, password },
+            SASLAuth::Plain {
+                username,
+                password,
+                mechanism: PasswordMechanism::ScramSha256,
+                plain_fallback,
+            } => ClientSASLAuth::ScramSha256 {
+                username,
+                password,
+                plain_fallback,
+            },
+            SASLAuth::External { cert, key } => ClientSASLAuth::External {
+                cert: load_client_cert(&cert, key.as_deref(), "")?,
             },
         })
     }
 }
 
+/// Read a client certificate: a PEM certificate with a separate PEM `key`, a PEM file with both
+/// the certificate and the key, or a PKCS#12 archive encrypted with `passphrase`.
+fn load_client_cert(
+    cert: &Path,
+    key: Option<&Path>,
+    passphrase: &str,
+) -> Result<ClientCert, String> {
+    let read = |path: &Path| {
+        fs::read(path)
+            .map_err(|e| format!("Could not read certificate file {}: {}", path.display(), e))
+    };
+    let cert_bytes = read(cert)?;
+    let cert = match key {
+        Some(key) => ClientCert::Pem {
+            cert: cert_bytes,
+            key: read(key)?,
+        },
+        None if is_pem(&cert_bytes) => ClientCert::Pem {
+            cert: cert_bytes.clone(),
+            key: cert_bytes,
+        },
+        None => ClientCert::Pkcs12 {
+            der: cert_bytes,
+            passphrase: passphrase.to_owned(),
+        },
+    };
+    cert.validate()?;
+    Ok(cert)
+}
+
+/// SASL mechanisms for password authentication
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq, Eq)]
+pub(crate) enum PasswordMechanism {
+    #[default]
+    #[serde(rename = "plain")]
+    Plain,
+    #[serde(rename = "scram-sha-256")]
+    ScramSha256,
+}
+
+fn is_pem(bytes: &[u8]) -> bool {
+    bytes
+        .windows(b"-----BEGIN ".len())
+        .any(|window| window == b"-----BEGIN ")
+}
+
+/// What to do when SASL authentication fails
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SASLFail {
+    /// Continue connecting without authentication
+    #[default]
+    Continue,
+    /// Disconnect from the server. `/connect` reconnects.
+    Disconnect,
+}
+
+impl From<SASLFail> for ClientSASLFail {
+    fn from(sasl_fail: SASLFail) -> Self {
+        match sasl_fail {
+            SASLFail::Continue => ClientSASLFail::Continue,
+            SASLFail::Disconnect => ClientSASLFail::Disconnect,
+        }
+    }
+}
+
+/// Order to try the IPv4 and IPv6 addresses of a server in
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IpPreference {
+    /// Alternate between the families, starting with the family of the first resolved address
+    #[default]
+    Auto,
+    PreferIpv4,
+    PreferIpv6,
+}
+
+impl From<IpPreference> for ClientIpPreference {
+    fn from(ip_preference: IpPreference) -> Self {
+        match ip_preference {
+            IpPreference::Auto => ClientIpPreference::Auto,
+            IpPreference::PreferIpv4 => ClientIpPreference::PreferIpv4,
+            IpPreference::PreferIpv6 => ClientIpPreference::PreferIpv6,
+        }
+    }
+}
+
+/// How to decode messages that are not valid UTF-8. See `libtiny_wire::FallbackEncoding`.
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FallbackEncoding {
+    /// Replace invalid sequences with the replacement character
+    #[default]
+    None,
+    /// Decode as ISO-8859-1
+    #[serde(alias = "iso-8859-1")]
+    Latin1,
+}
+
+impl From<FallbackEncoding> for libtiny_wire::FallbackEncoding {
+    fn from(encoding: FallbackEncoding) -> Self {
+        match encoding {
+            FallbackEncoding::None => libtiny_wire::FallbackEncoding::Replace,
+            FallbackEncoding::Latin1 => libtiny_wire::FallbackEncoding::Latin1,
+        }
+    }
+}
+
+/// Text encoding of a server. See `libtiny_wire::Encoding`.
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Encoding {
+    #[default]
+    #[serde(alias = "utf-8")]
+    Utf8,
+    #[serde(alias = "iso-8859-1")]
+    Latin1,
+    #[serde(alias = "windows-1252")]
+    Cp1252,
+}
+
+impl From<Encoding> for libtiny_wire::Encoding {
+    fn from(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Utf8 => libtiny_wire::Encoding::Utf8,
+            Encoding::Latin1 => libtiny_wire::Encoding::Latin1,
+            Encoding::Cp1252 => libtiny_wire::Encoding::Cp1252,
+        }
+    }
+}
+
+/// Delays between reconnect attempts. See `libtiny_client::ReconnectBackoff`.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub(crate) struct ReconnectBackoff {
+    /// Delay after the first failed connection
+    pub(crate) base_secs: u64,
+    /// Delay is multiplied with this after each failure
+    pub(crate) multiplier: f64,
+    pub(crate) max_secs: u64,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        ClientReconnectBackoff::default().into()
+    }
+}
+
+impl From<ClientReconnectBackoff> for ReconnectBackoff {
+    fn from(backoff: ClientReconnectBackoff) -> Self {
+        ReconnectBackoff {
+            base_secs: backoff.base.as_secs(),
+            multiplier: backoff.multiplier,
+            max_secs: backoff.max.as_secs(),
+        }
+    }
+}
+
+impl From<ReconnectBackoff> for ClientReconnectBackoff {
+    fn from(backoff: ReconnectBackoff) -> Self {
+        ClientReconnectBackoff {
+            base: Duration::from_secs(backoff.base_secs),
+            multiplier: backoff.multiplier,
+            max: Duration::from_secs(backoff.max_secs),
+        }
+    }
+}
+
+/// Rate limit of outgoing messages. See `libtiny_client::FloodProtection`.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct FloodProtection {
+    /// Number of messages that can be sent at once
+    pub(crate) burst: u32,
+    /// One message is sent in this interval after the burst. 0 disables flood protection.
+    pub(crate) interval_ms: u64,
+}
+
+impl Default for FloodProtection {
+    fn default() -> Self {
+        ClientFloodProtection::default().into()
+    }
+}
+
+impl From<ClientFloodProtection> for FloodProtection {
+    fn from(flood_protection: ClientFloodProtection) -> Self {
+        FloodProtection {
+            burst: flood_protection.burst,
+            interval_ms: flood_protection.interval.as_millis() as u64,
+        }
+    }
+}
+
+impl From<FloodProtection> for ClientFloodProtection {
+    fn from(flood_protection: FloodProtection) -> Self {
+        ClientFloodProtection {
+            burst: flood_protection.burst,
+            interval: Duration::from_millis(flood_protection.interval_ms),
+        }
+    }
+}
+
+/// Automatic replies to CTCP requests: `false` to not reply to any requests, or settings of
+/// each request type.
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum CtcpReplies {
+    Enabled(bool),
+    Settings(CtcpReplySettings),
+}
+
+impl Default for CtcpReplies {
+    fn default() -> Self {
+        CtcpReplies::Enabled(true)
+    }
+}
+
+impl CtcpReplies {
+    pub(crate) fn settings(&self) -> CtcpReplySettings {
+        match self {
+            CtcpReplies::Enabled(true) => CtcpReplySettings::default(),
+            CtcpReplies::Enabled(false) => CtcpReplySettings {
+                version: false,
+                time: false,
+                ping: false,
+                clientinfo: false,
+                ..CtcpReplySettings::default()
+            },
+            CtcpReplies::Settings(settings) => settings.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct CtcpReplySettings {
+    /// Reply to VERSION requests
+    pub(crate) version: bool,
+    /// VERSION reply
+    pub(crate) version_string: String,
+    /// Add the tiny version to the VERSION reply, e.g. `tiny 0.13.0`
+    pub(crate) include_version: bool,
+    /// Reply to TIME requests with the local time
+    pub(crate) time: bool,
+    /// Reply to PING requests with the request payload
+    pub(crate) ping: bool,
+    /// Reply to CLIENTINFO requests with the supported CTCP types
+    pub(crate) clientinfo: bool,
+}
+
+impl Default for CtcpReplySettings {
+    fn default() -> Self {
+        CtcpReplySettings {
+            version: true,
+            version_string: "tiny".to_owned(),
+            include_version: true,
+            time: true,
+            ping: true,
+            clientinfo: true,
+        }
+    }
+}
+
+impl CtcpReplySettings {
+    /// Reply to VERSION requests, `None` when disabled.
+    pub(crate) fn version_reply(&self) -> Option<String> {
+        if !self.version {
+            None
+        } else if self.include_version {
+            Some(format!(
+                "{} {}",
+                self.version_string,
+                env!("CARGO_PKG_VERSION")
+            ))
+        } else {
+            Some(self.version_string.clone())
+        }
+    }
+}
+
 #[derive(Clone, Deserialize)]
 #[serde(bound(deserialize = "P: Deserialize<'de>"))]
 pub(crate) struct Server<P> {
@@ -50,6 +362,12 @@ pub(crate) struct Server<P> {
     #[serde(default)]
     pub(crate) alias: Option<String>,
 
+    /// Network of the server, for networks with multiple servers. Tabs of the servers of a
+    /// network are shown next to each other, with the network name unless `alias` is set, and
+    /// `/connect <network>` connects to a server of the network.
+    #[serde(default)]
+    pub(crate) network: Option<String>,
+
     /// Port of the server
     pub(crate) port: u16,
 
@@ -65,14 +383,16 @@ pub(crate) struct Server<P> {
     #[serde(default = "default_true")]
     pub(crate) autoconnect: bool,
 
-    /// User name to be used in connection registration
-    /// If it is not specified, the first nick will be used instead
-    #[serde(default)]
-    pub(crate) user: Option<String>,
+    /// User name to be used in connection registration. If it is not specified, `username` of
+    /// `defaults` is used, and if that is not specified either, the first nick is used. `user` is
+    /// also accepted for backwards compatibility.
+    #[serde(default, alias = "user", deserialize_with = "deser_trimmed_opt_str")]
+    pub(crate) username: Option<String>,
 
-    /// Real name to be used in connection registration
-    #[serde(deserialize_with = "deser_trimmed_str")]
-    pub(crate) realname: String,
+    /// Real name to be used in connection registration. If it is not specified, `realname` of
+    /// `defaults` is used.
+    #[serde(default, deserialize_with = "deser_trimmed_opt_str")]
+    pub(crate) realname: Option<String>,
 
     /// Nicks to try when connecting to this server. tiny tries these sequentially, and starts
     /// adding trailing underscores to the last one if none of the nicks are available.
@@ -85,15 +405,133 @@ pub(crate) struct Server<P> {
     /// NickServ identification password. Used on connecting to the server and nick change.
     pub(crate) nickserv_ident: Option<P>,
 
+    /// Nick of the NickServ service, for networks that use a different name. Default is
+    /// `NickServ`.
+    pub(crate) nickserv: Option<String>,
+
+    /// How to decode messages that are not valid UTF-8.
+    #[serde(default)]
+    pub(crate) fallback_encoding: FallbackEncoding,
+
+    /// Text encoding of the server. Text of sent messages is encoded with it, and the trailing
+    /// parameter of received messages (message text, topics, reasons) is decoded with it.
+    #[serde(default)]
+    pub(crate) encoding: Encoding,
+
     /// Authenication method
     #[serde(rename = "sasl")]
     pub(crate) sasl_auth: Option<SASLAuth<P>>,
+
+    /// What to do when SASL authentication fails
+    #[serde(default)]
+    pub(crate) sasl_fail: SASLFail,
+
+    /// TLS client certificate, for CertFP: a PEM file with the certificate (and the private key,
+    /// if `tls_key` is not given), or a PKCS#12 file with the certificate and the private key.
+    #[serde(default)]
+    pub(crate) tls_cert: Option<PathBuf>,
+
+    /// Path to PEM file with the private key of `tls_cert` (PKCS8 format)
+    #[serde(default)]
+    pub(crate) tls_key: Option<PathBuf>,
+
+    /// Passphrase of a PKCS#12 `tls_cert`
+    pub(crate) tls_cert_passphrase: Option<P>,
+
+    /// PEM file with CA certificates to trust in addition to the system roots, e.g. of a private
+    /// CA.
+    #[serde(default)]
+    pub(crate) tls_ca_file: Option<PathBuf>,
+
+    /// SHA-256 fingerprint of the server certificate. When set only the certificate with this
+    /// fingerprint is accepted, the certificate chain and the host name are not validated.
+    #[serde(default)]
+    pub(crate) tls_pin_fingerprint: Option<String>,
+
+    /// Check that the server certificate is issued for `addr`. Onion services often use
+    /// certificates of the clearnet address of the server.
+    #[serde(default = "default_true")]
+    pub(crate) tls_verify_hostname: bool,
+
+    /// SOCKS5 proxy to connect through, e.g. `127.0.0.1:9050` for Tor.
+    #[serde(default)]
+    pub(crate) socks5_proxy: Option<SocketAddr>,
+
+    /// Local address to connect from. Overrides the default bind address.
+    #[serde(default)]
+    pub(crate) bind_address: Option<IpAddr>,
+
+    /// Order to try the server addresses in. Overrides the default preference.
+    #[serde(default)]
+    pub(crate) ip_preference: Option<IpPreference>,
+
+    /// Number of messages to fetch from the server when joining a channel. Overrides the default.
+    #[serde(default)]
+    pub(crate) join_history_lines: Option<usize>,
+
+    /// Connect through Tor: use the SOCKS port of a local Tor daemon (unless `socks5_proxy` is
+    /// set) and a longer ping timeout.
+    #[serde(default)]
+    pub(crate) via_tor: bool,
+
+    /// Timeout of opening the TCP connection to an address of the server (including the proxy
+    /// handshake), in seconds. No timeout by default.
+    #[serde(default)]
+    pub(crate) connect_timeout_secs: Option<u64>,
+
+    /// Timeout of the TLS handshake, in seconds. No timeout by default.
+    #[serde(default)]
+    pub(crate) tls_handshake_timeout_secs: Option<u64>,
+
+    /// Reconnect when the server doesn't complete the registration (e.g. SASL authentication) in
+    /// this many seconds after connecting. Default is 60 seconds.
+    #[serde(default)]
+    pub(crate) registration_timeout_secs: Option<u64>,
+
+    /// Send a PING after not hearing from the server for this many seconds. Default is 60
+    /// seconds.
+    #[serde(default)]
+    pub(crate) ping_interval_secs: Option<u64>,
+
+    /// Reconnect when there's no reply to a PING in this many seconds. Default is 60 seconds, 180
+    /// with `via_tor`.
+    #[serde(default)]
+    pub(crate) ping_timeout_secs: Option<u64>,
+
+    /// Whether to rejoin the channels we were in after reconnecting. When disabled only the
+    /// channels in `join` are joined.
+    #[serde(default = "default_true")]
+    pub(crate) rejoin_on_reconnect: bool,
+
+    /// Poll the away status of the users in our channels with WHO every this many seconds when
+    /// the server doesn't support IRCv3 `away-notify`. Disabled by default.
+    #[serde(default)]
+    pub(crate) away_poll_secs: Option<u64>,
+
+    /// Channels with more users than this are not polled with `away_poll_secs`. Default is 100.
+    #[serde(default)]
+    pub(crate) away_poll_max_users: Option<usize>,
 }
 
+/// Default for `Server::away_poll_max_users`.
+const DEFAULT_AWAY_POLL_MAX_USERS: usize = 100;
+
 fn default_true() -> bool {
     true
 }
 
+/// SOCKS port of a local Tor daemon, used with `via_tor`.
+pub(crate) const TOR_SOCKS_PROXY: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9050));
+
+/// Ping timeout with `via_tor`. Tor circuits are slower, and are sometimes rebuilt.
+const TOR_PING_TIMEOUT_SECS: u64 = 180;
+
+/// Whether `addr` is a Tor onion service address. These can only be connected through Tor.
+pub(crate) fn is_onion(addr: &str) -> bool {
+    addr.to_ascii_lowercase().ends_with(".onion")
+}
+
 /// Similar to `Server`, but used when connecting via the `/connect` command.
 #[derive(Clone, Deserialize)]
 pub(crate) struct Defaults {
@@ -101,10 +539,67 @@ pub(crate) struct Defaults {
     pub(crate) nicks: Vec<String>,
     #[serde(deserialize_with = "deser_trimmed_str")]
     pub(crate) realname: String,
+    #[serde(default, deserialize_with = "deser_trimmed_opt_str")]
+    pub(crate) username: Option<String>,
     #[serde(default)]
     pub(crate) join: Vec<String>,
     #[serde(default)]
     pub(crate) tls: bool,
+    #[serde(default = "default_true")]
+    pub(crate) rejoin_on_reconnect: bool,
+    #[serde(default)]
+    pub(crate) bind_address: Option<IpAddr>,
+    #[serde(default)]
+    pub(crate) ip_preference: IpPreference,
+    #[serde(default = "default_join_history_lines")]
+    pub(crate) join_history_lines: usize,
+    #[serde(default)]
+    pub(crate) reconnect_backoff: ReconnectBackoff,
+    #[serde(default)]
+    pub(crate) flood_protection: FloodProtection,
+    #[serde(default)]
+    pub(crate) ctcp_replies: CtcpReplies,
+    /// Number of recent messages per channel or user remembered to drop duplicates, e.g. messages
+    /// replayed by a bouncer after reconnecting. 0 disables dropping duplicates.
+    #[serde(default = "default_dedup_window")]
+    pub(crate) dedup_window: usize,
+    /// Reason sent when leaving a channel without one, e.g. with `/close`. `{version}` is replaced
+    /// with the tiny version.
+    #[serde(default)]
+    pub(crate) part_message: Option<String>,
+    /// Reason sent when quitting without one. `{version}` is replaced with the tiny version.
+    #[serde(default)]
+    pub(crate) quit_message: Option<String>,
+}
+
+impl Defaults {
+    /// Reason to send when leaving a channel. `reason` is the command argument, used when not
+    /// empty.
+    pub(crate) fn part_reason(&self, reason: &str) -> Option<String> {
+        reason_or_default(reason, self.part_message.as_deref())
+    }
+
+    /// Reason to send when quitting. `reason` is the command argument, used when not empty.
+    pub(crate) fn quit_reason(&self, reason: &str) -> Option<String> {
+        reason_or_default(reason, self.quit_message.as_deref())
+    }
+}
+
+fn reason_or_default(reason: &str, default: Option<&str>) -> Option<String> {
+    if !reason.is_empty() {
+        return Some(reason.to_owned());
+    }
+    default
+        .filter(|msg| !msg.is_empty())
+        .map(|msg| msg.replace("{version}", env!("CARGO_PKG_VERSION")))
+}
+
+fn default_join_history_lines() -> usize {
+    50
+}
+
+fn default_dedup_window() -> usize {
+    crate::conn::DEFAULT_DEDUP_WINDOW
 }
 
 #[derive(Deserialize)]
@@ -112,6 +607,14 @@ pub(crate) struct Config<P> {
     pub(crate) servers: Vec<Server<P>>,
     pub(crate) defaults: Defaults,
     pub(crate) log_dir: Option<PathBuf>,
+    /// Number of messages to load from the log file when a private conversation tab is opened.
+    /// 0 disables loading.
+    #[serde(default = "default_pm_history_lines")]
+    pub(crate) pm_history_lines: usize,
+}
+
+fn default_pm_history_lines() -> usize {
+    20
 }
 
 fn deser_trimmed_str<'de, D>(d: D) -> Result<String, D::Error>
@@ -122,6 +625,14 @@ where
     Ok(str.trim().to_owned())
 }
 
+fn deser_trimmed_opt_str<'de, D>(d: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let str: Option<String> = Option::deserialize(d)?;
+    Ok(str.map(|s| s.trim().to_owned()))
+}
+
 fn deser_trimmed_str_vec<'de, D>(d: D) -> Result<Vec<String>, D::Error>
 where
     D: Deserializer<'de>,
@@ -130,22 +641,33 @@ where
     Ok(strs.into_iter().map(|s| s.trim().to_owned()).collect())
 }
 
-/// A password, or a shell command to run the obtain a password. Used for password (server
-/// password, SASL, NickServ) fields of `Config`.
+/// A password, a shell command to run the obtain a password, or an OS keyring entry with the
+/// password. Used for password (server password, SASL, NickServ) fields of `Config`.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum PassOrCmd {
     /// Password is given directly as plain text
     Pass(String),
 
-    /// A shell command to run to get the password
-    Cmd(Vec<String>),
+    /// A shell command to run to get the password. Given as a map with a `command` key the command
+    /// is run on startup and the last line of the output is used. Given as `cmd:command` the
+    /// command is run when connecting, with a timeout, and the first line is used.
+    Cmd { args: Vec<String>, first_line: bool },
+
+    /// An OS keyring entry with the password, given as `keyring:service/account`
+    Keyring { service: String, account: String },
 }
 
+/// Prefix of password fields that refer to an OS keyring entry.
+const KEYRING_PREFIX: &str = "keyring:";
+
+/// Prefix of password fields that give a command to run to get the password.
+const CMD_PREFIX: &str = "cmd:";
+
 impl PassOrCmd {
     fn is_empty_cmd(&self) -> bool {
         match self {
-            PassOrCmd::Cmd(cmd) => cmd.is_empty(),
-            PassOrCmd::Pass(_) => false,
+            PassOrCmd::Cmd { args, .. } => args.is_empty(),
+            PassOrCmd::Pass(_) | PassOrCmd::Keyring { .. } => false,
         }
     }
 }
@@ -159,10 +681,38 @@ impl<'de> Deserialize<'de> for PassOrCmd {
         use serde_yaml::Value;
 
         match Value::deserialize(deserializer)? {
-            Value::String(str) => Ok(PassOrCmd::Pass(str)),
+            Value::String(str) if str.starts_with(CMD_PREFIX) => {
+                match shell_words::split(&str[CMD_PREFIX.len()..]) {
+                    Ok(args) => Ok(PassOrCmd::Cmd {
+                        args,
+                        first_line: true,
+                    }),
+                    Err(err) => Err(D::Error::custom(format!(
+                        "Unable to parse password field: {err}"
+                    ))),
+                }
+            }
+            Value::String(str) => match str.strip_prefix(KEYRING_PREFIX) {
+                None => Ok(PassOrCmd::Pass(str)),
+                Some(entry) => match entry.split_once('/') {
+                    Some((service, account)) if !service.is_empty() && !account.is_empty() => {
+                        Ok(PassOrCmd::Keyring {
+                            service: service.to_owned(),
+                            account: account.to_owned(),
+                        })
+                    }
+                    _ => Err(D::Error::custom(format!(
+                        "Keyring password field should be in form \
+                         `{KEYRING_PREFIX}service/account`: {str}"
+                    ))),
+                },
+            },
             Value::Mapping(map) => match map.get(&Value::String("command".to_owned())) {
                 Some(Value::String(cmd)) => match shell_words::split(cmd) {
-                    Ok(cmd_parts) => Ok(PassOrCmd::Cmd(cmd_parts)),
+                    Ok(args) => Ok(PassOrCmd::Cmd {
+                        args,
+                        first_line: false,
+                    }),
                     Err(err) => Err(D::Error::custom(format!(
                         "Unable to parse password field: {err}"
                     ))),
@@ -234,6 +784,269 @@ fn run_command(command_name: &str, server_addr: &str, args: &[String]) -> Option
     Some(stdout.lines().last().unwrap().to_owned())
 }
 
+/// Run the password command of `pass` if it's given in the map form, and replace it with the
+/// password. Errors are reported to stdout.
+fn run_password_command(
+    pass: &mut PassOrCmd,
+    password_name: &str,
+    server_addr: &str,
+) -> Option<()> {
+    if let PassOrCmd::Cmd {
+        args,
+        first_line: false,
+    } = pass
+    {
+        *pass = PassOrCmd::Pass(run_command(password_name, server_addr, args)?);
+    }
+    Some(())
+}
+
+impl<P> Server<P> {
+    /// User name to use in connection registration, falling back to the default user name.
+    /// `None` means the first nick will be used.
+    pub(crate) fn username(&self, defaults: &Defaults) -> Option<String> {
+        self.username.clone().or_else(|| defaults.username.clone())
+    }
+
+    /// Real name to use in connection registration, falling back to the default real name.
+    pub(crate) fn realname(&self, defaults: &Defaults) -> String {
+        self.realname
+            .clone()
+            .unwrap_or_else(|| defaults.realname.clone())
+    }
+
+    /// Local address to connect from, falling back to the default bind address.
+    pub(crate) fn bind_address(&self, defaults: &Defaults) -> Option<IpAddr> {
+        self.bind_address.or(defaults.bind_address)
+    }
+
+    /// Order to try the server addresses in, falling back to the default preference.
+    pub(crate) fn ip_preference(&self, defaults: &Defaults) -> IpPreference {
+        self.ip_preference.unwrap_or(defaults.ip_preference)
+    }
+
+    /// Number of messages to fetch when joining a channel, falling back to the default.
+    pub(crate) fn join_history_lines(&self, defaults: &Defaults) -> usize {
+        self.join_history_lines
+            .unwrap_or(defaults.join_history_lines)
+    }
+
+    /// SOCKS5 proxy to connect through.
+    pub(crate) fn socks5_proxy(&self) -> Option<SocketAddr> {
+        self.socks5_proxy
+            .or_else(|| self.via_tor.then_some(TOR_SOCKS_PROXY))
+    }
+
+    pub(crate) fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub(crate) fn tls_handshake_timeout(&self) -> Option<Duration> {
+        self.tls_handshake_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub(crate) fn registration_timeout(&self) -> Option<Duration> {
+        self.registration_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub(crate) fn ping_interval(&self) -> Option<Duration> {
+        self.ping_interval_secs.map(Duration::from_secs)
+    }
+
+    pub(crate) fn away_poll(&self) -> Option<AwayPoll> {
+        self.away_poll_secs.map(|secs| AwayPoll {
+            interval: Duration::from_secs(secs),
+            max_users: self
+                .away_poll_max_users
+                .unwrap_or(DEFAULT_AWAY_POLL_MAX_USERS),
+        })
+    }
+
+    pub(crate) fn ping_timeout(&self) -> Option<Duration> {
+        self.ping_timeout_secs
+            .or_else(|| self.via_tor.then_some(TOR_PING_TIMEOUT_SECS))
+            .map(Duration::from_secs)
+    }
+
+    /// Name to show in the tab line instead of the address: the alias, or the network name.
+    pub(crate) fn tab_name(&self) -> Option<String> {
+        self.alias.clone().or_else(|| self.network.clone())
+    }
+
+    /// Whether the server is in the given network. Network names are case insensitive.
+    pub(crate) fn in_network(&self, network: &str) -> bool {
+        self.network
+            .as_deref()
+            .is_some_and(|network_| network_.eq_ignore_ascii_case(network))
+    }
+}
+
+/// Order servers so that the servers of a network are next to each other, in the position of the
+/// first server of the network. Order of the servers is kept otherwise.
+pub(crate) fn group_by_network<P>(servers: Vec<Server<P>>) -> Vec<Server<P>> {
+    let mut groups: Vec<Vec<Server<P>>> = vec![];
+    for server in servers {
+        let group = server
+            .network
+            .as_deref()
+            .and_then(|network| groups.iter_mut().find(|group| group[0].in_network(network)));
+        match group {
+            Some(group) => group.push(server),
+            None => groups.push(vec![server]),
+        }
+    }
+    groups.into_iter().flatten().collect()
+}
+
+impl Server<String> {
+    /// Load the TLS client certificate, if one is configured.
+    pub(crate) fn client_cert(&self) -> Option<Result<ClientCert, String>> {
+        let cert = self.tls_cert.as_deref()?;
+        Some(load_client_cert(
+            cert,
+            self.tls_key.as_deref(),
+            self.tls_cert_passphrase.as_deref().unwrap_or(""),
+        ))
+    }
+
+    /// Read the CA certificates, if a CA file is configured.
+    pub(crate) fn ca_certs(&self) -> Option<Result<Vec<u8>, String>> {
+        let path = self.tls_ca_file.as_deref()?;
+        Some(match fs::read(path) {
+            Err(e) => Err(format!("Could not read CA file {}: {}", path.display(), e)),
+            Ok(bytes) if !is_pem(&bytes) => {
+                Err(format!("CA file {} is not a PEM file", path.display()))
+            }
+            Ok(bytes) => Ok(bytes),
+        })
+    }
+}
+
+impl<P> Server<P> {
+    /// Convert the password fields with `f`, which is called with the passwords and their names
+    /// (e.g. "SASL password"). Returns `None` when `f` fails.
+    pub(crate) async fn map_passwords<Q, F, Fut>(self, mut f: F) -> Option<Server<Q>>
+    where
+        F: FnMut(P, &'static str) -> Fut,
+        Fut: Future<Output = Option<Q>>,
+    {
+        let Server {
+            addr,
+            alias,
+            network,
+            port,
+            tls,
+            pass,
+            autoconnect,
+            username,
+            realname,
+            nicks,
+            join,
+            nickserv_ident,
+            nickserv,
+            fallback_encoding,
+            encoding,
+            sasl_auth,
+            sasl_fail,
+            tls_cert,
+            tls_key,
+            tls_cert_passphrase,
+            tls_ca_file,
+            tls_pin_fingerprint,
+            tls_verify_hostname,
+            socks5_proxy,
+            bind_address,
+            ip_preference,
+            join_history_lines,
+            via_tor,
+            connect_timeout_secs,
+            tls_handshake_timeout_secs,
+            registration_timeout_secs,
+            ping_interval_secs,
+            ping_timeout_secs,
+            rejoin_on_reconnect,
+            away_poll_secs,
+            away_poll_max_users,
+        } = self;
+
+        let pass = match pass {
+            None => None,
+            Some(pass) => Some(f(pass, "server password").await?),
+        };
+
+        let nickserv_ident = match nickserv_ident {
+            None => None,
+            Some(pass) => Some(f(pass, "NickServ password").await?),
+        };
+
+        let sasl_auth = match sasl_auth {
+            None => None,
+            Some(SASLAuth::Plain {
+                username,
+                password,
+                mechanism,
+                plain_fallback,
+            }) => Some(SASLAuth::Plain {
+                username,
+                password: f(password, "SASL password").await?,
+                mechanism,
+                plain_fallback,
+            }),
+            Some(SASLAuth::External { cert, key }) => Some(SASLAuth::External { cert, key }),
+        };
+
+        let tls_cert_passphrase = match tls_cert_passphrase {
+            None => None,
+            Some(pass) => Some(f(pass, "certificate passphrase").await?),
+        };
+
+        Some(Server {
+            addr,
+            alias,
+            network,
+            port,
+            tls,
+            pass,
+            autoconnect,
+            username,
+            realname,
+            nicks,
+            join,
+            nickserv_ident,
+            nickserv,
+            fallback_encoding,
+            encoding,
+            sasl_auth,
+            sasl_fail,
+            tls_cert,
+            tls_key,
+            tls_cert_passphrase,
+            tls_ca_file,
+            tls_pin_fingerprint,
+            tls_verify_hostname,
+            socks5_proxy,
+            bind_address,
+            ip_preference,
+            join_history_lines,
+            via_tor,
+            connect_timeout_secs,
+            tls_handshake_timeout_secs,
+            registration_timeout_secs,
+            ping_interval_secs,
+            ping_timeout_secs,
+            rejoin_on_reconnect,
+            away_poll_secs,
+            away_poll_max_users,
+        })
+    }
+}
+
+/// Whether `fingerprint` is a SHA-256 fingerprint: 64 hex digits, optionally separated with `:`.
+fn is_sha256_fingerprint(fingerprint: &str) -> bool {
+    let digits: Vec<char> = fingerprint.chars().filter(|c| *c != ':').collect();
+    digits.len() == 64 && digits.iter().all(char::is_ascii_hexdigit)
+}
+
 impl Config<PassOrCmd> {
     /// Returns error descriptions.
     pub(crate) fn validate(&self) -> Vec<String> {
@@ -257,6 +1070,22 @@ impl Config<PassOrCmd> {
             }
         }
 
+        let backoff = &self.defaults.reconnect_backoff;
+        if backoff.base_secs == 0 {
+            errors.push("'base_secs' of 'reconnect_backoff' can't be 0".to_owned());
+        }
+        if backoff.multiplier.is_nan() || backoff.multiplier < 1.0 {
+            errors.push("'multiplier' of 'reconnect_backoff' can't be less than 1".to_owned());
+        }
+        if backoff.max_secs < backoff.base_secs {
+            errors.push(
+                "'max_secs' of 'reconnect_backoff' can't be less than 'base_secs'".to_owned(),
+            );
+        }
+        if self.defaults.flood_protection.burst == 0 {
+            errors.push("'burst' of 'flood_protection' can't be 0".to_owned());
+        }
+
         for server in &self.servers {
             if server.nicks.is_empty() {
                 errors.push(format!(
@@ -274,7 +1103,9 @@ impl Config<PassOrCmd> {
                 }
             }
 
-            if server.realname.is_empty() {
+            if let Some(ref realname) = server.realname
+                && realname.is_empty()
+            {
                 errors.push(format!(
                     "'realname' can't be empty, please update 'realname' field of '{}'",
                     server.addr
@@ -311,6 +1142,75 @@ impl Config<PassOrCmd> {
                     server.addr
                 ));
             }
+
+            for (field, is_set) in [
+                ("tls_cert", server.tls_cert.is_some()),
+                ("tls_ca_file", server.tls_ca_file.is_some()),
+                ("tls_pin_fingerprint", server.tls_pin_fingerprint.is_some()),
+                ("tls_verify_hostname", !server.tls_verify_hostname),
+            ] {
+                if is_set && !server.tls {
+                    errors.push(format!(
+                        "TLS is not enabled for '{}', but '{}' is set. \
+                         Please enable TLS for this server in the config file.",
+                        server.addr, field
+                    ));
+                }
+            }
+
+            if let Some(fingerprint) = &server.tls_pin_fingerprint
+                && !is_sha256_fingerprint(fingerprint)
+            {
+                errors.push(format!(
+                    "'tls_pin_fingerprint' of '{}' is not a SHA-256 fingerprint (64 hex digits)",
+                    server.addr
+                ));
+            }
+
+            if server.tls_cert.is_none()
+                && (server.tls_key.is_some() || server.tls_cert_passphrase.is_some())
+            {
+                errors.push(format!(
+                    "'tls_key' and 'tls_cert_passphrase' of '{}' require 'tls_cert'",
+                    server.addr
+                ));
+            }
+
+            if let Some(ref passphrase) = server.tls_cert_passphrase
+                && passphrase.is_empty_cmd()
+            {
+                errors.push(format!(
+                    "Empty certificate passphrase command for '{}'",
+                    server.addr
+                ));
+            }
+
+            if is_onion(&server.addr) && server.socks5_proxy().is_none() {
+                errors.push(format!(
+                    "'{}' is an onion service, please set 'via_tor: true' (or 'socks5_proxy') \
+                     to connect through Tor",
+                    server.addr
+                ));
+            }
+
+            for (field, secs) in [
+                ("connect_timeout_secs", server.connect_timeout_secs),
+                (
+                    "tls_handshake_timeout_secs",
+                    server.tls_handshake_timeout_secs,
+                ),
+                (
+                    "registration_timeout_secs",
+                    server.registration_timeout_secs,
+                ),
+                ("ping_interval_secs", server.ping_interval_secs),
+                ("ping_timeout_secs", server.ping_timeout_secs),
+                ("away_poll_secs", server.away_poll_secs),
+            ] {
+                if secs == Some(0) {
+                    errors.push(format!("'{}' of '{}' can't be 0", field, server.addr));
+                }
+            }
         }
 
         errors
@@ -325,10 +1225,23 @@ impl Config<PassOrCmd> {
             server.sasl_auth = match &mut server.sasl_auth {
                 None => None,
                 Some(other @ SASLAuth::Plain { .. }) => Some(other.clone()),
-                Some(SASLAuth::External { pem }) => Some(SASLAuth::External {
-                    pem: expand_path(pem.to_path_buf(), &home_dir, &env_var)?,
+                Some(SASLAuth::External { cert, key }) => Some(SASLAuth::External {
+                    cert: expand_path(cert.to_path_buf(), &home_dir, &env_var)?,
+                    key: match key {
+                        None => None,
+                        Some(key) => Some(expand_path(key.to_path_buf(), &home_dir, &env_var)?),
+                    },
                 }),
             };
+            if let Some(cert) = &server.tls_cert {
+                server.tls_cert = Some(expand_path(cert.to_path_buf(), &home_dir, &env_var)?);
+            }
+            if let Some(key) = &server.tls_key {
+                server.tls_key = Some(expand_path(key.to_path_buf(), &home_dir, &env_var)?);
+            }
+            if let Some(ca_file) = &server.tls_ca_file {
+                server.tls_ca_file = Some(expand_path(ca_file.to_path_buf(), &home_dir, &env_var)?);
+            }
         }
 
         self.log_dir = match &self.log_dir {
@@ -339,85 +1252,26 @@ impl Config<PassOrCmd> {
         Ok(())
     }
 
-    /// Runs password commands and updates the config with plain passwords obtained from the
-    /// commands.
-    pub(crate) fn read_passwords(self) -> Option<Config<String>> {
-        let Config {
-            servers,
-            defaults,
-            log_dir,
-        } = self;
-
-        let mut servers_: Vec<Server<String>> = Vec::with_capacity(servers.len());
-
-        for server in servers {
-            let Server {
-                addr,
-                alias,
-                port,
-                tls,
-                pass,
-                autoconnect,
-                user,
-                realname,
-                nicks,
-                join,
-                nickserv_ident,
-                sasl_auth,
-            } = server;
-
-            let pass = match pass {
-                None => None,
-                Some(PassOrCmd::Pass(pass)) => Some(pass),
-                Some(PassOrCmd::Cmd(cmd)) => Some(run_command("server password", &addr, &cmd)?),
-            };
-
-            let nickserv_ident = match nickserv_ident {
-                None => None,
-                Some(PassOrCmd::Pass(pass)) => Some(pass),
-                Some(PassOrCmd::Cmd(cmd)) => Some(run_command("NickServ password", &addr, &cmd)?),
-            };
-
-            let sasl_auth = match sasl_auth {
-                None => None,
-                Some(SASLAuth::Plain {
-                    username,
-                    password: PassOrCmd::Pass(pass),
-                }) => Some(SASLAuth::Plain {
-                    username,
-                    password: pass,
-                }),
-                Some(SASLAuth::Plain {
-                    username,
-                    password: PassOrCmd::Cmd(cmd),
-                }) => {
-                    let password = run_command("SASL password", &addr, &cmd)?;
-                    Some(SASLAuth::Plain { username, password })
-                }
-                Some(SASLAuth::External { pem }) => Some(SASLAuth::External { pem }),
-            };
-
-            servers_.push(Server {
-                addr,
-                alias,
-                port,
-                tls,
-                pass,
-                autoconnect,
-                user,
-                realname,
-                nicks,
-                join,
-                nickserv_ident,
-                sasl_auth,
-            });
+    /// Runs password commands given in the map form, and updates the config with plain passwords
+    /// obtained from the commands. `cmd:` commands and keyring entries are run and read when
+    /// connecting to the servers.
+    pub(crate) fn read_passwords(mut self) -> Option<Self> {
+        for server in &mut self.servers {
+            let addr = &server.addr;
+            if let Some(pass) = &mut server.pass {
+                run_password_command(pass, "server password", addr)?;
+            }
+            if let Some(pass) = &mut server.nickserv_ident {
+                run_password_command(pass, "NickServ password", addr)?;
+            }
+            if let Some(SASLAuth::Plain { password, .. }) = &mut server.sasl_auth {
+                run_password_command(password, "SASL password", addr)?;
+            }
+            if let Some(pass) = &mut server.tls_cert_passphrase {
+                run_password_command(pass, "certificate passphrase", addr)?;
+            }
         }
-
-        Some(Config {
-            servers: servers_,
-            defaults,
-            log_dir,
-        })
+        Some(self)
     }
 }
 
@@ -524,6 +1378,85 @@ mod tests {
         }
     }
 
+    #[test]
+    fn username_realname_fallback() {
+        let config = r#"
+servers:
+    - addr: a
+      port: 6697
+      nicks: [n]
+      join: []
+    - addr: b
+      port: 6697
+      user: old_user
+      realname: " server_real "
+      nicks: [n]
+      join: []
+    - addr: c
+      port: 6697
+      username: new_user
+      nicks: [n]
+      join: []
+defaults:
+    nicks: [n]
+    realname: default_real
+    username: default_user
+"#;
+        let Config {
+            servers, defaults, ..
+        } = serde_yaml::from_str::<Config<String>>(config).unwrap();
+
+        assert_eq!(
+            servers[0].username(&defaults),
+            Some("default_user".to_owned())
+        );
+        assert_eq!(servers[0].realname(&defaults), "default_real");
+        assert_eq!(servers[1].username(&defaults), Some("old_user".to_owned()));
+        assert_eq!(servers[1].realname(&defaults), "server_real");
+        assert_eq!(servers[2].username(&defaults), Some("new_user".to_owned()));
+        assert_eq!(servers[2].realname(&defaults), "default_real");
+
+        let defaults = Defaults {
+            username: None,
+            ..defaults
+        };
+        assert_eq!(servers[0].username(&defaults), None);
+    }
+
+    #[test]
+    fn network_grouping() {
+        let config = r#"
+servers:
+    - addr: irc.a.net
+      port: 6697
+      nicks: [n]
+      join: []
+      network: a
+    - addr: irc.b.net
+      port: 6697
+      nicks: [n]
+      join: []
+    - addr: irc2.a.net
+      alias: a2
+      port: 6697
+      nicks: [n]
+      join: []
+      network: A
+defaults:
+    nicks: [n]
+    realname: n
+"#;
+        let Config { servers, .. } = serde_yaml::from_str::<Config<String>>(config).unwrap();
+        let servers = group_by_network(servers);
+        let addrs: Vec<&str> = servers.iter().map(|server| server.addr.as_str()).collect();
+        assert_eq!(addrs, vec!["irc.a.net", "irc2.a.net", "irc.b.net"]);
+        assert_eq!(servers[0].tab_name().as_deref(), Some("a"));
+        assert_eq!(servers[1].tab_name().as_deref(), Some("a2"));
+        assert_eq!(servers[2].tab_name(), None);
+        assert!(servers[1].in_network("a"));
+        assert!(!servers[2].in_network("a"));
+    }
+
     #[test]
     fn validation() {
         // We trim the string fields when deserializing, so `validate` doesn't consider non-empty
@@ -532,24 +1465,60 @@ mod tests {
             servers: vec![Server {
                 addr: "my_server".to_owned(),
                 alias: None,
+                network: None,
                 port: 123,
                 tls: false,
                 pass: None,
                 autoconnect: true,
-                user: None,
-                realname: "".to_owned(),
+                username: None,
+                realname: Some("".to_owned()),
                 nicks: vec!["".to_owned()],
                 join: vec![],
                 nickserv_ident: None,
+                nickserv: None,
+                fallback_encoding: FallbackEncoding::None,
+                encoding: Encoding::Utf8,
                 sasl_auth: None,
+                sasl_fail: SASLFail::Continue,
+                tls_cert: None,
+                tls_key: None,
+                tls_cert_passphrase: None,
+                tls_ca_file: None,
+                tls_pin_fingerprint: None,
+                tls_verify_hostname: true,
+                socks5_proxy: None,
+                bind_address: None,
+                ip_preference: None,
+                join_history_lines: None,
+                via_tor: false,
+                connect_timeout_secs: None,
+                tls_handshake_timeout_secs: None,
+                registration_timeout_secs: None,
+                ping_interval_secs: None,
+                ping_timeout_secs: None,
+                rejoin_on_reconnect: true,
+                away_poll_secs: None,
+                away_poll_max_users: None,
             }],
             defaults: Defaults {
                 nicks: vec!["".to_owned()],
                 realname: "".to_owned(),
+                username: None,
                 join: vec![],
                 tls: false,
+                rejoin_on_reconnect: true,
+                bind_address: None,
+                ip_preference: IpPreference::Auto,
+                join_history_lines: 50,
+                reconnect_backoff: ReconnectBackoff::default(),
+                flood_protection: FloodProtection::default(),
+                ctcp_replies: CtcpReplies::default(),
+                dedup_window: default_dedup_window(),
+                part_message: None,
+                quit_message: None,
             },
             log_dir: None,
+            pm_history_lines: 20,
         };
 
         let errors = config.validate();
@@ -570,12 +1539,282 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tls_cert_validation() {
+        let config = r#"
+servers:
+    - addr: a
+      port: 6667
+      nicks: [n]
+      join: []
+      tls_cert: a.p12
+      tls_cert_passphrase:
+        command: ""
+    - addr: b
+      port: 6697
+      tls: true
+      nicks: [n]
+      join: []
+      tls_key: b.key
+      tls_pin_fingerprint: "C3:3E:6D"
+defaults:
+    nicks: [n]
+    realname: n
+"#;
+        let config = serde_yaml::from_str::<Config<PassOrCmd>>(config).unwrap();
+        assert_eq!(
+            config.validate(),
+            vec![
+                "TLS is not enabled for 'a', but 'tls_cert' is set. \
+                 Please enable TLS for this server in the config file."
+                    .to_owned(),
+                "Empty certificate passphrase command for 'a'".to_owned(),
+                "'tls_pin_fingerprint' of 'b' is not a SHA-256 fingerprint (64 hex digits)"
+                    .to_owned(),
+                "'tls_key' and 'tls_cert_passphrase' of 'b' require 'tls_cert'".to_owned(),
+            ]
+        );
+        assert!(is_sha256_fingerprint(
+            "c33e6dd3920d72e57a71d2056f353cc97582db80173499ff73fdca7753f6a6d7"
+        ));
+    }
+
+    #[test]
+    fn tor_settings() {
+        let config = r#"
+servers:
+    - addr: abcdef.onion
+      port: 6697
+      tls: true
+      tls_verify_hostname: false
+      nicks: [n]
+      join: []
+      via_tor: true
+    - addr: ghijkl.ONION
+      port: 6667
+      nicks: [n]
+      join: []
+      tls_verify_hostname: false
+      connect_timeout_secs: 0
+    - addr: irc.example.org
+      port: 6667
+      nicks: [n]
+      join: []
+      socks5_proxy: "127.0.0.1:1080"
+      ping_timeout_secs: 90
+      ping_interval_secs: 30
+      tls_handshake_timeout_secs: 15
+      registration_timeout_secs: 0
+      join_history_lines: 0
+defaults:
+    nicks: [n]
+    realname: n
+"#;
+        let config = serde_yaml::from_str::<Config<PassOrCmd>>(config).unwrap();
+        assert_eq!(
+            config.validate(),
+            vec![
+                "TLS is not enabled for 'ghijkl.ONION', but 'tls_verify_hostname' is set. \
+                 Please enable TLS for this server in the config file."
+                    .to_owned(),
+                "'ghijkl.ONION' is an onion service, please set 'via_tor: true' (or \
+                 'socks5_proxy') to connect through Tor"
+                    .to_owned(),
+                "'connect_timeout_secs' of 'ghijkl.ONION' can't be 0".to_owned(),
+                "'registration_timeout_secs' of 'irc.example.org' can't be 0".to_owned(),
+            ]
+        );
+
+        let servers = &config.servers;
+        assert_eq!(servers[0].socks5_proxy(), Some(TOR_SOCKS_PROXY));
+        assert_eq!(servers[0].ping_timeout(), Some(Duration::from_secs(180)));
+        assert_eq!(
+            servers[2].socks5_proxy(),
+            Some("127.0.0.1:1080".parse().unwrap())
+        );
+        assert_eq!(servers[2].ping_timeout(), Some(Duration::from_secs(90)));
+        assert_eq!(servers[2].ping_interval(), Some(Duration::from_secs(30)));
+        assert_eq!(servers[0].ping_interval(), None);
+        assert_eq!(servers[2].connect_timeout(), None);
+        assert_eq!(
+            servers[2].tls_handshake_timeout(),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(servers[0].join_history_lines(&config.defaults), 50);
+        assert_eq!(servers[2].join_history_lines(&config.defaults), 0);
+    }
+
+    #[test]
+    fn reconnect_backoff_settings() {
+        let config = r#"
+servers: []
+defaults:
+    nicks: [n]
+    realname: n
+    reconnect_backoff:
+      base_secs: 5
+      multiplier: 0.5
+"#;
+        let config = serde_yaml::from_str::<Config<PassOrCmd>>(config).unwrap();
+        assert_eq!(
+            config.validate(),
+            vec!["'multiplier' of 'reconnect_backoff' can't be less than 1".to_owned()]
+        );
+        assert_eq!(
+            ClientReconnectBackoff::from(config.defaults.reconnect_backoff),
+            ClientReconnectBackoff {
+                base: Duration::from_secs(5),
+                multiplier: 0.5,
+                max: Duration::from_secs(600),
+            }
+        );
+    }
+
+    #[test]
+    fn flood_protection_settings() {
+        let config = r#"
+servers: []
+defaults:
+    nicks: [n]
+    realname: n
+    flood_protection:
+      interval_ms: 500
+"#;
+        let config = serde_yaml::from_str::<Config<PassOrCmd>>(config).unwrap();
+        assert!(config.validate().is_empty());
+        assert_eq!(
+            ClientFloodProtection::from(config.defaults.flood_protection),
+            ClientFloodProtection {
+                burst: 5,
+                interval: Duration::from_millis(500),
+            }
+        );
+    }
+
+    #[test]
+    fn ctcp_reply_settings() {
+        let config = |ctcp_replies: &str| {
+            let config =
+                format!("servers: []\ndefaults:\n    nicks: [n]\n    realname: n\n{ctcp_replies}");
+            serde_yaml::from_str::<Config<PassOrCmd>>(&config)
+                .unwrap()
+                .defaults
+                .ctcp_replies
+                .settings()
+        };
+
+        let settings = config("");
+        assert_eq!(settings, CtcpReplySettings::default());
+        assert_eq!(
+            settings.version_reply(),
+            Some(format!("tiny {}", env!("CARGO_PKG_VERSION")))
+        );
+
+        let settings = config("    ctcp_replies: false\n");
+        assert!(!settings.version && !settings.time && !settings.ping && !settings.clientinfo);
+        assert_eq!(settings.version_reply(), None);
+
+        let settings = config(
+            "    ctcp_replies:\n      version_string: my client\n      include_version: false\n      time: false\n",
+        );
+        assert_eq!(settings.version_reply(), Some("my client".to_owned()));
+        assert!(!settings.time && settings.ping && settings.clientinfo);
+    }
+
+    #[test]
+    fn part_quit_reasons() {
+        let defaults = |config: &str| {
+            let config =
+                format!("servers: []\ndefaults:\n    nicks: [n]\n    realname: n\n{config}");
+            serde_yaml::from_str::<Config<PassOrCmd>>(&config)
+                .unwrap()
+                .defaults
+        };
+
+        let no_messages = defaults("");
+        assert_eq!(no_messages.part_reason(""), None);
+        assert_eq!(no_messages.quit_reason(""), None);
+
+        let with_messages =
+            defaults("    part_message: bye\n    quit_message: tiny {version}, see you\n");
+        assert_eq!(with_messages.part_reason(""), Some("bye".to_owned()));
+        assert_eq!(
+            with_messages.quit_reason(""),
+            Some(format!("tiny {}, see you", env!("CARGO_PKG_VERSION")))
+        );
+        // Command arguments override the defaults
+        assert_eq!(with_messages.part_reason("later"), Some("later".to_owned()));
+        assert_eq!(with_messages.quit_reason("later"), Some("later".to_owned()));
+    }
+
+    #[test]
+    fn run_password_command() {
+        let args: Vec<String> = ["sh", "-c", "printf 'first\\nsecond\\n'"]
+            .iter()
+            .map(|s| (*s).to_owned())
+            .collect();
+        assert_eq!(
+            run_command("password", "server", &args).as_deref(),
+            Some("second")
+        );
+
+        let args = vec!["false".to_owned()];
+        assert_eq!(run_command("password", "server", &args), None);
+    }
+
+    #[test]
+    fn read_passwords_on_startup() {
+        let config = r#"
+servers:
+    - addr: irc.libera.chat
+      port: 6697
+      nicks: [n]
+      join: []
+      pass:
+        command: echo hunter2
+      nickserv_ident: cmd:echo hunter3
+      sasl:
+        username: n
+        password: keyring:tiny/libera
+defaults:
+    nicks: [n]
+    realname: n
+"#;
+        let config = serde_yaml::from_str::<Config<PassOrCmd>>(config).unwrap();
+        let config = config.read_passwords().unwrap();
+        let server = &config.servers[0];
+        assert_eq!(server.pass, Some(PassOrCmd::Pass("hunter2".to_owned())));
+        // `cmd:` commands and keyring entries are run and read when connecting
+        assert_eq!(
+            server.nickserv_ident,
+            Some(PassOrCmd::Cmd {
+                args: vec!["echo".to_owned(), "hunter3".to_owned()],
+                first_line: true,
+            })
+        );
+        assert_eq!(
+            server.sasl_auth,
+            Some(SASLAuth::Plain {
+                username: "n".to_owned(),
+                password: PassOrCmd::Keyring {
+                    service: "tiny".to_owned(),
+                    account: "libera".to_owned(),
+                },
+                mechanism: PasswordMechanism::Plain,
+                plain_fallback: false,
+            })
+        );
+    }
+
     #[test]
     fn parse_password_field() {
         let field = "command: my pass cmd";
         assert_eq!(
             serde_yaml::from_str::<PassOrCmd>(field).unwrap(),
-            PassOrCmd::Cmd(vec!["my".to_owned(), "pass".to_owned(), "cmd".to_owned()])
+            PassOrCmd::Cmd {
+                args: vec!["my".to_owned(), "pass".to_owned(), "cmd".to_owned()],
+                first_line: false
+            }
         );
 
         let field = "my password";
@@ -587,12 +1826,89 @@ mod tests {
         let field = "command: \"pass show 'my password'\"";
         assert_eq!(
             serde_yaml::from_str::<PassOrCmd>(field).unwrap(),
-            PassOrCmd::Cmd(vec![
-                "pass".to_string(),
-                "show".to_string(),
-                "my password".to_string()
-            ])
+            PassOrCmd::Cmd {
+                args: vec![
+                    "pass".to_string(),
+                    "show".to_string(),
+                    "my password".to_string()
+                ],
+                first_line: false
+            }
+        );
+
+        let field = "cmd:pass show irc/libera";
+        assert_eq!(
+            serde_yaml::from_str::<PassOrCmd>(field).unwrap(),
+            PassOrCmd::Cmd {
+                args: vec![
+                    "pass".to_owned(),
+                    "show".to_owned(),
+                    "irc/libera".to_owned()
+                ],
+                first_line: true
+            }
+        );
+
+        let field = "keyring:tiny/libera";
+        assert_eq!(
+            serde_yaml::from_str::<PassOrCmd>(field).unwrap(),
+            PassOrCmd::Keyring {
+                service: "tiny".to_owned(),
+                account: "libera".to_owned()
+            }
+        );
+
+        // Accounts can have slashes
+        let field = "keyring:tiny/irc.libera.chat/nick";
+        assert_eq!(
+            serde_yaml::from_str::<PassOrCmd>(field).unwrap(),
+            PassOrCmd::Keyring {
+                service: "tiny".to_owned(),
+                account: "irc.libera.chat/nick".to_owned()
+            }
+        );
+
+        assert!(serde_yaml::from_str::<PassOrCmd>("keyring:tiny").is_err());
+        assert!(serde_yaml::from_str::<PassOrCmd>("keyring:/libera").is_err());
+    }
+
+    #[test]
+    fn parse_sasl() {
+        let field = "pem: ~/tiny.pem";
+        assert_eq!(
+            serde_yaml::from_str::<SASLAuth<PassOrCmd>>(field).unwrap(),
+            SASLAuth::External {
+                cert: "~/tiny.pem".into(),
+                key: None,
+            }
+        );
+
+        let field = "cert: tiny.crt\nkey: tiny.key";
+        assert_eq!(
+            serde_yaml::from_str::<SASLAuth<PassOrCmd>>(field).unwrap(),
+            SASLAuth::External {
+                cert: "tiny.crt".into(),
+                key: Some("tiny.key".into()),
+            }
+        );
+
+        let field = "username: tiny\npassword: hunter2\nmechanism: scram-sha-256";
+        assert_eq!(
+            serde_yaml::from_str::<SASLAuth<PassOrCmd>>(field).unwrap(),
+            SASLAuth::Plain {
+                username: "tiny".to_owned(),
+                password: PassOrCmd::Pass("hunter2".to_owned()),
+                mechanism: PasswordMechanism::ScramSha256,
+                plain_fallback: false,
+            }
         );
+
+        assert_eq!(
+            serde_yaml::from_str::<SASLFail>("disconnect").unwrap(),
+            SASLFail::Disconnect
+        );
+        assert!(is_pem(b"Bag Attributes\n-----BEGIN CERTIFICATE-----\n"));
+        assert!(!is_pem(&[0x30, 0x82, 0x0a, 0x00]));
     }
 
     #[test]
@@ -601,26 +1917,63 @@ mod tests {
             servers: vec![Server {
                 addr: "my_server".to_owned(),
                 alias: None,
+                network: None,
                 port: 123,
                 tls: false,
                 pass: None,
                 autoconnect: true,
-                user: None,
-                realname: "".to_owned(),
+                username: None,
+                realname: Some("".to_owned()),
                 nicks: vec!["".to_owned()],
                 join: vec![],
                 nickserv_ident: None,
+                nickserv: None,
+                fallback_encoding: FallbackEncoding::None,
+                encoding: Encoding::Utf8,
                 sasl_auth: Some(SASLAuth::External {
-                    pem: "~/a/$SASL/b".into(),
+                    cert: "~/a/$SASL/b".into(),
+                    key: Some("$SASL/key".into()),
                 }),
+                sasl_fail: SASLFail::Continue,
+                tls_cert: Some("~/$SASL.pem".into()),
+                tls_key: None,
+                tls_cert_passphrase: None,
+                tls_ca_file: None,
+                tls_pin_fingerprint: None,
+                tls_verify_hostname: true,
+                socks5_proxy: None,
+                bind_address: None,
+                ip_preference: None,
+                join_history_lines: None,
+                via_tor: false,
+                connect_timeout_secs: None,
+                tls_handshake_timeout_secs: None,
+                registration_timeout_secs: None,
+                ping_interval_secs: None,
+                ping_timeout_secs: None,
+                rejoin_on_reconnect: true,
+                away_poll_secs: None,
+                away_poll_max_users: None,
             }],
             defaults: Defaults {
                 nicks: vec!["".to_owned()],
                 realname: "".to_owned(),
+                username: None,
                 join: vec![],
                 tls: false,
+                rejoin_on_reconnect: true,
+                bind_address: None,
+                ip_preference: IpPreference::Auto,
+                join_history_lines: 50,
+                reconnect_backoff: ReconnectBackoff::default(),
+                flood_protection: FloodProtection::default(),
+                ctcp_replies: CtcpReplies::default(),
+                dedup_window: default_dedup_window(),
+                part_message: None,
+                quit_message: None,
             },
             log_dir: Some("~/b/$LOG/c".into()),
+            pm_history_lines: 20,
         };
         config
             .expand_fields(
@@ -636,9 +1989,14 @@ mod tests {
         assert_eq!(
             config.servers[0].sasl_auth,
             Some(SASLAuth::External {
-                pem: PathBuf::from("/home/test/a/sasl_val/b"),
+                cert: PathBuf::from("/home/test/a/sasl_val/b"),
+                key: Some(PathBuf::from("sasl_val/key")),
             })
         );
+        assert_eq!(
+            config.servers[0].tls_cert,
+            Some(PathBuf::from("/home/test/sasl_val.pem"))
+        );
         assert_eq!(
             config.log_dir,
             Some(PathBuf::from("/home/test/b/log_val/c"))
@@ -652,10 +2010,22 @@ mod tests {
             defaults: Defaults {
                 nicks: vec!["nick".to_owned()],
                 realname: "real".to_owned(),
+                username: None,
                 join: vec![],
                 tls: false,
+                rejoin_on_reconnect: true,
+                bind_address: None,
+                ip_preference: IpPreference::Auto,
+                join_history_lines: 50,
+                reconnect_backoff: ReconnectBackoff::default(),
+                flood_protection: FloodProtection::default(),
+                ctcp_replies: CtcpReplies::default(),
+                dedup_window: default_dedup_window(),
+                part_message: None,
+                quit_message: None,
             },
             log_dir: Some("~/logs/$MISSING/data".into()),
+            pm_history_lines: 20,
         };
         let err = config
             .expand_fields(
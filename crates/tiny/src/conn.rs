@@ -3,20 +3,73 @@
 
 //! IRC event handling
 
+use crate::config::CtcpReplySettings;
+use crate::sts::StsPolicies;
 use crate::ui::UI;
-use libtiny_common::{ChanNameRef, MsgTarget, TabStyle};
+use crate::utils;
+use libtiny_client::{Batches, ConnectStage, ISupport};
+use libtiny_common::{CaseMapping, ChanName, ChanNameRef, MsgTarget, TabStyle};
 use libtiny_wire as wire;
 
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
 use tokio::sync::mpsc;
 use tokio_stream::StreamExt;
 use tokio_stream::wrappers::ReceiverStream;
 
+/// Default delay before rejoining a channel after a kick, in seconds.
+const DEFAULT_REJOIN_DELAY_SECS: u64 = 3;
+
+/// Max. number of rejoins after kicks in a channel within `KICK_REJOIN_WINDOW`. Avoids rejoin
+/// loops against kick bots. The rejoin delay is doubled after each rejoin in the window.
+const MAX_KICK_REJOINS: usize = 3;
+
+const KICK_REJOIN_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Default max. number of CTCP requests from a nick within `CTCP_FLOOD_WINDOW`.
+const DEFAULT_CTCP_FLOOD_LIMIT: usize = 5;
+
+const CTCP_FLOOD_WINDOW: Duration = Duration::from_secs(60);
+
+/// Default time after a netsplit in which joins of the users lost in the split are shown as a
+/// netjoin, in seconds.
+const DEFAULT_NETSPLIT_TIMEOUT_SECS: u64 = 10 * 60;
+
+/// Default number of recent messages per target remembered to drop duplicates, see `SeenMsgs`.
+pub(crate) const DEFAULT_DEDUP_WINDOW: usize = 100;
+
+/// Nicks from RPL_NAMREPLY messages are added to the UI in chunks of this size. Channels with
+/// thousands of users send hundreds of RPL_NAMREPLY messages when joined.
+const NAMES_CHUNK_SIZE: usize = 1000;
+
 pub(crate) trait Client {
     fn get_serv_name(&self) -> &str;
 
     fn get_nick(&self) -> String;
 
     fn is_nick_accepted(&self) -> bool;
+
+    /// Rejoin a channel after a kick, see `libtiny_client::Client::rejoin_after`.
+    fn rejoin_after(&self, chan: &ChanNameRef, delay: Duration);
+
+    fn is_away(&self, nick: &str) -> bool;
+
+    /// Address of the connection, see `libtiny_client::Client::get_addr`.
+    fn get_addr(&self) -> Option<SocketAddr>;
+
+    /// Reply to a CTCP request, see `libtiny_client::Client::ctcp_reply`.
+    fn ctcp_reply(&self, nick: &str, ctcp: &str, body: &str);
+
+    /// Features of the server, see `libtiny_client::Client::get_isupport`.
+    fn get_isupport(&self) -> ISupport;
+
+    /// IRCv3 capabilities advertised by the server, with their values.
+    fn get_server_caps(&self) -> Vec<String>;
 }
 
 impl Client for libtiny_client::Client {
@@ -31,21 +84,251 @@ impl Client for libtiny_client::Client {
     fn is_nick_accepted(&self) -> bool {
         self.is_nick_accepted()
     }
+
+    fn rejoin_after(&self, chan: &ChanNameRef, delay: Duration) {
+        libtiny_client::Client::rejoin_after(&mut self.clone(), chan, delay)
+    }
+
+    fn is_away(&self, nick: &str) -> bool {
+        self.is_away(nick)
+    }
+
+    fn get_addr(&self) -> Option<SocketAddr> {
+        self.get_addr()
+    }
+
+    fn ctcp_reply(&self, nick: &str, ctcp: &str, body: &str) {
+        libtiny_client::Client::ctcp_reply(&mut self.clone(), nick, ctcp, body)
+    }
+
+    fn get_isupport(&self) -> ISupport {
+        self.get_isupport()
+    }
+
+    fn get_server_caps(&self) -> Vec<String> {
+        self.get_server_caps()
+    }
+}
+
+/// Times of rejoins after kicks, by channel.
+#[derive(Default)]
+struct KickRejoins(HashMap<ChanName, Vec<Instant>>);
+
+impl KickRejoins {
+    /// Record a rejoin in the channel. Returns the delay before rejoining: `delay` doubled for
+    /// each recent rejoin. Returns `None` if we rejoined the channel too many times recently and
+    /// shouldn't rejoin again.
+    fn add(&mut self, chan: &ChanNameRef, now: Instant, delay: Duration) -> Option<Duration> {
+        let rejoins = self.0.entry(chan.to_owned()).or_default();
+        rejoins.retain(|t| now.duration_since(*t) < KICK_REJOIN_WINDOW);
+        if rejoins.len() >= MAX_KICK_REJOINS {
+            return None;
+        }
+        let delay = delay * 2u32.pow(rejoins.len() as u32);
+        rejoins.push(now);
+        Some(delay)
+    }
+}
+
+/// Users that quit in a netsplit, by nick, with the servers that split, the time of the quit, and
+/// the channels they were in. Joins of these users to the channels are shown as a netjoin, until
+/// the split is older than the netsplit timeout. Used when the server doesn't send IRCv3 `netjoin`
+/// batches.
+#[derive(Default)]
+struct Netsplits(HashMap<String, NetsplitUser>);
+
+struct NetsplitUser {
+    servers: String,
+    time: Instant,
+    chans: Vec<ChanName>,
+}
+
+impl Netsplits {
+    /// Record a user that quit in the netsplit between `servers`.
+    fn quit(&mut self, nick: &str, servers: &str, chans: &[ChanName], now: Instant) {
+        self.0.insert(
+            nick.to_owned(),
+            NetsplitUser {
+                servers: servers.to_owned(),
+                time: now,
+                chans: chans.to_vec(),
+            },
+        );
+    }
+
+    /// Record a join. Returns the servers of the netsplit if the user quit in a netsplit within
+    /// `timeout` while in the channel.
+    fn join(
+        &mut self,
+        nick: &str,
+        chan: &ChanNameRef,
+        now: Instant,
+        timeout: Duration,
+    ) -> Option<String> {
+        self.0
+            .retain(|_, user| now.duration_since(user.time) < timeout);
+        let user = self.0.get_mut(nick)?;
+        let chan_idx = user.chans.iter().position(|chan_| chan_ == chan)?;
+        user.chans.swap_remove(chan_idx);
+        let servers = user.servers.clone();
+        if user.chans.is_empty() {
+            self.0.remove(nick);
+        }
+        Some(servers)
+    }
+}
+
+/// Times of CTCP requests, by nick, and how to reply to them.
+#[derive(Default)]
+struct CtcpRequests {
+    replies: CtcpReplySettings,
+    requests: HashMap<String, Vec<Instant>>,
+    /// Nicks that are currently flooding, to report a flood only once.
+    flooding: HashSet<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CtcpRate {
+    Ok,
+    /// The nick started flooding with this request.
+    FloodStart,
+    /// The nick is still flooding.
+    Flood,
+}
+
+impl CtcpRequests {
+    /// Record a CTCP request from the nick. Requests that exceed `limit` within
+    /// `CTCP_FLOOD_WINDOW` are a flood.
+    fn add(&mut self, nick: &str, now: Instant, limit: usize) -> CtcpRate {
+        let requests = self.requests.entry(nick.to_owned()).or_default();
+        requests.retain(|t| now.duration_since(*t) < CTCP_FLOOD_WINDOW);
+        requests.push(now);
+        if requests.len() <= limit {
+            self.flooding.remove(nick);
+            CtcpRate::Ok
+        } else if self.flooding.insert(nick.to_owned()) {
+            CtcpRate::FloodStart
+        } else {
+            CtcpRate::Flood
+        }
+    }
+}
+
+/// Nicks from RPL_NAMREPLY messages not added to the UI yet, by channel.
+#[derive(Default)]
+struct NamesBuf(HashMap<ChanName, Vec<String>>);
+
+impl NamesBuf {
+    /// Add nicks of a channel. Returns the buffered nicks of the channel when there are enough of
+    /// them to add to the UI.
+    fn add<'a, I>(&mut self, chan: &ChanNameRef, nicks: I) -> Option<Vec<String>>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let buf = self.0.entry(chan.to_owned()).or_default();
+        buf.extend(nicks.map(str::to_owned));
+        if buf.len() >= NAMES_CHUNK_SIZE {
+            Some(std::mem::take(buf))
+        } else {
+            None
+        }
+    }
+
+    /// Remove the buffered nicks of a channel. Called on RPL_ENDOFNAMES.
+    fn finish(&mut self, chan: &ChanNameRef) -> Vec<String> {
+        self.0.remove(chan).unwrap_or_default()
+    }
+}
+
+/// Keys of the recent messages shown, by target, to drop the messages replayed by a bouncer after
+/// reconnecting. See `dedup_key` for the keys.
+#[derive(Default)]
+struct SeenMsgs {
+    /// Number of messages to remember per target. 0 disables deduplication.
+    window: usize,
+    msgs: HashMap<String, VecDeque<u64>>,
+}
+
+impl SeenMsgs {
+    /// Record a message sent to `target`. Returns `false` if the message is one of the last
+    /// `window` messages of the target, i.e. it was shown before.
+    fn add(&mut self, target: &str, key: u64) -> bool {
+        if self.window == 0 {
+            return true;
+        }
+        let msgs = self.msgs.entry(target.to_lowercase()).or_default();
+        if msgs.contains(&key) {
+            return false;
+        }
+        if msgs.len() >= self.window {
+            msgs.pop_front();
+        }
+        msgs.push_back(key);
+        true
+    }
+}
+
+/// Key of a message for `SeenMsgs`: the IRCv3 `msgid`, or the `server-time`, sender and text of
+/// the message. `None` without a `msgid` and `server-time`, as then a replayed message can't be
+/// told apart from a message that is repeated later.
+fn dedup_key(msgid: Option<&str>, time: Option<&str>, sender: &str, msg: &str) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    match (msgid, time) {
+        (Some(msgid), _) => msgid.hash(&mut hasher),
+        (None, Some(time)) => (time, sender, msg).hash(&mut hasher),
+        (None, None) => return None,
+    }
+    Some(hasher.finish())
 }
 
 pub(crate) async fn task(
     rcv_ev: mpsc::Receiver<libtiny_client::Event>,
     ui: UI,
     client: Box<dyn Client>,
+    ctcp_replies: CtcpReplySettings,
+    dedup_window: usize,
 ) {
     let mut rcv_ev = ReceiverStream::new(rcv_ev);
+    let mut kick_rejoins = KickRejoins::default();
+    let mut netsplits = Netsplits::default();
+    let mut ctcp_requests = CtcpRequests {
+        replies: ctcp_replies,
+        ..CtcpRequests::default()
+    };
+    let mut names = NamesBuf::default();
+    let mut batches = Batches::default();
+    let mut seen_msgs = SeenMsgs {
+        window: dedup_window,
+        ..SeenMsgs::default()
+    };
     while let Some(ev) = rcv_ev.next().await {
-        handle_conn_ev(&ui, &*client, ev);
+        handle_conn_ev(
+            &ui,
+            &*client,
+            &mut kick_rejoins,
+            &mut netsplits,
+            &mut ctcp_requests,
+            &mut names,
+            &mut batches,
+            &mut seen_msgs,
+            ev,
+        );
         ui.draw();
     }
 }
 
-fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
+#[allow(clippy::too_many_arguments)]
+fn handle_conn_ev(
+    ui: &UI,
+    client: &dyn Client,
+    kick_rejoins: &mut KickRejoins,
+    netsplits: &mut Netsplits,
+    ctcp_requests: &mut CtcpRequests,
+    names: &mut NamesBuf,
+    batches: &mut Batches,
+    seen_msgs: &mut SeenMsgs,
+    ev: libtiny_client::Event,
+) {
     use libtiny_client::Event::*;
     match ev {
         ResolvingHost => {
@@ -64,9 +347,21 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
                 },
             );
         }
+        ConnectingUnix(path) => {
+            ui.add_client_msg(
+                &format!("Connecting to Unix socket {path}"),
+                &MsgTarget::AllServTabs {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
         Connected => {
+            let msg = match client.get_addr() {
+                Some(addr) => format!("Connected to {addr}."),
+                None => "Connected.".to_owned(),
+            };
             ui.add_client_msg(
-                "Connected.",
+                &msg,
                 &MsgTarget::AllServTabs {
                     serv: client.get_serv_name(),
                 },
@@ -75,14 +370,24 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
         Disconnected => {
             let serv = client.get_serv_name();
             ui.add_err_msg(
-                &format!(
-                    "Disconnected. Will try to reconnect in {} seconds.",
-                    libtiny_client::RECONNECT_SECS
-                ),
+                "Disconnected.",
                 time::now(),
                 &MsgTarget::AllServTabs { serv },
             );
-            ui.clear_nicks(serv);
+            ui.clear_nicks(&MsgTarget::AllServTabs { serv });
+            *names = NamesBuf::default();
+            batches.clear();
+        }
+        WaitingToReconnect { delay } => {
+            ui.add_client_msg(
+                &format!(
+                    "Reconnecting in {}s; /connect to retry now",
+                    delay.as_secs()
+                ),
+                &MsgTarget::Server {
+                    serv: client.get_serv_name(),
+                },
+            );
         }
         IoErr(err) => {
             ui.add_err_msg(
@@ -93,6 +398,32 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
                 },
             );
         }
+        BindErr { addr, err } => {
+            ui.add_err_msg(
+                &format!("Can't connect from local address {addr}: {err}"),
+                time::now(),
+                &MsgTarget::Server {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+        TimedOut {
+            stage,
+            addr,
+            timeout,
+        } => {
+            let to = match stage {
+                ConnectStage::Registration => "with",
+                ConnectStage::Connect | ConnectStage::TlsHandshake => "to",
+            };
+            ui.add_err_msg(
+                &format!("{stage} {to} {addr} timed out after {}s", timeout.as_secs()),
+                time::now(),
+                &MsgTarget::Server {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
         ConnectionClosed => {
             ui.add_err_msg(
                 "Connection closed on the remote end",
@@ -111,6 +442,44 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
                 },
             );
         }
+        ClientCertErr(err) => {
+            ui.add_err_msg(
+                &format!("Client certificate error: {err}"),
+                time::now(),
+                &MsgTarget::AllServTabs {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+        ServerCertErr {
+            err,
+            fingerprint,
+            subject,
+        } => {
+            let serv = client.get_serv_name();
+            let target = MsgTarget::AllServTabs { serv };
+            ui.add_err_msg(
+                &format!("Server certificate rejected: {err}"),
+                time::now(),
+                &target,
+            );
+            ui.add_client_msg(
+                &format!(
+                    "Certificate subject: {}",
+                    subject.as_deref().unwrap_or("(unknown)")
+                ),
+                &target,
+            );
+            ui.add_client_msg(
+                &format!("Certificate SHA-256 fingerprint: {fingerprint}"),
+                &target,
+            );
+            ui.confirm(
+                "Accept and pin the certificate? (y/n)",
+                &format!("pin-cert {fingerprint}"),
+                &MsgTarget::Server { serv },
+            );
+        }
         CantResolveAddr => {
             ui.add_err_msg(
                 "Can't resolve address",
@@ -124,7 +493,17 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
             ui.set_nick(client.get_serv_name(), &new_nick);
         }
         Msg(msg) => {
-            handle_irc_msg(ui, client, msg);
+            handle_irc_msg(
+                ui,
+                client,
+                kick_rejoins,
+                netsplits,
+                ctcp_requests,
+                names,
+                batches,
+                seen_msgs,
+                msg,
+            );
         }
         WireError(err) => {
             ui.add_err_msg(
@@ -142,15 +521,226 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
                 chan: &chan,
             },
         ),
+        SASLFailDisconnect => {
+            let serv = client.get_serv_name();
+            ui.add_err_msg(
+                "Disconnected because SASL authentication failed. Use `/connect` to reconnect.",
+                time::now(),
+                &MsgTarget::AllServTabs { serv },
+            );
+            ui.clear_nicks(&MsgTarget::AllServTabs { serv });
+            *names = NamesBuf::default();
+            batches.clear();
+        }
+        PingTimeout { timeout } => {
+            ui.add_err_msg(
+                &format!(
+                    "Connection timed out: no reply from the server in {}s",
+                    timeout.as_secs()
+                ),
+                time::now(),
+                &MsgTarget::AllServTabs {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+        PingReply { nick, rtt } => {
+            let pinged = nick.as_deref().unwrap_or_else(|| client.get_serv_name());
+            ui.add_client_msg(
+                &format!("Lag to {}: {} ms", pinged, rtt.as_millis()),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        MsgModified { target, msg } => {
+            let serv = client.get_serv_name();
+            ui.add_client_msg(
+                &format!("Message was changed by the server (e.g. truncated). Sent: {msg}"),
+                &privmsg_target(serv, &target, &client.get_isupport()),
+            );
+        }
+        MsgNotSent {
+            target,
+            msg,
+            reason,
+        } => {
+            let serv = client.get_serv_name();
+            ui.add_client_err_msg(
+                &format!("Message not sent ({reason}): {msg}"),
+                &privmsg_target(serv, &target, &client.get_isupport()),
+            );
+        }
+        History {
+            target,
+            msgs,
+            older,
+        } => {
+            let serv = client.get_serv_name();
+            let msgs: Vec<libtiny_tui::HistoryMsg> =
+                msgs.into_iter().filter_map(history_msg).collect();
+            match target {
+                wire::MsgTarget::Chan(chan) => {
+                    ui.add_history(&msgs, older, &MsgTarget::Chan { serv, chan: &chan });
+                }
+                wire::MsgTarget::User(nick) => {
+                    // Don't create a tab for the history of a closed private chat
+                    if ui.user_tab_exists(serv, &nick) {
+                        ui.add_history(&msgs, older, &MsgTarget::User { serv, nick: &nick });
+                    }
+                }
+            }
+        }
+        StsUpgrade { port } => {
+            ui.add_client_msg(
+                &format!("Server has an STS policy, reconnecting with TLS on port {port}"),
+                &MsgTarget::AllServTabs {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+        StsPolicy { port, duration } => {
+            let serv = client.get_serv_name();
+            let mut sts_policies = StsPolicies::load();
+            sts_policies.set(serv, port, duration, SystemTime::now());
+            if let Err(err) = sts_policies.save() {
+                ui.add_client_err_msg(
+                    &format!(
+                        "Can't save STS policy to {:?}: {err}",
+                        sts_policies.path().unwrap_or_else(|| Path::new(""))
+                    ),
+                    &MsgTarget::Server { serv },
+                );
+            }
+        }
+        MsgsQueued { target, n_queued } => {
+            let serv = client.get_serv_name();
+            ui.set_queued(
+                n_queued,
+                &privmsg_target(serv, &target, &client.get_isupport()),
+            );
+        }
+        ChanModes { chan, modes } => {
+            ui.set_chan_modes(client.get_serv_name(), &chan, &modes);
+        }
+        NickAway { nick, away } => {
+            ui.set_nick_away(client.get_serv_name(), &nick, away);
+        }
+        UnmappableChars { target } => {
+            let serv = client.get_serv_name();
+            ui.add_client_err_msg(
+                "Message has characters that can't be sent in the server's encoding, sent '?' \
+                 instead",
+                &privmsg_target(serv, &target, &client.get_isupport()),
+            );
+        }
+    }
+}
+
+/// Convert a message in a history batch to a message to show in the UI. Only PRIVMSGs, NOTICEs,
+/// and ACTIONs are shown.
+fn history_msg(msg: wire::Msg) -> Option<libtiny_tui::HistoryMsg> {
+    let ts = msg.server_time().map(time::at).unwrap_or_else(time::now);
+    let wire::Msg { pfx, cmd, .. } = msg;
+    let (msg, ctcp) = match cmd {
+        wire::Cmd::PRIVMSG { msg, ctcp, .. } => (msg, ctcp),
+        _ => return None,
+    };
+    if ctcp.is_some() && ctcp != Some(wire::CTCP::Action) {
+        return None;
     }
+    let sender = match pfx? {
+        wire::Pfx::Server(serv) => serv,
+        wire::Pfx::User { nick, .. } | wire::Pfx::Ambiguous(nick) => nick,
+    };
+    Some(libtiny_tui::HistoryMsg {
+        sender,
+        msg,
+        ts,
+        is_action: ctcp == Some(wire::CTCP::Action),
+    })
 }
 
-fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
+/// Whether `nick` is our nick, according to the server's case mapping.
+fn is_our_nick(client: &dyn Client, nick: &str) -> bool {
+    client.get_isupport().nick_eq(nick, &client.get_nick())
+}
+
+/// Sender of a message to the users with a membership prefix in a channel (STATUSMSG, e.g.
+/// `@#chan`) as shown in the channel tab, marked with the users the message is sent to.
+pub(crate) fn statusmsg_sender(prefix: char, sender: &str) -> String {
+    let users = match prefix {
+        '~' => "owners",
+        '&' => "admins",
+        '@' => "ops",
+        '%' => "halfops",
+        '+' => "voiced",
+        _ => return format!("[{prefix}] {sender}"),
+    };
+    format!("[{users}] {sender}")
+}
+
+/// Nick prefix of bouncer control users used when `bouncer_control_prefix` is not set, e.g.
+/// `*status` and `*playback` with ZNC.
+const DEFAULT_BOUNCER_CONTROL_PREFIX: char = '*';
+
+/// Nick prefix of the bouncer control users of a server.
+pub(crate) fn bouncer_control_prefix(ui: &UI, serv: &str) -> char {
+    ui.get_tab_config(serv, None)
+        .bouncer_control_prefix
+        .unwrap_or(DEFAULT_BOUNCER_CONTROL_PREFIX)
+}
+
+/// Tab of a message we sent to `target`. Same as the tab `ui::send_msg` shows the message in.
+pub(crate) fn privmsg_target<'a>(
+    serv: &'a str,
+    target: &'a str,
+    isupport: &ISupport,
+) -> MsgTarget<'a> {
+    if isupport.is_chan(target) {
+        MsgTarget::Chan {
+            serv,
+            chan: ChanNameRef::new(target),
+        }
+    } else if utils::is_service(target) {
+        MsgTarget::Server { serv }
+    } else {
+        MsgTarget::User { serv, nick: target }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_irc_msg(
+    ui: &UI,
+    client: &dyn Client,
+    kick_rejoins: &mut KickRejoins,
+    netsplits: &mut Netsplits,
+    ctcp_requests: &mut CtcpRequests,
+    names: &mut NamesBuf,
+    batches: &mut Batches,
+    seen_msgs: &mut SeenMsgs,
+    msg: wire::Msg,
+) {
     use wire::Cmd::*;
     use wire::Pfx::*;
 
-    let wire::Msg { pfx, cmd } = msg;
-    let ts = time::now();
+    // Use the time from the server (e.g. a bouncer replaying old messages) when available
+    let ts = msg.server_time().map(time::at).unwrap_or_else(time::now);
+    let is_playback =
+        batches.in_batch(&msg, "chathistory") || batches.in_batch(&msg, "znc.in/playback");
+    // Servers that split, when the message is in a `netsplit` batch
+    let netsplit_servers = batches
+        .batches_of(&msg)
+        .into_iter()
+        .find(|batch| batch.batch_type == "netsplit")
+        .map(|batch| batch.params.join(" "));
+    // Servers that reconnected, when the message is in a `netjoin` batch
+    let netjoin_servers = batches
+        .batches_of(&msg)
+        .into_iter()
+        .find(|batch| batch.batch_type == "netjoin")
+        .map(|batch| batch.params.join(" "));
+    let typing = msg.typing();
+    batches.update(&msg);
+    let wire::Msg { tags, pfx, cmd } = msg;
     let serv = client.get_serv_name();
     match cmd {
         PRIVMSG {
@@ -173,37 +763,143 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 User { ref nick, .. } | Ambiguous(ref nick) => nick,
             };
 
-            if ctcp == Some(wire::CTCP::Version) {
+            // Bouncers replay recent messages after reconnecting, drop the ones already shown
+            let dedup_target = match &target {
+                wire::MsgTarget::Chan(chan) => chan.display(),
+                wire::MsgTarget::User(nick) => nick,
+            };
+            if let Some(key) = dedup_key(tags.get("msgid"), tags.get("time"), sender, &msg)
+                && !seen_msgs.add(dedup_target, key)
+            {
+                debug!("Dropping duplicate message to {dedup_target}: {msg:?}");
+                return;
+            }
+
+            // CTCP requests (other than ACTIONs) are shown as activity lines and replied
+            // automatically. Requests from nicks that send too many of them are ignored.
+            if let Some(ctcp) = &ctcp
+                && !is_notice
+                && *ctcp != wire::CTCP::Action
+                && !matches!(pfx, Server(_))
+                && !is_our_nick(client, sender)
+            {
+                let limit = ui
+                    .get_tab_config(serv, None)
+                    .ctcp_flood_limit
+                    .unwrap_or(DEFAULT_CTCP_FLOOD_LIMIT);
+                match ctcp_requests.add(sender, Instant::now(), limit) {
+                    CtcpRate::Ok => {}
+                    CtcpRate::FloodStart => {
+                        ui.add_client_err_msg(
+                            &format!("CTCP flood from {sender}, suppressing"),
+                            &MsgTarget::Server { serv },
+                        );
+                        return;
+                    }
+                    CtcpRate::Flood => {
+                        return;
+                    }
+                }
+
                 let msg_target = if ui.user_tab_exists(serv, sender) {
                     MsgTarget::User { serv, nick: sender }
                 } else {
                     MsgTarget::Server { serv }
                 };
-                ui.add_client_msg(
-                    &format!("Received version request from {sender}"),
+                ui.add_client_notify_msg(
+                    &format!("CTCP {} request from {sender}", ctcp.as_str()),
                     &msg_target,
                 );
+                if let Some(reply) = ctcp_reply(&ctcp_requests.replies, ctcp, &msg, time::now()) {
+                    client.ctcp_reply(sender, ctcp.as_str(), &reply);
+                }
+                return;
+            }
+
+            if is_notice && ctcp == Some(wire::CTCP::Ping) {
+                // Reply to a `/ping`, reported with a `PingReply` event
+                return;
+            }
+
+            if ctcp == Some(wire::CTCP::Ping) && is_our_nick(client, sender) {
+                // Our `/ping <nick>` echoed back with `echo-message`
                 return;
             }
 
             let is_action = ctcp == Some(wire::CTCP::Action);
 
+            // Other CTCP messages that get here (e.g. replies to our requests) are labeled with
+            // the CTCP type, on the screen and in the logs
+            let msg = match &ctcp {
+                Some(ctcp) if !is_action && msg.is_empty() => format!("CTCP {}", ctcp.as_str()),
+                Some(ctcp) if !is_action => format!("CTCP {} {}", ctcp.as_str(), msg),
+                _ => msg,
+            };
+
+            // Messages replayed by the server or a bouncer don't trigger notifications
+            let add_privmsg = |sender: &str, target: &MsgTarget, highlight: bool| {
+                if is_playback {
+                    ui.add_playback_privmsg(sender, &msg, ts, target, highlight, is_action);
+                } else {
+                    ui.add_privmsg(sender, &msg, ts, target, highlight, is_action);
+                }
+            };
+
+            // Messages to the users with a membership prefix in a channel (STATUSMSG, e.g.
+            // `@#chan`) are shown in the channel tab
+            let (target, status) = match target {
+                wire::MsgTarget::User(target) => {
+                    match client.get_isupport().split_statusmsg(&target) {
+                        Some((prefix, chan)) => (
+                            wire::MsgTarget::Chan(ChanName::new(chan.to_owned())),
+                            Some(prefix),
+                        ),
+                        None => (wire::MsgTarget::User(target), None),
+                    }
+                }
+                target => (target, None),
+            };
+
             match target {
                 wire::MsgTarget::Chan(chan) => {
                     let ui_msg_target = MsgTarget::Chan { serv, chan: &chan };
-                    // Highlight the message if it mentions us.
-                    if mentions_user(&msg, &client.get_nick()) {
-                        ui.add_privmsg(sender, &msg, ts, &ui_msg_target, true, is_action);
-                        ui.set_tab_style(TabStyle::Highlight, &ui_msg_target);
-                        let mentions_target = MsgTarget::Server { serv: "mentions" };
-                        ui.add_msg(
-                            &format!("{} in {}:{}: {}", sender, serv, chan.display(), msg),
+                    let is_ours = is_our_nick(client, sender);
+                    // Messages sent by us (echoed by the server with `echo-message`, or relayed
+                    // by a bouncer with `znc.in/self-message`) are shown with our current nick
+                    let our_nick = client.get_nick();
+                    let sender = if is_ours { our_nick.as_str() } else { sender };
+                    let status_sender = status.map(|prefix| statusmsg_sender(prefix, sender));
+                    let ui_sender = status_sender.as_deref().unwrap_or(sender);
+                    if is_ours {
+                        // Our own messages don't trigger notifications
+                        ui.add_playback_privmsg(
+                            ui_sender,
+                            &msg,
                             ts,
-                            &mentions_target,
+                            &ui_msg_target,
+                            false,
+                            is_action,
                         );
+                        ui.set_tab_style(TabStyle::NewMsg, &ui_msg_target);
+                    } else if mentions_user(
+                        // Formatting characters around the nick are not nick boundaries,
+                        // remove them
+                        &wire::formatting::remove_irc_control_chars(&msg),
+                        &our_nick,
+                        client.get_isupport().case_mapping(),
+                    ) {
+                        add_privmsg(ui_sender, &ui_msg_target, true);
+                        ui.set_tab_style(TabStyle::Highlight, &ui_msg_target);
+                        let mentions_target = MsgTarget::Server { serv: "mentions" };
+                        let mention = if is_action {
+                            format!("* {} in {}:{}: {}", ui_sender, serv, chan.display(), msg)
+                        } else {
+                            format!("{} in {}:{}: {}", ui_sender, serv, chan.display(), msg)
+                        };
+                        ui.add_msg(&mention, ts, &mentions_target);
                         ui.set_tab_style(TabStyle::Highlight, &mentions_target);
                     } else {
-                        ui.add_privmsg(sender, &msg, ts, &ui_msg_target, false, is_action);
+                        add_privmsg(ui_sender, &ui_msg_target, false);
                         ui.set_tab_style(TabStyle::NewMsg, &ui_msg_target);
                     }
                 }
@@ -215,16 +911,29 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                     // `user@host` part so we treat ambiguity as nick. See #247.
                     match pfx {
                         Server(_) => {
+                            // Server notices are informational, they don't highlight the tab
                             let msg_target = MsgTarget::Server { serv };
-                            ui.add_privmsg(serv, &msg, ts, &msg_target, false, is_action);
-                            if target == client.get_nick() {
-                                ui.set_tab_style(TabStyle::Highlight, &msg_target);
-                            } else {
-                                ui.set_tab_style(TabStyle::NewMsg, &msg_target);
-                            }
+                            ui.add_server_notice(&format!("[server] {msg}"), ts, &msg_target);
+                            ui.set_tab_style(TabStyle::NewMsg, &msg_target);
                         }
                         User { ref nick, .. } | Ambiguous(ref nick) => {
-                            if target == client.get_nick() {
+                            if is_our_nick(client, &target)
+                                && nick.starts_with(bouncer_control_prefix(ui, serv))
+                            {
+                                // Message from a bouncer control user (e.g. ZNC's `*status`),
+                                // usually a reply to our command or a playback notice. These
+                                // don't trigger notifications or mark the tab as unread.
+                                let msg_target = MsgTarget::User { serv, nick };
+                                ui.add_playback_privmsg(
+                                    nick,
+                                    &msg,
+                                    ts,
+                                    &msg_target,
+                                    false,
+                                    is_action,
+                                );
+                                ui.set_tab_style(TabStyle::JoinOrPart, &msg_target);
+                            } else if is_our_nick(client, &target) {
                                 // Message is sent to us. Show NOTICE messages in server tabs if we
                                 // don't have a tab for the sender already (see #21).
                                 let msg_target = if is_notice && !ui.user_tab_exists(serv, nick) {
@@ -232,8 +941,10 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                                 } else {
                                     MsgTarget::User { serv, nick }
                                 };
-                                ui.add_privmsg(nick, &msg, ts, &msg_target, false, is_action);
+                                add_privmsg(nick, &msg_target, false);
                                 ui.set_tab_style(TabStyle::Highlight, &msg_target);
+                                // The tab may be new, show the away status we know of
+                                ui.set_nick_away(serv, nick, client.is_away(nick));
                             } else {
                                 // PRIVMSG not sent to us. This case can happen in a few cases:
                                 //
@@ -244,7 +955,8 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                                 //       <our_nick> PRIVMSG <target> :...
                                 //
                                 //   In this case (when the sender is us) we show the message in
-                                //   the target's tab and our nick as the sender.
+                                //   the target's tab and our nick as the sender. Messages we send
+                                //   are also echoed back this way with IRCv3 `echo-message`.
                                 //
                                 // - When the message target is a "host mask" (e.g. message was
                                 //   sent to all users matching a mask), see #278. Example:
@@ -260,34 +972,21 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                                         // The target is probably a host mask. Show the message in
                                         // the server tab.
                                         let msg_target = MsgTarget::Server { serv };
-                                        ui.add_privmsg(
-                                            sender,
-                                            &msg,
-                                            ts,
-                                            &msg_target,
-                                            false,
-                                            is_action,
-                                        );
+                                        add_privmsg(sender, &msg_target, false);
                                         ui.set_tab_style(TabStyle::Highlight, &msg_target);
                                     }
                                     User { ref nick, .. } | Ambiguous(ref nick) => {
-                                        if nick == &client.get_nick() {
+                                        if is_our_nick(client, nick) {
                                             // Case (1). Don't highlight the tab as `Highlight`:
                                             // the message was sent by us so the tab probably
                                             // doesn't need that much attention. Highlight as
                                             // `NewMsg` instead.
-                                            let msg_target = MsgTarget::User {
+                                            let msg_target = privmsg_target(
                                                 serv,
-                                                nick: &target,
-                                            };
-                                            ui.add_privmsg(
-                                                &client.get_nick(),
-                                                &msg,
-                                                ts,
-                                                &msg_target,
-                                                false,
-                                                is_action,
+                                                &target,
+                                                &client.get_isupport(),
                                             );
+                                            add_privmsg(&client.get_nick(), &msg_target, false);
                                             // Don't highlight the tab as `Highlight`: the message was sent by us so
                                             // the tab probably doesn't need that much attention. Highlight as `NewMsg`
                                             // instead.
@@ -295,14 +994,7 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                                         } else {
                                             // Case (2)
                                             let msg_target = MsgTarget::User { serv, nick };
-                                            ui.add_privmsg(
-                                                nick,
-                                                &msg,
-                                                ts,
-                                                &msg_target,
-                                                false,
-                                                is_action,
-                                            );
+                                            add_privmsg(nick, &msg_target, false);
                                             ui.set_tab_style(TabStyle::Highlight, &msg_target);
                                         }
                                     }
@@ -314,29 +1006,41 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             }
         }
 
-        JOIN { chan } => {
+        JOIN { chan, account } => {
             let nick = match pfx {
                 Some(User { nick, .. }) | Some(Ambiguous(nick)) => nick,
                 Some(Server(_)) | None => {
                     debug!(
                         "JOIN with weird prefix: pfx={:?}, cmd={:?}",
                         pfx,
-                        JOIN { chan }
+                        JOIN { chan, account }
                     );
                     return;
                 }
             };
 
-            if nick == client.get_nick() {
+            if is_our_nick(client, &nick) {
                 ui.new_chan_tab(serv, &chan);
             } else {
                 let nick = wire::drop_nick_prefix(&nick);
                 let ts = Some(time::now());
-                ui.add_nick(nick, ts, &MsgTarget::Chan { serv, chan: &chan });
+                // Without a `netjoin` batch, joins of users lost in a recent netsplit are netjoins
+                let timeout = ui
+                    .get_tab_config(serv, None)
+                    .netsplit_timeout
+                    .unwrap_or(DEFAULT_NETSPLIT_TIMEOUT_SECS);
+                let netsplit_servers =
+                    netsplits.join(nick, &chan, Instant::now(), Duration::from_secs(timeout));
+                let netjoin_servers = netjoin_servers.or(netsplit_servers);
+                let add_nick = |target: &MsgTarget| match &netjoin_servers {
+                    Some(servers) => ui.netjoin(nick, servers, ts, target),
+                    None => ui.add_nick(nick, ts, target),
+                };
+                add_nick(&MsgTarget::Chan { serv, chan: &chan });
                 // Also update the private message tab if it exists
                 // Nothing will be shown if the user already known to be online by the tab
                 if ui.user_tab_exists(serv, nick) {
-                    ui.add_nick(nick, ts, &MsgTarget::User { serv, nick });
+                    add_nick(&MsgTarget::User { serv, nick });
                 }
                 ui.set_tab_style(TabStyle::JoinOrPart, &MsgTarget::Chan { serv, chan: &chan })
             }
@@ -354,7 +1058,7 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                     return;
                 }
             };
-            if nick != client.get_nick() {
+            if !is_our_nick(client, &nick) {
                 ui.remove_nick(
                     &nick,
                     Some(time::now()),
@@ -364,6 +1068,58 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             }
         }
 
+        KICK { chan, nick, msg } => {
+            let kicker = match pfx {
+                Some(User { nick, .. }) | Some(Ambiguous(nick)) | Some(Server(nick)) => nick,
+                None => {
+                    debug!("KICK without prefix: {:?}", KICK { chan, nick, msg });
+                    return;
+                }
+            };
+            let reason = msg.map(|msg| format!(" ({msg})")).unwrap_or_default();
+            let chan_target = MsgTarget::Chan { serv, chan: &chan };
+
+            if is_our_nick(client, &nick) {
+                ui.add_err_msg(
+                    &format!("You were kicked by {kicker}{reason}"),
+                    ts,
+                    &chan_target,
+                );
+                ui.set_tab_style(TabStyle::Highlight, &chan_target);
+
+                let config = ui.get_tab_config(serv, Some(&chan));
+                if config.rejoin_on_kick == Some(true) {
+                    let delay = config.rejoin_delay.unwrap_or(DEFAULT_REJOIN_DELAY_SECS);
+                    if let Some(delay) =
+                        kick_rejoins.add(&chan, Instant::now(), Duration::from_secs(delay))
+                    {
+                        ui.add_client_msg(
+                            &format!("Rejoining in {} seconds...", delay.as_secs()),
+                            &chan_target,
+                        );
+                        client.rejoin_after(&chan, delay);
+                    } else {
+                        ui.add_client_err_msg(
+                            &format!(
+                                "Not rejoining: rejoined {} times in the last {} minutes",
+                                MAX_KICK_REJOINS,
+                                KICK_REJOIN_WINDOW.as_secs() / 60
+                            ),
+                            &chan_target,
+                        );
+                    }
+                }
+            } else {
+                ui.add_msg(
+                    &format!("{nick} was kicked by {kicker}{reason}"),
+                    ts,
+                    &chan_target,
+                );
+                ui.remove_nick(&nick, Some(ts), &chan_target);
+                ui.set_tab_style(TabStyle::JoinOrPart, &chan_target);
+            }
+        }
+
         QUIT { chans, msg } => {
             let nick = match pfx {
                 Some(User { ref nick, .. }) | Some(Ambiguous(ref nick)) => nick,
@@ -377,14 +1133,117 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 }
             };
 
+            // Quits in a `netsplit` batch are netsplit quits regardless of the reason
+            let netsplit_servers = netsplit_servers
+                .as_deref()
+                .or_else(|| msg.as_deref().filter(|reason| is_netsplit_reason(reason)));
+            if let Some(servers) = netsplit_servers {
+                netsplits.quit(nick, servers, &chans, Instant::now());
+            }
+            let remove_nick = |target: &MsgTarget| match netsplit_servers {
+                Some(servers) => ui.netsplit_quit(nick, servers, Some(time::now()), target),
+                None => ui.remove_nick(nick, Some(time::now()), target),
+            };
             for chan in &chans {
-                ui.remove_nick(nick, Some(time::now()), &MsgTarget::Chan { serv, chan });
+                remove_nick(&MsgTarget::Chan { serv, chan });
             }
             if ui.user_tab_exists(serv, nick) {
-                ui.remove_nick(nick, Some(time::now()), &MsgTarget::User { serv, nick });
+                remove_nick(&MsgTarget::User { serv, nick });
+                ui.set_nick_away(serv, nick, false);
             }
         }
 
+        // IRCv3 batches are tracked above
+        BATCH { .. } => {}
+
+        // IRCv3 `+typing` notifications. Other client tags are ignored.
+        TAGMSG { target } => {
+            let (Some(state), Some(User { nick, .. } | Ambiguous(nick))) = (typing, pfx) else {
+                return;
+            };
+            if is_playback || is_our_nick(client, &nick) {
+                return;
+            }
+            match target {
+                wire::MsgTarget::Chan(chan) => {
+                    ui.set_typing(&nick, state, &MsgTarget::Chan { serv, chan: &chan });
+                }
+                wire::MsgTarget::User(_) => {
+                    ui.set_typing(&nick, state, &MsgTarget::User { serv, nick: &nick });
+                }
+            }
+        }
+
+        // IRCv3 `account-notify`: Show the change in the user tab. Channel states are updated by
+        // `libtiny_client`.
+        ACCOUNT { account } => match pfx {
+            Some(User { ref nick, .. }) | Some(Ambiguous(ref nick)) => {
+                if ui.user_tab_exists(serv, nick) {
+                    let msg = match account {
+                        Some(account) => format!("{nick} logged in as {account}"),
+                        None => format!("{nick} logged out"),
+                    };
+                    ui.add_client_msg(&msg, &MsgTarget::User { serv, nick });
+                }
+            }
+            Some(Server(_)) | None => {}
+        },
+
+        // IRCv3 `away-notify`: Update away status of the user in the user tab. Channel states are
+        // updated by `libtiny_client`.
+        AWAY { msg, .. } => match pfx {
+            Some(User { ref nick, .. }) | Some(Ambiguous(ref nick)) => {
+                ui.set_nick_away(serv, nick, msg.is_some());
+            }
+            Some(Server(_)) | None => {}
+        },
+
+        // IRCv3 `chghost`: Show the new host in channels of the user, unless joins and parts are
+        // ignored in the channel. Channel states are updated by `libtiny_client`.
+        CHGHOST { user, host, chans } => match pfx {
+            Some(User { ref nick, .. }) | Some(Ambiguous(ref nick)) => {
+                let ts = time::now();
+                for chan in &chans {
+                    if ui.get_tab_config(serv, Some(chan)).ignore == Some(true) {
+                        continue;
+                    }
+                    let target = MsgTarget::Chan { serv, chan };
+                    ui.add_msg(&format!("{nick} is now {user}@{host}"), ts, &target);
+                    ui.set_tab_style(TabStyle::JoinOrPart, &target);
+                }
+            }
+            Some(Server(_)) | None => {}
+        },
+
+        // IRCv3 `setname`: Show the new real name in channels of the user, unless joins and parts
+        // are ignored in the channel. Our own real name change is shown in the server tab.
+        // Channel states and our real name for reconnects are updated by `libtiny_client`.
+        SETNAME { realname, chans } => match pfx {
+            Some(User { ref nick, .. }) | Some(Ambiguous(ref nick)) => {
+                if is_our_nick(client, nick) {
+                    ui.add_client_msg(
+                        &format!("Real name changed to {realname}"),
+                        &MsgTarget::Server { serv },
+                    );
+                    return;
+                }
+                let ts = time::now();
+                for chan in &chans {
+                    if ui.get_tab_config(serv, Some(chan)).ignore == Some(true) {
+                        continue;
+                    }
+                    let target = MsgTarget::Chan { serv, chan };
+                    ui.add_msg(
+                        &format!("{nick} changed real name to {realname}"),
+                        ts,
+                        &target,
+                    );
+                    ui.set_tab_style(TabStyle::JoinOrPart, &target);
+                }
+            }
+            Some(Server(_)) | None => {}
+        },
+
         NICK { nick, chans } => {
             let old_nick = match pfx {
                 Some(User { nick, .. }) | Some(Ambiguous(nick)) => nick,
@@ -417,6 +1276,14 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                     },
                 );
             }
+            // Our nick is updated by the client before this message is handled. Show the change
+            // as it may be done by the server or services (e.g. renaming to a guest nick).
+            if is_our_nick(client, &nick) {
+                ui.add_client_msg(
+                    &format!("You are now known as {nick}"),
+                    &MsgTarget::Server { serv },
+                );
+            }
         }
 
         Reply { num: 433, .. } => {
@@ -444,10 +1311,63 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             ui.set_topic(&topic, time::now(), serv, &chan);
         }
 
+        // Invitations to channels. `/join` in the server tab joins the channel of the last one.
+        INVITE { nick, chan } => {
+            if let Some(User { nick: from, .. } | Ambiguous(from)) = &pfx
+                && is_our_nick(client, &nick)
+            {
+                let msg_target = MsgTarget::Server { serv };
+                ui.add_msg(
+                    &format!(
+                        "{from} invited you to {}, use `/join` in this tab to join",
+                        chan.display()
+                    ),
+                    ts,
+                    &msg_target,
+                );
+                ui.set_tab_style(TabStyle::Highlight, &msg_target);
+                let mentions_target = MsgTarget::Server { serv: "mentions" };
+                ui.add_msg(
+                    &format!("{from} invited you to {serv}:{}", chan.display()),
+                    ts,
+                    &mentions_target,
+                );
+                ui.set_tab_style(TabStyle::Highlight, &mentions_target);
+            }
+        }
+
+        // WALLOPS are shown in the server tab, and also in the mentions tab with
+        // `wallops_mentions`. Like server notices, they don't highlight the server tab.
+        WALLOPS { msg } => {
+            let sender = match &pfx {
+                Some(Server(sender))
+                | Some(User { nick: sender, .. })
+                | Some(Ambiguous(sender)) => sender,
+                None => serv,
+            };
+            let msg_target = MsgTarget::Server { serv };
+            ui.add_server_notice(&format!("[wallops] {sender}: {msg}"), ts, &msg_target);
+            ui.set_tab_style(TabStyle::NewMsg, &msg_target);
+            if ui
+                .get_tab_config(serv, None)
+                .wallops_mentions
+                .unwrap_or(false)
+            {
+                let mentions_target = MsgTarget::Server { serv: "mentions" };
+                ui.add_server_notice(
+                    &format!("[wallops] {sender} in {serv}: {msg}"),
+                    ts,
+                    &mentions_target,
+                );
+                ui.set_tab_style(TabStyle::Highlight, &mentions_target);
+            }
+        }
+
         CAP {
             client: _,
             subcommand,
             params,
+            more,
         } => match subcommand.as_ref() {
             "NAK" => {
                 if params.iter().any(|cap| cap.as_str() == "sasl") {
@@ -459,8 +1379,14 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                     );
                 }
             }
+            // Multiline reply, the list is complete after the last line
+            "LS" if more => {}
             "LS" => {
-                if !params.iter().any(|cap| cap.as_str() == "sasl") {
+                let caps = client.get_server_caps();
+                if !caps
+                    .iter()
+                    .any(|cap| cap == "sasl" || cap.starts_with("sasl="))
+                {
                     let msg_target = MsgTarget::Server { serv };
                     ui.add_err_msg(
                         "Server does not support SASL authentication",
@@ -470,6 +1396,22 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 }
             }
             "ACK" => {}
+            "NEW" | "DEL" => {
+                let caps: Vec<&str> = params
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|cap| !cap.is_empty())
+                    .collect();
+                let change = if subcommand == "NEW" {
+                    "added"
+                } else {
+                    "removed"
+                };
+                ui.add_client_msg(
+                    &format!("Server {change} capabilities: {}", caps.join(" ")),
+                    &MsgTarget::Server { serv },
+                );
+            }
             cmd => {
                 debug!("Ignoring CAP subcommand {cmd}: params={params:?}");
             }
@@ -479,12 +1421,40 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             // Ignore
         }
 
+        Reply {
+            num: 904..=906,
+            params,
+        } => {
+            // ERR_SASLFAIL, ERR_SASLTOOLONG, ERR_SASLABORTED
+            ui.add_err_msg(
+                &format!(
+                    "SASL authentication failed: {}",
+                    params.last().map(String::as_str).unwrap_or("")
+                ),
+                time::now(),
+                &MsgTarget::Server { serv },
+            );
+        }
+
         Reply { num: n, params } => {
+            let tab_config = ui.get_tab_config(serv, None);
+            let hide = match n {
+                // RPL_MOTD, RPL_MOTDSTART, RPL_ENDOFMOTD, ERR_NOMOTD
+                372 | 375 | 376 | 422 => tab_config.hide_motd,
+                // RPL_STATSCONN, RPL_LUSERCLIENT, RPL_LUSEROP, RPL_LUSERUNKNOWN,
+                // RPL_LUSERCHANNELS, RPL_LUSERME, RPL_LOCALUSERS, RPL_GLOBALUSERS
+                250..=255 | 265 | 266 => tab_config.hide_lusers,
+                _ => None,
+            };
+            if hide == Some(true) {
+                return;
+            }
+
             let n_params = params.len();
-            if (
+            if let Some(line) = lusers_line(n, &params) {
+                ui.add_msg(&line, time::now(), &MsgTarget::Server { serv });
+            } else if (
                 n <= 003 // RPL_WELCOME, RPL_YOURHOST, RPL_CREATED
-                    || n == 251 // RPL_LUSERCLIENT
-                    || n == 255 // RPL_LUSERME
                     || n == 372 // RPL_MOTD
                     || n == 375 // RPL_MOTDSTART
                     || n == 376
@@ -494,15 +1464,15 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 let msg = &params[1];
                 ui.add_msg(msg, time::now(), &MsgTarget::Server { serv });
             } else if n == 4 // RPL_MYINFO
-                    || n == 5 // RPL_BOUNCE
-                    || (252..=254).contains(&n)
-            // RPL_LUSEROP, RPL_LUSERUNKNOWN, RPL_LUSERCHANNELS
+                    || n == 5
+            // RPL_BOUNCE
             {
+                if n == 5 {
+                    // RPL_ISUPPORT: Compare nicks in the tabs of the server as the server does
+                    ui.set_case_mapping(serv, client.get_isupport().case_mapping());
+                }
                 let msg = params.into_iter().collect::<Vec<String>>().join(" ");
                 ui.add_msg(&msg, time::now(), &MsgTarget::Server { serv });
-            } else if (n == 265 || n == 266 || n == 250) && n_params > 0 {
-                let msg = &params[n_params - 1];
-                ui.add_msg(msg, time::now(), &MsgTarget::Server { serv });
             }
             // RPL_TOPIC
             else if n == 332 && (n_params == 3 || n_params == 2) {
@@ -512,25 +1482,82 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 let topic = &params[n_params - 1];
                 ui.set_topic(topic, time::now(), serv, ChanNameRef::new(chan));
             }
+            // RPL_TOPICWHOTIME: Who set the topic and when, sent after RPL_TOPIC
+            else if n == 333 && n_params > 3 {
+                let chan = ChanNameRef::new(&params[1]);
+                let msg = topic_set_line(&params[2], params[3].parse().ok());
+                ui.add_client_msg(&msg, &MsgTarget::Chan { serv, chan });
+            }
+            // RPL_CREATIONTIME
+            else if n == 329 && n_params > 2 {
+                let chan = ChanNameRef::new(&params[1]);
+                if let Ok(created) = params[2].parse() {
+                    ui.add_client_msg(
+                        &format!("Channel created on {}", format_unix_time(created)),
+                        &MsgTarget::Chan { serv, chan },
+                    );
+                }
+            }
             // RPL_NAMREPLY: List of users in a channel
             else if n == 353 && n_params > 3 {
-                let chan = &params[2];
-                let chan_target = MsgTarget::Chan {
-                    serv,
-                    chan: ChanNameRef::new(chan),
-                };
-
-                for nick in params[3].split_whitespace() {
-                    ui.add_nick(wire::drop_nick_prefix(nick), None, &chan_target);
+                let chan = ChanNameRef::new(&params[2]);
+                let isupport = client.get_isupport();
+                let nicks = params[3]
+                    .split_whitespace()
+                    .map(|name| isupport.names_nick(name));
+                if let Some(nicks) = names.add(chan, nicks) {
+                    ui.add_nicks(&nicks, &MsgTarget::Chan { serv, chan });
                 }
             }
             // RPL_ENDOFNAMES: End of NAMES list
-            else if n == 366 {
+            else if n == 366 && n_params > 1 {
+                let chan = ChanNameRef::new(&params[1]);
+                let nicks = names.finish(chan);
+                ui.add_nicks(&nicks, &MsgTarget::Chan { serv, chan });
             }
-            // RPL_UNAWAY or RPL_NOWAWAY
+            // RPL_UNAWAY or RPL_NOWAWAY. Servers also send RPL_UNAWAY when they unset away status
+            // automatically, e.g. when we send a message.
             else if (n == 305 || n == 306) && n_params > 1 {
                 let msg = &params[1];
                 ui.add_client_msg(msg, &MsgTarget::AllServTabs { serv });
+                ui.set_away(serv, n == 306);
+            }
+            // RPL_ISON: Reply to `/ison`, lists the nicks that are online
+            else if n == 303 && n_params > 1 {
+                let online: Vec<&str> = params[1].split_whitespace().collect();
+                let msg = if online.is_empty() {
+                    "None of the nicks are online".to_owned()
+                } else {
+                    format!("Online: {}", online.join(", "))
+                };
+                ui.add_client_msg(&msg, &MsgTarget::CurrentTab);
+            }
+            // RPL_VERSION: Reply to `/version`. Parameters are our nick, version, server, and
+            // comments.
+            else if n == 351 && n_params > 2 {
+                let version = &params[1];
+                let server = &params[2];
+                let msg = match params.get(3) {
+                    Some(comments) if !comments.is_empty() => {
+                        format!("{server} is running {version} ({comments})")
+                    }
+                    _ => format!("{server} is running {version}"),
+                };
+                ui.add_client_msg(&msg, &MsgTarget::Server { serv });
+            }
+            // RPL_TIME: Reply to `/time`. Parameters are our nick, server, optionally a timestamp
+            // and offset, and the time as a string.
+            else if n == 391 && n_params > 2 {
+                let server = &params[1];
+                let time = &params[n_params - 1];
+                ui.add_client_msg(
+                    &format!("Time at {server}: {time}"),
+                    &MsgTarget::Server { serv },
+                );
+            }
+            // Replies to `/stats` and `/admin`
+            else if let Some(line) = stats_line(n, &params).or_else(|| admin_line(n, &params)) {
+                ui.add_client_msg(&line, &MsgTarget::Server { serv });
             }
             // ERR_NOSUCHNICK
             else if n == 401 && n_params > 2 {
@@ -545,6 +1572,15 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                     &format!("{nick} is away: {msg}"),
                     &MsgTarget::User { serv, nick },
                 );
+            // WHOIS replies
+            } else if let Some(line) = whois_line(n, &params) {
+                ui.add_client_msg(&line, &MsgTarget::Server { serv });
+            // WHO replies to `/who`. Replies to WHOs sent by the client after joining a channel are
+            // not reported.
+            } else if let Some(line) = who_line(n, &params) {
+                ui.add_client_msg(&line, &MsgTarget::CurrentTab);
+            } else if n == 318 {
+                // RPL_ENDOFWHOIS: Nothing to show
             } else {
                 match pfx {
                     Some(Server(msg_serv)) | Some(Ambiguous(msg_serv)) => {
@@ -566,6 +1602,16 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             }
         }
 
+        // IRCv3 standard reply when the server rejects a `SETNAME`:
+        // FAIL SETNAME <code> :<description>
+        Other { cmd, params } if cmd == "FAIL" && params.len() > 2 && params[0] == "SETNAME" => {
+            ui.add_err_msg(
+                &format!("Could not change real name: {}", params[params.len() - 1]),
+                time::now(),
+                &MsgTarget::Server { serv },
+            );
+        }
+
         Other { cmd, params } => match pfx {
             Some(Server(msg_serv)) => {
                 let msg_target = MsgTarget::Server { serv };
@@ -591,10 +1637,14 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
 /// This takes IRC nick syntax into account (following [1]) when checking match boundaries, to
 /// avoid generating highlights incorrectly. For example, if the nick is "abc" and the messsage is
 /// "abcd" we don't consider that a mention, but we consider it a mention in message "abc: hi".
+/// Nicks are compared according to the server's case mapping.
 ///
 /// [1]: https://modern.ircdocs.horse/#clients
-fn mentions_user(msg: &str, nick: &str) -> bool {
-    for (match_idx, _) in msg.match_indices(nick) {
+fn mentions_user(msg: &str, nick: &str, case_mapping: CaseMapping) -> bool {
+    // Case mappings only map ASCII characters, so indices in the lowercase message are the same
+    let msg = &case_mapping.to_lower(msg);
+    let nick = &case_mapping.to_lower(nick);
+    for (match_idx, _) in msg.match_indices(nick.as_str()) {
         if check_nick_left_boundary(msg, match_idx)
             && check_nick_right_boundary(msg, match_idx + nick.len())
         {
@@ -623,13 +1673,661 @@ fn check_nick_right_boundary(msg: &str, idx: usize) -> bool {
     )
 }
 
+/// Whether a QUIT message `reason` is the one servers send for users lost in a netsplit: names of
+/// the two servers that split, e.g. "irc.example.net irc2.example.net".
+fn is_netsplit_reason(reason: &str) -> bool {
+    fn is_server_name(s: &str) -> bool {
+        s.contains('.')
+            && !s.starts_with('.')
+            && !s.ends_with('.')
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '*'))
+    }
+
+    let mut words = reason.split(' ');
+    match (words.next(), words.next(), words.next()) {
+        (Some(serv1), Some(serv2), None) => {
+            serv1 != serv2 && is_server_name(serv1) && is_server_name(serv2)
+        }
+        _ => false,
+    }
+}
+
+/// Format seconds since Unix epoch as local time.
+pub(crate) fn format_unix_time(secs: i64) -> String {
+    time::strftime("%F %T", &time::at(time::Timespec::new(secs, 0))).unwrap()
+}
+
+/// Reply to a CTCP request, `None` when replies to the request type are disabled or the type is
+/// not supported. `args` is the request payload.
+fn ctcp_reply(
+    settings: &CtcpReplySettings,
+    ctcp: &wire::CTCP,
+    args: &str,
+    now: time::Tm,
+) -> Option<String> {
+    match ctcp {
+        wire::CTCP::Version => settings.version_reply(),
+        wire::CTCP::Time if settings.time => Some(now.ctime().to_string()),
+        wire::CTCP::Ping if settings.ping => Some(args.to_owned()),
+        wire::CTCP::ClientInfo if settings.clientinfo => {
+            let mut types = vec!["ACTION", "CLIENTINFO"];
+            if settings.ping {
+                types.push("PING");
+            }
+            if settings.time {
+                types.push("TIME");
+            }
+            if settings.version {
+                types.push("VERSION");
+            }
+            Some(types.join(" "))
+        }
+        _ => None,
+    }
+}
+
+/// Who set a topic and when, from RPL_TOPICWHOTIME or `Client::get_topic`. `set_by` can be a
+/// nick or `nick!user@host`, we only show the nick.
+pub(crate) fn topic_set_line(set_by: &str, set_at: Option<i64>) -> String {
+    let nick = set_by.split('!').next().unwrap_or(set_by);
+    match set_at {
+        Some(set_at) => format!("Topic set by {nick} on {}", format_unix_time(set_at)),
+        None => format!("Topic set by {nick}"),
+    }
+}
+
+/// Format a WHOIS reply to show in the server tab. Returns `None` if the message is not a WHOIS
+/// reply, or it's RPL_ENDOFWHOIS. WHOIS replies that we don't format are shown verbatim, without
+/// our nick.
+fn whois_line(n: u16, params: &[String]) -> Option<String> {
+    match (n, params) {
+        // RPL_WHOISUSER
+        (311, [_, nick, user, host, _, realname]) => {
+            Some(format!("{nick} is {user}@{host} ({realname})"))
+        }
+        // RPL_WHOISSERVER
+        (312, [_, nick, server, info]) => Some(format!("{nick} is connected to {server} ({info})")),
+        // RPL_WHOISIDLE. Sign-on time is not sent by all servers.
+        (317, [_, nick, idle, rest @ ..]) => {
+            let signon = match rest {
+                [signon, _] => signon.parse::<i64>().ok(),
+                _ => None,
+            };
+            Some(match signon {
+                Some(signon) => format!(
+                    "{nick} has been idle for {idle} seconds, signed on at {}",
+                    format_unix_time(signon)
+                ),
+                None => format!("{nick} has been idle for {idle} seconds"),
+            })
+        }
+        // RPL_WHOISCHANNELS
+        (319, [_, nick, chans]) => Some(format!("{nick} is on {}", chans.trim())),
+        // RPL_WHOISACCOUNT
+        (330, [_, nick, account, ..]) => Some(format!("{nick} is logged in as {account}")),
+        // RPL_WHOISACTUALLY. Servers send the real host, IP, or both.
+        (338, [_, nick, hosts @ .., _]) if !hosts.is_empty() => {
+            Some(format!("{nick} is actually using host {}", hosts.join(" ")))
+        }
+        // RPL_WHOISSECURE
+        (671, [_, nick, ..]) => Some(format!("{nick} is using a secure connection")),
+        // RPL_WHOISCERTFP, RPL_WHOISREGNICK, RPL_WHOISHELPOP, RPL_WHOISOPERATOR,
+        // RPL_WHOISSPECIAL, RPL_WHOISBOT, RPL_WHOISHOST, RPL_WHOISMODES
+        (276 | 307 | 310 | 313 | 320 | 335 | 378 | 379, [_, rest @ ..]) if rest.len() > 1 => {
+            Some(rest.join(" "))
+        }
+        _ => None,
+    }
+}
+
+/// Format a WHO reply (RPL_WHOREPLY, RPL_ENDOFWHO). Returns `None` if the message is not a WHO
+/// reply.
+fn who_line(n: u16, params: &[String]) -> Option<String> {
+    match (n, params) {
+        // :server 352 <me> <chan> <user> <host> <server> <nick> <flags> :<hops> <realname>
+        (352, [_, chan, user, host, _, nick, flags, hops_realname]) => {
+            let realname = hops_realname
+                .split_once(' ')
+                .map(|(_hops, realname)| realname)
+                .unwrap_or_default();
+            let mut line = if chan == "*" {
+                format!("{nick} is {user}@{host}")
+            } else {
+                format!("{chan}: {nick} is {user}@{host}")
+            };
+            if !realname.is_empty() {
+                line.push_str(&format!(" ({realname})"));
+            }
+            // Flags start with 'G' (gone) when the user is away, 'H' (here) otherwise, followed
+            // by e.g. '*' for IRC operators and the channel prefix of the user
+            let flags = match flags.strip_prefix('G') {
+                Some(flags) => {
+                    line.push_str(", away");
+                    flags
+                }
+                None => flags.strip_prefix('H').unwrap_or(flags),
+            };
+            if !flags.is_empty() {
+                line.push_str(&format!(" [{flags}]"));
+            }
+            Some(line)
+        }
+        // RPL_ENDOFWHO
+        (315, [_, mask, ..]) => Some(format!("End of WHO list for {mask}")),
+        _ => None,
+    }
+}
+
+/// Format a STATS reply. Replies that are not formatted specially (e.g. server configuration lines
+/// like RPL_STATSKLINE) are shown as sent. Returns `None` if the message is not a STATS reply.
+/// RPL_STATSCONN (250) is also sent after connecting, and is handled by `lusers_line`.
+fn stats_line(n: u16, params: &[String]) -> Option<String> {
+    match (n, params) {
+        // RPL_STATSLINKINFO
+        (
+            211,
+            [
+                _,
+                link,
+                sendq,
+                sent_msgs,
+                sent_kb,
+                recv_msgs,
+                recv_kb,
+                time_open,
+                ..,
+            ],
+        ) => Some(format!(
+            "Link {link}: sendq {sendq}, sent {sent_msgs} messages ({sent_kb} KB), \
+                 received {recv_msgs} messages ({recv_kb} KB), open for {time_open} seconds"
+        )),
+        // RPL_STATSCOMMANDS
+        (212, [_, cmd, count, ..]) => Some(format!("{cmd}: used {count} times")),
+        // RPL_ENDOFSTATS
+        (219, [_, query, ..]) => Some(format!("End of STATS {query} report")),
+        // RPL_STATSUPTIME
+        (242, [_, .., msg]) => Some(msg.clone()),
+        // RPL_STATSOLINE
+        (243, [_, _, hostmask, _, name, ..]) => Some(format!("Operator {name} from {hostmask}")),
+        // Other RPL_STATS* replies
+        (213..=218 | 240 | 241 | 244..=249, [_, rest @ ..]) if !rest.is_empty() => {
+            Some(rest.join(" "))
+        }
+        _ => None,
+    }
+}
+
+/// Format an ADMIN reply. Returns `None` if the message is not an ADMIN reply.
+fn admin_line(n: u16, params: &[String]) -> Option<String> {
+    match (n, params) {
+        // RPL_ADMINME
+        (256, [_, .., msg]) => Some(msg.clone()),
+        // RPL_ADMINLOC1, RPL_ADMINLOC2
+        (257 | 258, [_, .., info]) if !info.is_empty() => Some(format!("Location: {info}")),
+        // RPL_ADMINEMAIL
+        (259, [_, .., email]) if !email.is_empty() => Some(format!("Email: {email}")),
+        _ => None,
+    }
+}
+
+/// Format a LUSERS reply, sent after connecting and for `/lusers`. Returns `None` if the message is
+/// not a LUSERS reply.
+fn lusers_line(n: u16, params: &[String]) -> Option<String> {
+    match (n, params) {
+        // RPL_STATSCONN, RPL_LUSERCLIENT, RPL_LUSERME
+        (250 | 251 | 255, [_, .., msg]) => Some(msg.clone()),
+        // RPL_LUSEROP
+        (252, [_, n_ops, _]) => Some(format!("Operators online: {n_ops}")),
+        // RPL_LUSERUNKNOWN
+        (253, [_, n_unknown, _]) => Some(format!("Unknown connections: {n_unknown}")),
+        // RPL_LUSERCHANNELS
+        (254, [_, n_chans, _]) => Some(format!("Channels formed: {n_chans}")),
+        // RPL_LOCALUSERS, RPL_GLOBALUSERS. Current and max. user counts are not sent by all
+        // servers.
+        (265 | 266, [_, rest @ ..]) => {
+            let scope = if n == 265 { "Local" } else { "Global" };
+            match rest {
+                [current, max, _] => Some(format!("{scope} users: {current} (max {max})")),
+                [.., msg] => Some(msg.clone()),
+                [] => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Show a LUSERS reply received with IRCv3 `labeled-response`.
+pub(crate) fn show_lusers_reply(ui: &UI, msgs: &[wire::Msg], target: &MsgTarget) {
+    for msg in msgs {
+        if let wire::Cmd::Reply { num, params } = &msg.cmd
+            && let Some(line) = lusers_line(*num, params)
+        {
+            ui.add_msg(&line, time::now(), target);
+        }
+    }
+}
+
+/// Show a WHOIS or WHO reply received with IRCv3 `labeled-response` in the tab the `/whois` or
+/// `/who` command was run in.
+pub(crate) fn show_whois_reply(ui: &UI, msgs: &[wire::Msg], target: &MsgTarget) {
+    for msg in msgs {
+        match &msg.cmd {
+            // RPL_ENDOFWHOIS
+            wire::Cmd::Reply { num: 318, .. } => {}
+            // RPL_AWAY
+            wire::Cmd::Reply { num: 301, params } if params.len() > 2 => {
+                ui.add_client_msg(&format!("{} is away: {}", params[1], params[2]), target);
+            }
+            wire::Cmd::Reply { num, params } => {
+                if let Some(line) = whois_line(*num, params).or_else(|| who_line(*num, params)) {
+                    ui.add_client_msg(&line, target);
+                    continue;
+                }
+                // First parameter is our nick
+                let params = params.get(1..).unwrap_or_default();
+                if *num >= 400 {
+                    // Errors, e.g. ERR_NOSUCHNICK
+                    ui.add_client_err_msg(&params.join(": "), target);
+                } else {
+                    ui.add_client_msg(&params.join(" "), target);
+                }
+            }
+            // IRCv3 standard replies
+            wire::Cmd::Other { cmd, params } if cmd == "FAIL" => {
+                ui.add_client_err_msg(params.last().map(String::as_str).unwrap_or(cmd), target);
+            }
+            _ => {}
+        }
+    }
+}
+
 #[test]
 fn mention_check() {
-    assert!(!mentions_user("", "abc"));
-    assert!(mentions_user("abc", "abc"));
-    assert!(mentions_user("abc: hi", "abc"));
-    assert!(mentions_user(" abc", "abc"));
-    assert!(mentions_user(" abc,", "abc"));
-    assert!(!mentions_user(" aaaa ", "aa"));
-    assert!(mentions_user(" aa,aa ", "aa"));
+    let mentions = |msg, nick| mentions_user(msg, nick, CaseMapping::default());
+    assert!(!mentions("", "abc"));
+    assert!(mentions("abc", "abc"));
+    assert!(mentions("abc: hi", "abc"));
+    assert!(mentions(" abc", "abc"));
+    assert!(mentions(" abc,", "abc"));
+    assert!(!mentions(" aaaa ", "aa"));
+    assert!(mentions(" aa,aa ", "aa"));
+    assert!(mentions("ABC: hi", "abc"));
+    assert!(mentions("hi foo{}", "Foo[]"));
+    assert!(!mentions_user("hi foo{}", "Foo[]", CaseMapping::Ascii));
+}
+
+#[test]
+fn seen_msgs() {
+    let mut seen_msgs = SeenMsgs {
+        window: 2,
+        ..SeenMsgs::default()
+    };
+    let key = |msgid, time, msg| dedup_key(msgid, time, "alice", msg).unwrap();
+    let t1 = Some("2024-01-01T00:00:00.000Z");
+    let t2 = Some("2024-01-01T00:05:00.000Z");
+
+    assert_eq!(dedup_key(None, None, "alice", "hi"), None);
+
+    assert!(seen_msgs.add("#chan", key(Some("a"), t1, "hi")));
+    assert!(!seen_msgs.add("#chan", key(Some("a"), t2, "hi")));
+    // Same message in another target
+    assert!(seen_msgs.add("#other", key(Some("a"), t1, "hi")));
+    // Without msgid messages are the same when the time, sender and text are the same
+    assert!(seen_msgs.add("#chan", key(None, t1, "hi")));
+    assert!(!seen_msgs.add("#CHAN", key(None, t1, "hi")));
+    assert!(seen_msgs.add("#chan", key(None, t2, "hi")));
+    // Only the last `window` messages are remembered
+    assert!(seen_msgs.add("#chan", key(Some("a"), t1, "hi")));
+
+    let mut seen_msgs = SeenMsgs::default();
+    assert!(seen_msgs.add("#chan", key(Some("a"), t1, "hi")));
+    assert!(seen_msgs.add("#chan", key(Some("a"), t1, "hi")));
+}
+
+#[test]
+fn kick_rejoin_limit() {
+    let mut kick_rejoins = KickRejoins::default();
+    let chan = ChanNameRef::new("#chan");
+    let now = Instant::now();
+    let delay = Duration::from_secs(3);
+    // Delay is doubled after each rejoin
+    for secs in [3, 6, 12] {
+        assert_eq!(
+            kick_rejoins.add(chan, now, delay),
+            Some(Duration::from_secs(secs))
+        );
+    }
+    assert_eq!(kick_rejoins.add(chan, now, delay), None);
+    assert_eq!(
+        kick_rejoins.add(ChanNameRef::new("#other"), now, delay),
+        Some(delay)
+    );
+    assert_eq!(
+        kick_rejoins.add(chan, now + KICK_REJOIN_WINDOW, delay),
+        Some(delay)
+    );
+}
+
+#[test]
+fn netsplit_rejoins() {
+    let mut netsplits = Netsplits::default();
+    let chan = ChanNameRef::new("#chan");
+    let other = ChanNameRef::new("#other");
+    let timeout = Duration::from_secs(60);
+    let now = Instant::now();
+
+    netsplits.quit(
+        "alice",
+        "a.net b.net",
+        &[chan.to_owned(), other.to_owned()],
+        now,
+    );
+    assert_eq!(netsplits.join("bob", chan, now, timeout), None);
+    assert_eq!(
+        netsplits.join("alice", chan, now, timeout),
+        Some("a.net b.net".to_owned())
+    );
+    // Joining the same channel again is a normal join
+    assert_eq!(netsplits.join("alice", chan, now, timeout), None);
+    assert_eq!(
+        netsplits.join("alice", other, now, timeout),
+        Some("a.net b.net".to_owned())
+    );
+
+    // Splits older than the timeout are forgotten
+    netsplits.quit("alice", "a.net b.net", &[chan.to_owned()], now);
+    assert_eq!(netsplits.join("alice", chan, now + timeout, timeout), None);
+}
+
+#[test]
+fn ctcp_replies() {
+    let settings = CtcpReplySettings::default();
+    let now = time::at_utc(time::Timespec::new(0, 0));
+    assert_eq!(
+        ctcp_reply(&settings, &wire::CTCP::Version, "", now),
+        Some(format!("tiny {}", env!("CARGO_PKG_VERSION")))
+    );
+    assert_eq!(
+        ctcp_reply(&settings, &wire::CTCP::Time, "", now).as_deref(),
+        Some("Thu Jan  1 00:00:00 1970")
+    );
+    assert_eq!(
+        ctcp_reply(&settings, &wire::CTCP::Ping, "1234 5678", now).as_deref(),
+        Some("1234 5678")
+    );
+    assert_eq!(
+        ctcp_reply(&settings, &wire::CTCP::ClientInfo, "", now).as_deref(),
+        Some("ACTION CLIENTINFO PING TIME VERSION")
+    );
+    assert_eq!(
+        ctcp_reply(&settings, &wire::CTCP::Other("FINGER".to_owned()), "", now),
+        None
+    );
+
+    let settings = CtcpReplySettings {
+        time: false,
+        version: false,
+        ..settings
+    };
+    assert_eq!(ctcp_reply(&settings, &wire::CTCP::Time, "", now), None);
+    assert_eq!(ctcp_reply(&settings, &wire::CTCP::Version, "", now), None);
+    assert_eq!(
+        ctcp_reply(&settings, &wire::CTCP::ClientInfo, "", now).as_deref(),
+        Some("ACTION CLIENTINFO PING")
+    );
+}
+
+#[test]
+fn ctcp_flood() {
+    let mut ctcp_requests = CtcpRequests::default();
+    let now = Instant::now();
+    for _ in 0..3 {
+        assert_eq!(ctcp_requests.add("bot", now, 3), CtcpRate::Ok);
+    }
+    assert_eq!(ctcp_requests.add("bot", now, 3), CtcpRate::FloodStart);
+    assert_eq!(ctcp_requests.add("bot", now, 3), CtcpRate::Flood);
+    assert_eq!(ctcp_requests.add("other", now, 3), CtcpRate::Ok);
+
+    // Flood ends when the requests in the window drop below the limit, a new flood is reported
+    // again
+    let later = now + CTCP_FLOOD_WINDOW;
+    assert_eq!(ctcp_requests.add("bot", later, 3), CtcpRate::Ok);
+    for _ in 0..2 {
+        assert_eq!(ctcp_requests.add("bot", later, 3), CtcpRate::Ok);
+    }
+    assert_eq!(ctcp_requests.add("bot", later, 3), CtcpRate::FloodStart);
+}
+
+#[test]
+fn names_chunks() {
+    let mut names = NamesBuf::default();
+    let chan = ChanNameRef::new("#chan");
+    let nicks: Vec<String> = (0..NAMES_CHUNK_SIZE).map(|i| format!("nick{i}")).collect();
+    assert_eq!(
+        names.add(chan, nicks[..10].iter().map(String::as_str)),
+        None
+    );
+    assert_eq!(
+        names.add(ChanNameRef::new("#other"), std::iter::once("a")),
+        None
+    );
+    assert_eq!(
+        names.add(chan, nicks[10..].iter().map(String::as_str)),
+        Some(nicks)
+    );
+    assert_eq!(names.add(chan, std::iter::once("b")), None);
+    assert_eq!(names.finish(chan), vec!["b".to_owned()]);
+    assert_eq!(names.finish(chan), Vec::<String>::new());
+    assert_eq!(
+        names.finish(ChanNameRef::new("#other")),
+        vec!["a".to_owned()]
+    );
+}
+
+#[test]
+fn lusers_lines() {
+    let line = |n: u16, params: &[&str]| {
+        let params: Vec<String> = params.iter().map(|s| (*s).to_owned()).collect();
+        lusers_line(n, &params)
+    };
+    assert_eq!(
+        line(
+            251,
+            &["me", "There are 3 users and 2 invisible on 1 servers"]
+        )
+        .as_deref(),
+        Some("There are 3 users and 2 invisible on 1 servers")
+    );
+    assert_eq!(
+        line(252, &["me", "4", "IRC Operators online"]).as_deref(),
+        Some("Operators online: 4")
+    );
+    assert_eq!(
+        line(254, &["me", "12", "channels formed"]).as_deref(),
+        Some("Channels formed: 12")
+    );
+    assert_eq!(
+        line(265, &["me", "5", "9", "Current local users 5, max 9"]).as_deref(),
+        Some("Local users: 5 (max 9)")
+    );
+    assert_eq!(
+        line(266, &["me", "Current global users: 5  Max: 9"]).as_deref(),
+        Some("Current global users: 5  Max: 9")
+    );
+    assert_eq!(line(252, &["me"]), None);
+    assert_eq!(line(311, &["me", "alice"]), None);
+}
+
+#[test]
+fn stats_admin_lines() {
+    let line = |n: u16, params: &[&str]| {
+        let params: Vec<String> = params.iter().map(|s| (*s).to_owned()).collect();
+        stats_line(n, &params).or_else(|| admin_line(n, &params))
+    };
+    assert_eq!(
+        line(212, &["me", "PRIVMSG", "42", "1024", "0"]).as_deref(),
+        Some("PRIVMSG: used 42 times")
+    );
+    assert_eq!(
+        line(219, &["me", "m", "End of /STATS report"]).as_deref(),
+        Some("End of STATS m report")
+    );
+    assert_eq!(
+        line(242, &["me", "Server Up 3 days 1:02:03"]).as_deref(),
+        Some("Server Up 3 days 1:02:03")
+    );
+    assert_eq!(
+        line(243, &["me", "O", "*@host", "*", "alice", "0", "oper"]).as_deref(),
+        Some("Operator alice from *@host")
+    );
+    // Unknown replies are shown as sent
+    assert_eq!(
+        line(216, &["me", "K", "*.example", "*", "spam", "0", "0"]).as_deref(),
+        Some("K *.example * spam 0 0")
+    );
+    assert_eq!(
+        line(
+            256,
+            &["me", "irc.server", "Administrative info about irc.server"]
+        )
+        .as_deref(),
+        Some("Administrative info about irc.server")
+    );
+    assert_eq!(
+        line(257, &["me", "Somewhere"]).as_deref(),
+        Some("Location: Somewhere")
+    );
+    assert_eq!(line(258, &["me", ""]), None);
+    assert_eq!(
+        line(259, &["me", "admin@example.com"]).as_deref(),
+        Some("Email: admin@example.com")
+    );
+    assert_eq!(line(251, &["me", "There are 2 users"]), None);
+}
+
+#[test]
+fn who_lines() {
+    let line = |n: u16, params: &[&str]| {
+        let params: Vec<String> = params.iter().map(|s| (*s).to_owned()).collect();
+        who_line(n, &params)
+    };
+    assert_eq!(
+        line(
+            352,
+            &[
+                "me",
+                "#chan",
+                "~a",
+                "host",
+                "irc.server",
+                "alice",
+                "H@",
+                "0 Alice A"
+            ]
+        )
+        .as_deref(),
+        Some("#chan: alice is ~a@host (Alice A) [@]")
+    );
+    assert_eq!(
+        line(
+            352,
+            &["me", "*", "~b", "host", "irc.server", "bob", "G", "0 Bob"]
+        )
+        .as_deref(),
+        Some("bob is ~b@host (Bob), away")
+    );
+    assert_eq!(
+        line(
+            352,
+            &[
+                "me",
+                "#chan",
+                "~c",
+                "host",
+                "irc.server",
+                "carol",
+                "G*+",
+                "3"
+            ]
+        )
+        .as_deref(),
+        Some("#chan: carol is ~c@host, away [*+]")
+    );
+    assert_eq!(
+        line(315, &["me", "#chan", "End of /WHO list."]).as_deref(),
+        Some("End of WHO list for #chan")
+    );
+    assert_eq!(line(352, &["me", "#chan"]), None);
+}
+
+#[test]
+fn whois_lines() {
+    let line = |n: u16, params: &[&str]| {
+        let params: Vec<String> = params.iter().map(|s| (*s).to_owned()).collect();
+        whois_line(n, &params)
+    };
+    assert_eq!(
+        line(311, &["me", "alice", "~a", "host", "*", "Alice"]).as_deref(),
+        Some("alice is ~a@host (Alice)")
+    );
+    assert_eq!(
+        line(312, &["me", "alice", "irc.server", "Server info"]).as_deref(),
+        Some("alice is connected to irc.server (Server info)")
+    );
+    assert_eq!(
+        line(317, &["me", "alice", "42", "seconds idle"]).as_deref(),
+        Some("alice has been idle for 42 seconds")
+    );
+    assert!(
+        line(
+            317,
+            &["me", "alice", "42", "0", "seconds idle, signon time"]
+        )
+        .unwrap()
+        .starts_with("alice has been idle for 42 seconds, signed on at ")
+    );
+    assert_eq!(
+        line(319, &["me", "alice", "@#a #b "]).as_deref(),
+        Some("alice is on @#a #b")
+    );
+    assert_eq!(
+        line(338, &["me", "alice", "1.2.3.4", "actually using host"]).as_deref(),
+        Some("alice is actually using host 1.2.3.4")
+    );
+    assert_eq!(
+        line(
+            338,
+            &["me", "alice", "a@host", "1.2.3.4", "Is actually using host"]
+        )
+        .as_deref(),
+        Some("alice is actually using host a@host 1.2.3.4")
+    );
+    assert_eq!(
+        line(
+            671,
+            &["me", "alice", "is using a secure connection [TLSv1.3]"]
+        )
+        .as_deref(),
+        Some("alice is using a secure connection")
+    );
+    assert_eq!(
+        line(379, &["me", "alice", "is using modes +iw"]).as_deref(),
+        Some("alice is using modes +iw")
+    );
+    assert_eq!(line(338, &["me", "alice", "actually using host"]), None);
+    assert_eq!(line(318, &["me", "alice", "End of /WHOIS list."]), None);
+    assert_eq!(line(1, &["me", "Welcome"]), None);
+}
+
+#[test]
+fn netsplit_reason() {
+    assert!(is_netsplit_reason("irc.example.net irc2.example.net"));
+    assert!(is_netsplit_reason("*.net *.split"));
+    assert!(!is_netsplit_reason("*.net *.net"));
+    assert!(!is_netsplit_reason("Quit: bye"));
+    assert!(!is_netsplit_reason("see you.later"));
+    assert!(!is_netsplit_reason("Ping timeout: 240 seconds"));
+    assert!(!is_netsplit_reason("a.b c.d e.f"));
+    assert!(!is_netsplit_reason("example.com."));
 }
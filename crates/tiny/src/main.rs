@@ -1,17 +1,21 @@
 #![allow(clippy::zero_prefixed_literal)]
 
+mod cert_pins;
 mod cli;
 mod cmd;
 mod config;
 mod conn;
 mod debug_logging;
+mod passwords;
+mod sts;
 mod ui;
 mod utils;
 
 #[cfg(test)]
 mod tests;
 
-use libtiny_client::{Client, SASLAuth, ServerInfo};
+use config::PassOrCmd;
+use libtiny_client::{Client, ClientCert, SASLAuth, ServerInfo};
 use libtiny_common::MsgTarget;
 use libtiny_logger::{Logger, LoggerInitError};
 use libtiny_tui::TUI;
@@ -20,6 +24,8 @@ use ui::UI;
 use std::path::PathBuf;
 use std::process::exit;
 
+use tokio::sync::mpsc;
+
 #[macro_use]
 extern crate log;
 
@@ -72,18 +78,19 @@ fn main() {
                     servers,
                     defaults,
                     log_dir,
+                    pm_history_lines,
                 } = config;
 
-                let servers = if !server_args.is_empty() {
-                    // Connect only to servers that match at least one of the given patterns
-                    servers
-                        .into_iter()
-                        .filter(|s| server_args.iter().any(|arg| s.addr.contains(arg)))
-                        .collect()
-                } else {
-                    servers
-                };
-                run(servers, defaults, config_path, log_dir)
+                // Servers of a network are shown next to each other
+                let servers = config::group_by_network(servers);
+                run(
+                    servers,
+                    server_args,
+                    defaults,
+                    config_path,
+                    log_dir,
+                    pm_history_lines,
+                )
             }
         }
     }
@@ -92,10 +99,12 @@ fn main() {
 const DEBUG_LOG_FILE: &str = "tiny_debug_logs.txt";
 
 fn run(
-    servers: Vec<config::Server<String>>,
+    servers: Vec<config::Server<PassOrCmd>>,
+    server_args: Vec<String>,
     defaults: config::Defaults,
     config_path: PathBuf,
     log_dir: Option<PathBuf>,
+    pm_history_lines: usize,
 ) {
     let debug_log_file = match log_dir.as_ref() {
         Some(log_dir) => {
@@ -149,55 +158,180 @@ fn run(
                 }
             });
 
-        let tui = UI::new(tui, logger);
-
-        let mut clients: Vec<Client> = Vec::with_capacity(servers.len());
+        let tui = UI::new(tui, logger, pm_history_lines);
 
-        for server in servers.iter().cloned() {
-            if !server.autoconnect {
-                continue;
+        // Clients are created after reading the passwords, and sent to the TUI task
+        let (snd_client, rcv_client) = mpsc::channel::<Client>(10);
+        for server in &servers {
+            // When servers are given in the command line connect only to servers that match at
+            // least one of the given patterns
+            if server.autoconnect
+                && (server_args.is_empty()
+                    || server_args.iter().any(|arg| server.addr.contains(arg)))
+            {
+                connect_server(server.clone(), &defaults, &tui, &snd_client);
             }
+        }
 
-            tui.new_server_tab(&server.addr, server.alias);
+        // Block on TUI task
+        ui::task(defaults, servers, tui, snd_client, rcv_client, rcv_tui_ev).await;
+    });
 
-            let tls = server.tls;
-            let sasl_auth = server.sasl_auth.and_then(|sasl| -> Option<SASLAuth> {
-                match sasl.try_into() {
-                    Ok(sasl) => Some(sasl),
-                    Err(e) => {
-                        tui.add_client_err_msg(&e, &MsgTarget::Server { serv: &server.addr });
-                        None
-                    }
-                }
-            });
+    runtime.block_on(local);
+}
 
-            let server_info = ServerInfo {
-                addr: server.addr,
-                port: server.port,
-                tls,
-                pass: server.pass,
-                user: server.user,
-                realname: server.realname,
-                nicks: server.nicks,
-                auto_join: server.join.iter().map(|c| c.name().to_owned()).collect(),
-                nickserv_ident: server.nickserv_ident,
-                sasl_auth,
-            };
-
-            let (client, rcv_conn_ev) = Client::new(server_info);
+/// Connect to a server in the config file: create the server tab, and spawn a task that reads the
+/// passwords and connects. The client is sent to `snd_client` after reading the passwords.
+/// Errors are reported in the server tab.
+pub(crate) fn connect_server(
+    server: config::Server<PassOrCmd>,
+    defaults: &config::Defaults,
+    tui: &UI,
+    snd_client: &mpsc::Sender<Client>,
+) {
+    tui.new_server_tab(&server.addr, server.tab_name());
 
-            let tui_clone = tui.clone();
-            let client_clone = client.clone();
+    let defaults = defaults.clone();
+    let tui = tui.clone();
+    let snd_client = snd_client.clone();
+    tokio::task::spawn_local(async move {
+        let serv = server.addr.clone();
+        match passwords::resolve_passwords(server, &tui).await {
+            Some(server) => {
+                let client = start_client(server, &defaults, &tui);
+                // Only fails when quitting
+                let _ = snd_client.send(client).await;
+            }
+            None => {
+                tui.add_client_err_msg(
+                    "Not connecting, use `/connect` to try again",
+                    &MsgTarget::Server { serv: &serv },
+                );
+                tui.draw();
+            }
+        }
+    });
+}
 
-            // Spawn a task to handle connection events
-            tokio::task::spawn_local(conn::task(rcv_conn_ev, tui_clone, Box::new(client_clone)));
+/// Report errors in the server settings in the server tab, create the client, and spawn the task
+/// handling the connection events.
+fn start_client(server: config::Server<String>, defaults: &config::Defaults, tui: &UI) -> Client {
+    let user = server.username(defaults);
+    let realname = server.realname(defaults);
 
-            clients.push(client);
+    let tls = server.tls;
+    let socks5_proxy = server.socks5_proxy();
+    let bind_address = server.bind_address(defaults);
+    let ip_preference = server.ip_preference(defaults).into();
+    let join_history_lines = server.join_history_lines(defaults);
+    let connect_timeout = server.connect_timeout();
+    let tls_handshake_timeout = server.tls_handshake_timeout();
+    let registration_timeout = server.registration_timeout();
+    let ping_interval = server.ping_interval();
+    let away_poll = server.away_poll();
+    let ping_timeout = server.ping_timeout();
+    if let Some(proxy) = socks5_proxy {
+        tui.add_client_msg(
+            &format!("Connecting through SOCKS5 proxy {proxy}"),
+            &MsgTarget::Server { serv: &server.addr },
+        );
+    }
+    let client_cert = server.client_cert().and_then(|cert| match cert {
+        Ok(cert) => Some(cert),
+        Err(e) => {
+            tui.add_client_err_msg(&e, &MsgTarget::Server { serv: &server.addr });
+            None
+        }
+    });
+    let ca_certs = server.ca_certs().and_then(|ca_certs| match ca_certs {
+        Ok(ca_certs) => Some(ca_certs),
+        Err(e) => {
+            tui.add_client_err_msg(&e, &MsgTarget::Server { serv: &server.addr });
+            None
+        }
+    });
+    let sasl_auth = server.sasl_auth.and_then(|sasl| -> Option<SASLAuth> {
+        match sasl.try_into() {
+            Ok(sasl) => Some(sasl),
+            Err(e) => {
+                tui.add_client_err_msg(&e, &MsgTarget::Server { serv: &server.addr });
+                None
+            }
         }
-
-        // Block on TUI task
-        ui::task(defaults, tui, clients, rcv_tui_ev).await;
     });
 
-    runtime.block_on(local);
+    let mut server_info = ServerInfo {
+        addr: server.addr,
+        port: server.port,
+        tls,
+        pass: server.pass,
+        user,
+        realname,
+        nicks: server.nicks,
+        auto_join: server.join.iter().map(|c| c.name().to_owned()).collect(),
+        nickserv_ident: server.nickserv_ident,
+        nickserv: server.nickserv,
+        fallback_encoding: server.fallback_encoding.into(),
+        encoding: server.encoding.into(),
+        sasl_auth,
+        sasl_fail: server.sasl_fail.into(),
+        client_cert,
+        ca_certs,
+        pinned_cert: server.tls_pin_fingerprint,
+        tls_verify_hostname: server.tls_verify_hostname,
+        socks5_proxy,
+        bind_address,
+        ip_preference,
+        connect_timeout,
+        tls_handshake_timeout,
+        registration_timeout,
+        ping_interval,
+        ping_timeout,
+        rejoin_on_reconnect: server.rejoin_on_reconnect,
+        join_history_lines,
+        reconnect_backoff: defaults.reconnect_backoff.into(),
+        flood_protection: defaults.flood_protection.into(),
+        away_poll,
+    };
+    cert_pins::CertPins::load().apply(&mut server_info);
+    if sts::StsPolicies::load().upgrade(&mut server_info) {
+        tui.add_client_msg(
+            &format!(
+                "Server has an STS policy, using TLS on port {}",
+                server_info.port
+            ),
+            &MsgTarget::Server {
+                serv: &server_info.addr,
+            },
+        );
+    }
+
+    let cert = match &server_info.sasl_auth {
+        Some(SASLAuth::External { cert }) => Some(cert),
+        _ => server_info.client_cert.as_ref(),
+    };
+    if let Some(fingerprint) = cert.and_then(ClientCert::fingerprint) {
+        tui.add_client_msg(
+            &format!("Client certificate SHA-256 fingerprint: {fingerprint}"),
+            &MsgTarget::Server {
+                serv: &server_info.addr,
+            },
+        );
+    }
+
+    let (client, rcv_conn_ev) = Client::new(server_info);
+
+    let tui_clone = tui.clone();
+    let client_clone = client.clone();
+
+    // Spawn a task to handle connection events
+    tokio::task::spawn_local(conn::task(
+        rcv_conn_ev,
+        tui_clone,
+        Box::new(client_clone),
+        defaults.ctcp_replies.settings(),
+        defaults.dedup_window,
+    ));
+
+    client
 }
@@ -0,0 +1,285 @@
+//! Running the `cmd:` password commands and reading the `keyring:` passwords of servers when
+//! connecting. Passwords missing in the keyring are asked in the server tab.
+
+use crate::config::{PassOrCmd, Server};
+use crate::ui::UI;
+use libtiny_common::MsgTarget;
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// `cmd:` password commands are killed when they don't finish in this time.
+const PASSWORD_CMD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run the `cmd:` password commands and read the `keyring:` passwords of `server`. Errors are
+/// shown in the server tab. Returns `None` when a password can't be read.
+pub(crate) async fn resolve_passwords(
+    server: Server<PassOrCmd>,
+    ui: &UI,
+) -> Option<Server<String>> {
+    let serv = server.addr.clone();
+    server
+        .map_passwords(|pass, password_name| resolve(pass, password_name, &serv, ui))
+        .await
+}
+
+async fn resolve(pass: PassOrCmd, password_name: &str, serv: &str, ui: &UI) -> Option<String> {
+    let ret = match pass {
+        PassOrCmd::Pass(pass) => return Some(pass),
+        PassOrCmd::Cmd {
+            args,
+            first_line: true,
+        } => resolve_cmd(args, password_name, serv, ui).await,
+        PassOrCmd::Cmd {
+            first_line: false, ..
+        } => unreachable!("Password commands in the map form are run on startup"),
+        PassOrCmd::Keyring { service, account } => {
+            resolve_keyring(service, account, password_name, serv, ui).await
+        }
+    };
+
+    match ret {
+        Ok(pass) => Some(pass),
+        Err(err) => {
+            ui.add_err_msg(&err, time::now(), &MsgTarget::Server { serv });
+            None
+        }
+    }
+}
+
+async fn resolve_cmd(
+    args: Vec<String>,
+    password_name: &str,
+    serv: &str,
+    ui: &UI,
+) -> Result<String, String> {
+    let cmd = shell_words::join(&args);
+    ui.add_client_msg(
+        &format!("Running {password_name} command (`{cmd}`)"),
+        &MsgTarget::Server { serv },
+    );
+    ui.draw();
+
+    blocking(move || run_command(&args, PASSWORD_CMD_TIMEOUT))
+        .await
+        .map_err(|err| format!("Can't get {password_name} from command `{cmd}`: {err}"))
+}
+
+async fn resolve_keyring(
+    service: String,
+    account: String,
+    password_name: &str,
+    serv: &str,
+    ui: &UI,
+) -> Result<String, String> {
+    let ret = {
+        let service = service.clone();
+        let account = account.clone();
+        blocking(move || read_keyring(&service, &account)).await
+    };
+
+    match ret {
+        Ok(Some(pass)) => Ok(pass),
+        Ok(None) => ask_password(password_name, &service, &account, serv, ui).await,
+        Err(err) => Err(err),
+    }
+    .map_err(|err| {
+        format!(
+            "Can't read {password_name} from the keyring (service `{service}`, account \
+             `{account}`): {err}"
+        )
+    })
+}
+
+/// Run a password command, and return the first line of its output. The command is killed when
+/// it doesn't finish in `timeout`.
+fn run_command(args: &[String], timeout: Duration) -> Result<String, String> {
+    assert!(!args.is_empty()); // should be checked in `validate`
+
+    let mut child = Command::new(&args[0])
+        .args(args[1..].iter())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+
+    // Read the outputs in threads while waiting, so the command doesn't block on a full pipe
+    let stdout = read_in_thread(child.stdout.take().unwrap());
+    let stderr = read_in_thread(child.stderr.take().unwrap());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait().map_err(|err| err.to_string())? {
+            Some(status) => break status,
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("command didn't finish in {timeout:?}, killed"));
+            }
+            None => std::thread::sleep(Duration::from_millis(50)),
+        }
+    };
+
+    // The pipes are closed when the command exits, unless it started background processes that
+    // keep them open. Don't wait for those after the timeout.
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    let stdout = stdout.recv_timeout(remaining()).unwrap_or_default();
+    let stderr = stderr.recv_timeout(remaining()).unwrap_or_default();
+
+    if !status.success() {
+        let status = match status.code() {
+            Some(code) => format!("exit code {code}"),
+            None => "killed by a signal".to_owned(),
+        };
+        let stderr = String::from_utf8_lossy(&stderr);
+        return Err(match stderr.trim() {
+            "" => format!("command failed ({status}), stderr is empty"),
+            stderr => format!("command failed ({status}), stderr: {stderr}"),
+        });
+    }
+
+    String::from_utf8_lossy(&stdout)
+        .lines()
+        .next()
+        .map(str::to_owned)
+        .ok_or_else(|| "command returned zero, but stdout is empty".to_owned())
+}
+
+/// Read all of `reader` in a new thread. The contents are sent to the returned receiver at EOF.
+fn read_in_thread<R: Read + Send + 'static>(mut reader: R) -> mpsc::Receiver<Vec<u8>> {
+    let (snd, rcv) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = vec![];
+        let _ = reader.read_to_end(&mut buf);
+        let _ = snd.send(buf);
+    });
+    rcv
+}
+
+/// Ask for a password missing in the keyring in the server tab, and offer to store it.
+async fn ask_password(
+    password_name: &str,
+    service: &str,
+    account: &str,
+    serv: &str,
+    ui: &UI,
+) -> Result<String, String> {
+    let target = MsgTarget::Server { serv };
+    ui.add_client_msg(
+        &format!(
+            "The {password_name} is not in the keyring (service `{service}`, account \
+             `{account}`), please enter it below"
+        ),
+        &target,
+    );
+    let rcv = ui.ask_password(
+        serv,
+        &format!("Enter the {password_name} (Esc to cancel):"),
+        "Store the password in the keyring? (y/n)",
+    );
+    ui.draw();
+
+    let (pass, store) = rcv
+        .await
+        .map_err(|_| "entry not found, and no password entered".to_owned())?;
+
+    if store {
+        let ret = {
+            let service = service.to_owned();
+            let account = account.to_owned();
+            let pass = pass.clone();
+            blocking(move || store_keyring(&service, &account, &pass)).await
+        };
+        match ret {
+            Ok(()) => ui.add_client_msg("Password stored in the keyring", &target),
+            Err(err) => ui.add_err_msg(
+                &format!("Can't store the password in the keyring: {err}"),
+                time::now(),
+                &target,
+            ),
+        }
+    }
+
+    Ok(pass)
+}
+
+/// Run a command or a keyring operation in a blocking thread, to not block the UI. Keyring
+/// operations may block e.g. on D-Bus calls to the secret service.
+async fn blocking<T, F>(f: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|err| Err(err.to_string()))
+}
+
+/// Read a keyring entry. Returns `None` when the entry doesn't exist.
+#[cfg(feature = "keyring")]
+fn read_keyring(service: &str, account: &str) -> Result<Option<String>, String> {
+    let entry = keyring::Entry::new(service, account).map_err(|err| err.to_string())?;
+    match entry.get_password() {
+        Ok(pass) => Ok(Some(pass)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn store_keyring(service: &str, account: &str, pass: &str) -> Result<(), String> {
+    keyring::Entry::new(service, account)
+        .and_then(|entry| entry.set_password(pass))
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "keyring"))]
+fn read_keyring(_service: &str, _account: &str) -> Result<Option<String>, String> {
+    Err("tiny was built without the `keyring` feature".to_owned())
+}
+
+#[cfg(not(feature = "keyring"))]
+fn store_keyring(_service: &str, _account: &str, _pass: &str) -> Result<(), String> {
+    Err("tiny was built without the `keyring` feature".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| (*arg).to_owned()).collect()
+    }
+
+    #[test]
+    fn run_password_command() {
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            run_command(&args(&["sh", "-c", "printf 'first\\nsecond\\n'"]), timeout),
+            Ok("first".to_owned())
+        );
+        assert_eq!(
+            run_command(&args(&["sh", "-c", "echo oops >&2; exit 3"]), timeout),
+            Err("command failed (exit code 3), stderr: oops".to_owned())
+        );
+        assert_eq!(
+            run_command(&args(&["true"]), timeout),
+            Err("command returned zero, but stdout is empty".to_owned())
+        );
+        // stderr larger than a pipe buffer doesn't block the command
+        assert_eq!(
+            run_command(
+                &args(&["sh", "-c", "head -c 1000000 /dev/zero >&2; echo pass"]),
+                timeout
+            ),
+            Ok("pass".to_owned())
+        );
+        assert_eq!(
+            run_command(&args(&["sleep", "10"]), Duration::from_millis(100)),
+            Err("command didn't finish in 100ms, killed".to_owned())
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! IRCv3 strict transport security (STS) policies learned from servers. Policies are persisted
+//! in a file, one policy per line:
+//!
+//! ```text
+//! <host> <port> <expiry time, in seconds since Unix epoch>
+//! ```
+//!
+//! See <https://ircv3.net/specs/extensions/sts>.
+
+use libtiny_client::ServerInfo;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A server that should only be connected to with TLS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StsPolicy {
+    /// Host name of the server, lowercase.
+    pub(crate) host: String,
+
+    /// Port to use TLS on.
+    pub(crate) port: u16,
+
+    /// Expiry time of the policy, in seconds since Unix epoch.
+    pub(crate) expires: u64,
+}
+
+/// Policies loaded from a policy file. Expired policies are dropped when loading.
+#[derive(Debug)]
+pub(crate) struct StsPolicies {
+    /// `None` when the data directory is not available. Policies are not persisted in this case.
+    path: Option<PathBuf>,
+    policies: Vec<StsPolicy>,
+}
+
+/// Policy file path: `$XDG_DATA_HOME/tiny/sts_policies`.
+fn policies_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|mut path| {
+        path.push("tiny");
+        path.push("sts_policies");
+        path
+    })
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+impl StsPolicies {
+    /// Load policies from the default policy file. A missing or invalid file is the same as an
+    /// empty one.
+    pub(crate) fn load() -> StsPolicies {
+        StsPolicies::load_from(policies_path(), SystemTime::now())
+    }
+
+    fn load_from(path: Option<PathBuf>, now: SystemTime) -> StsPolicies {
+        let now = unix_time(now);
+        let policies = path
+            .as_deref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                Some(StsPolicy {
+                    host: words.next()?.to_owned(),
+                    port: words.next()?.parse().ok()?,
+                    expires: words.next()?.parse().ok()?,
+                })
+            })
+            .filter(|policy| policy.expires > now)
+            .collect();
+        StsPolicies { path, policies }
+    }
+
+    /// Active policies.
+    pub(crate) fn policies(&self) -> &[StsPolicy] {
+        &self.policies
+    }
+
+    /// Policy of a host.
+    pub(crate) fn get(&self, host: &str) -> Option<&StsPolicy> {
+        let host = host.to_lowercase();
+        self.policies.iter().find(|policy| policy.host == host)
+    }
+
+    /// Add or update the policy of a host. Zero `duration` removes the policy.
+    pub(crate) fn set(&mut self, host: &str, port: u16, duration: Duration, now: SystemTime) {
+        let host = host.to_lowercase();
+        self.policies.retain(|policy| policy.host != host);
+        if !duration.is_zero() {
+            self.policies.push(StsPolicy {
+                host,
+                port,
+                expires: unix_time(now).saturating_add(duration.as_secs()),
+            });
+        }
+    }
+
+    /// Write the policies to the policy file.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = String::new();
+        for StsPolicy {
+            host,
+            port,
+            expires,
+        } in &self.policies
+        {
+            contents.push_str(&format!("{host} {port} {expires}\n"));
+        }
+        fs::write(path, contents)
+    }
+
+    /// Path of the policy file, for error messages.
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Use TLS on the policy port if the server has a policy, regardless of the configured port
+    /// and TLS setting. Returns whether the connection was upgraded.
+    pub(crate) fn upgrade(&self, server_info: &mut ServerInfo) -> bool {
+        match self.get(&server_info.addr) {
+            Some(policy) if !server_info.tls || server_info.port != policy.port => {
+                server_info.tls = true;
+                server_info.port = policy.port;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_file() {
+        let path = std::env::temp_dir().join(format!("tiny_sts_test_{}", std::process::id()));
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+
+        let mut policies = StsPolicies::load_from(Some(path.clone()), now);
+        assert!(policies.policies().is_empty());
+        policies.set("IRC.Example.org", 6697, Duration::from_secs(100), now);
+        policies.set("irc.other.net", 6697, Duration::from_secs(10), now);
+        policies.set("irc.gone.net", 6697, Duration::from_secs(10), now);
+        policies.set("irc.gone.net", 6697, Duration::ZERO, now);
+        policies.save().unwrap();
+
+        let policies = StsPolicies::load_from(Some(path.clone()), now);
+        assert_eq!(
+            policies.policies(),
+            &[
+                StsPolicy {
+                    host: "irc.example.org".to_owned(),
+                    port: 6697,
+                    expires: 1100,
+                },
+                StsPolicy {
+                    host: "irc.other.net".to_owned(),
+                    port: 6697,
+                    expires: 1010,
+                },
+            ]
+        );
+
+        // Expired policies are dropped
+        let policies = StsPolicies::load_from(Some(path), now + Duration::from_secs(50));
+        assert_eq!(policies.policies().len(), 1);
+        assert!(policies.get("irc.other.net").is_none());
+
+        let mut server_info = ServerInfo {
+            addr: "irc.example.org".to_owned(),
+            port: 6667,
+            tls: false,
+            pass: None,
+            user: None,
+            realname: "tiny".to_owned(),
+            nicks: vec!["tiny".to_owned()],
+            auto_join: vec![],
+            nickserv_ident: None,
+            nickserv: None,
+            fallback_encoding: libtiny_wire::FallbackEncoding::Replace,
+            encoding: libtiny_wire::Encoding::Utf8,
+            sasl_auth: None,
+            sasl_fail: libtiny_client::SASLFail::Continue,
+            client_cert: None,
+            ca_certs: None,
+            pinned_cert: None,
+            tls_verify_hostname: true,
+            socks5_proxy: None,
+            bind_address: None,
+            ip_preference: libtiny_client::IpPreference::Auto,
+            connect_timeout: None,
+            tls_handshake_timeout: None,
+            registration_timeout: None,
+            ping_interval: None,
+            ping_timeout: None,
+            rejoin_on_reconnect: true,
+            join_history_lines: 50,
+            reconnect_backoff: libtiny_client::ReconnectBackoff::default(),
+            flood_protection: libtiny_client::FloodProtection::default(),
+            away_poll: None,
+        };
+        assert!(policies.upgrade(&mut server_info));
+        assert!(server_info.tls);
+        assert_eq!(server_info.port, 6697);
+        assert!(!policies.upgrade(&mut server_info));
+
+        let _ = fs::remove_file(policies.path().unwrap());
+    }
+}
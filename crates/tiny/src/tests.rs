@@ -1,9 +1,11 @@
+use crate::config::CtcpReplySettings;
 use crate::conn;
 use crate::ui::UI;
-use libtiny_common::ChanName;
+use libtiny_common::{ChanName, ChanNameRef};
 use libtiny_tui::TUI;
+use libtiny_tui::config::{Colors, TabConfig};
 use libtiny_tui::test_utils::expect_screen;
-use libtiny_wire::{Cmd, Msg, MsgTarget, Pfx};
+use libtiny_wire::{CTCP, Cmd, Msg, MsgTarget, Pfx, Tags};
 
 use termbox_simple::CellBuf;
 
@@ -12,6 +14,7 @@ use term_input as input;
 
 use std::future::Future;
 use std::panic::Location;
+use std::time::Duration;
 
 use tokio::sync::mpsc;
 use tokio_stream::StreamExt;
@@ -33,6 +36,26 @@ impl conn::Client for TestClient {
     fn is_nick_accepted(&self) -> bool {
         true
     }
+
+    fn rejoin_after(&self, _chan: &ChanNameRef, _delay: Duration) {}
+
+    fn is_away(&self, _nick: &str) -> bool {
+        false
+    }
+
+    fn get_addr(&self) -> Option<std::net::SocketAddr> {
+        None
+    }
+
+    fn ctcp_reply(&self, _nick: &str, _ctcp: &str, _body: &str) {}
+
+    fn get_isupport(&self) -> client::ISupport {
+        client::ISupport::default()
+    }
+
+    fn get_server_caps(&self) -> Vec<String> {
+        vec![]
+    }
 }
 
 static SERV_NAME: &str = "x.y.z";
@@ -66,7 +89,7 @@ where
         let (tui, _rcv_tui_ev) =
             TUI::run_test(DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT, rcv_input_ev.map(Ok));
 
-        let tiny_ui = UI::new(tui.clone(), None);
+        let tiny_ui = UI::new(tui.clone(), None, 0);
 
         // Create test connection event channel
         let (snd_conn_ev, rcv_conn_ev) = mpsc::channel::<client::Event>(100);
@@ -76,6 +99,8 @@ where
             rcv_conn_ev,
             tiny_ui,
             Box::new(TestClient { nick }),
+            CtcpReplySettings::default(),
+            conn::DEFAULT_DEDUP_WINDOW,
         ));
 
         tui.new_server_tab(SERV_NAME, None);
@@ -110,12 +135,14 @@ fn test_privmsg_from_user_without_user_or_host_part_issue_247() {
 
             // Join a channel to test msg sent to channel
             let join = Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::User {
                     nick: "osa1".to_owned(),
                     user: "a@b".to_owned(),
                 }),
                 cmd: Cmd::JOIN {
                     chan: ChanName::new("#chan".to_owned()),
+                    account: None,
                 },
             };
             snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
@@ -123,6 +150,7 @@ fn test_privmsg_from_user_without_user_or_host_part_issue_247() {
 
             // Send a PRIVMSG to the channel
             let chan_msg = Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::Ambiguous("tiny_test_user".to_owned())),
                 cmd: Cmd::PRIVMSG {
                     target: MsgTarget::Chan(ChanName::new("#chan".to_owned())),
@@ -139,6 +167,7 @@ fn test_privmsg_from_user_without_user_or_host_part_issue_247() {
 
             // Send a PRIVMSG to current nick
             let msg = Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::Ambiguous("tiny_test_user".to_owned())),
                 cmd: Cmd::PRIVMSG {
                     target: MsgTarget::User("osa1".to_owned()),
@@ -221,6 +250,7 @@ fn test_bouncer_relay_issue_271() {
                 .unwrap();
 
             let msg = Msg {
+                tags: Tags::default(),
                 pfx: Some(Pfx::User {
                     nick: "osa1-soju".to_owned(),
                     user: "osa1-soju@127.0.0.1".to_owned(),
@@ -285,6 +315,7 @@ fn test_privmsg_targetmask_issue_278() {
 
             snd_conn_ev
                 .send(client::Event::Msg(Msg {
+                    tags: Tags::default(),
                     pfx: Some(Pfx::User {
                         nick: "tiny_test_user".to_owned(),
                         user: "e@a/b/c.d".to_owned(),
@@ -331,6 +362,1613 @@ fn test_privmsg_targetmask_issue_278() {
     )
 }
 
+#[test]
+fn test_kick_rejoin() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            let chan = ChanName::new("#chan".to_owned());
+            tui.set_tab_config(
+                SERV_NAME,
+                Some(&chan),
+                TabConfig {
+                    rejoin_on_kick: Some(true),
+                    ..Default::default()
+                },
+            );
+
+            let join = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "osa1".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::JOIN {
+                    chan: chan.clone(),
+                    account: None,
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
+            yield_(5).await;
+
+            let kick = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "op".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::KICK {
+                    chan,
+                    nick: "osa1".to_owned(),
+                    msg: Some("bye".to_owned()),
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(kick)).await.unwrap();
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |00:00 You were kicked by op (bye)       |
+             |Rejoining in 3 seconds...               |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_highlight_in_formatted_text() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            let join = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "osa1".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::JOIN {
+                    chan: ChanName::new("#chan".to_owned()),
+                    account: None,
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
+            yield_(5).await;
+
+            // Nick in red
+            let chan_msg = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::Ambiguous("tiny_test_user".to_owned())),
+                cmd: Cmd::PRIVMSG {
+                    target: MsgTarget::Chan(ChanName::new("#chan".to_owned())),
+                    msg: "\x0304osa1\x03: hi".to_owned(),
+                    is_notice: false,
+                    ctcp: None,
+                },
+            };
+            snd_conn_ev
+                .send(client::Event::Msg(chan_msg))
+                .await
+                .unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            let colors = Colors::default();
+            let tab_bar_y = usize::from(DEFAULT_TUI_HEIGHT - 1);
+            let cell = |front_buffer: &CellBuf, x: usize, y: usize| {
+                front_buffer.cells[y * usize::from(DEFAULT_TUI_WIDTH) + x]
+            };
+
+            // "#chan" in the tab bar is highlighted
+            let front_buffer = tui.get_front_buffer();
+            assert_eq!(cell(&front_buffer, 15, tab_bar_y).ch, '#');
+            assert_eq!(
+                cell(&front_buffer, 15, tab_bar_y).fg,
+                colors.tab_highlight.fg
+            );
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |                                        |
+             |00:00 tiny_test_user: osa1: hi          |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+
+            // The nick is shown as a mention
+            assert_eq!(cell(&front_buffer, 22, 2).fg, colors.mention.fg);
+        },
+    );
+}
+
+#[test]
+fn test_playback_dedup() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            let join = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "osa1".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::JOIN {
+                    chan: ChanName::new("#chan".to_owned()),
+                    account: None,
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
+            yield_(5).await;
+
+            let chan_msg = |tags: &str, msg: &str| Msg {
+                tags: Tags::parse(tags),
+                pfx: Some(Pfx::User {
+                    nick: "bob".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::PRIVMSG {
+                    target: MsgTarget::Chan(ChanName::new("#chan".to_owned())),
+                    msg: msg.to_owned(),
+                    is_notice: false,
+                    ctcp: None,
+                },
+            };
+            // The second message is replayed by a bouncer after reconnecting. Messages without a
+            // msgid and server-time are never dropped.
+            for msg in [
+                chan_msg("msgid=1", "one"),
+                chan_msg("msgid=1", "one"),
+                chan_msg("", "hi"),
+                chan_msg("", "hi"),
+            ] {
+                snd_conn_ev.send(client::Event::Msg(msg)).await.unwrap();
+            }
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|00:00 bob: one                          |
+             |bob: hi                                 |
+             |bob: hi                                 |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_self_message() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            let join = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "osa1".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::JOIN {
+                    chan: ChanName::new("#chan".to_owned()),
+                    account: None,
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
+            yield_(5).await;
+
+            // Sent by us from another client of the bouncer, relayed with `znc.in/self-message`.
+            // The bouncer may not use the same case in the nick.
+            let chan_msg = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "OSA1".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::PRIVMSG {
+                    target: MsgTarget::Chan(ChanName::new("#chan".to_owned())),
+                    msg: "osa1: note to self".to_owned(),
+                    is_notice: false,
+                    ctcp: None,
+                },
+            };
+            snd_conn_ev
+                .send(client::Event::Msg(chan_msg))
+                .await
+                .unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            let colors = Colors::default();
+            let tab_bar_y = usize::from(DEFAULT_TUI_HEIGHT - 1);
+            let cell = |front_buffer: &CellBuf, x: usize, y: usize| {
+                front_buffer.cells[y * usize::from(DEFAULT_TUI_WIDTH) + x]
+            };
+
+            // "#chan" in the tab bar is not highlighted
+            let front_buffer = tui.get_front_buffer();
+            assert_eq!(cell(&front_buffer, 15, tab_bar_y).ch, '#');
+            assert_eq!(cell(&front_buffer, 15, tab_bar_y).fg, colors.tab_new_msg.fg);
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |                                        |
+             |00:00 osa1: osa1: note to self          |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+
+            // Our nick in our own message is not a mention
+            assert_ne!(cell(&front_buffer, 12, 2).fg, colors.mention.fg);
+        },
+    );
+}
+
+#[test]
+fn test_away_indicator() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+
+            let reply = |num: u16, msg: &str| {
+                client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: Some(Pfx::Server("x.y.z".to_owned())),
+                    cmd: Cmd::Reply {
+                        num,
+                        params: vec!["osa1".to_owned(), msg.to_owned()],
+                    },
+                })
+            };
+
+            // RPL_NOWAWAY
+            snd_conn_ev
+                .send(reply(306, "You have been marked as being away"))
+                .await
+                .unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |Connected.                              |
+             |You have been marked as being away      |
+             |osa1:                                   |
+             |mentions x.y.z [away]                   |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+
+            // RPL_UNAWAY, e.g. after sending a message
+            snd_conn_ev
+                .send(reply(305, "You are no longer marked as being away"))
+                .await
+                .unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|Connected.                              |
+             |You have been marked as being away      |
+             |You are no longer marked as being away  |
+             |osa1:                                   |
+             |mentions x.y.z                          |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_away_notify() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            let pfx = Some(Pfx::User {
+                nick: "bob".to_owned(),
+                user: "~bob@host".to_owned(),
+            });
+            snd_conn_ev
+                .send(client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: pfx.clone(),
+                    cmd: Cmd::PRIVMSG {
+                        target: MsgTarget::User("osa1".to_owned()),
+                        msg: "hi".to_owned(),
+                        is_notice: false,
+                        ctcp: None,
+                    },
+                }))
+                .await
+                .unwrap();
+            let away = |msg: Option<&str>| {
+                client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: pfx.clone(),
+                    cmd: Cmd::AWAY {
+                        msg: msg.map(str::to_owned),
+                        chans: vec![],
+                    },
+                })
+            };
+            snd_conn_ev.send(away(Some("lunch"))).await.unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |                                        |
+             |Connected.                              |
+             |                                        |
+             |mentions x.y.z bob [away]               |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+
+            snd_conn_ev.send(away(None)).await.unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |                                        |
+             |Connected.                              |
+             |                                        |
+             |mentions x.y.z bob                      |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_chghost() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            let chan = ChanName::new("#chan".to_owned());
+            let join = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "osa1".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::JOIN {
+                    chan: chan.clone(),
+                    account: None,
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
+            let chghost = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "alice".to_owned(),
+                    user: "~alice@host".to_owned(),
+                }),
+                cmd: Cmd::CHGHOST {
+                    user: "alice".to_owned(),
+                    host: "cloak/alice".to_owned(),
+                    chans: vec![chan],
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(chghost)).await.unwrap();
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |                                        |
+             |00:00 alice is now alice@cloak/alice    |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_setname() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            let chan = ChanName::new("#chan".to_owned());
+            let join = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "osa1".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::JOIN {
+                    chan: chan.clone(),
+                    account: None,
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
+            let setname = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "alice".to_owned(),
+                    user: "~alice@host".to_owned(),
+                }),
+                cmd: Cmd::SETNAME {
+                    realname: "Alice".to_owned(),
+                    chans: vec![chan],
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(setname)).await.unwrap();
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |                                        |
+             |00:00 alice changed real name to Alice  |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_server_cert_err() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev
+                .send(client::Event::ServerCertErr {
+                    err: "expired".to_owned(),
+                    fingerprint: "c33e6d".to_owned(),
+                    subject: Some("CN=x.y.z".to_owned()),
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|expired                                 |
+             |Certificate subject: CN=x.y.z           |
+             |Certificate SHA-256 fingerprint: c33e6d |
+             |Accept and pin the certificate? (y/n)   |
+             |mentions x.y.z                          |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_history() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            let chan = ChanName::new("#chan".to_owned());
+            let join = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "osa1".to_owned(),
+                    user: "a@b".to_owned(),
+                }),
+                cmd: Cmd::JOIN {
+                    chan: chan.clone(),
+                    account: None,
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
+            let privmsg = |msg: &str, ctcp: Option<CTCP>| Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "bob".to_owned(),
+                    user: "~bob@host".to_owned(),
+                }),
+                cmd: Cmd::PRIVMSG {
+                    target: MsgTarget::Chan(chan.clone()),
+                    msg: msg.to_owned(),
+                    is_notice: false,
+                    ctcp,
+                },
+            };
+            let msgs = vec![
+                privmsg("hi osa1", None),
+                privmsg("", Some(CTCP::Version)),
+                privmsg("waves", Some(CTCP::Action)),
+            ];
+            snd_conn_ev
+                .send(client::Event::History {
+                    target: MsgTarget::Chan(chan.clone()),
+                    msgs,
+                    older: false,
+                })
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|00:00 bob: hi osa1                      |
+             |**  bob waves                           |
+             |End of history                          |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_netsplit_batch() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            let parse = |s: &str| {
+                let mut buf = s.as_bytes().to_vec();
+                libtiny_wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+            };
+            snd_conn_ev
+                .send(client::Event::Msg(parse(":osa1!a@b JOIN #chan\r\n")))
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            snd_input_ev
+                .send(input::Event::String(
+                    "/set collapse_netsplits true".to_owned(),
+                ))
+                .await
+                .unwrap();
+            snd_input_ev
+                .send(input::Event::Key(input::Key::Char('\r')))
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            // Quits in a `netsplit` batch are collapsed, regardless of the quit messages
+            let mut msgs = vec![parse(":x.y.z BATCH +ns netsplit irc.hub other.host\r\n")];
+            for nick in ["alice", "bob"] {
+                let mut quit = parse(&format!("@batch=ns :{nick}!~{nick}@host QUIT :Bye\r\n"));
+                if let Cmd::QUIT { chans, .. } = &mut quit.cmd {
+                    chans.push(ChanName::new("#chan".to_owned()));
+                }
+                msgs.push(quit);
+            }
+            msgs.push(parse(":x.y.z BATCH -ns\r\n"));
+            for msg in msgs {
+                snd_conn_ev.send(client::Event::Msg(msg)).await.unwrap();
+            }
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|collapse_netsplits: true                |
+             |00:00 Netsplit (irc.hub other.host): 2  |
+             |users quit                              |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+
+            // Joins in a `netjoin` batch are collapsed the same way
+            let mut msgs = vec![parse(":x.y.z BATCH +nj netjoin irc.hub other.host\r\n")];
+            for nick in ["alice", "bob"] {
+                msgs.push(parse(&format!(
+                    "@batch=nj :{nick}!~{nick}@host JOIN #chan\r\n"
+                )));
+            }
+            msgs.push(parse(":x.y.z BATCH -nj\r\n"));
+            for msg in msgs {
+                snd_conn_ev.send(client::Event::Msg(msg)).await.unwrap();
+            }
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|users quit                              |
+             |Netjoin (irc.hub other.host): 2 users   |
+             |joined                                  |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_netsplit_rejoin() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            let parse = |s: &str| {
+                let mut buf = s.as_bytes().to_vec();
+                libtiny_wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+            };
+            snd_conn_ev
+                .send(client::Event::Msg(parse(":osa1!a@b JOIN #chan\r\n")))
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            snd_input_ev
+                .send(input::Event::String(
+                    "/set collapse_netsplits true".to_owned(),
+                ))
+                .await
+                .unwrap();
+            snd_input_ev
+                .send(input::Event::Key(input::Key::Char('\r')))
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            // Without batches, quits with a netsplit reason are collapsed, and joins of the same
+            // users after the split are shown as a netjoin
+            for nick in ["alice", "bob"] {
+                let mut quit = parse(&format!(
+                    ":{nick}!~{nick}@host QUIT :irc.hub other.host\r\n"
+                ));
+                if let Cmd::QUIT { chans, .. } = &mut quit.cmd {
+                    chans.push(ChanName::new("#chan".to_owned()));
+                }
+                snd_conn_ev.send(client::Event::Msg(quit)).await.unwrap();
+            }
+            for nick in ["alice", "bob"] {
+                let join = parse(&format!(":{nick}!~{nick}@host JOIN #chan\r\n"));
+                snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
+            }
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|users quit                              |
+             |Netjoin (irc.hub other.host): 2 users   |
+             |joined                                  |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_typing_tagmsg() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "osa1".to_owned(),
+                })
+                .await
+                .unwrap();
+            let parse = |s: &str| {
+                let mut buf = s.as_bytes().to_vec();
+                libtiny_wire::parse_irc_msg(&mut buf).unwrap().unwrap()
+            };
+            snd_conn_ev
+                .send(client::Event::Msg(parse(":osa1!a@b JOIN #chan\r\n")))
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            next_tab(&snd_input_ev).await; // server tab
+            next_tab(&snd_input_ev).await; // channel tab
+            snd_input_ev
+                .send(input::Event::String(
+                    "/set typing_notifications true".to_owned(),
+                ))
+                .await
+                .unwrap();
+            snd_input_ev
+                .send(input::Event::Key(input::Key::Char('\r')))
+                .await
+                .unwrap();
+            yield_(5).await;
+
+            for msg in [
+                "@+typing=active :alice!a@b TAGMSG #chan\r\n",
+                // Our own typing, echoed back, is not shown
+                "@+typing=active :osa1!a@b TAGMSG #chan\r\n",
+            ] {
+                snd_conn_ev
+                    .send(client::Event::Msg(parse(msg)))
+                    .await
+                    .unwrap();
+            }
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |typing_notifications: true              |
+             |alice is typing…                        |
+             |osa1:                                   |
+             |mentions x.y.z #chan                    |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_ison_reply() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            let ison_reply = |online: &str| {
+                client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: Some(Pfx::Server("x.y.z".to_owned())),
+                    cmd: Cmd::Reply {
+                        num: 303,
+                        params: vec!["osa1".to_owned(), online.to_owned()],
+                    },
+                })
+            };
+
+            snd_conn_ev.send(ison_reply("bob alice ")).await.unwrap();
+            snd_conn_ev.send(ison_reply("")).await.unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|Connected.                              |
+             |Online: bob, alice                      |
+             |None of the nicks are online            |
+             |                                        |
+             |mentions x.y.z                          |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_cap_new_del() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            let cap = |subcommand: &str, caps: &str| {
+                client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: Some(Pfx::Server("x.y.z".to_owned())),
+                    cmd: Cmd::CAP {
+                        client: "osa1".to_owned(),
+                        subcommand: subcommand.to_owned(),
+                        params: caps.split(' ').map(str::to_owned).collect(),
+                        more: false,
+                    },
+                })
+            };
+
+            snd_conn_ev.send(cap("NEW", "away-notify")).await.unwrap();
+            snd_conn_ev.send(cap("DEL", "setname")).await.unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|Connected.                              |
+             |Server added capabilities: away-notify  |
+             |Server removed capabilities: setname    |
+             |                                        |
+             |mentions x.y.z                          |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_ctcp_request() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            let request = |ctcp: CTCP| {
+                client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: Some(Pfx::User {
+                        nick: "bob".to_owned(),
+                        user: "b@host".to_owned(),
+                    }),
+                    cmd: Cmd::PRIVMSG {
+                        target: MsgTarget::User("osa1".to_owned()),
+                        msg: "".to_owned(),
+                        is_notice: false,
+                        ctcp: Some(ctcp),
+                    },
+                })
+            };
+
+            snd_conn_ev.send(request(CTCP::Version)).await.unwrap();
+            snd_conn_ev.send(request(CTCP::Time)).await.unwrap();
+            snd_conn_ev
+                .send(request(CTCP::Other("FINGER".to_owned())))
+                .await
+                .unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|CTCP VERSION request from bob           |
+             |CTCP TIME request from bob              |
+             |CTCP FINGER request from bob            |
+             |                                        |
+             |mentions x.y.z                          |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_ctcp_reply_label() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            // Reply to our `/ctcp bob VERSION`
+            let reply = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "bob".to_owned(),
+                    user: "b@host".to_owned(),
+                }),
+                cmd: Cmd::PRIVMSG {
+                    target: MsgTarget::User("osa1".to_owned()),
+                    msg: "irssi 1.4".to_owned(),
+                    is_notice: true,
+                    ctcp: Some(CTCP::Version),
+                },
+            };
+            snd_conn_ev.send(client::Event::Msg(reply)).await.unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            // Notices to us are shown in the server tab
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |Connected.                              |
+             |00:00 bob: CTCP VERSION irssi 1.4       |
+             |                                        |
+             |mentions x.y.z                          |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_time_version_replies() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            let reply = |num: u16, params: &[&str]| {
+                client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: Some(Pfx::Server("x.y.z".to_owned())),
+                    cmd: Cmd::Reply {
+                        num,
+                        params: params.iter().map(|s| (*s).to_owned()).collect(),
+                    },
+                })
+            };
+
+            snd_conn_ev
+                .send(reply(
+                    391,
+                    &["osa1", "x.y.z", "1735725600", "0", "Wed Jan 1"],
+                ))
+                .await
+                .unwrap();
+            snd_conn_ev
+                .send(reply(351, &["osa1", "ircd-1.0", "x.y.z", "TS6"]))
+                .await
+                .unwrap();
+            snd_conn_ev
+                .send(reply(351, &["osa1", "ircd-1.0", "x.y.z", ""]))
+                .await
+                .unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|Time at x.y.z: Wed Jan 1                |
+             |x.y.z is running ircd-1.0 (TS6)         |
+             |x.y.z is running ircd-1.0               |
+             |                                        |
+             |mentions x.y.z                          |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_hide_motd() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+            tui.set_tab_config(
+                SERV_NAME,
+                None,
+                TabConfig {
+                    hide_motd: Some(true),
+                    ..Default::default()
+                },
+            );
+
+            let reply = |num: u16, params: &[&str]| {
+                client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: Some(Pfx::Server("x.y.z".to_owned())),
+                    cmd: Cmd::Reply {
+                        num,
+                        params: params.iter().map(|s| (*s).to_owned()).collect(),
+                    },
+                })
+            };
+
+            for (num, params) in [
+                (251, &["osa1", "There are 3 users"][..]),
+                (375, &["osa1", "- x.y.z Message of the day -"]),
+                (372, &["osa1", "- Welcome!"]),
+                (376, &["osa1", "End of /MOTD command."]),
+            ] {
+                snd_conn_ev.send(reply(num, params)).await.unwrap();
+            }
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |Connected.                              |
+             |00:00 There are 3 users                 |
+             |                                        |
+             |mentions x.y.z                          |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_wallops_server_notice() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            let notice = client::Event::Msg(Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::Server("x.y.z".to_owned())),
+                cmd: Cmd::PRIVMSG {
+                    target: MsgTarget::User("osa1".to_owned()),
+                    msg: "*** Notice".to_owned(),
+                    is_notice: true,
+                    ctcp: None,
+                },
+            });
+            let wallops = client::Event::Msg(Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "oper".to_owned(),
+                    user: "~oper@host".to_owned(),
+                }),
+                cmd: Cmd::WALLOPS {
+                    msg: "Restart soon".to_owned(),
+                },
+            });
+            snd_conn_ev.send(notice).await.unwrap();
+            snd_conn_ev.send(wallops).await.unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|Connected.                              |
+             |00:00 [server] *** Notice               |
+             |[wallops] oper: Restart soon            |
+             |                                        |
+             |mentions x.y.z                          |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+
+            let colors = Colors::default();
+            let fg = |x: usize, y: usize| front_buffer.cells[y * DEFAULT_TUI_WIDTH as usize + x].fg;
+            assert_eq!(fg(0, 0), colors.user_msg.fg);
+            assert_eq!(fg(6, 1), colors.server_notice.fg);
+            assert_eq!(fg(0, 2), colors.server_notice.fg);
+        },
+    );
+}
+
+#[test]
+fn test_invite() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            let invite = |nick: &str| {
+                client::Event::Msg(Msg {
+                    tags: Tags::default(),
+                    pfx: Some(Pfx::User {
+                        nick: "alice".to_owned(),
+                        user: "~alice@host".to_owned(),
+                    }),
+                    cmd: Cmd::INVITE {
+                        nick: nick.to_owned(),
+                        chan: ChanName::new("#chan".to_owned()),
+                    },
+                })
+            };
+            snd_conn_ev.send(invite("osa1")).await.unwrap();
+            // Not ours
+            snd_conn_ev.send(invite("bob")).await.unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|Connected.                              |
+             |00:00 alice invited you to #chan, use   |
+             |`/join` in this tab to join             |
+             |                                        |
+             |mentions x.y.z                          |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_bouncer_control_user() {
+    run_test(
+        "osa1".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            // Notices from bouncer control users are shown in their tabs
+            let notice = client::Event::Msg(Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "*playback".to_owned(),
+                    user: "znc@znc.in".to_owned(),
+                }),
+                cmd: Cmd::PRIVMSG {
+                    target: MsgTarget::User("osa1".to_owned()),
+                    msg: "Playback done".to_owned(),
+                    is_notice: true,
+                    ctcp: None,
+                },
+            });
+            snd_conn_ev.send(notice).await.unwrap();
+            yield_(5).await;
+            next_tab(&snd_input_ev).await;
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |                                        |
+             |00:00 *playback: Playback done          |
+             |                                        |
+             |mentions x.y.z *playback                |";
+
+            let mut front_buffer = tui.get_front_buffer();
+            normalize_timestamps(&mut front_buffer, DEFAULT_TUI_WIDTH, DEFAULT_TUI_HEIGHT);
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_forced_nick_change() {
+    // The client updates our nick before the NICK message is handled
+    run_test(
+        "Guest123".to_owned(),
+        |TestSetup {
+             tui,
+             snd_input_ev,
+             snd_conn_ev,
+         }| async move {
+            next_tab(&snd_input_ev).await;
+            snd_conn_ev.send(client::Event::Connected).await.unwrap();
+
+            let nick = Msg {
+                tags: Tags::default(),
+                pfx: Some(Pfx::User {
+                    nick: "osa1".to_owned(),
+                    user: "~osa1@host".to_owned(),
+                }),
+                cmd: Cmd::NICK {
+                    nick: "Guest123".to_owned(),
+                    chans: vec![],
+                },
+            };
+            snd_conn_ev
+                .send(client::Event::NickChange {
+                    new_nick: "Guest123".to_owned(),
+                })
+                .await
+                .unwrap();
+            snd_conn_ev.send(client::Event::Msg(nick)).await.unwrap();
+            yield_(5).await;
+            tui.draw();
+
+            #[rustfmt::skip]
+            let screen =
+            "|                                        |
+             |Connected.                              |
+             |You are now known as Guest123           |
+             |Guest123:                               |
+             |mentions x.y.z                          |";
+
+            let front_buffer = tui.get_front_buffer();
+            expect_screen(
+                screen,
+                &front_buffer,
+                DEFAULT_TUI_WIDTH,
+                DEFAULT_TUI_HEIGHT,
+                Location::caller(),
+            );
+        },
+    );
+}
+
 async fn next_tab(snd_input_ev: &mpsc::Sender<input::Event>) {
     snd_input_ev
         .send(term_input::Event::Key(term_input::Key::Ctrl('n')))
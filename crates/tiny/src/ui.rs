@@ -1,17 +1,16 @@
 //! UI event handling
 
 use crate::cmd::run_cmd;
-use crate::config;
+use crate::config::{self, PassOrCmd};
+use crate::utils;
 use libtiny_client::Client;
-use libtiny_common::{ChanNameRef, MsgSource, MsgTarget, TabStyle};
+use libtiny_common::{CaseMapping, ChanNameRef, MsgSource, MsgTarget, TabStyle, TypingState};
 use libtiny_logger::Logger;
-use libtiny_tui::TUI;
+use libtiny_tui::{HistoryMsg, TUI};
 
 use libtiny_tui::config::TabConfig;
 use time::Tm;
-use tokio::sync::mpsc;
-use tokio_stream::StreamExt;
-use tokio_stream::wrappers::ReceiverStream;
+use tokio::sync::{mpsc, oneshot};
 
 macro_rules! delegate {
     ( $name:ident ( $( $x:ident: $t:ty, )* )) => {
@@ -36,11 +35,106 @@ macro_rules! delegate_ui {
 pub(crate) struct UI {
     ui: TUI,
     logger: Option<Logger>,
+    /// Number of messages to load from the log file when a private conversation tab is opened.
+    pm_history_lines: usize,
 }
 
 impl UI {
-    pub(crate) fn new(ui: TUI, logger: Option<Logger>) -> UI {
-        UI { ui, logger }
+    pub(crate) fn new(ui: TUI, logger: Option<Logger>, pm_history_lines: usize) -> UI {
+        UI {
+            ui,
+            logger,
+            pm_history_lines,
+        }
+    }
+
+    pub(crate) fn add_privmsg(
+        &self,
+        sender: &str,
+        msg: &str,
+        ts: Tm,
+        target: &MsgTarget,
+        highlight: bool,
+        is_action: bool,
+    ) {
+        if let MsgTarget::User { serv, nick } = *target {
+            self.load_user_history(serv, nick);
+        }
+        self.ui
+            .add_privmsg(sender, msg, ts, target, highlight, is_action);
+        if let Some(logger) = &self.logger {
+            logger.add_privmsg(sender, msg, ts, target, highlight, is_action);
+        }
+    }
+
+    /// Server notices and WALLOPS are shown in their own color, and logged as normal messages.
+    pub(crate) fn add_server_notice(&self, msg: &str, ts: Tm, target: &MsgTarget) {
+        self.ui.add_server_notice(msg, ts, target);
+        if let Some(logger) = &self.logger {
+            logger.add_msg(msg, ts, target);
+        }
+    }
+
+    /// When opening a private conversation tab, show the last messages of the conversation from
+    /// the log file, followed by an "End of history" line.
+    fn load_user_history(&self, serv: &str, nick: &str) {
+        let Some(logger) = &self.logger else {
+            return;
+        };
+        if self.pm_history_lines == 0 || self.ui.user_tab_exists(serv, nick) {
+            return;
+        }
+        let msgs: Vec<HistoryMsg> = logger
+            .user_history(serv, nick, self.pm_history_lines)
+            .into_iter()
+            .map(|msg| HistoryMsg {
+                sender: msg.sender,
+                msg: msg.msg,
+                ts: msg.ts,
+                is_action: msg.is_action,
+            })
+            .collect();
+        if !msgs.is_empty() {
+            self.ui.new_user_tab(serv, nick);
+            self.ui
+                .add_history(&msgs, false, &MsgTarget::User { serv, nick });
+        }
+    }
+
+    pub(crate) fn netsplit_quit(
+        &self,
+        nick: &str,
+        servers: &str,
+        ts: Option<Tm>,
+        target: &MsgTarget,
+    ) {
+        self.ui.netsplit_quit(nick, servers, ts, target);
+        if let Some(logger) = &self.logger {
+            logger.remove_nick(nick, ts, target);
+        }
+    }
+
+    pub(crate) fn netjoin(&self, nick: &str, servers: &str, ts: Option<Tm>, target: &MsgTarget) {
+        self.ui.netjoin(nick, servers, ts, target);
+        if let Some(logger) = &self.logger {
+            logger.add_nick(nick, ts, target);
+        }
+    }
+
+    pub(crate) fn add_playback_privmsg(
+        &self,
+        sender: &str,
+        msg: &str,
+        ts: Tm,
+        target: &MsgTarget,
+        highlight: bool,
+        is_action: bool,
+    ) {
+        self.ui
+            .add_playback_privmsg(sender, msg, ts, target, highlight, is_action);
+        if let Some(logger) = &self.logger {
+            logger.add_privmsg(sender, msg, ts, target, highlight, is_action);
+        }
     }
 
     pub(crate) fn new_server_tab(&self, serv_name: &str, alias: Option<String>) {
@@ -56,14 +150,6 @@ impl UI {
     delegate!(close_user_tab(serv: &str, nick: &str,));
     delegate!(add_client_msg(msg: &str, target: &MsgTarget,));
     delegate!(add_msg(msg: &str, ts: Tm, target: &MsgTarget,));
-    delegate!(add_privmsg(
-        sender: &str,
-        msg: &str,
-        ts: Tm,
-        target: &MsgTarget,
-        highlight: bool,
-        is_action: bool,
-    ));
     delegate!(add_nick(nick: &str, ts: Option<Tm>, target: &MsgTarget,));
     delegate!(remove_nick(nick: &str, ts: Option<Tm>, target: &MsgTarget,));
     delegate!(rename_nick(
@@ -80,12 +166,24 @@ impl UI {
     ));
 
     delegate_ui!(draw());
+    delegate_ui!(ask_password(serv: &str, msg: &str, store_msg: &str,) -> oneshot::Receiver<(String, bool)>);
     delegate_ui!(add_err_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate_ui!(add_client_err_msg(msg: &str, target: &MsgTarget,));
-    delegate_ui!(clear_nicks(serv: &str,));
+    delegate_ui!(add_client_notify_msg(msg: &str, target: &MsgTarget,));
+    delegate_ui!(clear_nicks(target: &MsgTarget,));
+    delegate_ui!(add_nicks(nicks: &[String], target: &MsgTarget,));
+    delegate_ui!(add_history(msgs: &[HistoryMsg], prepend: bool, target: &MsgTarget,));
     delegate_ui!(set_nick(serv: &str, nick: &str,));
+    delegate_ui!(set_away(serv: &str, away: bool,));
+    delegate_ui!(set_chan_modes(serv: &str, chan: &ChanNameRef, modes: &str,));
+    delegate_ui!(set_case_mapping(serv: &str, case_mapping: CaseMapping,));
+    delegate_ui!(set_nick_away(serv: &str, nick: &str, away: bool,));
     delegate_ui!(set_tab_style(style: TabStyle, target: &MsgTarget,));
+    delegate_ui!(set_typing(nick: &str, state: TypingState, target: &MsgTarget,));
+    delegate_ui!(set_queued(n_queued: usize, target: &MsgTarget,));
     delegate_ui!(user_tab_exists(serv_name: &str, nick: &str,) -> bool);
+    delegate_ui!(confirm_close(src: &MsgSource, cmd: &str,) -> bool);
+    delegate_ui!(confirm(msg: &str, cmd: &str, target: &MsgTarget,));
     delegate_ui!(get_tab_config(serv_name: &str, chan_name: Option<&ChanNameRef>,) -> TabConfig);
     delegate_ui!(set_tab_config(
         serv_name: &str,
@@ -98,28 +196,43 @@ impl UI {
     }
 }
 
+/// Handle TUI events. Clients of the servers in the config file are received from `rcv_client`
+/// after reading their passwords.
 pub(crate) async fn task(
     defaults: config::Defaults,
+    servers: Vec<config::Server<PassOrCmd>>,
     ui: UI,
-    mut clients: Vec<Client>,
-    rcv_ev: mpsc::Receiver<libtiny_common::Event>,
+    snd_client: mpsc::Sender<Client>,
+    mut rcv_client: mpsc::Receiver<Client>,
+    mut rcv_ev: mpsc::Receiver<libtiny_common::Event>,
 ) {
-    let mut rcv_ev = ReceiverStream::new(rcv_ev);
-    while let Some(ev) = rcv_ev.next().await {
-        handle_input_ev(&defaults, &ui, &mut clients, ev);
+    let mut clients: Vec<Client> = Vec::with_capacity(servers.len());
+    loop {
+        tokio::select! {
+            ev = rcv_ev.recv() => match ev {
+                None => return,
+                Some(ev) => {
+                    handle_input_ev(&defaults, &servers, &ui, &mut clients, &snd_client, ev);
+                }
+            },
+            Some(client) = rcv_client.recv() => clients.push(client),
+        }
         ui.draw();
     }
 }
 
 fn handle_input_ev(
     defaults: &config::Defaults,
+    servers: &[config::Server<PassOrCmd>],
     ui: &UI,
     clients: &mut Vec<Client>,
+    snd_client: &mpsc::Sender<Client>,
     ev: libtiny_common::Event,
 ) {
     use libtiny_common::Event::*;
     match ev {
         Quit { msg } => {
+            let msg = defaults.quit_reason(msg.as_deref().unwrap_or_default());
             for client in clients {
                 client.quit(msg.clone());
             }
@@ -136,7 +249,21 @@ fn handle_input_ev(
         }
 
         Cmd { cmd, source } => {
-            run_cmd(&cmd, source, defaults, ui, clients);
+            run_cmd(&cmd, source, defaults, servers, ui, clients, snd_client);
+        }
+
+        Typing { state, source } => {
+            let target = match &source {
+                MsgSource::Serv { .. } => return,
+                MsgSource::Chan { chan, .. } => chan.display(),
+                MsgSource::User { nick, .. } => nick,
+            };
+            if let Some(client) = clients
+                .iter_mut()
+                .find(|client| client.get_serv_name() == source.serv_name())
+            {
+                client.typing(target, state);
+            }
         }
     }
 }
@@ -183,9 +310,7 @@ pub(crate) fn send_msg(
             MsgSource::Chan { serv, chan } => (MsgTarget::Chan { serv, chan }, chan.display()),
 
             MsgSource::User { serv, nick } => {
-                let msg_target = if nick.eq_ignore_ascii_case("nickserv")
-                    || nick.eq_ignore_ascii_case("chanserv")
-                {
+                let msg_target = if utils::is_service(nick) {
                     MsgTarget::Server { serv }
                 } else {
                     MsgTarget::User { serv, nick }
@@ -202,8 +327,12 @@ pub(crate) fn send_msg(
         } else {
             0
         };
+    // With `echo-message` messages are shown when the server echoes them back
+    let echo_message = client.echo_message();
     for msg in client.split_privmsg(extra_len, &msg) {
         client.privmsg(msg_target, msg, is_action);
-        ui.add_privmsg(&client.get_nick(), msg, ts, &ui_target, false, is_action);
+        if !echo_message {
+            ui.add_privmsg(&client.get_nick(), msg, ts, &ui_target, false, is_action);
+        }
     }
 }
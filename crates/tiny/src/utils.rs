@@ -37,6 +37,11 @@ pub(crate) fn is_nick_first_char(c: char) -> bool {
     c != '#'
 }
 
+/// Messages to NickServ and ChanServ are shown in server tabs, rather than in user tabs.
+pub(crate) fn is_service(nick: &str) -> bool {
+    nick.eq_ignore_ascii_case("nickserv") || nick.eq_ignore_ascii_case("chanserv")
+}
+
 /*
 pub(crate) fn is_nick_char(c: char) -> bool {
     c.is_alphanumeric() // 'letter' or 'digit'